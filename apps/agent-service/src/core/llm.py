@@ -686,6 +686,8 @@ async def call_gemini_with_context(
         # Metadata extraction
         input_tokens = 0
         output_tokens = 0
+        cached_tokens = 0
+        thinking_tokens = 0
         cache_hit = False
 
         if response and hasattr(response, "usage_metadata"):
@@ -693,6 +695,7 @@ async def call_gemini_with_context(
             input_tokens = getattr(usage, "prompt_token_count", 0) or 0
             output_tokens = getattr(usage, "candidates_token_count", 0) or 0
             cached_tokens = getattr(usage, "cached_content_token_count", 0) or 0
+            thinking_tokens = getattr(usage, "thoughts_token_count", 0) or 0
             cache_hit = cached_tokens > 0
 
         signature_data = f"{agent_id or 'unknown'}_{datetime.now().isoformat()}"
@@ -717,6 +720,8 @@ async def call_gemini_with_context(
             "text": final_response_text,  # Clean model output only
             "input_tokens": input_tokens,
             "output_tokens": output_tokens,
+            "cached_tokens": cached_tokens,
+            "thinking_tokens": thinking_tokens,
             "thought_signature": thought_signature,
             "cache_hit": cache_hit,
             "files_generated": all_files_generated,
@@ -832,6 +837,8 @@ async def call_gemini_batch(
                 "text": "",
                 "input_tokens": 0,
                 "output_tokens": 0,
+                "cached_tokens": 0,
+                "thinking_tokens": 0,
                 "thought_signature": None,
                 "cache_hit": False,
                 "error": str(result)
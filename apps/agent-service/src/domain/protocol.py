@@ -16,6 +16,7 @@ class AgentRole(str, Enum):
     ORCHESTRATOR = "orchestrator"
     WORKER = "worker"
     OBSERVER = "observer"
+    SUPERVISOR = "supervisor"
 
 class DelegationStrategy(str, Enum):
     CHILD = "child"
@@ -112,6 +113,15 @@ class AgentRequest(BaseModel):
     thinking_level: Optional[int] = None
     file_paths: List[str] = []
 
+    # Generation parameters (None = model default); recorded by the Kernel per invocation
+    max_output_tokens: Optional[int] = None
+    temperature: Optional[float] = None
+    top_p: Optional[float] = None
+    seed: Optional[int] = None
+
+    # Per-agent log verbosity override (e.g. "debug"); service default when None
+    log_level: Optional[str] = None
+
     # [[NEW FIELDS]]
     allow_delegation: bool = False
     graph_view: str = "Context unavailable"
@@ -123,6 +133,9 @@ class AgentResponse(BaseModel):
     tokens_used: int = 0
     input_tokens: int = 0
     output_tokens: int = 0
+    # Part of input_tokens served from context cache
+    cached_tokens: int = 0
+    thinking_tokens: int = 0
     cache_hit: bool = False
 
     # [[CONTEXT CACHING]]
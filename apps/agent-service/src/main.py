@@ -367,7 +367,9 @@ async def _execute_agent_logic(request: AgentRequest) -> AgentResponse:
             delegation=delegation_request,
             input_tokens=result["input_tokens"],
             output_tokens=result["output_tokens"],
-            tokens_used=result["input_tokens"] + result["output_tokens"],
+            cached_tokens=result.get("cached_tokens", 0),
+            thinking_tokens=result.get("thinking_tokens", 0),
+            tokens_used=result["input_tokens"] + result["output_tokens"] + result.get("thinking_tokens", 0),
             thought_signature=result["thought_signature"],
             cache_hit=result["cache_hit"],
 
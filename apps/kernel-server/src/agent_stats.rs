@@ -0,0 +1,223 @@
+// [[RARO]]/apps/kernel-server/src/agent_stats.rs
+// Purpose: Historical per-agent performance. Keeps an exponential moving average of latency and
+//          tokens for every agent id of every workflow, updated on each successful invocation
+//          and written to disk so it survives restarts. Recording only marks the table dirty;
+//          a background task writes it out (flush), so invocations never wait on the disk.
+// Architecture: Accounting Layer
+// Dependencies: DashMap, Serde, Chrono
+
+use chrono::Utc;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::fs_manager;
+use crate::models::{AgentInvocation, InvocationStatus};
+use crate::observability::TokenBreakdown;
+
+const DEFAULT_STATS_PATH: &str = "/app/storage/agent_stats.json";
+
+/// Weight of the newest observation; older ones decay by (1 - EMA_ALPHA) per sample
+pub const EMA_ALPHA: f64 = 0.2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AgentStats {
+    /// Successful invocations folded in
+    pub samples: u64,
+    pub latency_ms_ema: f64,
+    pub tokens_ema: f64,
+    pub last_latency_ms: u64,
+    pub last_tokens: usize,
+    /// Cumulative over every sample that reported a split
+    #[serde(default)]
+    pub token_breakdown: TokenBreakdown,
+    /// Failed attempts escalated to the retry policy's stronger model. An agent that keeps
+    /// escalating probably belongs on that model.
+    #[serde(default)]
+    pub escalations: u64,
+    pub updated_at: String,
+}
+
+impl AgentStats {
+    fn observe(&mut self, latency_ms: u64, tokens: usize) {
+        if self.samples == 0 {
+            // The first observation seeds the averages as-is rather than decaying up from zero
+            self.latency_ms_ema = latency_ms as f64;
+            self.tokens_ema = tokens as f64;
+        } else {
+            self.latency_ms_ema += EMA_ALPHA * (latency_ms as f64 - self.latency_ms_ema);
+            self.tokens_ema += EMA_ALPHA * (tokens as f64 - self.tokens_ema);
+        }
+        self.samples += 1;
+        self.last_latency_ms = latency_ms;
+        self.last_tokens = tokens;
+        self.updated_at = Utc::now().to_rfc3339();
+    }
+}
+
+pub struct AgentStatsStore {
+    /// None keeps the stats in memory only
+    path: Option<PathBuf>,
+    workflows: DashMap<String, HashMap<String, AgentStats>>, // workflow_id -> agent_id -> stats
+    write_lock: Mutex<()>,
+    /// Changed since the last flush
+    dirty: AtomicBool,
+}
+
+impl AgentStatsStore {
+    /// RARO_AGENT_STATS_PATH (default /app/storage/agent_stats.json; empty = memory only)
+    pub fn from_env() -> Self {
+        let path = std::env::var("RARO_AGENT_STATS_PATH").unwrap_or_else(|_| DEFAULT_STATS_PATH.to_string());
+        Self::new((!path.is_empty()).then(|| PathBuf::from(path)))
+    }
+
+    /// Load the stats file at `path` when there is one
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let mut workflows = DashMap::new();
+        if let Some(data) = path.as_ref().and_then(|p| fs::read_to_string(p).ok()) {
+            match serde_json::from_str::<HashMap<String, HashMap<String, AgentStats>>>(&data) {
+                Ok(stored) => workflows.extend(stored),
+                Err(e) => tracing::warn!("Agent stats file not loaded: {}", e),
+            }
+        }
+        Self { path, workflows, write_lock: Mutex::new(()), dirty: AtomicBool::new(false) }
+    }
+
+    /// Fold successful invocations into their agents' averages
+    pub fn record(&self, workflow_id: &str, invocations: &[AgentInvocation]) {
+        let mut successes = invocations.iter().filter(|i| i.status == InvocationStatus::Success).peekable();
+        if successes.peek().is_none() {
+            return;
+        }
+        {
+            let mut agents = self.workflows.entry(workflow_id.to_string()).or_default();
+            for invocation in successes {
+                let stats = agents.entry(invocation.agent_id.clone()).or_default();
+                stats.observe(invocation.latency_ms, invocation.tokens_used);
+                stats.token_breakdown.add(invocation);
+            }
+        }
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    pub fn record_escalation(&self, workflow_id: &str, agent_id: &str) {
+        {
+            let mut agents = self.workflows.entry(workflow_id.to_string()).or_default();
+            let stats = agents.entry(agent_id.to_string()).or_default();
+            stats.escalations += 1;
+            stats.updated_at = Utc::now().to_rfc3339();
+        }
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Per-agent stats of a workflow, by agent id. None when no run of it has been recorded.
+    pub fn for_workflow(&self, workflow_id: &str) -> Option<BTreeMap<String, AgentStats>> {
+        self.workflows.get(workflow_id).map(|agents| agents.iter().map(|(id, s)| (id.clone(), s.clone())).collect())
+    }
+
+    /// Escalations summed over each workflow's agents, for workflows that had any
+    pub fn escalations_by_workflow(&self) -> BTreeMap<String, u64> {
+        self.workflows.iter()
+            .map(|w| (w.key().clone(), w.value().values().map(|s| s.escalations).sum::<u64>()))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    /// Historical token average, rounded, for estimates
+    pub fn tokens_estimate(&self, workflow_id: &str, agent_id: &str) -> Option<usize> {
+        self.workflows.get(workflow_id)?.get(agent_id).filter(|s| s.samples > 0).map(|s| s.tokens_ema.round() as usize)
+    }
+
+    /// Write the table out if it changed since the last flush. Blocking file I/O: async
+    /// callers go through spawn_blocking.
+    pub fn flush(&self) {
+        if self.path.is_some() && self.dirty.swap(false, Ordering::AcqRel) && !self.save() {
+            // Retried on the next flush
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Write the whole table (temp file + rename, so readers never see a partial file)
+    fn save(&self) -> bool {
+        let Some(path) = &self.path else { return true };
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let snapshot: HashMap<String, HashMap<String, AgentStats>> = self.workflows.iter()
+            .map(|w| (w.key().clone(), w.value().clone()))
+            .collect();
+        let result = serde_json::to_vec(&snapshot)
+            .map_err(|e| e.to_string())
+            .and_then(|data| fs_manager::write_snapshot(path, &data).map_err(|e| e.to_string()));
+        if let Err(e) = &result {
+            tracing::warn!("Failed to persist agent stats to {}: {}", path.display(), e);
+        }
+        result.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{GenerationParams, ModelVariant};
+
+    fn success(agent_id: &str, latency_ms: u64, tokens_used: usize) -> AgentInvocation {
+        AgentInvocation {
+            id: uuid::Uuid::new_v4().to_string(),
+            agent_id: agent_id.to_string(),
+            model_variant: ModelVariant::Fast,
+            thought_signature: None,
+            tools_used: vec![],
+            tokens_used,
+            latency_ms,
+            status: InvocationStatus::Success,
+            started_at: Utc::now().to_rfc3339(),
+            timestamp: Utc::now().to_rfc3339(),
+            artifact_id: None,
+            error_message: None,
+            replay_of: None,
+            generation: GenerationParams::default(),
+            token_split: Default::default(),
+            escalated_from: None,
+        }
+    }
+
+    #[test]
+    fn test_ema_moves_toward_observations_and_persists() {
+        let path = std::env::temp_dir().join(format!("agent_stats_{}.json", uuid::Uuid::new_v4()));
+        let store = AgentStatsStore::new(Some(path.clone()));
+
+        // First observation is taken as-is
+        store.record("wf", &[success("a", 1000, 500)]);
+        assert_eq!(store.for_workflow("wf").unwrap()["a"].latency_ms_ema, 1000.0);
+
+        let mut previous = 1000.0;
+        for _ in 0..5 {
+            store.record("wf", &[success("a", 2000, 1500)]);
+            let latency = store.for_workflow("wf").unwrap()["a"].latency_ms_ema;
+            assert!(latency > previous && latency < 2000.0);
+            previous = latency;
+        }
+        let stats = store.for_workflow("wf").unwrap()["a"].clone();
+        assert_eq!(stats.samples, 6);
+        assert!((stats.latency_ms_ema - (2000.0 - 1000.0 * 0.8_f64.powi(5))).abs() < 1e-6);
+
+        // Failures don't count
+        let mut failed = success("a", 60_000, 0);
+        failed.status = InvocationStatus::Failed;
+        store.record("wf", &[failed]);
+        assert_eq!(store.for_workflow("wf").unwrap()["a"].samples, 6);
+
+        // Written out on flush only
+        assert!(!path.exists());
+        store.flush();
+
+        // Reloaded from disk
+        let reloaded = AgentStatsStore::new(Some(path.clone()));
+        assert_eq!(reloaded.for_workflow("wf").unwrap()["a"], stats);
+        assert_eq!(reloaded.tokens_estimate("wf", "a"), Some(stats.tokens_ema.round() as usize));
+        assert!(reloaded.for_workflow("other").is_none());
+        let _ = fs::remove_file(path);
+    }
+}
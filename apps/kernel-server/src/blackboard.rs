@@ -0,0 +1,128 @@
+// [[RARO]]/apps/kernel-server/src/blackboard.rs
+// Purpose: Per-run working memory ("blackboard"): small JSON values under string keys that any
+//          agent of the run can read and overwrite. Meant for scratch state shared across the
+//          run, not deliverables (those are artifacts) or reasoning continuity (signatures).
+//          Each run is capped in key count and in total bytes (keys plus serialized values).
+// Architecture: Domain Helper Layer
+// Dependencies: DashMap, serde_json
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryLimits {
+    pub max_keys_per_run: usize,
+    /// Keys plus serialized values, summed over the run
+    pub max_bytes_per_run: usize,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            max_keys_per_run: 256,
+            max_bytes_per_run: 1024 * 1024,
+        }
+    }
+}
+
+impl MemoryLimits {
+    /// RARO_MEMORY_MAX_KEYS_PER_RUN, RARO_MEMORY_MAX_BYTES_PER_RUN (unset or 0 = default)
+    pub fn from_env() -> Self {
+        let read = |key: &str| std::env::var(key).ok().and_then(|v| v.parse::<usize>().ok()).filter(|v| *v > 0);
+        let defaults = Self::default();
+        Self {
+            max_keys_per_run: read("RARO_MEMORY_MAX_KEYS_PER_RUN").unwrap_or(defaults.max_keys_per_run),
+            max_bytes_per_run: read("RARO_MEMORY_MAX_BYTES_PER_RUN").unwrap_or(defaults.max_bytes_per_run),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum MemoryLimitError {
+    #[error("Run memory holds at most {0} keys")]
+    TooManyKeys(usize),
+    #[error("Run memory holds at most {limit} bytes; this write needs {needed}")]
+    TooLarge { limit: usize, needed: usize },
+}
+
+#[derive(Default)]
+struct RunMemory {
+    values: HashMap<String, serde_json::Value>,
+    bytes: usize,
+}
+
+fn entry_size(key: &str, value: &serde_json::Value) -> usize {
+    key.len() + serde_json::to_vec(value).map(|v| v.len()).unwrap_or(0)
+}
+
+pub struct Blackboard {
+    pub limits: MemoryLimits,
+    runs: DashMap<String, RunMemory>, // run_id -> its keys
+}
+
+impl Blackboard {
+    pub fn new(limits: MemoryLimits) -> Self {
+        Self { limits, runs: DashMap::new() }
+    }
+
+    /// Runs holding at least one write
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn get(&self, run_id: &str, key: &str) -> Option<serde_json::Value> {
+        self.runs.get(run_id)?.values.get(key).cloned()
+    }
+
+    /// Insert or overwrite a key. Returns whether the key was new; nothing changes when the
+    /// write would take the run over either limit.
+    pub fn put(&self, run_id: &str, key: &str, value: serde_json::Value) -> Result<bool, MemoryLimitError> {
+        let mut memory = self.runs.entry(run_id.to_string()).or_default();
+        let replaced = memory.values.get(key).map(|old| entry_size(key, old));
+        if replaced.is_none() && memory.values.len() >= self.limits.max_keys_per_run {
+            return Err(MemoryLimitError::TooManyKeys(self.limits.max_keys_per_run));
+        }
+        let needed = memory.bytes - replaced.unwrap_or(0) + entry_size(key, &value);
+        if needed > self.limits.max_bytes_per_run {
+            return Err(MemoryLimitError::TooLarge { limit: self.limits.max_bytes_per_run, needed });
+        }
+        memory.bytes = needed;
+        memory.values.insert(key.to_string(), value);
+        Ok(replaced.is_none())
+    }
+
+    /// Drop a run's whole memory (run garbage collection)
+    pub fn clear_run(&self, run_id: &str) {
+        self.runs.remove(run_id);
+    }
+
+    pub fn delete(&self, run_id: &str, key: &str) -> Option<serde_json::Value> {
+        let mut memory = self.runs.get_mut(run_id)?;
+        let value = memory.values.remove(key)?;
+        memory.bytes -= entry_size(key, &value);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_overwrites_and_deletes_keep_byte_count() {
+        let board = Blackboard::new(MemoryLimits { max_keys_per_run: 2, max_bytes_per_run: 20 });
+        assert_eq!(board.put("run-1", "k", json!("0123456789")), Ok(true));
+        // Overwriting frees the old value's bytes first
+        assert_eq!(board.put("run-1", "k", json!("0123456789abcdef")), Ok(false));
+        assert_eq!(board.put("run-1", "j", json!(1)), Err(MemoryLimitError::TooLarge { limit: 20, needed: 21 }));
+        assert_eq!(board.get("run-1", "k"), Some(json!("0123456789abcdef")));
+
+        assert!(board.delete("run-1", "k").is_some());
+        assert_eq!(board.put("run-1", "j", json!(1)), Ok(true));
+        // Runs are independent
+        assert_eq!(board.get("run-2", "j"), None);
+    }
+}
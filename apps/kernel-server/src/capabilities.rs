@@ -0,0 +1,167 @@
+// [[RARO]]/apps/kernel-server/src/capabilities.rs
+// Purpose: Self-describing feature/limit document for SDKs (GET /capabilities). No secrets.
+// Architecture: Discovery Layer
+// Dependencies: Serde, Models, Runtime
+
+use serde::Serialize;
+use crate::models::{
+    MAX_ENVIRONMENT_KEYS, MAX_ENVIRONMENT_VALUE_CHARS, MAX_METADATA_BYTES, MAX_RUN_PRIORITY, MAX_TOKEN_BUDGET,
+};
+use crate::event_limits::EventLimits;
+use crate::blackboard::MemoryLimits;
+use crate::runtime::RARORuntime;
+
+/// Request body cap for library uploads (applied to the upload route in main.rs)
+pub const MAX_UPLOAD_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub api_version: &'static str,
+    pub auth: AuthCapabilities,
+    /// Accepted WorkflowConfig.execution_mode types
+    pub execution_modes: Vec<&'static str>,
+    /// WorkflowConfig.simulation runs against the built-in fake executor
+    pub simulation: bool,
+    /// Accepted WorkflowConfig.hooks
+    pub hooks: Vec<&'static str>,
+    /// Unknown WorkflowConfig fields are rejected unless a request passes ?strict=false
+    pub strict_config: bool,
+    pub storage: StorageCapabilities,
+    pub streaming: StreamingCapabilities,
+    pub models: Vec<ModelCapability>,
+    pub limits: Limits,
+    /// Every `error` code an API failure can carry
+    pub error_codes: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthCapabilities {
+    /// Header naming the tenant; requests without it act as "public"
+    pub client_header: &'static str,
+    /// Whether /admin routes accept a token (RARO_ADMIN_TOKEN is set)
+    pub admin_enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageCapabilities {
+    pub backend: &'static str,
+    /// Run state survives restarts (Redis configured)
+    pub persistent_state: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamingCapabilities {
+    pub websocket: bool,
+    pub ndjson_logs: bool,
+    pub sse: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelCapability {
+    pub variant: String,
+    pub enabled: bool,
+    pub supports_thinking: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Limits {
+    pub max_upload_bytes: usize,
+    pub max_agents_per_workflow: usize,
+    pub max_edges_per_workflow: usize,
+    pub max_dependencies_per_agent: usize,
+    pub max_prompt_chars: usize,
+    pub max_config_bytes: usize,
+    pub max_token_budget: usize,
+    pub max_metadata_bytes: usize,
+    pub max_environment_keys: usize,
+    pub max_environment_value_chars: usize,
+    pub max_run_priority: u8,
+    /// None: runs are not capped
+    pub max_concurrent_runs: Option<usize>,
+    /// Per-run limits on agent-posted events (429 beyond them)
+    pub events: EventLimits,
+    /// Per-run working memory (/runtime/:run_id/memory/:key; 413 beyond them)
+    pub memory: MemoryLimits,
+}
+
+impl Capabilities {
+    pub fn from_runtime(runtime: &RARORuntime) -> Self {
+        Self {
+            api_version: env!("CARGO_PKG_VERSION"),
+            auth: AuthCapabilities {
+                client_header: "X-RARO-CLIENT-ID",
+                admin_enabled: std::env::var("RARO_ADMIN_TOKEN").map(|t| !t.is_empty()).unwrap_or(false),
+            },
+            execution_modes: vec!["managed", "pull", "push"],
+            simulation: true,
+            hooks: vec!["before_agent", "after_agent", "on_complete", "on_fail"],
+            strict_config: runtime.strict_config,
+            storage: StorageCapabilities {
+                backend: "local",
+                persistent_state: runtime.redis_client.is_some(),
+            },
+            streaming: StreamingCapabilities { websocket: true, ndjson_logs: true, sse: true },
+            models: runtime.model_registry.list().into_iter()
+                .map(|m| ModelCapability { variant: m.variant, enabled: m.enabled, supports_thinking: m.supports_thinking })
+                .collect(),
+            limits: Limits {
+                max_upload_bytes: MAX_UPLOAD_BYTES,
+                max_agents_per_workflow: runtime.workflow_limits.max_agents,
+                max_edges_per_workflow: runtime.workflow_limits.max_edges,
+                max_dependencies_per_agent: runtime.workflow_limits.max_dependencies_per_agent,
+                max_prompt_chars: runtime.workflow_limits.max_prompt_chars,
+                max_config_bytes: runtime.workflow_limits.max_config_bytes,
+                max_token_budget: MAX_TOKEN_BUDGET,
+                max_metadata_bytes: MAX_METADATA_BYTES,
+                max_environment_keys: MAX_ENVIRONMENT_KEYS,
+                max_environment_value_chars: MAX_ENVIRONMENT_VALUE_CHARS,
+                max_run_priority: MAX_RUN_PRIORITY,
+                max_concurrent_runs: None,
+                events: runtime.event_limiter.limits.clone(),
+                memory: runtime.blackboard.limits.clone(),
+            },
+            error_codes: crate::server::error::ERROR_CODES,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ValidationError, WorkflowConfig};
+
+    fn workflow(agents: usize, overrides: serde_json::Value) -> WorkflowConfig {
+        let agents: Vec<serde_json::Value> = (0..agents)
+            .map(|i| serde_json::json!({ "id": format!("a{}", i), "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null }))
+            .collect();
+        let mut config = serde_json::json!({ "id": "wf", "name": "wf", "agents": agents, "max_token_budget": 0, "timeout_ms": 1 });
+        config.as_object_mut().unwrap().extend(overrides.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn test_advertised_limits_are_enforced() {
+        let runtime = RARORuntime::new();
+        let limits = Capabilities::from_runtime(&runtime).limits;
+
+        assert!(workflow(limits.max_agents_per_workflow, serde_json::json!({})).check_limits(&runtime.workflow_limits).is_ok());
+        assert_eq!(
+            workflow(limits.max_agents_per_workflow + 1, serde_json::json!({})).check_limits(&runtime.workflow_limits).unwrap_err(),
+            vec![ValidationError::TooManyAgents { observed: limits.max_agents_per_workflow + 1, max: limits.max_agents_per_workflow }],
+        );
+
+        let over_budget = workflow(1, serde_json::json!({ "max_token_budget": limits.max_token_budget + 1 }));
+        assert_eq!(over_budget.validate().unwrap_err(), vec![ValidationError::InvalidTokenBudget(limits.max_token_budget + 1)]);
+
+        let over_priority = workflow(1, serde_json::json!({ "priority": limits.max_run_priority + 1 }));
+        assert_eq!(over_priority.validate().unwrap_err(), vec![ValidationError::InvalidPriority(limits.max_run_priority + 1)]);
+
+        let keys: serde_json::Map<String, serde_json::Value> = (0..=limits.max_environment_keys)
+            .map(|i| (format!("K{}", i), serde_json::json!("v")))
+            .collect();
+        assert!(workflow(1, serde_json::json!({ "global_environment": keys })).validate_environment().is_err());
+
+        let metadata = serde_json::json!({ "blob": "x".repeat(limits.max_metadata_bytes) });
+        assert!(workflow(1, serde_json::json!({ "metadata": metadata })).validate_metadata().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+// [[RARO]]/apps/kernel-server/src/client_policy.rs
+// Purpose: Per-client model usage policy: which model variants a client may use at all, and
+//          how many invocations of a variant it may prepare per UTC day.
+// Architecture: Accounting Layer
+// Dependencies: DashMap, Redis, Chrono
+//
+// Variants are compared canonically: a custom model id that resolves to the same API model as
+// a named variant counts as that variant, so it can't be used to get around the policy.
+
+use chrono::Utc;
+use dashmap::DashMap;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use crate::models::ModelVariant;
+use crate::observability;
+use crate::redis_keys;
+
+const PROFILE_KEY_PREFIX: &str = "client_profile:";
+const MODEL_QUOTA_KEY_PREFIX: &str = "model_quota:";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ClientProfile {
+    /// Variants the client may use; every variant when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_models: Option<Vec<ModelVariant>>,
+    /// Invocations per UTC day, keyed by variant ("thinking", ...); unlisted variants are unlimited
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub daily_model_quotas: BTreeMap<String, u64>,
+}
+
+impl ClientProfile {
+    pub fn allows(&self, variant: &ModelVariant) -> bool {
+        self.allowed_models.as_ref().is_none_or(|allowed| allowed.contains(variant))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyViolation {
+    NotAllowed,
+    QuotaExceeded { limit: u64 },
+}
+
+/// GET /admin/clients/:client_id/model_policy
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelPolicyReport {
+    pub client_id: String,
+    pub profile: ClientProfile,
+    /// UTC date the counters belong to (YYYY-MM-DD); they reset at midnight
+    pub day: String,
+    /// Invocations counted today, for variants with a quota
+    pub used_today: BTreeMap<String, u64>,
+}
+
+/// Profiles and today's counters are mirrored to Redis (`client_profile:{client_id}` and
+/// `model_quota:{client_id}:{day}`) so a restart neither forgets a ban nor refills a quota.
+pub struct ClientPolicies {
+    profiles: DashMap<String, ClientProfile>,
+    daily_counts: DashMap<(String, String), HashMap<String, u64>>, // (client_id, day) -> variant -> invocations
+    redis_client: Option<redis::Client>,
+}
+
+impl ClientPolicies {
+    pub fn new(redis_client: Option<redis::Client>) -> Self {
+        Self {
+            profiles: DashMap::new(),
+            daily_counts: DashMap::new(),
+            redis_client,
+        }
+    }
+
+    pub fn profile(&self, client_id: &str) -> ClientProfile {
+        self.profiles.get(client_id).map(|p| p.clone()).unwrap_or_default()
+    }
+
+    pub fn set_profile(&self, client_id: &str, profile: ClientProfile) {
+        let stored = serde_json::to_string(&profile).unwrap_or_default();
+        if profile == ClientProfile::default() {
+            self.profiles.remove(client_id);
+        } else {
+            self.profiles.insert(client_id.to_string(), profile);
+        }
+
+        if let Some(client) = self.redis_client.clone() {
+            let key = format!("{}{}", PROFILE_KEY_PREFIX, client_id);
+            let client_label = observability::anonymize_client(client_id);
+            tokio::spawn(async move {
+                let result: redis::RedisResult<()> = async {
+                    let mut con = client.get_async_connection().await?;
+                    con.set(&key, stored).await
+                }.await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to persist client profile of {}: {}", client_label, e);
+                }
+            });
+        }
+    }
+
+    /// Count one invocation of `variant` against today's quota. Nothing is counted on refusal.
+    pub fn consume(&self, client_id: &str, variant: &ModelVariant) -> Result<(), PolicyViolation> {
+        let profile = self.profile(client_id);
+        if !profile.allows(variant) {
+            return Err(PolicyViolation::NotAllowed);
+        }
+        let Some(&limit) = profile.daily_model_quotas.get(variant.as_str()) else { return Ok(()) };
+
+        let day = current_day();
+        let counter_key = (client_id.to_string(), day.clone());
+        if !self.daily_counts.contains_key(&counter_key) {
+            // First quota'd invocation of the day: earlier days' counters are done with
+            self.daily_counts.retain(|(client, counted_day), _| client != client_id || *counted_day == day);
+        }
+        {
+            let mut counts = self.daily_counts.entry(counter_key).or_default();
+            let used = counts.entry(variant.as_str().to_string()).or_default();
+            if *used >= limit {
+                return Err(PolicyViolation::QuotaExceeded { limit });
+            }
+            *used += 1;
+        }
+
+        if let Some(client) = self.redis_client.clone() {
+            let key = format!("{}{}:{}", MODEL_QUOTA_KEY_PREFIX, client_id, day);
+            let field = variant.as_str().to_string();
+            let client_label = observability::anonymize_client(client_id);
+            tokio::spawn(async move {
+                let result: redis::RedisResult<()> = async {
+                    let mut con = client.get_async_connection().await?;
+                    redis::pipe()
+                        .hincr(&key, field, 1u64).ignore()
+                        // Yesterday's counters are never read again
+                        .expire(&key, 2 * 86400).ignore()
+                        .query_async(&mut con)
+                        .await
+                }.await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to persist model quota counter of {}: {}", client_label, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub fn report(&self, client_id: &str) -> ModelPolicyReport {
+        let day = current_day();
+        let used_today = self.daily_counts.get(&(client_id.to_string(), day.clone()))
+            .map(|counts| counts.iter().map(|(k, v)| (k.clone(), *v)).collect())
+            .unwrap_or_default();
+        ModelPolicyReport { client_id: client_id.to_string(), profile: self.profile(client_id), day, used_today }
+    }
+
+    /// Restore profiles and today's counters at boot
+    pub async fn load_from_redis(&self) {
+        let Some(client) = &self.redis_client else { return };
+        let mut con = match client.get_async_connection().await {
+            Ok(con) => con,
+            Err(e) => {
+                tracing::warn!("Client profiles not restored: {}", e);
+                return;
+            }
+        };
+
+        let keys = redis_keys::scan_keys(&mut con, &format!("{}*", PROFILE_KEY_PREFIX)).await.unwrap_or_default();
+        for key in keys {
+            let Some(client_id) = key.strip_prefix(PROFILE_KEY_PREFIX) else { continue };
+            let stored: Option<String> = con.get(&key).await.unwrap_or(None);
+            if let Some(profile) = stored.and_then(|json| serde_json::from_str::<ClientProfile>(&json).ok()) {
+                self.profiles.insert(client_id.to_string(), profile);
+            }
+        }
+
+        let day = current_day();
+        let keys = redis_keys::scan_keys(&mut con, &format!("{}*:{}", MODEL_QUOTA_KEY_PREFIX, day)).await.unwrap_or_default();
+        for key in keys {
+            let Some(client_id) = key.strip_prefix(MODEL_QUOTA_KEY_PREFIX).and_then(|rest| rest.strip_suffix(&format!(":{}", day))) else { continue };
+            let counts: HashMap<String, u64> = con.hgetall(&key).await.unwrap_or_default();
+            self.daily_counts.insert((client_id.to_string(), day.clone()), counts);
+        }
+        tracing::info!("Restored {} client profiles", self.profiles.len());
+    }
+}
+
+fn current_day() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_counts_per_client_and_refuses_at_limit() {
+        let policies = ClientPolicies::new(None);
+        policies.set_profile("acme", ClientProfile {
+            allowed_models: Some(vec![ModelVariant::Fast, ModelVariant::Thinking]),
+            daily_model_quotas: BTreeMap::from([("thinking".to_string(), 2)]),
+        });
+
+        assert_eq!(policies.consume("acme", &ModelVariant::Reasoning), Err(PolicyViolation::NotAllowed));
+        assert_eq!(policies.consume("acme", &ModelVariant::Thinking), Ok(()));
+        assert_eq!(policies.consume("acme", &ModelVariant::Thinking), Ok(()));
+        assert_eq!(policies.consume("acme", &ModelVariant::Thinking), Err(PolicyViolation::QuotaExceeded { limit: 2 }));
+        // Unquoted variants and other clients are unaffected
+        assert_eq!(policies.consume("acme", &ModelVariant::Fast), Ok(()));
+        assert_eq!(policies.consume("other", &ModelVariant::Thinking), Ok(()));
+
+        let report = policies.report("acme");
+        assert_eq!(report.used_today, BTreeMap::from([("thinking".to_string(), 2)]));
+    }
+}
@@ -0,0 +1,267 @@
+// [[RARO]]/apps/kernel-server/src/cortex.rs
+// Purpose: Cortex Engine. Wires the event bus to the pattern registry and executes matched actions.
+// Architecture: Cortex Layer
+// Dependencies: Tokio, Reqwest, Registry, Runtime
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use crate::events::{EventType, RuntimeEvent};
+use crate::models::FailureCode;
+use crate::registry::{Pattern, PatternAction};
+use crate::runtime::{RARORuntime, RuntimeError};
+
+#[async_trait]
+pub trait PatternActionExecutor: Send + Sync {
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError>;
+}
+
+/// Fails the run at the triggering agent
+pub struct InterruptExecutor;
+
+#[async_trait]
+impl PatternActionExecutor for InterruptExecutor {
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError> {
+        let PatternAction::Interrupt { reason } = action else { return Ok(()) };
+        if let Some(agent) = &event.agent_id {
+            runtime.fail_run(&event.run_id, agent, FailureCode::PatternInterrupt, reason).await;
+        }
+        Ok(())
+    }
+}
+
+/// Pauses the run until a human resumes it
+pub struct ApprovalExecutor;
+
+#[async_trait]
+impl PatternActionExecutor for ApprovalExecutor {
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError> {
+        let PatternAction::RequestApproval { reason } = action else { return Ok(()) };
+        tracing::warn!("✋ Safety Pattern Triggered: Approval Required - {}", reason);
+        runtime.request_approval(&event.run_id, event.agent_id.as_deref(), reason).await;
+        Ok(())
+    }
+}
+
+/// Injects a new agent below the triggering agent
+pub struct SpawnAgentExecutor;
+
+#[async_trait]
+impl PatternActionExecutor for SpawnAgentExecutor {
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError> {
+        let PatternAction::SpawnAgent { config } = action else { return Ok(()) };
+        let parent = event.agent_id.as_deref()
+            .ok_or_else(|| RuntimeError::PatternAction("SpawnAgent requires an event with an agent".to_string()))?;
+        runtime.spawn_agent(&event.run_id, parent, (**config).clone(), "Spawned by Cortex pattern").await
+    }
+}
+
+/// Delivers the triggering event to an external endpoint
+pub struct WebhookExecutor {
+    client: reqwest::Client,
+}
+
+impl WebhookExecutor {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_else(|_| reqwest::Client::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl PatternActionExecutor for WebhookExecutor {
+    /// Delivered in the background: a slow endpoint must not hold up the engine's event loop
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, _runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError> {
+        let PatternAction::Webhook { url } = action else { return Ok(()) };
+        let delivery = self.client.post(url).json(event).send();
+        let (url, run_id) = (url.clone(), event.run_id.clone());
+        tokio::spawn(async move {
+            match delivery.await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => tracing::error!("Webhook {} for run {} returned {}", url, run_id, response.status()),
+                Err(e) => tracing::error!("Webhook {} for run {} unreachable: {}", url, run_id, e),
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Seals the run; optionally stops the process (RARO_ABORT_EXIT=true and non-zero exit_code)
+pub struct AbortExecutor;
+
+#[async_trait]
+impl PatternActionExecutor for AbortExecutor {
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError> {
+        let PatternAction::Abort { reason, exit_code } = action else { return Ok(()) };
+        let result = runtime.abort_run(&event.run_id, reason).await;
+
+        let exit_enabled = std::env::var("RARO_ABORT_EXIT").map(|v| v == "true").unwrap_or(false);
+        if *exit_code != 0 && exit_enabled {
+            tracing::error!(target: "raro::audit", severity = "CRITICAL", "Abort pattern on run {} requested process exit ({})", event.run_id, exit_code);
+            std::process::exit(*exit_code as i32);
+        }
+        result
+    }
+}
+
+/// Annotates the run's metadata, leaving a trail GET /runtime/runs?tag_key= can find
+pub struct SetRunMetadataExecutor;
+
+#[async_trait]
+impl PatternActionExecutor for SetRunMetadataExecutor {
+    async fn execute(&self, action: &PatternAction, event: &RuntimeEvent, runtime: &Arc<RARORuntime>) -> Result<(), RuntimeError> {
+        let PatternAction::SetRunMetadata { key, value } = action else { return Ok(()) };
+        runtime.upsert_run_metadata(&event.run_id, key, value.clone()).await
+    }
+}
+
+pub struct CortexEngine {
+    runtime: Arc<RARORuntime>,
+    interrupt: InterruptExecutor,
+    approval: ApprovalExecutor,
+    spawn_agent: SpawnAgentExecutor,
+    webhook: WebhookExecutor,
+    abort: AbortExecutor,
+    set_run_metadata: SetRunMetadataExecutor,
+}
+
+impl CortexEngine {
+    pub fn new(runtime: Arc<RARORuntime>) -> Self {
+        Self {
+            runtime,
+            interrupt: InterruptExecutor,
+            approval: ApprovalExecutor,
+            spawn_agent: SpawnAgentExecutor,
+            webhook: WebhookExecutor::new(),
+            abort: AbortExecutor,
+            set_run_metadata: SetRunMetadataExecutor,
+        }
+    }
+
+    /// Subscribe to the event bus and process events in a background task
+    pub fn start(self) -> JoinHandle<()> {
+        let mut rx = self.runtime.event_bus.subscribe();
+
+        tokio::spawn(async move {
+            tracing::info!("Cortex Pattern Engine started");
+            loop {
+                match rx.recv().await {
+                    Ok(event) => self.handle_event(&event).await,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("Cortex lagged behind the event bus, {} events skipped", missed);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+            tracing::warn!("Cortex Pattern Engine stopped: event bus closed");
+        })
+    }
+
+    pub async fn handle_event(&self, event: &RuntimeEvent) {
+        // 0. Built-in reactions
+        if let (EventType::AgentCompleted, Some(agent_id)) = (&event.event_type, &event.agent_id) {
+            let rt = self.runtime.clone();
+            let (run_id, agent_id) = (event.run_id.clone(), agent_id.clone());
+            tokio::spawn(async move {
+                rt.auto_promote_artifacts(&run_id, &agent_id).await;
+            });
+        }
+
+        // 1. Find matching patterns
+        let patterns = self.runtime.pattern_registry.get_patterns_for_trigger(&format!("{:?}", event.event_type));
+
+        for pattern in patterns.iter().filter(|p| condition_met(p, event)) {
+            self.runtime.note_pattern_trigger(&event.run_id, &pattern.id);
+            tracing::info!("⚠️  Pattern Triggered: {} on Agent {}", pattern.name, event.agent_id.as_deref().unwrap_or("?"));
+
+            // 2. Execute Action
+            if let Err(e) = self.executor_for(&pattern.action).execute(&pattern.action, event, &self.runtime).await {
+                tracing::error!("Pattern {} action failed on run {}: {}", pattern.id, event.run_id, e);
+            }
+        }
+    }
+
+    fn executor_for(&self, action: &PatternAction) -> &dyn PatternActionExecutor {
+        match action {
+            PatternAction::Interrupt { .. } => &self.interrupt,
+            PatternAction::RequestApproval { .. } => &self.approval,
+            PatternAction::SpawnAgent { .. } => &self.spawn_agent,
+            PatternAction::Webhook { .. } => &self.webhook,
+            PatternAction::Abort { .. } => &self.abort,
+            PatternAction::SetRunMetadata { .. } => &self.set_run_metadata,
+        }
+    }
+}
+
+/// Simple string match for MVP. In Phase 4, we use a real JSONPath engine here.
+fn condition_met(pattern: &Pattern, event: &RuntimeEvent) -> bool {
+    pattern.condition == "*" || event.payload.to_string().contains(&pattern.condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, routing::post, Json, Router};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_matched_pattern_dispatches_webhook() {
+        let (tx, mut rx) = mpsc::channel::<serde_json::Value>(4);
+        let app = Router::new()
+            .route("/hook", post(|State(tx): State<mpsc::Sender<serde_json::Value>>, Json(body): Json<serde_json::Value>| async move {
+                tx.send(body).await.unwrap();
+            }))
+            .with_state(tx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let runtime = Arc::new(RARORuntime::new());
+        runtime.pattern_registry.register(Pattern {
+            id: "notify_shell".to_string(),
+            name: "Notify on shell use".to_string(),
+            trigger_event: "ToolCall".to_string(),
+            condition: "shell_exec".to_string(),
+            action: PatternAction::Webhook { url: format!("http://{}/hook", addr) },
+        });
+        let engine = CortexEngine::new(runtime);
+
+        let ignored = RuntimeEvent::new("run-1", EventType::ToolCall, Some("a".to_string()), serde_json::json!({ "tool": "read_file" }));
+        engine.handle_event(&ignored).await;
+        let matched = RuntimeEvent::new("run-1", EventType::ToolCall, Some("a".to_string()), serde_json::json!({ "tool": "shell_exec" }));
+        engine.handle_event(&matched).await;
+
+        let delivered = rx.recv().await.unwrap();
+        assert_eq!(delivered["id"], matched.id.as_str());
+        assert_eq!(delivered["payload"]["tool"], "shell_exec");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slow_webhook_does_not_block_the_engine() {
+        let app = Router::new().route("/hook", post(|| async { tokio::time::sleep(std::time::Duration::from_secs(30)).await }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let runtime = Arc::new(RARORuntime::new());
+        runtime.pattern_registry.register(Pattern {
+            id: "notify_all".to_string(),
+            name: "Notify on every tool call".to_string(),
+            trigger_event: "ToolCall".to_string(),
+            condition: "*".to_string(),
+            action: PatternAction::Webhook { url: format!("http://{}/hook", addr) },
+        });
+        let engine = CortexEngine::new(runtime);
+
+        let event = RuntimeEvent::new("run-1", EventType::ToolCall, Some("a".to_string()), serde_json::json!({ "tool": "read_file" }));
+        tokio::time::timeout(std::time::Duration::from_secs(2), engine.handle_event(&event))
+            .await
+            .expect("the engine should not wait for the webhook");
+    }
+}
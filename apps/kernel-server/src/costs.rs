@@ -0,0 +1,441 @@
+// [[RARO]]/apps/kernel-server/src/costs.rs
+// Purpose: Monthly cost attribution rollups per client and per run tag, plus their Prometheus export.
+//          Per-run breakdowns by agent and model, with a projection for agents still running.
+// Architecture: Accounting Layer
+// Dependencies: DashMap, Redis, Chrono
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use redis::AsyncCommands;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::models::{AgentInvocation, InvocationStatus, ModelVariant, RuntimeState};
+use crate::redis_keys;
+
+const COSTS_KEY_PREFIX: &str = "costs:";
+/// Distinct tag keys tracked per month; later keys land in the overflow bucket
+const MAX_TAG_KEYS: usize = 16;
+/// Distinct values tracked per tag key and month; later values land in the overflow bucket
+const MAX_TAG_VALUES: usize = 100;
+/// Stands in for tag keys/values beyond the cardinality caps
+pub const OVERFLOW_BUCKET: &str = "_other";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CostRollup {
+    pub invocations: u64,
+    pub tokens_used: u64,
+    /// Whole micro-dollars so Redis can HINCRBY them
+    pub cost_micro_usd: u64,
+}
+
+impl CostRollup {
+    fn of(invocation: &AgentInvocation) -> Self {
+        Self {
+            invocations: 1,
+            tokens_used: invocation.tokens_used as u64,
+            cost_micro_usd: micro_usd(&invocation.model_variant, invocation.tokens_used),
+        }
+    }
+
+    fn add(&mut self, other: &CostRollup) {
+        self.invocations += other.invocations;
+        self.tokens_used += other.tokens_used;
+        self.cost_micro_usd += other.cost_micro_usd;
+    }
+
+    fn cost_usd(&self) -> f64 {
+        self.cost_micro_usd as f64 / 1_000_000.0
+    }
+}
+
+/// Priced from ModelVariant::usd_per_million_tokens (1 token at $1/M = 1 micro-dollar)
+fn micro_usd(variant: &ModelVariant, tokens: usize) -> u64 {
+    (tokens as f64 * variant.usd_per_million_tokens()).round() as u64
+}
+
+#[derive(Debug, Default)]
+struct MonthRollups {
+    clients: HashMap<String, CostRollup>,
+    tags: HashMap<String, HashMap<String, CostRollup>>, // tag key -> value -> counters
+}
+
+/// `?group_by=` of GET /metrics/costs
+#[derive(Debug, Clone, PartialEq)]
+pub enum CostGrouping {
+    Client,
+    Tag(String),
+}
+
+impl std::str::FromStr for CostGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "client" => Ok(CostGrouping::Client),
+            Some(("tag", key)) if !key.is_empty() => Ok(CostGrouping::Tag(key.to_string())),
+            _ => Err(format!("Invalid group_by '{}' (expected 'client' or 'tag:<key>')", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CostGroup {
+    /// client_id, or the tag value (OVERFLOW_BUCKET for values past the cardinality cap)
+    pub key: String,
+    pub invocations: u64,
+    pub tokens_used: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CostReport {
+    /// Calendar month (UTC, YYYY-MM)
+    pub period: String,
+    pub group_by: String,
+    pub total_cost_usd: f64,
+    /// Most expensive first
+    pub groups: Vec<CostGroup>,
+}
+
+/// Tags of a run: its metadata entries with string, number or bool values.
+/// Structured metadata values are not tags.
+pub fn run_tags(metadata: &HashMap<String, serde_json::Value>) -> Vec<(String, String)> {
+    metadata.iter()
+        .filter_map(|(key, value)| match value {
+            serde_json::Value::String(s) => Some((key.clone(), s.clone())),
+            serde_json::Value::Number(n) => Some((key.clone(), n.to_string())),
+            serde_json::Value::Bool(b) => Some((key.clone(), b.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AgentCost {
+    pub agent_id: String,
+    pub model: ModelVariant,
+    pub tokens_used: usize,
+    pub cost_usd: f64,
+}
+
+/// GET /runtime/:run_id/cost_breakdown
+#[derive(Debug, Clone, Serialize)]
+pub struct CostBreakdown {
+    pub run_id: String,
+    pub total_usd: f64,
+    /// total_usd plus the extrapolated cost of agents still running
+    pub projected_total_usd: f64,
+    /// One entry per agent and model it ran on, most expensive first
+    pub by_agent: Vec<AgentCost>,
+    /// Keyed by model variant
+    pub by_model: HashMap<String, f64>,
+}
+
+fn usd(variant: &ModelVariant, tokens: f64) -> f64 {
+    tokens * variant.usd_per_million_tokens() / 1_000_000.0
+}
+
+/// Cost of every invocation of the run, priced like the rollups. `models` maps agent ids to
+/// their configured variant, used to price agents that have not reported yet.
+///
+/// Each running agent is extrapolated at its own token rate so far (tokens of its Running
+/// invocation / elapsed ms), or the run's average rate when it has reported nothing, up to
+/// the run's average invocation latency: rate * estimated_remaining_ms is added on top.
+pub fn breakdown(state: &RuntimeState, models: &HashMap<String, ModelVariant>, now: DateTime<Utc>) -> CostBreakdown {
+    let mut per_agent: Vec<AgentCost> = Vec::new();
+    for invocation in &state.invocations {
+        match per_agent.iter_mut().find(|c| c.agent_id == invocation.agent_id && c.model == invocation.model_variant) {
+            Some(cost) => cost.tokens_used += invocation.tokens_used,
+            None => per_agent.push(AgentCost {
+                agent_id: invocation.agent_id.clone(),
+                model: invocation.model_variant.clone(),
+                tokens_used: invocation.tokens_used,
+                cost_usd: 0.0,
+            }),
+        }
+    }
+    let mut by_model: HashMap<String, f64> = HashMap::new();
+    for cost in &mut per_agent {
+        cost.cost_usd = usd(&cost.model, cost.tokens_used as f64);
+        *by_model.entry(cost.model.as_str().to_string()).or_default() += cost.cost_usd;
+    }
+    per_agent.sort_by(|a, b| b.cost_usd.total_cmp(&a.cost_usd).then(a.agent_id.cmp(&b.agent_id)));
+    let total_usd: f64 = per_agent.iter().map(|c| c.cost_usd).sum();
+
+    // Run averages over finished invocations
+    let finished: Vec<&AgentInvocation> = state.invocations.iter()
+        .filter(|i| matches!(i.status, InvocationStatus::Success | InvocationStatus::Failed) && i.latency_ms > 0)
+        .collect();
+    let finished_ms: u64 = finished.iter().map(|i| i.latency_ms).sum();
+    let avg_rate = if finished_ms > 0 { finished.iter().map(|i| i.tokens_used).sum::<usize>() as f64 / finished_ms as f64 } else { 0.0 };
+    let avg_latency_ms = if finished.is_empty() { 0.0 } else { finished_ms as f64 / finished.len() as f64 };
+
+    let elapsed_since = |ts: &str| DateTime::parse_from_rfc3339(ts).ok()
+        .map(|t| (now - t.with_timezone(&Utc)).num_milliseconds().max(0) as f64);
+    let projected_extra: f64 = state.active_agents.iter()
+        .map(|agent_id| {
+            let running = state.invocations.iter().rev()
+                .find(|i| &i.agent_id == agent_id)
+                .filter(|i| i.status == InvocationStatus::Running);
+            let model = models.get(agent_id).or(running.map(|i| &i.model_variant)).cloned().unwrap_or_default();
+            let started = running.map(|i| i.started_at.as_str()).or(state.active_since.get(agent_id).map(String::as_str));
+            let elapsed_ms = started.and_then(elapsed_since).unwrap_or(0.0);
+            let remaining_ms = (avg_latency_ms - elapsed_ms).max(0.0);
+            match running.map(|i| i.tokens_used).filter(|t| *t > 0 && elapsed_ms > 0.0) {
+                // Its own tokens are already in total_usd
+                Some(tokens) => usd(&model, tokens as f64 / elapsed_ms * remaining_ms),
+                None => usd(&model, avg_rate * (elapsed_ms + remaining_ms)),
+            }
+        })
+        .sum();
+
+    CostBreakdown {
+        run_id: state.run_id.clone(),
+        total_usd,
+        projected_total_usd: total_usd + projected_extra,
+        by_agent: per_agent,
+        by_model,
+    }
+}
+
+/// Rollups are updated per recorded invocation and mirrored to Redis (`costs:{month}` hashes
+/// whose fields are JSON arrays such as `["tag","project","q3","tokens_used"]`).
+pub struct CostTracker {
+    months: DashMap<String, MonthRollups>,
+    redis_client: Option<redis::Client>,
+}
+
+impl CostTracker {
+    pub fn new(redis_client: Option<redis::Client>) -> Self {
+        Self {
+            months: DashMap::new(),
+            redis_client,
+        }
+    }
+
+    pub fn record_invocation(&self, client_id: &str, tags: &[(String, String)], invocation: &AgentInvocation) {
+        self.record(&current_month(), client_id, tags, CostRollup::of(invocation));
+    }
+
+    fn record(&self, month: &str, client_id: &str, tags: &[(String, String)], delta: CostRollup) {
+        let mut buckets = vec![vec!["client".to_string(), client_id.to_string()]];
+        {
+            let mut rollups = self.months.entry(month.to_string()).or_default();
+            rollups.clients.entry(client_id.to_string()).or_default().add(&delta);
+
+            for (key, value) in tags {
+                let key = if rollups.tags.contains_key(key) || rollups.tags.len() < MAX_TAG_KEYS { key.as_str() } else { OVERFLOW_BUCKET };
+                let values = rollups.tags.entry(key.to_string()).or_default();
+                let value = if values.contains_key(value) || values.len() < MAX_TAG_VALUES { value.as_str() } else { OVERFLOW_BUCKET };
+                values.entry(value.to_string()).or_default().add(&delta);
+                buckets.push(vec!["tag".to_string(), key.to_string(), value.to_string()]);
+            }
+        }
+
+        if let Some(client) = self.redis_client.clone() {
+            let key = format!("{}{}", COSTS_KEY_PREFIX, month);
+            tokio::spawn(async move {
+                let mut pipe = redis::pipe();
+                for bucket in &buckets {
+                    for (metric, amount) in [("invocations", delta.invocations), ("tokens_used", delta.tokens_used), ("cost_micro_usd", delta.cost_micro_usd)] {
+                        let mut field = bucket.clone();
+                        field.push(metric.to_string());
+                        pipe.hincr(&key, serde_json::to_string(&field).unwrap_or_default(), amount).ignore();
+                    }
+                }
+                let result: redis::RedisResult<()> = async {
+                    let mut con = client.get_async_connection().await?;
+                    pipe.query_async(&mut con).await
+                }.await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to persist cost rollup {}: {}", key, e);
+                }
+            });
+        }
+    }
+
+    /// Restore persisted rollups at boot
+    pub async fn load_from_redis(&self) {
+        let Some(client) = &self.redis_client else { return };
+        let mut con = match client.get_async_connection().await {
+            Ok(con) => con,
+            Err(e) => {
+                tracing::warn!("Cost rollups not restored: {}", e);
+                return;
+            }
+        };
+
+        let keys = redis_keys::scan_keys(&mut con, &format!("{}*", COSTS_KEY_PREFIX)).await.unwrap_or_default();
+        for key in keys {
+            let Some(month) = key.strip_prefix(COSTS_KEY_PREFIX) else { continue };
+            let fields: HashMap<String, u64> = con.hgetall(&key).await.unwrap_or_default();
+            let mut rollups = self.months.entry(month.to_string()).or_default();
+            for (field, amount) in fields {
+                let Ok(parts) = serde_json::from_str::<Vec<String>>(&field) else { continue };
+                let rollup = match parts.as_slice() {
+                    [kind, client_id, _] if kind == "client" => rollups.clients.entry(client_id.clone()).or_default(),
+                    [kind, tag, value, _] if kind == "tag" => rollups.tags.entry(tag.clone()).or_default().entry(value.clone()).or_default(),
+                    _ => continue,
+                };
+                match parts.last().map(String::as_str) {
+                    Some("invocations") => rollup.invocations = amount,
+                    Some("tokens_used") => rollup.tokens_used = amount,
+                    Some("cost_micro_usd") => rollup.cost_micro_usd = amount,
+                    _ => {}
+                }
+            }
+        }
+        tracing::info!("Restored cost rollups for {} months", self.months.len());
+    }
+
+    /// `period` defaults to the current month
+    pub fn report(&self, grouping: &CostGrouping, period: Option<&str>) -> CostReport {
+        let period = period.map(str::to_string).unwrap_or_else(current_month);
+        let mut groups: Vec<CostGroup> = self.months.get(&period)
+            .and_then(|rollups| {
+                let selected = match grouping {
+                    CostGrouping::Client => &rollups.clients,
+                    CostGrouping::Tag(key) => rollups.tags.get(key)?,
+                };
+                Some(selected.iter().map(|(key, r)| CostGroup {
+                    key: key.clone(),
+                    invocations: r.invocations,
+                    tokens_used: r.tokens_used,
+                    cost_usd: r.cost_usd(),
+                }).collect())
+            })
+            .unwrap_or_default();
+        groups.sort_by(|a, b| b.cost_usd.total_cmp(&a.cost_usd).then_with(|| a.key.cmp(&b.key)));
+
+        CostReport {
+            group_by: match grouping {
+                CostGrouping::Client => "client".to_string(),
+                CostGrouping::Tag(key) => format!("tag:{}", key),
+            },
+            total_cost_usd: groups.iter().map(|g| g.cost_usd).sum(),
+            period,
+            groups,
+        }
+    }
+
+    /// Current-month counters in Prometheus text format. /metrics is unauthenticated, so spend
+    /// is exported as kernel-wide totals only; per-client figures stay behind the admin-only
+    /// GET /metrics/costs. Tag series are bounded by MAX_TAG_KEYS x MAX_TAG_VALUES.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let Some(rollups) = self.months.get(&current_month()) else { return out };
+        let mut total = CostRollup::default();
+        for r in rollups.clients.values() {
+            total.add(r);
+        }
+
+        let metrics: [(&str, &str, RollupField); 3] = [
+            ("raro_cost_usd_total", "Estimated spend this month", |r| r.cost_usd().to_string()),
+            ("raro_tokens_total", "Tokens recorded this month", |r| r.tokens_used.to_string()),
+            ("raro_invocations_total", "Invocations recorded this month", |r| r.invocations.to_string()),
+        ];
+        for (name, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value(&total));
+
+            let tag_name = name.replacen("raro_", "raro_tag_", 1);
+            let _ = writeln!(out, "# HELP {} {} by run tag", tag_name, help);
+            let _ = writeln!(out, "# TYPE {} counter", tag_name);
+            for (tag, values) in sorted(&rollups.tags) {
+                for (tag_value, r) in sorted(values) {
+                    let _ = writeln!(out, "{}{{tag=\"{}\",value=\"{}\"}} {}", tag_name, escape_label(tag), escape_label(tag_value), value(r));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Renders one counter of a rollup as a sample value
+type RollupField = fn(&CostRollup) -> String;
+
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_costs_roll_up_by_client_and_tag_with_overflow() {
+        let tracker = CostTracker::new(None);
+        let thinking = CostRollup { invocations: 1, tokens_used: 500_000, cost_micro_usd: micro_usd(&ModelVariant::Thinking, 500_000) };
+        tracker.record("2024-06", "alice", &tags(&[("project", "q3"), ("team", "fin")]), thinking);
+        tracker.record("2024-06", "alice", &tags(&[("project", "q3")]), thinking);
+        tracker.record("2024-06", "bob", &tags(&[("project", "q4")]), CostRollup { cost_micro_usd: 500_000, ..thinking });
+
+        let report = tracker.report(&"tag:project".parse().unwrap(), Some("2024-06"));
+        assert_eq!(report.groups.iter().map(|g| (g.key.as_str(), g.cost_usd)).collect::<Vec<_>>(), vec![("q3", 4.0), ("q4", 0.5)]);
+        assert_eq!(report.total_cost_usd, 4.5);
+        let by_client = tracker.report(&CostGrouping::Client, Some("2024-06"));
+        assert_eq!((by_client.groups[0].key.as_str(), by_client.groups[0].invocations), ("alice", 2));
+        assert!(tracker.report(&CostGrouping::Client, Some("2024-05")).groups.is_empty());
+
+        for i in 0..MAX_TAG_VALUES + 5 {
+            tracker.record("2024-06", "carol", &tags(&[("ticket", &format!("T-{}", i))]), thinking);
+        }
+        let tickets = tracker.report(&"tag:ticket".parse().unwrap(), Some("2024-06"));
+        assert_eq!(tickets.groups.len(), MAX_TAG_VALUES + 1);
+        assert_eq!(tickets.groups.iter().find(|g| g.key == OVERFLOW_BUCKET).unwrap().invocations, 5);
+
+        assert!("tag:".parse::<CostGrouping>().is_err());
+        assert_eq!(escape_label("a\"b\\"), "a\\\"b\\\\");
+    }
+
+    #[test]
+    fn test_breakdown_by_agent_and_model_with_projection() {
+        let invocation = |agent: &str, model: &str, tokens: usize, latency_ms: u64, status: &str, started_at: &str| serde_json::json!({
+            "id": format!("{}-{}", agent, model), "agent_id": agent, "model_variant": model, "thought_signature": null,
+            "tools_used": [], "tokens_used": tokens, "latency_ms": latency_ms, "status": status,
+            "started_at": started_at, "timestamp": "2024-01-01T00:00:09Z", "artifact_id": null, "error_message": null
+        });
+        let state: RuntimeState = serde_json::from_value(serde_json::json!({
+            "run_id": "run-1", "workflow_id": "wf", "client_id": "public", "status": "running",
+            "active_agents": ["c", "d"], "completed_agents": ["a", "b"], "failed_agents": [],
+            "invocations": [
+                invocation("a", "fast", 1_000_000, 1_000, "success", "2024-01-01T00:00:01Z"),
+                invocation("b", "thinking", 500_000, 1_000, "success", "2024-01-01T00:00:02Z"),
+                invocation("b", "reasoning", 100_000, 1_000, "success", "2024-01-01T00:00:03Z"),
+                // Half a second in at 500 tokens/ms
+                invocation("c", "thinking", 250_000, 0, "running", "2024-01-01T00:00:09.500Z"),
+            ],
+            "active_since": { "c": "2024-01-01T00:00:09.500Z", "d": "2024-01-01T00:00:09.800Z" },
+            "total_tokens_used": 1_850_000, "start_time": "2024-01-01T00:00:00Z", "end_time": null
+        })).unwrap();
+        let models = HashMap::from([("d".to_string(), ModelVariant::Fast)]);
+        let now = DateTime::parse_from_rfc3339("2024-01-01T00:00:10Z").unwrap().with_timezone(&Utc);
+
+        let breakdown = breakdown(&state, &models, now);
+        let agents: Vec<(&str, &str, f64)> = breakdown.by_agent.iter().map(|c| (c.agent_id.as_str(), c.model.as_str(), c.cost_usd)).collect();
+        assert_eq!(agents, vec![("b", "thinking", 2.0), ("b", "reasoning", 1.0), ("c", "thinking", 1.0), ("a", "fast", 0.5)]);
+        assert_eq!(breakdown.by_model["thinking"], 3.0);
+        assert_eq!(breakdown.total_usd, 4.5);
+
+        // c: 500 tokens/ms for the 500ms left of the 1s average latency = $1.00 on thinking.
+        // d: nothing reported, so the run average (1.6M tokens / 3s) over a full 1s on fast.
+        let expected = 4.5 + 1.0 + 1_600_000.0 / 3_000.0 * 1_000.0 * 0.50 / 1_000_000.0;
+        assert!((breakdown.projected_total_usd - expected).abs() < 1e-9, "{}", breakdown.projected_total_usd);
+    }
+}
@@ -5,23 +5,49 @@
 
 use std::collections::{HashMap, HashSet, VecDeque};
 use thiserror::Error;
+use crate::models::{AgentNodeConfig, Dependency, ValidationError, WorkflowConfig};
 
 #[derive(Error, Debug)]
 pub enum DAGError {
     #[error("Cycle detected in DAG")]
     CycleDetected,
-    #[error("Invalid node: {0}")]
+    /// The node id itself is malformed (empty or whitespace-only)
+    #[error("Invalid node id: '{0}'")]
     InvalidNode(String),
+    /// The id is well-formed but no such node was added to the DAG
     #[error("Dependency not found: {0}")]
     DependencyNotFound(String),
     #[error("Edge not found: {0} -> {1}")]
     EdgeNotFound(String, String),
 }
 
+impl DAGError {
+    /// The validation error a submission of `config` reports when its graph fails to load,
+    /// naming the offending agent or edge like WorkflowConfig::validate does
+    pub fn into_validation_error(self, config: &WorkflowConfig) -> ValidationError {
+        match self {
+            DAGError::InvalidNode(agent_id) => ValidationError::InvalidAgentId { agent_id, reason: "is blank".to_string() },
+            DAGError::DependencyNotFound(dep_id) => {
+                let agent_id = config.agents.iter()
+                    .find(|a| a.depends_on.iter().any(|d| d.agent == dep_id))
+                    .map(|a| a.id.clone())
+                    .unwrap_or_default();
+                ValidationError::UnknownDependency { agent_id, dep_id }
+            }
+            DAGError::EdgeNotFound(dep_id, agent_id) => ValidationError::UnknownDependency { agent_id, dep_id },
+            DAGError::CycleDetected => config.validate().err().into_iter().flatten()
+                .find(|e| matches!(e, ValidationError::CycleDetected(_)))
+                .unwrap_or(ValidationError::CycleDetected(Vec::new())),
+        }
+    }
+}
+
 #[derive(Clone, Debug)] // Added Clone/Debug for easier state management
+#[allow(clippy::upper_case_acronyms)]
 pub struct DAG {
     nodes: HashSet<String>,
     edges: HashMap<String, Vec<String>>, // Adjacency list: Source -> [Targets]
+    edge_specs: HashMap<(String, String), Dependency>, // Non-plain depends_on entries: (Source, Target) -> spec
 }
 
 impl DAG {
@@ -29,11 +55,41 @@ impl DAG {
         DAG {
             nodes: HashSet::new(),
             edges: HashMap::new(),
+            edge_specs: HashMap::new(),
         }
     }
 
-    /// Add a node to the DAG
+    /// Bulk-load a workflow's agents and depends_on edges. Skips the per-edge cycle DFS of
+    /// add_edge and rejects cycles with a single topological pass at the end instead.
+    pub fn from_config(config: &WorkflowConfig) -> Result<Self, DAGError> {
+        let mut dag = DAG::new();
+        if let Some(agent) = config.agents.iter().find(|a| a.id.trim().is_empty()) {
+            return Err(DAGError::InvalidNode(agent.id.clone()));
+        }
+        dag.nodes = config.agents.iter().map(|a| a.id.clone()).collect();
+
+        for agent in &config.agents {
+            for dep in &agent.depends_on {
+                if !dag.nodes.contains(&dep.agent) {
+                    return Err(DAGError::DependencyNotFound(dep.agent.clone()));
+                }
+                let targets = dag.edges.entry(dep.agent.clone()).or_default();
+                if !targets.contains(&agent.id) {
+                    targets.push(agent.id.clone());
+                }
+            }
+            dag.annotate_edges(agent);
+        }
+
+        dag.topological_sort()?;
+        Ok(dag)
+    }
+
+    /// Add a node to the DAG. Empty or whitespace-only ids are rejected.
     pub fn add_node(&mut self, node_id: String) -> Result<(), DAGError> {
+        if node_id.trim().is_empty() {
+            return Err(DAGError::InvalidNode(node_id));
+        }
         self.nodes.insert(node_id);
         Ok(())
     }
@@ -41,10 +97,10 @@ impl DAG {
     /// Add an edge from source to target (Idempotent)
     pub fn add_edge(&mut self, from: String, to: String) -> Result<(), DAGError> {
         if !self.nodes.contains(&from) {
-            return Err(DAGError::InvalidNode(from));
+            return Err(DAGError::DependencyNotFound(from));
         }
         if !self.nodes.contains(&to) {
-            return Err(DAGError::InvalidNode(to));
+            return Err(DAGError::DependencyNotFound(to));
         }
 
         // Idempotency Check: Don't add if already exists
@@ -59,7 +115,7 @@ impl DAG {
             return Err(DAGError::CycleDetected);
         }
 
-        self.edges.entry(from).or_insert_with(Vec::new).push(to);
+        self.edges.entry(from).or_default().push(to);
         Ok(())
     }
 
@@ -74,6 +130,19 @@ impl DAG {
         Err(DAGError::EdgeNotFound(from.to_string(), to.to_string()))
     }
 
+    /// Record what each of the agent's incoming edges passes (plain entries need no record)
+    pub fn annotate_edges(&mut self, agent: &AgentNodeConfig) {
+        self.edge_specs.retain(|(_, to), _| *to != agent.id);
+        for dep in agent.depends_on.iter().filter(|d| !d.is_plain()) {
+            self.edge_specs.insert((dep.agent.clone(), agent.id.clone()), dep.clone());
+        }
+    }
+
+    /// Label for an edge in topology exports, e.g. `signature` or `output as research`
+    pub fn edge_label(&self, from: &str, to: &str) -> Option<String> {
+        self.edge_specs.get(&(from.to_string(), to.to_string())).and_then(|d| d.label())
+    }
+
     /// Clear all incoming edges for a specific node.
     /// Essential for "Update" operations where dependencies might change.
     pub fn clear_incoming_edges(&mut self, node_id: &str) {
@@ -86,7 +155,7 @@ impl DAG {
     /// Used for pruning redundant nodes during delegation
     pub fn remove_node(&mut self, node_id: &str) -> Result<(), DAGError> {
         if !self.nodes.contains(node_id) {
-            return Err(DAGError::InvalidNode(node_id.to_string()));
+            return Err(DAGError::DependencyNotFound(node_id.to_string()));
         }
 
         // 1. Remove the node from the set
@@ -99,6 +168,7 @@ impl DAG {
         for targets in self.edges.values_mut() {
             targets.retain(|target| target != node_id);
         }
+        self.edge_specs.retain(|(from, to), _| from != node_id && to != node_id);
 
         Ok(())
     }
@@ -193,6 +263,82 @@ impl DAG {
         deps
     }
     
+    /// All transitive dependencies of a node (excluding the node itself)
+    pub fn ancestors(&self, node_id: &str) -> Result<HashSet<String>, DAGError> {
+        if !self.nodes.contains(node_id) {
+            return Err(DAGError::DependencyNotFound(node_id.to_string()));
+        }
+
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<String> = self.get_dependencies(node_id).into();
+        while let Some(dep) = queue.pop_front() {
+            if seen.insert(dep.clone()) {
+                queue.extend(self.get_dependencies(&dep));
+            }
+        }
+        Ok(seen)
+    }
+
+    /// All transitive dependents of a node (excluding the node itself)
+    pub fn descendants(&self, node_id: &str) -> Result<HashSet<String>, DAGError> {
+        if !self.nodes.contains(node_id) {
+            return Err(DAGError::DependencyNotFound(node_id.to_string()));
+        }
+
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<String> = self.get_children(node_id).into();
+        while let Some(child) = queue.pop_front() {
+            if seen.insert(child.clone()) {
+                queue.extend(self.get_children(&child));
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Sub-graph of `roots` and everything downstream of them, e.g. the remaining work when
+    /// resuming from a checkpoint. Unreachable nodes are dropped; unknown roots are ignored.
+    #[allow(dead_code)] // Graph API: not yet used by the execution loop
+    pub fn prune_unreachable_from(&self, roots: &[&str]) -> DAG {
+        let reachable = self.reachable_from(roots);
+        self.induced(|node| reachable.contains(node))
+    }
+
+    /// Inverse of prune_unreachable_from: drops `roots` and everything downstream of them,
+    /// e.g. work that has already completed.
+    #[allow(dead_code)] // Graph API: not yet used by the execution loop
+    pub fn prune_reachable_from(&self, roots: &[&str]) -> DAG {
+        let reachable = self.reachable_from(roots);
+        self.induced(|node| !reachable.contains(node))
+    }
+
+    /// `roots` plus all their descendants
+    fn reachable_from(&self, roots: &[&str]) -> HashSet<String> {
+        let mut reachable = HashSet::new();
+        for root in roots {
+            if let Ok(descendants) = self.descendants(root) {
+                reachable.insert(root.to_string());
+                reachable.extend(descendants);
+            }
+        }
+        reachable
+    }
+
+    /// Copy holding only the nodes accepted by `keep` and the edges (with specs) between them
+    fn induced(&self, keep: impl Fn(&str) -> bool) -> DAG {
+        DAG {
+            nodes: self.nodes.iter().filter(|n| keep(n)).cloned().collect(),
+            edges: self.edges.iter()
+                .filter(|(from, _)| keep(from))
+                .map(|(from, targets)| (from.clone(), targets.iter().filter(|t| keep(t)).cloned().collect::<Vec<_>>()))
+                .filter(|(_, targets)| !targets.is_empty())
+                .collect(),
+            edge_specs: self.edge_specs.iter()
+                .filter(|((from, to), _)| keep(from) && keep(to))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+
     /// Export edges as a flat vector for UI visualization
     pub fn export_edges(&self) -> Vec<(String, String)> {
         let mut edge_list = Vec::new();
@@ -210,6 +356,7 @@ impl DAG {
     }
 
     /// Get dependents for a given node
+    #[allow(dead_code)]
     pub fn get_dependents(&self, node_id: &str) -> Option<Vec<String>> {
         self.edges.get(node_id).cloned()
     }
@@ -245,6 +392,18 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_malformed_ids_and_unknown_nodes_distinguished() {
+        let mut dag = DAG::new();
+        assert!(matches!(dag.add_node("".to_string()), Err(DAGError::InvalidNode(_))));
+        assert!(matches!(dag.add_node("  \t".to_string()), Err(DAGError::InvalidNode(_))));
+        dag.add_node("a".to_string()).unwrap();
+
+        assert!(matches!(dag.add_edge("ghost".to_string(), "a".to_string()), Err(DAGError::DependencyNotFound(n)) if n == "ghost"));
+        assert!(matches!(dag.add_edge("a".to_string(), "ghost".to_string()), Err(DAGError::DependencyNotFound(n)) if n == "ghost"));
+        assert!(matches!(dag.remove_node("ghost"), Err(DAGError::DependencyNotFound(_))));
+    }
+
     #[test]
     fn test_add_edge_idempotency() {
         let mut dag = DAG::new();
@@ -319,4 +478,86 @@ mod tests {
         let order = dag.topological_sort().unwrap();
         assert_eq!(order.len(), 4);
     }
+
+    #[test]
+    fn test_ancestors() {
+        let mut dag = DAG::new();
+        for n in ["a", "b", "c", "d"] {
+            dag.add_node(n.to_string()).unwrap();
+        }
+
+        // a->b->d, c->d
+        dag.add_edge("a".to_string(), "b".to_string()).unwrap();
+        dag.add_edge("b".to_string(), "d".to_string()).unwrap();
+        dag.add_edge("c".to_string(), "d".to_string()).unwrap();
+
+        let ancestors = dag.ancestors("d").unwrap();
+        assert_eq!(ancestors, ["a", "b", "c"].iter().map(|s| s.to_string()).collect());
+        assert!(dag.ancestors("a").unwrap().is_empty());
+        assert!(dag.ancestors("missing").is_err());
+    }
+
+    fn config(agents: serde_json::Value) -> WorkflowConfig {
+        serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1, "agents": agents
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_from_config_bulk_loads_and_rejects_cycles() {
+        let dag = DAG::from_config(&config(serde_json::json!([
+            { "id": "a", "role": "worker", "prompt": "", "position": null },
+            { "id": "b", "role": "worker", "prompt": "", "position": null, "depends_on": ["a", "a"] },
+            { "id": "c", "role": "worker", "prompt": "", "position": null, "depends_on": ["a", "b"] }
+        ]))).unwrap();
+        assert_eq!(dag.topological_sort().unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(dag.get_children("a").len(), 2);
+
+        let cyclic = config(serde_json::json!([
+            { "id": "a", "role": "worker", "prompt": "", "position": null, "depends_on": ["b"] },
+            { "id": "b", "role": "worker", "prompt": "", "position": null, "depends_on": ["a"] }
+        ]));
+        assert!(matches!(DAG::from_config(&cyclic), Err(DAGError::CycleDetected)));
+        assert_eq!(DAG::from_config(&cyclic).unwrap_err().into_validation_error(&cyclic),
+            ValidationError::CycleDetected(vec!["a".to_string(), "b".to_string()]));
+
+        let dangling = config(serde_json::json!([
+            { "id": "a", "role": "worker", "prompt": "", "position": null, "depends_on": ["ghost"] }
+        ]));
+        assert!(matches!(DAG::from_config(&dangling), Err(DAGError::DependencyNotFound(n)) if n == "ghost"));
+        assert_eq!(DAG::from_config(&dangling).unwrap_err().into_validation_error(&dangling),
+            ValidationError::UnknownDependency { agent_id: "a".to_string(), dep_id: "ghost".to_string() });
+
+        // A long chain is one linear pass rather than a DFS per edge
+        let chain: Vec<serde_json::Value> = (0..5_000)
+            .map(|i| serde_json::json!({
+                "id": format!("n{}", i), "role": "worker", "prompt": "", "position": null,
+                "depends_on": if i == 0 { vec![] } else { vec![format!("n{}", i - 1)] }
+            }))
+            .collect();
+        assert_eq!(DAG::from_config(&config(serde_json::json!(chain))).unwrap().topological_sort().unwrap().len(), 5_000);
+    }
+
+    #[test]
+    fn test_prune_reachable_and_unreachable() {
+        // a->b->d, c->d, d->e, x (isolated)
+        let mut dag = DAG::new();
+        for n in ["a", "b", "c", "d", "e", "x"] {
+            dag.add_node(n.to_string()).unwrap();
+        }
+        for (from, to) in [("a", "b"), ("b", "d"), ("c", "d"), ("d", "e")] {
+            dag.add_edge(from.to_string(), to.to_string()).unwrap();
+        }
+        let sorted = |mut v: Vec<String>| { v.sort(); v };
+
+        let remaining = dag.prune_unreachable_from(&["b", "missing"]);
+        assert_eq!(sorted(remaining.export_nodes()), vec!["b", "d", "e"]);
+        assert_eq!(remaining.export_edges().len(), 2);
+        assert_eq!(remaining.topological_sort().unwrap(), vec!["b", "d", "e"]);
+
+        let pruned = dag.prune_reachable_from(&["b"]);
+        assert_eq!(sorted(pruned.export_nodes()), vec!["a", "c", "x"]);
+        assert!(pruned.export_edges().is_empty());
+        assert!(pruned.topological_sort().is_ok());
+    }
 }
@@ -0,0 +1,103 @@
+// [[RARO]]/apps/kernel-server/src/egress.rs
+// Purpose: Outbound policy for URLs that come from workflows (push-mode orchestrator webhooks,
+//          lifecycle hooks). Such a URL is only dialled when every address its host resolves
+//          to is publicly routable, so a workflow can't point the kernel at loopback,
+//          private-network or link-local services (e.g. cloud metadata at 169.254.169.254).
+//          Hosts in RARO_EGRESS_ALLOWLIST skip the address check (in-cluster orchestrators).
+// Architecture: Domain Helper Layer
+// Dependencies: reqwest (Url), tokio (DNS)
+//
+// The check runs right before each delivery rather than at registration, so a DNS record
+// changed after the workflow was accepted is still caught.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug, Clone, Default)]
+pub struct EgressPolicy {
+    /// Lower-cased host names or IP literals exempt from the address check
+    allowed_hosts: Vec<String>,
+}
+
+impl EgressPolicy {
+    /// RARO_EGRESS_ALLOWLIST: comma-separated hosts (unset = none)
+    pub fn from_env() -> Self {
+        let hosts = std::env::var("RARO_EGRESS_ALLOWLIST").unwrap_or_default();
+        Self::allowing(hosts.split(',').map(str::trim).filter(|h| !h.is_empty()))
+    }
+
+    pub fn allowing<'a>(hosts: impl IntoIterator<Item = &'a str>) -> Self {
+        Self { allowed_hosts: hosts.into_iter().map(str::to_ascii_lowercase).collect() }
+    }
+
+    /// Ok when `url` is http(s) and its host is allowlisted or resolves only to public addresses
+    pub async fn check(&self, url: &str) -> Result<(), String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL {}: {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(format!("{} is not an http(s) URL", url));
+        }
+        let host = parsed.host_str().ok_or_else(|| format!("{} has no host", url))?.to_ascii_lowercase();
+        if self.allowed_hosts.contains(&host) {
+            return Ok(());
+        }
+        let port = parsed.port_or_known_default().unwrap_or(443);
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        let addresses: Vec<IpAddr> = match literal.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => tokio::net::lookup_host((host.as_str(), port)).await
+                .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+                .map(|addr| addr.ip())
+                .collect(),
+        };
+        match addresses.iter().find(|ip| !is_public(**ip)) {
+            Some(ip) => Err(format!("{} resolves to non-public address {}", host, ip)),
+            None if addresses.is_empty() => Err(format!("{} resolves to no address", host)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_v4(v4),
+            None => is_public_v6(v6),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64; // 100.64.0.0/10 (CGNAT)
+    !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+        || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation() || shared)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    let unique_local = (first & 0xfe00) == 0xfc00; // fc00::/7
+    let link_local = (first & 0xffc0) == 0xfe80; // fe80::/10
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_non_public_addresses_are_refused_unless_allowlisted() {
+        let policy = EgressPolicy::default();
+        for url in [
+            "http://127.0.0.1:8080/hook", "http://10.1.2.3/hook", "http://169.254.169.254/latest/meta-data",
+            "http://192.168.0.10/hook", "http://100.64.0.1/hook", "http://[::1]/hook", "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook", "http://localhost:9000/hook", "file:///etc/passwd",
+        ] {
+            assert!(policy.check(url).await.is_err(), "{} should be refused", url);
+        }
+        assert!(policy.check("https://93.184.216.34/hook").await.is_ok());
+
+        let allowed = EgressPolicy::allowing(["127.0.0.1", "Orchestrator.internal"]);
+        assert!(allowed.check("http://127.0.0.1:8080/hook").await.is_ok());
+        assert!(allowed.check("http://orchestrator.internal/dispatch").await.is_ok());
+        assert!(allowed.check("http://10.1.2.3/hook").await.is_err());
+    }
+}
@@ -0,0 +1,194 @@
+// [[RARO]]/apps/kernel-server/src/event_limits.rs
+// Purpose: Admission control for agent-sourced events (IntermediateLog, ToolCall), so a chatty
+//          agent can't grow a run's event log without bound or lag every bus subscriber. Each run
+//          gets a one-second budget overall and per event type, and keeps at most a fixed number
+//          of such events (oldest evicted first). Rejected events are only counted: the counts
+//          are reported in one EventsDropped event once their window has closed, and exported
+//          per run as metrics. Kernel lifecycle events are never limited (replay needs them).
+// Architecture: Domain Helper Layer
+// Dependencies: DashMap
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use crate::events::EventType;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventLimits {
+    /// Agent events per run per second, all types together
+    pub per_run_per_sec: u32,
+    /// Agent events per run per second of any single type
+    pub per_type_per_sec: u32,
+    /// Agent events a run's log keeps before evicting the oldest
+    pub max_buffered_per_run: usize,
+}
+
+impl Default for EventLimits {
+    fn default() -> Self {
+        Self {
+            per_run_per_sec: 200,
+            per_type_per_sec: 100,
+            max_buffered_per_run: 10_000,
+        }
+    }
+}
+
+impl EventLimits {
+    /// RARO_EVENT_RATE_PER_RUN, RARO_EVENT_RATE_PER_TYPE, RARO_EVENT_LOG_MAX_PER_RUN (unset or 0 = default)
+    pub fn from_env() -> Self {
+        let read = |key: &str| std::env::var(key).ok().and_then(|v| v.parse::<u64>().ok()).filter(|v| *v > 0);
+        let defaults = Self::default();
+        Self {
+            per_run_per_sec: read("RARO_EVENT_RATE_PER_RUN").map(|v| v as u32).unwrap_or(defaults.per_run_per_sec),
+            per_type_per_sec: read("RARO_EVENT_RATE_PER_TYPE").map(|v| v as u32).unwrap_or(defaults.per_type_per_sec),
+            max_buffered_per_run: read("RARO_EVENT_LOG_MAX_PER_RUN").map(|v| v as usize).unwrap_or(defaults.max_buffered_per_run),
+        }
+    }
+}
+
+/// Events produced at the agent's pace rather than the kernel's
+pub fn is_agent_sourced(event_type: &EventType) -> bool {
+    matches!(event_type, EventType::IntermediateLog | EventType::ToolCall)
+}
+
+pub fn type_name(event_type: &EventType) -> String {
+    format!("{:?}", event_type)
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct DropCounts {
+    /// Rejected by the per-second limits
+    pub rate_limited: u64,
+    /// Stored, then evicted to keep the run under max_buffered_per_run
+    pub evicted: u64,
+}
+
+#[derive(Default)]
+struct RunWindow {
+    started: Option<Instant>,
+    total: u32,
+    per_type: HashMap<String, u32>,
+    /// Agent events currently held in the run's log
+    buffered: usize,
+    /// Rejections not yet reported, and the start of the window they began in
+    pending: BTreeMap<String, u64>,
+    pending_since: Option<Instant>,
+}
+
+pub struct EventLimiter {
+    pub limits: EventLimits,
+    windows: DashMap<String, RunWindow>, // run_id -> current window
+    /// Kept per event type only, so deleted runs leave nothing behind (their own EventsDropped
+    /// events carry the per-run detail)
+    dropped: DashMap<String, DropCounts>, // event type -> totals
+}
+
+impl EventLimiter {
+    pub fn new(limits: EventLimits) -> Self {
+        Self {
+            limits,
+            windows: DashMap::new(),
+            dropped: DashMap::new(),
+        }
+    }
+
+    /// Count an agent event against its run's window. Err holds the time until the window reopens.
+    pub fn admit(&self, run_id: &str, event_type: &str, now: Instant) -> Result<(), Duration> {
+        let mut window = self.windows.entry(run_id.to_string()).or_default();
+        let started = match window.started {
+            Some(started) if now.duration_since(started) < WINDOW => started,
+            _ => {
+                window.started = Some(now);
+                window.total = 0;
+                window.per_type.clear();
+                now
+            }
+        };
+
+        let of_type = window.per_type.get(event_type).copied().unwrap_or(0);
+        if window.total >= self.limits.per_run_per_sec || of_type >= self.limits.per_type_per_sec {
+            *window.pending.entry(event_type.to_string()).or_default() += 1;
+            window.pending_since.get_or_insert(started);
+            self.dropped.entry(event_type.to_string()).or_default().rate_limited += 1;
+            return Err(WINDOW.saturating_sub(now.duration_since(started)));
+        }
+        window.total += 1;
+        *window.per_type.entry(event_type.to_string()).or_default() += 1;
+        Ok(())
+    }
+
+    /// Rejections from a window that has closed, per event type; each is handed out once
+    pub fn take_dropped(&self, run_id: &str, now: Instant) -> Option<BTreeMap<String, u64>> {
+        let mut window = self.windows.get_mut(run_id)?;
+        let since = window.pending_since?;
+        if now.duration_since(since) < WINDOW {
+            return None;
+        }
+        window.pending_since = None;
+        Some(std::mem::take(&mut window.pending))
+    }
+
+    /// Note one more agent event stored for the run; true when the oldest must be evicted
+    pub fn buffer_one(&self, run_id: &str) -> bool {
+        let mut window = self.windows.entry(run_id.to_string()).or_default();
+        if window.buffered >= self.limits.max_buffered_per_run {
+            return true;
+        }
+        window.buffered += 1;
+        false
+    }
+
+    /// Forget a deleted run's window; the per-type drop totals keep its counts, as Prometheus
+    /// counters must not go down
+    pub fn forget_run(&self, run_id: &str) {
+        self.windows.remove(run_id);
+    }
+
+    pub fn record_eviction(&self, event_type: &str) {
+        self.dropped.entry(event_type.to_string()).or_default().evicted += 1;
+    }
+
+    /// Totals per event type, across every run
+    pub fn dropped_totals(&self) -> BTreeMap<String, DropCounts> {
+        self.dropped.iter().map(|e| (e.key().clone(), e.value().clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_reject_then_report_once_window_closes() {
+        let limiter = EventLimiter::new(EventLimits { per_run_per_sec: 3, per_type_per_sec: 2, max_buffered_per_run: 1 });
+        let t0 = Instant::now();
+
+        assert!(limiter.admit("run-1", "IntermediateLog", t0).is_ok());
+        assert!(limiter.admit("run-1", "IntermediateLog", t0).is_ok());
+        let retry = limiter.admit("run-1", "IntermediateLog", t0 + Duration::from_millis(300)).unwrap_err();
+        assert_eq!(retry, Duration::from_millis(700));
+        // Another type still fits under the run-wide limit, then that is exhausted too
+        assert!(limiter.admit("run-1", "ToolCall", t0).is_ok());
+        assert!(limiter.admit("run-1", "ToolCall", t0).is_err());
+        // Other runs are unaffected
+        assert!(limiter.admit("run-2", "IntermediateLog", t0).is_ok());
+
+        assert_eq!(limiter.take_dropped("run-1", t0 + Duration::from_millis(500)), None);
+        let dropped = limiter.take_dropped("run-1", t0 + WINDOW).unwrap();
+        assert_eq!(dropped, BTreeMap::from([("IntermediateLog".to_string(), 1), ("ToolCall".to_string(), 1)]));
+        assert_eq!(limiter.take_dropped("run-1", t0 + WINDOW * 2), None);
+
+        // A new window starts with a fresh budget
+        assert!(limiter.admit("run-1", "IntermediateLog", t0 + WINDOW).is_ok());
+
+        assert!(!limiter.buffer_one("run-1"));
+        assert!(limiter.buffer_one("run-1"));
+        limiter.record_eviction("IntermediateLog");
+        limiter.forget_run("run-1");
+        let totals = limiter.dropped_totals();
+        assert_eq!(totals["IntermediateLog"], DropCounts { rate_limited: 1, evicted: 1 });
+    }
+}
@@ -0,0 +1,113 @@
+// [[RARO]]/apps/kernel-server/src/event_stream.rs
+// Purpose: Per-run view of the shared event bus. Every subscriber (WebSocket, SSE, observers)
+//          holds its own receiver on the one broadcast channel, so publishing never waits on
+//          a slow reader: a reader that falls behind is dropped from the ring and resyncs
+//          from the run's event log instead.
+// Architecture: Domain Event Layer
+// Dependencies: Tokio broadcast
+
+use std::collections::VecDeque;
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::events::{EventType, RuntimeEvent};
+use crate::runtime::RARORuntime;
+
+/// Default ring size of the event bus (RARO_EVENT_BUS_CAPACITY overrides)
+pub const DEFAULT_EVENT_BUS_CAPACITY: usize = 1024;
+
+pub fn event_bus_capacity_from_env() -> usize {
+    std::env::var("RARO_EVENT_BUS_CAPACITY").ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_EVENT_BUS_CAPACITY)
+}
+
+/// Events of one run, in seq order, without gaps or duplicates
+pub struct RunEventStream {
+    run_id: String,
+    rx: broadcast::Receiver<RuntimeEvent>,
+    last_seq: u64,
+    backlog: VecDeque<RuntimeEvent>,
+}
+
+impl RunEventStream {
+    /// Starts at the live tail: only events emitted after subscribing are delivered
+    pub fn new(run_id: &str, rx: broadcast::Receiver<RuntimeEvent>, last_seq: u64) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            rx,
+            last_seq,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    /// Seq of the last event delivered (or already logged when subscribing)
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Next event of the run; None once the bus is closed. Cancel-safe (usable in select!).
+    pub async fn next(&mut self, runtime: &RARORuntime) -> Option<RuntimeEvent> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                self.last_seq = event.seq;
+                return Some(event);
+            }
+            match self.rx.recv().await {
+                Ok(event) if event.run_id == self.run_id && event.seq > self.last_seq => {
+                    self.last_seq = event.seq;
+                    return Some(event);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    // The bus dropped our oldest entries; the log still has them
+                    tracing::debug!("Subscriber of run {} lagged by {} events, resyncing from the log", self.run_id, missed);
+                    self.backlog = runtime.events_after(&self.run_id, self.last_seq).into_iter()
+                        .filter(|e| !matches!(e.event_type, EventType::StateChanged))
+                        .collect();
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_event_reaches_concurrent_subscribers() {
+        let runtime = Arc::new(RARORuntime::new());
+        let mut streams: Vec<RunEventStream> = (0..3).map(|_| runtime.subscribe_run("run-1")).collect();
+        assert_eq!(runtime.event_subscriber_count(), 3);
+
+        let readers: Vec<_> = streams.drain(..).map(|mut stream| {
+            let rt = runtime.clone();
+            tokio::spawn(async move { stream.next(&rt).await.map(|e| (e.run_id, e.seq)) })
+        }).collect();
+        runtime.emit_event(RuntimeEvent::new("run-other", EventType::AgentStarted, None, serde_json::json!({})));
+        runtime.emit_event(RuntimeEvent::new("run-1", EventType::AgentStarted, Some("a".to_string()), serde_json::json!({})));
+
+        for reader in readers {
+            assert_eq!(reader.await.unwrap(), Some(("run-1".to_string(), 1)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_resyncs_from_log() {
+        let mut runtime = RARORuntime::new();
+        runtime.event_bus = broadcast::channel(2).0;
+        let mut slow = runtime.subscribe_run("run-1");
+
+        for i in 0..5 {
+            runtime.emit_event(RuntimeEvent::new("run-1", EventType::IntermediateLog, None, serde_json::json!({ "i": i })));
+        }
+
+        let mut seqs = Vec::new();
+        for _ in 0..5 {
+            seqs.push(slow.next(&runtime).await.unwrap().seq);
+        }
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+    }
+}
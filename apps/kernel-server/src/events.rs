@@ -23,11 +23,28 @@ pub enum EventType {
     SystemIntervention,
     /// Real-time intermediate log from agent (tool calls, thoughts)
     IntermediateLog,
+    /// A session output file was promoted to persistent artifact storage
+    ArtifactPromoted,
+    /// A thought signature was stored for an agent
+    SignatureUpdated,
+    /// The run state was persisted; payload holds the per-field delta (see replay.rs)
+    StateChanged,
+    /// Token usage first crossed one of the workflow's budget_warning_thresholds; payload {used, budget, pct}
+    TokenBudgetWarning,
+    /// Agent events rejected by the run's rate limits; payload counts them per type (see event_limits.rs)
+    EventsDropped,
+    /// A completed run was judged against its workflow's success_criteria; payload is the RunVerdict
+    VerdictRecorded,
+    /// A configured fault was applied to an agent's attempt; payload {fault, attempt}
+    FaultInjected,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeEvent {
     pub id: String,
+    /// Position in the run's event log, from 1 (assigned when emitted)
+    #[serde(default)]
+    pub seq: u64,
     pub run_id: String,
     pub event_type: EventType,
     pub agent_id: Option<String>,
@@ -39,6 +56,7 @@ impl RuntimeEvent {
     pub fn new(run_id: &str, event_type: EventType, agent_id: Option<String>, payload: Value) -> Self {
         Self {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             run_id: run_id.to_string(),
             event_type,
             agent_id,
@@ -0,0 +1,273 @@
+// [[RARO]]/apps/kernel-server/src/execution_trace.rs
+// Purpose: Gantt-style execution traces rebuilt from invocation timestamps: when each agent
+//          ran, how long it waited after its dependencies finished, and the parallelism the
+//          run achieved against what its DAG layers allowed. Two traces can be compared to
+//          spot agents that were scheduled later than before (accidental serialization).
+// Architecture: Domain Helper Layer
+// Dependencies: chrono, DAG
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use crate::dag::DAG;
+use crate::models::{InvocationStatus, RuntimeState};
+
+/// Start-delay growth below this is treated as scheduling noise in comparisons
+pub const SCHEDULING_TOLERANCE_MS: i64 = 50;
+
+/// One invocation as a bar on the chart. Times are ms since the run started.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AgentInterval {
+    pub agent_id: String,
+    pub invocation_id: String,
+    /// 1 for the first invocation of the agent, 2 for its first retry, ...
+    pub attempt: u32,
+    pub status: InvocationStatus,
+    /// DAG layer (longest dependency chain above the agent)
+    pub layer: usize,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// When the last dependency finished (0 for roots)
+    pub ready_ms: u64,
+    /// start_ms - ready_ms: time spent ready but not yet running
+    pub start_delay_ms: u64,
+}
+
+/// Number of agents running from `at_ms` until the next sample
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ParallelismSample {
+    pub at_ms: u64,
+    pub running: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionTrace {
+    pub run_id: String,
+    pub workflow_id: String,
+    pub started_at: String,
+    /// End of the last invocation
+    pub makespan_ms: u64,
+    /// Ordered by start time
+    pub intervals: Vec<AgentInterval>,
+    pub parallelism: Vec<ParallelismSample>,
+    pub max_parallelism_achieved: usize,
+    /// Width of the widest DAG layer
+    pub max_parallelism_possible: usize,
+    pub layers: Vec<Vec<String>>,
+}
+
+/// An agent present in both runs, compared on its first attempt
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AgentTraceDiff {
+    pub agent_id: String,
+    pub base_start_delay_ms: u64,
+    pub other_start_delay_ms: u64,
+    /// other - base; positive means the agent waited longer after becoming ready
+    pub delay_change_ms: i64,
+    /// delay_change_ms exceeds SCHEDULING_TOLERANCE_MS
+    pub started_later: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceComparison {
+    pub base: ExecutionTrace,
+    pub other: ExecutionTrace,
+    /// Agents common to both runs started in the same relative order
+    pub same_order: bool,
+    pub agents: Vec<AgentTraceDiff>,
+    /// Agents flagged started_later, worst first
+    pub started_later: Vec<String>,
+}
+
+fn parse(ts: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(ts).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// Longest-path layering; agents in one layer could all run at once
+fn dag_layers(dag: &DAG) -> HashMap<String, usize> {
+    let mut layer_of = HashMap::new();
+    for node in dag.topological_sort().unwrap_or_default() {
+        let layer = dag.get_dependencies(&node).iter()
+            .filter_map(|dep| layer_of.get(dep))
+            .map(|l| l + 1)
+            .max()
+            .unwrap_or(0);
+        layer_of.insert(node, layer);
+    }
+    layer_of
+}
+
+/// Build the trace of a run. Invocations are stamped when they finish, so each bar spans
+/// latency_ms back from its timestamp. Replays, approval pauses and skips are not executions
+/// and are left out.
+pub fn build(state: &RuntimeState, dag: &DAG) -> ExecutionTrace {
+    let run_start = parse(&state.start_time);
+    let offset = |ts: DateTime<Utc>| match run_start {
+        Some(start) => (ts - start).num_milliseconds().max(0) as u64,
+        None => 0,
+    };
+
+    let layer_of = dag_layers(dag);
+    let mut attempts: HashMap<&str, u32> = HashMap::new();
+    let mut intervals: Vec<AgentInterval> = state.invocations.iter()
+        .filter(|i| i.replay_of.is_none() && !matches!(i.status, InvocationStatus::Paused | InvocationStatus::Skipped))
+        .filter_map(|i| {
+            let end = parse(&i.timestamp)?;
+            let attempt = attempts.entry(i.agent_id.as_str()).or_default();
+            *attempt += 1;
+            Some(AgentInterval {
+                agent_id: i.agent_id.clone(),
+                invocation_id: i.id.clone(),
+                attempt: *attempt,
+                status: i.status.clone(),
+                layer: layer_of.get(&i.agent_id).copied().unwrap_or(0),
+                start_ms: offset(end - chrono::Duration::milliseconds(i.latency_ms as i64)),
+                end_ms: offset(end),
+                ready_ms: 0,
+                start_delay_ms: 0,
+            })
+        })
+        .collect();
+
+    // An agent is ready once every dependency has finished its last attempt
+    let mut finished_at: HashMap<String, u64> = HashMap::new();
+    for interval in &intervals {
+        let end = finished_at.entry(interval.agent_id.clone()).or_default();
+        *end = (*end).max(interval.end_ms);
+    }
+    for interval in &mut intervals {
+        interval.ready_ms = dag.get_dependencies(&interval.agent_id).iter()
+            .filter_map(|dep| finished_at.get(dep))
+            .copied()
+            .max()
+            .unwrap_or(0);
+        interval.start_delay_ms = interval.start_ms.saturating_sub(interval.ready_ms);
+    }
+    intervals.sort_by(|a, b| (a.start_ms, &a.agent_id, a.attempt).cmp(&(b.start_ms, &b.agent_id, b.attempt)));
+
+    // Sweep: at equal times ends come before starts, so back-to-back bars don't overlap
+    let mut edges: Vec<(u64, i64)> = intervals.iter()
+        .flat_map(|i| [(i.start_ms, 1), (i.end_ms, -1)])
+        .collect();
+    edges.sort();
+    let mut parallelism: Vec<ParallelismSample> = Vec::new();
+    let mut running: i64 = 0;
+    for (at_ms, change) in edges {
+        running += change;
+        match parallelism.last_mut() {
+            Some(last) if last.at_ms == at_ms => last.running = running as usize,
+            _ => parallelism.push(ParallelismSample { at_ms, running: running as usize }),
+        }
+    }
+    parallelism.dedup_by(|next, prev| next.running == prev.running);
+
+    let mut layers: Vec<Vec<String>> = Vec::new();
+    for (agent_id, layer) in &layer_of {
+        if layers.len() <= *layer {
+            layers.resize(layer + 1, Vec::new());
+        }
+        layers[*layer].push(agent_id.clone());
+    }
+    layers.iter_mut().for_each(|l| l.sort());
+
+    ExecutionTrace {
+        run_id: state.run_id.clone(),
+        workflow_id: state.workflow_id.clone(),
+        started_at: state.start_time.clone(),
+        makespan_ms: intervals.iter().map(|i| i.end_ms).max().unwrap_or(0),
+        max_parallelism_achieved: parallelism.iter().map(|s| s.running).max().unwrap_or(0),
+        max_parallelism_possible: layers.iter().map(Vec::len).max().unwrap_or(0),
+        intervals,
+        parallelism,
+        layers,
+    }
+}
+
+/// Compare two traces on the first attempt of every agent they share
+pub fn compare(base: ExecutionTrace, other: ExecutionTrace) -> TraceComparison {
+    let first_attempts = |trace: &ExecutionTrace| -> Vec<AgentInterval> {
+        trace.intervals.iter().filter(|i| i.attempt == 1).cloned().collect()
+    };
+    let base_first = first_attempts(&base);
+    let other_first: HashMap<String, AgentInterval> = first_attempts(&other).into_iter()
+        .map(|i| (i.agent_id.clone(), i))
+        .collect();
+
+    let mut agents: Vec<AgentTraceDiff> = base_first.iter()
+        .filter_map(|b| {
+            let o = other_first.get(&b.agent_id)?;
+            let delay_change_ms = o.start_delay_ms as i64 - b.start_delay_ms as i64;
+            Some(AgentTraceDiff {
+                agent_id: b.agent_id.clone(),
+                base_start_delay_ms: b.start_delay_ms,
+                other_start_delay_ms: o.start_delay_ms,
+                delay_change_ms,
+                started_later: delay_change_ms > SCHEDULING_TOLERANCE_MS,
+            })
+        })
+        .collect();
+
+    let base_order: Vec<&str> = agents.iter().map(|a| a.agent_id.as_str()).collect();
+    let mut other_order = base_order.clone();
+    other_order.sort_by_key(|a| (other_first[*a].start_ms, *a));
+    let same_order = base_order == other_order;
+
+    agents.sort_by(|a, b| a.agent_id.cmp(&b.agent_id));
+    let mut later: Vec<&AgentTraceDiff> = agents.iter().filter(|a| a.started_later).collect();
+    later.sort_by(|a, b| b.delay_change_ms.cmp(&a.delay_change_ms).then(a.agent_id.cmp(&b.agent_id)));
+    let started_later = later.into_iter().map(|a| a.agent_id.clone()).collect();
+
+    TraceComparison { base, other, same_order, agents, started_later }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (agent, start_ms, end_ms) on a run started at midnight
+    fn state(run_id: &str, bars: &[(&str, i64, i64)]) -> RuntimeState {
+        let start = parse("2024-01-01T00:00:00Z").unwrap();
+        let invocations: Vec<serde_json::Value> = bars.iter().map(|(agent, from, to)| serde_json::json!({
+            "id": format!("{}-{}", run_id, agent), "agent_id": agent, "model_variant": "fast", "thought_signature": null,
+            "tools_used": [], "tokens_used": 1, "latency_ms": to - from, "status": "success",
+            "timestamp": (start + chrono::Duration::milliseconds(*to)).to_rfc3339(), "artifact_id": null, "error_message": null
+        })).collect();
+        serde_json::from_value(serde_json::json!({
+            "run_id": run_id, "workflow_id": "wf", "client_id": "public", "status": "completed",
+            "active_agents": [], "completed_agents": [], "failed_agents": [], "invocations": invocations,
+            "total_tokens_used": 0, "start_time": start.to_rfc3339(), "end_time": null
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_trace_detects_accidental_serialization() {
+        let mut dag = DAG::new();
+        for node in ["a", "b", "c", "d"] {
+            dag.add_node(node.to_string()).unwrap();
+        }
+        for (from, to) in [("a", "b"), ("a", "c"), ("b", "d"), ("c", "d")] {
+            dag.add_edge(from.to_string(), to.to_string()).unwrap();
+        }
+
+        let parallel = build(&state("run-1", &[("a", 0, 100), ("b", 100, 200), ("c", 100, 200), ("d", 200, 300)]), &dag);
+        assert_eq!((parallel.max_parallelism_achieved, parallel.max_parallelism_possible, parallel.makespan_ms), (2, 2, 300));
+        assert_eq!(parallel.layers, vec![vec!["a"], vec!["b", "c"], vec!["d"]]);
+        assert_eq!(parallel.parallelism, vec![
+            ParallelismSample { at_ms: 0, running: 1 },
+            ParallelismSample { at_ms: 100, running: 2 },
+            ParallelismSample { at_ms: 200, running: 1 },
+            ParallelismSample { at_ms: 300, running: 0 },
+        ]);
+
+        let serial = build(&state("run-2", &[("a", 0, 100), ("b", 100, 200), ("c", 200, 300), ("d", 300, 400)]), &dag);
+        assert_eq!(serial.max_parallelism_achieved, 1);
+        let c = serial.intervals.iter().find(|i| i.agent_id == "c").unwrap();
+        assert_eq!((c.ready_ms, c.start_delay_ms, c.layer), (100, 100, 1));
+
+        let comparison = compare(parallel, serial);
+        assert!(comparison.same_order);
+        assert_eq!(comparison.started_later, vec!["c"]);
+        // d waited for c in both runs, so it is not flagged
+        assert_eq!(comparison.agents.iter().find(|a| a.agent_id == "d").unwrap().delay_change_ms, 0);
+    }
+}
@@ -0,0 +1,55 @@
+// [[RARO]]/apps/kernel-server/src/faults.rs
+// Purpose: Fault injection for managed execution, so retry, timeout and cortex paths can be
+//          exercised with controlled failures instead of a flaky network. Rules come from the
+//          workflow's `faults` and are only accepted when RARO_FAULT_INJECTION=true.
+// Architecture: Execution Layer (wraps the agent service / simulation call)
+// Dependencies: Tokio
+
+use std::future::Future;
+use std::time::Duration;
+use crate::models::{Fault, FaultRule, RemoteAgentResponse};
+use crate::runtime::InvocationPayload;
+
+/// RARO_FAULT_INJECTION: default false
+pub fn enabled_from_env() -> bool {
+    std::env::var("RARO_FAULT_INJECTION").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+/// First rule covering the agent's `attempt` (1-based)
+pub fn fault_for<'a>(rules: &'a [FaultRule], agent_id: &str, attempt: u32) -> Option<&'a Fault> {
+    rules.iter().find(|r| r.applies_to(agent_id, attempt)).map(|r| &r.fault)
+}
+
+/// `call` with `fault` applied; an Error fault never reaches the agent service
+pub async fn inject<F>(fault: Fault, payload: InvocationPayload, call: F) -> Result<RemoteAgentResponse, reqwest::Error>
+where
+    F: Future<Output = Result<RemoteAgentResponse, reqwest::Error>>,
+{
+    match fault {
+        Fault::Error { status } => Ok(RemoteAgentResponse {
+            agent_id: payload.agent_id,
+            success: false,
+            output: None,
+            error: Some(format!("Injected fault: agent service answered HTTP {}", status)),
+            tokens_used: 0,
+            thought_signature: None,
+            input_tokens: 0,
+            output_tokens: 0,
+            cache_hit: false,
+            latency_ms: 0.0,
+            cached_content_id: None,
+            cached_tokens: 0,
+            thinking_tokens: 0,
+            executed_tools: Vec::new(),
+            delegation: None,
+        }),
+        Fault::Delay { ms } => {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            call.await
+        }
+        Fault::InvalidOutput { output } => call.await.map(|mut res| {
+            res.output = Some(output);
+            res
+        }),
+    }
+}
@@ -3,15 +3,178 @@
 // Architecture: Infrastructure Helper Layer.
 // Dependencies: std::fs, std::path
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::io;
 use std::io::Write;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 use serde::{Serialize, Deserialize};
-use chrono::Utc; 
+use sha2::{Digest, Sha256};
+use chrono::{DateTime, Utc};
+use crate::observability::anonymize_client;
 
 // Hard anchor to prevent escaping the storage volume
 const STORAGE_ROOT: &str = "/app/storage";
+/// Soft-deleted library files live here, inside each client's library folder
+const TRASH_DIR: &str = ".trash";
+const TOMBSTONE_SUFFIX: &str = ".tombstone.json";
+/// Starter library copied into every new client's private scope (admins manage it as this client id)
+pub const DEFAULTS_SCOPE: &str = "_defaults";
+/// Written into a client's library folder once the defaults were copied in
+const INITIALIZED_MARKER: &str = ".initialized";
+/// Shared library; never bootstrapped, since its folder is the public scope itself
+const PUBLIC_SCOPE: &str = "public";
+/// Prefix of the per-batch staging folder inside a run's artifacts directory
+const STAGING_PREFIX: &str = ".staging-";
+
+/// Deployment-specific extension -> MIME mappings, consulted before the built-in guesses
+static CONTENT_TYPE_OVERRIDES: RwLock<Option<HashMap<String, String>>> = RwLock::new(None);
+/// Clients whose workspace was already checked by this process (skips the marker stat per request)
+static BOOTSTRAPPED_CLIENTS: RwLock<Option<HashSet<String>>> = RwLock::new(None);
+/// Clients BOOTSTRAPPED_CLIENTS remembers; an evicted one just has its marker checked again
+const MAX_BOOTSTRAPPED_CLIENTS: usize = 10_000;
+/// Parsed metadata.json files keyed by path, valid while their (mtime, size) is unchanged
+static ARTIFACT_METADATA_CACHE: RwLock<Option<HashMap<PathBuf, CachedMetadata>>> = RwLock::new(None);
+type CachedMetadata = (SystemTime, u64, ArtifactMetadata);
+/// Entries ARTIFACT_METADATA_CACHE holds before an arbitrary one makes room
+const MAX_CACHED_ARTIFACT_METADATA: usize = 5_000;
+/// Writes are refused while the storage volume would be left with less than this free
+static MIN_FREE_BYTES: AtomicU64 = AtomicU64::new(DEFAULT_MIN_FREE_MB * 1024 * 1024);
+/// Writes that failed or were refused for lack of space since boot, for GET /metrics
+static STORAGE_FULL_EVENTS: AtomicU64 = AtomicU64::new(0);
+const DEFAULT_MIN_FREE_MB: u64 = 100;
+
+/// RARO_MIN_FREE_DISK_MB (default 100; 0 = only fail when the disk is actually full)
+pub fn init_min_free_space_from_env() {
+    let min_free_mb = std::env::var("RARO_MIN_FREE_DISK_MB").ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MIN_FREE_MB);
+    MIN_FREE_BYTES.store(min_free_mb.saturating_mul(1024 * 1024), Ordering::Relaxed);
+}
+
+/// ENOSPC or a filesystem quota, as opposed to other write failures
+pub fn is_storage_full(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)
+}
+
+/// Log and count a write that failed for lack of space; other errors pass through untouched
+pub fn note_storage_full(e: io::Error) -> io::Error {
+    if is_storage_full(&e) {
+        STORAGE_FULL_EVENTS.fetch_add(1, Ordering::Relaxed);
+        tracing::error!("CRITICAL: storage volume out of space: {}", e);
+    }
+    e
+}
+
+pub fn storage_full_events() -> u64 {
+    STORAGE_FULL_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Bytes available to the kernel on the storage volume; None where it can't be determined
+pub fn storage_free_bytes() -> Option<u64> {
+    available_bytes(Path::new(STORAGE_ROOT))
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // statvfs field widths vary by platform
+fn available_bytes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    // The target folder may not exist yet; its volume is that of the nearest existing ancestor
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid, writable statvfs
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Refuse a write of `incoming` bytes into `dir` that would leave less than the configured
+/// minimum free, so the volume never fills up completely (checkpoint and log writes still fit)
+fn ensure_free_space(dir: &Path, incoming: u64) -> io::Result<()> {
+    ensure_free_space_above(dir, incoming, MIN_FREE_BYTES.load(Ordering::Relaxed))
+}
+
+fn ensure_free_space_above(dir: &Path, incoming: u64, min_free: u64) -> io::Result<()> {
+    let Some(available) = available_bytes(dir) else { return Ok(()) };
+    if incoming <= available && available - incoming >= min_free {
+        return Ok(());
+    }
+    Err(note_storage_full(io::Error::new(io::ErrorKind::StorageFull, format!(
+        "Insufficient storage: writing {} bytes would leave {} MB free, below the {} MB minimum",
+        incoming,
+        available.saturating_sub(incoming) / (1024 * 1024),
+        min_free / (1024 * 1024),
+    ))))
+}
+
+/// Replace `path` with `data` through a temp file and rename, so readers never see a partial
+/// file. Used for the kernel's own snapshot files; refused like any other write when space is low.
+pub fn write_snapshot(path: &Path, data: &[u8]) -> io::Result<()> {
+    ensure_free_space(path.parent().unwrap_or(Path::new(".")), data.len() as u64)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, data).map_err(note_storage_full)?;
+    fs::rename(&tmp, path).map_err(note_storage_full)
+}
+
+/// RARO_CONTENT_TYPES="parquet=application/vnd.apache.parquet,ipynb=application/x-ipynb+json"
+pub fn init_content_types_from_env() {
+    let overrides = std::env::var("RARO_CONTENT_TYPES")
+        .map(|v| parse_content_types(&v))
+        .unwrap_or_default();
+    if !overrides.is_empty() {
+        tracing::info!("Loaded {} content-type overrides", overrides.len());
+    }
+    set_content_type_overrides(overrides);
+}
+
+pub fn set_content_type_overrides(overrides: HashMap<String, String>) {
+    *CONTENT_TYPE_OVERRIDES.write().unwrap_or_else(|e| e.into_inner()) = Some(overrides);
+}
+
+/// `ext=type` pairs separated by commas; a leading dot on the extension is optional
+fn parse_content_types(spec: &str) -> HashMap<String, String> {
+    spec.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(ext, mime)| (ext.trim().trim_start_matches('.').to_lowercase(), mime.trim().to_string()))
+        .filter(|(ext, mime)| !ext.is_empty() && !mime.is_empty())
+        .collect()
+}
+
+/// MIME type for a download: configured overrides first, then the built-in map,
+/// then application/octet-stream
+pub fn guess_content_type(filename: &str) -> String {
+    let overrides = CONTENT_TYPE_OVERRIDES.read().unwrap_or_else(|e| e.into_inner());
+    content_type_for(filename, overrides.as_ref())
+}
+
+fn content_type_for(filename: &str, overrides: Option<&HashMap<String, String>>) -> String {
+    let ext = Path::new(filename).extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if let Some(mime) = overrides.and_then(|o| o.get(&ext)) {
+        return mime.clone();
+    }
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "md" => "text/markdown",
+        "txt" => "text/plain",
+        _ => "application/octet-stream",
+    }
+    .to_string()
+}
 
 /// Metadata for artifact storage - tracks all files generated during a workflow run
 #[derive(Serialize, Deserialize, Clone)]
@@ -26,13 +189,92 @@ pub struct ArtifactMetadata {
 }
 
 /// Individual file metadata within an artifact collection
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ArtifactFile {
     pub filename: String,
     pub agent_id: String,
     pub generated_at: String,
     pub size_bytes: u64,
     pub content_type: String,
+    /// sha256 of the content at promotion time (absent for entries written before digests)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+/// One run's promoted artifacts, as listed by GET /artifacts
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ArtifactRunSummary {
+    pub run_id: String,
+    pub workflow_id: String,
+    /// None once the workflow is no longer loaded
+    pub workflow_name: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    /// The run's status while the kernel still knows the run, else the collection's own
+    pub status: String,
+    /// Files counted after the content_type filter
+    pub artifact_count: usize,
+    pub total_bytes: u64,
+    /// The run's tags (see costs::run_tags)
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Query of GET /artifacts
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct ArtifactListFilter {
+    /// Only collections expiring (or already expired) within this many days
+    pub expiring_within_days: Option<i64>,
+    /// Only files of this content type; runs without any are left out
+    pub content_type: Option<String>,
+}
+
+impl ArtifactListFilter {
+    /// Summary of the collection's files that pass the filter; None when the run is filtered out
+    pub fn summarize(&self, metadata: &ArtifactMetadata, now: DateTime<Utc>) -> Option<ArtifactRunSummary> {
+        if let Some(days) = self.expiring_within_days {
+            let expires = DateTime::parse_from_rfc3339(&metadata.expires_at).ok()?;
+            if expires > now + chrono::Duration::days(days) {
+                return None;
+            }
+        }
+        let files: Vec<&ArtifactFile> = metadata.artifacts.iter()
+            .filter(|f| self.content_type.as_ref().map(|t| f.content_type.eq_ignore_ascii_case(t)).unwrap_or(true))
+            .collect();
+        if self.content_type.is_some() && files.is_empty() {
+            return None;
+        }
+
+        Some(ArtifactRunSummary {
+            run_id: metadata.run_id.clone(),
+            workflow_id: metadata.workflow_id.clone(),
+            workflow_name: None,
+            created_at: metadata.created_at.clone(),
+            expires_at: metadata.expires_at.clone(),
+            status: metadata.status.clone(),
+            artifact_count: files.len(),
+            total_bytes: files.iter().map(|f| f.size_bytes).sum(),
+            tags: BTreeMap::new(),
+        })
+    }
+}
+
+/// Written next to a trashed library file: who deleted it and when
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Tombstone {
+    pub filename: String,
+    /// Name of the trashed copy inside .trash/, unique per deletion so deleting a re-uploaded
+    /// name keeps the earlier copy. Empty in tombstones written before trash ids existed.
+    #[serde(default)]
+    pub trash_id: String,
+    pub deleted_by: String,
+    pub deleted_at: String,
+    pub size_bytes: u64,
+}
+
+impl Tombstone {
+    fn trashed_name(&self) -> &str {
+        if self.trash_id.is_empty() { &self.filename } else { &self.trash_id }
+    }
 }
 
 pub struct WorkspaceInitializer;
@@ -41,18 +283,21 @@ impl WorkspaceInitializer {
     // === 1. LAYERED PATH RESOLUTION ===
     /// Resolves a filename by checking Private Storage first, then Public Library.
     fn resolve_library_path(client_id: &str, filename: &str) -> Option<PathBuf> {
-        // Sanitize input
+        // Sanitize input (dot names are internal, e.g. the .trash folder)
         let safe_name = Path::new(filename).file_name()?;
+        if safe_name.to_string_lossy().starts_with('.') {
+            return None;
+        }
 
         // Path A: User Private Storage
-        let private_path = PathBuf::from(format!("{}/library/{}/{}", STORAGE_ROOT, client_id, safe_name.to_string_lossy()));
-        if private_path.exists() {
+        let private_path = Self::client_library_dir(client_id).join(safe_name);
+        if private_path.is_file() {
             return Some(private_path);
         }
 
         // Path B: Public Shared Storage
         let public_path = PathBuf::from(format!("{}/library/public/{}", STORAGE_ROOT, safe_name.to_string_lossy()));
-        if public_path.exists() {
+        if public_path.is_file() {
             return Some(public_path);
         }
 
@@ -72,7 +317,7 @@ impl WorkspaceInitializer {
         fs::create_dir_all(&input_path)?;
         fs::create_dir_all(&output_path)?;
 
-        tracing::info!("Initializing workspace for run {} (Client: {})", run_id, client_id);
+        tracing::info!("Initializing workspace for run {} (Client: {})", run_id, anonymize_client(client_id));
 
         // 2. Copy requested files from Library -> Session Input using layered resolver
         for filename in library_files {
@@ -81,17 +326,31 @@ impl WorkspaceInitializer {
             // Use the layered resolver
             if let Some(src_path) = Self::resolve_library_path(client_id, &filename) {
                 match fs::copy(&src_path, &dest) {
-                    Ok(_) => tracing::info!("Attached {:?} to run {}", src_path, run_id),
+                    Ok(_) => tracing::info!("Attached {} to run {}", filename, run_id),
                     Err(e) => tracing::error!("Failed to copy {}: {}", filename, e),
                 }
             } else {
-                tracing::warn!("File '{}' not found in Private or Public library for client {}", filename, client_id);
+                tracing::warn!("File '{}' not found in Private or Public library for client {}", filename, anonymize_client(client_id));
             }
         }
 
         Ok(())
     }
     
+    /// Copy one library file into an existing session's inputs (two-phase start).
+    /// Unlike init_run_session, a file that doesn't resolve is an error. Returns the stored name.
+    pub fn attach_to_session(run_id: &str, client_id: &str, filename: &str) -> io::Result<String> {
+        let src_path = Self::resolve_library_path(client_id, filename)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("'{}' is not in the library", filename)))?;
+        let name = src_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let input_path = format!("{}/sessions/{}/input", STORAGE_ROOT, run_id);
+        ensure_free_space(Path::new(&input_path), fs::metadata(&src_path)?.len())?;
+        fs::create_dir_all(&input_path).map_err(note_storage_full)?;
+        fs::copy(&src_path, format!("{}/{}", input_path, name)).map_err(note_storage_full)?;
+        tracing::info!("Attached {} to preparing run {}", name, run_id);
+        Ok(name)
+    }
+
     // === 3. SCOPED UPLOAD ===
     /// Securely saves a byte buffer to the client-scoped Library folder.
     pub async fn save_to_library(client_id: &str, filename: &str, data: &[u8]) -> io::Result<()> {
@@ -105,24 +364,33 @@ impl WorkspaceInitializer {
 
         // Save SPECIFICALLY to the client's folder
         let user_lib_path = format!("{}/library/{}", STORAGE_ROOT, client_id);
-        fs::create_dir_all(&user_lib_path)?;
+        ensure_free_space(Path::new(&user_lib_path), data.len() as u64)?;
+        tokio::fs::create_dir_all(&user_lib_path).await.map_err(note_storage_full)?;
 
         let target_path = format!("{}/{}", user_lib_path, safe_name);
-        let mut file = fs::File::create(&target_path)?;
-        file.write_all(data)?;
+        tokio::fs::write(&target_path, data).await.map_err(note_storage_full)?;
 
-        tracing::info!("File uploaded to private scope ({}): {}", client_id, target_path);
+        tracing::info!("File uploaded to private scope ({}): {}", anonymize_client(client_id), safe_name);
         Ok(())
     }
 
     // === 4. LISTING ===
     /// Lists all files accessible to a client (private + public merged)
     pub async fn list_scoped_files(client_id: &str) -> io::Result<Vec<String>> {
-        let mut file_set = std::collections::HashSet::new();
+        // Public first, then private (duplicates merge in the set)
+        Self::list_merged_dirs(vec![
+            PathBuf::from(format!("{}/library/public", STORAGE_ROOT)),
+            Self::client_library_dir(client_id),
+        ]).await
+    }
 
-        // Helper to read a dir and insert into set
-        let mut read_dir = |path: String| {
-            if let Ok(entries) = fs::read_dir(path) {
+    /// Sorted, de-duplicated entry names of `dirs` (dot names skipped). The scan runs on the
+    /// blocking pool so large libraries don't stall the async workers.
+    async fn list_merged_dirs(dirs: Vec<PathBuf>) -> io::Result<Vec<String>> {
+        tokio::task::spawn_blocking(move || {
+            let mut file_set = std::collections::HashSet::new();
+            for dir in dirs {
+                let Ok(entries) = fs::read_dir(dir) else { continue };
                 for entry in entries.flatten() {
                     if let Ok(name) = entry.file_name().into_string() {
                         if !name.starts_with('.') {
@@ -131,20 +399,180 @@ impl WorkspaceInitializer {
                     }
                 }
             }
+
+            let mut files: Vec<String> = file_set.into_iter().collect();
+            files.sort();
+            files
+        })
+        .await
+        .map_err(io::Error::other)
+    }
+
+    // === 4b. DEFAULT WORKSPACE ===
+    /// Copies the `_defaults` library into a client's private scope on first sighting.
+    /// Returns how many files were copied (0 when the client was already initialized).
+    pub async fn ensure_client_workspace(client_id: &str) -> io::Result<usize> {
+        if client_id == PUBLIC_SCOPE || client_id == DEFAULTS_SCOPE {
+            return Ok(0);
+        }
+        let seen = |c: &str| BOOTSTRAPPED_CLIENTS.read().ok().and_then(|s| s.as_ref().map(|s| s.contains(c))).unwrap_or(false);
+        if seen(client_id) {
+            return Ok(0);
+        }
+
+        let defaults = Self::client_library_dir(DEFAULTS_SCOPE);
+        let client_dir = Self::client_library_dir(client_id);
+        let copied = tokio::task::spawn_blocking(move || Self::bootstrap_library(&defaults, &client_dir))
+            .await
+            .map_err(io::Error::other)??;
+
+        if let Ok(mut guard) = BOOTSTRAPPED_CLIENTS.write() {
+            let set = guard.get_or_insert_with(HashSet::new);
+            if set.len() >= MAX_BOOTSTRAPPED_CLIENTS {
+                if let Some(evicted) = set.iter().next().cloned() {
+                    set.remove(&evicted);
+                }
+            }
+            set.insert(client_id.to_string());
+        }
+        if let Some(count) = copied {
+            tracing::info!("Initialized workspace for client {} with {} default files", anonymize_client(client_id), count);
+        }
+        Ok(copied.unwrap_or(0))
+    }
+
+    /// Copy every default file that the client does not already have, then write the marker.
+    /// None if the marker was already present. Existing client files are never overwritten.
+    fn bootstrap_library(defaults: &Path, client_dir: &Path) -> io::Result<Option<usize>> {
+        let marker = client_dir.join(INITIALIZED_MARKER);
+        if marker.exists() {
+            return Ok(None);
+        }
+        fs::create_dir_all(client_dir)?;
+
+        let mut copied = 0;
+        if let Ok(entries) = fs::read_dir(defaults) {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let dest = client_dir.join(&name);
+                if name.to_string_lossy().starts_with('.') || !entry.path().is_file() || dest.exists() {
+                    continue;
+                }
+                fs::copy(entry.path(), &dest)?;
+                copied += 1;
+            }
+        }
+
+        fs::write(&marker, Utc::now().to_rfc3339())?;
+        Ok(Some(copied))
+    }
+
+    // === 5. SOFT DELETE ===
+    pub fn client_library_dir(client_id: &str) -> PathBuf {
+        PathBuf::from(format!("{}/library/{}", STORAGE_ROOT, client_id))
+    }
+
+    fn trash_name(filename: &str) -> io::Result<String> {
+        let safe_name = Path::new(filename).file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .filter(|n| !n.starts_with('.') && !n.ends_with(TOMBSTONE_SUFFIX))
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Invalid filename"))?;
+        Ok(safe_name)
+    }
+
+    /// Moves a private library file into `.trash/` under a fresh trash id and writes its tombstone
+    pub fn trash_library_file(library_dir: &Path, filename: &str, deleted_by: &str) -> io::Result<Tombstone> {
+        let name = Self::trash_name(filename)?;
+        let src = library_dir.join(&name);
+        if !src.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found in library", name)));
+        }
+
+        let trash = library_dir.join(TRASH_DIR);
+        fs::create_dir_all(&trash)?;
+        let tombstone = Tombstone {
+            trash_id: format!("{}-{}", uuid::Uuid::new_v4().simple(), name),
+            filename: name.clone(),
+            deleted_by: deleted_by.to_string(),
+            deleted_at: Utc::now().to_rfc3339(),
+            size_bytes: fs::metadata(&src)?.len(),
         };
+        fs::rename(&src, trash.join(&tombstone.trash_id))?;
+        fs::write(trash.join(format!("{}{}", tombstone.trash_id, TOMBSTONE_SUFFIX)), serde_json::to_vec_pretty(&tombstone)?)?;
+
+        tracing::info!("Library file {} moved to trash by {}", name, anonymize_client(deleted_by));
+        Ok(tombstone)
+    }
 
-        // 1. Read Public
-        read_dir(format!("{}/library/public", STORAGE_ROOT));
+    /// Tombstones of trashed files, newest first
+    pub fn list_trash(library_dir: &Path) -> io::Result<Vec<Tombstone>> {
+        let trash = library_dir.join(TRASH_DIR);
+        if !trash.exists() {
+            return Ok(Vec::new());
+        }
 
-        // 2. Read Private (overwrites duplicates in set, effectively merging)
-        read_dir(format!("{}/library/{}", STORAGE_ROOT, client_id));
+        let mut tombstones: Vec<Tombstone> = fs::read_dir(&trash)?
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(TOMBSTONE_SUFFIX))
+            .filter_map(|e| fs::read(e.path()).ok())
+            .filter_map(|data| serde_json::from_slice(&data).ok())
+            .collect();
+        tombstones.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+        Ok(tombstones)
+    }
 
-        let mut files: Vec<String> = file_set.into_iter().collect();
-        files.sort();
-        Ok(files)
+    /// Moves a trashed file back, picked by trash id or, for a filename, its newest trashed copy.
+    /// Fails with AlreadyExists if the name was re-uploaded, unless `overwrite`.
+    pub fn restore_library_file(library_dir: &Path, trash_id_or_filename: &str, overwrite: bool) -> io::Result<Tombstone> {
+        let name = Self::trash_name(trash_id_or_filename)?;
+        let trash = library_dir.join(TRASH_DIR);
+        let tombstone = Self::list_trash(library_dir)?.into_iter()
+            .find(|t| t.trash_id == name || t.filename == name)
+            .filter(|t| trash.join(t.trashed_name()).is_file())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found in trash", name)))?;
+
+        let dest = library_dir.join(&tombstone.filename);
+        if dest.exists() && !overwrite {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists in library", tombstone.filename)));
+        }
+
+        fs::rename(trash.join(tombstone.trashed_name()), &dest)?;
+        let _ = fs::remove_file(trash.join(format!("{}{}", tombstone.trashed_name(), TOMBSTONE_SUFFIX)));
+        Ok(tombstone)
     }
 
-    /// Optional: Cleanup routine for old sessions (commented until used)
+    /// Permanently removes trashed files deleted more than `retention` ago. Returns how many.
+    pub fn purge_trash(library_dir: &Path, retention: chrono::Duration) -> io::Result<usize> {
+        let trash = library_dir.join(TRASH_DIR);
+        let cutoff = Utc::now() - retention;
+        let mut purged = 0;
+
+        for tombstone in Self::list_trash(library_dir)? {
+            let expired = chrono::DateTime::parse_from_rfc3339(&tombstone.deleted_at)
+                .map(|t| t < cutoff)
+                .unwrap_or(true);
+            if expired {
+                let _ = fs::remove_file(trash.join(tombstone.trashed_name()));
+                fs::remove_file(trash.join(format!("{}{}", tombstone.trashed_name(), TOMBSTONE_SUFFIX)))?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Janitor pass over every client library
+    pub fn purge_all_trash(retention: chrono::Duration) -> usize {
+        let Ok(entries) = fs::read_dir(format!("{}/library", STORAGE_ROOT)) else { return 0 };
+        entries.flatten()
+            .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|e| Self::purge_trash(&e.path(), retention).unwrap_or_else(|err| {
+                tracing::warn!("Trash purge failed for client {}: {}", anonymize_client(&e.file_name().to_string_lossy()), err);
+                0
+            }))
+            .sum()
+    }
+
+    // Optional: Cleanup routine for old sessions (commented until used)
     // pub fn cleanup_run(run_id: &str) -> io::Result<()> {
     //     let path = format!("{}/sessions/{}", STORAGE_ROOT, run_id);
     //     if Path::new(&path).exists() {
@@ -168,8 +596,6 @@ impl WorkspaceInitializer {
 
         // 2. Destination: Artifacts directory (organized by client and run)
         let artifacts_dir = format!("{}/artifacts/{}/{}", STORAGE_ROOT, client_id, run_id);
-        fs::create_dir_all(&artifacts_dir)?;
-
         let dest_path = format!("{}/{}", artifacts_dir, filename);
 
         if !Path::new(&src_path).exists() {
@@ -178,10 +604,12 @@ impl WorkspaceInitializer {
                 format!("Artifact {} not found in session output", filename)
             ));
         }
+        ensure_free_space(Path::new(&artifacts_dir), fs::metadata(&src_path)?.len())?;
+        fs::create_dir_all(&artifacts_dir).map_err(note_storage_full)?;
 
         // 3. Copy file (keep session copy for integrity)
-        fs::copy(&src_path, &dest_path)?;
-        tracing::info!("Promoted artifact: {} → {}", src_path, dest_path);
+        fs::copy(&src_path, &dest_path).map_err(note_storage_full)?;
+        tracing::info!("Promoted artifact {} of run {} for client {}", filename, run_id, anonymize_client(client_id));
 
         // 4. Update/Create Metadata
         let metadata_path = format!("{}/metadata.json", artifacts_dir);
@@ -200,17 +628,274 @@ impl WorkspaceInitializer {
             agent_id: agent_id.to_string(),
             generated_at: Utc::now().to_rfc3339(),
             size_bytes: file_meta.len(),
-            content_type: Self::guess_content_type(filename),
+            content_type: guess_content_type(filename),
+            digest: Self::file_digest(Path::new(&dest_path)).ok(),
         });
 
         // 6. Write metadata
         let json = serde_json::to_string_pretty(&metadata)?;
-        let mut meta_file = fs::File::create(&metadata_path)?;
-        meta_file.write_all(json.as_bytes())?;
+        let mut meta_file = fs::File::create(&metadata_path).map_err(note_storage_full)?;
+        meta_file.write_all(json.as_bytes()).map_err(note_storage_full)?;
+
+        Ok(())
+    }
+
+    /// Promotes several session outputs as one unit, e.g. a report and its images. Every file is
+    /// copied into a staging folder and checked against its source before any is moved into
+    /// place, and metadata.json is replaced once at the end. On failure the staging folder is
+    /// removed, files already moved are put back and the metadata is left as it was.
+    /// Returns the new entries, digests included.
+    pub async fn promote_artifacts_batch(
+        client_id: &str,
+        run_id: &str,
+        workflow_id: &str,
+        agent_id: &str,
+        filenames: &[String],
+        user_directive: &str,
+    ) -> io::Result<Vec<ArtifactFile>> {
+        let output_dir = Self::session_output_dir(run_id);
+        let artifacts_dir = Self::client_artifacts_dir(client_id).join(run_id);
+        let filenames = filenames.to_vec();
+        let agent_id = agent_id.to_string();
+        let new_metadata = {
+            let (run_id, workflow_id, user_directive) = (run_id.to_string(), workflow_id.to_string(), user_directive.to_string());
+            move || Self::create_new_metadata(&run_id, &workflow_id, &user_directive)
+        };
+
+        let promoted = tokio::task::spawn_blocking(move || {
+            Self::promote_batch_into(&output_dir, &artifacts_dir, &filenames, &agent_id, new_metadata)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        tracing::info!("Promoted {} artifacts of run {} for client {}", promoted.len(), run_id, anonymize_client(client_id));
+        Ok(promoted)
+    }
+
+    fn promote_batch_into(
+        output_dir: &Path,
+        artifacts_dir: &Path,
+        filenames: &[String],
+        agent_id: &str,
+        new_metadata: impl FnOnce() -> ArtifactMetadata,
+    ) -> io::Result<Vec<ArtifactFile>> {
+        if filenames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No files to promote"));
+        }
+        let mut seen = HashSet::new();
+        let mut total_bytes = 0;
+        for name in filenames {
+            Self::check_plain_name(name)?;
+            if !seen.insert(name) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is listed twice", name)));
+            }
+            let src = output_dir.join(name);
+            if !src.is_file() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("Artifact {} not found in session output", name)));
+            }
+            total_bytes += fs::metadata(&src)?.len();
+        }
+        ensure_free_space(artifacts_dir, total_bytes)?;
+        fs::create_dir_all(artifacts_dir).map_err(note_storage_full)?;
+
+        let staging = artifacts_dir.join(format!("{}{}", STAGING_PREFIX, uuid::Uuid::new_v4()));
+        let result = Self::stage_and_commit(output_dir, artifacts_dir, &staging, filenames, agent_id, new_metadata);
+        if let Err(e) = fs::remove_dir_all(&staging) {
+            tracing::warn!("Failed to remove staging folder {}: {}", staging.display(), e);
+        }
+        result
+    }
+
+    /// Copy and verify into `staging`, then move everything into `artifacts_dir`
+    fn stage_and_commit(
+        output_dir: &Path,
+        artifacts_dir: &Path,
+        staging: &Path,
+        filenames: &[String],
+        agent_id: &str,
+        new_metadata: impl FnOnce() -> ArtifactMetadata,
+    ) -> io::Result<Vec<ArtifactFile>> {
+        let (staged_dir, previous_dir) = (staging.join("new"), staging.join("previous"));
+        fs::create_dir_all(&staged_dir).map_err(note_storage_full)?;
+        fs::create_dir_all(&previous_dir).map_err(note_storage_full)?;
+
+        let generated_at = Utc::now().to_rfc3339();
+        let mut entries = Vec::new();
+        for name in filenames {
+            let src = output_dir.join(name);
+            let staged = staged_dir.join(name);
+            let source_digest = Self::file_digest(&src)?;
+            let size_bytes = fs::copy(&src, &staged).map_err(note_storage_full)?;
+            let digest = Self::file_digest(&staged)?;
+            if size_bytes != fs::metadata(&src)?.len() || digest != source_digest {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} changed while it was being staged", name)));
+            }
+            entries.push(ArtifactFile {
+                filename: name.clone(),
+                agent_id: agent_id.to_string(),
+                generated_at: generated_at.clone(),
+                size_bytes,
+                content_type: guess_content_type(name),
+                digest: Some(digest),
+            });
+        }
+
+        // Re-promoted names replace their old entries
+        let metadata_path = artifacts_dir.join("metadata.json");
+        let mut metadata = if metadata_path.exists() {
+            serde_json::from_str::<ArtifactMetadata>(&fs::read_to_string(&metadata_path)?).unwrap_or_else(|_| new_metadata())
+        } else {
+            new_metadata()
+        };
+        metadata.artifacts.retain(|a| !filenames.contains(&a.filename));
+        metadata.artifacts.extend(entries.iter().cloned());
+        let staged_metadata = staging.join("metadata.json");
+        fs::write(&staged_metadata, serde_json::to_string_pretty(&metadata)?).map_err(note_storage_full)?;
+
+        // Renames within one volume from here on, so running out of space can no longer interrupt
+        let mut moved = Vec::new();
+        if let Err(e) = Self::commit_staged(artifacts_dir, staging, filenames, &mut moved) {
+            for name in moved.iter().rev() {
+                let dest = artifacts_dir.join(name);
+                let _ = fs::remove_file(&dest);
+                let previous = previous_dir.join(name);
+                if previous.exists() {
+                    if let Err(e) = fs::rename(&previous, &dest) {
+                        tracing::error!("Rollback could not restore {}: {}", dest.display(), e);
+                    }
+                }
+            }
+            return Err(e);
+        }
+        Ok(entries)
+    }
+
+    /// Move each staged file into place (setting any file it replaces aside), then the metadata.
+    /// `moved` collects the names whose destination was touched, for the rollback.
+    fn commit_staged(artifacts_dir: &Path, staging: &Path, filenames: &[String], moved: &mut Vec<String>) -> io::Result<()> {
+        for name in filenames {
+            let dest = artifacts_dir.join(name);
+            if dest.exists() {
+                fs::rename(&dest, staging.join("previous").join(name))?;
+            }
+            moved.push(name.clone());
+            fs::rename(staging.join("new").join(name), &dest)?;
+        }
+        fs::rename(staging.join("metadata.json"), artifacts_dir.join("metadata.json"))
+    }
 
+    pub fn session_output_dir(run_id: &str) -> PathBuf {
+        PathBuf::from(format!("{}/sessions/{}/output", STORAGE_ROOT, run_id))
+    }
+
+    /// Contents of one file an agent wrote to the run's output directory
+    #[allow(dead_code)] // Library API: the HTTP handler streams session_output_file instead
+    pub fn read_session_output(run_id: &str, filename: &str) -> io::Result<Vec<u8>> {
+        Self::read_output_file(&Self::session_output_dir(run_id), filename)
+    }
+
+    /// Path of one file an agent wrote to the run's output directory, for streaming
+    pub fn session_output_file(run_id: &str, filename: &str) -> io::Result<PathBuf> {
+        Self::output_file_path(&Self::session_output_dir(run_id), filename)
+    }
+
+    /// Sorted names of the files in the run's output directory (empty before any were written)
+    pub fn list_session_outputs(run_id: &str) -> io::Result<Vec<String>> {
+        Self::list_output_files(&Self::session_output_dir(run_id))
+    }
+
+    /// A single plain name: no separators, no `..`, no dot names
+    fn check_plain_name(filename: &str) -> io::Result<()> {
+        let mut components = Path::new(filename).components();
+        let plain = matches!((components.next(), components.next()), (Some(std::path::Component::Normal(name)), None) if name == filename);
+        if !plain || filename.starts_with('.') {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Invalid path"));
+        }
         Ok(())
     }
 
+    fn output_file_path(output_dir: &Path, filename: &str) -> io::Result<PathBuf> {
+        Self::check_plain_name(filename)?;
+
+        let path = output_dir.join(filename);
+        if !path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found in session outputs", filename)));
+        }
+        Ok(path)
+    }
+
+    fn read_output_file(output_dir: &Path, filename: &str) -> io::Result<Vec<u8>> {
+        fs::read(Self::output_file_path(output_dir, filename)?)
+    }
+
+    fn list_output_files(output_dir: &Path) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(output_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut files: Vec<String> = entries.flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Bytes stored for a client: private library plus promoted artifacts
+    pub fn client_storage_bytes(client_id: &str) -> u64 {
+        fn dir_size(path: &Path) -> u64 {
+            fs::read_dir(path).map(|entries| {
+                entries.flatten().map(|entry| match entry.file_type() {
+                    Ok(t) if t.is_dir() => dir_size(&entry.path()),
+                    Ok(_) => entry.metadata().map(|m| m.len()).unwrap_or(0),
+                    Err(_) => 0,
+                }).sum()
+            }).unwrap_or(0)
+        }
+
+        ["library", "artifacts"].iter()
+            .map(|area| dir_size(Path::new(&format!("{}/{}/{}", STORAGE_ROOT, area, client_id))))
+            .sum()
+    }
+
+    pub fn file_digest(path: &Path) -> io::Result<String> {
+        let data = fs::read(path)?;
+        Ok(format!("{:x}", Sha256::digest(&data)))
+    }
+
+    /// Files in `output_dir` matching any pattern, minus those already promoted with the same digest
+    pub fn promotion_candidates(
+        output_dir: &Path,
+        patterns: &[glob::Pattern],
+        promoted: &[ArtifactFile],
+    ) -> io::Result<Vec<String>> {
+        if !output_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut candidates = Vec::new();
+        for entry in fs::read_dir(output_dir)?.flatten() {
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let Ok(filename) = entry.file_name().into_string() else { continue };
+            if !patterns.iter().any(|p| p.matches(&filename)) {
+                continue;
+            }
+
+            let digest = Self::file_digest(&entry.path())?;
+            let already_promoted = promoted.iter()
+                .any(|a| a.filename == filename && a.digest.as_deref() == Some(digest.as_str()));
+            if !already_promoted {
+                candidates.push(filename);
+            }
+        }
+
+        candidates.sort();
+        Ok(candidates)
+    }
+
     /// Creates new artifact metadata for a workflow run
     fn create_new_metadata(run_id: &str, workflow_id: &str, user_directive: &str) -> ArtifactMetadata {
         let now = Utc::now();
@@ -227,34 +912,20 @@ impl WorkspaceInitializer {
         }
     }
 
-    /// Guesses MIME type from file extension
-    fn guess_content_type(filename: &str) -> String {
-        if filename.ends_with(".png") { "image/png" }
-        else if filename.ends_with(".jpg") || filename.ends_with(".jpeg") { "image/jpeg" }
-        else if filename.ends_with(".csv") { "text/csv" }
-        else if filename.ends_with(".json") { "application/json" }
-        else if filename.ends_with(".md") { "text/markdown" }
-        else if filename.ends_with(".txt") { "text/plain" }
-        else { "application/octet-stream" }
-        .to_string()
-    }
-
     /// List all artifact runs for a specific client
     pub async fn list_artifact_runs(client_id: &str) -> io::Result<Vec<String>> {
         let artifacts_root = format!("{}/artifacts/{}", STORAGE_ROOT, client_id);
-        if !Path::new(&artifacts_root).exists() {
+        if !tokio::fs::try_exists(&artifacts_root).await.unwrap_or(false) {
             return Ok(Vec::new());
         }
 
-        let entries = fs::read_dir(&artifacts_root)?;
+        let mut entries = tokio::fs::read_dir(&artifacts_root).await?;
         let mut runs = Vec::new();
 
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if entry.file_type()?.is_dir() {
-                    if let Ok(name) = entry.file_name().into_string() {
-                        runs.push(name);
-                    }
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                if let Ok(name) = entry.file_name().into_string() {
+                    runs.push(name);
                 }
             }
         }
@@ -262,11 +933,365 @@ impl WorkspaceInitializer {
         Ok(runs)
     }
 
+    pub fn client_artifacts_dir(client_id: &str) -> PathBuf {
+        PathBuf::from(format!("{}/artifacts/{}", STORAGE_ROOT, client_id))
+    }
+
+    /// metadata.json of every run under `artifacts_dir`. Files unchanged since the last call
+    /// (same mtime and size) come from a process-wide cache instead of being re-read; entries
+    /// for runs no longer under the directory are evicted. Unreadable or malformed collections
+    /// are skipped. Blocking: call from spawn_blocking.
+    pub fn artifact_collections(artifacts_dir: &Path) -> io::Result<Vec<ArtifactMetadata>> {
+        if !artifacts_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut collections = Vec::new();
+        let mut seen = HashSet::new();
+        for entry in fs::read_dir(artifacts_dir)?.flatten() {
+            if !entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                continue;
+            }
+            let path = entry.path().join("metadata.json");
+            seen.insert(path.clone());
+            let Ok(stat) = fs::metadata(&path) else { continue };
+            let (modified, size) = (stat.modified()?, stat.len());
+
+            let cached = ARTIFACT_METADATA_CACHE.read().unwrap_or_else(|e| e.into_inner()).as_ref()
+                .and_then(|cache| cache.get(&path))
+                .filter(|(m, s, _)| *m == modified && *s == size)
+                .map(|(_, _, metadata)| metadata.clone());
+            let metadata = match cached {
+                Some(metadata) => metadata,
+                None => {
+                    let Ok(metadata) = fs::read_to_string(&path).map_err(|e| e.to_string())
+                        .and_then(|data| serde_json::from_str::<ArtifactMetadata>(&data).map_err(|e| e.to_string()))
+                        .inspect_err(|e| tracing::warn!("Skipping artifact collection {}: {}", path.display(), e))
+                    else { continue };
+                    let mut guard = ARTIFACT_METADATA_CACHE.write().unwrap_or_else(|e| e.into_inner());
+                    let cache = guard.get_or_insert_with(HashMap::new);
+                    if cache.len() >= MAX_CACHED_ARTIFACT_METADATA && !cache.contains_key(&path) {
+                        if let Some(evicted) = cache.keys().next().cloned() {
+                            cache.remove(&evicted);
+                        }
+                    }
+                    cache.insert(path, (modified, size, metadata.clone()));
+                    metadata
+                }
+            };
+            collections.push(metadata);
+        }
+
+        if let Some(cache) = ARTIFACT_METADATA_CACHE.write().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            cache.retain(|path, _| !path.starts_with(artifacts_dir) || seen.contains(path));
+        }
+        Ok(collections)
+    }
+
     /// Get metadata for a specific run's artifacts
     pub async fn get_artifact_metadata(client_id: &str, run_id: &str) -> io::Result<ArtifactMetadata> {
         let path = format!("{}/artifacts/{}/{}/metadata.json", STORAGE_ROOT, client_id, run_id);
-        let data = fs::read_to_string(&path)?;
+        let data = tokio::fs::read_to_string(&path).await?;
         serde_json::from_str(&data)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_promotion_candidates_match_globs_and_skip_promoted() {
+        let dir = std::env::temp_dir().join(format!("raro-output-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("summary.md"), "# Summary").unwrap();
+        fs::write(dir.join("report.csv"), "a,b").unwrap();
+        fs::write(dir.join("scratch.py"), "print(1)").unwrap();
+        fs::write(dir.join("notes.md"), "changed").unwrap();
+
+        let patterns = vec![glob::Pattern::new("*.md").unwrap(), glob::Pattern::new("report.*").unwrap()];
+        let promoted = vec![
+            ArtifactFile {
+                filename: "summary.md".to_string(),
+                agent_id: "writer".to_string(),
+                generated_at: Utc::now().to_rfc3339(),
+                size_bytes: 9,
+                content_type: "text/markdown".to_string(),
+                digest: Some(WorkspaceInitializer::file_digest(&dir.join("summary.md")).unwrap()),
+            },
+            ArtifactFile {
+                filename: "notes.md".to_string(),
+                agent_id: "writer".to_string(),
+                generated_at: Utc::now().to_rfc3339(),
+                size_bytes: 3,
+                content_type: "text/markdown".to_string(),
+                digest: Some("stale".to_string()),
+            },
+        ];
+
+        let candidates = WorkspaceInitializer::promotion_candidates(&dir, &patterns, &promoted).unwrap();
+        assert_eq!(candidates, vec!["notes.md", "report.csv"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_session_outputs_listed_and_read_within_output_dir() {
+        let session = std::env::temp_dir().join(format!("raro-session-{}", uuid::Uuid::new_v4()));
+        let output = session.join("output");
+        fs::create_dir_all(output.join("nested")).unwrap();
+        fs::write(output.join("report.md"), "# Report").unwrap();
+        fs::write(output.join("chart.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        fs::write(output.join(".partial"), "").unwrap();
+        fs::write(session.join("secret.txt"), "outside").unwrap();
+
+        assert_eq!(WorkspaceInitializer::list_output_files(&output).unwrap(), vec!["chart.png", "report.md"]);
+        assert!(WorkspaceInitializer::list_output_files(&session.join("missing")).unwrap().is_empty());
+        let chart = WorkspaceInitializer::output_file_path(&output, "chart.png").unwrap();
+        assert_eq!(fs::read(chart).unwrap(), vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(WorkspaceInitializer::read_output_file(&output, "chart.png").unwrap(), vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(WorkspaceInitializer::read_output_file(&output, "../secret.txt").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+        for escape in ["../secret.txt", "nested/../../secret.txt", "/etc/passwd", ".partial", "..", ""] {
+            let err = WorkspaceInitializer::output_file_path(&output, escape).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", escape);
+        }
+        assert_eq!(WorkspaceInitializer::output_file_path(&output, "nested").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(WorkspaceInitializer::output_file_path(&output, "absent.md").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let _ = fs::remove_dir_all(&session);
+    }
+
+    #[test]
+    fn test_batch_promotion_is_all_or_nothing() {
+        let root = std::env::temp_dir().join(format!("raro-batch-{}", uuid::Uuid::new_v4()));
+        let (output, artifacts) = (root.join("output"), root.join("artifacts"));
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("report.html"), "<h1>v1</h1>").unwrap();
+        fs::write(output.join("chart.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        let new_metadata = || WorkspaceInitializer::create_new_metadata("run-1", "wf-1", "write a report");
+        let names = |list: &[&str]| list.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let read_metadata = || serde_json::from_str::<ArtifactMetadata>(&fs::read_to_string(artifacts.join("metadata.json")).unwrap()).unwrap();
+
+        let promoted = WorkspaceInitializer::promote_batch_into(&output, &artifacts, &names(&["report.html", "chart.png"]), "writer", new_metadata).unwrap();
+        assert_eq!(promoted.len(), 2);
+        assert_eq!(promoted[0].digest, Some(WorkspaceInitializer::file_digest(&output.join("report.html")).unwrap()));
+        assert_eq!(read_metadata().artifacts.len(), 2);
+
+        // One missing file stops the whole batch; the promoted copy and the metadata are untouched
+        fs::write(output.join("report.html"), "<h1>v2</h1>").unwrap();
+        let before = fs::read_to_string(artifacts.join("metadata.json")).unwrap();
+        let err = WorkspaceInitializer::promote_batch_into(&output, &artifacts, &names(&["report.html", "missing.png"]), "writer", new_metadata).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = WorkspaceInitializer::promote_batch_into(&output, &artifacts, &names(&["report.html", "report.html"]), "writer", new_metadata).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read_to_string(artifacts.join("report.html")).unwrap(), "<h1>v1</h1>");
+        assert_eq!(fs::read_to_string(artifacts.join("metadata.json")).unwrap(), before);
+
+        // Re-promoting replaces the file and its entry
+        WorkspaceInitializer::promote_batch_into(&output, &artifacts, &names(&["report.html"]), "writer", new_metadata).unwrap();
+        assert_eq!(fs::read_to_string(artifacts.join("report.html")).unwrap(), "<h1>v2</h1>");
+        let metadata = read_metadata();
+        assert_eq!(metadata.artifacts.len(), 2);
+        let entry = metadata.artifacts.iter().find(|a| a.filename == "report.html").unwrap();
+        assert_eq!(entry.digest, Some(WorkspaceInitializer::file_digest(&artifacts.join("report.html")).unwrap()));
+
+        let leftovers = fs::read_dir(&artifacts).unwrap().flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(STAGING_PREFIX))
+            .count();
+        assert_eq!(leftovers, 0);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_free_space_check_refuses_writes_below_threshold() {
+        let dir = std::env::temp_dir().join(format!("raro-space-{}", uuid::Uuid::new_v4()));
+        // The folder doesn't exist yet: its volume is measured through the nearest ancestor
+        let available = available_bytes(&dir).expect("statvfs of the temp volume");
+
+        assert!(ensure_free_space_above(&dir, 1024, 0).is_ok());
+        let before = storage_full_events();
+        let err = ensure_free_space_above(&dir, 1024, u64::MAX).unwrap_err();
+        assert!(is_storage_full(&err));
+        assert!(err.to_string().starts_with("Insufficient storage"));
+        assert!(storage_full_events() > before);
+        // Larger than the whole volume
+        assert!(ensure_free_space_above(&dir, available.saturating_add(1), 0).is_err());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_library_soft_delete_and_restore() {
+        let lib = std::env::temp_dir().join(format!("raro-library-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(lib.join("dataset.csv"), "a,b").unwrap();
+
+        let tombstone = WorkspaceInitializer::trash_library_file(&lib, "dataset.csv", "alice").unwrap();
+        assert_eq!((tombstone.deleted_by.as_str(), tombstone.size_bytes), ("alice", 3));
+        assert!(!lib.join("dataset.csv").exists());
+        assert_eq!(WorkspaceInitializer::list_trash(&lib).unwrap(), vec![tombstone]);
+
+        // Re-uploaded meanwhile: restore needs overwrite
+        fs::write(lib.join("dataset.csv"), "new").unwrap();
+        let err = WorkspaceInitializer::restore_library_file(&lib, "dataset.csv", false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        WorkspaceInitializer::restore_library_file(&lib, "dataset.csv", true).unwrap();
+        assert_eq!(fs::read_to_string(lib.join("dataset.csv")).unwrap(), "a,b");
+        assert!(WorkspaceInitializer::list_trash(&lib).unwrap().is_empty());
+
+        // Deleting a name twice keeps both copies; a filename restores the newest, a trash id its own
+        let first = WorkspaceInitializer::trash_library_file(&lib, "dataset.csv", "alice").unwrap();
+        fs::write(lib.join("dataset.csv"), "v2").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        WorkspaceInitializer::trash_library_file(&lib, "dataset.csv", "bob").unwrap();
+        assert_eq!(WorkspaceInitializer::list_trash(&lib).unwrap().len(), 2);
+        assert_eq!(WorkspaceInitializer::restore_library_file(&lib, "dataset.csv", false).unwrap().deleted_by, "bob");
+        assert_eq!(fs::read_to_string(lib.join("dataset.csv")).unwrap(), "v2");
+        WorkspaceInitializer::restore_library_file(&lib, &first.trash_id, true).unwrap();
+        assert_eq!(fs::read_to_string(lib.join("dataset.csv")).unwrap(), "a,b");
+
+        // Purge honours retention
+        WorkspaceInitializer::trash_library_file(&lib, "dataset.csv", "alice").unwrap();
+        assert_eq!(WorkspaceInitializer::purge_trash(&lib, chrono::Duration::days(1)).unwrap(), 0);
+        assert_eq!(WorkspaceInitializer::purge_trash(&lib, chrono::Duration::zero()).unwrap(), 1);
+        assert!(fs::read_dir(lib.join(TRASH_DIR)).unwrap().next().is_none());
+
+        let _ = fs::remove_dir_all(&lib);
+    }
+
+    #[tokio::test]
+    async fn test_large_listing_does_not_block_runtime() {
+        let public = std::env::temp_dir().join(format!("raro-public-{}", uuid::Uuid::new_v4()));
+        let private = std::env::temp_dir().join(format!("raro-private-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&public).unwrap();
+        fs::create_dir_all(&private).unwrap();
+        for i in 0..3_000 {
+            fs::write(public.join(format!("shared-{:04}.txt", i)), "").unwrap();
+        }
+        fs::write(private.join("shared-0000.txt"), "").unwrap();
+        fs::write(private.join(".trash"), "").unwrap();
+
+        // Single-threaded runtime: a synchronous scan would keep the other task from being polled
+        let listing = async {
+            let files = WorkspaceInitializer::list_merged_dirs(vec![public.clone(), private.clone()]).await.unwrap();
+            (files, std::time::Instant::now())
+        };
+        let other_request = async {
+            tokio::task::yield_now().await;
+            std::time::Instant::now()
+        };
+        let ((files, listed_at), other_done_at) = tokio::join!(listing, other_request);
+
+        assert_eq!(files.len(), 3_000);
+        assert_eq!(files[0], "shared-0000.txt");
+        assert!(other_done_at < listed_at);
+
+        let _ = fs::remove_dir_all(&public);
+        let _ = fs::remove_dir_all(&private);
+    }
+
+    #[test]
+    fn test_content_type_overrides_extend_builtin_map() {
+        let overrides = parse_content_types(" .parquet = application/vnd.apache.parquet, MD=text/x-markdown, broken, =x");
+        assert_eq!(overrides.len(), 2);
+
+        assert_eq!(content_type_for("events.PARQUET", Some(&overrides)), "application/vnd.apache.parquet");
+        assert_eq!(content_type_for("notes.md", Some(&overrides)), "text/x-markdown");
+        assert_eq!(content_type_for("notes.md", None), "text/markdown");
+        assert_eq!(content_type_for("chart.jpeg", Some(&overrides)), "image/jpeg");
+        assert_eq!(content_type_for("model.onnx", Some(&overrides)), "application/octet-stream");
+        assert_eq!(content_type_for("README", Some(&overrides)), "application/octet-stream");
+
+        // The download handlers go through the process-wide map
+        let _settings = crate::observability::GlobalSettingsGuard::acquire_blocking();
+        set_content_type_overrides(parse_content_types("parquet=application/vnd.apache.parquet"));
+        assert_eq!(guess_content_type("run_output.parquet"), "application/vnd.apache.parquet");
+    }
+
+    #[test]
+    fn test_client_workspace_bootstrapped_once() {
+        let root = std::env::temp_dir().join(format!("raro-bootstrap-{}", uuid::Uuid::new_v4()));
+        let defaults = root.join(DEFAULTS_SCOPE);
+        let client = root.join("acme");
+        fs::create_dir_all(&defaults).unwrap();
+        fs::write(defaults.join("style-guide.md"), "# Style").unwrap();
+        fs::write(defaults.join("glossary.csv"), "term,meaning").unwrap();
+        fs::write(defaults.join(".hidden"), "").unwrap();
+
+        assert_eq!(WorkspaceInitializer::bootstrap_library(&defaults, &client).unwrap(), Some(2));
+        assert!(client.join(INITIALIZED_MARKER).exists());
+        assert!(!client.join(".hidden").exists());
+        assert_eq!(WorkspaceInitializer::bootstrap_library(&defaults, &client).unwrap(), None);
+
+        // Even a forced re-initialization leaves the client's edits alone
+        fs::write(client.join("style-guide.md"), "# Our style").unwrap();
+        fs::remove_file(client.join("glossary.csv")).unwrap();
+        fs::remove_file(client.join(INITIALIZED_MARKER)).unwrap();
+        assert_eq!(WorkspaceInitializer::bootstrap_library(&defaults, &client).unwrap(), Some(1));
+        assert_eq!(fs::read_to_string(client.join("style-guide.md")).unwrap(), "# Our style");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_artifact_collections_cached_and_filtered() {
+        let root = std::env::temp_dir().join(format!("raro-artifacts-{}", uuid::Uuid::new_v4()));
+        let now = Utc::now();
+        let file = |filename: &str, content_type: &str, size_bytes: u64| ArtifactFile {
+            filename: filename.to_string(),
+            agent_id: "writer".to_string(),
+            generated_at: now.to_rfc3339(),
+            size_bytes,
+            content_type: content_type.to_string(),
+            digest: None,
+        };
+        let write = |run_id: &str, expires_in_days: i64, artifacts: Vec<ArtifactFile>| {
+            fs::create_dir_all(root.join(run_id)).unwrap();
+            let metadata = ArtifactMetadata {
+                run_id: run_id.to_string(),
+                workflow_id: "wf".to_string(),
+                user_directive: String::new(),
+                created_at: now.to_rfc3339(),
+                expires_at: (now + chrono::Duration::days(expires_in_days)).to_rfc3339(),
+                artifacts,
+                status: "active".to_string(),
+            };
+            fs::write(root.join(run_id).join("metadata.json"), serde_json::to_string(&metadata).unwrap()).unwrap();
+        };
+        write("run-soon", 1, vec![file("a.csv", "text/csv", 10), file("a.md", "text/markdown", 5)]);
+        write("run-later", 6, vec![file("b.md", "text/markdown", 7)]);
+        fs::create_dir_all(root.join("run-broken")).unwrap();
+        fs::write(root.join("run-broken").join("metadata.json"), "{").unwrap();
+
+        let collections = WorkspaceInitializer::artifact_collections(&root).unwrap();
+        assert_eq!(collections.len(), 2);
+        let path = root.join("run-soon").join("metadata.json");
+        assert!(ARTIFACT_METADATA_CACHE.read().unwrap().as_ref().unwrap().contains_key(&path));
+
+        let summaries = |filter: ArtifactListFilter| {
+            let mut s: Vec<(String, usize, u64)> = WorkspaceInitializer::artifact_collections(&root).unwrap().iter()
+                .filter_map(|m| filter.summarize(m, now))
+                .map(|s| (s.run_id, s.artifact_count, s.total_bytes))
+                .collect();
+            s.sort();
+            s
+        };
+        let expiring = ArtifactListFilter { expiring_within_days: Some(2), content_type: None };
+        assert_eq!(summaries(expiring.clone()), vec![("run-soon".to_string(), 2, 15)]);
+        let csv = ArtifactListFilter { expiring_within_days: None, content_type: Some("text/csv".to_string()) };
+        assert_eq!(summaries(csv.clone()), vec![("run-soon".to_string(), 1, 10)]);
+
+        // A rewritten metadata.json is picked up despite the cache
+        write("run-later", 1, vec![file("b.md", "text/markdown", 7), file("b.csv", "text/csv", 3)]);
+        assert_eq!(summaries(expiring).len(), 2);
+        assert_eq!(summaries(csv), vec![("run-later".to_string(), 1, 3), ("run-soon".to_string(), 1, 10)]);
+
+        // A deleted run's entry leaves the cache on the next listing
+        fs::remove_dir_all(root.join("run-soon")).unwrap();
+        assert_eq!(WorkspaceInitializer::artifact_collections(&root).unwrap().len(), 1);
+        assert!(!ARTIFACT_METADATA_CACHE.read().unwrap().as_ref().unwrap().contains_key(&path));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,93 @@
+// [[RARO]]/apps/kernel-server/src/hooks.rs
+// Purpose: Workflow lifecycle hooks (WorkflowConfig::hooks). Lets external policy engines
+//          inspect, rewrite or veto agent invocations and observe run outcomes.
+// Architecture: Integration Layer
+// Dependencies: Reqwest, Serde
+//
+// Hook URLs come from workflows, so each delivery first passes the EgressPolicy check.
+
+use serde::Serialize;
+use crate::egress::EgressPolicy;
+use crate::runtime::InvocationPayload;
+
+/// Verdict of a before_agent hook
+#[derive(Debug)]
+pub enum HookDecision {
+    /// Dispatch this payload (the original, or the hook's rewrite)
+    Proceed(Box<InvocationPayload>),
+    /// The hook answered 403; carries the response body as the reason
+    Veto(String),
+}
+
+/// Same delivery semantics as the Cortex webhook action: one POST, 10s timeout
+#[derive(Clone)]
+pub struct HookClient {
+    client: reqwest::Client,
+    egress: EgressPolicy,
+}
+
+impl HookClient {
+    pub fn new(egress: EgressPolicy) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .unwrap_or_else(|_| reqwest::Client::new()),
+            egress,
+        }
+    }
+
+    /// An empty 2xx body keeps the payload; a JSON body replaces it. The rewrite may not
+    /// retarget the invocation to another run or agent.
+    pub async fn before_agent(&self, url: &str, payload: InvocationPayload) -> Result<HookDecision, String> {
+        self.egress.check(url).await.map_err(|e| format!("before_agent hook refused: {}", e))?;
+        let response = self.client.post(url).json(&payload).send().await
+            .map_err(|e| format!("before_agent hook {} unreachable: {}", url, e))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::FORBIDDEN {
+            let reason = response.text().await.unwrap_or_default();
+            return Ok(HookDecision::Veto(if reason.trim().is_empty() { "Vetoed by before_agent hook".to_string() } else { reason }));
+        }
+        if !status.is_success() {
+            return Err(format!("before_agent hook {} returned {}", url, status));
+        }
+
+        let body = response.bytes().await
+            .map_err(|e| format!("before_agent hook {} response unreadable: {}", url, e))?;
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(HookDecision::Proceed(Box::new(payload)));
+        }
+
+        let rewritten: InvocationPayload = serde_json::from_slice(&body)
+            .map_err(|e| format!("before_agent hook {} returned an invalid payload: {}", url, e))?;
+        if rewritten.run_id != payload.run_id || rewritten.agent_id != payload.agent_id {
+            return Err(format!("before_agent hook {} changed the run or agent id", url));
+        }
+        Ok(HookDecision::Proceed(Box::new(rewritten)))
+    }
+
+    /// Fire-and-forget notification (after_agent, on_complete, on_fail); failures are logged
+    pub fn notify<T: Serialize>(&self, hook: &'static str, url: &str, body: &T) {
+        let body = match serde_json::to_value(body) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("Hook {} body not serializable: {}", hook, e);
+                return;
+            }
+        };
+        let (client, egress) = (self.client.clone(), self.egress.clone());
+        let url = url.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = egress.check(&url).await {
+                tracing::warn!("Hook {} not delivered: {}", hook, e);
+                return;
+            }
+            match client.post(&url).json(&body).send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => tracing::warn!("Hook {} at {} returned {}", hook, url, response.status()),
+                Err(e) => tracing::warn!("Hook {} at {} unreachable: {}", hook, url, e),
+            }
+        });
+    }
+}
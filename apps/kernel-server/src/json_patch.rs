@@ -0,0 +1,156 @@
+// [[RARO]]/apps/kernel-server/src/json_patch.rs
+// Purpose: RFC 6902 JSON Patch (add, remove, replace, move, copy, test) over serde_json values.
+// Architecture: Domain Helper Layer
+// Dependencies: serde_json, thiserror
+
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PatchError {
+    #[error("Operation {index}: invalid JSON pointer '{pointer}'")]
+    InvalidPointer { index: usize, pointer: String },
+    #[error("Operation {index}: path '{path}' does not exist")]
+    PathNotFound { index: usize, path: String },
+    #[error("Operation {index}: test failed at '{path}'")]
+    TestFailed { index: usize, path: String },
+    #[error("Operation {index}: cannot move '{from}' into its own child")]
+    MoveIntoChild { index: usize, from: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Apply `ops` in order to a copy of `doc`; the original is untouched if any operation fails
+pub fn apply(doc: &Value, ops: &[PatchOp]) -> Result<Value, PatchError> {
+    let mut patched = doc.clone();
+    for (index, op) in ops.iter().enumerate() {
+        match op {
+            PatchOp::Add { path, value } => add(&mut patched, index, path, value.clone())?,
+            PatchOp::Remove { path } => {
+                remove(&mut patched, index, path)?;
+            }
+            PatchOp::Replace { path, value } => {
+                *lookup(&mut patched, index, path)? = value.clone();
+            }
+            PatchOp::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(PatchError::MoveIntoChild { index, from: from.clone() });
+                }
+                let value = remove(&mut patched, index, from)?;
+                add(&mut patched, index, path, value)?;
+            }
+            PatchOp::Copy { from, path } => {
+                let value = lookup(&mut patched, index, from)?.clone();
+                add(&mut patched, index, path, value)?;
+            }
+            PatchOp::Test { path, value } => {
+                if lookup(&mut patched, index, path)? != value {
+                    return Err(PatchError::TestFailed { index, path: path.clone() });
+                }
+            }
+        }
+    }
+    Ok(patched)
+}
+
+/// "/a/b~1c" -> ["a", "b/c"]
+fn tokens(index: usize, pointer: &str) -> Result<Vec<String>, PatchError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = pointer.strip_prefix('/')
+        .ok_or_else(|| PatchError::InvalidPointer { index, pointer: pointer.to_string() })?;
+    Ok(rest.split('/').map(|t| t.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+fn lookup<'a>(doc: &'a mut Value, index: usize, path: &str) -> Result<&'a mut Value, PatchError> {
+    let not_found = || PatchError::PathNotFound { index, path: path.to_string() };
+    let mut current = doc;
+    for token in tokens(index, path)? {
+        current = match current {
+            Value::Object(map) => map.get_mut(&token),
+            Value::Array(items) => token.parse::<usize>().ok().and_then(move |i| items.get_mut(i)),
+            _ => None,
+        }.ok_or_else(not_found)?;
+    }
+    Ok(current)
+}
+
+/// Container of the last token, plus that token
+fn parent<'a>(doc: &'a mut Value, index: usize, path: &str) -> Result<(&'a mut Value, String), PatchError> {
+    let mut tokens = tokens(index, path)?;
+    let last = tokens.pop().ok_or_else(|| PatchError::InvalidPointer { index, pointer: path.to_string() })?;
+    let parent_path: String = tokens.iter().map(|t| format!("/{}", t.replace('~', "~0").replace('/', "~1"))).collect();
+    Ok((lookup(doc, index, &parent_path)?, last))
+}
+
+fn add(doc: &mut Value, index: usize, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let not_found = || PatchError::PathNotFound { index, path: path.to_string() };
+    match parent(doc, index, path)? {
+        (Value::Object(map), key) => {
+            map.insert(key, value);
+        }
+        (Value::Array(items), key) if key == "-" => items.push(value),
+        (Value::Array(items), key) => {
+            let i = key.parse::<usize>().ok().filter(|i| *i <= items.len()).ok_or_else(not_found)?;
+            items.insert(i, value);
+        }
+        _ => return Err(not_found()),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, index: usize, path: &str) -> Result<Value, PatchError> {
+    let not_found = || PatchError::PathNotFound { index, path: path.to_string() };
+    match parent(doc, index, path)? {
+        (Value::Object(map), key) => map.remove(&key).ok_or_else(not_found),
+        (Value::Array(items), key) => {
+            let i = key.parse::<usize>().ok().filter(|i| *i < items.len()).ok_or_else(not_found)?;
+            Ok(items.remove(i))
+        }
+        _ => Err(not_found()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(v: Value) -> Vec<PatchOp> {
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[test]
+    fn test_operations_follow_rfc6902() {
+        let doc = serde_json::json!({ "a": { "b~c": 1 }, "list": [1, 2] });
+        let patched = apply(&doc, &ops(serde_json::json!([
+            { "op": "test", "path": "/a/b~0c", "value": 1 },
+            { "op": "add", "path": "/list/-", "value": 3 },
+            { "op": "add", "path": "/list/0", "value": 0 },
+            { "op": "replace", "path": "/a/b~0c", "value": 2 },
+            { "op": "copy", "from": "/a", "path": "/copy" },
+            { "op": "move", "from": "/copy/b~0c", "path": "/moved" },
+            { "op": "remove", "path": "/list/1" }
+        ]))).unwrap();
+        assert_eq!(patched, serde_json::json!({ "a": { "b~c": 2 }, "list": [0, 2, 3], "copy": {}, "moved": 2 }));
+
+        let failing = ops(serde_json::json!([{ "op": "remove", "path": "/missing" }]));
+        assert_eq!(apply(&doc, &failing), Err(PatchError::PathNotFound { index: 0, path: "/missing".to_string() }));
+        let failing = ops(serde_json::json!([{ "op": "test", "path": "/list/0", "value": 9 }]));
+        assert!(matches!(apply(&doc, &failing), Err(PatchError::TestFailed { .. })));
+    }
+}
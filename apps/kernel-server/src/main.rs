@@ -10,17 +10,39 @@ mod runtime;
 mod observability;
 mod events;
 mod registry;
+mod model_registry;
 mod fs_manager; // Register new module
 mod security; // Session identity extractor
+mod template; // Prompt templating ({{agents.<id>.output.<path>}})
+mod search; // Run search index
+mod signatures; // Thought signature validation/compression
+mod cortex; // Event bus -> pattern -> action pipeline
+mod usage; // Per-client monthly usage rollups
+mod capabilities; // GET /capabilities feature/limit discovery
+mod simulation; // Fake agent executor for simulated runs
+mod hooks; // Workflow lifecycle webhooks
+mod replay; // State reconstruction from the event log
+mod json_patch; // RFC 6902 patches for stored workflows
+mod costs; // Cost attribution rollups per client and run tag
+mod event_stream; // Per-run event bus subscriptions with lag resync
+mod execution_trace; // Gantt traces and scheduling comparison from invocation timings
+mod event_limits; // Rate limits and bounded buffering for agent-sourced events
+mod blackboard; // Per-run working memory shared by a run's agents
+mod agent_stats; // Per-agent latency/token moving averages across runs
+mod client_policy; // Per-client allowed models and daily model quotas
+mod output_cache; // Cross-run reuse of agent outputs (cache_policy "cross_run")
+mod share; // Signed read-only share links for finished runs
+mod verdict; // Success-criteria verdicts for completed runs
+mod faults; // Fault injection for resiliency testing (RARO_FAULT_INJECTION)
+mod timeline; // Typed run timeline for dashboards (events + state)
+mod egress; // Outbound URL policy for workflow-supplied webhooks (RARO_EGRESS_ALLOWLIST)
+mod redis_keys; // SCAN-based key listing for boot-time restores
 
 use axum::{
     Router,
     routing::{get, post},
-    http::Method,
 };
 use std::sync::Arc;
-use tower_http::cors::{CorsLayer, Any};
-use tracing_subscriber;
 use futures::StreamExt;  // For Redis PubSub stream
 
 use crate::runtime::RARORuntime;
@@ -28,83 +50,140 @@ use crate::server::handlers;
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("raro_kernel=debug".parse().unwrap())
-                .add_directive("tower_http=trace".parse().unwrap()),
-        )
-        .init();
+    // Initialize tracing (filter can be swapped later via POST /admin/log_level)
+    let log_filter = observability::init_tracing();
 
     tracing::info!("Initializing RARO Kernel...");
 
-    let runtime = Arc::new(RARORuntime::new());
+    observability::init_prompt_redaction_from_env();
+    observability::init_client_anonymization_from_env();
+    fs_manager::init_content_types_from_env();
+    fs_manager::init_min_free_space_from_env();
+
+    let mut runtime = RARORuntime::new();
+    runtime.log_filter = Some(Arc::new(std::sync::Mutex::new(log_filter)));
+    runtime.agent_stats = agent_stats::AgentStatsStore::from_env();
+    let runtime = Arc::new(runtime);
 
     // === PERSISTENCE RECOVERY ===
     // Attempt to load previous run states from Redis into memory
     runtime.rehydrate_from_redis().await;
 
     // === CORTEX: Pattern Engine ===
-    // Subscribe to the event bus and spawn background pattern matcher
-    let mut rx = runtime.event_bus.subscribe();
+    // Subscribe to the event bus and dispatch matched pattern actions in the background
+    let cortex_task = cortex::CortexEngine::new(runtime.clone()).start();
+    runtime.register_background_task("cortex", &cortex_task);
+
+    // === CACHE VERIFICATION ===
+    // Optional periodic sweep for cached contents that expired upstream
+    let verify_interval = std::env::var("CACHE_VERIFY_INTERVAL_SECS").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+    if verify_interval > 0 {
+        let runtime_ref = runtime.clone();
+        let verify_task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(verify_interval));
+            loop {
+                ticker.tick().await;
+                if let Err(e) = runtime_ref.verify_cache_resources().await {
+                    tracing::warn!("Cache verification skipped: {}", e);
+                    break;
+                }
+            }
+        });
+        runtime.register_background_task("cache_verifier", &verify_task);
+    }
+
+    // === AGENT STATS FLUSH ===
+    // Recording only marks the stats table dirty; it is written out here, off the async workers
     let runtime_ref = runtime.clone();
+    let stats_task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(5));
+        loop {
+            ticker.tick().await;
+            let runtime = runtime_ref.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || runtime.agent_stats.flush()).await {
+                tracing::warn!("Agent stats flush failed: {}", e);
+            }
+        }
+    });
+    runtime.register_background_task("agent_stats_flush", &stats_task);
 
-    tokio::spawn(async move {
-        tracing::info!("Cortex Pattern Engine started");
+    // === LIBRARY TRASH JANITOR ===
+    // Hourly purge of soft-deleted library files older than RARO_TRASH_RETENTION_DAYS (default 30)
+    let retention_days = std::env::var("RARO_TRASH_RETENTION_DAYS").ok().and_then(|v| v.parse::<i64>().ok()).unwrap_or(30);
+    let janitor_task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
         loop {
-            if let Ok(event) = rx.recv().await {
-                // 1. Find matching patterns
-                let patterns = runtime_ref.pattern_registry.get_patterns_for_trigger(&format!("{:?}", event.event_type));
-
-                for pattern in patterns {
-                    // 2. Evaluate Condition (Simple string match for MVP)
-                    // In Phase 4, we use a real JSONPath engine here.
-                    let condition_met = if pattern.condition == "*" {
-                        true
-                    } else {
-                        // Very basic check: Does payload string contain the condition keyword?
-                        event.payload.to_string().contains(&pattern.condition)
-                    };
-
-                    if condition_met {
-                        tracing::info!("⚠️  Pattern Triggered: {} on Agent {}", pattern.name, event.agent_id.as_deref().unwrap_or("?"));
-
-                        // 3. Execute Action
-                        match pattern.action {
-                            crate::registry::PatternAction::Interrupt { reason } => {
-                                if let Some(agent) = &event.agent_id {
-                                    // Direct call to fail_run (simulating interrupt)
-                                    runtime_ref.fail_run(&event.run_id, agent, &reason).await;
-                                }
-                            }
-                            crate::registry::PatternAction::RequestApproval { reason } => {
-                                tracing::warn!("✋ Safety Pattern Triggered: Approval Required - {}", reason);
-
-                                // CALL THE NEW PAUSE METHOD
-                                if let Some(agent) = &event.agent_id {
-                                    runtime_ref.request_approval(&event.run_id, Some(agent), &reason).await;
-                                } else {
-                                    runtime_ref.request_approval(&event.run_id, None, &reason).await;
-                                }
-                            }
-                            crate::registry::PatternAction::SpawnAgent { .. } => {
-                                tracing::warn!("SpawnAgent action not yet implemented in Cortex");
-                            }
-                        }
-                    }
+            ticker.tick().await;
+            // Walks every client's trash: blocking fs work, kept off the async workers
+            let retention = chrono::Duration::days(retention_days);
+            let purged = tokio::task::spawn_blocking(move || fs_manager::WorkspaceInitializer::purge_all_trash(retention))
+                .await
+                .unwrap_or(0);
+            if purged > 0 {
+                tracing::info!("Trash janitor purged {} library files", purged);
+            }
+        }
+    });
+    runtime.register_background_task("trash_janitor", &janitor_task);
+
+    // === RUN JANITOR ===
+    // Same purge as POST /admin/gc, every 10 minutes, for runs finished more than
+    // RARO_RUN_RETENTION_MINUTES ago (default 0 = keep runs until restart)
+    let run_retention = std::env::var("RARO_RUN_RETENTION_MINUTES").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+    if run_retention > 0 {
+        let runtime_ref = runtime.clone();
+        let run_janitor = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(600));
+            let request = runtime::GcRequest { max_age_minutes: run_retention, status_filter: Vec::new(), dry_run: false };
+            loop {
+                ticker.tick().await;
+                match runtime_ref.collect_garbage(&request) {
+                    Ok(result) if result.deleted_run_count > 0 || !result.errors.is_empty() => tracing::info!(
+                        "Run janitor deleted {} runs ({} signatures freed, {} errors)",
+                        result.deleted_run_count, result.freed_signatures, result.errors.len()
+                    ),
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Run janitor failed: {}", e),
                 }
             }
+        });
+        runtime.register_background_task("run_janitor", &run_janitor);
+    }
+
+    // === STALL DETECTOR ===
+    // Flags runs whose active agents went silent for RARO_STALL_THRESHOLD_SECS (default 900, 0 = off)
+    let stall_threshold = std::env::var("RARO_STALL_THRESHOLD_SECS").ok().and_then(|v| v.parse::<i64>().ok()).unwrap_or(900);
+    if stall_threshold > 0 {
+        let runtime_ref = runtime.clone();
+        let stall_task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                ticker.tick().await;
+                runtime_ref.detect_stalled_runs(chrono::Duration::seconds(stall_threshold)).await;
+            }
+        });
+        runtime.register_background_task("stall_detector", &stall_task);
+    }
+
+    // === PREPARE TIMEOUT ===
+    // Cancels two-phase runs left in Preparing past their deadline (RARO_PREPARE_TIMEOUT_SECS, default 600)
+    let runtime_ref = runtime.clone();
+    let prepare_task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(30));
+        loop {
+            ticker.tick().await;
+            runtime_ref.cancel_expired_preparations(chrono::Utc::now()).await;
         }
     });
+    runtime.register_background_task("prepare_timeout", &prepare_task);
 
     // === REDIS LIVE LOG SUBSCRIBER ===
     // Listens to "raro:live_logs" channel and bridges messages to internal event bus
     if let Some(redis_client) = &runtime.redis_client {
         let client = redis_client.clone();
-        let event_bus = runtime.event_bus.clone();
+        let runtime_ref = runtime.clone();
 
-        tokio::spawn(async move {
+        let subscriber_task = tokio::spawn(async move {
             tracing::info!("🎧 Started Redis Log Subscriber on 'raro:live_logs'");
 
             // Establish PubSub connection
@@ -136,8 +215,9 @@ async fn main() {
                     let metadata = data["metadata"].as_str().unwrap_or("INFO");
                     let category = data["category"].as_str().unwrap_or("INFO");
 
-                    // Bridge to internal Event Bus (which WebSockets subscribe to)
-                    let _ = event_bus.send(crate::events::RuntimeEvent::new(
+                    // Bridge to internal Event Bus (which WebSockets subscribe to). Over the
+                    // run's rate limit the entry is dropped; the limiter counts it.
+                    let _ = runtime_ref.try_emit_event(crate::events::RuntimeEvent::new(
                         run_id,
                         crate::events::EventType::IntermediateLog,
                         agent_id.map(|s| s.to_string()),
@@ -148,45 +228,121 @@ async fn main() {
                         })
                     ));
                 } else {
-                    tracing::warn!("Failed to parse Redis log payload: {}", payload_str);
+                    tracing::warn!("Failed to parse Redis log payload: {}", observability::loggable_prompt(&payload_str));
                 }
             }
 
             tracing::warn!("Redis log subscriber exited unexpectedly");
         });
+        runtime.register_background_task("redis_log_subscriber", &subscriber_task);
     } else {
         tracing::warn!("Redis client not available - live logs disabled");
     }
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::DELETE])
-        .allow_headers(Any)
-        .expose_headers(Any); // Allow custom headers like X-RARO-CLIENT-ID
+    // CORS plus nosniff/framing/HSTS/CSP headers (artifacts are user content served from our origin)
+    let security_headers = server::security_headers::security_headers_from_env();
 
-    // Build router
+    // Build router. Everything here is subject to the request timeout; the streaming routes
+    // below are merged in after it is applied.
     let app = Router::new()
         .route("/health", get(handlers::health))
-        .route("/runtime/start", post(handlers::start_workflow))
+        .route("/capabilities", get(handlers::get_capabilities))
+        .route("/me/usage", get(handlers::get_my_usage))
+        .route("/metrics", get(handlers::get_prometheus_metrics))
+        .route("/metrics/models", get(handlers::get_model_metrics))
+        .route("/metrics/costs", get(handlers::get_cost_metrics))
+        .route("/metrics/trend", get(handlers::get_metrics_trend))
+        .route("/runtime/start", post(handlers::start_workflow)
+            .layer(axum::extract::DefaultBodyLimit::max(runtime.workflow_limits.max_config_bytes)))
+        .route("/workflows/:workflow_id", axum::routing::patch(handlers::patch_workflow))
+        .route("/workflows/:workflow_id/stats", get(handlers::get_workflow_stats))
+        .route("/runtime/validate", post(handlers::validate_workflow)
+            .layer(axum::extract::DefaultBodyLimit::max(runtime.workflow_limits.max_config_bytes)))
+        .route("/runtime/validate/verdict", post(handlers::dry_run_verdict)
+            .layer(axum::extract::DefaultBodyLimit::max(runtime.workflow_limits.max_config_bytes)))
         .route("/runtime/state", get(handlers::get_runtime_state))
+        .route("/runtime/import", post(handlers::import_run))
+        .route("/runtime/search", get(handlers::search_runs))
+        .route("/runtime/:run_id/export", get(handlers::export_run))
         .route("/runtime/:run_id/agent/:agent_id/invoke", post(handlers::invoke_agent))
+        .route("/runtime/runs", get(handlers::list_runs).post(handlers::create_run)
+            .layer(axum::extract::DefaultBodyLimit::max(runtime.workflow_limits.max_config_bytes)))
+        .route("/runtime/:run_id/launch", post(handlers::launch_run))
+        .route("/runtime/:run_id/attachments", post(handlers::attach_run_files))
         .route("/runtime/signatures", get(handlers::get_signatures))
+        .route("/runtime/:run_id/signatures/history", get(handlers::get_signature_history))
         .route("/runtime/:run_id/artifact/:agent_id", get(handlers::get_artifact))
+        .route("/runtime/:run_id/result", get(handlers::get_run_result))
+        .route("/runtime/:run_id/stages", get(handlers::get_run_stages))
+        .route("/runtime/:run_id/cost_breakdown", get(handlers::get_cost_breakdown))
+        .route("/runtime/:run_id/trace/execution", get(handlers::get_execution_trace))
+        .route("/runtime/:run_id/timeline", get(handlers::get_run_timeline))
+        .route("/runtime/:run_id/trace/execution/compare/:other_run_id", get(handlers::compare_execution_traces))
+        .route("/runtime/:run_id/ready", get(handlers::get_ready_agents))
+        .route("/runtime/:run_id/ready_agents", get(handlers::get_ready_agents))
         .route("/runtime/:run_id/resume", post(handlers::resume_run))
         .route("/runtime/:run_id/stop", post(handlers::stop_run))
+        .route("/runtime/:run_id/feedback", post(handlers::submit_feedback))
+        .route("/runtime/:run_id/cache", axum::routing::put(handlers::register_run_cache).delete(handlers::detach_run_cache))
+        .route("/runtime/:run_id/agent/:agent_id/routing", get(handlers::get_agent_routing))
+        .route("/runtime/:run_id/agent/:agent_id/skip", post(handlers::skip_agent))
+        .route("/runtime/:run_id/agent/:agent_id/config", axum::routing::patch(handlers::patch_agent_config))
+        .route("/runtime/:run_id/agent/:agent_id/logs", get(handlers::get_agent_logs).post(handlers::post_agent_log))
+        .route("/runtime/:run_id/memory/:key", get(handlers::get_memory).put(handlers::put_memory).delete(handlers::delete_memory))
+        .route("/runtime/:run_id/invocations/batch", post(handlers::record_invocations_batch))
+        .route("/runtime/:run_id/invocations/:invocation_id/replay", post(handlers::replay_invocation))
+        .route("/runtime/:run_id/state/at", get(handlers::get_state_at))
+        .route("/runtime/:run_id/checkpoint", get(handlers::get_checkpoint).post(handlers::apply_checkpoint))
         .route("/runtime/library", get(handlers::list_library_files))
-        .route("/runtime/library/upload", post(handlers::upload_library_file))
-        .route("/runtime/:run_id/files/:filename", get(handlers::serve_session_file))
+        .route("/runtime/library/files/:filename", axum::routing::delete(handlers::delete_library_file))
+        .route("/runtime/library/trash", get(handlers::list_library_trash))
+        .route("/runtime/library/trash/:filename/restore", post(handlers::restore_library_file))
+        .route("/runtime/:run_id/files/:filename", get(handlers::read_session_output))
+        .route("/runtime/:run_id/outputs", get(handlers::list_session_outputs))
+        .route("/runtime/:run_id/output/:filename", get(handlers::read_session_output))
+        .route("/runtime/:run_id/artifacts/promote", post(handlers::promote_artifacts_batch))
+        .route("/runtime/:run_id/share", post(handlers::create_share))
+        .route("/runtime/:run_id/shares", get(handlers::list_shares))
+        .route("/runtime/:run_id/share/:share_id", axum::routing::delete(handlers::revoke_share))
+        .route("/shared/:token", get(handlers::get_shared_run))
+        .route("/shared/:token/files/:filename", get(handlers::serve_shared_file))
         // Artifact Storage Routes
+        .route("/artifacts", get(handlers::list_client_artifacts))
         .route("/runtime/artifacts", get(handlers::list_all_artifacts))
         .route("/runtime/artifacts/:run_id", get(handlers::get_run_artifacts))
         .route("/runtime/artifacts/:run_id", axum::routing::delete(handlers::delete_artifact_run))
         .route("/runtime/artifacts/:run_id/files/:filename", get(handlers::serve_artifact_file))
         .route("/runtime/artifacts/:run_id/files/:filename/promote", post(handlers::promote_artifact_to_library))
+        // Admin Routes
+        .route("/admin/models", get(handlers::list_model_mappings))
+        .route("/admin/models/reload", post(handlers::reload_model_mappings))
+        .route("/admin/caches", get(handlers::list_caches))
+        .route("/admin/caches/verify", post(handlers::verify_caches))
+        .route("/admin/clients/:client_id/usage", get(handlers::get_client_usage))
+        .route("/admin/clients/:client_id/halt", post(handlers::halt_client))
+        .route("/admin/clients/:client_id/model_policy", get(handlers::get_model_policy).put(handlers::set_model_policy))
+        .route("/admin/runs/:run_id/priority", post(handlers::set_run_priority))
+        .route("/admin/schedule", get(handlers::get_dispatch_queue))
+        .route("/admin/system", get(handlers::get_system_status))
+        .route("/admin/gc", post(handlers::collect_garbage))
+        .route("/admin/log_level", post(handlers::set_log_level))
+        .route("/admin/maintenance", get(handlers::get_maintenance).post(handlers::set_maintenance))
+        .route("/admin/clients/:client_id/unhalt", post(handlers::unhalt_client));
+
+    let streaming = Router::new()
         // WebSocket
         .route("/ws/runtime/:run_id", axum::routing::get(handlers::ws_runtime_stream))
-        .layer(cors)
+        // SSE
+        .route("/runtime/:run_id/agent/:agent_id/logs/tail", get(handlers::tail_agent_logs))
+        // Large multipart bodies may legitimately take longer than the timeout to arrive
+        .route("/runtime/library/upload", post(handlers::upload_library_file)
+            .layer(axum::extract::DefaultBodyLimit::max(capabilities::MAX_UPLOAD_BYTES)));
+
+    let app = server::timeout::with_request_timeout(app, server::timeout::request_timeout_from_env())
+        .merge(streaming)
+        .layer(axum::middleware::from_fn_with_state(runtime.clone(), handlers::reject_aborted_runs));
+    let app = server::security_headers::with_security_headers(app, &security_headers)
+        .layer(axum::middleware::from_fn(server::error::ensure_error_body))
         .with_state(runtime);
 
     let port = std::env::var("KERNEL_PORT").unwrap_or_else(|_| "3000".to_string());
@@ -0,0 +1,187 @@
+// [[RARO]]/apps/kernel-server/src/model_registry.rs
+// Purpose: Model Mapping Table. Resolves ModelVariant aliases to concrete API models.
+// Architecture: Configuration Layer
+// Dependencies: Serde, Models
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::RwLock;
+use crate::models::ModelVariant;
+
+const DEFAULT_MODEL_MAP_PATH: &str = "config/model_mappings.json";
+
+/// How a single ModelVariant is served
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelMapping {
+    /// Variant key: "fast", "reasoning", "thinking" or a custom model id
+    pub variant: String,
+    pub api_model_name: String,
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+    #[serde(default)]
+    pub supports_thinking: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+pub struct ModelRegistry {
+    path: String,
+    mappings: RwLock<HashMap<String, ModelMapping>>,
+}
+
+impl ModelRegistry {
+    pub fn new() -> Self {
+        let path = std::env::var("MODEL_MAP_PATH").unwrap_or_else(|_| DEFAULT_MODEL_MAP_PATH.to_string());
+        Self::from_path(&path)
+    }
+
+    pub fn from_path(path: &str) -> Self {
+        let registry = Self {
+            path: path.to_string(),
+            mappings: RwLock::new(HashMap::new()),
+        };
+
+        if let Err(e) = registry.reload() {
+            tracing::warn!("{}. Loading fallback model mappings.", e);
+            registry.install(Self::fallback_mappings());
+        }
+
+        registry
+    }
+
+    /// Re-read the mapping file. On failure the currently loaded table is kept.
+    pub fn reload(&self) -> Result<usize, String> {
+        let data = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Model mapping file not readable at '{}': {}", self.path, e))?;
+        let mappings = serde_json::from_str::<Vec<ModelMapping>>(&data)
+            .map_err(|e| format!("Failed to parse model mapping file: {}", e))?;
+
+        let count = mappings.len();
+        self.install(mappings);
+        tracing::info!("Loaded {} model mappings from '{}'", count, self.path);
+        Ok(count)
+    }
+
+    fn install(&self, mappings: Vec<ModelMapping>) {
+        let table = mappings.into_iter().map(|m| (m.variant.clone(), m)).collect();
+        if let Ok(mut guard) = self.mappings.write() {
+            *guard = table;
+        }
+    }
+
+    /// Resolve a variant to its live mapping.
+    /// Named variants must be mapped and enabled; custom ids pass through unless explicitly disabled.
+    pub fn resolve(&self, variant: &ModelVariant) -> Result<ModelMapping, String> {
+        let key = variant.as_str();
+        let guard = self.mappings.read().map_err(|_| "Model registry lock poisoned".to_string())?;
+
+        match (guard.get(key), variant) {
+            (Some(m), _) if m.enabled => Ok(m.clone()),
+            (Some(_), _) => Err(format!("Model variant '{}' is disabled", key)),
+            (None, ModelVariant::Custom(id)) => Ok(ModelMapping {
+                variant: id.clone(),
+                api_model_name: id.clone(),
+                max_output_tokens: None,
+                supports_thinking: false,
+                enabled: true,
+            }),
+            (None, _) => Err(format!("No model mapping configured for variant '{}'", key)),
+        }
+    }
+
+    /// The variant a mapping key names; keys other than the built-in ones are custom ids
+    pub fn variant_for_key(key: &str) -> ModelVariant {
+        match key {
+            "fast" => ModelVariant::Fast,
+            "reasoning" => ModelVariant::Reasoning,
+            "thinking" => ModelVariant::Thinking,
+            other => ModelVariant::Custom(other.to_string()),
+        }
+    }
+
+    /// Reverse lookup from a concrete API model name (first match in variant order). Ambiguous
+    /// when variants share an API model (fast and thinking by default): prefer the mapping key
+    /// a payload records (InvocationPayload::model_variant).
+    pub fn variant_for_api_model(&self, api_model_name: &str) -> ModelVariant {
+        let guard = match self.mappings.read() {
+            Ok(g) => g,
+            Err(_) => return ModelVariant::Custom(api_model_name.to_string()),
+        };
+
+        for variant in [ModelVariant::Fast, ModelVariant::Reasoning, ModelVariant::Thinking] {
+            if guard.get(variant.as_str()).map(|m| m.api_model_name == api_model_name).unwrap_or(false) {
+                return variant;
+            }
+        }
+
+        Self::variant_for_key(api_model_name)
+    }
+
+    pub fn list(&self) -> Vec<ModelMapping> {
+        let mut mappings: Vec<ModelMapping> = self.mappings
+            .read()
+            .map(|g| g.values().cloned().collect())
+            .unwrap_or_default();
+        mappings.sort_by(|a, b| a.variant.cmp(&b.variant));
+        mappings
+    }
+
+    /// Keep in sync with MODEL_ALIASES in the agent service
+    fn fallback_mappings() -> Vec<ModelMapping> {
+        let mapping = |variant: &str, api_model_name: &str, supports_thinking: bool| ModelMapping {
+            variant: variant.to_string(),
+            api_model_name: api_model_name.to_string(),
+            max_output_tokens: None,
+            supports_thinking,
+            enabled: true,
+        };
+
+        vec![
+            mapping("fast", "gemini-3-flash-preview", false),
+            mapping("reasoning", "gemini-3-pro-preview", false),
+            mapping("thinking", "gemini-3-flash-preview", true),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry_with(contents: &str) -> ModelRegistry {
+        let path = std::env::temp_dir().join(format!("raro-models-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, contents).unwrap();
+        let registry = ModelRegistry::from_path(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        registry
+    }
+
+    #[test]
+    fn test_fallback_when_file_missing() {
+        let registry = ModelRegistry::from_path("/nonexistent/model_mappings.json");
+        let thinking = registry.resolve(&ModelVariant::Thinking).unwrap();
+        assert!(thinking.supports_thinking);
+        assert_eq!(registry.list().len(), 3);
+    }
+
+    #[test]
+    fn test_disabled_and_missing_variants_rejected() {
+        let registry = registry_with(r#"[
+            {"variant": "fast", "api_model_name": "gemini-fast-eu"},
+            {"variant": "reasoning", "api_model_name": "gemini-pro", "enabled": false}
+        ]"#);
+
+        let fast = registry.resolve(&ModelVariant::Fast).unwrap();
+        assert_eq!(fast.api_model_name, "gemini-fast-eu");
+        assert!(registry.resolve(&ModelVariant::Reasoning).is_err());
+        assert!(registry.resolve(&ModelVariant::Thinking).is_err());
+        // Unmapped custom ids pass through untouched
+        assert_eq!(registry.resolve(&ModelVariant::Custom("gemini-x".into())).unwrap().api_model_name, "gemini-x");
+        assert_eq!(registry.variant_for_api_model("gemini-fast-eu"), ModelVariant::Fast);
+    }
+}
@@ -1,4 +1,22 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use crate::models::{AgentInvocation, InvocationStatus, ModelVariant, RuntimeState};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use tracing_subscriber::{prelude::*, reload, EnvFilter, Registry};
+
+/// Relative change between the two halves of a trend window still reported as Stable
+const TREND_TOLERANCE: f64 = 0.05;
+pub const DEFAULT_TREND_WINDOW_HOURS: u64 = 24;
+pub const MAX_TREND_WINDOW_HOURS: u64 = 30 * 24;
+
+/// Metadata keys whose values are prompt content and get redacted in TraceEvents
+const PROMPT_FIELDS: [&str; 3] = ["prompt", "user_directive", "system_prompt"];
+
+static REDACT_PROMPTS: AtomicBool = AtomicBool::new(false);
+/// Salt for client id hashing; None leaves ids readable in logs and metrics
+static CLIENT_ID_SALT: RwLock<Option<String>> = RwLock::new(None);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metrics {
@@ -7,6 +25,8 @@ pub struct Metrics {
     pub cost_per_run: f64,
     pub total_errors: usize,
     pub average_tokens_per_invocation: usize,
+    #[serde(default)]
+    pub model_usage: HashMap<ModelVariant, ModelUsage>,
 }
 
 impl Default for Metrics {
@@ -17,10 +37,200 @@ impl Default for Metrics {
             cost_per_run: 0.0,
             total_errors: 0,
             average_tokens_per_invocation: 0,
+            model_usage: HashMap::new(),
+        }
+    }
+}
+
+/// Direction of a metric between the earlier and the later half of a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trend {
+    Improving,
+    Stable,
+    Degrading,
+}
+
+/// GET /metrics/trend response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricsTrend {
+    pub p99_latency_trend: Trend,
+    pub cache_hit_trend: Trend,
+    pub cost_trend: Trend,
+    pub error_rate_trend: Trend,
+    /// Runs inside the window the trend was computed from
+    pub runs: usize,
+}
+
+impl Metrics {
+    /// Snapshot of one run from its finished (successful or failed) invocations
+    pub fn from_run(state: &RuntimeState) -> Self {
+        let finished: Vec<&AgentInvocation> = state.invocations.iter()
+            .filter(|i| matches!(i.status, InvocationStatus::Success | InvocationStatus::Failed))
+            .collect();
+        let mut latencies: Vec<u64> = finished.iter().map(|i| i.latency_ms).collect();
+        latencies.sort_unstable();
+        let p99_latency_ms = match latencies.len() {
+            0 => 0,
+            n => latencies[((n * 99).div_ceil(100)).saturating_sub(1)],
+        };
+
+        let usage = model_usage(finished.iter().copied());
+        let (prompt, cached) = usage.values()
+            .fold((0, 0), |(p, c), u| (p + u.token_breakdown.prompt_tokens, c + u.token_breakdown.cached_tokens));
+        let total_tokens: usize = finished.iter().map(|i| i.tokens_used).sum();
+
+        Metrics {
+            p99_latency_ms,
+            cache_hit_percentage: if prompt == 0 { 0.0 } else { cached as f64 * 100.0 / prompt as f64 },
+            cost_per_run: usage.values().map(|u| u.total_cost_usd).sum(),
+            total_errors: finished.iter().filter(|i| i.status == InvocationStatus::Failed).count(),
+            average_tokens_per_invocation: total_tokens.checked_div(finished.len()).unwrap_or(0),
+            model_usage: usage,
+        }
+    }
+
+    /// Compare the earlier and the later half (by start_time) of the runs started within
+    /// `window` of now. Fewer than two such runs is Stable across the board.
+    pub fn trend(window: chrono::Duration, runs: &[RuntimeState]) -> MetricsTrend {
+        // (start, snapshot, failed share of finished invocations)
+        type Sample = (chrono::DateTime<chrono::Utc>, Metrics, f64);
+        let cutoff = chrono::Utc::now() - window;
+        let mut in_window: Vec<Sample> = runs.iter()
+            .filter_map(|run| {
+                let started = chrono::DateTime::parse_from_rfc3339(&run.start_time).ok()?.with_timezone(&chrono::Utc);
+                let metrics = Metrics::from_run(run);
+                let finished = metrics.model_usage.values().map(|u| u.invocations).sum::<usize>();
+                let error_rate = if finished == 0 { 0.0 } else { metrics.total_errors as f64 / finished as f64 };
+                (started >= cutoff).then_some((started, metrics, error_rate))
+            })
+            .collect();
+        in_window.sort_by_key(|(started, _, _)| *started);
+
+        let count = in_window.len();
+        if count < 2 {
+            return MetricsTrend {
+                p99_latency_trend: Trend::Stable,
+                cache_hit_trend: Trend::Stable,
+                cost_trend: Trend::Stable,
+                error_rate_trend: Trend::Stable,
+                runs: count,
+            };
         }
+        let (earlier, later) = in_window.split_at(count / 2);
+        let compare = |metric: fn(&Sample) -> f64, higher_is_better: bool| {
+            let mean = |half: &[Sample]| half.iter().map(metric).sum::<f64>() / half.len() as f64;
+            trend_between(mean(earlier), mean(later), higher_is_better)
+        };
+
+        MetricsTrend {
+            p99_latency_trend: compare(|(_, m, _)| m.p99_latency_ms as f64, false),
+            cache_hit_trend: compare(|(_, m, _)| m.cache_hit_percentage, true),
+            cost_trend: compare(|(_, m, _)| m.cost_per_run, false),
+            error_rate_trend: compare(|(_, _, rate)| *rate, false),
+            runs: count,
+        }
+    }
+}
+
+fn trend_between(earlier: f64, later: f64, higher_is_better: bool) -> Trend {
+    let scale = earlier.abs().max(later.abs());
+    if scale == 0.0 || (later - earlier).abs() / scale <= TREND_TOLERANCE {
+        Trend::Stable
+    } else if (later > earlier) == higher_is_better {
+        Trend::Improving
+    } else {
+        Trend::Degrading
     }
 }
 
+/// Process vitals for GET /admin/system
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemStatus {
+    pub uptime_secs: u64,
+    /// Resident set size from /proc/self/status (0 where unavailable)
+    pub memory_rss_mb: u64,
+    /// Runs not yet Completed/Failed/Aborted
+    pub active_runs: usize,
+    pub total_registered_runs: usize,
+    pub total_pattern_count: usize,
+    /// Registered background tasks that are still running
+    pub background_tasks: usize,
+    /// Receivers attached to the runtime event bus
+    pub event_subscribers: usize,
+    /// Entry count per in-memory runtime map
+    pub map_entries: std::collections::BTreeMap<&'static str, usize>,
+}
+
+/// VmRSS of this process in MiB (Linux only)
+pub fn memory_rss_mb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status.lines()
+        .find(|l| l.starts_with("VmRSS:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    Some(kb / 1024)
+}
+
+/// Aggregates for one ModelVariant across recorded invocations
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ModelUsage {
+    pub invocations: usize,
+    pub total_tokens: usize,
+    /// Estimated from ModelVariant::usd_per_million_tokens
+    pub total_cost_usd: f64,
+    pub average_latency_ms: f64,
+    #[serde(default)]
+    pub token_breakdown: TokenBreakdown,
+}
+
+/// Summed token split of the invocations that reported one
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct TokenBreakdown {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64,
+    pub thinking_tokens: u64,
+    /// cached_tokens priced at the full rate minus the cached rate of their model
+    pub cache_savings_usd: f64,
+}
+
+impl TokenBreakdown {
+    pub fn add(&mut self, invocation: &AgentInvocation) {
+        let split = &invocation.token_split;
+        let cached = split.cached_tokens.unwrap_or(0) as u64;
+        self.prompt_tokens += split.prompt_tokens.unwrap_or(0) as u64;
+        self.completion_tokens += split.completion_tokens.unwrap_or(0) as u64;
+        self.cached_tokens += cached;
+        self.thinking_tokens += split.thinking_tokens.unwrap_or(0) as u64;
+        let variant = &invocation.model_variant;
+        self.cache_savings_usd += cached as f64
+            * (variant.usd_per_million_tokens() - variant.cached_usd_per_million_tokens()) / 1_000_000.0;
+    }
+}
+
+/// Per-model breakdown of the given invocations (every status counts; replays included)
+pub fn model_usage<'a>(invocations: impl IntoIterator<Item = &'a AgentInvocation>) -> HashMap<ModelVariant, ModelUsage> {
+    let mut usage: HashMap<ModelVariant, ModelUsage> = HashMap::new();
+    let mut latency_totals: HashMap<ModelVariant, u64> = HashMap::new();
+
+    for inv in invocations {
+        let entry = usage.entry(inv.model_variant.clone()).or_default();
+        entry.invocations += 1;
+        entry.total_tokens += inv.tokens_used;
+        entry.total_cost_usd += inv.tokens_used as f64 * inv.model_variant.usd_per_million_tokens() / 1_000_000.0;
+        entry.token_breakdown.add(inv);
+        *latency_totals.entry(inv.model_variant.clone()).or_default() += inv.latency_ms;
+    }
+
+    for (variant, entry) in usage.iter_mut() {
+        entry.average_latency_ms = latency_totals[variant] as f64 / entry.invocations as f64;
+    }
+    usage
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TraceEvent {
     pub timestamp: String,
@@ -29,3 +239,225 @@ pub struct TraceEvent {
     pub agent_id: Option<String>,
     pub metadata: serde_json::Value,
 }
+
+impl TraceEvent {
+    /// Prompt fields in `metadata` are passed through `loggable_prompt`
+    pub fn new(level: &str, message: &str, agent_id: Option<&str>, mut metadata: serde_json::Value) -> Self {
+        if let Some(map) = metadata.as_object_mut() {
+            for key in PROMPT_FIELDS {
+                if let Some(serde_json::Value::String(text)) = map.get(key) {
+                    let safe = loggable_prompt(text);
+                    map.insert(key.to_string(), serde_json::Value::String(safe));
+                }
+            }
+        }
+
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            level: level.to_string(),
+            message: message.to_string(),
+            agent_id: agent_id.map(|s| s.to_string()),
+            metadata,
+        }
+    }
+}
+
+// === PROMPT REDACTION ===
+
+/// RARO_REDACT_PROMPTS=true|1 turns on prompt redaction in logs
+pub fn init_prompt_redaction_from_env() {
+    let enabled = std::env::var("RARO_REDACT_PROMPTS")
+        .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+        .unwrap_or(false);
+    set_prompt_redaction(enabled);
+}
+
+pub fn set_prompt_redaction(enabled: bool) {
+    REDACT_PROMPTS.store(enabled, Ordering::Relaxed);
+}
+
+// === CLIENT ID ANONYMIZATION ===
+
+/// RARO_ANONYMIZE_CLIENT_IDS=true|1 hashes client ids in logs and metric labels, salted with
+/// RARO_CLIENT_ID_SALT. Without a salt a random one is drawn, so hashes change on restart.
+pub fn init_client_anonymization_from_env() {
+    let enabled = std::env::var("RARO_ANONYMIZE_CLIENT_IDS")
+        .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+        .unwrap_or(false);
+    if !enabled {
+        return;
+    }
+    let salt = std::env::var("RARO_CLIENT_ID_SALT").ok().filter(|s| !s.is_empty()).unwrap_or_else(|| {
+        tracing::warn!("RARO_CLIENT_ID_SALT not set; anonymized client ids will not be stable across restarts");
+        uuid::Uuid::new_v4().to_string()
+    });
+    set_client_anonymization(Some(salt));
+}
+
+pub fn set_client_anonymization(salt: Option<String>) {
+    *CLIENT_ID_SALT.write().unwrap_or_else(std::sync::PoisonError::into_inner) = salt;
+}
+
+/// Log- and label-safe form of a client id: verbatim unless anonymization is on, then a
+/// salted hash. Storage scoping and API responses always use the real id.
+pub fn anonymize_client(client_id: &str) -> String {
+    match &*CLIENT_ID_SALT.read().unwrap_or_else(std::sync::PoisonError::into_inner) {
+        None => client_id.to_string(),
+        Some(salt) => {
+            let digest = Sha256::digest(format!("{}:{}", salt, client_id).as_bytes());
+            format!("client-{}", &format!("{:x}", digest)[..12])
+        }
+    }
+}
+
+/// Held by tests that change the process-wide settings (prompt redaction, client id
+/// anonymization, content-type overrides) so that they never run at the same time. Dropping it
+/// restores the defaults, even when the test panicked. Tests that rely on the defaults hold it too.
+#[cfg(test)]
+pub(crate) struct GlobalSettingsGuard {
+    _lock: tokio::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+static GLOBAL_SETTINGS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[cfg(test)]
+impl GlobalSettingsGuard {
+    pub(crate) async fn acquire() -> Self {
+        Self { _lock: GLOBAL_SETTINGS.lock().await }
+    }
+
+    /// For synchronous tests (panics inside a runtime)
+    pub(crate) fn acquire_blocking() -> Self {
+        Self { _lock: GLOBAL_SETTINGS.blocking_lock() }
+    }
+}
+
+#[cfg(test)]
+impl Drop for GlobalSettingsGuard {
+    fn drop(&mut self) {
+        set_prompt_redaction(false);
+        set_client_anonymization(None);
+        crate::fs_manager::set_content_type_overrides(HashMap::new());
+    }
+}
+
+// === LIVE LOG LEVEL ===
+
+/// Swaps the global EnvFilter at runtime (POST /admin/log_level)
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Install the global subscriber: RUST_LOG plus the kernel defaults, behind a reloadable filter
+pub fn init_tracing() -> LogFilterHandle {
+    let filter = EnvFilter::from_default_env()
+        .add_directive("raro_kernel=debug".parse().unwrap())
+        .add_directive("tower_http=trace".parse().unwrap());
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    handle
+}
+
+/// Log-safe form of prompt text. Verbatim unless redaction is on, then only length and hash.
+/// Never use the result for anything sent downstream.
+pub fn loggable_prompt(text: &str) -> String {
+    if !REDACT_PROMPTS.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    format!("[REDACTED len={} sha256={}]", text.len(), prompt_hash(text))
+}
+
+/// Short sha256 prefix, enough to correlate identical prompts across log lines
+pub fn prompt_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InvocationStatus;
+
+    fn invocation(model_variant: ModelVariant, tokens_used: usize, latency_ms: u64) -> AgentInvocation {
+        AgentInvocation {
+            id: uuid::Uuid::new_v4().to_string(),
+            agent_id: "a".to_string(),
+            model_variant,
+            thought_signature: None,
+            tools_used: vec![],
+            tokens_used,
+            latency_ms,
+            status: InvocationStatus::Success,
+            started_at: String::new(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            artifact_id: None,
+            error_message: None,
+            replay_of: None,
+            generation: Default::default(),
+            token_split: Default::default(),
+            escalated_from: None,
+        }
+    }
+
+    #[test]
+    fn test_model_usage_per_variant() {
+        let invocations = vec![
+            invocation(ModelVariant::Fast, 1_000, 100),
+            invocation(ModelVariant::Fast, 3_000, 300),
+            invocation(ModelVariant::Thinking, 500_000, 9_000),
+        ];
+
+        let usage = model_usage(&invocations);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[&ModelVariant::Fast], ModelUsage {
+            invocations: 2,
+            total_tokens: 4_000,
+            total_cost_usd: 4_000.0 * 0.50 / 1_000_000.0,
+            average_latency_ms: 200.0,
+            token_breakdown: TokenBreakdown::default(),
+        });
+        assert_eq!(usage[&ModelVariant::Thinking].total_cost_usd, 2.0);
+
+        let json = serde_json::to_value(&usage).unwrap();
+        assert_eq!(json["thinking"]["invocations"], 1);
+    }
+
+    #[test]
+    fn test_trend_compares_earlier_and_later_runs() {
+        let run = |hours_ago: i64, invocations: Vec<AgentInvocation>| -> RuntimeState {
+            serde_json::from_value(serde_json::json!({
+                "run_id": uuid::Uuid::new_v4().to_string(), "workflow_id": "wf", "client_id": "c",
+                "status": "completed", "active_agents": [], "completed_agents": [], "failed_agents": [],
+                "invocations": invocations, "total_tokens_used": 0, "end_time": null,
+                "start_time": (chrono::Utc::now() - chrono::Duration::hours(hours_ago)).to_rfc3339()
+            })).unwrap()
+        };
+        let cached = |latency_ms: u64| {
+            let mut inv = invocation(ModelVariant::Fast, 1_000, latency_ms);
+            inv.token_split.prompt_tokens = Some(800);
+            inv.token_split.cached_tokens = Some(400);
+            inv
+        };
+        let failed = AgentInvocation { status: InvocationStatus::Failed, ..invocation(ModelVariant::Fast, 1_000, 300) };
+
+        let runs = vec![
+            run(50, vec![invocation(ModelVariant::Fast, 1_000, 5)]), // outside the window
+            run(6, vec![invocation(ModelVariant::Fast, 1_000, 100)]),
+            run(5, vec![invocation(ModelVariant::Fast, 1_000, 100)]),
+            run(2, vec![cached(300), failed.clone()]),
+            run(1, vec![cached(300)]),
+        ];
+        let trend = Metrics::trend(chrono::Duration::hours(24), &runs);
+        assert_eq!(trend, MetricsTrend {
+            p99_latency_trend: Trend::Degrading,
+            cache_hit_trend: Trend::Improving,
+            cost_trend: Trend::Degrading,
+            error_rate_trend: Trend::Degrading,
+            runs: 4,
+        });
+
+        let lone = Metrics::trend(chrono::Duration::hours(24), &runs[3..4]);
+        assert_eq!((lone.p99_latency_trend, lone.error_rate_trend, lone.runs), (Trend::Stable, Trend::Stable, 1));
+    }
+}
@@ -0,0 +1,254 @@
+// [[RARO]]/apps/kernel-server/src/output_cache.rs
+// Purpose: Cross-run output cache. Agents with cache_policy "cross_run" have their successful
+//          responses stored under a hash of everything that shapes the output, so a later run of
+//          the same workflow with the same inputs reuses the output instead of calling the model.
+//          Unrelated to the per-run Gemini context cache (cached_content_id).
+// Architecture: Accounting Layer
+// Dependencies: DashMap, Serde, SHA-256, Chrono
+//
+// Run-scoped values (run ids, thought signatures, context cache ids) are left out of the key, so
+// identical inputs hash the same in every run; the client id is part of it, so entries are never
+// shared between tenants. Responses that delegate or write files are never cached: neither can
+// be replayed into another run. The cache file is rewritten off the async workers, at most once
+// per SAVE_DEBOUNCE.
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::models::{GenerationParams, RemoteAgentResponse};
+use crate::fs_manager;
+use crate::runtime::InvocationPayload;
+
+const DEFAULT_CACHE_PATH: &str = "/app/storage/output_cache.json";
+const DEFAULT_TTL_SECS: u64 = 7 * 86400;
+const DEFAULT_MAX_ENTRIES: usize = 1_000;
+const SAVE_DEBOUNCE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedOutput {
+    /// Entries from before tenancy was keyed have none and are never served
+    #[serde(default)]
+    pub client_id: String,
+    pub workflow_id: String,
+    pub agent_id: String,
+    pub response: RemoteAgentResponse,
+    pub stored_at: String,
+}
+
+/// Counters since boot, for GET /metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct OutputCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Tokens the cached responses originally cost, summed over hits
+    pub tokens_saved: u64,
+}
+
+/// Everything that shapes an agent's output
+#[derive(Serialize)]
+struct KeyMaterial<'a> {
+    client_id: &'a str,
+    workflow_id: &'a str,
+    /// Simulated outputs never stand in for real ones
+    simulation: bool,
+    agent_id: &'a str,
+    model: &'a str,
+    prompt: &'a str,
+    user_directive: &'a str,
+    input_data: &'a serde_json::Value,
+    thinking_level: Option<i32>,
+    tools: &'a [String],
+    file_paths: &'a [String],
+    generation: GenerationParams,
+    allow_delegation: bool,
+}
+
+pub struct OutputCache {
+    /// None keeps entries in memory only
+    path: Option<PathBuf>,
+    /// None: entries never expire
+    ttl: Option<chrono::Duration>,
+    max_entries: usize,
+    entries: Arc<DashMap<String, CachedOutput>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    tokens_saved: AtomicU64,
+    /// Set while a debounced save is scheduled
+    save_pending: Arc<AtomicBool>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl OutputCache {
+    /// RARO_OUTPUT_CACHE_PATH (default /app/storage/output_cache.json; empty = memory only),
+    /// RARO_OUTPUT_CACHE_TTL_SECS (default 7 days; 0 = never expire) and
+    /// RARO_OUTPUT_CACHE_MAX_ENTRIES (unset or 0 = 1000)
+    pub fn from_env() -> Self {
+        let path = std::env::var("RARO_OUTPUT_CACHE_PATH").unwrap_or_else(|_| DEFAULT_CACHE_PATH.to_string());
+        let ttl_secs = std::env::var("RARO_OUTPUT_CACHE_TTL_SECS").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(DEFAULT_TTL_SECS);
+        let max_entries = std::env::var("RARO_OUTPUT_CACHE_MAX_ENTRIES").ok().and_then(|v| v.parse::<usize>().ok()).filter(|v| *v > 0);
+        let mut cache = Self::new((!path.is_empty()).then(|| PathBuf::from(path)));
+        cache.ttl = (ttl_secs > 0).then(|| chrono::Duration::seconds(ttl_secs as i64));
+        cache.max_entries = max_entries.unwrap_or(DEFAULT_MAX_ENTRIES);
+        cache
+    }
+
+    /// Load the cache file at `path` when there is one
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let mut entries = DashMap::new();
+        if let Some(data) = path.as_ref().and_then(|p| fs::read_to_string(p).ok()) {
+            match serde_json::from_str::<HashMap<String, CachedOutput>>(&data) {
+                Ok(stored) => entries.extend(stored),
+                Err(e) => tracing::warn!("Output cache file not loaded: {}", e),
+            }
+        }
+        Self {
+            path,
+            ttl: Some(chrono::Duration::seconds(DEFAULT_TTL_SECS as i64)),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            entries: Arc::new(entries),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            tokens_saved: AtomicU64::new(0),
+            save_pending: Arc::new(AtomicBool::new(false)),
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Content hash of a payload. Occurrences of the run id are masked so run-specific paths
+    /// and references in the inputs don't make every run's key unique.
+    pub fn key(client_id: &str, workflow_id: &str, simulation: bool, payload: &InvocationPayload) -> String {
+        let material = KeyMaterial {
+            client_id,
+            workflow_id,
+            simulation,
+            agent_id: &payload.agent_id,
+            model: &payload.model,
+            prompt: &payload.prompt,
+            user_directive: &payload.user_directive,
+            input_data: &payload.input_data,
+            thinking_level: payload.thinking_level,
+            tools: &payload.tools,
+            file_paths: &payload.file_paths,
+            generation: payload.generation(),
+            allow_delegation: payload.allow_delegation,
+        };
+        let text = serde_json::to_string(&material).unwrap_or_default().replace(&payload.run_id, "{run_id}");
+        format!("{:x}", Sha256::digest(text.as_bytes()))
+    }
+
+    /// The stored response replayed for a new invocation: no tokens, no latency, no context cache
+    pub fn lookup(&self, key: &str, client_id: &str) -> Option<RemoteAgentResponse> {
+        let expired = |entry: &CachedOutput| self.ttl.is_some_and(|ttl| {
+            DateTime::parse_from_rfc3339(&entry.stored_at).map_or(true, |at| Utc::now() - at.with_timezone(&Utc) > ttl)
+        });
+        let Some(entry) = self.entries.get(key).map(|e| e.clone()).filter(|e| e.client_id == client_id && !expired(e)) else {
+            self.entries.remove(key);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.tokens_saved.fetch_add(entry.response.tokens_used as u64, Ordering::Relaxed);
+        let mut response = entry.response;
+        response.tokens_used = 0;
+        response.input_tokens = 0;
+        response.output_tokens = 0;
+        response.cached_tokens = 0;
+        response.thinking_tokens = 0;
+        response.latency_ms = 0.0;
+        response.cache_hit = true;
+        response.cached_content_id = None;
+        // The agent service stored the original under the earlier run's key; store it again here
+        if let Some(stored) = response.output.as_mut().and_then(|o| o.get_mut("artifact_stored")) {
+            *stored = serde_json::Value::Bool(false);
+        }
+        Some(response)
+    }
+
+    /// Keep a successful response for later runs. Returns whether it was cacheable.
+    pub fn store(&self, key: &str, client_id: &str, workflow_id: &str, agent_id: &str, response: &RemoteAgentResponse) -> bool {
+        let wrote_files = response.output.as_ref()
+            .and_then(|o| o.get("files_generated"))
+            .and_then(|f| f.as_array())
+            .is_some_and(|files| !files.is_empty());
+        if !response.success || response.output.is_none() || response.delegation.is_some() || wrote_files {
+            return false;
+        }
+
+        self.entries.insert(key.to_string(), CachedOutput {
+            client_id: client_id.to_string(),
+            workflow_id: workflow_id.to_string(),
+            agent_id: agent_id.to_string(),
+            response: response.clone(),
+            stored_at: Utc::now().to_rfc3339(),
+        });
+        if self.entries.len() > self.max_entries {
+            self.evict_oldest();
+        }
+        self.schedule_save();
+        true
+    }
+
+    /// Drop the oldest tenth of the entries (at least enough to get under max_entries), so the
+    /// scan runs once per many stores rather than on each one
+    fn evict_oldest(&self) {
+        let excess = self.entries.len().saturating_sub(self.max_entries) + self.max_entries / 10;
+        let mut by_age: Vec<(String, String)> = self.entries.iter()
+            .map(|e| (e.stored_at.clone(), e.key().clone()))
+            .collect();
+        by_age.sort_unstable();
+        for (_, key) in by_age.into_iter().take(excess.max(1)) {
+            self.entries.remove(&key);
+        }
+    }
+
+    pub fn stats(&self) -> OutputCacheStats {
+        OutputCacheStats {
+            entries: self.entries.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            tokens_saved: self.tokens_saved.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Save once SAVE_DEBOUNCE has passed, on a blocking thread; stores in the meantime ride along.
+    /// Outside a Tokio runtime the table is written at once.
+    fn schedule_save(&self) {
+        let Some(path) = self.path.clone() else { return };
+        let (entries, lock) = (self.entries.clone(), self.write_lock.clone());
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return Self::save(&entries, &path, &lock);
+        };
+        if self.save_pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let pending = self.save_pending.clone();
+        handle.spawn(async move {
+            tokio::time::sleep(SAVE_DEBOUNCE).await;
+            pending.store(false, Ordering::Release);
+            let _ = tokio::task::spawn_blocking(move || Self::save(&entries, &path, &lock)).await;
+        });
+    }
+
+    /// Write the whole table (temp file + rename, so readers never see a partial file)
+    fn save(entries: &DashMap<String, CachedOutput>, path: &Path, write_lock: &Mutex<()>) {
+        let _guard = write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let snapshot: HashMap<String, CachedOutput> = entries.iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        let result = serde_json::to_vec(&snapshot)
+            .map_err(|e| e.to_string())
+            .and_then(|data| fs_manager::write_snapshot(path, &data).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            tracing::warn!("Failed to persist output cache to {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// [[RARO]]/apps/kernel-server/src/redis_keys.rs
+// Purpose: Key listing for the boot-time restores (costs, usage, client policies, share links).
+//          Uses SCAN rather than KEYS: KEYS walks the whole keyspace in one blocking command
+//          and stalls every other Redis client while it runs.
+// Architecture: Persistence Layer
+// Dependencies: Redis
+
+/// Keys matching `pattern`, gathered with SCAN cursors (a key may appear twice; callers
+/// restore idempotently)
+pub async fn scan_keys(con: &mut redis::aio::Connection, pattern: &str) -> redis::RedisResult<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut iter = redis::AsyncCommands::scan_match::<_, String>(con, pattern).await?;
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+    Ok(keys)
+}
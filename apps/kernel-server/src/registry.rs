@@ -22,7 +22,17 @@ pub enum PatternAction {
     // Serde will automatically handle the JSON structure {"Interrupt": {"reason": "..."}}
     Interrupt { reason: String },
     RequestApproval { reason: String },
-    SpawnAgent { config: AgentNodeConfig },
+    SpawnAgent { config: Box<AgentNodeConfig> },
+    /// POSTs the triggering event as JSON to `url`
+    Webhook { url: String },
+    /// Terminal: seals the run. Non-zero exit_code also stops the process when RARO_ABORT_EXIT=true.
+    Abort {
+        reason: String,
+        #[serde(default)]
+        exit_code: u8,
+    },
+    /// Merges `value` into the run's metadata under `key`, e.g. {"safety_flags": ["fs_delete_attempted"]}
+    SetRunMetadata { key: String, value: serde_json::Value },
 }
 
 pub struct PatternRegistry {
@@ -41,6 +51,10 @@ impl PatternRegistry {
         registry
     }
 
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
     pub fn register(&self, pattern: Pattern) {
         tracing::info!("Registering Safety Pattern: [{}] {}", pattern.id, pattern.name);
         self.patterns.insert(pattern.id.clone(), pattern);
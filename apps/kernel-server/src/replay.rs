@@ -0,0 +1,161 @@
+// [[RARO]]/apps/kernel-server/src/replay.rs
+// Purpose: Time-travel state reconstruction. Every persisted state change is logged as a
+//          StateChanged event carrying a per-field delta; folding the deltas up to a given
+//          event rebuilds the RuntimeState as it was at that point.
+// Architecture: Domain Helper Layer
+// Dependencies: serde_json
+
+use serde_json::{Map, Value};
+use crate::events::{EventType, RuntimeEvent};
+use crate::models::RuntimeState;
+
+/// Top-level fields that changed between two serialized states. Arrays that only grew are
+/// recorded as `{"append": [...]}`, other changes as `{"set": value}`, dropped fields as
+/// `{"unset": true}`. None when nothing changed.
+pub fn state_delta(prev: &Map<String, Value>, next: &Map<String, Value>) -> Option<Map<String, Value>> {
+    let mut changes = Map::new();
+    for (key, value) in next {
+        match (prev.get(key), value) {
+            (Some(old), new) if old == new => {}
+            (Some(Value::Array(old)), Value::Array(new)) if new.len() > old.len() && new.starts_with(old) => {
+                changes.insert(key.clone(), serde_json::json!({ "append": new[old.len()..] }));
+            }
+            _ => {
+                changes.insert(key.clone(), serde_json::json!({ "set": value }));
+            }
+        }
+    }
+    for key in prev.keys().filter(|k| !next.contains_key(*k)) {
+        changes.insert(key.clone(), serde_json::json!({ "unset": true }));
+    }
+    (!changes.is_empty()).then_some(changes)
+}
+
+/// A run's state as of its last StateChanged event. `invocations` only ever grows (a recorded
+/// invocation is never edited), so it is tracked by length and only new entries are
+/// serialized; the remaining fields are small and diffed in full. This keeps each delta
+/// proportional to the change rather than to the run's history.
+#[derive(Debug, Default)]
+pub struct StateSnapshot {
+    fields: Map<String, Value>,
+    invocations: usize,
+}
+
+impl StateSnapshot {
+    /// StateChanged payload taking the snapshot to `state`, which it then reflects. None when
+    /// nothing changed. `state` is only borrowed mutably to serialize it without its invocations.
+    pub fn advance(&mut self, state: &mut RuntimeState) -> Option<Value> {
+        let invocations = std::mem::take(&mut state.invocations);
+        let fields = serde_json::to_value(&*state);
+        state.invocations = invocations;
+        let Ok(Value::Object(mut fields)) = fields else { return None };
+        fields.remove("invocations");
+
+        let reset = self.fields.is_empty();
+        let mut changes = state_delta(&self.fields, &fields).unwrap_or_default();
+        let invocations = &state.invocations;
+        if reset || invocations.len() < self.invocations {
+            changes.insert("invocations".to_string(), serde_json::json!({ "set": invocations }));
+        } else if invocations.len() > self.invocations {
+            changes.insert("invocations".to_string(), serde_json::json!({ "append": &invocations[self.invocations..] }));
+        }
+        self.fields = fields;
+        self.invocations = invocations.len();
+        (!changes.is_empty()).then(|| state_changed_payload(changes, reset))
+    }
+}
+
+/// Payload of a StateChanged event. `reset` marks a delta taken against no previous state
+/// (first persist, import, rehydration), so replay starts over from it.
+pub fn state_changed_payload(changes: Map<String, Value>, reset: bool) -> Value {
+    serde_json::json!({ "reset": reset, "changes": changes })
+}
+
+fn apply_delta(state: &mut Map<String, Value>, payload: &Value) {
+    if payload["reset"].as_bool().unwrap_or(false) {
+        state.clear();
+    }
+    let Some(changes) = payload["changes"].as_object() else { return };
+    for (key, op) in changes {
+        if let Some(value) = op.get("set") {
+            state.insert(key.clone(), value.clone());
+        } else if let Some(Value::Array(items)) = op.get("append") {
+            match state.get_mut(key) {
+                Some(Value::Array(existing)) => existing.extend(items.iter().cloned()),
+                _ => {
+                    state.insert(key.clone(), Value::Array(items.clone()));
+                }
+            }
+        } else if op.get("unset").is_some() {
+            state.remove(key);
+        }
+    }
+}
+
+/// Fold the StateChanged events among `events` (in log order). None if there are none.
+pub fn reconstruct<'a>(events: impl IntoIterator<Item = &'a RuntimeEvent>) -> Option<Result<RuntimeState, serde_json::Error>> {
+    let mut state: Option<Map<String, Value>> = None;
+    for event in events.into_iter().filter(|e| matches!(e.event_type, EventType::StateChanged)) {
+        apply_delta(state.get_or_insert_with(Map::new), &event.payload);
+    }
+    state.map(|s| serde_json::from_value(Value::Object(s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_appends_sets_and_unsets() {
+        let obj = |v: Value| v.as_object().unwrap().clone();
+        let before = obj(serde_json::json!({ "status": "running", "completed_agents": ["a"], "budget_warning": true }));
+        let after = obj(serde_json::json!({ "status": "completed", "completed_agents": ["a", "b"] }));
+
+        let changes = state_delta(&before, &after).unwrap();
+        assert_eq!(changes["completed_agents"], serde_json::json!({ "append": ["b"] }));
+        assert_eq!(changes["status"], serde_json::json!({ "set": "completed" }));
+        assert_eq!(changes["budget_warning"], serde_json::json!({ "unset": true }));
+        assert!(state_delta(&after, &after).is_none());
+
+        let mut state = before.clone();
+        apply_delta(&mut state, &state_changed_payload(changes, false));
+        assert_eq!(state, after);
+
+        // A reset delta discards whatever was folded before it
+        apply_delta(&mut state, &state_changed_payload(obj(serde_json::json!({ "status": { "set": "idle" } })), true));
+        assert_eq!(Value::Object(state), serde_json::json!({ "status": "idle" }));
+    }
+
+    #[test]
+    fn test_snapshot_logs_only_new_invocations() {
+        let mut state: RuntimeState = serde_json::from_value(serde_json::json!({
+            "run_id": "run-1", "workflow_id": "wf", "client_id": "public", "status": "running",
+            "active_agents": [], "completed_agents": [], "failed_agents": [], "invocations": [],
+            "total_tokens_used": 0, "start_time": "2024-06-01T12:00:00+00:00", "end_time": null
+        })).unwrap();
+        let invocation = |agent: &str| serde_json::from_value::<crate::models::AgentInvocation>(serde_json::json!({
+            "id": agent, "agent_id": agent, "model_variant": "fast", "thought_signature": null, "tools_used": [],
+            "tokens_used": 10, "latency_ms": 5, "status": "success", "timestamp": "2024-06-01T12:00:01+00:00",
+            "artifact_id": null
+        })).unwrap();
+        let mut snapshot = StateSnapshot::default();
+        let mut events = Vec::new();
+        let mut log = |payload: Option<Value>| events.extend(payload.map(|p| RuntimeEvent::new("run-1", EventType::StateChanged, None, p)));
+
+        log(snapshot.advance(&mut state));
+        assert!(snapshot.advance(&mut state).is_none());
+        state.invocations.push(invocation("a"));
+        state.total_tokens_used = 10;
+        let delta = snapshot.advance(&mut state).unwrap();
+        assert_eq!(delta["changes"]["invocations"]["append"].as_array().unwrap().len(), 1);
+        log(Some(delta));
+        state.invocations.push(invocation("b"));
+        state.completed_agents = vec!["a".to_string(), "b".to_string()];
+        let delta = snapshot.advance(&mut state).unwrap();
+        assert_eq!(delta["changes"]["invocations"]["append"][0]["agent_id"], "b");
+        log(Some(delta));
+
+        let rebuilt = reconstruct(&events).unwrap().unwrap();
+        assert_eq!(serde_json::to_value(&rebuilt).unwrap(), serde_json::to_value(&state).unwrap());
+    }
+}
@@ -3,20 +3,399 @@
 // Architecture: Domain Logic Layer
 // Dependencies: reqwest, dashmap, tokio, redis, serde_json
 
-use crate::dag::DAG;
+use crate::dag::{DAG, DAGError};
 use crate::models::*;
 use crate::events::{RuntimeEvent, EventType};
+use crate::event_stream::{self, RunEventStream};
+use crate::event_limits::{self, EventLimiter, EventLimits};
 use crate::registry::PatternRegistry;
+use crate::model_registry::ModelRegistry;
 use chrono::Utc;
 use dashmap::DashMap;
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::env;
-use std::collections::HashMap; // Added for ID remapping
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque}; // Added for ID remapping
 use redis::AsyncCommands;
 use tokio::sync::broadcast;
+use tokio::task::{AbortHandle, JoinHandle};
+use thiserror::Error;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
 use crate::fs_manager;
+use crate::template;
+use crate::observability::{self, LogFilterHandle, Metrics, MetricsTrend, ModelUsage, SystemStatus, TraceEvent};
+use crate::search::SearchIndex;
+use crate::signatures::{self, SignaturePolicy, SignatureSummary};
+use crate::usage::{UsageReport, UsageTracker};
+use crate::costs::{self, CostTracker};
+use crate::agent_stats::{AgentStats, AgentStatsStore};
+use crate::output_cache::OutputCache;
+use crate::share::{self, CreatedShare, ShareLink, ShareLinks};
+use crate::verdict;
+use crate::faults;
+use crate::egress::EgressPolicy;
+use crate::client_policy::{ClientPolicies, PolicyViolation};
+use crate::simulation;
+use crate::hooks::{HookClient, HookDecision};
+use crate::replay;
+use crate::json_patch::{self, PatchOp};
+use crate::execution_trace::{self, ExecutionTrace};
+use crate::timeline::{self, TimelinePage};
+use crate::blackboard::{Blackboard, MemoryLimitError, MemoryLimits};
+
+/// Identifier of the span an invocation was prepared under, used to parent follow-up spans.
+pub type SpanContext = tracing::span::Id;
+
+#[derive(Error, Debug)]
+pub enum RuntimeError {
+    #[error("Run not found: {0}")]
+    RunNotFound(String),
+    #[error("Checkpoint belongs to run {found}, expected {expected}")]
+    CheckpointMismatch { expected: String, found: String },
+    #[error("Persistence error: {0}")]
+    Persistence(String),
+    #[error("Invocation not found: {0}")]
+    InvocationNotFound(String),
+    #[error("Agent service error: {0}")]
+    AgentService(String),
+    #[error("Run already exists: {0}")]
+    RunAlreadyExists(String),
+    #[error("Invalid import bundle: {0}")]
+    InvalidImport(String),
+    #[error("Agent not found: {0}")]
+    AgentNotFound(String),
+    #[error("Not configured: {0}")]
+    NotConfigured(String),
+    #[error("Unknown target agents: {}", .0.join(", "))]
+    UnknownTargets(Vec<String>),
+    #[error("Pattern action failed: {0}")]
+    PatternAction(String),
+    #[error("Invalid supervisor directive: {0}")]
+    InvalidDirective(String),
+    #[error("Run is still in progress: {0}")]
+    RunInProgress(String),
+    #[error("DAG error: {0}")]
+    Dag(#[from] DAGError),
+    #[error("Run is not awaiting approval: {0}")]
+    NotAwaitingApproval(String),
+    #[error("Priority {0} is outside 0-{max}", max = MAX_RUN_PRIORITY)]
+    InvalidPriority(u8),
+    #[error("Workflow not found: {0}")]
+    WorkflowNotFound(String),
+    #[error("Invalid patch: {0}")]
+    InvalidPatch(String),
+    #[error("Invalid workflow: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    InvalidWorkflow(Vec<ValidationError>),
+    #[error("No recorded state for run {0} at that point")]
+    NoStateHistory(String),
+    #[error("State replay failed: {0}")]
+    StateReplay(String),
+    #[error("Client {0} is halted")]
+    ClientHalted(String),
+    #[error("Kernel is draining for maintenance; new runs are not accepted")]
+    Maintenance,
+    #[error("Agent '{agent_id}' uses model '{model}', which this client may not use")]
+    ModelNotAllowed { agent_id: String, model: String },
+    #[error("Daily quota of {limit} '{model}' invocations reached; it resets at 00:00 UTC")]
+    ModelQuotaExceeded { model: String, limit: u64 },
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Storage error: {0}")]
+    Storage(#[from] std::io::Error),
+    #[error("Halted: Contextual Data Drought for agent {0}")]
+    ContextDrought(String),
+    #[error("Template error: {0}")]
+    Template(String),
+    #[error("Invalid thought signature: {0}")]
+    InvalidSignature(String),
+    #[error("Webhook delivery failed: {0}")]
+    Webhook(String),
+    #[error("Invalid log filter: {0}")]
+    InvalidLogFilter(String),
+    /// A WorkflowConfig body that doesn't deserialize (strict-mode parsing reads it as raw JSON)
+    #[error("Malformed workflow config: {0}")]
+    MalformedWorkflow(String),
+    #[error("Event rate limit reached; retry in {retry_after_ms} ms")]
+    RateLimited { retry_after_ms: u64 },
+    #[error("Memory key not found: {0}")]
+    MemoryKeyNotFound(String),
+    #[error(transparent)]
+    MemoryLimit(#[from] MemoryLimitError),
+    #[error("Run is not preparing: {0}")]
+    NotPreparing(String),
+    #[error("Run has not been launched: {0}")]
+    RunNotLaunched(String),
+    #[error("Agent {0} is not pending or running; only those can be skipped")]
+    AgentNotSkippable(String),
+    #[error("Agent {agent_id} cannot be patched: it is {reason}")]
+    AgentNotPatchable { agent_id: String, reason: &'static str },
+    #[error("Run is not running: {0}")]
+    RunNotRunning(String),
+    #[error("Share link not found: {0}")]
+    ShareNotFound(String),
+    /// Tampered, expired or revoked tokens look the same to the holder
+    #[error("Share link is invalid, expired or revoked")]
+    InvalidShareToken,
+    #[error("Agent {agent_id} of run {run_id} already has an invocation in flight")]
+    AgentInFlight { run_id: String, agent_id: String },
+    /// The run has already been failed when this is returned
+    #[error("before_agent hook refused agent {agent_id}: {reason}")]
+    HookRefused { agent_id: String, reason: String },
+}
+
+/// Agent-service caches are created with a 1h TTL (see agent-service core/llm.py)
+const DEFAULT_CACHE_TTL_SECS: u64 = 3600;
+const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
+/// error_message recorded for invocations that exceed their per-agent timeout
+const AGENT_TIMEOUT_MESSAGE: &str = "agent timeout";
+/// Delivery attempts per payload in push mode before the run is failed
+const PUSH_WEBHOOK_ATTEMPTS: u32 = 3;
+/// Per-run cap on buffered agent log entries (oldest dropped first)
+const MAX_AGENT_LOG_ENTRIES: usize = 5_000;
+/// Per-run cap on frozen invocation payloads kept for replay (oldest dropped first)
+const MAX_PAYLOAD_SNAPSHOTS: usize = 500;
+/// Labelled series one /metrics family exports before the rest are summed (see cap_series)
+const MAX_METRIC_SERIES: usize = 50;
+
+/// One line of an agent's log: an IntermediateLog event or a kernel trace
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentLogEntry {
+    /// Monotonic per run; resume a dropped tail with after_seq
+    pub seq: u64,
+    pub run_id: String,
+    pub agent_id: String,
+    pub timestamp: String,
+    pub level: String,
+    /// "log" (agent-service IntermediateLog) or "trace" (kernel TraceEvent)
+    pub source: String,
+    pub message: String,
+    pub metadata: serde_json::Value,
+}
+
+/// A Gemini cached-content resource attached to a run
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheRegistration {
+    pub run_id: String,
+    pub cached_content_id: String,
+    pub registered_at: String,
+    pub ttl_seconds: Option<u64>,
+}
+
+/// Redis key holding the maintenance mode, so a drain survives a restart
+const MAINTENANCE_MODE_KEY: &str = "sys:maintenance_mode";
+
+/// Kernel-wide admission mode. Drain refuses new runs while in-flight ones finish.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceMode {
+    #[default]
+    Normal,
+    Drain,
+}
+
+/// GET /admin/maintenance: drain is complete once active_runs reaches 0
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceStatus {
+    pub mode: MaintenanceMode,
+    /// Runs that are not terminal yet (including Preparing and AwaitingApproval)
+    pub active_runs: usize,
+    pub oldest_active_run: Option<ActiveRunRef>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRunRef {
+    pub run_id: String,
+    pub status: RuntimeStatus,
+    pub start_time: String,
+}
+
+/// A live run with dispatchable agents, as ordered by dispatch_queue
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledRun {
+    pub run_id: String,
+    pub priority: u8,
+    /// priority plus the aging boost, capped at MAX_RUN_PRIORITY
+    pub effective_priority: u8,
+    pub waiting_secs: u64,
+    pub ready_agents: Vec<String>,
+}
+
+/// RARO_PRIORITY_AGING_SECS: each full interval a run has waited adds one priority level (0 = off, default 300)
+pub fn priority_aging_from_env() -> Option<std::time::Duration> {
+    let secs = std::env::var("RARO_PRIORITY_AGING_SECS").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(300);
+    (secs > 0).then(|| std::time::Duration::from_secs(secs))
+}
+
+/// RARO_PREPARE_TIMEOUT_SECS: how long a run created by the two-phase start may wait for its
+/// launch before it is cancelled (default 600, unset or 0 = default, capped at MAX_PREPARE_TIMEOUT_SECS)
+pub fn prepare_timeout_from_env() -> std::time::Duration {
+    let secs = std::env::var("RARO_PREPARE_TIMEOUT_SECS").ok().and_then(|v| v.parse::<u64>().ok()).filter(|v| *v > 0).unwrap_or(600);
+    std::time::Duration::from_secs(secs.min(MAX_PREPARE_TIMEOUT_SECS))
+}
+
+/// Longest a run may stay Preparing, whatever the client or environment asks for (one day)
+pub const MAX_PREPARE_TIMEOUT_SECS: u64 = 86_400;
+
+fn effective_priority(priority: u8, waited: std::time::Duration, aging: Option<std::time::Duration>) -> u8 {
+    let boost = aging.map(|a| waited.as_secs() / a.as_secs().max(1)).unwrap_or(0);
+    (priority as u64 + boost).min(MAX_RUN_PRIORITY as u64) as u8
+}
+
+/// Agent outputs of a run. Partial results carry the agents that have not finished yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunResult {
+    pub run_id: String,
+    pub status: RuntimeStatus,
+    /// True while the run can still produce more outputs
+    pub partial: bool,
+    /// agent_id -> stored output, for completed agents
+    pub outputs: HashMap<String, serde_json::Value>,
+    /// Agents not yet completed or failed (including running ones), sorted
+    pub pending_agents: Vec<String>,
+}
+
+/// Progress of one AgentNodeConfig::stage (agents without a stage are grouped under None)
+#[derive(Debug, Clone, Serialize)]
+pub struct StageSummary {
+    pub stage: Option<String>,
+    pub status: StageStatus,
+    pub agents: Vec<String>,
+    pub completed: usize,
+    pub failed: usize,
+    pub running: usize,
+    pub skipped: usize,
+    pub pending: usize,
+    pub invocations: usize,
+    pub tokens_used: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Outcome of record_invocations
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchRecordResult {
+    /// Invocation ids applied, in order
+    pub recorded: Vec<String>,
+    /// Id of the invocation that pushed usage over the hard budget limit
+    pub budget_exceeded_at: Option<String>,
+    /// Invocation ids not applied because the budget was exhausted before them
+    pub skipped: Vec<String>,
+}
+
+/// Body of POST /runtime/:run_id/artifacts/promote
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromoteArtifactsRequest {
+    /// Agent the files are attributed to
+    pub agent_id: String,
+    /// Session output files, promoted together or not at all
+    pub filenames: Vec<String>,
+}
+
+/// Body of POST /runtime/:run_id/share
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ShareRequest {
+    /// Promoted artifacts of the run the link may download; none by default
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+    /// Lifetime of the link (default 7 days, at most 30)
+    pub expires_in_secs: Option<u64>,
+}
+
+/// What a share link shows: status only, never prompts, outputs, signatures or events
+#[derive(Clone, Serialize)]
+pub struct SharedRunView {
+    pub run_id: String,
+    pub workflow_id: String,
+    pub workflow_name: Option<String>,
+    pub status: RuntimeStatus,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub agents: Vec<SharedAgentStatus>,
+    /// Download with GET /shared/:token/files/:filename
+    pub artifacts: Vec<fs_manager::ArtifactFile>,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedAgentStatus {
+    pub agent_id: String,
+    /// completed, failed, skipped, running or pending
+    pub status: &'static str,
+}
+
+/// Body of PATCH /runtime/:run_id/agent/:agent_id/config. Unset fields are left as they are.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PatchAgentConfig {
+    pub prompt: Option<String>,
+    pub thinking_budget: Option<u8>,
+    pub max_output_tokens: Option<usize>,
+}
+
+impl PatchAgentConfig {
+    /// Names of the fields the patch sets
+    fn fields(&self) -> Vec<&'static str> {
+        [
+            ("prompt", self.prompt.is_some()),
+            ("thinking_budget", self.thinking_budget.is_some()),
+            ("max_output_tokens", self.max_output_tokens.is_some()),
+        ].into_iter().filter(|(_, set)| *set).map(|(name, _)| name).collect()
+    }
+}
+
+/// Why prepare_invocation_payload routed an agent the way it did
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingDecision {
+    pub run_id: String,
+    pub agent_id: String,
+    /// First dependency (in depends_on order) whose edge passes a signature and that holds one
+    pub signature_source: Option<String>,
+    pub dependencies_without_signature: Vec<String>,
+    pub cache_policy: String,
+    pub cached_content_id: Option<String>,
+    pub cache_reason: String,
+    pub model_variant: String,
+    pub api_model_name: Option<String>,
+    pub thinking_level: Option<i32>,
+    /// Set when the model cannot be resolved (the invocation would fail)
+    pub model_error: Option<String>,
+}
+
+/// Self-contained bundle of everything needed to reconstruct a run elsewhere
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunExport {
+    pub exported_at: String,
+    /// Includes every AgentInvocation (with error_message)
+    pub state: RuntimeState,
+    pub workflow: WorkflowConfig,
+    pub thought_signatures: HashMap<String, String>,
+    pub dag: DagExport,
+    #[serde(default)]
+    pub agent_outputs: HashMap<String, String>,
+    #[serde(default)]
+    pub events: Vec<RuntimeEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DagExport {
+    pub nodes: Vec<String>,
+    pub edges: Vec<(String, String)>,
+}
+
+/// Result of replaying a frozen invocation payload
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayResult {
+    pub payload: InvocationPayload,
+    pub invocation: Option<AgentInvocation>,
+}
 
 /// Payload for invoking an agent with signature routing and caching
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +403,19 @@ pub struct InvocationPayload {
     pub run_id: String,
     pub agent_id: String,
     pub model: String,
+    /// Key of the model mapping `model` was resolved from; fast and thinking share an API model
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub variant: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
     pub prompt: String,
     pub user_directive: String,  // Runtime task from operator
     pub input_data: serde_json::Value,
@@ -38,16 +430,116 @@ pub struct InvocationPayload {
     pub graph_view: String,
 }
 
+impl InvocationPayload {
+    /// The variant the payload was prepared for. Payloads frozen before `variant` was recorded
+    /// fall back to a reverse lookup of the API model.
+    pub fn model_variant(&self, registry: &ModelRegistry) -> ModelVariant {
+        match self.variant.as_str() {
+            "" => registry.variant_for_api_model(&self.model),
+            key => ModelRegistry::variant_for_key(key),
+        }
+    }
+
+    /// Effective generation parameters, as recorded on the invocation
+    pub fn generation(&self) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_output_tokens: self.max_output_tokens.map(i64::from),
+            seed: self.seed,
+        }
+    }
+
+    /// Fan-in merge: file_paths are unioned (order kept, duplicates dropped) and parent
+    /// signatures combined with the default strategy. Everything else, including model,
+    /// thinking_level and tools, comes from `self`. Chainable for any number of parents.
+    #[allow(dead_code)] // Builder API for external fan-in orchestrators
+    pub fn merge_context(&self, other: &InvocationPayload) -> InvocationPayload {
+        self.merge_context_with(other, &signatures::SignatureMergeStrategy::default())
+    }
+
+    #[allow(dead_code)]
+    pub fn merge_context_with(&self, other: &InvocationPayload, strategy: &signatures::SignatureMergeStrategy) -> InvocationPayload {
+        let mut merged = self.clone();
+        for path in &other.file_paths {
+            if !merged.file_paths.contains(path) {
+                merged.file_paths.push(path.clone());
+            }
+        }
+        merged.parent_signature = strategy.merge(self.parent_signature.as_deref(), other.parent_signature.as_deref());
+        merged
+    }
+}
+
+/// POST /admin/gc body
+#[derive(Debug, Clone, Deserialize)]
+pub struct GcRequest {
+    pub max_age_minutes: u64,
+    /// Completed / Failed / Aborted; every finished status when empty
+    #[serde(default)]
+    pub status_filter: Vec<RuntimeStatus>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Counts describe what was deleted, or for a dry run what would be
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct GcResult {
+    pub deleted_run_count: usize,
+    pub freed_signatures: usize,
+    pub errors: Vec<String>,
+    pub run_ids: Vec<String>,
+    pub dry_run: bool,
+}
+
 pub struct RARORuntime {
     workflows: DashMap<String, WorkflowConfig>,
+    workflow_owners: DashMap<String, String>, // workflow_id -> client that registered it (only they may patch it)
+    workflow_swap: std::sync::Mutex<()>, // Held while changing which workflow entry runs read (register, patch, pin)
     runtime_states: DashMap<String, RuntimeState>,
     thought_signatures: DashMap<String, ThoughtSignatureStore>,
     dag_store: DashMap<String, DAG>,
-    cache_resources: DashMap<String, String>, // run_id -> cached_content_id
+    cache_resources: DashMap<String, CacheRegistration>, // run_id -> cached content
+    payload_snapshots: DashMap<String, VecDeque<(String, InvocationPayload)>>, // run_id -> (invocation_id, frozen payload), oldest first
+    event_log: DashMap<String, VecDeque<RuntimeEvent>>, // run_id -> emitted events (in order)
+    halted_clients: DashMap<String, String>, // client_id -> halted_at (blocks new runs)
+    maintenance_mode: std::sync::RwLock<MaintenanceMode>, // Drain blocks new runs for every client
+    aborted_runs: DashMap<String, String>, // run_id -> abort reason (sealed against further API calls)
+    triggered_patterns: DashMap<String, Vec<String>>, // run_id -> pattern ids that fired, in order
+    budget_thresholds_fired: DashMap<String, u8>, // run_id -> highest budget_warning_thresholds percentage already announced
+    agent_logs: DashMap<String, Vec<AgentLogEntry>>, // run_id -> log/trace entries of all agents, by seq
+    inflight_invocations: DashMap<String, (String, AbortHandle)>, // invocation_id -> (run_id, remote call task)
+    agents_in_flight: DashMap<(String, String), String>, // (run_id, agent_id) -> prepared_at of a pull-mode invocation not yet recorded
+    local_outputs: DashMap<String, serde_json::Value>, // artifact key -> agent output (only when Redis is unavailable)
+    background_tasks: DashMap<String, AbortHandle>, // name -> long-lived task spawned at boot
+    state_snapshots: DashMap<String, replay::StateSnapshot>, // run_id -> state as of its last StateChanged event
+    started_at: std::time::Instant,
+    signature_policy: SignaturePolicy,
+    pub workflow_limits: WorkflowLimits,
+    /// Reject unknown WorkflowConfig fields unless a request opts out (RARO_STRICT_CONFIG)
+    pub strict_config: bool,
+    /// Accept workflows with `faults` (RARO_FAULT_INJECTION); testing deployments only
+    pub fault_injection: bool,
+    /// Which push webhooks may be dialled (RARO_EGRESS_ALLOWLIST); hook_client holds a copy for hooks
+    pub egress: EgressPolicy,
     http_client: reqwest::Client,
+    hook_client: HookClient,
     pub redis_client: Option<redis::Client>,
     pub event_bus: broadcast::Sender<RuntimeEvent>,
+    pub log_bus: broadcast::Sender<AgentLogEntry>,
+    pub event_limiter: EventLimiter,
+    pub blackboard: Blackboard,
     pub pattern_registry: Arc<PatternRegistry>,
+    pub model_registry: Arc<ModelRegistry>,
+    pub search_index: SearchIndex,
+    pub usage: UsageTracker,
+    pub costs: CostTracker,
+    pub agent_stats: AgentStatsStore,
+    pub output_cache: OutputCache,
+    pub client_policies: ClientPolicies,
+    pub share_links: ShareLinks,
+    /// Reload handle of the global log filter; None when tracing was not set up by main
+    pub log_filter: Option<Arc<std::sync::Mutex<LogFilterHandle>>>,
 }
 
 impl RARORuntime {
@@ -73,30 +565,71 @@ impl RARORuntime {
         };
 
         // Initialize Event Bus for Cortex
-        let (tx, _) = broadcast::channel(100); // Buffer 100 events
+        let (tx, _) = broadcast::channel(event_stream::event_bus_capacity_from_env());
+
+        let usage = UsageTracker::new(redis_client.clone());
+        let costs = CostTracker::new(redis_client.clone());
+        let client_policies = ClientPolicies::new(redis_client.clone());
+        let share_links = ShareLinks::from_env(redis_client.clone());
+        let egress = EgressPolicy::from_env();
 
         RARORuntime {
             workflows: DashMap::new(),
+            workflow_owners: DashMap::new(),
+            workflow_swap: std::sync::Mutex::new(()),
             runtime_states: DashMap::new(),
             thought_signatures: DashMap::new(),
             dag_store: DashMap::new(),
             cache_resources: DashMap::new(),
+            payload_snapshots: DashMap::new(),
+            event_log: DashMap::new(),
+            halted_clients: DashMap::new(),
+            maintenance_mode: std::sync::RwLock::new(MaintenanceMode::Normal),
+            aborted_runs: DashMap::new(),
+            triggered_patterns: DashMap::new(),
+            budget_thresholds_fired: DashMap::new(),
+            agent_logs: DashMap::new(),
+            inflight_invocations: DashMap::new(),
+            agents_in_flight: DashMap::new(),
+            local_outputs: DashMap::new(),
+            background_tasks: DashMap::new(),
+            state_snapshots: DashMap::new(),
+            started_at: std::time::Instant::now(),
+            signature_policy: SignaturePolicy::from_env(),
+            workflow_limits: WorkflowLimits::from_env(),
+            strict_config: env::var("RARO_STRICT_CONFIG").map(|v| v == "true" || v == "1").unwrap_or(false),
+            fault_injection: faults::enabled_from_env(),
+            egress: egress.clone(),
             // http_client: reqwest::Client::new(),
             http_client: reqwest::Client::builder()
                 .pool_max_idle_per_host(0) // Disable pooling
                 .build()
                 .unwrap_or_else(|_| reqwest::Client::new()),
+            hook_client: HookClient::new(egress),
             redis_client,
             event_bus: tx,
+            log_bus: broadcast::channel(256).0,
+            event_limiter: EventLimiter::new(EventLimits::from_env()),
+            blackboard: Blackboard::new(MemoryLimits::from_env()),
             pattern_registry: Arc::new(PatternRegistry::new()),
+            model_registry: Arc::new(ModelRegistry::new()),
+            search_index: SearchIndex::new(),
+            usage,
+            costs,
+            // In memory; the server installs AgentStatsStore::from_env at boot
+            agent_stats: AgentStatsStore::new(None),
+            output_cache: OutputCache::from_env(),
+            client_policies,
+            share_links,
+            log_filter: None,
         }
     }
-    /// Saves the current state of a run to Redis and manages the active index
 
     // === PERSISTENCE LAYER ===
 
     /// Saves the current state of a run to Redis and manages the active index
     async fn persist_state(&self, run_id: &str) {
+        self.log_state_change(run_id);
         if let Some(client) = &self.redis_client {
             if let Some(state) = self.runtime_states.get(run_id) {
                 let state_key = format!("run:{}:state", run_id);
@@ -111,7 +644,7 @@ impl RARORuntime {
                                 
                                 // 2. Manage Index
                                 // If Completed or Failed, remove from active set. Otherwise add.
-                                if state.status == RuntimeStatus::Completed || state.status == RuntimeStatus::Failed {
+                                if state.status.is_terminal() {
                                     let _: redis::RedisResult<()> = con.srem(active_set_key, run_id).await;
                                     // Optional: Set expiry on the state key so old runs eventually clean up (e.g., 24 hours)
                                     let _: redis::RedisResult<()> = con.expire(&state_key, 86400).await;
@@ -168,9 +701,14 @@ impl RARORuntime {
                                              tokens_used: 0,
                                              latency_ms: 0,
                                              status: InvocationStatus::Failed,
+                                             started_at: Utc::now().to_rfc3339(),
                                              timestamp: Utc::now().to_rfc3339(),
                                              artifact_id: None,
                                              error_message: Some("Kernel restarted unexpectedly. Workflow terminated.".to_string()),
+                                             replay_of: None,
+                                             generation: GenerationParams::default(),
+                                             token_split: TokenSplit::default(),
+                                             escalated_from: None,
                                         });
                                     }
 
@@ -180,27 +718,576 @@ impl RARORuntime {
                             }
                         }
                     }
+                    self.load_aborted_runs(&mut con).await;
                 },
                 Err(e) => tracing::error!("Failed to connect to Redis for rehydration: {}", e),
             }
         }
+
+        self.load_maintenance_mode().await;
+
+        self.usage.load_from_redis().await;
+        self.costs.load_from_redis().await;
+        self.client_policies.load_from_redis().await;
+        self.share_links.load_from_redis().await;
+        self.rebuild_search_index().await;
+    }
+
+    /// Re-seal runs aborted before the restart, from their abort audit records. Aborted runs
+    /// leave sys:active_runs, so the state pass never sees them.
+    async fn load_aborted_runs(&self, con: &mut redis::aio::Connection) {
+        let keys = match crate::redis_keys::scan_keys(con, "run:*:audit").await {
+            Ok(keys) => keys,
+            Err(e) => {
+                tracing::error!("Failed to list abort audit records: {}", e);
+                return;
+            }
+        };
+        for key in keys {
+            let Some(run_id) = key.strip_prefix("run:").and_then(|k| k.strip_suffix(":audit")) else { continue };
+            let last: Option<String> = con.lindex(&key, -1).await.unwrap_or(None);
+            let reason = last.and_then(|record| serde_json::from_str::<serde_json::Value>(&record).ok())
+                .and_then(|record| record["reason"].as_str().map(String::from))
+                .unwrap_or_default();
+            self.aborted_runs.insert(run_id.to_string(), reason);
+        }
+        if !self.aborted_runs.is_empty() {
+            tracing::info!("Re-sealed {} aborted runs", self.aborted_runs.len());
+        }
+    }
+
+    async fn load_maintenance_mode(&self) {
+        let Some(client) = &self.redis_client else { return };
+        let Ok(mut con) = client.get_async_connection().await else { return };
+        let stored: Option<String> = con.get(MAINTENANCE_MODE_KEY).await.unwrap_or(None);
+        if stored.as_deref() == Some("drain") {
+            tracing::warn!("Resuming maintenance drain from before restart; new runs stay refused");
+            *self.maintenance_mode.write().unwrap_or_else(|e| e.into_inner()) = MaintenanceMode::Drain;
+        }
+    }
+
+    /// Re-index every known run (workflow text when registered, plus stored outputs)
+    async fn rebuild_search_index(&self) {
+        let runs: Vec<(String, String, String, Vec<String>)> = self.runtime_states.iter()
+            .map(|s| (s.run_id.clone(), s.client_id.clone(), s.workflow_id.clone(), s.completed_agents.clone()))
+            .collect();
+
+        for (run_id, client_id, workflow_id, completed) in &runs {
+            match self.workflows.get(workflow_id) {
+                Some(workflow) => self.search_index.index_workflow(run_id, client_id, &workflow),
+                None => self.search_index.register_run(run_id, client_id, workflow_id),
+            }
+
+            if let Some(client) = &self.redis_client {
+                if let Ok(mut con) = client.get_async_connection().await {
+                    for agent_id in completed {
+                        let key = format!("run:{}:agent:{}:output", run_id, agent_id);
+                        let data: Option<String> = con.get(&key).await.unwrap_or(None);
+                        if let Some(val) = data.and_then(|d| serde_json::from_str::<serde_json::Value>(&d).ok()) {
+                            self.search_index.index_output(run_id, agent_id, &Self::output_search_text(&val));
+                        }
+                    }
+                }
+            }
+        }
+
+        tracing::info!("Search index rebuilt for {} runs", runs.len());
+    }
+
+    /// The text of an agent output worth indexing: `result` when present, else the raw JSON
+    fn output_search_text(output: &serde_json::Value) -> String {
+        output.get("result")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| output.to_string())
+    }
+
+    // === CHECKPOINTING ===
+
+    /// Capture the current progress of a run (outputs, signatures, agent status) as a checkpoint
+    pub async fn save_checkpoint(&self, run_id: &str) -> Result<RunCheckpoint, RuntimeError> {
+        let (completed_agents, failed_agents) = self.runtime_states.get(run_id)
+            .map(|s| (s.completed_agents.clone(), s.failed_agents.clone()))
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+
+        let thought_signatures = self.get_all_signatures(run_id)
+            .map(|s| s.signatures)
+            .unwrap_or_default();
+
+        // Agent outputs live in Redis; without it the checkpoint only carries status + signatures
+        let mut agent_outputs = HashMap::new();
+        if let Some(client) = &self.redis_client {
+            let mut con = client.get_async_connection().await
+                .map_err(|e| RuntimeError::Persistence(e.to_string()))?;
+
+            for agent_id in &completed_agents {
+                let key = format!("run:{}:agent:{}:output", run_id, agent_id);
+                let data: Option<String> = con.get(&key).await.unwrap_or(None);
+                if let Some(json) = data {
+                    agent_outputs.insert(agent_id.clone(), json);
+                }
+            }
+        } else {
+            tracing::warn!("Redis unavailable. Checkpoint for {} will not include agent outputs.", run_id);
+        }
+
+        Ok(RunCheckpoint {
+            run_id: run_id.to_string(),
+            agent_outputs,
+            thought_signatures,
+            completed_agents,
+            failed_agents,
+        })
+    }
+
+    /// A refused checkpoint write. Redis out of memory (OOM) or unable to persist to its disk
+    /// (MISCONF) is reported like a full volume (507); anything else is a persistence failure.
+    fn checkpoint_write_error(e: redis::RedisError) -> RuntimeError {
+        match e.code() {
+            Some("OOM" | "MISCONF") => RuntimeError::Storage(fs_manager::note_storage_full(
+                std::io::Error::new(std::io::ErrorKind::StorageFull, e.to_string()),
+            )),
+            _ => RuntimeError::Persistence(e.to_string()),
+        }
+    }
+
+    /// Restore a run from a checkpoint and transition it back to Running.
+    /// Outputs are written first so a persistence failure leaves in-memory state untouched.
+    /// Only a paused (AwaitingApproval) or terminal run can be restored: a live run already has
+    /// an execution loop and in-flight agents.
+    pub async fn apply_checkpoint(&self, run_id: &str, checkpoint: RunCheckpoint) -> Result<(), RuntimeError> {
+        if checkpoint.run_id != run_id {
+            return Err(RuntimeError::CheckpointMismatch {
+                expected: run_id.to_string(),
+                found: checkpoint.run_id,
+            });
+        }
+
+        let restorable = |status: &RuntimeStatus| status.is_terminal() || *status == RuntimeStatus::AwaitingApproval;
+        match self.runtime_states.get(run_id) {
+            None => return Err(RuntimeError::RunNotFound(run_id.to_string())),
+            Some(state) if !restorable(&state.status) => return Err(RuntimeError::RunInProgress(run_id.to_string())),
+            Some(_) => {}
+        }
+
+        // 1. Restore agent outputs (Redis)
+        if !checkpoint.agent_outputs.is_empty() {
+            let client = self.redis_client.as_ref()
+                .ok_or_else(|| RuntimeError::Persistence("Redis unavailable, cannot restore agent outputs".to_string()))?;
+            let mut con = client.get_async_connection().await
+                .map_err(|e| RuntimeError::Persistence(e.to_string()))?;
+
+            for (agent_id, json) in &checkpoint.agent_outputs {
+                let key = format!("run:{}:agent:{}:output", run_id, agent_id);
+                con.set_ex::<_, _, ()>(&key, json, 3600).await
+                    .map_err(Self::checkpoint_write_error)?;
+            }
+        }
+
+        // 2. Swap in-memory state under the run's write lock
+        {
+            let mut state = self.runtime_states.get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            // Re-checked under the guard: the run may have resumed while outputs were written
+            if !restorable(&state.status) {
+                return Err(RuntimeError::RunInProgress(run_id.to_string()));
+            }
+
+            self.install_signatures(run_id, checkpoint.thought_signatures);
+
+            state.completed_agents = checkpoint.completed_agents;
+            state.failed_agents = checkpoint.failed_agents;
+            state.blocked_agents.clear();
+            state.active_agents.clear(); // Nothing survives a restart mid-flight
+            state.status = RuntimeStatus::Running;
+            state.end_time = None;
+        }
+
+        let failed: Vec<String> = self.runtime_states.get(run_id)
+            .map(|s| s.failed_agents.iter().map(|f| f.agent_id.clone()).collect())
+            .unwrap_or_default();
+        for agent_id in &failed {
+            self.block_downstream(run_id, agent_id);
+        }
+
+        self.persist_state(run_id).await;
+        tracing::info!("Run {} restored from checkpoint", run_id);
+        Ok(())
+    }
+
+    // === EXPORT / IMPORT ===
+
+    /// Bundle a run's complete state into a portable export
+    pub async fn export_run(&self, run_id: &str) -> Result<RunExport, RuntimeError> {
+        let state = self.get_state(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+
+        let workflow = self.workflows.get(&state.workflow_id)
+            .map(|w| w.clone())
+            .ok_or_else(|| RuntimeError::RunNotFound(format!("workflow {} for run {}", state.workflow_id, run_id)))?;
+
+        let dag = self.dag_store.get(run_id)
+            .map(|d| {
+                let mut nodes = d.export_nodes();
+                nodes.sort();
+                DagExport { nodes, edges: d.export_edges() }
+            })
+            .ok_or_else(|| RuntimeError::RunNotFound(format!("DAG for run {}", run_id)))?;
+
+        // Reuse the checkpoint path for signatures + Redis-backed outputs
+        let checkpoint = self.save_checkpoint(run_id).await?;
+
+        Ok(RunExport {
+            exported_at: Utc::now().to_rfc3339(),
+            state,
+            workflow,
+            thought_signatures: checkpoint.thought_signatures,
+            dag,
+            agent_outputs: checkpoint.agent_outputs,
+            events: self.get_events(run_id),
+        })
+    }
+
+    /// Reconstruct a run from an export bundle. Runs that were mid-flight come back
+    /// paused (AwaitingApproval) so an operator can resume them explicitly.
+    pub async fn import_run(&self, mut bundle: RunExport, client_id: &str) -> Result<String, RuntimeError> {
+        let run_id = bundle.state.run_id.clone();
+
+        if self.runtime_states.contains_key(&run_id) {
+            return Err(RuntimeError::RunAlreadyExists(run_id));
+        }
+        if bundle.state.workflow_id != bundle.workflow.id {
+            return Err(RuntimeError::InvalidImport("state.workflow_id does not match workflow.id".to_string()));
+        }
+        self.check_fault_injection(&bundle.workflow).map_err(|e| RuntimeError::InvalidWorkflow(vec![e]))?;
+
+        // 1. Rebuild DAG through the normal mutation API (re-validates cycles)
+        let mut dag = DAG::new();
+        for node in &bundle.dag.nodes {
+            dag.add_node(node.clone()).map_err(|e| RuntimeError::InvalidImport(e.to_string()))?;
+        }
+        for (from, to) in &bundle.dag.edges {
+            dag.add_edge(from.clone(), to.clone()).map_err(|e| RuntimeError::InvalidImport(e.to_string()))?;
+        }
+        for agent in &bundle.workflow.agents {
+            dag.annotate_edges(agent);
+        }
+
+        // 2. Restore agent outputs before touching in-memory maps
+        if !bundle.agent_outputs.is_empty() {
+            match &self.redis_client {
+                Some(client) => {
+                    let mut con = client.get_async_connection().await
+                        .map_err(|e| RuntimeError::Persistence(e.to_string()))?;
+                    for (agent_id, json) in &bundle.agent_outputs {
+                        let key = format!("run:{}:agent:{}:output", run_id, agent_id);
+                        con.set_ex::<_, _, ()>(&key, json, 3600).await
+                            .map_err(|e| RuntimeError::Persistence(e.to_string()))?;
+                    }
+                }
+                None => tracing::warn!("Redis unavailable. Imported run {} has no agent outputs.", run_id),
+            }
+        }
+
+        // 3. Normalize state for this environment
+        bundle.state.client_id = client_id.to_string();
+        bundle.state.active_agents.clear();
+        if bundle.state.status == RuntimeStatus::Running {
+            bundle.state.status = RuntimeStatus::AwaitingApproval;
+        }
+
+        self.search_index.index_workflow(&run_id, client_id, &bundle.workflow);
+        for (agent_id, json) in &bundle.agent_outputs {
+            if let Ok(val) = serde_json::from_str::<serde_json::Value>(json) {
+                self.search_index.index_output(&run_id, agent_id, &Self::output_search_text(&val));
+            }
+        }
+        let swap = self.workflow_swap.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        // A registered workflow is never replaced by an imported copy: the run gets its own
+        let workflow_id = if self.workflows.contains_key(&bundle.workflow.id) {
+            pinned_workflow_id(&bundle.workflow.id, &run_id)
+        } else {
+            self.claim_workflow_id(&bundle.workflow.id, &run_id, client_id)
+        };
+        if workflow_id != bundle.workflow.id {
+            tracing::info!("Imported run {} keeps workflow {} as {}", run_id, bundle.workflow.id, workflow_id);
+        }
+        bundle.workflow.id = workflow_id.clone();
+        bundle.state.workflow_id = workflow_id.clone();
+        self.workflows.insert(workflow_id, bundle.workflow);
+        self.dag_store.insert(run_id.clone(), dag);
+        self.install_signatures(&run_id, bundle.thought_signatures);
+        self.event_log.insert(run_id.clone(), bundle.events.into());
+        self.runtime_states.insert(run_id.clone(), bundle.state);
+        drop(swap);
+
+        self.persist_state(&run_id).await;
+        tracing::info!("Imported run {} for client {}", run_id, observability::anonymize_client(client_id));
+        Ok(run_id)
+    }
+
+    /// Emit a StateChanged event with what changed since the last one (nothing if unchanged).
+    /// Every state mutation is followed by persist_state, which makes the log replayable.
+    fn log_state_change(&self, run_id: &str) {
+        // Hold the snapshot entry while emitting so concurrent persists log in order
+        let mut snapshot = self.state_snapshots.entry(run_id.to_string()).or_default();
+        let Some(payload) = self.runtime_states.get_mut(run_id).and_then(|mut state| snapshot.advance(&mut state)) else { return };
+        // Logged only: bus subscribers (Cortex, WebSocket) have no use for state deltas
+        self.append_event(RuntimeEvent::new(run_id, EventType::StateChanged, None, payload));
+    }
+
+    /// The run's state as of event `seq` (inclusive) or time `at`, rebuilt from the event log
+    /// without touching the live state. Neither bound means the latest logged state.
+    pub fn state_at(&self, run_id: &str, seq: Option<u64>, at: Option<chrono::DateTime<Utc>>) -> Result<RuntimeState, RuntimeError> {
+        if !self.runtime_states.contains_key(run_id) {
+            return Err(RuntimeError::RunNotFound(run_id.to_string()));
+        }
+        let events = self.get_events(run_id);
+        let before_time = |e: &&RuntimeEvent| at.map(|at| {
+            chrono::DateTime::parse_from_rfc3339(&e.timestamp).map(|t| t.with_timezone(&Utc) <= at).unwrap_or(false)
+        }).unwrap_or(true);
+
+        replay::reconstruct(events.iter().filter(|e| seq.map(|n| e.seq <= n).unwrap_or(true)).filter(before_time))
+            .ok_or_else(|| RuntimeError::NoStateHistory(run_id.to_string()))?
+            .map_err(|e| RuntimeError::StateReplay(e.to_string()))
+    }
+
+    /// Consistency check: replaying the whole log reproduces the live state
+    fn replay_matches_live(&self, run_id: &str) -> bool {
+        let live = self.runtime_states.get(run_id).and_then(|s| serde_json::to_value(&*s).ok());
+        let replayed = self.state_at(run_id, None, None).ok().and_then(|s| serde_json::to_value(s).ok());
+        live.is_some() && live == replayed
     }
 
-    /// Emit an event to the event bus for Cortex pattern matching
     // === EVENT EMISSION ===
 
+    /// Keep a per-run history for export/audit; assigns the event's seq
+    fn append_event(&self, mut event: RuntimeEvent) -> RuntimeEvent {
+        let mut log = self.event_log.entry(event.run_id.clone()).or_default();
+        event.seq = log.back().map(|e| e.seq + 1).unwrap_or(1);
+        log.push_back(event.clone());
+        if event_limits::is_agent_sourced(&event.event_type) && self.event_limiter.buffer_one(&event.run_id) {
+            // Bounded per run: the oldest agent event makes room (its seq is simply gone). It
+            // sits near the front, behind only the run's early system events, so the deque
+            // shifts those few rather than the whole log.
+            if let Some(evicted) = log.iter().position(|e| event_limits::is_agent_sourced(&e.event_type)).and_then(|oldest| log.remove(oldest)) {
+                self.event_limiter.record_eviction(&event_limits::type_name(&evicted.event_type));
+            }
+        }
+        event
+    }
+
+    /// Emit an event to the event bus for Cortex pattern matching. Agent events rejected by the
+    /// run's limits since the last window are reported first, as one EventsDropped event.
     pub(crate) fn emit_event(&self, event: RuntimeEvent) {
+        // tokio's clock, so tests can step through rate windows with a paused clock
+        if let Some(dropped) = self.event_limiter.take_dropped(&event.run_id, tokio::time::Instant::now().into_std()) {
+            tracing::warn!("Run {} dropped agent events over its rate limit: {:?}", event.run_id, dropped);
+            self.publish_event(RuntimeEvent::new(&event.run_id, EventType::EventsDropped, None, serde_json::json!({ "dropped": dropped })));
+        }
+        self.publish_event(event);
+    }
+
+    /// Emit an agent-sourced event (logs, tool calls) subject to the run's event limits
+    pub(crate) fn try_emit_event(&self, event: RuntimeEvent) -> Result<(), RuntimeError> {
+        if event_limits::is_agent_sourced(&event.event_type) {
+            self.event_limiter.admit(&event.run_id, &event_limits::type_name(&event.event_type), tokio::time::Instant::now().into_std())
+                .map_err(|wait| RuntimeError::RateLimited { retry_after_ms: wait.as_millis() as u64 })?;
+        }
+        self.emit_event(event);
+        Ok(())
+    }
+
+    fn publish_event(&self, event: RuntimeEvent) {
+        let event = self.append_event(event);
+
+        if let (EventType::IntermediateLog, Some(agent_id)) = (&event.event_type, &event.agent_id) {
+            let level = event.payload["metadata"].as_str().unwrap_or("INFO").to_string();
+            let message = event.payload["message"].as_str().unwrap_or_default().to_string();
+            self.append_agent_log(&event.run_id, agent_id, &event.timestamp, level, "log", message, event.payload.clone());
+        }
+
         // Broadcast to subscribers (Observers, WebSocket, PatternEngine)
         let _ = self.event_bus.send(event);
     }
 
+    pub fn get_events(&self, run_id: &str) -> Vec<RuntimeEvent> {
+        self.event_log.get(run_id).map(|log| log.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Logged events of a run with seq > `seq`
+    pub fn events_after(&self, run_id: &str, seq: u64) -> Vec<RuntimeEvent> {
+        self.event_log.get(run_id)
+            .map(|log| log.iter().filter(|e| e.seq > seq).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Live events of one run, starting after the last one already logged
+    pub fn subscribe_run(&self, run_id: &str) -> RunEventStream {
+        let rx = self.event_bus.subscribe();
+        let last_seq = self.event_log.get(run_id).and_then(|log| log.back().map(|e| e.seq)).unwrap_or(0);
+        RunEventStream::new(run_id, rx, last_seq)
+    }
+
+    /// Receivers currently attached to the event bus (streams, Cortex, observers)
+    pub fn event_subscriber_count(&self) -> usize {
+        self.event_bus.receiver_count()
+    }
+
+    /// Prometheus text for GET /metrics: cost counters plus live gauges
+    pub fn prometheus_metrics(&self) -> String {
+        let mut out = self.costs.prometheus();
+        let cache = self.output_cache.stats();
+        for (name, kind, help, value) in [
+            ("raro_output_cache_hits_total", "counter", "Invocations served from the cross-run output cache", cache.hits),
+            ("raro_output_cache_misses_total", "counter", "Cross-run cacheable invocations that had to call the model", cache.misses),
+            ("raro_output_cache_tokens_saved_total", "counter", "Tokens the reused outputs originally cost", cache.tokens_saved),
+            ("raro_output_cache_entries", "gauge", "Outputs held in the cross-run output cache", cache.entries as u64),
+        ] {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+        }
+        out.push_str("# HELP raro_storage_full_total Storage writes that failed or were refused for lack of disk space\n");
+        out.push_str("# TYPE raro_storage_full_total counter\n");
+        out.push_str(&format!("raro_storage_full_total {}\n", fs_manager::storage_full_events()));
+        if let Some(free) = fs_manager::storage_free_bytes() {
+            out.push_str("# HELP raro_storage_free_bytes Bytes available on the storage volume\n");
+            out.push_str("# TYPE raro_storage_free_bytes gauge\n");
+            out.push_str(&format!("raro_storage_free_bytes {}\n", free));
+        }
+        out.push_str("# HELP raro_event_subscribers Receivers attached to the runtime event bus\n");
+        out.push_str("# TYPE raro_event_subscribers gauge\n");
+        out.push_str(&format!("raro_event_subscribers {}\n", self.event_subscriber_count()));
+
+        // Per run would grow a series per run ever throttled; the run's own EventsDropped
+        // events carry that detail
+        let mut dropped: BTreeMap<(String, &str), u64> = BTreeMap::new();
+        for (event_type, counts) in self.event_limiter.dropped_totals() {
+            for (reason, count) in [("rate_limited", counts.rate_limited), ("evicted", counts.evicted)] {
+                if count > 0 {
+                    *dropped.entry((event_type.clone(), reason)).or_default() += count;
+                }
+            }
+        }
+        if !dropped.is_empty() {
+            out.push_str("# HELP raro_events_dropped_total Agent events rate limited or evicted\n");
+            out.push_str("# TYPE raro_events_dropped_total counter\n");
+            for ((event_type, reason), count) in dropped {
+                out.push_str(&format!("raro_events_dropped_total{{event_type=\"{}\",reason=\"{}\"}} {}\n", event_type, reason, count));
+            }
+        }
+
+        let escalations = cap_series(self.agent_stats.escalations_by_workflow());
+        if !escalations.is_empty() {
+            out.push_str("# HELP raro_agent_escalations_total Failed agents retried on their escalation model, per workflow\n");
+            out.push_str("# TYPE raro_agent_escalations_total counter\n");
+            for (workflow_id, count) in escalations {
+                out.push_str(&format!("raro_agent_escalations_total{{workflow_id=\"{}\"}} {}\n", workflow_id, count));
+            }
+        }
+        out
+    }
+
+    // === AGENT LOGS ===
+
+    /// Keep a kernel trace in the agent's log (traces without an agent are not kept)
+    fn record_trace(&self, run_id: &str, trace: &TraceEvent) {
+        if let Some(agent_id) = &trace.agent_id {
+            self.append_agent_log(run_id, agent_id, &trace.timestamp, trace.level.clone(), "trace", trace.message.clone(), trace.metadata.clone());
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn append_agent_log(&self, run_id: &str, agent_id: &str, timestamp: &str, level: String, source: &str, message: String, metadata: serde_json::Value) {
+        let entry = {
+            let mut log = self.agent_logs.entry(run_id.to_string()).or_default();
+            let entry = AgentLogEntry {
+                seq: log.last().map(|e| e.seq + 1).unwrap_or(1),
+                run_id: run_id.to_string(),
+                agent_id: agent_id.to_string(),
+                timestamp: timestamp.to_string(),
+                level,
+                source: source.to_string(),
+                message,
+                metadata,
+            };
+            log.push(entry.clone());
+            if log.len() > MAX_AGENT_LOG_ENTRIES {
+                let excess = log.len() - MAX_AGENT_LOG_ENTRIES;
+                log.drain(..excess);
+            }
+            entry
+        };
+        let _ = self.log_bus.send(entry);
+    }
+
+    /// RunNotFound / AgentNotFound unless the agent is a node of the run's DAG
+    pub fn ensure_agent_exists(&self, run_id: &str, agent_id: &str) -> Result<(), RuntimeError> {
+        let dag = self.dag_store.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        if !dag.export_nodes().iter().any(|n| n == agent_id) {
+            return Err(RuntimeError::AgentNotFound(agent_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Runs owned by another client are reported as missing, so their ids don't leak
+    pub(crate) fn ensure_run_owner(&self, run_id: &str, client_id: &str) -> Result<(), RuntimeError> {
+        match self.runtime_states.get(run_id) {
+            Some(state) if state.client_id == client_id => Ok(()),
+            _ => Err(RuntimeError::RunNotFound(run_id.to_string())),
+        }
+    }
+
+    pub fn get_memory(&self, run_id: &str, client_id: &str, key: &str) -> Result<serde_json::Value, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        self.blackboard.get(run_id, key).ok_or_else(|| RuntimeError::MemoryKeyNotFound(key.to_string()))
+    }
+
+    /// Write a key of the run's working memory; true when the key is new
+    pub fn put_memory(&self, run_id: &str, client_id: &str, key: &str, value: serde_json::Value) -> Result<bool, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        Ok(self.blackboard.put(run_id, key, value)?)
+    }
+
+    pub fn delete_memory(&self, run_id: &str, client_id: &str, key: &str) -> Result<(), RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        self.blackboard.delete(run_id, key).map(|_| ()).ok_or_else(|| RuntimeError::MemoryKeyNotFound(key.to_string()))
+    }
+
+    /// Buffered log entries of one agent with seq > after_seq, in order
+    pub fn get_agent_logs(&self, run_id: &str, agent_id: &str, after_seq: u64) -> Result<Vec<AgentLogEntry>, RuntimeError> {
+        self.ensure_agent_exists(run_id, agent_id)?;
+        Ok(self.agent_logs.get(run_id)
+            .map(|log| log.iter().filter(|e| e.agent_id == agent_id && e.seq > after_seq).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    /// Last `limit` IntermediateLog events of one agent with seq <= up_to_seq, in order
+    pub fn recent_intermediate_logs(&self, run_id: &str, agent_id: &str, up_to_seq: u64, limit: usize) -> Result<Vec<RuntimeEvent>, RuntimeError> {
+        self.ensure_agent_exists(run_id, agent_id)?;
+        let mut events: Vec<RuntimeEvent> = self.event_log.get(run_id)
+            .map(|log| log.iter()
+                .filter(|e| e.seq <= up_to_seq && matches!(e.event_type, EventType::IntermediateLog) && e.agent_id.as_deref() == Some(agent_id))
+                .cloned()
+                .collect())
+            .unwrap_or_default();
+        events.drain(..events.len().saturating_sub(limit));
+        Ok(events)
+    }
+
+    /// The agent will produce no more logs: it finished, failed, or its run is over (or gone)
+    pub fn is_agent_finished(&self, run_id: &str, agent_id: &str) -> bool {
+        self.runtime_states.get(run_id)
+            .map(|s| s.status.is_terminal() || s.completed_agents.iter().any(|a| a == agent_id) || s.has_failed(agent_id))
+            .unwrap_or(true)
+    }
+
     // === RESOURCE CLEANUP ===
 
     /// Notify Agent Service to clean up resources (E2B Sandboxes)
     async fn trigger_remote_cleanup(&self, run_id: &str) {
         let host = env::var("AGENT_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
         let port = env::var("AGENT_PORT").unwrap_or_else(|_| "8000".to_string());
-        let scheme = if host.contains("localhost") || host == "127.0.0.1" { "http" } else { "http" };
+        let scheme = "http"; // Agent service is only reachable on the internal network
 
         let url = format!("{}://{}:{}/runtime/{}/cleanup", scheme, host, port, run_id);
 
@@ -219,12 +1306,15 @@ impl RARORuntime {
             }
         });
     }
-    /// Request approval from user, pausing execution
 
     // === APPROVAL CONTROL ===
 
+    /// Request approval from user, pausing execution
     pub async fn request_approval(&self, run_id: &str, agent_id: Option<&str>, reason: &str) {
         if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            if state.status == RuntimeStatus::Aborted {
+                return;
+            }
             state.status = RuntimeStatus::AwaitingApproval;
             // Log the intervention event
 
@@ -241,1336 +1331,6074 @@ impl RARORuntime {
         self.persist_state(run_id).await;
         tracing::info!("Run {} PAUSED for approval: {}", run_id, reason);
     }
-    /// Start a new workflow execution
-
-    // === EXECUTION LOGIC ===
 
-    pub fn start_workflow(self: &Arc<Self>, config: WorkflowConfig, client_id: &str) -> Result<String, String> {
-        // Validate workflow structure
-        let mut dag = DAG::new();
-        // Add all nodes
-
-        for agent in &config.agents {
-            dag.add_node(agent.id.clone())
-                .map_err(|e| format!("Failed to add node: {}", e))?;
+    /// Pause a running run on its owner's request, as an approval gate would. Agents already in
+    /// flight finish; nothing new starts until the run is resumed.
+    pub async fn pause_run(&self, run_id: &str, client_id: &str, reason: &str) -> Result<(), RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        if !self.runtime_states.get(run_id).is_some_and(|s| s.status == RuntimeStatus::Running) {
+            return Err(RuntimeError::RunNotRunning(run_id.to_string()));
         }
-        // Add edges based on dependencies
+        self.request_approval(run_id, None, reason).await;
+        Ok(())
+    }
 
-        for agent in &config.agents {
-            for dep in &agent.depends_on {
-                dag.add_edge(dep.clone(), agent.id.clone())
-                    .map_err(|e| format!("Failed to add edge: {}", e))?;
-            }
+    /// Flip a paused run back to Running and restart its execution loop, optionally narrowed
+    /// to the given target agents
+    pub async fn resume_run(self: &Arc<Self>, run_id: &str, target_agents: Option<Vec<String>>) -> Result<(), RuntimeError> {
+        // Fail fast if structural integrity is lost (DAG missing from memory)
+        if !self.has_dag(run_id) {
+            tracing::error!("Cannot resume run {}: DAG structure missing from memory.", run_id);
+            return Err(RuntimeError::RunNotFound(run_id.to_string()));
+        }
+        if !self.runtime_states.get(run_id).is_some_and(|s| s.status == RuntimeStatus::AwaitingApproval) {
+            tracing::warn!("Resume called on non-paused run: {}", run_id);
+            return Err(RuntimeError::NotAwaitingApproval(run_id.to_string()));
+        }
+        if let Some(targets) = target_agents {
+            self.restrict_to_targets(run_id, &targets).await?;
         }
-        // Verify topological sort (catches cycles)
-
-        let _execution_order = dag
-            .topological_sort()
-            .map_err(|e| format!("Invalid workflow: {}", e))?;
 
-        let workflow_id = config.id.clone();
-        let run_id = Uuid::new_v4().to_string();
-        // === RFS INITIALIZATION ===
-        // Create the session folder and copy files
+        self.set_run_status(run_id, RuntimeStatus::Running);
+        self.relaunch(run_id);
+        Ok(())
+    }
 
-        if let Err(e) = fs_manager::WorkspaceInitializer::init_run_session(
-            &run_id,
-            config.attached_files.clone(),
-            client_id // <--- PASS DOWN
-        ) {
-             tracing::error!("Workspace init failed: {}", e);
-             return Err(format!("FS Error: {}", e));
-        }
-        // Store workflow and DAG
+    /// Restart the execution loop of a run just flipped back to Running
+    fn relaunch(self: &Arc<Self>, run_id: &str) {
+        let runtime = self.clone();
+        let rid = run_id.to_string();
+        tokio::spawn(async move {
+            runtime.launch_execution(rid).await;
+        });
 
-        self.workflows.insert(workflow_id.clone(), config.clone());
-        self.dag_store.insert(run_id.clone(), dag);
-        // Initialize runtime state
+        // Emit event for UI to update logs
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            None,
+            serde_json::json!({ "action": "resume", "reason": "User approved execution" }),
+        ));
+        tracing::info!("Run {} resumed by user", run_id);
+    }
 
-        let state = RuntimeState {
-            run_id: run_id.clone(),
-            workflow_id: workflow_id.clone(),
-            client_id: client_id.to_string(),
-            status: RuntimeStatus::Running,
-            active_agents: Vec::new(),
-            completed_agents: Vec::new(),
-            failed_agents: Vec::new(),
-            invocations: Vec::new(),
-            total_tokens_used: 0,
-            start_time: Utc::now().to_rfc3339(),
-            end_time: None,
+    /// Record a reviewer's decision on a paused run and act on it: approval resumes the run,
+    /// rejection fails it with the comment as reason. The pause check and the transition happen
+    /// under one state lock, so of two concurrent decisions only the first takes effect.
+    pub async fn record_feedback(self: &Arc<Self>, run_id: &str, feedback: &FeedbackPayload) -> Result<ApprovalRecord, RuntimeError> {
+        // Same precondition as resume
+        if feedback.approved && !self.has_dag(run_id) {
+            return Err(RuntimeError::RunNotFound(run_id.to_string()));
+        }
+        let rejection = (!feedback.approved)
+            .then(|| feedback.comment.clone().unwrap_or_else(|| format!("Rejected by {}", feedback.reviewer_id)));
+        let (record, failure) = {
+            let mut state = self.runtime_states.get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            if state.status != RuntimeStatus::AwaitingApproval {
+                return Err(RuntimeError::NotAwaitingApproval(run_id.to_string()));
+            }
+            let record = ApprovalRecord {
+                approved: feedback.approved,
+                reviewer_id: feedback.reviewer_id.clone(),
+                comment: feedback.comment.clone(),
+                decided_at: Utc::now().to_rfc3339(),
+            };
+            state.approval_history.push(record.clone());
+            let failure = match &rejection {
+                Some(reason) => Self::fail_state(&mut state, "REVIEWER", FailureCode::ApprovalRejected, reason),
+                None => {
+                    state.status = RuntimeStatus::Running;
+                    None
+                }
+            };
+            (record, failure)
         };
+        if let Some(reason) = &rejection {
+            self.settle_failure(run_id, "REVIEWER", failure.is_some(), reason).await;
+        } else {
+            self.persist_state(run_id).await;
+        }
 
-        self.runtime_states.insert(run_id.clone(), state);
-        // Initialize thought signature store
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            None,
+            serde_json::json!({
+                "action": "feedback",
+                "approved": record.approved,
+                "reviewer_id": record.reviewer_id,
+                "comment": record.comment,
+            }),
+        ));
+        tracing::info!("Run {} {} by {}", run_id, if record.approved { "approved" } else { "rejected" }, record.reviewer_id);
+        if record.approved {
+            self.relaunch(run_id);
+        }
+        Ok(record)
+    }
 
-        self.thought_signatures.insert(
-            run_id.clone(),
-            ThoughtSignatureStore {
-                signatures: Default::default(),
-            },
-        );
-        // Spawn the execution task (Fire and Forget)
+    // === ABORT (CRITICAL SAFETY) ===
 
-        let runtime_clone = self.clone();
-        let run_id_clone = run_id.clone();
+    /// Remember that a Cortex pattern fired for a run (forms the audit trail for aborts)
+    pub fn note_pattern_trigger(&self, run_id: &str, pattern_id: &str) {
+        self.triggered_patterns.entry(run_id.to_string()).or_default().push(pattern_id.to_string());
+    }
 
-        tokio::spawn(async move {
-            runtime_clone.persist_state(&run_id_clone).await;
-            runtime_clone.execute_dynamic_dag(run_id_clone).await;
-        });
+    /// Merge `value` into the run's metadata under `key`: objects merge key by key, arrays gain
+    /// the items they don't have yet, anything else is overwritten. Refused when the result
+    /// would exceed MAX_METADATA_BYTES, so repeated triggers can't grow it without bound.
+    pub async fn upsert_run_metadata(&self, run_id: &str, key: &str, value: serde_json::Value) -> Result<(), RuntimeError> {
+        {
+            let mut state = self.runtime_states.get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            let mut metadata = state.metadata.clone();
+            match metadata.get_mut(key) {
+                Some(existing) => merge_metadata_value(existing, value),
+                None => {
+                    metadata.insert(key.to_string(), value);
+                }
+            }
+            let size = serde_json::to_vec(&metadata).map(|v| v.len()).unwrap_or(usize::MAX);
+            if size > crate::models::MAX_METADATA_BYTES {
+                return Err(RuntimeError::InvalidConfig(format!(
+                    "Run metadata would be {} bytes serialized (max {})", size, crate::models::MAX_METADATA_BYTES
+                )));
+            }
+            state.metadata = metadata;
+        }
+        self.persist_state(run_id).await;
+        Ok(())
+    }
 
-        Ok(run_id)
+    pub fn is_run_aborted(&self, run_id: &str) -> bool {
+        self.aborted_runs.contains_key(run_id)
     }
 
-    /// DYNAMIC EXECUTION LOOP
-    pub(crate) async fn execute_dynamic_dag(&self, run_id: String) {
-        tracing::info!("Starting DYNAMIC DAG execution for run_id: {}", run_id);
-        // We use a simplified loop: Re-calculate topology, filter for uncompleted, take the next one.
-        // In a real high-throughput system, we'd use a proper ready-queue, but re-calculating topology
-        // on a small graph (<100 nodes) is negligible and safer for consistency.
+    pub fn abort_reason(&self, run_id: &str) -> Option<String> {
+        self.aborted_runs.get(run_id).map(|r| r.clone())
+    }
 
-        loop {
-            // 1. Check if Run is still valid/active or paused
-            if let Some(state) = self.runtime_states.get(&run_id) {
-                // Check for pause state
-                if state.status == RuntimeStatus::AwaitingApproval {
-                    tracing::info!("Execution loop for {} suspending (Awaiting Approval).", run_id);
-                    break;
-                }
-                // Check for terminal states
-                if state.status == RuntimeStatus::Failed || state.status == RuntimeStatus::Completed {
-                    break;
-                }
-            } else {
-                // Run vanished
-                break;
+    /// Hard-stop a run: status Aborted, sealed against further API calls, audit record written.
+    /// Unlike fail_run this cannot be resumed or overwritten.
+    pub async fn abort_run(&self, run_id: &str, reason: &str) -> Result<(), RuntimeError> {
+        // State lock is released before touching any other map or doing I/O
+        {
+            let mut state = self.runtime_states.get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            if state.status == RuntimeStatus::Aborted {
+                return Ok(());
             }
-            // 2. Determine Next Agent(s)
-            // We get the full topological sort, then find the first node that is NOT complete and NOT running.
-
-            // 2. Determine Next Agent(s) - FIX: INTEGRATED DEPENDENCY CHECK
-            // We search for the first node that is pending AND has all dependencies satisfied.
-            // This prevents head-of-line blocking where a waiting node prevents independent siblings from running.
-            let next_agent_opt = {
-                // Scope for locks
-                let dag = match self.dag_store.get(&run_id) {
-                    Some(d) => d,
-                    None => {
-                        tracing::error!("DAG not found for run {}", run_id);
-                        break;
-                    }
-                };
+            state.status = RuntimeStatus::Aborted;
+            state.end_time = Some(Utc::now().to_rfc3339());
+            state.active_agents.clear();
+        }
+        self.aborted_runs.insert(run_id.to_string(), reason.to_string());
+        self.cancel_inflight_invocations(run_id);
+        self.release_run_in_flight(run_id);
+
+        let patterns = self.triggered_patterns.get(run_id).map(|p| p.clone()).unwrap_or_default();
+        let audit = serde_json::json!({
+            "run_id": run_id,
+            "reason": reason,
+            "triggered_patterns": patterns,
+            "aborted_at": Utc::now().to_rfc3339(),
+        });
 
-                let execution_order = match dag.topological_sort() {
-                    Ok(order) => order,
-                    Err(e) => {
-                        self.fail_run(&run_id, "SYSTEM", &format!("DAG cycle detected during execution: {}", e)).await;
-                        break;
-                    }
-                };
+        tracing::error!(target: "raro::audit", severity = "CRITICAL", run_id = %run_id, patterns = ?patterns, "Run aborted: {}", reason);
 
-                let state = self.runtime_states.get(&run_id).unwrap();  // Safe due to check above
-                // Find first node that isn't done and isn't currently running
+        if let Some(client) = &self.redis_client {
+            match client.get_async_connection().await {
+                Ok(mut con) => {
+                    let _: redis::RedisResult<()> = con.rpush(format!("run:{}:audit", run_id), audit.to_string()).await;
+                }
+                Err(e) => tracing::error!("Failed to write abort audit record for {}: {}", run_id, e),
+            }
+        }
 
-                // FIX: Filter for nodes that are NOT complete, NOT running, AND have dependencies met
-                execution_order.into_iter().find(|agent_id| {
-                    let is_pending = !state.completed_agents.contains(agent_id) &&
-                                     !state.failed_agents.contains(agent_id) &&
-                                     !state.active_agents.contains(agent_id);
-                    
-                    if !is_pending { return false; }
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            None,
+            serde_json::json!({ "action": "abort", "reason": reason, "triggered_patterns": patterns }),
+        ));
 
-                    // Immediate dependency check
-                    let deps = dag.get_dependencies(agent_id);
-                    deps.iter().all(|d| state.completed_agents.contains(d))
-                })
-            };
-            // 3. If no next agent, check if we are done
+        self.persist_state(run_id).await;
+        self.trigger_remote_cleanup(run_id).await;
+        Ok(())
+    }
 
-            // 3. Process Selection or Wait
-            let agent_id = match next_agent_opt {
-                Some(id) => id,
-                None => {
-                    // No agents ready. Are any running?
-                    let running_count = self.runtime_states.get(&run_id)
-                        .map(|s| s.active_agents.len())
-                        .unwrap_or(0);
+    /// Per-agent stats of a workflow the client registered (or, after a restart, runs). Another
+    /// client's workflow is reported as missing, like its runs.
+    pub fn workflow_stats(&self, workflow_id: &str, client_id: &str) -> Result<BTreeMap<String, AgentStats>, RuntimeError> {
+        let owned = match self.workflow_owners.get(workflow_id) {
+            Some(owner) => *owner == client_id,
+            None => self.runtime_states.iter().any(|s| s.workflow_id == workflow_id && s.client_id == client_id),
+        };
+        owned.then(|| self.agent_stats.for_workflow(workflow_id))
+            .flatten()
+            .ok_or_else(|| RuntimeError::WorkflowNotFound(workflow_id.to_string()))
+    }
 
-                    if running_count > 0 {
-                        // Wait for them to finish (simple polling for this implementation)
-                        // Wait for active agents to finish
-                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                        continue;
-                    } else {
-                        // Nothing running, nothing ready -> We are done!
-                        if let Some(mut state) = self.runtime_states.get_mut(&run_id) {
-                            state.status = RuntimeStatus::Completed;
-                            state.end_time = Some(Utc::now().to_rfc3339());
-                        }
-                        self.persist_state(&run_id).await;
-                        // Trigger Cleanup
-                        self.trigger_remote_cleanup(&run_id).await;
+    /// Edit a stored workflow with an RFC 6902 patch. The result must pass the same checks as
+    /// a start; runs that used the prior version are pinned to a copy of it first.
+    pub async fn patch_workflow(&self, workflow_id: &str, client_id: &str, ops: &[PatchOp]) -> Result<WorkflowConfig, RuntimeError> {
+        let swap = self.workflow_swap.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        // Another client's workflow is reported as missing, like its runs
+        let owned = self.workflow_owners.get(workflow_id).is_some_and(|o| *o == client_id)
+            && self.runtime_states.iter().all(|s| s.workflow_id != workflow_id || s.client_id == client_id);
+        let current = self.workflows.get(workflow_id)
+            .filter(|_| owned)
+            .map(|w| w.clone())
+            .ok_or_else(|| RuntimeError::WorkflowNotFound(workflow_id.to_string()))?;
+
+        let doc = serde_json::to_value(&current).map_err(|e| RuntimeError::InvalidPatch(e.to_string()))?;
+        let patched = json_patch::apply(&doc, ops).map_err(|e| RuntimeError::InvalidPatch(e.to_string()))?;
+        let mut config: WorkflowConfig = serde_json::from_value(patched)
+            .map_err(|e| RuntimeError::InvalidPatch(format!("patched workflow does not parse: {}", e)))?;
+        if config.id != workflow_id {
+            return Err(RuntimeError::InvalidPatch("the workflow id cannot be patched".to_string()));
+        }
 
-                        tracing::info!("Workflow run {} completed successfully (Dynamic)", run_id);
-                        break;
-                    }
-                }
-            };
+        config.apply_agent_defaults();
+        config.check_limits(&self.workflow_limits)
+            .and_then(|_| config.validate())
+            .and_then(|_| self.check_fault_injection(&config).map_err(|e| vec![e]))
+            .map_err(RuntimeError::InvalidWorkflow)?;
 
-            // 5. Execute Agent
-            tracing::info!("Processing agent: {}", agent_id);
+        // Runs look their workflow up by id: move existing ones onto a frozen copy
+        let pinned_runs: Vec<String> = self.runtime_states.iter()
+            .filter(|s| s.workflow_id == workflow_id)
+            .map(|s| s.run_id.clone())
+            .collect();
+        for run_id in &pinned_runs {
+            self.pin_run_workflow_locked(run_id)?;
+        }
+        self.workflows.insert(workflow_id.to_string(), config.clone());
+        drop(swap);
 
-            // === PUPPET MODE: PAUSE FOR INSPECTION ===
-            let puppet_mode = env::var("PUPPET_MODE")
-                .unwrap_or_else(|_| "false".to_string())
-                .to_lowercase() == "true";
+        for run_id in &pinned_runs {
+            self.persist_state(run_id).await;
+        }
+        tracing::info!("Patched workflow {} ({} operations, {} runs pinned to the prior version)", workflow_id, ops.len(), pinned_runs.len());
+        Ok(config)
+    }
 
-            if puppet_mode {
-                tracing::info!("🎭 PUPPET MODE: Pausing execution for agent {}", agent_id);
+    /// Workflows with `faults` are only accepted when RARO_FAULT_INJECTION is on
+    fn check_fault_injection(&self, config: &WorkflowConfig) -> Result<(), ValidationError> {
+        if !config.faults.is_empty() && !self.fault_injection {
+            return Err(ValidationError::FaultInjectionDisabled);
+        }
+        Ok(())
+    }
 
-                if let Some(client) = &self.redis_client {
-                    // Gather context for puppet UI
-                    let workflow_id = self.runtime_states.get(&run_id)
-                        .map(|s| s.workflow_id.clone())
-                        .unwrap_or_default();
+    /// Move a run onto its own "{id}@{run_id}" copy of its workflow, so changes made for the
+    /// run don't reach other runs of the same id. Returns the copy's id; persisting is up to the
+    /// caller.
+    pub(crate) fn pin_run_workflow(&self, run_id: &str) -> Result<String, RuntimeError> {
+        let _swap = self.workflow_swap.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.pin_run_workflow_locked(run_id)
+    }
 
-                    let dependencies = self.dag_store.get(&run_id)
-                        .map(|dag| dag.get_dependencies(&agent_id))
-                        .unwrap_or_default();
+    /// pin_run_workflow for callers already holding workflow_swap. A run that is already
+    /// pinned keeps its copy.
+    fn pin_run_workflow_locked(&self, run_id: &str) -> Result<String, RuntimeError> {
+        let mut state = self.runtime_states.get_mut(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let pinned_id = pinned_workflow_id(&state.workflow_id, run_id);
+        if pinned_id == state.workflow_id {
+            return Ok(pinned_id);
+        }
+        let mut pinned = self.workflows.get(&state.workflow_id)
+            .map(|w| w.clone())
+            .ok_or_else(|| RuntimeError::WorkflowNotFound(state.workflow_id.clone()))?;
+        pinned.id = pinned_id.clone();
+        self.workflows.insert(pinned_id.clone(), pinned);
+        state.workflow_id = pinned_id.clone();
+        Ok(pinned_id)
+    }
 
-                    let agent_context = serde_json::json!({
-                        "run_id": run_id,
-                        "agent_id": agent_id,
-                        "workflow_id": workflow_id,
-                        "dependencies": dependencies,
-                        "timestamp": Utc::now().to_rfc3339(),
-                        "status": "awaiting_decision"
-                    });
+    /// Id to store a new run's workflow under: its own, unless another client holds that id,
+    /// in which case the run gets a private copy. Call with workflow_swap held.
+    fn claim_workflow_id(&self, workflow_id: &str, run_id: &str, client_id: &str) -> String {
+        let owner = self.workflow_owners.entry(workflow_id.to_string())
+            .or_insert_with(|| {
+                // After a restart the owner map is empty; runs still referencing the id hold it
+                self.runtime_states.iter()
+                    .find(|s| s.workflow_id == workflow_id)
+                    .map_or_else(|| client_id.to_string(), |s| s.client_id.clone())
+            })
+            .clone();
+        if owner == client_id { workflow_id.to_string() } else { pinned_workflow_id(workflow_id, run_id) }
+    }
 
-                    // Publish to puppet channel
-                    match client.get_async_connection().await {
-                        Ok(mut con) => {
-                            let _: Result<(), _> = con.publish(
-                                "puppet:channel",
-                                agent_context.to_string()
-                            ).await;
+    // === EXECUTION LOGIC ===
 
-                            // BLOCKING WAIT for puppet response (60s timeout)
-                            let response_key = format!("puppet:response:{}:{}", run_id, agent_id);
-                            let timeout = std::time::Duration::from_secs(60);
+    /// Start a new workflow execution
+    /// Single-shot start: create the run and launch it at once (both phases of create_run /
+    /// launch_run, with no window in which the run sits in Preparing)
+    pub fn start_workflow(self: &Arc<Self>, config: WorkflowConfig, client_id: &str) -> Result<String, RuntimeError> {
+        let run_id = self.register_run(config, client_id, None)?;
+        self.spawn_execution(&run_id);
+        Ok(run_id)
+    }
 
-                            let wait_result = tokio::time::timeout(timeout, async {
-                                loop {
-                                    match con.get::<_, Option<String>>(&response_key).await {
-                                        Ok(Some(response)) => {
-                                            // Delete response key
-                                            let _: Result<(), _> = con.del(&response_key).await;
-                                            return Some(response);
-                                        }
-                                        Ok(None) => {
-                                            // Not ready yet, wait a bit
-                                            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-                                        }
-                                        Err(e) => {
-                                            tracing::error!("Redis error waiting for puppet: {}", e);
-                                            return None;
-                                        }
-                                    }
-                                }
-                            }).await;
+    /// Phase one of the two-phase start: validate and store the run in Preparing. Files and
+    /// caches can then be attached against its id; nothing is dispatched until launch_run.
+    /// The run is cancelled if it is not launched within `prepare_timeout` (capped at
+    /// MAX_PREPARE_TIMEOUT_SECS).
+    pub async fn create_run(&self, config: WorkflowConfig, client_id: &str, prepare_timeout: std::time::Duration) -> Result<String, RuntimeError> {
+        let capped = prepare_timeout.min(std::time::Duration::from_secs(MAX_PREPARE_TIMEOUT_SECS));
+        let deadline = chrono::Duration::from_std(capped).ok()
+            .and_then(|timeout| Utc::now().checked_add_signed(timeout))
+            .ok_or_else(|| RuntimeError::InvalidConfig("prepare_timeout_secs is out of range".to_string()))?;
+        let run_id = self.register_run(config, client_id, Some(deadline))?;
+        self.persist_state(&run_id).await;
+        tracing::info!("Run {} created; preparing until {}", run_id, deadline.to_rfc3339());
+        Ok(run_id)
+    }
 
-                            match wait_result {
-                                Ok(Some(response)) => {
-                                    tracing::info!("🎭 Puppet response for {}: {}", agent_id, response);
-                                    // Continue execution (mock may be set in Redis by puppet service)
-                                }
-                                Ok(None) | Err(_) => {
-                                    tracing::warn!("🎭 Puppet timeout for {} - proceeding with normal execution", agent_id);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to connect to Redis for puppet mode: {}", e);
-                        }
-                    }
-                } else {
-                    tracing::warn!("🎭 PUPPET_MODE enabled but Redis unavailable");
-                }
+    /// Phase two: make a Preparing run runnable and start executing it
+    pub async fn launch_run(self: &Arc<Self>, run_id: &str, client_id: &str) -> Result<(), RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        // Launching starts execution, so a drain holds prepared runs back too (they time out)
+        if self.maintenance_mode() == MaintenanceMode::Drain {
+            return Err(RuntimeError::Maintenance);
+        }
+        {
+            let mut state = self.runtime_states.get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            if state.status != RuntimeStatus::Preparing {
+                return Err(RuntimeError::NotPreparing(run_id.to_string()));
             }
-            // ==========================================
+            // The run starts now; time spent preparing is not execution time
+            state.status = RuntimeStatus::Running;
+            state.start_time = Utc::now().to_rfc3339();
+            state.prepare_deadline = None;
+        }
+        tracing::info!("Run {} launched", run_id);
+        self.spawn_execution(run_id);
+        Ok(())
+    }
 
-            self.update_agent_status(&run_id, &agent_id, InvocationStatus::Running).await;
-            // Emit AgentStarted event
+    /// Path of one of the run's session outputs, for its owner only. Resolved on the blocking pool.
+    pub async fn session_output_path(&self, run_id: &str, client_id: &str, filename: &str) -> Result<std::path::PathBuf, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        let (run_id, filename) = (run_id.to_string(), filename.to_string());
+        tokio::task::spawn_blocking(move || fs_manager::WorkspaceInitializer::session_output_file(&run_id, &filename))
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(RuntimeError::from)
+    }
+
+    pub async fn list_session_outputs(&self, run_id: &str, client_id: &str) -> Result<Vec<String>, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        let run_id = run_id.to_string();
+        tokio::task::spawn_blocking(move || fs_manager::WorkspaceInitializer::list_session_outputs(&run_id))
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(RuntimeError::from)
+    }
 
+    /// Promote several of an agent's session outputs as one unit (see promote_artifacts_batch)
+    pub async fn promote_artifacts(&self, run_id: &str, client_id: &str, request: PromoteArtifactsRequest) -> Result<Vec<fs_manager::ArtifactFile>, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        let workflow_id = self.runtime_states.get(run_id).map(|s| s.workflow_id.clone()).unwrap_or_default();
+        let user_directive = self.workflows.get(&workflow_id)
+            .and_then(|w| w.agents.iter().find(|a| a.id == request.agent_id).map(|a| a.user_directive.clone()))
+            .ok_or_else(|| RuntimeError::AgentNotFound(request.agent_id.clone()))?;
+
+        let promoted = fs_manager::WorkspaceInitializer::promote_artifacts_batch(
+            client_id, run_id, &workflow_id, &request.agent_id, &request.filenames, &user_directive
+        ).await?;
+        for file in &promoted {
             self.emit_event(RuntimeEvent::new(
-                &run_id,
-                EventType::AgentStarted,
-                Some(agent_id.clone()),
-                serde_json::json!({"agent_id": agent_id}),
+                run_id,
+                EventType::ArtifactPromoted,
+                Some(request.agent_id.clone()),
+                serde_json::json!({ "filename": file.filename, "digest": file.digest, "rule": "batch" }),
             ));
+        }
+        Ok(promoted)
+    }
 
-            let payload_res = self.prepare_invocation_payload(&run_id, &agent_id).await;
-            if let Err(e) = payload_res {
-                // Check if this is a soft failure (context drought) vs hard failure
-                let is_context_drought = e.contains("Context Drought") || e.contains("Contextual Data Drought");
+    // === SHARE LINKS ===
 
-                if is_context_drought {
-                    // SOFT FAILURE: Already called request_approval in prepare_invocation_payload
-                    // Just record it and break to allow user intervention
-                    tracing::info!("Context Drought detected for {}. Execution paused for approval.", agent_id);
+    /// Public read-only link to a finished run. Shared artifacts must already be promoted.
+    pub async fn create_share(&self, run_id: &str, client_id: &str, request: ShareRequest) -> Result<CreatedShare, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        if !self.runtime_states.get(run_id).is_some_and(|s| s.status.is_terminal()) {
+            return Err(RuntimeError::RunInProgress(run_id.to_string()));
+        }
+        let ttl_secs = request.expires_in_secs.unwrap_or(share::DEFAULT_SHARE_TTL_SECS);
+        if ttl_secs == 0 || ttl_secs > share::MAX_SHARE_TTL_SECS {
+            return Err(RuntimeError::InvalidConfig(format!("expires_in_secs must be 1-{}", share::MAX_SHARE_TTL_SECS)));
+        }
+        if !request.artifacts.is_empty() {
+            let promoted = fs_manager::WorkspaceInitializer::get_artifact_metadata(client_id, run_id).await
+                .map(|m| m.artifacts)
+                .unwrap_or_default();
+            if let Some(missing) = request.artifacts.iter().find(|name| !promoted.iter().any(|a| &a.filename == *name)) {
+                return Err(RuntimeError::Storage(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not a promoted artifact of run {}", missing, run_id))));
+            }
+        }
 
-                    {
-                        if let Some(mut state) = self.runtime_states.get_mut(&run_id) {
-                            state.active_agents.retain(|a| a != &agent_id);
+        let mut artifacts = request.artifacts;
+        artifacts.sort();
+        artifacts.dedup();
+        let created = self.share_links.create(run_id, artifacts, chrono::Duration::seconds(ttl_secs as i64));
+        tracing::info!("Share {} created for run {} until {}", created.link.share_id, run_id, created.link.expires_at);
+        Ok(created)
+    }
 
-                            // Record the paused invocation
-                            state.invocations.push(AgentInvocation {
-                                id: Uuid::new_v4().to_string(),
-                                agent_id: agent_id.clone(),
-                                model_variant: ModelVariant::Fast,
-                                thought_signature: None,
-                                tools_used: vec![],
-                                tokens_used: 0,
-                                latency_ms: 0,
-                                status: InvocationStatus::Paused,
-                                timestamp: Utc::now().to_rfc3339(),
-                                artifact_id: None,
-                                error_message: Some(e.clone()),
-                            });
-                        }
-                        self.persist_state(&run_id).await;
-                    }
+    pub fn list_shares(&self, run_id: &str, client_id: &str) -> Result<Vec<ShareLink>, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        Ok(self.share_links.for_run(run_id))
+    }
 
-                    // Emit event for UI notification (triggers approval card)
-                    self.emit_event(RuntimeEvent::new(
-                        &run_id,
-                        EventType::SystemIntervention,
-                        Some(agent_id.clone()),
-                        serde_json::json!({
-                            "reason": e,
-                            "agent_id": agent_id,
-                            "type": "context_drought",
-                            "recovery_hint": "Upstream agents provided insufficient data. Review and choose to Skip or Stop"
-                        }),
-                    ));
+    pub fn revoke_share(&self, run_id: &str, client_id: &str, share_id: &str) -> Result<(), RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        if !self.share_links.revoke(run_id, share_id) {
+            return Err(RuntimeError::ShareNotFound(share_id.to_string()));
+        }
+        tracing::info!("Share {} of run {} revoked", share_id, run_id);
+        Ok(())
+    }
 
-                    // Break to suspend execution
-                    break;
-                } else {
-                    // HARD FAILURE: Preparation error (missing workflow, etc.)
-                    self.fail_run(&run_id, &agent_id, &e).await;
-                    self.trigger_remote_cleanup(&run_id).await;
-                    continue;
+    /// GET /shared/:token
+    pub async fn shared_run(&self, token: &str) -> Result<SharedRunView, RuntimeError> {
+        let claims = self.share_links.verify(token).ok_or(RuntimeError::InvalidShareToken)?;
+        // A run deleted since the link was made is as gone as a revoked link
+        let state = self.get_state(&claims.run_id).ok_or(RuntimeError::InvalidShareToken)?;
+
+        let mut nodes = self.dag_store.get(&claims.run_id).map(|dag| dag.export_nodes()).unwrap_or_default();
+        nodes.sort();
+        let agents = nodes.into_iter().map(|agent_id| {
+            let status = if state.completed_agents.contains(&agent_id) { "completed" }
+                else if state.has_failed(&agent_id) { "failed" }
+                else if state.skipped_agents.contains(&agent_id) { "skipped" }
+                else if state.active_agents.contains(&agent_id) { "running" }
+                else { "pending" };
+            SharedAgentStatus { agent_id, status }
+        }).collect();
+
+        let artifacts = if claims.artifacts.is_empty() {
+            Vec::new()
+        } else {
+            fs_manager::WorkspaceInitializer::get_artifact_metadata(&state.client_id, &claims.run_id).await
+                .map(|m| m.artifacts.into_iter().filter(|a| claims.artifacts.contains(&a.filename)).collect())
+                .unwrap_or_default()
+        };
+
+        Ok(SharedRunView {
+            workflow_name: self.workflows.get(&state.workflow_id).map(|w| w.name.clone()),
+            run_id: state.run_id,
+            workflow_id: state.workflow_id,
+            status: state.status,
+            start_time: state.start_time,
+            end_time: state.end_time,
+            agents,
+            artifacts,
+            expires_at: chrono::DateTime::from_timestamp(claims.expires_at, 0).unwrap_or_default().to_rfc3339(),
+        })
+    }
+
+    /// Path of an artifact a share link grants, for download
+    pub fn shared_artifact_path(&self, token: &str, filename: &str) -> Result<std::path::PathBuf, RuntimeError> {
+        let claims = self.share_links.verify(token).ok_or(RuntimeError::InvalidShareToken)?;
+        let client_id = self.runtime_states.get(&claims.run_id).map(|s| s.client_id.clone()).ok_or(RuntimeError::InvalidShareToken)?;
+        // Only names the owner listed; they were checked against the promoted artifacts
+        if !claims.artifacts.iter().any(|a| a == filename) {
+            return Err(RuntimeError::Storage(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not shared", filename))));
+        }
+        Ok(fs_manager::WorkspaceInitializer::client_artifacts_dir(&client_id).join(&claims.run_id).join(filename))
+    }
+
+    /// Copy library files into a Preparing run's session inputs. Every file must resolve.
+    /// Copies run on the blocking pool. Files copied before a failure stay recorded, so the
+    /// run's attached_files always matches its input folder.
+    pub async fn attach_run_files(&self, run_id: &str, client_id: &str, files: &[String]) -> Result<Vec<String>, RuntimeError> {
+        self.ensure_preparing(run_id, client_id)?;
+        let (owned_run_id, owned_client_id, owned_files) = (run_id.to_string(), client_id.to_string(), files.to_vec());
+        let (attached, failure) = tokio::task::spawn_blocking(move || {
+            let mut attached = Vec::new();
+            for filename in &owned_files {
+                match fs_manager::WorkspaceInitializer::attach_to_session(&owned_run_id, &owned_client_id, filename) {
+                    Ok(name) => attached.push(name),
+                    Err(e) => return (attached, Some(e)),
                 }
             }
-            let payload = payload_res.unwrap();
+            (attached, None)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            for filename in &attached {
+                if !state.attached_files.contains(filename) {
+                    state.attached_files.push(filename.clone());
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(e.into()),
+            None => Ok(attached),
+        }
+    }
 
-            let response = self.invoke_remote_agent(&payload).await;
+    /// Register the cached content a Preparing run's invocations should use
+    pub fn register_run_cache(&self, run_id: &str, client_id: &str, cached_content_id: String) -> Result<(), RuntimeError> {
+        self.ensure_preparing(run_id, client_id)?;
+        self.set_cache_resource(run_id, cached_content_id)
+    }
 
-            // 6. Handle Result & Potential Delegation
-            match response {
-                Ok(res) => {
-                    // === POST-FLIGHT: PROTOCOL VALIDATOR & SEMANTIC CHECK ===
-                    let text = res.output.as_ref()
-                        .and_then(|o| o.get("result"))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("");
-
-                    // 1. Analyze Signals
-                    let is_semantic_null = text.contains("[STATUS: NULL]");
-                    let is_bypassed = text.contains("[BYPASS:");
-
-                    // 2. Check Tool Evidence (Robust Check)
-                    // We check the explicit list from Python first, fall back to text scan
-                    let used_python = res.executed_tools.contains(&"execute_python".to_string())
-                                      || text.contains("execute_python")
-                                      || text.contains("Tool 'execute_python' Result");
-
-                    let used_search = res.executed_tools.contains(&"web_search".to_string())
-                                      || text.contains("web_search")
-                                      || text.contains("Tool 'web_search' Result");
-
-                    // [[FIX 1 START: Detect File Writing]]
-                    let used_write = res.executed_tools.contains(&"write_file".to_string())
-                                      || text.contains("write_file")
-                                      || text.contains("Tool 'write_file' Result");
-                    // [[FIX 1 END]]
-
-                    // 3. Protocol Validation Logic
-                    let mut protocol_violation = None;
-
-                    if !is_bypassed {
-                        if agent_id.starts_with("research_") && !used_search {
-                            protocol_violation = Some("Protocol Violation: 'research_' agent did not use web_search (Hallucination Risk).");
-                        } else if (agent_id.starts_with("analyze_") || agent_id.starts_with("coder_")) {
-                            // [[FIX 1 START: Allow write_file as a valid output action for analysts]]
-                            if !used_python && !used_write {
-                                protocol_violation = Some("Protocol Violation: 'analyze_'/'coder_' agent did not use execute_python or write_file (Integrity Risk).");
-                            }
-                            // [[FIX 1 END]]
-                        }
-                    }
+    fn ensure_preparing(&self, run_id: &str, client_id: &str) -> Result<(), RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        match self.runtime_states.get(run_id) {
+            Some(state) if state.status == RuntimeStatus::Preparing => Ok(()),
+            _ => Err(RuntimeError::NotPreparing(run_id.to_string())),
+        }
+    }
 
-                    // 4. Circuit Breaker Decision & Output Handling
-                    if res.success && !is_semantic_null && protocol_violation.is_none() {
-                        // [[CONTEXT CACHING PERSISTENCE]]
-                        // If the agent returned a cache ID (either created new or refreshed),
-                        // update the runtime store so subsequent agents reuse it.
-                        if let Some(cache_id) = &res.cached_content_id {
-                            if let Err(e) = self.set_cache_resource(&run_id, cache_id.clone()) {
-                                tracing::warn!("Failed to update cache resource for run {}: {}", run_id, e);
-                            } else {
-                                tracing::debug!("Updated Context Cache for run {}: {}", run_id, cache_id);
-                            }
-                        }
+    /// Cancel Preparing runs whose deadline has passed. Returns their ids.
+    pub async fn cancel_expired_preparations(&self, now: chrono::DateTime<Utc>) -> Vec<String> {
+        let expired: Vec<String> = self.runtime_states.iter()
+            .filter(|s| s.status == RuntimeStatus::Preparing)
+            .filter(|s| s.prepare_deadline.as_deref()
+                .and_then(|d| chrono::DateTime::parse_from_rfc3339(d).ok())
+                .map(|d| d.with_timezone(&Utc) <= now)
+                .unwrap_or(false))
+            .map(|s| s.run_id.clone())
+            .collect();
+        for run_id in &expired {
+            tracing::warn!("Run {} was not launched before its prepare deadline; cancelling", run_id);
+            self.fail_run(run_id, "SYSTEM", FailureCode::PrepareTimeout, "Not launched before the prepare timeout").await;
+        }
+        expired
+    }
 
-                        // A. Check for Delegation (Dynamic Splicing)
-                        if let Some(delegation) = res.delegation {
-                            tracing::info!("Agent {} requested delegation: {}", agent_id, delegation.reason);
+    fn spawn_execution(self: &Arc<Self>, run_id: &str) {
+        let runtime_clone = self.clone();
+        let run_id_clone = run_id.to_string();
 
-                            // === FIX: SEPARATE LOCK SCOPES TO PREVENT DEADLOCK ===
-                            // === FIX: DEADLOCK PREVENTION ===
-                            // Separate lock scopes.
-                            // 1. Acquire Read Lock to check permission, then DROP IT immediately.
-                            let can_delegate = if let Some(state) = self.runtime_states.get(&run_id) {
-                                let wf_id = state.workflow_id.clone();
-                                if let Some(workflow) = self.workflows.get(&wf_id) {
-                                    workflow.agents.iter()
-                                        .find(|a| a.id == agent_id)
-                                        .map(|a| a.allow_delegation)
-                                        .unwrap_or(false)
-                                } else { false }
-                            } else { false };
+        tokio::spawn(async move {
+            runtime_clone.persist_state(&run_id_clone).await;
+            runtime_clone.launch_execution(run_id_clone).await;
+        });
+    }
 
-                            // 2. Acquire Write Lock inside handle_delegation (if permitted)
-                            if !can_delegate {
-                                tracing::warn!("Agent {} attempted delegation without permission. Ignoring.", agent_id);
-                            } else {
-                                match self.handle_delegation(&run_id, &agent_id, delegation).await {
-                                    Ok(_) => {
-                                        tracing::info!("Delegation processed. Graph updated.");
-                                    }
-                                    Err(e) => {
-                                        tracing::error!("Delegation failed: {}", e);
-                                        self.fail_run(&run_id, &agent_id, &format!("Delegation error: {}", e)).await;
-                                        continue;
-                                    }
-                                }
-                            }
-                        }
+    /// Validate a workflow and store a new run of it: Running, or Preparing until
+    /// `prepare_deadline` when given. Does not start execution.
+    fn register_run(&self, mut config: WorkflowConfig, client_id: &str, prepare_deadline: Option<chrono::DateTime<Utc>>) -> Result<String, RuntimeError> {
+        if self.maintenance_mode() == MaintenanceMode::Drain {
+            return Err(RuntimeError::Maintenance);
+        }
+        if self.is_client_halted(client_id) {
+            return Err(RuntimeError::ClientHalted(client_id.to_string()));
+        }
 
-                        // B. Standard Completion Logic
-                        if let Some(sig) = res.thought_signature {
-                            let _ = self.set_thought_signature(&run_id, &agent_id, sig);
-                        }
+        config.apply_agent_defaults();
+        config.check_limits(&self.workflow_limits).map_err(RuntimeError::InvalidWorkflow)?;
 
-                        let artifact_id = if let Some(output_data) = &res.output {
-                            // File Promotion Logic
-                            if let Some(files_array) = output_data.get("files_generated").and_then(|v| v.as_array()) {
-                                let (workflow_id, client_id) = self.runtime_states.get(&run_id)
-                                    .map(|s| (s.workflow_id.clone(), s.client_id.clone()))
-                                    .unwrap_or_default();
+        // Upgrade models for agents whose declared capabilities exceed their variant
+        let unsatisfiable: Vec<ValidationError> = config.agents.iter_mut()
+            .filter_map(|agent| agent.assign_capable_model().err())
+            .collect();
+        if !unsatisfiable.is_empty() {
+            return Err(RuntimeError::InvalidWorkflow(unsatisfiable));
+        }
 
-                                let user_directive = {
-                                    if let Some(workflow) = self.workflows.get(&workflow_id) {
-                                        workflow.agents.iter()
-                                            .find(|a| a.id == agent_id)
-                                            .map(|a| a.user_directive.clone())
-                                            .unwrap_or_default()
-                                    } else { String::new() }
-                                };
+        config.validate().map_err(RuntimeError::InvalidWorkflow)?;
+        let mut problems: Vec<ValidationError> = [config.validate_environment(), config.validate_metadata(), config.validate_promotion_rules()]
+            .into_iter()
+            .filter_map(Result::err)
+            .collect();
+        problems.extend(self.check_fault_injection(&config).err());
+        if !problems.is_empty() {
+            return Err(RuntimeError::InvalidWorkflow(problems));
+        }
+        if let Some(criteria) = &config.success_criteria {
+            let problems: Vec<ValidationError> = verdict::condition_problems(criteria).into_iter()
+                .map(ValidationError::InvalidSuccessCriteria)
+                .collect();
+            if !problems.is_empty() {
+                return Err(RuntimeError::InvalidWorkflow(problems));
+            }
+        }
 
-                                for file_val in files_array {
-                                    if let Some(filename) = file_val.as_str() {
-                                        let cid = client_id.clone();
-                                        let rid = run_id.clone();
-                                        let wid = workflow_id.clone();
-                                        let aid = agent_id.clone();
-                                        let fname = filename.to_string();
-                                        let directive = user_directive.clone();
-
-                                        tokio::spawn(async move {
-                                            match fs_manager::WorkspaceInitializer::promote_artifact_to_storage(
-                                                &cid, &rid, &wid, &aid, &fname, &directive
-                                            ).await {
-                                                Ok(_) => tracing::info!("✓ Artifact '{}' promoted to persistent storage", fname),
-                                                Err(e) => tracing::error!("✗ Failed to promote artifact '{}': {}", fname, e),
-                                            }
-                                        });
-                                    }
-                                }
-                            }
+        // Validate workflow structure (bulk load; one cycle check for the whole graph)
+        let mut dag = DAG::from_config(&config)
+            .map_err(|e| RuntimeError::InvalidWorkflow(vec![e.into_validation_error(&config)]))?;
+        // Every agent must run on a live model mapping
+
+        let unavailable: Vec<ValidationError> = config.agents.iter()
+            .filter_map(|agent| self.model_registry.resolve(&agent.model).err().map(|reason| ValidationError::ModelUnavailable {
+                agent_id: agent.id.clone(),
+                model: agent.model.as_str().to_string(),
+                reason,
+            }))
+            .collect();
+        if !unavailable.is_empty() {
+            return Err(RuntimeError::InvalidWorkflow(unavailable));
+        }
+        // Checked after defaults and capability upgrades, so neither can bring in a forbidden model
+        let profile = self.client_policies.profile(client_id);
+        let forbidden: Vec<ValidationError> = config.agents.iter()
+            .flat_map(|a| std::iter::once(&a.model).chain(a.retry.as_ref().and_then(|r| r.escalation.as_ref())).map(move |model| (a, model)))
+            .filter(|(_, model)| !profile.allows(&self.canonical_model(model)))
+            .map(|(a, model)| ValidationError::ModelNotAllowed { agent_id: a.id.clone(), model: model.as_str().to_string() })
+            .collect();
+        if !forbidden.is_empty() {
+            return Err(RuntimeError::InvalidWorkflow(forbidden));
+        }
+        // Partial execution: keep only the targets and what they depend on
 
-                            let agent_stored_flag = output_data.get("artifact_stored")
-                                .and_then(|v| v.as_bool())
-                                .unwrap_or(false);
+        let skipped_agents = match &config.target_agents {
+            Some(targets) => prune_to_targets(&mut dag, targets, &[])?,
+            None => Vec::new(),
+        };
 
-                            if agent_stored_flag {
-                                Some(format!("run:{}:agent:{}:output", run_id, agent_id))
-                            } else {
-                                self.store_artifact(&run_id, &agent_id, output_data).await
-                            }
-                        } else { None };
+        let run_id = Uuid::new_v4().to_string();
+        // === RFS INITIALIZATION ===
+        // Create the session folder and copy files
 
-                        let invocation = AgentInvocation {
-                            id: Uuid::new_v4().to_string(),
-                            agent_id: agent_id.clone(),
-                            model_variant: match payload.model.as_str() {
-                                "gemini-3-flash-preview" => ModelVariant::Fast,
-                                _ => ModelVariant::Reasoning,
-                            },
-                            thought_signature: None,
-                            tools_used: payload.tools.clone(),
-                            tokens_used: res.tokens_used,
-                            latency_ms: res.latency_ms as u64,
-                            status: InvocationStatus::Success,
-                            timestamp: Utc::now().to_rfc3339(),
-                            artifact_id,
-                            error_message: None,
-                        };
+        if let Err(e) = fs_manager::WorkspaceInitializer::init_run_session(
+            &run_id,
+            config.attached_files.clone(),
+            client_id // <--- PASS DOWN
+        ) {
+             tracing::error!("Workspace init failed: {}", e);
+             return Err(RuntimeError::Storage(e));
+        }
+        // Store workflow and DAG
 
-                        let _ = self.record_invocation(&run_id, invocation).await;
+        self.search_index.index_workflow(&run_id, client_id, &config);
+        let swap = self.workflow_swap.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        config.id = self.claim_workflow_id(&config.id, &run_id, client_id);
+        let workflow_id = config.id.clone();
+        self.workflows.insert(workflow_id.clone(), config.clone());
+        self.dag_store.insert(run_id.clone(), dag);
+        // Initialize runtime state
 
-                        self.emit_event(RuntimeEvent::new(
-                            &run_id,
-                            EventType::AgentCompleted,
-                            Some(agent_id.clone()),
-                            serde_json::json!({"agent_id": agent_id, "tokens_used": res.tokens_used}),
-                        ));
+        let mut state = Self::initial_state(&run_id, client_id, &config, skipped_agents);
+        if let Some(deadline) = prepare_deadline {
+            state.status = RuntimeStatus::Preparing;
+            state.prepare_deadline = Some(deadline.to_rfc3339());
+        }
 
-                    } else {
-                        // === CIRCUIT BREAKER: PAUSE LOGIC (SOFT VS HARD FAILURES) ===
-                        let (pause_reason, is_fatal) = if is_semantic_null {
-                            (format!("Agent '{}' reported a Semantic Null (found no data). Verification required.", agent_id), false)
-                        } else if let Some(violation) = protocol_violation {
-                            (violation.to_string(), false)
-                        } else {
-                            (res.error.unwrap_or_else(|| "Unknown Execution Error".to_string()), true)
-                        };
+        self.runtime_states.insert(run_id.clone(), state);
+        drop(swap);
+        if !config.simulation {
+            self.usage.record_run_started(client_id);
+        }
+        // Initialize thought signature store
 
-                        tracing::warn!("Circuit Breaker Triggered for {}: {}", agent_id, pause_reason);
+        self.thought_signatures.insert(run_id.clone(), ThoughtSignatureStore::default());
 
-                        if is_fatal {
-                            // HARD FAILURE: Crash the run (Network errors, Panics)
-                            self.emit_event(RuntimeEvent::new(
-                                &run_id,
-                                EventType::AgentFailed,
-                                Some(agent_id.clone()),
-                                serde_json::json!({"error": pause_reason}),
-                            ));
-                            self.fail_run(&run_id, &agent_id, &pause_reason).await;
-                            self.trigger_remote_cleanup(&run_id).await;
-                            break;
-                        } else {
-                            // SOFT FAILURE: Pause for Human Intervention (Nulls, Policy Violations)
-                            // We do NOT mark as failed. We simply remove from active and request approval.
-                            // This allows the user to Resume (Retry) or Edit the state.
+        Ok(run_id)
+    }
 
-                            {
-                                if let Some(mut state) = self.runtime_states.get_mut(&run_id) {
-                                    state.active_agents.retain(|a| a != &agent_id);
+    pub fn execution_mode(&self, run_id: &str) -> ExecutionMode {
+        self.runtime_states.get(run_id)
+            .and_then(|s| self.workflows.get(&s.workflow_id).map(|w| w.execution_mode.clone()))
+            .unwrap_or_default()
+    }
 
-                                    // Record the "Paused" invocation so it appears in logs
-                                    state.invocations.push(AgentInvocation {
-                                        id: Uuid::new_v4().to_string(),
-                                        agent_id: agent_id.clone(),
-                                        model_variant: ModelVariant::Fast,
-                                        thought_signature: None,
-                                        tools_used: payload.tools.clone(),
-                                        tokens_used: res.tokens_used,
-                                        latency_ms: res.latency_ms as u64,
-                                        status: InvocationStatus::Paused,
-                                        timestamp: Utc::now().to_rfc3339(),
-                                        artifact_id: None,
-                                        error_message: Some(pause_reason.clone()),
-                                    });
-                                }
-                                self.persist_state(&run_id).await;
-                            }
+    /// Start or resume a run according to its workflow's execution_mode
+    pub async fn launch_execution(&self, run_id: String) {
+        match self.execution_mode(&run_id) {
+            ExecutionMode::Managed => self.execute_dynamic_dag(run_id).await,
+            ExecutionMode::Pull => tracing::info!("Run {} is in pull mode; waiting for the orchestrator", run_id),
+            ExecutionMode::Push { .. } => self.push_ready_agents(&run_id).await,
+        }
+    }
+
+    /// Push mode: claim every ready agent (marks it active) and POST its payload to the
+    /// orchestrator webhook. Called at launch and whenever the orchestrator reports results.
+    /// No-op for other modes.
+    pub async fn push_ready_agents(&self, run_id: &str) {
+        let ExecutionMode::Push { webhook_url } = self.execution_mode(run_id) else { return };
+        if self.runtime_states.get(run_id).map(|s| s.status != RuntimeStatus::Running).unwrap_or(true) {
+            return;
+        }
+        if let Err(e) = self.egress.check(&webhook_url).await {
+            tracing::warn!("Refusing orchestrator webhook of run {}: {}", run_id, e);
+            self.fail_run(run_id, "SYSTEM", FailureCode::ServiceUnavailable, &format!("Orchestrator webhook refused: {}", e)).await;
+            return;
+        }
+        let ready = match self.ready_agents(run_id) {
+            Ok(ready) => ready,
+            Err(e) => {
+                tracing::error!("Cannot schedule push run {}: {}", run_id, e);
+                return;
+            }
+        };
+
+        // Claim under the state lock so concurrent reports don't push the same agent twice
+        let (claimed, still_active) = {
+            let Some(mut state) = self.runtime_states.get_mut(run_id) else { return };
+            let claimed: Vec<String> = ready.into_iter().filter(|a| !state.active_agents.contains(a)).collect();
+            let now = Utc::now().to_rfc3339();
+            for agent_id in &claimed {
+                state.mark_active(agent_id, &now);
+            }
+            (claimed, state.active_agents.len())
+        };
+        if claimed.is_empty() {
+            if still_active == 0 {
+                self.complete_run(run_id).await;
+            }
+            return;
+        }
+        self.persist_state(run_id).await;
+
+        for (index, agent_id) in claimed.iter().enumerate() {
+            self.emit_event(RuntimeEvent::new(run_id, EventType::AgentStarted, Some(agent_id.clone()), serde_json::json!({"agent_id": agent_id})));
+
+            let payload = match self.prepare_invocation_payload(run_id, agent_id)
+                .instrument(Self::prepare_span(run_id, agent_id))
+                .await
+            {
+                Ok(payload) => payload,
+                Err(RuntimeError::ContextDrought(_)) => {
+                    // Already paused for approval by prepare_invocation_payload. Release this
+                    // agent and the rest of the batch: none of them was pushed, and the resume
+                    // after approval claims them again.
+                    let unpushed = &claimed[index..];
+                    if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+                        state.active_agents.retain(|a| !unpushed.contains(a));
+                        state.active_since.retain(|a, _| !unpushed.contains(a));
+                    }
+                    self.persist_state(run_id).await;
+                    return;
+                }
+                // Already failed by prepare_invocation_payload
+                Err(RuntimeError::HookRefused { .. }) => return,
+                Err(e) => {
+                    let failure = self.fail_run(run_id, agent_id, FailureCode::PreparationError, &e.to_string()).await;
+                    self.emit_agent_failed(run_id, failure);
+                    return;
+                }
+            };
+
+            if let Err(e) = self.push_payload(&webhook_url, &payload).await {
+                let reason = format!("Orchestrator webhook failed after {} attempts: {}", PUSH_WEBHOOK_ATTEMPTS, e);
+                let failure = self.fail_run(run_id, agent_id, FailureCode::ServiceUnavailable, &reason).await;
+                self.emit_agent_failed(run_id, failure);
+                return;
+            }
+            tracing::info!("Pushed payload for agent {} of run {} to orchestrator", agent_id, run_id);
+        }
+    }
+
+    /// POST with exponential backoff (250ms, 500ms, ...) between attempts
+    async fn push_payload(&self, url: &str, payload: &InvocationPayload) -> Result<(), RuntimeError> {
+        let mut last_error = String::new();
+        for attempt in 1..=PUSH_WEBHOOK_ATTEMPTS {
+            match self.http_client.post(url).json(payload).send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => last_error = format!("HTTP {}", response.status()),
+                Err(e) => last_error = e.to_string(),
+            }
+            tracing::warn!("Push of agent {} to {} failed (attempt {}/{}): {}", payload.agent_id, url, attempt, PUSH_WEBHOOK_ATTEMPTS, last_error);
+            if attempt < PUSH_WEBHOOK_ATTEMPTS {
+                tokio::time::sleep(std::time::Duration::from_millis(250 << (attempt - 1))).await;
+            }
+        }
+        Err(RuntimeError::Webhook(last_error))
+    }
+
+    fn workflow_hooks(&self, run_id: &str) -> WorkflowHooks {
+        self.runtime_states.get(run_id)
+            .and_then(|s| self.workflows.get(&s.workflow_id).map(|w| w.hooks.clone()))
+            .unwrap_or_default()
+    }
+
+    /// Run the workflow's before_agent hook, if any, on a prepared payload. A veto or an
+    /// unusable hook response fails the agent rather than dispatching it unchecked.
+    async fn apply_before_agent_hook(&self, run_id: &str, payload: InvocationPayload) -> Result<InvocationPayload, (FailureCode, String)> {
+        let Some(url) = self.workflow_hooks(run_id).before_agent else { return Ok(payload) };
+        match self.hook_client.before_agent(&url, payload).await {
+            Ok(HookDecision::Proceed(payload)) => Ok(*payload),
+            Ok(HookDecision::Veto(reason)) => Err((FailureCode::HookVetoed, reason)),
+            Err(e) => Err((FailureCode::PreparationError, e)),
+        }
+    }
+
+    /// POST the run summary to on_complete or on_fail
+    fn notify_run_hook(&self, run_id: &str, hook: &'static str) {
+        let hooks = self.workflow_hooks(run_id);
+        let url = if hook == "on_complete" { hooks.on_complete } else { hooks.on_fail };
+        if let (Some(url), Some(state)) = (url, self.runtime_states.get(run_id)) {
+            self.hook_client.notify(hook, &url, &RunSummary::from(&*state));
+        }
+    }
+
+    /// Flag Running runs whose active agents have all been running longer than `threshold`
+    /// with no invocation recorded in that window (e.g. a crashed orchestrator), and emit a
+    /// SystemIntervention for each newly stalled run. Returns the newly flagged run ids.
+    pub async fn detect_stalled_runs(&self, threshold: chrono::Duration) -> Vec<String> {
+        let now = Utc::now();
+        let cutoff = now - threshold;
+        let older_than_cutoff = |ts: &str| chrono::DateTime::parse_from_rfc3339(ts)
+            .map(|t| t.with_timezone(&Utc) < cutoff)
+            .unwrap_or(false);
+
+        let mut flagged = Vec::new();
+        for mut state in self.runtime_states.iter_mut() {
+            if state.status != RuntimeStatus::Running || state.stalled || state.active_agents.is_empty() {
+                continue;
+            }
+            // Agents without a timestamp (rehydrated runs) count as recent
+            let all_stale = state.active_agents.iter()
+                .all(|a| state.active_since.get(a).map(|ts| older_than_cutoff(ts)).unwrap_or(false));
+            let quiet = state.invocations.iter().all(|i| older_than_cutoff(&i.timestamp));
+            if all_stale && quiet {
+                state.stalled = true;
+                let mut agents = state.active_agents.clone();
+                agents.sort();
+                flagged.push((state.run_id.clone(), agents));
+            }
+        }
+
+        for (run_id, agents) in &flagged {
+            tracing::warn!("Run {} stalled: {:?} silent for over {}s", run_id, agents, threshold.num_seconds());
+            self.persist_state(run_id).await;
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::SystemIntervention,
+                None,
+                serde_json::json!({
+                    "type": "stall",
+                    "reason": format!("No invocation recorded for {}s while agents were running", threshold.num_seconds()),
+                    "stalled_agents": agents,
+                    "threshold_secs": threshold.num_seconds(),
+                }),
+            ));
+        }
+        flagged.into_iter().map(|(run_id, _)| run_id).collect()
+    }
+
+    /// Nothing running, nothing ready: mark the run Completed and clean up
+    async fn complete_run(&self, run_id: &str) {
+        let run_verdict = self.run_verdict(run_id).await;
+        let mut skipped = Vec::new();
+        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            state.status = RuntimeStatus::Completed;
+            state.end_time = Some(Utc::now().to_rfc3339());
+            state.verdict = run_verdict.clone();
+            skipped = state.invocations.iter()
+                .filter(|i| i.status == InvocationStatus::Skipped)
+                .map(|i| i.agent_id.clone())
+                .collect();
+        }
+        self.cancel_inflight_invocations(run_id);
+        self.persist_state(run_id).await;
+        if let Some(run_verdict) = run_verdict {
+            if let RunVerdict::Failed { reasons } = &run_verdict {
+                tracing::warn!("Run {} completed but failed its success criteria: {:?}", run_id, reasons);
+            }
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::VerdictRecorded,
+                None,
+                serde_json::to_value(&run_verdict).unwrap_or_default(),
+            ));
+        }
+        // Completed, but not everything ran
+        if !skipped.is_empty() {
+            tracing::warn!("Run {} completed with skipped agents: {:?}", run_id, skipped);
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::SystemIntervention,
+                None,
+                serde_json::json!({ "action": "completed_with_skips", "skipped_agents": skipped }),
+            ));
+        }
+        if cfg!(debug_assertions) && !self.replay_matches_live(run_id) {
+            tracing::error!("Invariant violated: replaying the event log of run {} does not reproduce its state", run_id);
+        }
+        self.notify_run_hook(run_id, "on_complete");
+        // Trigger Cleanup
+        self.trigger_remote_cleanup(run_id).await;
+        tracing::info!("Workflow run {} completed successfully", run_id);
+    }
+
+    /// The run judged against its workflow's success_criteria (None when it has none)
+    async fn run_verdict(&self, run_id: &str) -> Option<RunVerdict> {
+        let (criteria, completed) = {
+            let state = self.runtime_states.get(run_id)?;
+            let criteria = self.workflows.get(&state.workflow_id)?.success_criteria.clone()?;
+            (criteria, state.completed_agents.clone())
+        };
+        let terminal_agents = verdict::terminal_agents(&criteria, &*self.dag_store.get(run_id)?);
+
+        let mut outputs = serde_json::Map::new();
+        for agent_id in completed {
+            if let Ok(Some(output)) = self.get_agent_output(run_id, &agent_id).await {
+                outputs.insert(agent_id, output);
+            }
+        }
+        let owned_run_id = run_id.to_string();
+        let files = tokio::task::spawn_blocking(move || fs_manager::WorkspaceInitializer::list_session_outputs(&owned_run_id))
+            .await
+            .ok()
+            .and_then(|listed| listed.map_err(|e| tracing::warn!("Output files of {} not listed for its verdict: {}", run_id, e)).ok())
+            .unwrap_or_default();
+
+        Some(verdict::evaluate(&criteria, &outputs, &terminal_agents, &files))
+    }
+
+    /// Agents that can be dispatched now: not completed, failed or running, with their
+    /// dependencies satisfied under the agent's join_policy. Topological order; the execution
+    /// loop takes the first one.
+    pub fn ready_agents(&self, run_id: &str) -> Result<Vec<String>, RuntimeError> {
+        let dag = self.dag_store.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let execution_order = dag.topological_sort()?;
+        let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        // A paused, unlaunched or finished run dispatches nothing
+        if matches!(state.status, RuntimeStatus::AwaitingApproval | RuntimeStatus::Preparing) || state.status.is_terminal() {
+            return Ok(Vec::new());
+        }
+        let workflow = self.workflows.get(&state.workflow_id);
+        let join_policy = |agent_id: &str| workflow.as_ref()
+            .and_then(|w| w.agents.iter().find(|a| a.id == agent_id).map(|a| a.join_policy.clone()))
+            .unwrap_or_default();
+
+        Ok(execution_order.into_iter().filter(|agent_id| {
+            let is_pending = !state.completed_agents.contains(agent_id) &&
+                             !state.has_failed(agent_id) &&
+                             !state.active_agents.contains(agent_id);
+
+            is_pending && join_policy(agent_id).is_satisfied(&dag.get_dependencies(agent_id), &state.completed_agents)
+        }).collect())
+    }
+
+    /// Live runs that have ready agents, in dispatch order: effective priority first, then FIFO
+    /// (longest waiting) within a level. `aging` lifts long-waiting runs to avoid starvation.
+    pub fn dispatch_queue(&self, aging: Option<std::time::Duration>) -> Vec<ScheduledRun> {
+        let now = Utc::now();
+        let candidates: Vec<(String, u8, String)> = self.runtime_states.iter()
+            .filter(|s| s.status == RuntimeStatus::Running)
+            .map(|s| (s.run_id.clone(), s.priority, s.start_time.clone()))
+            .collect();
+
+        let mut queue: Vec<ScheduledRun> = candidates.into_iter().filter_map(|(run_id, priority, start_time)| {
+            let ready_agents = self.ready_agents(&run_id).ok().filter(|r| !r.is_empty())?;
+            let waited = chrono::DateTime::parse_from_rfc3339(&start_time).ok()
+                .and_then(|t| (now - t.with_timezone(&Utc)).to_std().ok())
+                .unwrap_or_default();
+            Some(ScheduledRun {
+                run_id,
+                priority,
+                effective_priority: effective_priority(priority, waited, aging),
+                waiting_secs: waited.as_secs(),
+                ready_agents,
+            })
+        }).collect();
+
+        queue.sort_by(|a, b| b.effective_priority.cmp(&a.effective_priority)
+            .then(b.waiting_secs.cmp(&a.waiting_secs))
+            .then(a.run_id.cmp(&b.run_id)));
+        queue
+    }
+
+    /// Admin override of a run's priority. Returns the previous value.
+    pub async fn set_run_priority(&self, run_id: &str, priority: u8) -> Result<u8, RuntimeError> {
+        if priority > MAX_RUN_PRIORITY {
+            return Err(RuntimeError::InvalidPriority(priority));
+        }
+        let previous = {
+            let mut state = self.runtime_states.get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            std::mem::replace(&mut state.priority, priority)
+        };
+        self.persist_state(run_id).await;
+        tracing::info!("Run {} priority {} -> {}", run_id, previous, priority);
+        Ok(previous)
+    }
+
+    /// Fresh Running state for a run of `config`; run-level settings are copied onto it
+    fn initial_state(run_id: &str, client_id: &str, config: &WorkflowConfig, skipped_agents: Vec<String>) -> RuntimeState {
+        RuntimeState {
+            run_id: run_id.to_string(),
+            workflow_id: config.id.clone(),
+            client_id: client_id.to_string(),
+            status: RuntimeStatus::Running,
+            active_agents: Vec::new(),
+            completed_agents: Vec::new(),
+            failed_agents: Vec::new(),
+            blocked_agents: Vec::new(),
+            invocations: Vec::new(),
+            total_tokens_used: 0,
+            start_time: Utc::now().to_rfc3339(),
+            end_time: None,
+            budget_warning: false,
+            skipped_agents,
+            metadata: config.metadata.clone(),
+            approval_history: Vec::new(),
+            priority: config.priority.unwrap_or(DEFAULT_RUN_PRIORITY),
+            simulation: config.simulation,
+            seed: config.seed,
+            active_since: HashMap::new(),
+            stalled: false,
+            attached_files: Vec::new(),
+            prepare_deadline: None,
+            verdict: None,
+        }
+    }
+
+    /// DYNAMIC EXECUTION LOOP
+    pub(crate) async fn execute_dynamic_dag(&self, run_id: String) {
+        tracing::info!("Starting DYNAMIC DAG execution for run_id: {}", run_id);
+        // We use a simplified loop: Re-calculate topology, filter for uncompleted, take the next one.
+        // In a real high-throughput system, we'd use a proper ready-queue, but re-calculating topology
+        // on a small graph (<100 nodes) is negligible and safer for consistency.
+
+        loop {
+            // 1. Check if Run is still valid/active or paused
+            if let Some(state) = self.runtime_states.get(&run_id) {
+                // Check for pause state
+                if state.status == RuntimeStatus::AwaitingApproval {
+                    tracing::info!("Execution loop for {} suspending (Awaiting Approval).", run_id);
+                    break;
+                }
+                // Check for terminal states
+                if state.status.is_terminal() {
+                    break;
+                }
+            } else {
+                // Run vanished
+                break;
+            }
+            // 2. Determine Next Agent(s)
+            // We get the full topological sort, then find the first node that is NOT complete and NOT running.
+
+            // 2. Determine Next Agent(s) - FIX: INTEGRATED DEPENDENCY CHECK
+            // We search for the first node that is pending AND has all dependencies satisfied.
+            // This prevents head-of-line blocking where a waiting node prevents independent siblings from running.
+            let next_agent_opt = match self.ready_agents(&run_id) {
+                Ok(ready) => ready.into_iter().next(),
+                Err(RuntimeError::Dag(e)) => {
+                    self.fail_run(&run_id, "SYSTEM", FailureCode::DagCycle, &format!("DAG cycle detected during execution: {}", e)).await;
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("Cannot schedule run {}: {}", run_id, e);
+                    break;
+                }
+            };
+            // 3. If no next agent, check if we are done
+
+            // 3. Process Selection or Wait
+            let agent_id = match next_agent_opt {
+                Some(id) => id,
+                None => {
+                    // No agents ready. Are any running?
+                    let running_count = self.runtime_states.get(&run_id)
+                        .map(|s| s.active_agents.len())
+                        .unwrap_or(0);
+
+                    if running_count > 0 {
+                        // Wait for them to finish (simple polling for this implementation)
+                        // Wait for active agents to finish
+                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                        continue;
+                    } else {
+                        // Nothing running, nothing ready -> We are done!
+                        self.complete_run(&run_id).await;
+                        break;
+                    }
+                }
+            };
+
+            // 5. Execute Agent
+            tracing::info!("Processing agent: {}", agent_id);
+
+            // === PUPPET MODE: PAUSE FOR INSPECTION ===
+            let puppet_mode = env::var("PUPPET_MODE")
+                .unwrap_or_else(|_| "false".to_string())
+                .to_lowercase() == "true";
+
+            if puppet_mode {
+                tracing::info!("🎭 PUPPET MODE: Pausing execution for agent {}", agent_id);
+
+                if let Some(client) = &self.redis_client {
+                    // Gather context for puppet UI
+                    let workflow_id = self.runtime_states.get(&run_id)
+                        .map(|s| s.workflow_id.clone())
+                        .unwrap_or_default();
+
+                    let dependencies = self.dag_store.get(&run_id)
+                        .map(|dag| dag.get_dependencies(&agent_id))
+                        .unwrap_or_default();
+
+                    let agent_context = serde_json::json!({
+                        "run_id": run_id,
+                        "agent_id": agent_id,
+                        "workflow_id": workflow_id,
+                        "dependencies": dependencies,
+                        "timestamp": Utc::now().to_rfc3339(),
+                        "status": "awaiting_decision"
+                    });
+
+                    // Publish to puppet channel
+                    match client.get_async_connection().await {
+                        Ok(mut con) => {
+                            let _: Result<(), _> = con.publish(
+                                "puppet:channel",
+                                agent_context.to_string()
+                            ).await;
+
+                            // BLOCKING WAIT for puppet response (60s timeout)
+                            let response_key = format!("puppet:response:{}:{}", run_id, agent_id);
+                            let timeout = std::time::Duration::from_secs(60);
+
+                            let wait_result = tokio::time::timeout(timeout, async {
+                                loop {
+                                    match con.get::<_, Option<String>>(&response_key).await {
+                                        Ok(Some(response)) => {
+                                            // Delete response key
+                                            let _: Result<(), _> = con.del(&response_key).await;
+                                            return Some(response);
+                                        }
+                                        Ok(None) => {
+                                            // Not ready yet, wait a bit
+                                            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                                        }
+                                        Err(e) => {
+                                            tracing::error!("Redis error waiting for puppet: {}", e);
+                                            return None;
+                                        }
+                                    }
+                                }
+                            }).await;
+
+                            match wait_result {
+                                Ok(Some(response)) => {
+                                    tracing::info!("🎭 Puppet response for {}: {}", agent_id, response);
+                                    // Continue execution (mock may be set in Redis by puppet service)
+                                }
+                                Ok(None) | Err(_) => {
+                                    tracing::warn!("🎭 Puppet timeout for {} - proceeding with normal execution", agent_id);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("Failed to connect to Redis for puppet mode: {}", e);
+                        }
+                    }
+                } else {
+                    tracing::warn!("🎭 PUPPET_MODE enabled but Redis unavailable");
+                }
+            }
+            // ==========================================
+
+            self.update_agent_status(&run_id, &agent_id, InvocationStatus::Running).await;
+            // Emit AgentStarted event
+
+            self.emit_event(RuntimeEvent::new(
+                &run_id,
+                EventType::AgentStarted,
+                Some(agent_id.clone()),
+                serde_json::json!({"agent_id": agent_id}),
+            ));
+
+            let prepare_span = Self::prepare_span(&run_id, &agent_id);
+            let payload_res = self.prepare_invocation_payload(&run_id, &agent_id)
+                .instrument(prepare_span.clone())
+                .await;
+            if let Err(e) = payload_res {
+                // Check if this is a soft failure (context drought) vs hard failure
+                let is_context_drought = matches!(e, RuntimeError::ContextDrought(_));
+
+                if is_context_drought {
+                    // SOFT FAILURE: Already called request_approval in prepare_invocation_payload
+                    // Just record it and break to allow user intervention
+                    tracing::info!("Context Drought detected for {}. Execution paused for approval.", agent_id);
+
+                    {
+                        if let Some(mut state) = self.runtime_states.get_mut(&run_id) {
+                            state.active_agents.retain(|a| a != &agent_id);
+
+                            // Record the paused invocation
+                            state.invocations.push(AgentInvocation {
+                                id: Uuid::new_v4().to_string(),
+                                agent_id: agent_id.clone(),
+                                model_variant: ModelVariant::Fast,
+                                thought_signature: None,
+                                tools_used: vec![],
+                                tokens_used: 0,
+                                latency_ms: 0,
+                                status: InvocationStatus::Paused,
+                                started_at: Utc::now().to_rfc3339(),
+                                timestamp: Utc::now().to_rfc3339(),
+                                artifact_id: None,
+                                error_message: Some(e.to_string()),
+                                replay_of: None,
+                                generation: GenerationParams::default(),
+                                token_split: TokenSplit::default(),
+                                escalated_from: None,
+                            });
+                        }
+                        self.persist_state(&run_id).await;
+                    }
+
+                    // Emit event for UI notification (triggers approval card)
+                    self.emit_event(RuntimeEvent::new(
+                        &run_id,
+                        EventType::SystemIntervention,
+                        Some(agent_id.clone()),
+                        serde_json::json!({
+                            "reason": e.to_string(),
+                            "agent_id": agent_id,
+                            "type": "context_drought",
+                            "recovery_hint": "Upstream agents provided insufficient data. Review and choose to Skip or Stop"
+                        }),
+                    ));
+
+                    // Break to suspend execution
+                    break;
+                } else {
+                    // HARD FAILURE: Preparation error (missing workflow, etc.). A refusing
+                    // before_agent hook has already failed the run.
+                    if !matches!(e, RuntimeError::HookRefused { .. }) {
+                        self.fail_run(&run_id, &agent_id, FailureCode::PreparationError, &e.to_string()).await;
+                    }
+                    self.trigger_remote_cleanup(&run_id).await;
+                    continue;
+                }
+            }
+            let payload = payload_res.unwrap();
+
+            // Freeze the exact payload for replay before it goes over the wire
+            let invocation_id = Uuid::new_v4().to_string();
+            let started_at = Utc::now().to_rfc3339();
+            self.snapshot_payload(&invocation_id, &payload);
+
+            let workflow = self.workflows.get(&self.runtime_states.get(&run_id).map(|s| s.workflow_id.clone()).unwrap_or_default())
+                .map(|w| w.clone());
+            let timeout = workflow.as_ref().and_then(|w| w.agent_timeout(&agent_id));
+            let client_id = self.runtime_states.get(&run_id).map(|s| s.client_id.clone()).unwrap_or_default();
+            let cache_key = workflow.as_ref()
+                .filter(|w| w.agents.iter().any(|a| a.id == agent_id && a.cache_policy == CROSS_RUN_CACHE_POLICY))
+                .map(|w| OutputCache::key(&client_id, &w.id, w.simulation, &payload));
+            let cached = cache_key.as_deref().and_then(|key| self.output_cache.lookup(key, &client_id));
+            let cross_run_hit = cached.is_some();
+            let (payload, response, escalated_from) = match cached {
+                Some(hit) => {
+                    tracing::info!("Agent {} in run {} reused a cross-run cached output", agent_id, run_id);
+                    (payload, Some(Ok(hit)), None)
+                }
+                None => {
+                    let simulation_delay = workflow.as_ref().filter(|w| w.simulation)
+                        .map(|w| w.simulation_delay_ms.map(std::time::Duration::from_millis).unwrap_or_else(simulation::default_delay));
+                    let http_client = self.http_client.clone();
+                    let agent_config = workflow.as_ref().and_then(|w| w.agents.iter().find(|a| a.id == agent_id).cloned());
+                    // Also checked here: imports, patches and restarts must not bypass the flag
+                    let fault_rules = workflow.as_ref().filter(|_| self.fault_injection).map(|w| w.faults.clone()).unwrap_or_default();
+                    let attempts = std::sync::atomic::AtomicU32::new(0);
+                    self.invoke_with_retries(&run_id, &invocation_id, agent_config.as_ref(), payload, timeout, |payload| {
+                        let attempt = attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                        let http_client = http_client.clone();
+                        let sent = payload.clone();
+                        let call = async move {
+                            match simulation_delay {
+                                Some(delay) => simulation::invoke(sent, delay).await,
+                                None => Self::send_invocation(http_client, sent).await,
+                            }
+                        };
+                        match faults::fault_for(&fault_rules, &agent_id, attempt).cloned() {
+                            Some(fault) => {
+                                tracing::info!("Injecting fault {:?} into attempt {} of agent {} in run {}", fault, attempt, agent_id, run_id);
+                                self.emit_event(RuntimeEvent::new(
+                                    &run_id,
+                                    EventType::FaultInjected,
+                                    Some(agent_id.clone()),
+                                    serde_json::json!({ "fault": fault, "attempt": attempt }),
+                                ));
+                                tokio::spawn(faults::inject(fault, payload, call))
+                            }
+                            None => tokio::spawn(call),
+                        }
+                    }).await
+                }
+            };
+            let Some(response) = response else {
+                // === PER-AGENT TIMEOUT ===
+                let limit = timeout.unwrap_or_default();
+                tracing::warn!("Agent {} in run {} timed out after {:?}", agent_id, run_id, limit);
+
+                let invocation = AgentInvocation {
+                    id: invocation_id.clone(),
+                    agent_id: agent_id.clone(),
+                    model_variant: payload.model_variant(&self.model_registry),
+                    thought_signature: None,
+                    tools_used: payload.tools.clone(),
+                    tokens_used: 0,
+                    latency_ms: limit.as_millis() as u64,
+                    status: InvocationStatus::Failed,
+                    started_at: started_at.clone(),
+                    timestamp: Utc::now().to_rfc3339(),
+                    artifact_id: None,
+                    error_message: Some(AGENT_TIMEOUT_MESSAGE.to_string()),
+                    replay_of: None,
+                    generation: payload.generation(),
+                    token_split: TokenSplit::default(),
+                    escalated_from: escalated_from.clone(),
+                };
+                let _ = self.record_invocation(&run_id, invocation, prepare_span.id()).await;
+
+                let failure = self.runtime_states.get_mut(&run_id).and_then(|mut state| {
+                    if state.status.is_terminal() {
+                        return None;
+                    }
+                    let now = Utc::now().to_rfc3339();
+                    state.status = RuntimeStatus::Failed;
+                    state.end_time = Some(now.clone());
+                    Some(state.record_failure(&agent_id, FailureCode::Timeout, AGENT_TIMEOUT_MESSAGE, &now))
+                });
+                if failure.is_some() {
+                    // Calls of other agents still running are of no use to a failed run
+                    self.cancel_inflight_invocations(&run_id);
+                    self.notify_run_hook(&run_id, "on_fail");
+                }
+                self.emit_agent_failed(&run_id, failure);
+                self.persist_state(&run_id).await;
+                self.trigger_remote_cleanup(&run_id).await;
+                break;
+            };
+
+            // 6. Handle Result & Potential Delegation
+            match response {
+                Ok(res) => {
+                    let token_split = res.token_split();
+                    // === POST-FLIGHT: PROTOCOL VALIDATOR & SEMANTIC CHECK ===
+                    let agent_config = workflow.as_ref().and_then(|w| w.agents.iter().find(|a| a.id == agent_id));
+                    let soft_failure = Self::post_flight_problem(&agent_id, agent_config, &res);
+
+                    // Circuit Breaker Decision & Output Handling
+                    if res.success && soft_failure.is_none() {
+                        if let (Some(key), Some(w), false) = (&cache_key, &workflow, cross_run_hit) {
+                            self.output_cache.store(key, &client_id, &w.id, &agent_id, &res);
+                        }
+                        // [[CONTEXT CACHING PERSISTENCE]]
+                        // If the agent returned a cache ID (either created new or refreshed),
+                        // update the runtime store so subsequent agents reuse it.
+                        if let Some(cache_id) = &res.cached_content_id {
+                            if let Err(e) = self.set_cache_resource(&run_id, cache_id.clone()) {
+                                tracing::warn!("Failed to update cache resource for run {}: {}", run_id, e);
+                            } else {
+                                tracing::debug!("Updated Context Cache for run {}: {}", run_id, cache_id);
+                            }
+                        }
+
+                        // A. Check for Delegation (Dynamic Splicing)
+                        if let Some(delegation) = res.delegation {
+                            tracing::info!("Agent {} requested delegation: {}", agent_id, delegation.reason);
+
+                            // === FIX: SEPARATE LOCK SCOPES TO PREVENT DEADLOCK ===
+                            // === FIX: DEADLOCK PREVENTION ===
+                            // Separate lock scopes.
+                            // 1. Acquire Read Lock to check permission, then DROP IT immediately.
+                            let can_delegate = if let Some(state) = self.runtime_states.get(&run_id) {
+                                let wf_id = state.workflow_id.clone();
+                                if let Some(workflow) = self.workflows.get(&wf_id) {
+                                    workflow.agents.iter()
+                                        .find(|a| a.id == agent_id)
+                                        .map(|a| a.allow_delegation)
+                                        .unwrap_or(false)
+                                } else { false }
+                            } else { false };
+
+                            // 2. Acquire Write Lock inside handle_delegation (if permitted)
+                            if !can_delegate {
+                                tracing::warn!("Agent {} attempted delegation without permission. Ignoring.", agent_id);
+                            } else {
+                                match self.handle_delegation(&run_id, &agent_id, delegation).await {
+                                    Ok(_) => {
+                                        tracing::info!("Delegation processed. Graph updated.");
+                                    }
+                                    Err(e) => {
+                                        tracing::error!("Delegation failed: {}", e);
+                                        self.fail_run(&run_id, &agent_id, FailureCode::DelegationError, &format!("Delegation error: {}", e)).await;
+                                        // Never recorded, so there is nothing to replay
+                                        self.forget_payload(&run_id, &invocation_id);
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+
+                        // B. Standard Completion Logic
+                        if let Some(sig) = res.thought_signature {
+                            if let Err(e) = self.set_thought_signature(&run_id, &agent_id, sig) {
+                                tracing::warn!("Thought signature of {} in run {} not stored: {}", agent_id, run_id, e);
+                            }
+                        }
+
+                        let artifact_id = if let Some(output_data) = &res.output {
+                            self.search_index.index_output(&run_id, &agent_id, &Self::output_search_text(output_data));
+
+                            // File Promotion Logic
+                            if let Some(files_array) = output_data.get("files_generated").and_then(|v| v.as_array()) {
+                                let (workflow_id, client_id) = self.runtime_states.get(&run_id)
+                                    .map(|s| (s.workflow_id.clone(), s.client_id.clone()))
+                                    .unwrap_or_default();
+
+                                let user_directive = {
+                                    if let Some(workflow) = self.workflows.get(&workflow_id) {
+                                        workflow.agents.iter()
+                                            .find(|a| a.id == agent_id)
+                                            .map(|a| a.user_directive.clone())
+                                            .unwrap_or_default()
+                                    } else { String::new() }
+                                };
+
+                                // One batch, so a report and its images are promoted together or not at all
+                                let filenames: Vec<String> = files_array.iter().filter_map(|v| v.as_str().map(String::from)).collect();
+                                if !filenames.is_empty() {
+                                    let rid = run_id.clone();
+                                    let aid = agent_id.clone();
+                                    tokio::spawn(async move {
+                                        match fs_manager::WorkspaceInitializer::promote_artifacts_batch(
+                                            &client_id, &rid, &workflow_id, &aid, &filenames, &user_directive
+                                        ).await {
+                                            Ok(files) => tracing::info!("✓ {} artifacts promoted to persistent storage", files.len()),
+                                            Err(e) => tracing::error!("✗ Failed to promote artifacts {:?}: {}", filenames, e),
+                                        }
+                                    });
+                                }
+                            }
+
+                            let agent_stored_flag = output_data.get("artifact_stored")
+                                .and_then(|v| v.as_bool())
+                                .unwrap_or(false);
+
+                            if agent_stored_flag {
+                                Some(format!("run:{}:agent:{}:output", run_id, agent_id))
+                            } else {
+                                self.store_artifact(&run_id, &agent_id, output_data).await
+                            }
+                        } else { None };
+
+                        let invocation = AgentInvocation {
+                            id: invocation_id.clone(),
+                            agent_id: agent_id.clone(),
+                            model_variant: payload.model_variant(&self.model_registry),
+                            thought_signature: None,
+                            tools_used: payload.tools.clone(),
+                            tokens_used: res.tokens_used,
+                            latency_ms: res.latency_ms as u64,
+                            status: InvocationStatus::Success,
+                            started_at: started_at.clone(),
+                            timestamp: Utc::now().to_rfc3339(),
+                            artifact_id,
+                            error_message: None,
+                            replay_of: None,
+                            generation: payload.generation(),
+                            token_split,
+                            escalated_from: escalated_from.clone(),
+                        };
+
+                        let _ = self.record_invocation(&run_id, invocation, prepare_span.id()).await;
+
+                        self.emit_event(RuntimeEvent::new(
+                            &run_id,
+                            EventType::AgentCompleted,
+                            Some(agent_id.clone()),
+                            serde_json::json!({"agent_id": agent_id, "tokens_used": res.tokens_used, "cross_run_cache_hit": cross_run_hit}),
+                        ));
+
+                        // C. Supervisor Directives (meta-agent governing the others)
+                        let workflow_id = self.runtime_states.get(&run_id).map(|s| s.workflow_id.clone()).unwrap_or_default();
+                        let is_supervisor = self.workflows.get(&workflow_id)
+                            .and_then(|w| w.agents.iter().find(|a| a.id == agent_id).map(|a| a.role == AgentRole::Supervisor))
+                            .unwrap_or(false);
+                        if let Some(directive) = res.output.as_ref().filter(|_| is_supervisor).and_then(SupervisorDirective::from_output) {
+                            if let Err(e) = self.apply_supervisor_directive(&run_id, &agent_id, &directive).await {
+                                tracing::warn!("Supervisor {} directive rejected: {}", agent_id, e);
+                            }
+                        }
+
+                    } else {
+                        // === CIRCUIT BREAKER: PAUSE LOGIC (SOFT VS HARD FAILURES) ===
+                        let (pause_reason, is_fatal) = match soft_failure {
+                            Some(problem) => (problem, false),
+                            None => (res.error.unwrap_or_else(|| "Unknown Execution Error".to_string()), true),
+                        };
+
+                        tracing::warn!("Circuit Breaker Triggered for {}: {}", agent_id, pause_reason);
+
+                        if is_fatal {
+                            // HARD FAILURE: Crash the run (Network errors, Panics)
+                            let failure = self.fail_run(&run_id, &agent_id, FailureCode::AgentError, &pause_reason).await;
+                            self.emit_agent_failed(&run_id, failure);
+                            self.trigger_remote_cleanup(&run_id).await;
+                            break;
+                        } else {
+                            // SOFT FAILURE: Pause for Human Intervention (Nulls, Policy Violations)
+                            // We do NOT mark as failed. We simply remove from active and request approval.
+                            // This allows the user to Resume (Retry) or Edit the state.
+
+                            {
+                                if let Some(mut state) = self.runtime_states.get_mut(&run_id) {
+                                    state.active_agents.retain(|a| a != &agent_id);
+
+                                    // Record the "Paused" invocation so it appears in logs
+                                    let mut paused = AgentInvocation {
+                                        id: invocation_id.clone(),
+                                        agent_id: agent_id.clone(),
+                                        model_variant: payload.model_variant(&self.model_registry),
+                                        thought_signature: None,
+                                        tools_used: payload.tools.clone(),
+                                        tokens_used: res.tokens_used,
+                                        latency_ms: res.latency_ms as u64,
+                                        status: InvocationStatus::Paused,
+                                        started_at: started_at.clone(),
+                                        timestamp: Utc::now().to_rfc3339(),
+                                        artifact_id: None,
+                                        error_message: Some(pause_reason.clone()),
+                                        replay_of: None,
+                                        generation: payload.generation(),
+                                        token_split,
+                                        escalated_from: escalated_from.clone(),
+                                    };
+                                    paused.reconcile_tokens();
+                                    state.invocations.push(paused);
+                                }
+                                self.persist_state(&run_id).await;
+                            }
+
+                            // Emit event for UI notification (triggers approval card)
+                            self.emit_event(RuntimeEvent::new(
+                                &run_id,
+                                EventType::SystemIntervention,
+                                Some(agent_id.clone()),
+                                serde_json::json!({
+                                    "reason": pause_reason,
+                                    "agent_id": agent_id,
+                                    "type": "soft_failure",
+                                    "recovery_hint": "Review agent output and choose to Resume, Skip, or Stop"
+                                }),
+                            ));
+
+                            // Trigger the Pause (sets status to AwaitingApproval)
+                            self.request_approval(&run_id, Some(&agent_id), &pause_reason).await;
+
+                            // Break the loop to suspend execution until resumed
+                            break;
+                        }
+                    }
+                }
+                // Cancelled because the run already ended elsewhere: nothing left to fail
+                Err(_) if self.run_ended(&run_id) => break,
+                Err(e) => {
+                    let failure = self.fail_run(&run_id, &agent_id, FailureCode::ServiceUnavailable, &e.to_string()).await;
+                    self.emit_agent_failed(&run_id, failure);
+                    self.trigger_remote_cleanup(&run_id).await;
+                }
+            }
+        }
+    }
+
+    /// Inject a single agent below `parent_id` (Cortex SpawnAgent action).
+    /// Picked up by the run's execution loop if it is still live.
+    pub async fn spawn_agent(&self, run_id: &str, parent_id: &str, config: AgentNodeConfig, reason: &str) -> Result<(), RuntimeError> {
+        let req = DelegationRequest {
+            reason: reason.to_string(),
+            new_nodes: vec![config],
+            strategy: DelegationStrategy::Child,
+            prune_nodes: Vec::new(),
+        };
+        self.handle_delegation(run_id, parent_id, req).await
+    }
+
+    /// Execute a Supervisor agent's directive against another agent of the run.
+    /// Running agents cannot be targeted (their invocation is already in flight).
+    pub async fn apply_supervisor_directive(&self, run_id: &str, supervisor_id: &str, directive: &SupervisorDirective) -> Result<(), RuntimeError> {
+        let target = directive.target_agent_id.as_str();
+        if target == supervisor_id {
+            return Err(RuntimeError::InvalidDirective("a supervisor cannot target itself".to_string()));
+        }
+
+        let downstream = self.dag_store.get(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?
+            .descendants(target)
+            .map_err(|_| RuntimeError::AgentNotFound(target.to_string()))?;
+
+        let (finished, pending_downstream) = {
+            let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            if state.active_agents.iter().any(|a| a == target) {
+                return Err(RuntimeError::InvalidDirective(format!("agent '{}' is running", target)));
+            }
+            let is_pending = |a: &String| !state.completed_agents.contains(a) && !state.active_agents.contains(a) && !state.has_failed(a);
+            let pending_downstream: Vec<String> = downstream.iter().filter(|a| is_pending(a)).cloned().collect();
+            let finished = state.completed_agents.iter().any(|a| a == target) || state.has_failed(target);
+            (finished, pending_downstream)
+        };
+
+        match &directive.action {
+            SupervisorAction::CancelAgent => {
+                if finished {
+                    return Err(RuntimeError::InvalidDirective(format!("agent '{}' already finished", target)));
+                }
+                // Dependents of a cancelled agent would never get their input
+                let mut cancelled = vec![target.to_string()];
+                cancelled.extend(pending_downstream);
+                cancelled.sort();
+
+                if let Some(mut dag) = self.dag_store.get_mut(run_id) {
+                    for node in &cancelled {
+                        let _ = dag.remove_node(node);
+                    }
+                }
+                if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+                    state.skipped_agents.extend(cancelled);
+                }
+            }
+            SupervisorAction::RetryAgent => {
+                if !finished {
+                    return Err(RuntimeError::InvalidDirective(format!("agent '{}' has not run yet", target)));
+                }
+                if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+                    state.completed_agents.retain(|a| a != target);
+                    state.failed_agents.retain(|f| f.agent_id != target);
+                    // A retried skip is no longer a skip; the agent_skipped event keeps the record
+                    state.invocations.retain(|i| !(i.agent_id == target && i.status == InvocationStatus::Skipped));
+                    state.blocked_agents.retain(|b| !b.blocked_by.iter().any(|f| f == target));
+                }
+            }
+            SupervisorAction::ModifyPrompt { new_prompt } => {
+                // Rewrite this run's own copy: other runs may share the workflow id
+                let workflow_id = self.pin_run_workflow(run_id)?;
+                let mut workflow = self.workflows.get_mut(&workflow_id)
+                    .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+                let agent = workflow.agents.iter_mut()
+                    .find(|a| a.id == target)
+                    .ok_or_else(|| RuntimeError::AgentNotFound(target.to_string()))?;
+                agent.prompt = new_prompt.clone();
+            }
+        }
+
+        tracing::info!("Supervisor {} applied {:?} to {}: {}", supervisor_id, directive.action, target, directive.reason);
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            Some(supervisor_id.to_string()),
+            serde_json::json!({
+                "type": "supervisor_directive",
+                "reason": directive.reason,
+                "directive": directive,
+            }),
+        ));
+        self.persist_state(run_id).await;
+        Ok(())
+    }
+
+    /// Handles the "Graph Surgery" when an agent requests delegation
+    async fn handle_delegation(&self, run_id: &str, parent_id: &str, mut req: DelegationRequest) -> Result<(), RuntimeError> {
+        let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let workflow_id = state.workflow_id.clone();
+
+        // Snapshot safe IDs to check status
+        let active_agents = state.active_agents.clone();
+        let completed_agents = state.completed_agents.clone();
+        drop(state);  // Drop read lock
+
+        // === STEP 1: PRUNING & ORPHAN CAPTURE ===
+        // Handle removals first to clear the graph
+        // [[CRITICAL FIX]] We must capture "orphans" of pruned nodes to reconnect them!
+        let mut orphaned_dependents: Vec<String> = Vec::new();
+
+        if !req.prune_nodes.is_empty() {
+            tracing::info!("Delegation PRUNE: Removing nodes {:?}", req.prune_nodes);
+
+            for node_to_remove in &req.prune_nodes {
+                // Safety Check: Cannot remove Active or Completed nodes
+                if active_agents.contains(node_to_remove) || completed_agents.contains(node_to_remove) {
+                    tracing::warn!("Agent {} tried to prune active/completed node {}. Ignoring.", parent_id, node_to_remove);
+                    continue;
+                }
+
+                // 1. Remove from DAG & Capture Orphans
+                if let Some(mut dag) = self.dag_store.get_mut(run_id) {
+                    // [[CRITICAL FIX]] Save children of the node we are about to delete
+                    // These nodes (e.g., 'writer_report') were waiting on 'analyze_data'.
+                    // If we delete 'analyze_data', they become orphans.
+                    // We must add them to the connection list for the NEW nodes.
+                    let children = dag.get_children(node_to_remove);
+                    orphaned_dependents.extend(children);
+
+                    if let Err(e) = dag.remove_node(node_to_remove) {
+                        tracing::warn!("Failed to prune node {}: {}", node_to_remove, e);
+                    }
+                }
+
+                // 2. Remove from Workflow Config (Metadata)
+                if let Some(mut workflow) = self.workflows.get_mut(&workflow_id) {
+                    if let Some(pos) = workflow.agents.iter().position(|a| a.id == *node_to_remove) {
+                        workflow.agents.remove(pos);
+                        tracing::info!("Pruned node '{}' from workflow config", node_to_remove);
+                    }
+                }
+            }
+        }
+
+        // === STEP 2: PRE-FETCH DEPENDENTS (Re-fetch after pruning) ===
+        let (existing_dependents, existing_node_ids) = if let Some(dag) = self.dag_store.get(run_id) {
+            (dag.get_children(parent_id), dag.export_nodes())
+        } else {
+            return Err(RuntimeError::RunNotFound(run_id.to_string()));
+        };
+
+        // FIX: ID Collision Remapping with Ghost Prevention
+        // If a new node has an ID that already exists:
+        // - If the existing node is PENDING (not started), adopt it (overwrite)
+        // - If the existing node is ACTIVE/COMPLETED, rename to avoid corruption
+        let mut id_map: HashMap<String, String> = HashMap::new();
+
+        for node in &mut req.new_nodes {
+            if existing_node_ids.contains(&node.id) {
+                // CHECK STATUS OF EXISTING NODE
+                let is_pending = if let Some(state) = self.runtime_states.get(run_id) {
+                    !state.active_agents.contains(&node.id) &&
+                    !state.completed_agents.contains(&node.id) &&
+                    !state.has_failed(&node.id)
+                } else {
+                    false
+                };
+
+                if is_pending {
+                    tracing::info!("Delegation UPDATE: Adopting/Overwriting pending node '{}'.", node.id);
+
+                    // UPDATE LOGIC: Remove old definition so we can replace it
+                    if let Some(mut workflow) = self.workflows.get_mut(&workflow_id) {
+                        if let Some(pos) = workflow.agents.iter().position(|a| a.id == node.id) {
+                            workflow.agents.remove(pos);
+                        }
+                    }
+
+                    // DAG LOGIC: Clear old incoming edges to allow "Rewiring"
+                    // This allows the agent to change dependencies for an existing pending node
+                    if let Some(mut dag) = self.dag_store.get_mut(run_id) {
+                        dag.clear_incoming_edges(&node.id);
+                    }
+
+                } else {
+                    // Node is already running/done, we MUST rename to avoid history corruption
+                    let old_id = node.id.clone();
+                    let suffix = Uuid::new_v4().to_string().split('-').next().unwrap().to_string();
+                    let new_id = format!("{}_{}", old_id, suffix);
+                    tracing::warn!("Delegation ID Collision: Renaming '{}' to '{}' (node already active/completed)", old_id, new_id);
+
+                    node.id = new_id.clone();
+                    id_map.insert(old_id, new_id);
+                }
+            }
+        }
+
+        // Apply rewiring to new nodes' dependency lists
+        for node in &mut req.new_nodes {
+            node.assign_capable_model().map_err(|e| RuntimeError::InvalidWorkflow(vec![e]))?;
+            for dep in &mut node.depends_on {
+                // If dependency is in our map, update it. Otherwise keep original.
+                if let Some(new_id) = id_map.get(&dep.agent) {
+                    dep.agent = new_id.clone();
+                }
+            }
+        }
+
+        // [[CRITICAL FIX START]]: Create a list of IDs being injected/updated
+        // We must NOT treat these as "downstream dependents" to avoid self-referential cycles.
+        let new_node_ids: Vec<String> = req.new_nodes.iter().map(|n| n.id.clone()).collect();
+
+        // [[CRITICAL FIX]]: MERGE ORPHANS into Downstream Dependents
+        // Combine parent's original children AND the children of the nodes we just pruned.
+        let mut all_downstream = existing_dependents.clone();
+        all_downstream.extend(orphaned_dependents);
+        // Dedup
+        all_downstream.sort();
+        all_downstream.dedup();
+
+        // Filter existing dependents to finding TRUE downstream nodes
+        // 1. Must not be a new node
+        // 2. Must not be a pruned node (dead link)
+        let downstream_dependents: Vec<String> = all_downstream
+            .into_iter()
+            .filter(|dep_id| !new_node_ids.contains(dep_id))
+            .filter(|dep_id| !req.prune_nodes.contains(dep_id)) // <--- ADD THIS to prevent linking to dead nodes
+            .collect();
+        // [[CRITICAL FIX END]]
+
+        // 3. MUTATE WORKFLOW CONFIG
+        if let Some(mut workflow) = self.workflows.get_mut(&workflow_id) {
+            for node in &req.new_nodes {
+                workflow.agents.push(node.clone());
+            }
+
+            if req.strategy == DelegationStrategy::Child {
+                // Use the filtered list (downstream_dependents) instead of existing_dependents
+                for dep_id in &downstream_dependents {
+                    if let Some(dep_agent) = workflow.agents.iter_mut().find(|a| a.id == *dep_id) {
+                        dep_agent.depends_on.retain(|p| p.agent != parent_id);
+                        for new_node in &req.new_nodes {
+                            if dep_agent.dependency(&new_node.id).is_none() {
+                                dep_agent.depends_on.push(Dependency::from(new_node.id.clone()));
+                            }
+                        }
+                        tracing::info!("Rewired Config: Agent {} now depends on {:?}", dep_id, dep_agent.depends_on);
+                    }
+                }
+            }
+        } else {
+            return Err(RuntimeError::WorkflowNotFound(workflow_id));
+        }
+
+        // 4. MUTATE DAG TOPOLOGY
+        if let Some(mut dag) = self.dag_store.get_mut(run_id) {
+
+            for node in &req.new_nodes {
+                dag.add_node(node.id.clone())?;
+
+                for dep in &node.depends_on {
+                    if let Err(e) = dag.add_edge(dep.agent.clone(), node.id.clone()) {
+                        tracing::debug!("Adding dependency edge {} -> {}: {:?}", dep.agent, node.id, e);
+                    }
+                }
+                dag.annotate_edges(node);
+
+                if node.depends_on.is_empty() || node.dependency(parent_id).is_some() {
+                     let _ = dag.add_edge(parent_id.to_string(), node.id.clone());
+                }
+
+                // B. Connect New Nodes -> TRUE Downstream Dependents
+                if req.strategy == DelegationStrategy::Child {
+                    for dep in &downstream_dependents {
+                        dag.add_edge(node.id.clone(), dep.clone())?;
+                    }
+                }
+            }
+
+            // C. Remove Old Edges (Parent -> TRUE Downstream Dependents)
+            if req.strategy == DelegationStrategy::Child {
+                for dep in &downstream_dependents {
+                    let _ = dag.remove_edge(parent_id, dep);
+                }
+            }
+
+            if let Err(e) = dag.topological_sort() {
+                tracing::error!("Delegation created a cycle: {:?}", e);
+                return Err(e.into());
+            }
+        } else {
+            return Err(RuntimeError::RunNotFound(run_id.to_string()));
+        }
+
+        // Keep search in sync with the spliced-in prompts
+        if let (Some(workflow), Some(state)) = (self.workflows.get(&workflow_id), self.runtime_states.get(run_id)) {
+            self.search_index.index_workflow(run_id, &state.client_id, &workflow);
+        }
+
+        Ok(())
+    }
+
+    // === INVOCATION REPLAY ===
+
+    /// Fetch the frozen payload captured for an invocation
+    pub fn get_payload_snapshot(&self, run_id: &str, invocation_id: &str) -> Option<InvocationPayload> {
+        self.payload_snapshots.get(run_id)?
+            .iter()
+            .find(|(id, _)| id == invocation_id)
+            .map(|(_, payload)| payload.clone())
+    }
+
+    /// Freeze `payload` as what `invocation_id` sent, replacing an earlier capture of it
+    fn snapshot_payload(&self, invocation_id: &str, payload: &InvocationPayload) {
+        let mut snapshots = self.payload_snapshots.entry(payload.run_id.clone()).or_default();
+        match snapshots.iter_mut().find(|(id, _)| id == invocation_id) {
+            Some((_, frozen)) => *frozen = payload.clone(),
+            None => {
+                if snapshots.len() >= MAX_PAYLOAD_SNAPSHOTS {
+                    snapshots.pop_front();
+                }
+                snapshots.push_back((invocation_id.to_string(), payload.clone()));
+            }
+        }
+    }
+
+    /// Drop the capture of an invocation that never made it into the run's state
+    fn forget_payload(&self, run_id: &str, invocation_id: &str) {
+        if let Some(mut snapshots) = self.payload_snapshots.get_mut(run_id) {
+            snapshots.retain(|(id, _)| id != invocation_id);
+        }
+    }
+
+    /// Re-send a frozen payload to the Agent Service.
+    /// Without `commit`, the replay is recorded as a new invocation but the run's signatures,
+    /// artifacts, completed_agents and token total are left untouched.
+    pub async fn replay_invocation(
+        &self,
+        run_id: &str,
+        invocation_id: &str,
+        execute: bool,
+        commit: bool,
+    ) -> Result<ReplayResult, RuntimeError> {
+        let payload = self.get_payload_snapshot(run_id, invocation_id)
+            .ok_or_else(|| RuntimeError::InvocationNotFound(invocation_id.to_string()))?;
+
+        if !execute {
+            return Ok(ReplayResult { payload, invocation: None });
+        }
+
+        if !self.runtime_states.contains_key(run_id) {
+            return Err(RuntimeError::RunNotFound(run_id.to_string()));
+        }
+
+        tracing::info!("Replaying invocation {} for agent {} (commit={})", invocation_id, payload.agent_id, commit);
+        let replay_started_at = Utc::now().to_rfc3339();
+
+        // A simulated run replays through the simulator, never the Agent Service
+        let simulation_delay = self.runtime_states.get(run_id)
+            .and_then(|s| self.workflows.get(&s.workflow_id).map(|w| w.clone()))
+            .filter(|w| w.simulation)
+            .map(|w| w.simulation_delay_ms.map(std::time::Duration::from_millis).unwrap_or_else(simulation::default_delay));
+        let res = match simulation_delay {
+            Some(delay) => simulation::invoke(payload.clone(), delay).await,
+            None => self.invoke_remote_agent(&payload).await,
+        }.map_err(|e| RuntimeError::AgentService(e.to_string()))?;
+
+        let replay_id = Uuid::new_v4().to_string();
+        self.snapshot_payload(&replay_id, &payload);
+
+        let artifact_id = if commit && res.success {
+            if let Some(sig) = res.thought_signature.clone() {
+                if let Err(e) = self.set_thought_signature(run_id, &payload.agent_id, sig) {
+                    tracing::warn!("Thought signature of replayed {} in run {} not stored: {}", payload.agent_id, run_id, e);
+                }
+            }
+            match &res.output {
+                Some(output) => self.store_artifact(run_id, &payload.agent_id, output).await,
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let mut invocation = AgentInvocation {
+            id: replay_id,
+            agent_id: payload.agent_id.clone(),
+            model_variant: payload.model_variant(&self.model_registry),
+            thought_signature: None,
+            tools_used: payload.tools.clone(),
+            tokens_used: res.tokens_used,
+            latency_ms: res.latency_ms as u64,
+            status: if res.success { InvocationStatus::Success } else { InvocationStatus::Failed },
+            started_at: replay_started_at,
+            timestamp: Utc::now().to_rfc3339(),
+            artifact_id,
+            error_message: res.error.clone(),
+            replay_of: Some(invocation_id.to_string()),
+            generation: payload.generation(),
+            token_split: res.token_split(),
+            escalated_from: None,
+        };
+        invocation.reconcile_tokens();
+
+        if commit {
+            self.record_invocation(run_id, invocation.clone(), None).await?;
+        } else {
+            // Log-only: keep the replay visible without touching agent status lists or the
+            // run's token total (a dry run must not eat into its budget)
+            if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+                state.invocations.push(invocation.clone());
+            }
+            self.persist_state(run_id).await;
+        }
+
+        Ok(ReplayResult { payload, invocation: Some(invocation) })
+    }
+
+    // === PARTIAL EXECUTION ===
+
+    /// Narrow a live run to `targets` plus their ancestors. Agents that already started or
+    /// finished are kept. Returns the newly skipped agents.
+    pub async fn restrict_to_targets(&self, run_id: &str, targets: &[String]) -> Result<Vec<String>, RuntimeError> {
+        let started: Vec<String> = {
+            let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            state.completed_agents.iter()
+                .chain(state.failed_agents.iter().map(|f| &f.agent_id))
+                .chain(&state.active_agents)
+                .cloned()
+                .collect()
+        };
+
+        let skipped = {
+            let mut dag = self.dag_store.get_mut(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            prune_to_targets(&mut dag, targets, &started)?
+        };
+
+        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            state.skipped_agents.extend(skipped.iter().cloned());
+        }
+        self.persist_state(run_id).await;
+
+        tracing::info!("Run {} scoped to {:?} ({} agents skipped)", run_id, targets, skipped.len());
+        Ok(skipped)
+    }
+
+    // === ARTIFACT AUTO-PROMOTION ===
+
+    /// Promote session outputs matching the agent's auto_promote globs. Driven by AgentCompleted.
+    /// Returns the promoted filenames.
+    pub async fn auto_promote_artifacts(&self, run_id: &str, agent_id: &str) -> Vec<String> {
+        let (client_id, workflow_id) = match self.runtime_states.get(run_id) {
+            Some(s) => (s.client_id.clone(), s.workflow_id.clone()),
+            None => return Vec::new(),
+        };
+
+        let (rules, user_directive) = match self.workflows.get(&workflow_id) {
+            Some(w) => match w.agents.iter().find(|a| a.id == agent_id) {
+                Some(agent) => (w.promotion_rules_for(agent), agent.user_directive.clone()),
+                None => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+
+        if rules.is_empty() {
+            return Vec::new();
+        }
+
+        // Validated at workflow start; anything that fails here is skipped
+        let patterns: Vec<glob::Pattern> = rules.iter().filter_map(|r| glob::Pattern::new(r).ok()).collect();
+        let promoted = fs_manager::WorkspaceInitializer::get_artifact_metadata(&client_id, run_id)
+            .await
+            .map(|m| m.artifacts)
+            .unwrap_or_default();
+
+        let output_dir = fs_manager::WorkspaceInitializer::session_output_dir(run_id);
+        let candidates = match fs_manager::WorkspaceInitializer::promotion_candidates(&output_dir, &patterns, &promoted) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("Auto-promotion scan failed for run {}: {}", run_id, e);
+                return Vec::new();
+            }
+        };
+
+        let mut done = Vec::new();
+        for filename in candidates {
+            match fs_manager::WorkspaceInitializer::promote_artifact_to_storage(
+                &client_id, run_id, &workflow_id, agent_id, &filename, &user_directive
+            ).await {
+                Ok(_) => {
+                    self.emit_event(RuntimeEvent::new(
+                        run_id,
+                        EventType::ArtifactPromoted,
+                        Some(agent_id.to_string()),
+                        serde_json::json!({ "filename": filename, "rule": "auto_promote" }),
+                    ));
+                    done.push(filename);
+                }
+                Err(e) => tracing::error!("Auto-promotion of '{}' failed: {}", filename, e),
+            }
+        }
+
+        if !done.is_empty() {
+            tracing::info!("Auto-promoted {} artifacts for {}/{}", done.len(), run_id, agent_id);
+        }
+        done
+    }
+
+    // === CLIENT KILL-SWITCH ===
+
+    /// Stop every live run owned by a client. With `block_new_runs`, later starts are refused
+    /// until `unhalt_client`. Returns the cancelled run ids.
+    pub async fn halt_client(&self, client_id: &str, block_new_runs: bool, reason: &str) -> Vec<String> {
+        if block_new_runs {
+            self.halted_clients.insert(client_id.to_string(), Utc::now().to_rfc3339());
+        }
+
+        let live_runs: Vec<String> = self.runtime_states.iter()
+            .filter(|s| s.client_id == client_id)
+            .filter(|s| matches!(s.status, RuntimeStatus::Running | RuntimeStatus::AwaitingApproval | RuntimeStatus::Preparing))
+            .map(|s| s.run_id.clone())
+            .collect();
+
+        for run_id in &live_runs {
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::SystemIntervention,
+                None,
+                serde_json::json!({ "action": "halt", "reason": reason }),
+            ));
+            self.fail_run(run_id, "OPERATOR", FailureCode::OperatorStop, reason).await;
+            self.trigger_remote_cleanup(run_id).await;
+        }
+
+        tracing::warn!("Client {} halted ({} runs cancelled, block_new_runs={})", observability::anonymize_client(client_id), live_runs.len(), block_new_runs);
+        live_runs
+    }
+
+    /// Lift a halt. Returns false if the client was not halted.
+    pub fn unhalt_client(&self, client_id: &str) -> bool {
+        self.halted_clients.remove(client_id).is_some()
+    }
+
+    pub fn is_client_halted(&self, client_id: &str) -> bool {
+        self.halted_clients.contains_key(client_id)
+    }
+
+    pub fn maintenance_mode(&self) -> MaintenanceMode {
+        *self.maintenance_mode.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Switch the admission mode and persist it, so a kernel restarted mid-drain stays drained
+    pub async fn set_maintenance_mode(&self, mode: MaintenanceMode) {
+        *self.maintenance_mode.write().unwrap_or_else(|e| e.into_inner()) = mode;
+        tracing::warn!("Maintenance mode set to {:?}", mode);
+
+        let Some(client) = &self.redis_client else { return };
+        match client.get_async_connection().await {
+            Ok(mut con) => {
+                let result: redis::RedisResult<()> = match mode {
+                    MaintenanceMode::Normal => con.del(MAINTENANCE_MODE_KEY).await,
+                    MaintenanceMode::Drain => con.set(MAINTENANCE_MODE_KEY, "drain").await,
+                };
+                if let Err(e) = result {
+                    tracing::error!("Failed to persist maintenance mode: {}", e);
+                }
+            }
+            Err(e) => tracing::error!("Failed to persist maintenance mode: {}", e),
+        }
+    }
+
+    pub fn maintenance_status(&self) -> MaintenanceStatus {
+        let active: Vec<ActiveRunRef> = self.runtime_states.iter()
+            .filter(|s| !s.status.is_terminal())
+            .map(|s| ActiveRunRef { run_id: s.run_id.clone(), status: s.status.clone(), start_time: s.start_time.clone() })
+            .collect();
+        MaintenanceStatus {
+            mode: self.maintenance_mode(),
+            active_runs: active.len(),
+            // RFC 3339 timestamps from Utc::now() sort chronologically as strings
+            oldest_active_run: active.into_iter().min_by(|a, b| a.start_time.cmp(&b.start_time)),
+        }
+    }
+
+    /// Helper to fail the run and update state (Async + Persistent).
+    /// Returns the failure record, or None if the run is unknown or sealed.
+    pub async fn fail_run(&self, run_id: &str, agent_id: &str, error_code: FailureCode, error: &str) -> Option<FailedAgent> {
+        let failure = self.runtime_states.get_mut(run_id)
+            .and_then(|mut state| Self::fail_state(&mut state, agent_id, error_code, error));
+        self.settle_failure(run_id, agent_id, failure.is_some(), error).await;
+        failure
+    }
+
+    /// The state half of fail_run, for callers already holding the state lock. None when the
+    /// run was aborted (nothing is changed then).
+    fn fail_state(state: &mut RuntimeState, agent_id: &str, error_code: FailureCode, error: &str) -> Option<FailedAgent> {
+        if state.status == RuntimeStatus::Aborted {
+            return None;
+        }
+        let now = Utc::now().to_rfc3339();
+        state.status = RuntimeStatus::Failed;
+        state.end_time = Some(now.clone());
+
+        // Record failed invocation
+        state.invocations.push(AgentInvocation {
+            id: Uuid::new_v4().to_string(),
+            agent_id: agent_id.to_string(),
+            model_variant: ModelVariant::Fast, // Fallback
+            thought_signature: None,
+            tools_used: vec![],
+            tokens_used: 0,
+            latency_ms: 0,
+            status: InvocationStatus::Failed,
+            started_at: now.clone(),
+            timestamp: Utc::now().to_rfc3339(),
+            artifact_id: None,
+            error_message: Some(error.to_string()), 
+            replay_of: None,
+            generation: GenerationParams::default(),
+            token_split: TokenSplit::default(),
+            escalated_from: None,
+        });
+
+        Some(state.record_failure(agent_id, error_code, error, &now))
+    }
+
+    /// The rest of fail_run once the state lock is released; `failed` is whether fail_state
+    /// changed anything
+    async fn settle_failure(&self, run_id: &str, agent_id: &str, failed: bool, error: &str) {
+        if failed {
+            self.block_downstream(run_id, agent_id);
+            self.release_run_in_flight(run_id);
+            self.cancel_inflight_invocations(run_id);
+        }
+
+        self.persist_state(run_id).await;
+        if failed {
+            self.notify_run_hook(run_id, "on_fail");
+        }
+        tracing::error!("Run {} failed at agent {}: {}", run_id, agent_id, error);
+    }
+
+    /// Mark every transitive dependent of a failed agent as blocked by it
+    fn block_downstream(&self, run_id: &str, failed_id: &str) {
+        let mut downstream: Vec<String> = match self.dag_store.get(run_id).map(|dag| dag.descendants(failed_id)) {
+            Some(Ok(d)) => d.into_iter().collect(),
+            _ => return, // Pseudo-agents (SYSTEM, OPERATOR) have no place in the DAG
+        };
+        downstream.sort();
+
+        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            state.mark_blocked(failed_id, downstream);
+        }
+    }
+
+    fn emit_agent_failed(&self, run_id: &str, failure: Option<FailedAgent>) {
+        if let Some(failure) = failure {
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::AgentFailed,
+                Some(failure.agent_id.clone()),
+                serde_json::to_value(&failure).unwrap_or_default(),
+            ));
+        }
+    }
+
+    /// Helper to update status to Running (Async + Persistent)
+    async fn update_agent_status(&self, run_id: &str, agent_id: &str, status: InvocationStatus) {
+         let mut changed = false;
+         if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            if status == InvocationStatus::Running
+                && !state.active_agents.contains(&agent_id.to_string())
+            {
+                state.mark_active(agent_id, &Utc::now().to_rfc3339());
+                changed = true;
+            }  // Drop write lock before persisting
+         }
+         
+         if changed {
+             self.persist_state(run_id).await;
+         }
+    }
+
+    /// Perform the actual HTTP request to the Agent Service
+    async fn invoke_remote_agent(&self, payload: &InvocationPayload) -> Result<RemoteAgentResponse, reqwest::Error> {
+        Self::send_invocation(self.http_client.clone(), payload.clone()).await
+    }
+
+    /// Await a spawned remote call, tracked in inflight_invocations so it can be cancelled.
+    /// Returns None if `timeout` elapsed first (the call is aborted).
+    async fn await_invocation(
+        &self,
+        run_id: &str,
+        invocation_id: &str,
+        mut call: JoinHandle<Result<RemoteAgentResponse, reqwest::Error>>,
+        timeout: Option<std::time::Duration>,
+    ) -> Option<Result<RemoteAgentResponse, RuntimeError>> {
+        self.inflight_invocations.insert(invocation_id.to_string(), (run_id.to_string(), call.abort_handle()));
+
+        let joined = match timeout {
+            Some(limit) => tokio::time::timeout(limit, &mut call).await.ok(),
+            None => Some((&mut call).await),
+        };
+        self.inflight_invocations.remove(invocation_id);
+
+        match joined {
+            Some(Ok(result)) => Some(result.map_err(|e| RuntimeError::AgentService(e.to_string()))),
+            Some(Err(e)) => Some(Err(RuntimeError::AgentService(format!("Invocation task ended: {}", e)))),
+            None => {
+                call.abort();
+                None
+            }
+        }
+    }
+
+    /// Run an invocation under the agent's retry policy: up to max_retries further attempts on
+    /// its own model, then one on the escalation variant. Calls that fail and calls whose output
+    /// fails post-flight (see post_flight_problem) are retried; attempts followed by another one
+    /// are recorded as Failed invocations without failing the agent. Returns the last
+    /// attempt's outcome, the payload it was sent with, and the agent's own variant when that
+    /// attempt was escalated.
+    async fn invoke_with_retries<F>(
+        &self,
+        run_id: &str,
+        invocation_id: &str,
+        agent: Option<&AgentNodeConfig>,
+        mut payload: InvocationPayload,
+        timeout: Option<std::time::Duration>,
+        call: F,
+    ) -> (InvocationPayload, Option<Result<RemoteAgentResponse, RuntimeError>>, Option<ModelVariant>)
+    where
+        F: Fn(InvocationPayload) -> JoinHandle<Result<RemoteAgentResponse, reqwest::Error>>,
+    {
+        let policy = agent.and_then(|a| a.retry.clone()).unwrap_or_default();
+        let mut retries_left = policy.max_retries;
+        let mut escalated_from = None;
+        loop {
+            let started_at = Utc::now().to_rfc3339();
+            let attempt_start = std::time::Instant::now();
+            let response = self.await_invocation(run_id, invocation_id, call(payload.clone()), timeout).await;
+            // Timeouts keep their own handling; only completed calls are retried
+            let (error, tokens_used, token_split) = match &response {
+                Some(Ok(res)) if !res.success => (res.error.clone().unwrap_or_else(|| "Unknown Execution Error".to_string()), res.tokens_used, res.token_split()),
+                Some(Ok(res)) => match Self::post_flight_problem(&payload.agent_id, agent, res) {
+                    Some(problem) => (problem, res.tokens_used, res.token_split()),
+                    None => return (payload, response, escalated_from),
+                },
+                Some(Err(e)) => (e.to_string(), 0, TokenSplit::default()),
+                None => return (payload, response, escalated_from),
+            };
+
+            if self.run_ended(run_id) {
+                return (payload, response, escalated_from);
+            }
+            let failed_payload = payload.clone();
+            if retries_left > 0 {
+                // A retry is another call on the agent's own model and counts against its quota
+                let quota = agent.map_or(Ok(()), |agent| self.consume_model_quota(run_id, &agent.id, &agent.model));
+                if let Err(e) = quota {
+                    tracing::warn!("Agent {} in run {} failed ({}) and cannot be retried: {}", payload.agent_id, run_id, error, e);
+                    return (payload, response, escalated_from);
+                }
+                retries_left -= 1;
+                tracing::info!("Agent {} in run {} failed ({}); retrying", payload.agent_id, run_id, error);
+            } else {
+                let escalation = policy.escalation.as_ref().filter(|_| escalated_from.is_none());
+                let (Some(variant), Some(agent)) = (escalation, agent) else {
+                    return (payload, response, escalated_from);
+                };
+                let own_variant = agent.model.clone();
+                if let Err(e) = self.escalate_payload(run_id, &mut payload, agent, variant) {
+                    tracing::warn!("Agent {} in run {} could not escalate to {}: {}", agent.id, run_id, variant.as_str(), e);
+                    return (payload, response, escalated_from);
+                }
+                tracing::info!("Agent {} in run {} failed ({}); escalating from {} to {}", agent.id, run_id, error, own_variant.as_str(), variant.as_str());
+                escalated_from = Some(own_variant);
+                // Replays of the invocation re-send what was actually sent last
+                self.snapshot_payload(invocation_id, &payload);
+            }
+
+            let attempt = AgentInvocation {
+                id: Uuid::new_v4().to_string(),
+                agent_id: failed_payload.agent_id.clone(),
+                model_variant: failed_payload.model_variant(&self.model_registry),
+                thought_signature: None,
+                tools_used: failed_payload.tools.clone(),
+                tokens_used,
+                latency_ms: attempt_start.elapsed().as_millis() as u64,
+                status: InvocationStatus::Failed,
+                started_at,
+                timestamp: Utc::now().to_rfc3339(),
+                artifact_id: None,
+                error_message: Some(format!("Attempt failed, retried: {}", error)),
+                replay_of: None,
+                generation: failed_payload.generation(),
+                token_split,
+                escalated_from: None,
+            };
+            if self.record_attempt(run_id, attempt, None, true).await.is_err() {
+                return (payload, response, escalated_from);
+            }
+            let (terminal, escalated_workflow) = match self.runtime_states.get(run_id) {
+                Some(state) => (state.status.is_terminal(), (escalated_from.is_some() && !state.simulation).then(|| state.workflow_id.clone())),
+                None => (true, None),
+            };
+            // Outside the state lock: the stats table has locks of its own
+            if let Some(workflow_id) = escalated_workflow {
+                self.agent_stats.record_escalation(&workflow_id, &failed_payload.agent_id);
+            }
+            // The failed attempt may have crossed the token budget
+            if terminal {
+                return (payload, response, escalated_from);
+            }
+        }
+    }
+
+    /// Why a completed call's output can't be accepted as is: a semantic null, a violated
+    /// tool protocol or output that fails the agent's output_schema. None when it passes.
+    fn post_flight_problem(agent_id: &str, agent: Option<&AgentNodeConfig>, res: &RemoteAgentResponse) -> Option<String> {
+        let text = res.output.as_ref()
+            .and_then(|o| o.get("result"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+
+        // 1. Analyze Signals
+        if text.contains("[STATUS: NULL]") {
+            return Some(format!("Agent '{}' reported a Semantic Null (found no data). Verification required.", agent_id));
+        }
+        let is_bypassed = text.contains("[BYPASS:");
+
+        // 2. Check Tool Evidence (Robust Check)
+        // We check the explicit list from Python first, fall back to text scan
+        let used = |tool: &str| res.executed_tools.iter().any(|t| t == tool)
+            || text.contains(tool)
+            || text.contains(&format!("Tool '{}' Result", tool));
+
+        // 3. Protocol Validation Logic
+        if !is_bypassed {
+            if agent_id.starts_with("research_") && !used("web_search") {
+                return Some("Protocol Violation: 'research_' agent did not use web_search (Hallucination Risk).".to_string());
+            }
+            // write_file is a valid output action for analysts
+            if (agent_id.starts_with("analyze_") || agent_id.starts_with("coder_")) && !used("execute_python") && !used("write_file") {
+                return Some("Protocol Violation: 'analyze_'/'coder_' agent did not use execute_python or write_file (Integrity Risk).".to_string());
+            }
+        }
+
+        // 4. Output contract
+        let violations = match (agent, res.success) {
+            (Some(agent), true) => agent.output_schema_violations(res.output.as_ref().unwrap_or(&serde_json::Value::Null)),
+            _ => Vec::new(),
+        };
+        (!violations.is_empty()).then(|| format!("Output of '{}' does not match its output_schema: {}", agent_id, violations.join("; ")))
+    }
+
+    /// Count one invocation of `model` against the run's client policy (simulations are free)
+    fn consume_model_quota(&self, run_id: &str, agent_id: &str, model: &ModelVariant) -> Result<(), RuntimeError> {
+        let (client_id, simulation) = self.runtime_states.get(run_id)
+            .map(|s| (s.client_id.clone(), s.simulation))
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        if simulation {
+            return Ok(());
+        }
+        let canonical = self.canonical_model(model);
+        self.client_policies.consume(&client_id, &canonical).map_err(|violation| match violation {
+            PolicyViolation::NotAllowed => RuntimeError::ModelNotAllowed { agent_id: agent_id.to_string(), model: canonical.as_str().to_string() },
+            PolicyViolation::QuotaExceeded { limit } => RuntimeError::ModelQuotaExceeded { model: canonical.as_str().to_string(), limit },
+        })
+    }
+
+    /// Point a prepared payload at `variant` for an escalated attempt. Counts against the
+    /// client's policy for that variant like any other invocation.
+    fn escalate_payload(&self, run_id: &str, payload: &mut InvocationPayload, agent: &AgentNodeConfig, variant: &ModelVariant) -> Result<(), RuntimeError> {
+        let mapping = self.model_registry.resolve(variant).map_err(RuntimeError::InvalidConfig)?;
+        self.consume_model_quota(run_id, &agent.id, variant)?;
+
+        payload.thinking_level = Self::thinking_level_for(&mapping, agent);
+        if agent.generation.max_output_tokens.is_none() {
+            payload.max_output_tokens = mapping.max_output_tokens;
+        }
+        payload.model = mapping.api_model_name;
+        payload.variant = mapping.variant;
+        Ok(())
+    }
+
+    /// Completed, failed or aborted (see cancel_inflight_invocations)
+    fn run_ended(&self, run_id: &str) -> bool {
+        self.runtime_states.get(run_id).is_some_and(|s| s.status.is_terminal())
+    }
+
+    /// Abort every remote call still in flight for a run. Called on every terminal transition.
+    fn cancel_inflight_invocations(&self, run_id: &str) {
+        self.inflight_invocations.retain(|_, (owner, handle)| {
+            if owner == run_id {
+                handle.abort();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    async fn send_invocation(client: reqwest::Client, payload: InvocationPayload) -> Result<RemoteAgentResponse, reqwest::Error> {
+        // Resolve Agent Host from Env or Default
+        let host = env::var("AGENT_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let port = env::var("AGENT_PORT").unwrap_or_else(|_| "8000".to_string());
+        let scheme = "http"; // Agent service is only reachable on the internal network
+        
+        let url = format!("{}://{}:{}/invoke", scheme, host, port);
+
+        tracing::debug!("Sending invocation request to: {}", url);
+
+        let response = client
+            .post(&url)
+            .json(&payload)
+            .send()
+            .await?;
+
+        response.json::<RemoteAgentResponse>().await
+    }
+
+    /// Store agent output to Redis with TTL
+    async fn store_artifact(
+        &self,
+        run_id: &str,
+        agent_id: &str,
+        output: &serde_json::Value,
+    ) -> Option<String> {
+        let artifact_id = format!("run:{}:agent:{}:output", run_id, agent_id);
+
+        let json_str = match serde_json::to_string(output) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!("Failed to serialize artifact for {}: {}", agent_id, e);
+                return None;
+            }
+        };
+
+        if let Some(client) = &self.redis_client {
+            match client.get_async_connection().await {
+                Ok(mut con) => {
+                    match con.set_ex::<_, _, ()>(&artifact_id, json_str, 3600).await {
+                        Ok(_) => {
+                            tracing::debug!("Stored artifact: {}", artifact_id);
+                            return Some(artifact_id);
+                        }
+                        Err(e) => {
+                            tracing::error!("Failed to write artifact to Redis: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Failed to get Redis connection: {}", e);
+                }
+            }
+        } else {
+            tracing::debug!("No Redis client available, keeping artifact in memory");
+            self.local_outputs.insert(artifact_id.clone(), output.clone());
+            return Some(artifact_id);
+        }
+
+        None
+    }
+
+    /// Stored output of one agent (Redis, or the in-memory fallback without Redis)
+    pub async fn get_agent_output(&self, run_id: &str, agent_id: &str) -> Result<Option<serde_json::Value>, RuntimeError> {
+        let key = format!("run:{}:agent:{}:output", run_id, agent_id);
+        let Some(client) = &self.redis_client else {
+            return Ok(self.local_outputs.get(&key).map(|v| v.clone()));
+        };
+
+        let mut con = client.get_async_connection().await
+            .map_err(|e| RuntimeError::Persistence(e.to_string()))?;
+        let data: Option<String> = con.get(&key).await
+            .map_err(|e| RuntimeError::Persistence(e.to_string()))?;
+        Ok(data.and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    /// Outputs of completed agents. Unless `partial`, only available once the run is over.
+    pub async fn get_run_result(&self, run_id: &str, partial: bool) -> Result<RunResult, RuntimeError> {
+        let (status, completed, finished_or_failed) = {
+            let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            let done: HashSet<String> = state.completed_agents.iter()
+                .cloned()
+                .chain(state.failed_agents.iter().map(|f| f.agent_id.clone()))
+                .collect();
+            (state.status.clone(), state.completed_agents.clone(), done)
+        };
+
+        let in_progress = !status.is_terminal();
+        if in_progress && !partial {
+            return Err(RuntimeError::RunInProgress(run_id.to_string()));
+        }
+
+        let mut pending_agents: Vec<String> = self.dag_store.get(run_id)
+            .map(|dag| dag.export_nodes())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|a| !finished_or_failed.contains(a))
+            .collect();
+        pending_agents.sort();
+
+        let mut outputs = HashMap::new();
+        for agent_id in completed {
+            if let Some(output) = self.get_agent_output(run_id, &agent_id).await? {
+                outputs.insert(agent_id, output);
+            }
+        }
+
+        Ok(RunResult {
+            run_id: run_id.to_string(),
+            status,
+            partial: in_progress,
+            outputs,
+            pending_agents: if in_progress { pending_agents } else { Vec::new() },
+        })
+    }
+
+    /// Per-agent execution intervals and achieved parallelism, from invocation timings
+    pub fn execution_trace(&self, run_id: &str) -> Result<ExecutionTrace, RuntimeError> {
+        let state = self.runtime_states.get(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let dag = self.dag_store.get(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        Ok(execution_trace::build(&state, &dag))
+    }
+
+    /// Typed timeline entries of the events after `after_seq`, in log order
+    pub fn timeline(&self, run_id: &str, client_id: &str, after_seq: u64, limit: usize) -> Result<TimelinePage, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        let state = self.get_state(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        Ok(timeline::build(&self.get_events(run_id), &state, after_seq, limit))
+    }
+
+    /// Every artifact collection of the client, enriched with what the kernel still knows about
+    /// each run. Soonest-expiring first when filtering on expiry, newest first otherwise.
+    pub async fn client_artifacts(&self, client_id: &str, filter: &fs_manager::ArtifactListFilter) -> Result<Vec<fs_manager::ArtifactRunSummary>, RuntimeError> {
+        let dir = fs_manager::WorkspaceInitializer::client_artifacts_dir(client_id);
+        // Walks and parses every run's metadata.json: blocking fs work, kept off the async workers
+        let collections = tokio::task::spawn_blocking(move || fs_manager::WorkspaceInitializer::artifact_collections(&dir))
+            .await
+            .map_err(std::io::Error::other)??;
+        let now = Utc::now();
+        let mut summaries: Vec<fs_manager::ArtifactRunSummary> = collections
+            .iter()
+            .filter_map(|metadata| filter.summarize(metadata, now))
+            .collect();
+
+        for summary in &mut summaries {
+            if let Some(state) = self.runtime_states.get(&summary.run_id).filter(|s| s.client_id == client_id) {
+                if let Some(status) = serde_json::to_value(&state.status).ok().and_then(|v| v.as_str().map(String::from)) {
+                    summary.status = status;
+                }
+                summary.tags = costs::run_tags(&state.metadata).into_iter().collect();
+            }
+            summary.workflow_name = self.workflows.get(&summary.workflow_id).map(|w| w.name.clone());
+        }
+
+        if filter.expiring_within_days.is_some() {
+            summaries.sort_by(|a, b| a.expires_at.cmp(&b.expires_at));
+        } else {
+            summaries.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        }
+        Ok(summaries)
+    }
+
+    /// Per-agent and per-model cost of a run so far, plus its projected total
+    pub fn cost_breakdown(&self, run_id: &str) -> Result<costs::CostBreakdown, RuntimeError> {
+        let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let models: HashMap<String, ModelVariant> = self.workflows.get(&state.workflow_id)
+            .map(|w| w.agents.iter().map(|a| (a.id.clone(), a.model.clone())).collect())
+            .unwrap_or_default();
+        Ok(costs::breakdown(&state, &models, Utc::now()))
+    }
+
+    /// Per-stage rollup in workflow declaration order, unstaged agents last. Delegated agents
+    /// have no stage. Completed and skipped agents both count towards a finished stage.
+    pub fn stage_report(&self, run_id: &str) -> Result<Vec<StageSummary>, RuntimeError> {
+        let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let workflow = self.workflows.get(&state.workflow_id);
+        let declared = workflow.iter().flat_map(|w| w.agents.iter());
+        let stage_of: HashMap<&str, &str> = declared.clone()
+            .filter_map(|a| Some((a.id.as_str(), a.stage.as_deref()?)))
+            .collect();
+
+        let mut agents: Vec<String> = self.dag_store.get(run_id)
+            .map(|dag| dag.export_nodes())
+            .unwrap_or_else(|| declared.clone().map(|a| a.id.clone()).collect());
+        agents.sort();
+
+        let mut order: Vec<Option<String>> = Vec::new();
+        for stage in declared.map(|a| a.stage.clone()).chain([None]) {
+            if !order.contains(&stage) {
+                order.push(stage);
+            }
+        }
+
+        Ok(order.into_iter().filter_map(|stage| {
+            let members: Vec<String> = agents.iter()
+                .filter(|a| stage_of.get(a.as_str()).copied() == stage.as_deref())
+                .cloned()
+                .collect();
+            if members.is_empty() {
+                return None;
+            }
+            let count = |pred: &dyn Fn(&String) -> bool| members.iter().filter(|a| pred(a)).count();
+            let completed = count(&|a| state.completed_agents.contains(a));
+            let failed = count(&|a| state.has_failed(a));
+            let running = count(&|a| state.active_agents.contains(a));
+            let skipped = count(&|a| state.skipped_agents.contains(a));
+            let stage_invocations = || state.invocations.iter().filter(|i| members.contains(&i.agent_id));
+
+            let status = if failed > 0 {
+                StageStatus::Failed
+            } else if completed + skipped == members.len() {
+                StageStatus::Completed
+            } else if running + completed > 0 {
+                StageStatus::Running
+            } else {
+                StageStatus::Pending
+            };
+            Some(StageSummary {
+                stage,
+                status,
+                pending: members.len().saturating_sub(completed + failed + running + skipped),
+                invocations: stage_invocations().count(),
+                tokens_used: stage_invocations().map(|i| i.tokens_used).sum(),
+                agents: members,
+                completed,
+                failed,
+                running,
+                skipped,
+            })
+        }).collect())
+    }
+
+    /// Get current runtime state
+    pub fn get_state(&self, run_id: &str) -> Option<RuntimeState> {
+        self.runtime_states.get(run_id).map(|r| (*r).clone())
+    }
+
+    /// Current-month usage for a client. Counters come from incremental rollups;
+    /// only the run store and the client's storage directories are scanned.
+    pub fn usage_report(&self, client_id: &str) -> UsageReport {
+        let runs_active = self.runtime_states.iter()
+            .filter(|s| s.client_id == client_id && !s.status.is_terminal())
+            .count();
+        let storage_bytes = fs_manager::WorkspaceInitializer::client_storage_bytes(client_id);
+        self.usage.report(client_id, runs_active, storage_bytes)
+    }
+
+    /// Track a long-lived task (event bus consumer, janitor, ...) for /admin/system
+    pub fn register_background_task(&self, name: &str, handle: &JoinHandle<()>) {
+        self.background_tasks.insert(name.to_string(), handle.abort_handle());
+    }
+
+    // === RUN GARBAGE COLLECTION ===
+
+    /// Drop a finished run and everything held for it in memory, plus its persisted state.
+    /// Returns how many thought signatures were freed. Audit logs and artifacts are kept.
+    pub fn delete_run(&self, run_id: &str) -> Result<usize, RuntimeError> {
+        let terminal = self.runtime_states.get(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?
+            .status.is_terminal();
+        if !terminal {
+            return Err(RuntimeError::RunInProgress(run_id.to_string()));
+        }
+
+        self.runtime_states.remove(run_id);
+        let pinned_suffix = pinned_workflow_id("", run_id);
+        self.workflows.retain(|id, _| !id.ends_with(&pinned_suffix));
+        let freed_signatures = self.thought_signatures.remove(run_id).map_or(0, |(_, store)| store.signatures.len());
+        self.dag_store.remove(run_id);
+        self.cache_resources.remove(run_id);
+        self.event_log.remove(run_id);
+        self.aborted_runs.remove(run_id);
+        self.triggered_patterns.remove(run_id);
+        self.budget_thresholds_fired.remove(run_id);
+        self.agent_logs.remove(run_id);
+        self.state_snapshots.remove(run_id);
+        self.payload_snapshots.remove(run_id);
+        self.inflight_invocations.retain(|_, (owner, _)| owner != run_id);
+        self.release_run_in_flight(run_id);
+        let output_prefix = format!("run:{}:", run_id);
+        self.local_outputs.retain(|key, _| !key.starts_with(&output_prefix));
+        self.blackboard.clear_run(run_id);
+        self.event_limiter.forget_run(run_id);
+        self.search_index.remove_run(run_id);
+
+        if let Some(client) = self.redis_client.clone() {
+            let state_key = format!("run:{}:state", run_id);
+            tokio::spawn(async move {
+                let result: redis::RedisResult<()> = async {
+                    let mut con = client.get_async_connection().await?;
+                    con.del(&state_key).await
+                }.await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to delete {}: {}", state_key, e);
+                }
+            });
+        }
+        tracing::info!("Deleted run {} ({} thought signatures freed)", run_id, freed_signatures);
+        Ok(freed_signatures)
+    }
+
+    /// Delete finished runs older than `max_age_minutes` (by end time, else start time) whose
+    /// status is in the filter (every terminal status when empty). Shared by POST /admin/gc and
+    /// the run janitor; a dry run reports what would be deleted and touches nothing.
+    pub fn collect_garbage(&self, request: &GcRequest) -> Result<GcResult, RuntimeError> {
+        if let Some(status) = request.status_filter.iter().find(|s| !s.is_terminal()) {
+            return Err(RuntimeError::InvalidConfig(format!("status_filter may only name finished statuses, got {:?}", status)));
+        }
+        let cutoff = i64::try_from(request.max_age_minutes).ok()
+            .and_then(chrono::Duration::try_minutes)
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+            .ok_or_else(|| RuntimeError::InvalidConfig(format!("max_age_minutes {} is out of range", request.max_age_minutes)))?;
+        let mut candidates: Vec<(String, usize)> = self.runtime_states.iter()
+            .filter(|s| s.status.is_terminal() && (request.status_filter.is_empty() || request.status_filter.contains(&s.status)))
+            .filter(|s| chrono::DateTime::parse_from_rfc3339(s.end_time.as_deref().unwrap_or(&s.start_time))
+                .is_ok_and(|t| t.with_timezone(&Utc) < cutoff))
+            .map(|s| (s.run_id.clone(), self.thought_signatures.get(&s.run_id).map_or(0, |store| store.signatures.len())))
+            .collect();
+        candidates.sort();
+
+        let mut result = GcResult { dry_run: request.dry_run, ..GcResult::default() };
+        for (run_id, signatures) in candidates {
+            let freed = if request.dry_run { Ok(signatures) } else { self.delete_run(&run_id) };
+            match freed {
+                Ok(freed) => {
+                    result.deleted_run_count += 1;
+                    result.freed_signatures += freed;
+                    result.run_ids.push(run_id);
+                }
+                Err(e) => result.errors.push(format!("{}: {}", run_id, e)),
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn system_status(&self) -> SystemStatus {
+        let map_entries = [
+            ("workflows", self.workflows.len()),
+            ("runtime_states", self.runtime_states.len()),
+            ("thought_signatures", self.thought_signatures.len()),
+            ("dag_store", self.dag_store.len()),
+            ("cache_resources", self.cache_resources.len()),
+            ("payload_snapshots", self.payload_snapshots.iter().map(|s| s.len()).sum()),
+            ("event_log", self.event_log.len()),
+            ("halted_clients", self.halted_clients.len()),
+            ("aborted_runs", self.aborted_runs.len()),
+            ("triggered_patterns", self.triggered_patterns.len()),
+            ("agent_logs", self.agent_logs.len()),
+            ("inflight_invocations", self.inflight_invocations.len()),
+            ("agents_in_flight", self.agents_in_flight.len()),
+            ("local_outputs", self.local_outputs.len()),
+            ("state_snapshots", self.state_snapshots.len()),
+            ("blackboard", self.blackboard.run_count()),
+        ].into_iter().collect();
+
+        SystemStatus {
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            memory_rss_mb: observability::memory_rss_mb().unwrap_or(0),
+            active_runs: self.runtime_states.iter().filter(|s| !s.status.is_terminal()).count(),
+            total_registered_runs: self.runtime_states.len(),
+            total_pattern_count: self.pattern_registry.pattern_count(),
+            background_tasks: self.background_tasks.iter().filter(|t| !t.is_finished()).count(),
+            event_subscribers: self.event_subscriber_count(),
+            map_entries,
+        }
+    }
+
+    /// Replace the global log filter, e.g. "raro_kernel=debug,tower_http=warn".
+    /// The expression is parsed first, so a typo never leaves the process without a filter.
+    pub fn set_log_filter(&self, filter: &str) -> Result<(), RuntimeError> {
+        let handle = self.log_filter.as_ref()
+            .ok_or_else(|| RuntimeError::NotConfigured("log filter reloading".to_string()))?;
+        if filter.trim().is_empty() {
+            return Err(RuntimeError::InvalidLogFilter("filter is empty".to_string()));
+        }
+        let parsed = EnvFilter::try_new(filter).map_err(|e| RuntimeError::InvalidLogFilter(e.to_string()))?;
+        handle.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+            .reload(parsed)
+            .map_err(|e| RuntimeError::NotConfigured(format!("log filter reloading: {}", e)))?;
+        tracing::warn!("Log filter changed to '{}'", filter);
+        Ok(())
+    }
+
+    /// Per-model invocation counts, tokens, cost and latency across all runs held in memory
+    pub fn model_usage(&self) -> HashMap<ModelVariant, ModelUsage> {
+        let states: Vec<RuntimeState> = self.runtime_states.iter()
+            .filter(|s| !s.simulation)
+            .map(|s| s.value().clone())
+            .collect();
+        observability::model_usage(states.iter().flat_map(|s| s.invocations.iter()))
+    }
+
+    /// Trend across the client's finished runs of a workflow (simulated runs excluded)
+    pub fn metrics_trend(&self, client_id: &str, workflow_id: &str, window: chrono::Duration) -> MetricsTrend {
+        let runs: Vec<RuntimeState> = self.runtime_states.iter()
+            .filter(|s| s.client_id == client_id && s.workflow_id == workflow_id && s.status.is_terminal() && !s.simulation)
+            .map(|s| s.value().clone())
+            .collect();
+        Metrics::trend(window, &runs)
+    }
+
+    /// The client's runs, newest first; with `tag_key`, only those whose metadata has that key
+    pub fn list_runs(&self, client_id: &str, tag_key: Option<&str>) -> Vec<RunSummary> {
+        let mut runs: Vec<RunSummary> = self.runtime_states.iter()
+            .filter(|s| s.client_id == client_id)
+            .filter(|s| tag_key.is_none_or(|key| s.metadata.contains_key(key)))
+            .map(|s| RunSummary::from(&*s))
+            .collect();
+        runs.sort_by(|a, b| b.start_time.cmp(&a.start_time));
+        runs
+    }
+
+    /// Record an agent invocation (Async + Persistent)
+    pub async fn record_invocation(
+        &self,
+        run_id: &str,
+        invocation: AgentInvocation,
+        trace_context: Option<SpanContext>,
+    ) -> Result<(), RuntimeError> {
+        let invocation_id = invocation.id.clone();
+        let recorded = self.record_attempt(run_id, invocation, trace_context, false).await;
+        if recorded.is_err() {
+            self.forget_payload(run_id, &invocation_id);
+        }
+        recorded
+    }
+
+    /// record_invocation for one attempt of an agent. A `retried` attempt is accounted like any
+    /// other (tokens, usage, costs, stats, budget) but leaves the agent's status alone.
+    async fn record_attempt(
+        &self,
+        run_id: &str,
+        mut invocation: AgentInvocation,
+        trace_context: Option<SpanContext>,
+        retried: bool,
+    ) -> Result<(), RuntimeError> {
+        invocation.reconcile_tokens();
+        let span = match trace_context {
+            Some(parent) => tracing::info_span!(parent: parent, "agent.record", run_id = %run_id, agent_id = %invocation.agent_id, invocation_id = %invocation.id),
+            None => tracing::info_span!("agent.record", run_id = %run_id, agent_id = %invocation.agent_id, invocation_id = %invocation.id),
+        };
+
+        let stats_workflow = {
+            let _enter = span.enter();
+            let mut state = self
+                .runtime_states
+                .get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            if state.status == RuntimeStatus::Preparing {
+                return Err(RuntimeError::RunNotLaunched(run_id.to_string()));
+            }
+            // Checked under the same lock the skip is applied with, so two skips (or a skip and
+            // the agent finishing) can't both pass
+            if invocation.status == InvocationStatus::Skipped && !retried {
+                Self::ensure_skippable(&state, &invocation.agent_id)?;
+            }
+
+            if retried {
+                Self::store_invocation(&mut state, &invocation);
+            } else {
+                Self::apply_invocation(&mut state, &invocation);
+                self.release_in_flight(run_id, &invocation);
+            }
+            if !state.simulation {
+                self.usage.record_invocations(&state.client_id, 1, invocation.tokens_used as u64);
+                self.costs.record_invocation(&state.client_id, &costs::run_tags(&state.metadata), &invocation);
+            }
+
+            tracing::debug!(status = ?invocation.status, tokens = invocation.tokens_used, "Invocation recorded");
+            (!state.simulation).then(|| state.workflow_id.clone())
+        };
+        // Outside the state lock: the stats table has locks of its own
+        if let Some(workflow_id) = stats_workflow {
+            self.agent_stats.record(&workflow_id, std::slice::from_ref(&invocation));
+        }
+
+        if !retried {
+            if invocation.status == InvocationStatus::Failed {
+                self.block_downstream(run_id, &invocation.agent_id);
+            }
+            if let Some(url) = self.workflow_hooks(run_id).after_agent {
+                self.hook_client.notify("after_agent", &url, &invocation);
+            }
+        }
+
+        self.persist_state(run_id).instrument(span).await;
+        self.check_token_budget(run_id).await;
+
+        Ok(())
+    }
+
+    /// Record a whole wave of invocations under a single state lock and a single persist.
+    /// Stops at the invocation that crosses the hard budget limit; later ones are not applied.
+    pub async fn record_invocations(&self, run_id: &str, invocations: Vec<AgentInvocation>) -> Result<BatchRecordResult, RuntimeError> {
+        let span = tracing::info_span!("agent.record_batch", run_id = %run_id, count = invocations.len());
+
+        let workflow_id = self.runtime_states.get(run_id)
+            .map(|s| s.workflow_id.clone())
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let fail_at = self.budget_limits(&workflow_id).map(|(_, _, fail_at)| fail_at);
+
+        let after_agent = self.workflow_hooks(run_id).after_agent;
+        let mut result = BatchRecordResult::default();
+        let mut failed_agents = Vec::new();
+        let mut applied = Vec::new();
+        let simulation = {
+            let _enter = span.enter();
+            let mut state = self
+                .runtime_states
+                .get_mut(run_id)
+                .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            if state.status == RuntimeStatus::Preparing {
+                return Err(RuntimeError::RunNotLaunched(run_id.to_string()));
+            }
+            // An orchestrator replaying a batch after the run ended must not reopen its accounting
+            if state.status.is_terminal() {
+                return Err(RuntimeError::RunNotRunning(run_id.to_string()));
+            }
+
+            let tags = costs::run_tags(&state.metadata);
+            let mut pending = invocations.into_iter();
+            let mut applied_tokens = 0;
+            for mut invocation in pending.by_ref() {
+                invocation.reconcile_tokens();
+                Self::apply_invocation(&mut state, &invocation);
+                self.release_in_flight(run_id, &invocation);
+                applied_tokens += invocation.tokens_used as u64;
+                if !state.simulation {
+                    self.costs.record_invocation(&state.client_id, &tags, &invocation);
+                }
+                if invocation.status == InvocationStatus::Failed {
+                    failed_agents.push(invocation.agent_id.clone());
+                }
+                result.recorded.push(invocation.id.clone());
+                applied.push(invocation.clone());
+                if let Some(url) = &after_agent {
+                    self.hook_client.notify("after_agent", url, &invocation);
+                }
+
+                if fail_at.map(|limit| state.total_tokens_used as f64 >= limit).unwrap_or(false) {
+                    tracing::warn!("Invocation {} crossed the token budget mid-batch", invocation.id);
+                    result.budget_exceeded_at = Some(invocation.id);
+                    break;
+                }
+            }
+            result.skipped = pending.map(|i| i.id).collect();
+            if !state.simulation {
+                self.usage.record_invocations(&state.client_id, result.recorded.len() as u64, applied_tokens);
+            }
+            state.simulation
+        };
+        if !simulation {
+            self.agent_stats.record(&workflow_id, &applied);
+        }
+
+        for failed in &failed_agents {
+            self.block_downstream(run_id, failed);
+        }
+
+        self.persist_state(run_id).instrument(span).await;
+        self.check_token_budget(run_id).await;
+
+        Ok(result)
+    }
+
+    /// State transition for one invocation. Callers hold the state lock.
+    fn apply_invocation(state: &mut RuntimeState, invocation: &AgentInvocation) {
+        // A call still in flight when its agent was skipped is kept for the record only
+        let was_skipped = state.invocations.iter()
+            .any(|i| i.agent_id == invocation.agent_id && i.status == InvocationStatus::Skipped);
+        Self::store_invocation(state, invocation);
+        if was_skipped {
+            return;
+        }
+
+        match invocation.status {
+            InvocationStatus::Running if !state.active_agents.contains(&invocation.agent_id) => {
+                state.mark_active(&invocation.agent_id, &invocation.timestamp);
+            }
+            InvocationStatus::Success | InvocationStatus::Skipped => {
+                state.active_agents.retain(|a| a != &invocation.agent_id);
+                state.completed_agents.push(invocation.agent_id.clone());
+            }
+            InvocationStatus::Failed => {
+                let reason = invocation.error_message.as_deref().unwrap_or("Invocation failed");
+                state.record_failure(&invocation.agent_id, FailureCode::AgentError, reason, &invocation.timestamp);
+            }
+            _ => {}
+        }
+    }
+
+    /// Append an invocation to the run's log and token total, without any status transition
+    fn store_invocation(state: &mut RuntimeState, invocation: &AgentInvocation) {
+        let mut stored = invocation.clone();
+        if stored.started_at.is_empty() {
+            // Reporters that leave it out: the call ran for latency_ms up to timestamp
+            stored.started_at = chrono::DateTime::parse_from_rfc3339(&stored.timestamp)
+                .map(|end| (end - chrono::Duration::milliseconds(stored.latency_ms as i64)).to_rfc3339())
+                .unwrap_or_else(|_| stored.timestamp.clone());
+        }
+        state.invocations.push(stored);
+        state.total_tokens_used += invocation.tokens_used;
+        state.stalled = false;
+    }
+
+    /// (budget, warning threshold, hard limit) in tokens. None when unlimited (zero budget).
+    fn budget_limits(&self, workflow_id: &str) -> Option<(f64, f64, f64)> {
+        let w = self.workflows.get(workflow_id)?;
+        let budget = w.max_token_budget as f64;
+        if budget <= 0.0 {
+            return None;
+        }
+        Some((budget, budget * w.budget_warning_threshold, budget * w.budget_hard_limit))
+    }
+
+    /// Soft/hard budget enforcement. Crossing the warning threshold flags the run and notifies
+    /// the UI once; only crossing the hard limit terminates the run.
+    async fn check_token_budget(&self, run_id: &str) {
+        let (used, workflow_id, already_warned, status) = match self.runtime_states.get(run_id) {
+            Some(s) => (s.total_tokens_used, s.workflow_id.clone(), s.budget_warning, s.status.clone()),
+            None => return,
+        };
+
+        if status.is_terminal() {
+            return;
+        }
+
+        // A zero budget means "unlimited"
+        let (budget, warn_at, fail_at) = match self.budget_limits(&workflow_id) {
+            Some(limits) => limits,
+            None => return,
+        };
+
+        let used_f = used as f64;
+        self.announce_budget_thresholds(run_id, &workflow_id, used, budget as usize);
+
+        if used_f >= fail_at {
+            let reason = format!("Token budget exhausted: {} of {} tokens used", used, budget as usize);
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::SystemIntervention,
+                None,
+                serde_json::json!({ "action": "budget_exceeded", "reason": reason, "tokens_used": used }),
+            ));
+            self.fail_run(run_id, "SYSTEM", FailureCode::BudgetExceeded, &reason).await;
+            self.trigger_remote_cleanup(run_id).await;
+        } else if used_f >= warn_at && !already_warned {
+            if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+                state.budget_warning = true;
+            }
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::SystemIntervention,
+                None,
+                serde_json::json!({
+                    "action": "budget_warning",
+                    "reason": format!("Token usage at {:.0}% of budget", used_f / budget * 100.0),
+                    "tokens_used": used,
+                    "max_token_budget": budget as usize
+                }),
+            ));
+            self.persist_state(run_id).await;
+            tracing::warn!("Run {} crossed budget warning threshold ({} tokens)", run_id, used);
+        }
+    }
+
+    /// One TokenBudgetWarning per configured threshold, the first time usage reaches it.
+    /// Thresholds jumped over by a single invocation each still get their event, lowest first.
+    fn announce_budget_thresholds(&self, run_id: &str, workflow_id: &str, used: usize, budget: usize) {
+        let mut thresholds = self.workflows.get(workflow_id)
+            .map(|w| w.budget_warning_thresholds.clone())
+            .unwrap_or_default();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+
+        let pct = used as f32 / budget as f32 * 100.0;
+        let crossed: Vec<u8> = {
+            let mut fired = self.budget_thresholds_fired.entry(run_id.to_string()).or_default();
+            let crossed: Vec<u8> = thresholds.into_iter().filter(|t| *t > *fired && pct >= *t as f32).collect();
+            if let Some(highest) = crossed.last() {
+                *fired = *highest;
+            }
+            crossed
+        };
+
+        for threshold in crossed {
+            tracing::info!("Run {} reached {}% of its token budget ({} of {} tokens)", run_id, threshold, used, budget);
+            self.emit_event(RuntimeEvent::new(
+                run_id,
+                EventType::TokenBudgetWarning,
+                None,
+                serde_json::json!({ "used": used, "budget": budget, "pct": pct, "threshold": threshold }),
+            ));
+        }
+    }
+
+    /// Store or retrieve thought signature.
+    /// Storage is validated (size/format) and large values are compressed; reads are always raw.
+    pub fn set_thought_signature(&self, run_id: &str, agent_id: &str, signature: String) -> Result<(), RuntimeError> {
+        if !self.store_signature(run_id, agent_id, signature)? {
+            return Ok(());
+        }
+
+        let mut store = self
+            .thought_signatures
+            .get_mut(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let seq = store.history.last().map(|w| w.seq + 1).unwrap_or(1);
+        let event = RuntimeEvent::new(
+            run_id,
+            EventType::SignatureUpdated,
+            Some(agent_id.to_string()),
+            serde_json::json!({ "seq": seq, "agent_id": agent_id }),
+        );
+        store.history.push(SignatureWrite {
+            seq,
+            agent_id: agent_id.to_string(),
+            timestamp: event.timestamp.clone(),
+            event_id: event.id.clone(),
+        });
+        drop(store);
+
+        self.emit_event(event);
+        Ok(())
+    }
+
+    /// Validate, encode and store without recording a history entry. Ok(false) when the
+    /// policy dropped the signature for its size; the agent's previous one is removed with it.
+    fn store_signature(&self, run_id: &str, agent_id: &str, signature: String) -> Result<bool, RuntimeError> {
+        let mut store = self
+            .thought_signatures
+            .get_mut(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+
+        let raw_bytes = signature.len();
+        let encoded = signatures::encode(&self.signature_policy, signature).map_err(RuntimeError::InvalidSignature)?;
+
+        store.dropped.retain(|a| a != agent_id);
+        match encoded {
+            Some(stored) => {
+                store.signatures.insert(agent_id.to_string(), stored);
+                Ok(true)
+            }
+            None => {
+                tracing::warn!("Thought signature for {}/{} is {} bytes (max {}); dropped", run_id, agent_id, raw_bytes, self.signature_policy.max_bytes);
+                store.signatures.remove(agent_id);
+                store.dropped.push(agent_id.to_string());
+                Ok(false)
+            }
+        }
+    }
+
+    /// Ordered signature writes for a run
+    pub fn get_signature_history(&self, run_id: &str) -> Option<Vec<SignatureWrite>> {
+        self.thought_signatures.get(run_id).map(|store| store.history.clone())
+    }
+
+    pub fn get_thought_signature(&self, run_id: &str, agent_id: &str) -> Option<String> {
+        self.thought_signatures
+            .get(run_id)
+            .and_then(|store| store.signatures.get(agent_id).map(|s| signatures::decode(s)))
+    }
+
+    pub fn get_all_signatures(&self, run_id: &str) -> Option<ThoughtSignatureStore> {
+        self.thought_signatures.get(run_id).map(|store| ThoughtSignatureStore {
+            signatures: store.signatures.iter().map(|(k, v)| (k.clone(), signatures::decode(v))).collect(),
+            dropped: store.dropped.clone(),
+            history: store.history.clone(),
+        })
+    }
+
+    /// Lengths and hashes per agent, without the signature content
+    pub fn get_signature_summaries(&self, run_id: &str) -> Option<HashMap<String, SignatureSummary>> {
+        self.thought_signatures.get(run_id).map(|store| {
+            store.signatures.iter()
+                .map(|(agent_id, stored)| (agent_id.clone(), signatures::summarize(stored)))
+                .collect()
+        })
+    }
+
+    /// Replace a run's signatures wholesale (checkpoint/import), applying the storage policy.
+    /// This is a restore, not propagation, so the write history starts empty.
+    fn install_signatures(&self, run_id: &str, raw: HashMap<String, String>) {
+        self.thought_signatures.insert(run_id.to_string(), ThoughtSignatureStore::default());
+        for (agent_id, signature) in raw {
+            if let Err(e) = self.store_signature(run_id, &agent_id, signature) {
+                tracing::warn!("Restored run {} left out the thought signature of {}: {}", run_id, agent_id, e);
+            }
+        }
+    }
+
+    /// Generate a contextual graph view based on agent's delegation privilege.
+    /// Includes "Optics" (specialty info) for Pending nodes to facilitate updates.
+    ///
+    /// - **detailed=true**: Returns JSON array with full topology (for orchestrators)
+    /// - **detailed=false**: Returns linear text view (for workers)
+    fn generate_graph_context(&self, run_id: &str, current_agent_id: &str, detailed: bool) -> String {
+        let state = match self.runtime_states.get(run_id) {
+            Some(s) => s,
+            None => return "Graph state unavailable.".to_string(),
+        };
+
+        let dag = match self.dag_store.get(run_id) {
+            Some(d) => d,
+            None => return "Graph topology unavailable.".to_string(),
+        };
+
+        let workflow_id = state.workflow_id.clone();
+
+        // Helper to get specialty for a node
+        let get_node_info = |node_id: &str| -> String {
+            if let Some(workflow) = self.workflows.get(&workflow_id) {
+                if let Some(agent) = workflow.agents.iter().find(|a| a.id == node_id) {
+                    return agent.prompt.chars().take(50).collect::<String>();
+                }
+            }
+            "Unknown Specialty".to_string()
+        };
+
+        if detailed {
+            let nodes: Vec<serde_json::Value> = dag.export_nodes().iter().map(|node_id| {
+                let status = if state.completed_agents.contains(node_id) { "completed" }
+                else if state.has_failed(node_id) { "failed" }
+                else if state.active_agents.contains(node_id) { "running" }
+                else { "pending" };
+
+                let specialty = if status == "pending" {
+                    get_node_info(node_id)
+                } else {
+                    "".to_string()
+                };
+
+                serde_json::json!({
+                    "id": node_id,
+                    "status": status,
+                    "is_you": node_id == current_agent_id,
+                    "dependencies": dag.get_dependencies(node_id),
+                    "specialty_preview": specialty
+                })
+            }).collect();
+
+            serde_json::to_string_pretty(&nodes).unwrap_or_default()
+        } else {
+            match dag.topological_sort() {
+                Ok(order) => {
+                    let parts: Vec<String> = order.iter().map(|node_id| {
+                        let status = if state.completed_agents.contains(node_id) { "COMPLETE" }
+                        else if state.has_failed(node_id) { "FAILED" }
+                        else if state.active_agents.contains(node_id) { "RUNNING" }
+                        else { "PENDING" };
+
+                        if node_id == current_agent_id {
+                            format!("[{}:{}(YOU)]", node_id, status)
+                        } else if status == "PENDING" {
+                            // OPTICS: Show specialty for pending nodes
+                            format!("[{}:{} ({})]", node_id, status, get_node_info(node_id))
+                        } else {
+                            format!("[{}:{}]", node_id, status)
+                        }
+                    }).collect();
+                    parts.join(" -> ")
+                },
+                Err(_) => "Cycle detected in graph view.".to_string()
+            }
+        }
+    }
+
+    /// Span covering payload preparation for one agent; its id parents the matching `agent.record` span
+    pub fn prepare_span(run_id: &str, agent_id: &str) -> tracing::Span {
+        tracing::info_span!("agent.prepare", run_id = %run_id, agent_id = %agent_id)
+    }
+
+    /// prepare_invocation_payload for a caller that dispatches the agent itself (pull mode).
+    /// Only one such invocation per agent may be outstanding: a second prepare is refused with
+    /// AgentInFlight until the first is recorded, the agent has otherwise finished, or the
+    /// first has been outstanding longer than the agent's timeout (the caller gave up on it).
+    pub async fn prepare_exclusive_invocation(&self, run_id: &str, agent_id: &str) -> Result<InvocationPayload, RuntimeError> {
+        let key = (run_id.to_string(), agent_id.to_string());
+        let timeout = self.runtime_states.get(run_id)
+            .and_then(|s| self.workflows.get(&s.workflow_id).and_then(|w| w.agent_timeout(agent_id)));
+        match self.agents_in_flight.entry(key.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(mark)
+                if !Self::in_flight_mark_expired(mark.get(), timeout) && !self.agent_finished(run_id, agent_id) => {
+                return Err(RuntimeError::AgentInFlight { run_id: run_id.to_string(), agent_id: agent_id.to_string() });
+            }
+            entry => {
+                entry.insert(Utc::now().to_rfc3339());
+            }
+        }
+
+        let payload = self.prepare_invocation_payload(run_id, agent_id).await;
+        if payload.is_err() {
+            self.agents_in_flight.remove(&key);
+        }
+        payload
+    }
+
+    /// A mark prepared longer ago than the agent's timeout; marks of agents without one never expire
+    fn in_flight_mark_expired(prepared_at: &str, timeout: Option<std::time::Duration>) -> bool {
+        let (Some(timeout), Ok(prepared_at)) = (timeout, chrono::DateTime::parse_from_rfc3339(prepared_at)) else {
+            return false;
+        };
+        chrono::Duration::from_std(timeout).is_ok_and(|timeout| Utc::now() - prepared_at.with_timezone(&Utc) > timeout)
+    }
+
+    /// Drop every in-flight mark of a run that has ended (or is being deleted)
+    fn release_run_in_flight(&self, run_id: &str) {
+        self.agents_in_flight.retain(|(owner, _), _| owner != run_id);
+    }
+
+    /// Completed, failed or skipped, so a leftover in-flight mark no longer blocks a prepare
+    fn agent_finished(&self, run_id: &str, agent_id: &str) -> bool {
+        self.runtime_states.get(run_id).is_some_and(|s| {
+            s.completed_agents.iter().any(|a| a == agent_id)
+                || s.skipped_agents.iter().any(|a| a == agent_id)
+                || s.failed_agents.iter().any(|f| f.agent_id == agent_id)
+        })
+    }
+
+    /// A Pending or Running record is progress on the same invocation; anything else ends it
+    fn release_in_flight(&self, run_id: &str, invocation: &AgentInvocation) {
+        if !matches!(invocation.status, InvocationStatus::Pending | InvocationStatus::Running) {
+            self.agents_in_flight.remove(&(run_id.to_string(), invocation.agent_id.clone()));
+        }
+    }
+
+    /// The payload an agent is dispatched with, after the workflow's before_agent hook. Every
+    /// execution mode prepares through here, so a hook veto (or an unreachable hook) fails the
+    /// run the same way whether the kernel or an external orchestrator dispatches.
+    pub async fn prepare_invocation_payload(&self, run_id: &str, agent_id: &str) -> Result<InvocationPayload, RuntimeError> {
+        let payload = self.build_invocation_payload(run_id, agent_id).await?;
+        match self.apply_before_agent_hook(run_id, payload).await {
+            Ok(payload) => Ok(payload),
+            Err((code, reason)) => {
+                let failure = self.fail_run(run_id, agent_id, code, &reason).await;
+                self.emit_agent_failed(run_id, failure);
+                Err(RuntimeError::HookRefused { agent_id: agent_id.to_string(), reason })
+            }
+        }
+    }
+
+    async fn build_invocation_payload(
+        &self,
+        run_id: &str,
+        agent_id: &str,
+    ) -> Result<InvocationPayload, RuntimeError> {
+        let state = self
+            .runtime_states
+            .get(run_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        if state.status == RuntimeStatus::Preparing {
+            return Err(RuntimeError::RunNotLaunched(run_id.to_string()));
+        }
+
+        let workflow = self
+            .workflows
+            .get(&state.workflow_id)
+            .ok_or_else(|| RuntimeError::WorkflowNotFound(state.workflow_id.clone()))?;
+
+        let agent_config = workflow
+            .agents
+            .iter()
+            .find(|a| a.id == agent_id)
+            .ok_or_else(|| RuntimeError::AgentNotFound(agent_id.to_string()))?;
+
+        let (signature_source, _) = self.signature_routing(run_id, agent_config);
+        let parent_signature = signature_source.and_then(|parent_id| self.get_thought_signature(run_id, &parent_id));
+
+        let mut context_prompt_appendix = String::new();
+        let mut input_data_map = serde_json::Map::new();
+        let mut dynamic_file_mounts: Vec<String> = Vec::new();
+
+        // (parent id, key its output is passed under). Supervisors see every finished agent of
+        // the run; other agents only dependencies whose edge passes output, keyed by alias.
+        let context_sources: Vec<(String, String)> = if agent_config.role == AgentRole::Supervisor {
+            let mut finished: Vec<String> = state.completed_agents.iter().filter(|a| *a != agent_id).cloned().collect();
+            finished.sort();
+            finished.into_iter().map(|id| (id.clone(), id)).collect()
+        } else {
+            agent_config.depends_on.iter()
+                .filter(|d| d.pass.includes_output())
+                .map(|d| (d.agent.clone(), d.output_key().to_string()))
+                .collect()
+        };
+
+        let mut parent_outputs: Vec<(String, serde_json::Value)> = Vec::new();
+        if !context_sources.is_empty() {
+            if let Some(client) = &self.redis_client {
+                match client.get_async_connection().await {
+                    Ok(mut con) => {
+                        for (parent_id, output_key) in &context_sources {
+                            let key = format!("run:{}:agent:{}:output", run_id, parent_id);
+                            
+                            let data: Option<String> = con.get(&key).await.unwrap_or(None);
+
+                            if let Some(val) = data.and_then(|json_str| serde_json::from_str::<serde_json::Value>(&json_str).ok()) {
+                                parent_outputs.push((output_key.clone(), val));
+                            }
+                        }
+                    },
+                    Err(e) => tracing::warn!("Could not connect to Redis for context fetching: {}", e),
+                }
+            } else {
+                // No Redis: store_artifact kept the outputs in memory
+                for (parent_id, output_key) in &context_sources {
+                    let key = format!("run:{}:agent:{}:output", run_id, parent_id);
+                    if let Some(val) = self.local_outputs.get(&key) {
+                        parent_outputs.push((output_key.clone(), val.clone()));
+                    }
+                }
+            }
+        }
+
+        // A parent skipped a moment ago may not have its placeholder output stored yet
+        for (parent_id, output_key) in &context_sources {
+            if parent_outputs.iter().any(|(key, _)| key == output_key) {
+                continue;
+            }
+            let skip = state.invocations.iter().rev()
+                .find(|i| &i.agent_id == parent_id && i.status == InvocationStatus::Skipped);
+            if let Some(skip) = skip {
+                parent_outputs.push((output_key.clone(), Self::skipped_output(skip.error_message.as_deref().unwrap_or_default())));
+            }
+        }
+
+        for (parent_id, val) in parent_outputs {
+            let content = val.get("result")
+                .and_then(|v| v.as_str())
+                .or_else(|| val.get("output").and_then(|v| v.as_str()))
+                .unwrap_or("No text output");
+
+            context_prompt_appendix.push_str(&format!("\n\n=== CONTEXT FROM AGENT {} ===\n{}\n", parent_id, content));
+
+            if let Some(files_array) = val.get("files_generated").and_then(|v| v.as_array()) {
+                for file_val in files_array {
+                    if let Some(filename) = file_val.as_str() {
+                        let mount_path = format!("/app/storage/sessions/{}/output/{}", run_id, filename);
+                        if !dynamic_file_mounts.contains(&mount_path) {
+                            dynamic_file_mounts.push(mount_path);
+                        }
+                    }
+                }
+            }
+            input_data_map.insert(parent_id, val);
+        }
+
+        // === PRE-FLIGHT: CONTEXT DROUGHT PREVENTION ===
+        // Check if upstream agents provided any usable data
+        let has_null_signal = context_prompt_appendix.contains("[STATUS: NULL]");
+        let has_files = !dynamic_file_mounts.is_empty();
+        // Edges passing only a signature (or nothing) carry no context to run dry
+        let is_root_node = !agent_config.depends_on.iter().any(|d| d.pass.includes_output());
+
+        // If we depend on others, and they gave us nothing but NULLs or empty text, pause.
+        if !is_root_node && (context_prompt_appendix.trim().is_empty() || (has_null_signal && !has_files)) {
+            let drought_msg = format!("Pre-Execution Halt: Agent '{}' is facing a Context Drought. Upstream nodes provided no usable data.", agent_id);
+            tracing::warn!("{}", drought_msg);
+
+            // Trigger the Circuit Breaker (releases our read locks first; it writes the run state)
+            drop(workflow);
+            drop(state);
+            self.request_approval(run_id, Some(agent_id), &drought_msg).await;
+
+            return Err(RuntimeError::ContextDrought(agent_id.to_string()));
+        }
+
+        let cached_content_id = self.get_cache_resource(run_id);
+
+        let model_mapping = self.model_registry.resolve(&agent_config.model).map_err(RuntimeError::InvalidConfig)?;
+
+        let thinking_level = Self::thinking_level_for(&model_mapping, agent_config);
+
+        let mut full_file_paths: Vec<String> = workflow.attached_files.iter().chain(&state.attached_files)
+            .map(|f| format!("/app/storage/sessions/{}/input/{}", run_id, f))
+            .collect();
+
+        let has_dynamic_artifacts = !dynamic_file_mounts.is_empty();
+        let dynamic_artifact_count = dynamic_file_mounts.len();
+
+        if has_dynamic_artifacts {
+            tracing::info!("Mounting {} dynamic artifacts for agent {}", dynamic_artifact_count, agent_id);
+            full_file_paths.extend(dynamic_file_mounts);
+        }
+
+        // === AUTHORITATIVE IDENTITY PROVISIONING (MERGE & VALIDATE) ===
+        // STRATEGY: Start with user configuration, then enforce identity mandates
+        // This allows manual additions while preventing capability removal
+
+        // 1. Start with tools defined in the Configuration (from UI/Architect)
+        let mut tools = agent_config.tools.clone();
+
+        // 2. UNIVERSAL BASELINE (Always ensure these exist)
+        if !tools.contains(&"read_file".to_string()) { tools.push("read_file".to_string()); }
+        if !tools.contains(&"list_files".to_string()) { tools.push("list_files".to_string()); }
+
+        let id_lower = agent_id.to_lowercase();
+
+        // 3. MANDATORY IDENTITY GRANTS (Enforce identity contract)
+        // Even if user removed them in UI, identity demands them.
+
+        // Research Class (Broader matching: research_, researcher, web_)
+        if (id_lower.contains("research") || id_lower.starts_with("web_"))
+            && !tools.contains(&"web_search".to_string())
+        {
+            tools.push("web_search".to_string());
+        }
+
+        // Logic/Math Class (analyze_, analyst, coder, math)
+        if (id_lower.contains("analy") || id_lower.contains("code") || id_lower.contains("math"))
+            && !tools.contains(&"execute_python".to_string())
+        {
+            tools.push("execute_python".to_string());
+        }
+
+        // Output/I-O Class (writer, coder, logger)
+        if (id_lower.contains("code") || id_lower.contains("writ"))
+            && !tools.contains(&"write_file".to_string())
+        {
+            tools.push("write_file".to_string());
+        }
+
+        // Admin Class (master_, orchestrator)
+        if id_lower.starts_with("master_") || id_lower.starts_with("orchestrator") {
+            for t in ["web_search", "execute_python", "write_file"] {
+                if !tools.contains(&t.to_string()) {
+                    tools.push(t.to_string());
+                }
+            }
+        }
+
+        // Dynamic artifacts require python (Special Case)
+        if has_dynamic_artifacts && !tools.contains(&"execute_python".to_string()) {
+            tools.push("execute_python".to_string());
+            tracing::info!("Agent {}: Provisioned 'execute_python' for dynamic artifact handling", agent_id);
+        }
+
+        tracing::info!("Final provisioned tools for {}: {:?}", agent_id, tools);
+
+        // === ARCHITECTURAL FIX: SEPARATE IDENTITY FROM CONTEXT ===
+
+        // 1. Prepare System Prompt (Identity Only - Pure Constitution)
+        // Do NOT append context here. Keep this immutable.
+        // Resolve {{agents.<id>.output.<path>}} references against upstream outputs
+        // and {{KEY}} placeholders against the agent's environment
+        let environment = workflow.environment_for(agent_config);
+        let mut final_prompt = template::render(&agent_config.prompt, &input_data_map, &environment)
+            .map_err(|e| RuntimeError::Template(format!("prompt of agent {}: {}", agent_id, e)))?;
+
+        if tools.contains(&"write_file".to_string()) {
+            final_prompt.push_str("\n\n[SYSTEM NOTICE]: You have access to 'write_file'. When generating a file, DO NOT output the file content in your text response. Simply state 'Writing [filename]...' and then execute the tool immediately. Duplicating content in text and tool arguments is prohibited.");
+        }
+
+        // 2. Prepare User Directive (Task + Context - Dynamic Data)
+        // We prepend context to the directive so it appears in the USER message.
+        // This prevents system instruction leakage.
+        let mut final_user_directive = template::render(&agent_config.user_directive, &input_data_map, &environment)
+            .map_err(|e| RuntimeError::Template(format!("directive of agent {}: {}", agent_id, e)))?;
+
+        if !context_prompt_appendix.is_empty() {
+            // Prepend context before the directive so model sees data before command
+            final_user_directive = if !final_user_directive.is_empty() {
+                format!("{}\n\n[OPERATIONAL CONTEXT]\n{}", context_prompt_appendix, final_user_directive)
+            } else {
+                // If no directive, just pass the context
+                context_prompt_appendix
+            };
+        }
+
+        let graph_view = self.generate_graph_context(
+            run_id,
+            agent_id,
+            agent_config.allow_delegation
+        );
+
+        let payload = InvocationPayload {
+            run_id: run_id.to_string(),
+            agent_id: agent_id.to_string(),
+            model: model_mapping.api_model_name,
+            variant: model_mapping.variant,
+            // Agent overrides win over the model mapping's output cap (validated to fit u32)
+            max_output_tokens: agent_config.generation.max_output_tokens
+                .and_then(|t| u32::try_from(t).ok())
+                .or(model_mapping.max_output_tokens),
+            temperature: agent_config.generation.temperature,
+            top_p: agent_config.generation.top_p,
+            seed: agent_config.generation.seed.or(workflow.seed),
+            log_level: agent_config.log_level.clone(),
+            prompt: final_prompt,              // Pure Identity (System Instruction)
+            user_directive: final_user_directive,  // Task + Context (User Message)
+            input_data: serde_json::Value::Object(input_data_map),
+            parent_signature,
+            cached_content_id,
+            thinking_level,
+            file_paths: full_file_paths,
+            tools,  // Now contains Architect's choices + smart baseline guarantees
+            allow_delegation: agent_config.allow_delegation,
+            graph_view,
+        };
+
+        // Counted only once the payload is complete, so a template or drought failure costs nothing.
+        // Also covers agents added after start (delegation) and profiles changed mid-run.
+        if !state.simulation {
+            let variant = self.canonical_model(&agent_config.model);
+            self.client_policies.consume(&state.client_id, &variant).map_err(|violation| match violation {
+                PolicyViolation::NotAllowed => RuntimeError::ModelNotAllowed { agent_id: agent_id.to_string(), model: variant.as_str().to_string() },
+                PolicyViolation::QuotaExceeded { limit } => RuntimeError::ModelQuotaExceeded { model: variant.as_str().to_string(), limit },
+            })?;
+        }
+
+        // Prompt content is redacted here when RARO_REDACT_PROMPTS is on; the payload is untouched
+        let trace = TraceEvent::new("DEBUG", "Invocation payload prepared", Some(agent_id), serde_json::json!({
+            "model": payload.model,
+            "prompt": payload.prompt,
+            "user_directive": payload.user_directive,
+            "tools": payload.tools,
+        }));
+        tracing::debug!(trace = %serde_json::to_string(&trace).unwrap_or_default(), "Invocation payload prepared");
+        self.record_trace(run_id, &trace);
+
+        Ok(payload)
+    }
+
+    // === ROUTING INTROSPECTION ===
+
+    /// Pick the dependency whose signature is forwarded. Returns (source, deps lacking a signature).
+    fn signature_routing(&self, run_id: &str, agent_config: &AgentNodeConfig) -> (Option<String>, Vec<String>) {
+        let (with_sig, without_sig): (Vec<String>, Vec<String>) = agent_config
+            .depends_on
+            .iter()
+            .filter(|d| d.pass.includes_signature())
+            .map(|d| d.agent.clone())
+            .partition(|parent_id| self.get_thought_signature(run_id, parent_id).is_some());
+        (with_sig.into_iter().next(), without_sig)
+    }
+
+    fn thinking_level_for(mapping: &crate::model_registry::ModelMapping, agent: &AgentNodeConfig) -> Option<i32> {
+        mapping.supports_thinking.then(|| agent.thinking_budget.unwrap_or(DEFAULT_THINKING_BUDGET) as i32)
+    }
+
+    /// The named variant a model stands for. Custom ids naming a variant's API model count as
+    /// that variant, so client policies can't be sidestepped with a raw model id.
+    fn canonical_model(&self, model: &ModelVariant) -> ModelVariant {
+        match (model, self.model_registry.resolve(model)) {
+            (ModelVariant::Custom(_), Ok(mapping)) => self.model_registry.variant_for_api_model(&mapping.api_model_name),
+            _ => model.clone(),
+        }
+    }
+
+    /// Explain the signature, cache and model routing prepare_invocation_payload applies to an agent
+    pub fn explain_routing(&self, run_id: &str, agent_id: &str) -> Result<RoutingDecision, RuntimeError> {
+        let workflow_id = self.runtime_states.get(run_id)
+            .map(|s| s.workflow_id.clone())
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let workflow = self.workflows.get(&workflow_id)
+            .ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let agent_config = workflow.agents.iter()
+            .find(|a| a.id == agent_id)
+            .ok_or_else(|| RuntimeError::AgentNotFound(agent_id.to_string()))?;
 
-                            // Emit event for UI notification (triggers approval card)
-                            self.emit_event(RuntimeEvent::new(
-                                &run_id,
-                                EventType::SystemIntervention,
-                                Some(agent_id.clone()),
-                                serde_json::json!({
-                                    "reason": pause_reason,
-                                    "agent_id": agent_id,
-                                    "type": "soft_failure",
-                                    "recovery_hint": "Review agent output and choose to Resume, Skip, or Stop"
-                                }),
-                            ));
+        let (signature_source, dependencies_without_signature) = self.signature_routing(run_id, agent_config);
 
-                            // Trigger the Pause (sets status to AwaitingApproval)
-                            self.request_approval(&run_id, Some(&agent_id), &pause_reason).await;
+        let cached_content_id = self.get_cache_resource(run_id);
+        let cache_reason = match &cached_content_id {
+            Some(id) => format!("Run-level cache {} exists; attached to every invocation (cache_policy '{}')", id, agent_config.cache_policy),
+            None => "No cached content registered for this run".to_string(),
+        };
 
-                            // Break the loop to suspend execution until resumed
-                            break;
-                        }
-                    }
-                }
-                Err(e) => {
-                    self.emit_event(RuntimeEvent::new(
-                        &run_id,
-                        EventType::AgentFailed,
-                        Some(agent_id.clone()),
-                        serde_json::json!({"agent_id": agent_id, "error": e.to_string()}),
-                    ));
-                    self.fail_run(&run_id, &agent_id, &e.to_string()).await;
-                    self.trigger_remote_cleanup(&run_id).await;
-                }
-            }
+        let (api_model_name, thinking_level, model_error) = match self.model_registry.resolve(&agent_config.model) {
+            Ok(mapping) => (Some(mapping.api_model_name.clone()), Self::thinking_level_for(&mapping, agent_config), None),
+            Err(e) => (None, None, Some(e)),
+        };
+
+        Ok(RoutingDecision {
+            run_id: run_id.to_string(),
+            agent_id: agent_id.to_string(),
+            signature_source,
+            dependencies_without_signature,
+            cache_policy: agent_config.cache_policy.clone(),
+            cached_content_id,
+            cache_reason,
+            model_variant: agent_config.model.as_str().to_string(),
+            api_model_name,
+            thinking_level,
+            model_error,
+        })
+    }
+
+    pub fn set_run_status(&self, run_id: &str, status: RuntimeStatus) {
+        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            state.status = status;
         }
     }
+    
+    pub fn get_topology_snapshot(&self, run_id: &str) -> Option<serde_json::Value> {
+        // 1. Get the DAG
+        let dag = self.dag_store.get(run_id)?;
 
-    /// Handles the "Graph Surgery" when an agent requests delegation
-    async fn handle_delegation(&self, run_id: &str, parent_id: &str, mut req: DelegationRequest) -> Result<(), String> {
-        let state = self.runtime_states.get(run_id).ok_or("Run not found")?;
-        let workflow_id = state.workflow_id.clone();
+        // 2. Get the Workflow Config (where prompts/metadata live)
+        let workflow_id = self.runtime_states.get(run_id).map(|s| s.workflow_id.clone())?;
+        let workflow = self.workflows.get(&workflow_id)?;
 
-        // Snapshot safe IDs to check status
-        let active_agents = state.active_agents.clone();
-        let completed_agents = state.completed_agents.clone();
-        drop(state);  // Drop read lock
+        let edges = dag.export_edges();
+        let node_ids = dag.export_nodes();
 
-        // === STEP 1: PRUNING & ORPHAN CAPTURE ===
-        // Handle removals first to clear the graph
-        // [[CRITICAL FIX]] We must capture "orphans" of pruned nodes to reconnect them!
-        let mut orphaned_dependents: Vec<String> = Vec::new();
+        // 3. Enrich Nodes with Config Data
+        let enriched_nodes: Vec<serde_json::Value> = node_ids.iter().map(|node_id| {
+            // Find the config for this node
+            let config = workflow.agents.iter().find(|a| a.id == *node_id);
 
-        if !req.prune_nodes.is_empty() {
-            tracing::info!("Delegation PRUNE: Removing nodes {:?}", req.prune_nodes);
+            if let Some(c) = config {
+                serde_json::json!({
+                    "id": c.id,
+                    "label": c.id, // Or human readable name if available
+                    "prompt": c.prompt,
+                    "model": match c.model {
+                        ModelVariant::Fast => "fast",
+                        ModelVariant::Reasoning => "reasoning",
+                        ModelVariant::Thinking => "thinking",
+                        ModelVariant::Custom(ref s) => s.as_str(),
+                    },
+                    "role": match c.role {
+                        AgentRole::Orchestrator => "orchestrator",
+                        AgentRole::Worker => "worker",
+                        AgentRole::Observer => "observer",
+                        AgentRole::Supervisor => "supervisor",
+                    },
+                    "tools": c.tools,
+                    "accepts_directive": c.accepts_directive,
+                    "allow_delegation": c.allow_delegation,
+                    // If position is stored in config (from initial layout), pass it
+                    // Dynamic nodes usually lack this, handled by frontend layout engine
+                    "x": c.position.as_ref().map(|p| p.x).unwrap_or(0.0),
+                    "y": c.position.as_ref().map(|p| p.y).unwrap_or(0.0)
+                })
+            } else {
+                // Fallback for purely structural nodes (rare)
+                serde_json::json!({
+                    "id": node_id,
+                    "label": node_id,
+                    "prompt": "Dynamic Agent (Metadata Missing)",
+                    "model": "fast",
+                    "role": "worker",
+                    "tools": [],
+                    "accepts_directive": false,
+                    "allow_delegation": false,
+                    "x": 0.0,
+                    "y": 0.0
+                })
+            }
+        }).collect();
 
-            for node_to_remove in &req.prune_nodes {
-                // Safety Check: Cannot remove Active or Completed nodes
-                if active_agents.contains(node_to_remove) || completed_agents.contains(node_to_remove) {
-                    tracing::warn!("Agent {} tried to prune active/completed node {}. Ignoring.", parent_id, node_to_remove);
-                    continue;
+        Some(serde_json::json!({
+            "nodes": enriched_nodes, // <--- Now returns rich objects, not just strings
+            "edges": edges.into_iter().map(|(from, to)| {
+                // Label only edges declared with pass/alias; plain ones pass everything
+                match dag.edge_label(&from, &to) {
+                    Some(label) => serde_json::json!({ "from": from, "to": to, "label": label }),
+                    None => serde_json::json!({ "from": from, "to": to }),
                 }
+            }).collect::<Vec<_>>()
+        }))
+    }
 
-                // 1. Remove from DAG & Capture Orphans
-                if let Some(mut dag) = self.dag_store.get_mut(run_id) {
-                    // [[CRITICAL FIX]] Save children of the node we are about to delete
-                    // These nodes (e.g., 'writer_report') were waiting on 'analyze_data'.
-                    // If we delete 'analyze_data', they become orphans.
-                    // We must add them to the connection list for the NEW nodes.
-                    let children = dag.get_children(node_to_remove);
-                    orphaned_dependents.extend(children);
+    pub fn set_cache_resource(&self, run_id: &str, cached_content_id: String) -> Result<(), RuntimeError> {
+        self.cache_resources.insert(run_id.to_string(), CacheRegistration {
+            run_id: run_id.to_string(),
+            cached_content_id,
+            registered_at: Utc::now().to_rfc3339(),
+            ttl_seconds: Some(DEFAULT_CACHE_TTL_SECS),
+        });
+        Ok(())
+    }
 
-                    if let Err(e) = dag.remove_node(node_to_remove) {
-                        tracing::warn!("Failed to prune node {}: {}", node_to_remove, e);
-                    }
-                }
+    pub fn get_cache_resource(&self, run_id: &str) -> Option<String> {
+        self.cache_resources.get(run_id).map(|c| c.cached_content_id.clone())
+    }
 
-                // 2. Remove from Workflow Config (Metadata)
-                if let Some(mut workflow) = self.workflows.get_mut(&workflow_id) {
-                    if let Some(pos) = workflow.agents.iter().position(|a| a.id == *node_to_remove) {
-                        workflow.agents.remove(pos);
-                        tracing::info!("Pruned node '{}' from workflow config", node_to_remove);
-                    }
-                }
-            }
-        }
+    pub fn list_cache_resources(&self) -> Vec<CacheRegistration> {
+        let mut caches: Vec<CacheRegistration> = self.cache_resources.iter().map(|c| c.clone()).collect();
+        caches.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+        caches
+    }
 
-        // === STEP 2: PRE-FETCH DEPENDENTS (Re-fetch after pruning) ===
-        let (existing_dependents, existing_node_ids) = if let Some(dag) = self.dag_store.get(run_id) {
-            (dag.get_children(parent_id), dag.export_nodes())
-        } else {
-            return Err("DAG not found for pre-fetch".to_string());
+    /// Operator override for a stuck run: mark a pending or running agent as skipped. Its
+    /// dependents see an empty output and can proceed. A call still in flight for the agent
+    /// is not cancelled; its result is recorded but no longer changes the agent's outcome.
+    pub async fn skip_agent(&self, run_id: &str, agent_id: &str, reason: &str) -> Result<AgentInvocation, RuntimeError> {
+        self.ensure_agent_exists(run_id, agent_id)?;
+        let model_variant = {
+            let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+            self.workflows.get(&state.workflow_id)
+                .and_then(|w| w.agents.iter().find(|a| a.id == agent_id).map(|a| a.model.clone()))
+                .unwrap_or(ModelVariant::Fast)
         };
 
-        // FIX: ID Collision Remapping with Ghost Prevention
-        // If a new node has an ID that already exists:
-        // - If the existing node is PENDING (not started), adopt it (overwrite)
-        // - If the existing node is ACTIVE/COMPLETED, rename to avoid corruption
-        let mut id_map: HashMap<String, String> = HashMap::new();
-
-        for node in &mut req.new_nodes {
-            if existing_node_ids.contains(&node.id) {
-                // CHECK STATUS OF EXISTING NODE
-                let is_pending = if let Some(state) = self.runtime_states.get(run_id) {
-                    !state.active_agents.contains(&node.id) &&
-                    !state.completed_agents.contains(&node.id) &&
-                    !state.failed_agents.contains(&node.id)
-                } else {
-                    false
-                };
+        let now = Utc::now().to_rfc3339();
+        let mut invocation = AgentInvocation {
+            id: Uuid::new_v4().to_string(),
+            agent_id: agent_id.to_string(),
+            model_variant,
+            thought_signature: None,
+            tools_used: vec![],
+            tokens_used: 0,
+            latency_ms: 0,
+            status: InvocationStatus::Skipped,
+            started_at: now.clone(),
+            timestamp: now,
+            artifact_id: None,
+            error_message: Some(reason.to_string()),
+            replay_of: None,
+            generation: GenerationParams::default(),
+            token_split: TokenSplit::default(),
+            escalated_from: None,
+        };
+        // Recorded first (refused when the agent already finished); a dependent prepared before
+        // the output below is stored gets the same placeholder (see prepare_invocation_payload)
+        self.record_invocation(run_id, invocation.clone(), None).await?;
+        invocation.artifact_id = self.store_artifact(run_id, agent_id, &Self::skipped_output(reason)).await;
+        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
+            if let Some(recorded) = state.invocations.iter_mut().find(|i| i.id == invocation.id) {
+                recorded.artifact_id = invocation.artifact_id.clone();
+            }
+        }
+        self.persist_state(run_id).await;
+        tracing::warn!("Agent {} of run {} skipped by operator: {}", agent_id, run_id, reason);
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            Some(agent_id.to_string()),
+            serde_json::json!({ "action": "agent_skipped", "agent_id": agent_id, "reason": reason }),
+        ));
+        self.push_ready_agents(run_id).await;
+        Ok(invocation)
+    }
 
-                if is_pending {
-                    tracing::info!("Delegation UPDATE: Adopting/Overwriting pending node '{}'.", node.id);
+    /// Only pending or running agents of a live run can be skipped
+    fn ensure_skippable(state: &RuntimeState, agent_id: &str) -> Result<(), RuntimeError> {
+        if state.status.is_terminal() || state.completed_agents.iter().any(|a| a == agent_id) || state.has_failed(agent_id) {
+            return Err(RuntimeError::AgentNotSkippable(agent_id.to_string()));
+        }
+        Ok(())
+    }
 
-                    // UPDATE LOGIC: Remove old definition so we can replace it
-                    if let Some(mut workflow) = self.workflows.get_mut(&workflow_id) {
-                        if let Some(pos) = workflow.agents.iter().position(|a| a.id == node.id) {
-                            workflow.agents.remove(pos);
-                        }
-                    }
+    /// Output a skipped agent passes on to its dependents
+    fn skipped_output(reason: &str) -> serde_json::Value {
+        serde_json::json!({ "result": "", "skipped": true, "reason": reason })
+    }
 
-                    // DAG LOGIC: Clear old incoming edges to allow "Rewiring"
-                    // This allows the agent to change dependencies for an existing pending node
-                    if let Some(mut dag) = self.dag_store.get_mut(run_id) {
-                        dag.clear_incoming_edges(&node.id);
-                    }
+    /// Change an agent's prompt or generation settings before it is dispatched. The run is
+    /// moved onto its own "{id}@{run_id}" copy of the workflow first and only that copy is
+    /// patched, so runs sharing the workflow id are unaffected; the next
+    /// prepare_invocation_payload picks the change up.
+    pub async fn patch_agent_config(&self, run_id: &str, client_id: &str, agent_id: &str, patch: &PatchAgentConfig) -> Result<AgentNodeConfig, RuntimeError> {
+        self.ensure_run_owner(run_id, client_id)?;
+        self.ensure_agent_exists(run_id, agent_id)?;
+        let patched = self.patch_pinned_agent(run_id, agent_id, patch)?;
+        self.persist_state(run_id).await;
 
-                } else {
-                    // Node is already running/done, we MUST rename to avoid history corruption
-                    let old_id = node.id.clone();
-                    let suffix = Uuid::new_v4().to_string().split('-').next().unwrap().to_string();
-                    let new_id = format!("{}_{}", old_id, suffix);
-                    tracing::warn!("Delegation ID Collision: Renaming '{}' to '{}' (node already active/completed)", old_id, new_id);
+        tracing::info!("Agent {} of run {} patched: {:?}", agent_id, run_id, patch.fields());
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            Some(agent_id.to_string()),
+            serde_json::json!({ "action": "agent_config_patched", "agent_id": agent_id, "fields": patch.fields() }),
+        ));
+        Ok(patched)
+    }
 
-                    node.id = new_id.clone();
-                    id_map.insert(old_id, new_id);
-                }
-            }
+    fn patch_pinned_agent(&self, run_id: &str, agent_id: &str, patch: &PatchAgentConfig) -> Result<AgentNodeConfig, RuntimeError> {
+        let _swap = self.workflow_swap.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let workflow_id = self.pin_run_workflow_locked(run_id)?;
+        // Held until the workflow is replaced, so the agent can't be dispatched in between
+        let state = self.runtime_states.get(run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.to_string()))?;
+        let not_patchable = |reason| RuntimeError::AgentNotPatchable { agent_id: agent_id.to_string(), reason };
+        if state.status.is_terminal() {
+            return Err(not_patchable("part of a finished run"));
+        }
+        if state.completed_agents.iter().any(|a| a == agent_id) || state.has_failed(agent_id) {
+            return Err(not_patchable("already finished"));
+        }
+        if state.active_agents.iter().any(|a| a == agent_id) {
+            return Err(not_patchable("already running"));
         }
 
-        // Apply rewiring to new nodes' dependency lists
-        for node in &mut req.new_nodes {
-            node.depends_on = node.depends_on.iter().map(|dep| {
-                // If dependency is in our map, update it. Otherwise keep original.
-                id_map.get(dep).cloned().unwrap_or(dep.clone())
-            }).collect();
+        let mut workflow = self.workflows.get(&workflow_id)
+            .map(|w| w.clone())
+            .ok_or_else(|| RuntimeError::WorkflowNotFound(workflow_id.clone()))?;
+        let agent = workflow.agents.iter_mut().find(|a| a.id == agent_id)
+            .ok_or_else(|| RuntimeError::AgentNotFound(agent_id.to_string()))?;
+        if let Some(prompt) = &patch.prompt {
+            agent.prompt = prompt.clone();
+        }
+        if let Some(level) = patch.thinking_budget {
+            agent.thinking_budget = Some(level);
+        }
+        if let Some(max) = patch.max_output_tokens {
+            agent.generation.max_output_tokens = Some(max.min(i64::MAX as usize) as i64);
         }
+        let patched = agent.clone();
+
+        // Same checks as at start, so a patch can't produce a config a start would refuse
+        workflow.check_limits(&self.workflow_limits).map_err(RuntimeError::InvalidWorkflow)?;
+        workflow.validate().map_err(RuntimeError::InvalidWorkflow)?;
+        self.workflows.insert(workflow_id, workflow);
+        drop(state);
+        Ok(patched)
+    }
 
-        // [[CRITICAL FIX START]]: Create a list of IDs being injected/updated
-        // We must NOT treat these as "downstream dependents" to avoid self-referential cycles.
-        let new_node_ids: Vec<String> = req.new_nodes.iter().map(|n| n.id.clone()).collect();
+    /// Stop attaching a cache to a run's invocations
+    pub fn detach_cache_resource(&self, run_id: &str, reason: &str) -> Option<CacheRegistration> {
+        let (_, registration) = self.cache_resources.remove(run_id)?;
+        tracing::warn!("Detached cache {} from run {}: {}", registration.cached_content_id, run_id, reason);
+        self.emit_event(RuntimeEvent::new(
+            run_id,
+            EventType::SystemIntervention,
+            None,
+            serde_json::json!({
+                "action": "cache_detached",
+                "cached_content_id": registration.cached_content_id,
+                "reason": reason
+            }),
+        ));
+        Some(registration)
+    }
 
-        // [[CRITICAL FIX]]: MERGE ORPHANS into Downstream Dependents
-        // Combine parent's original children AND the children of the nodes we just pruned.
-        let mut all_downstream = existing_dependents.clone();
-        all_downstream.extend(orphaned_dependents);
-        // Dedup
-        all_downstream.sort();
-        all_downstream.dedup();
+    /// Check every registered cache id against the Gemini API (requires GEMINI_API_KEY) and
+    /// detach the ones upstream no longer knows. Returns the affected run ids.
+    pub async fn verify_cache_resources(&self) -> Result<Vec<String>, RuntimeError> {
+        let api_key = env::var("GEMINI_API_KEY")
+            .ok()
+            .filter(|k| !k.is_empty())
+            .ok_or_else(|| RuntimeError::NotConfigured("GEMINI_API_KEY is not set".to_string()))?;
+        let base = env::var("GEMINI_API_BASE").unwrap_or_else(|_| GEMINI_API_BASE.to_string());
+        Ok(self.verify_cache_resources_against(&base, &api_key).await)
+    }
 
-        // Filter existing dependents to finding TRUE downstream nodes
-        // 1. Must not be a new node
-        // 2. Must not be a pruned node (dead link)
-        let downstream_dependents: Vec<String> = all_downstream
-            .into_iter()
-            .filter(|dep_id| !new_node_ids.contains(dep_id))
-            .filter(|dep_id| !req.prune_nodes.contains(dep_id)) // <--- ADD THIS to prevent linking to dead nodes
-            .collect();
-        // [[CRITICAL FIX END]]
+    async fn verify_cache_resources_against(&self, base_url: &str, api_key: &str) -> Vec<String> {
+        let mut ids: Vec<String> = self.cache_resources.iter().map(|c| c.cached_content_id.clone()).collect();
+        ids.sort();
+        ids.dedup();
+
+        let mut detached = Vec::new();
+        for id in ids {
+            let url = format!("{}/{}", base_url.trim_end_matches('/'), id.trim_start_matches('/'));
+            // Key in a header, never in the URL: request URLs end up in logs and error messages
+            let status = match self.http_client.get(&url).header("x-goog-api-key", api_key).send().await {
+                Ok(resp) => resp.status(),
+                Err(e) => {
+                    // Unreachable API says nothing about the cache; keep it
+                    tracing::warn!("Cache verification for {} failed: {}", id, e.without_url());
+                    continue;
+                }
+            };
 
-        // 3. MUTATE WORKFLOW CONFIG
-        if let Some(mut workflow) = self.workflows.get_mut(&workflow_id) {
-            for node in &req.new_nodes {
-                workflow.agents.push(node.clone());
+            if status != reqwest::StatusCode::NOT_FOUND {
+                continue;
             }
 
-            if req.strategy == DelegationStrategy::Child {
-                // Use the filtered list (downstream_dependents) instead of existing_dependents
-                for dep_id in &downstream_dependents {
-                    if let Some(dep_agent) = workflow.agents.iter_mut().find(|a| a.id == *dep_id) {
-                        dep_agent.depends_on.retain(|p| p != parent_id);
-                        for new_node in &req.new_nodes {
-                            if !dep_agent.depends_on.contains(&new_node.id) {
-                                dep_agent.depends_on.push(new_node.id.clone());
-                            }
-                        }
-                        tracing::info!("Rewired Config: Agent {} now depends on {:?}", dep_id, dep_agent.depends_on);
-                    }
-                }
+            let runs: Vec<String> = self.cache_resources.iter()
+                .filter(|c| c.cached_content_id == id)
+                .map(|c| c.run_id.clone())
+                .collect();
+            for run_id in runs {
+                self.detach_cache_resource(&run_id, "Cached content no longer exists upstream");
+                detached.push(run_id);
             }
-        } else {
-            return Err("Workflow config not found".to_string());
         }
 
-        // 4. MUTATE DAG TOPOLOGY
-        if let Some(mut dag) = self.dag_store.get_mut(run_id) {
+        detached
+    }
+
+    pub fn has_dag(&self, run_id: &str) -> bool {
+        self.dag_store.contains_key(run_id)
+    }
+}
+/// The MAX_METRIC_SERIES largest series of a labelled metric, with the rest summed under
+/// costs::OVERFLOW_BUCKET, so a scrape stays bounded however many label values exist
+fn cap_series(series: BTreeMap<String, u64>) -> BTreeMap<String, u64> {
+    if series.len() <= MAX_METRIC_SERIES {
+        return series;
+    }
+    let mut by_count: Vec<(String, u64)> = series.into_iter().collect();
+    by_count.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let overflow: u64 = by_count.drain(MAX_METRIC_SERIES..).map(|(_, count)| count).sum();
+    let mut capped: BTreeMap<String, u64> = by_count.into_iter().collect();
+    *capped.entry(costs::OVERFLOW_BUCKET.to_string()).or_default() += overflow;
+    capped
+}
 
-            for node in &req.new_nodes {
-                dag.add_node(node.id.clone()).map_err(|e| e.to_string())?;
+/// "{workflow_id}@{run_id}": a workflow copy private to one run (already pinned ids are kept)
+fn pinned_workflow_id(workflow_id: &str, run_id: &str) -> String {
+    let suffix = format!("@{}", run_id);
+    if workflow_id.ends_with(&suffix) { workflow_id.to_string() } else { format!("{}{}", workflow_id, suffix) }
+}
 
-                for dep in &node.depends_on {
-                    if let Err(e) = dag.add_edge(dep.clone(), node.id.clone()) {
-                        tracing::debug!("Adding dependency edge {} -> {}: {:?}", dep, node.id, e);
-                    }
-                }
+/// Remove every node that is neither a target, an ancestor of one, nor in `keep`.
+/// Returns the removed node ids (sorted).
+fn prune_to_targets(dag: &mut DAG, targets: &[String], keep: &[String]) -> Result<Vec<String>, RuntimeError> {
+    let mut unknown: Vec<String> = targets.iter()
+        .filter(|t| dag.ancestors(t).is_err())
+        .cloned()
+        .collect();
+    if targets.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !unknown.is_empty() {
+        unknown.sort();
+        return Err(RuntimeError::UnknownTargets(unknown));
+    }
 
-                if node.depends_on.is_empty() || node.depends_on.contains(&parent_id.to_string()) {
-                     let _ = dag.add_edge(parent_id.to_string(), node.id.clone());
-                }
+    let mut scope: HashSet<String> = keep.iter().cloned().collect();
+    for target in targets {
+        scope.insert(target.clone());
+        scope.extend(dag.ancestors(target).unwrap_or_default());
+    }
 
-                // B. Connect New Nodes -> TRUE Downstream Dependents
-                if req.strategy == DelegationStrategy::Child {
-                    for dep in &downstream_dependents {
-                        dag.add_edge(node.id.clone(), dep.clone()).map_err(|e| e.to_string())?;
+    let mut removed: Vec<String> = dag.export_nodes()
+        .into_iter()
+        .filter(|n| !scope.contains(n))
+        .collect();
+    removed.sort();
+
+    for node in &removed {
+        let _ = dag.remove_node(node);
+    }
+    Ok(removed)
+}
+
+/// upsert_run_metadata's merge: objects recurse key by key, arrays append new items, else replace
+fn merge_metadata_value(existing: &mut serde_json::Value, incoming: serde_json::Value) {
+    use serde_json::Value;
+    match (existing, incoming) {
+        (Value::Object(existing), Value::Object(incoming)) => {
+            for (key, value) in incoming {
+                match existing.get_mut(&key) {
+                    Some(current) => merge_metadata_value(current, value),
+                    None => {
+                        existing.insert(key, value);
                     }
                 }
             }
-
-            // C. Remove Old Edges (Parent -> TRUE Downstream Dependents)
-            if req.strategy == DelegationStrategy::Child {
-                for dep in &downstream_dependents {
-                    let _ = dag.remove_edge(parent_id, dep);
+        }
+        (Value::Array(existing), Value::Array(incoming)) => {
+            for item in incoming {
+                if !existing.contains(&item) {
+                    existing.push(item);
                 }
             }
+        }
+        (existing, incoming) => *existing = incoming,
+    }
+}
 
-            if let Err(e) = dag.topological_sort() {
-                tracing::error!("Delegation created a cycle: {:?}", e);
-                return Err("Delegation created a cycle in DAG".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    fn agent(id: &str, depends_on: &[&str]) -> AgentNodeConfig {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "role": "worker",
+            "model": "fast",
+            "tools": [],
+            "depends_on": depends_on,
+            "prompt": format!("You are {}", id),
+            "position": null
+        }))
+        .unwrap()
+    }
+
+    /// Registers a run directly in the in-memory maps (no FS session, no execution loop)
+    fn seed_run(runtime: &RARORuntime, run_id: &str, agents: Vec<AgentNodeConfig>) {
+        seed_run_with(runtime, run_id, agents, serde_json::json!({}));
+    }
+
+    /// Like `seed_run`, with extra WorkflowConfig fields merged in
+    fn seed_run_with(runtime: &RARORuntime, run_id: &str, agents: Vec<AgentNodeConfig>, overrides: serde_json::Value) {
+        let mut dag = DAG::new();
+        for a in &agents {
+            dag.add_node(a.id.clone()).unwrap();
+        }
+        for a in &agents {
+            for dep in &a.depends_on {
+                dag.add_edge(dep.agent.clone(), a.id.clone()).unwrap();
             }
-        } else {
-            return Err("DAG not found".to_string());
+            dag.annotate_edges(a);
         }
 
-        Ok(())
+        let workflow_id = format!("wf-{}", run_id);
+        let mut config = serde_json::json!({
+            "id": workflow_id,
+            "name": "test",
+            "agents": agents,
+            "max_token_budget": 10_000,
+            "timeout_ms": 60_000
+        });
+        if let (Some(base), Some(extra)) = (config.as_object_mut(), overrides.as_object()) {
+            base.extend(extra.clone());
+        }
+
+        runtime.workflows.insert(workflow_id.clone(), serde_json::from_value(config).unwrap());
+        runtime.workflow_owners.insert(workflow_id.clone(), "public".to_string());
+        runtime.dag_store.insert(run_id.to_string(), dag);
+        runtime.runtime_states.insert(run_id.to_string(), serde_json::from_value(serde_json::json!({
+            "run_id": run_id,
+            "workflow_id": workflow_id,
+            "client_id": "public",
+            "status": "running",
+            "active_agents": [],
+            "completed_agents": [],
+            "failed_agents": [],
+            "invocations": [],
+            "total_tokens_used": 0,
+            "start_time": Utc::now().to_rfc3339(),
+            "end_time": null
+        })).unwrap());
+        runtime.thought_signatures.insert(run_id.to_string(), ThoughtSignatureStore::default());
     }
 
-    /// Helper to fail the run and update state (Async + Persistent)
-    pub async fn fail_run(&self, run_id: &str, agent_id: &str, error: &str) {
-        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
-            state.status = RuntimeStatus::Failed;
-            state.end_time = Some(Utc::now().to_rfc3339());
-            state.failed_agents.push(agent_id.to_string());
-            
-            // Remove from active if present
-            state.active_agents.retain(|a| a != agent_id);
-            
-            // Record failed invocation
-            state.invocations.push(AgentInvocation {
-                id: Uuid::new_v4().to_string(),
-                agent_id: agent_id.to_string(),
-                model_variant: ModelVariant::Fast, // Fallback
-                thought_signature: None,
-                tools_used: vec![],
-                tokens_used: 0,
-                latency_ms: 0,
-                status: InvocationStatus::Failed,
-                timestamp: Utc::now().to_rfc3339(),
-                artifact_id: None,
-                error_message: Some(error.to_string()), 
-            });
+    fn success_invocation(agent_id: &str, tokens_used: usize) -> AgentInvocation {
+        AgentInvocation {
+            id: Uuid::new_v4().to_string(),
+            agent_id: agent_id.to_string(),
+            model_variant: ModelVariant::Fast,
+            thought_signature: None,
+            tools_used: vec![],
+            tokens_used,
+            latency_ms: 10,
+            status: InvocationStatus::Success,
+            started_at: Utc::now().to_rfc3339(),
+            timestamp: Utc::now().to_rfc3339(),
+            artifact_id: None,
+            error_message: None,
+            replay_of: None,
+            generation: GenerationParams::default(),
+            token_split: TokenSplit::default(),
+            escalated_from: None,
         }
-        
-        self.persist_state(run_id).await;
-        tracing::error!("Run {} failed at agent {}: {}", run_id, agent_id, error);
     }
 
-    /// Helper to update status to Running (Async + Persistent)
-    async fn update_agent_status(&self, run_id: &str, agent_id: &str, status: InvocationStatus) {
-         let mut changed = false;
-         if let Some(mut state) = self.runtime_states.get_mut(run_id) {
-            match status {
-                InvocationStatus::Running => {
-                     if !state.active_agents.contains(&agent_id.to_string()) {
-                         state.active_agents.push(agent_id.to_string());
-                         changed = true;
-                     }
-                },
-                _ => {}
-            }  // Drop write lock before persisting
-         }
-         
-         if changed {
-             self.persist_state(run_id).await;
-         }
+    #[tokio::test]
+    #[traced_test]
+    async fn test_prepare_and_record_spans() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("root", &[])]);
+
+        let prepare_span = RARORuntime::prepare_span("run-1", "root");
+        runtime
+            .prepare_invocation_payload("run-1", "root")
+            .instrument(prepare_span.clone())
+            .await
+            .unwrap();
+        assert!(logs_contain("agent.prepare{run_id=run-1 agent_id=root}"));
+
+        runtime
+            .record_invocation("run-1", success_invocation("root", 42), prepare_span.id())
+            .await
+            .unwrap();
+        // Child span is nested under the prepare span
+        assert!(logs_contain("agent.prepare{run_id=run-1 agent_id=root}:agent.record{run_id=run-1 agent_id=root"));
+        assert!(logs_contain("Invocation recorded"));
     }
 
-    /// Perform the actual HTTP request to the Agent Service
-    async fn invoke_remote_agent(&self, payload: &InvocationPayload) -> Result<RemoteAgentResponse, reqwest::Error> {
-        // Resolve Agent Host from Env or Default
-        let host = env::var("AGENT_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-        let port = env::var("AGENT_PORT").unwrap_or_else(|_| "8000".to_string());
-        let scheme = if host.contains("localhost") || host == "127.0.0.1" { "http" } else { "http" };
-        
-        let url = format!("{}://{}:{}/invoke", scheme, host, port);
+    #[tokio::test]
+    async fn test_budget_warning_then_hard_limit() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-budget", vec![agent("a", &[]), agent("b", &["a"])]);
+
+        // 91% -> warning only
+        runtime.record_invocation("run-budget", success_invocation("a", 9_100), None).await.unwrap();
+        let state = runtime.get_state("run-budget").unwrap();
+        assert!(state.budget_warning);
+        assert_eq!(state.status, RuntimeStatus::Running);
+
+        // 101% -> hard failure
+        runtime.record_invocation("run-budget", success_invocation("b", 1_000), None).await.unwrap();
+        let state = runtime.get_state("run-budget").unwrap();
+        assert_eq!(state.status, RuntimeStatus::Failed);
+    }
 
-        tracing::debug!("Sending invocation request to: {}", url);
+    #[tokio::test]
+    async fn test_budget_thresholds_configurable() {
+        let runtime = RARORuntime::new();
+        seed_run_with(
+            &runtime,
+            "run-budget-cfg",
+            vec![agent("a", &[])],
+            serde_json::json!({ "budget_warning_threshold": 0.5, "budget_hard_limit": 1.2 }),
+        );
 
-        let response = self.http_client
-            .post(&url)
-            .json(payload)
-            .send()
-            .await?;
+        runtime.record_invocation("run-budget-cfg", success_invocation("a", 11_000), None).await.unwrap();
+        let state = runtime.get_state("run-budget-cfg").unwrap();
+        assert!(state.budget_warning);
+        assert_eq!(state.status, RuntimeStatus::Running);
+    }
 
-        response.json::<RemoteAgentResponse>().await
+    #[tokio::test]
+    async fn test_skipped_agent_unblocks_dependents_and_run_completes_with_warning() {
+        let runtime = RARORuntime::new();
+        seed_run_with(&runtime, "run-skip", vec![agent("a", &[]), agent("b", &["a"]), agent("c", &[])],
+            serde_json::json!({ "execution_mode": { "type": "pull" } }));
+        runtime.record_invocation("run-skip", AgentInvocation { status: InvocationStatus::Running, ..success_invocation("c", 0) }, None).await.unwrap();
+
+        // Pending and running agents can both be skipped
+        let skipped = runtime.skip_agent("run-skip", "a", "flaky upstream").await.unwrap();
+        assert_eq!((skipped.status, skipped.error_message.as_deref()), (InvocationStatus::Skipped, Some("flaky upstream")));
+        runtime.skip_agent("run-skip", "c", "hung").await.unwrap();
+        assert!(matches!(runtime.skip_agent("run-skip", "a", "again").await, Err(RuntimeError::AgentNotSkippable(_))));
+
+        let state = runtime.get_state("run-skip").unwrap();
+        assert!(state.active_agents.is_empty());
+        assert_eq!(runtime.ready_agents("run-skip").unwrap(), vec!["b"]);
+        // b gets a's empty output rather than pausing for a context drought
+        let payload = runtime.prepare_invocation_payload("run-skip", "b").await.unwrap();
+        assert_eq!(payload.input_data["a"]["skipped"], true);
+
+        // The hung call finishing late doesn't count twice
+        runtime.record_invocation("run-skip", success_invocation("c", 5), None).await.unwrap();
+        runtime.record_invocation("run-skip", success_invocation("b", 5), None).await.unwrap();
+        assert_eq!(runtime.get_state("run-skip").unwrap().completed_agents, vec!["a", "c", "b"]);
+
+        runtime.complete_run("run-skip").await;
+        assert_eq!(runtime.get_state("run-skip").unwrap().status, RuntimeStatus::Completed);
+        let interventions: Vec<String> = runtime.get_events("run-skip").iter()
+            .filter(|e| matches!(e.event_type, EventType::SystemIntervention))
+            .map(|e| e.payload["action"].as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(interventions, vec!["agent_skipped", "agent_skipped", "completed_with_skips"]);
     }
 
-    /// Store agent output to Redis with TTL
-    async fn store_artifact(
-        &self,
-        run_id: &str,
-        agent_id: &str,
-        output: &serde_json::Value,
-    ) -> Option<String> {
-        let artifact_id = format!("run:{}:agent:{}:output", run_id, agent_id);
+    #[tokio::test]
+    async fn test_concurrent_skips_record_once_and_a_retry_clears_the_skip() {
+        let runtime = RARORuntime::new();
+        seed_run_with(&runtime, "run-skip", vec![agent("a", &[]), agent("b", &["a"])],
+            serde_json::json!({ "execution_mode": { "type": "pull" } }));
+
+        let (first, second) = tokio::join!(runtime.skip_agent("run-skip", "a", "one"), runtime.skip_agent("run-skip", "a", "two"));
+        assert_eq!([first.is_ok(), second.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        let skips = |runtime: &RARORuntime| runtime.get_state("run-skip").unwrap().invocations.iter()
+            .filter(|i| i.status == InvocationStatus::Skipped).count();
+        assert_eq!(skips(&runtime), 1);
+        assert!(runtime.get_state("run-skip").unwrap().invocations[0].artifact_id.is_some());
+
+        // After a retry the agent's next result counts again
+        let retry = SupervisorDirective { action: SupervisorAction::RetryAgent, target_agent_id: "a".to_string(), reason: "try again".to_string() };
+        runtime.apply_supervisor_directive("run-skip", "b", &retry).await.unwrap();
+        assert_eq!(skips(&runtime), 0);
+        runtime.record_invocation("run-skip", success_invocation("a", 5), None).await.unwrap();
+        assert_eq!(runtime.get_state("run-skip").unwrap().completed_agents, vec!["a"]);
+        assert!(matches!(runtime.skip_agent("run-skip", "a", "late").await, Err(RuntimeError::AgentNotSkippable(_))));
+    }
 
-        let json_str = match serde_json::to_string(output) {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to serialize artifact for {}: {}", agent_id, e);
-                return None;
-            }
+    #[tokio::test]
+    async fn test_preparing_run_waits_for_launch_or_times_out() {
+        let runtime = Arc::new(RARORuntime::new());
+        let now = Utc::now();
+        let prepare = |run_id: &str, deadline: chrono::DateTime<Utc>| {
+            seed_run_with(&runtime, run_id, vec![agent("a", &[])], serde_json::json!({ "execution_mode": { "type": "pull" } }));
+            let mut state = runtime.runtime_states.get_mut(run_id).unwrap();
+            state.status = RuntimeStatus::Preparing;
+            state.prepare_deadline = Some(deadline.to_rfc3339());
         };
+        prepare("run-prep", now + chrono::Duration::minutes(10));
+        prepare("run-stale", now - chrono::Duration::seconds(1));
+
+        // Nothing can be dispatched or recorded before launch
+        assert!(runtime.ready_agents("run-prep").unwrap().is_empty());
+        assert!(matches!(runtime.prepare_invocation_payload("run-prep", "a").await, Err(RuntimeError::RunNotLaunched(_))));
+        assert!(matches!(runtime.record_invocation("run-prep", success_invocation("a", 10), None).await, Err(RuntimeError::RunNotLaunched(_))));
+
+        // Inputs are attached against the run id, by its owner only
+        assert!(matches!(runtime.register_run_cache("run-prep", "other", "cachedContents/x".to_string()), Err(RuntimeError::RunNotFound(_))));
+        runtime.register_run_cache("run-prep", "public", "cachedContents/x".to_string()).unwrap();
+
+        runtime.launch_run("run-prep", "public").await.unwrap();
+        let state = runtime.get_state("run-prep").unwrap();
+        assert_eq!((state.status, state.prepare_deadline), (RuntimeStatus::Running, None));
+        assert_eq!(runtime.ready_agents("run-prep").unwrap(), vec!["a"]);
+        assert!(matches!(runtime.launch_run("run-prep", "public").await, Err(RuntimeError::NotPreparing(_))));
+        assert!(matches!(runtime.register_run_cache("run-prep", "public", "cachedContents/y".to_string()), Err(RuntimeError::NotPreparing(_))));
+
+        assert_eq!(runtime.cancel_expired_preparations(now).await, vec!["run-stale"]);
+        let stale = runtime.get_state("run-stale").unwrap();
+        assert_eq!((stale.status, stale.failed_agents[0].error_code), (RuntimeStatus::Failed, FailureCode::PrepareTimeout));
+        assert_eq!(runtime.get_state("run-prep").unwrap().status, RuntimeStatus::Running);
+    }
 
-        if let Some(client) = &self.redis_client {
-            match client.get_async_connection().await {
-                Ok(mut con) => {
-                    match con.set_ex::<_, _, ()>(&artifact_id, json_str, 3600).await {
-                        Ok(_) => {
-                            tracing::debug!("Stored artifact: {}", artifact_id);
-                            return Some(artifact_id);
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed to write artifact to Redis: {}", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to get Redis connection: {}", e);
-                }
-            }
-        } else {
-            tracing::debug!("No Redis client available, artifact not stored");
+    #[tokio::test]
+    async fn test_budget_threshold_events_fire_once_each() {
+        let runtime = RARORuntime::new();
+        let warnings = |runtime: &RARORuntime, run_id: &str| -> Vec<u64> {
+            runtime.get_events(run_id).iter()
+                .filter(|e| matches!(e.event_type, EventType::TokenBudgetWarning))
+                .map(|e| e.payload["threshold"].as_u64().unwrap())
+                .collect()
+        };
+
+        // Default thresholds: 50% and 80% of the 10k budget
+        seed_run(&runtime, "run-thresholds", vec![agent("a", &[]), agent("b", &[]), agent("c", &[]), agent("d", &[])]);
+        for (agent_id, tokens, expected) in [("a", 3_000, vec![]), ("b", 2_500, vec![50]), ("c", 1_000, vec![50]), ("d", 2_000, vec![50, 80])] {
+            runtime.record_invocation("run-thresholds", success_invocation(agent_id, tokens), None).await.unwrap();
+            assert_eq!(warnings(&runtime, "run-thresholds"), expected, "after {}", agent_id);
         }
+        let event = runtime.get_events("run-thresholds").into_iter().find(|e| matches!(e.event_type, EventType::TokenBudgetWarning)).unwrap();
+        assert_eq!((event.payload["used"].as_u64(), event.payload["budget"].as_u64()), (Some(5_500), Some(10_000)));
+
+        // One invocation jumping several configured thresholds announces each of them once
+        seed_run_with(&runtime, "run-jump", vec![agent("a", &[]), agent("b", &[])], serde_json::json!({ "budget_warning_thresholds": [30, 10, 20] }));
+        runtime.record_invocation("run-jump", success_invocation("a", 3_500), None).await.unwrap();
+        runtime.record_invocation("run-jump", success_invocation("b", 100), None).await.unwrap();
+        assert_eq!(warnings(&runtime, "run-jump"), vec![10, 20, 30]);
+    }
 
-        None
+    #[tokio::test]
+    async fn test_export_import_roundtrip() {
+        let source = RARORuntime::new();
+        seed_run(&source, "run-export", vec![agent("a", &[]), agent("b", &["a"])]);
+        source.record_invocation("run-export", success_invocation("a", 100), None).await.unwrap();
+        source.set_thought_signature("run-export", "a", "sig-a".to_string()).unwrap();
+        source.emit_event(RuntimeEvent::new("run-export", EventType::AgentCompleted, Some("a".to_string()), serde_json::json!({})));
+
+        let bundle = source.export_run("run-export").await.unwrap();
+        let json = serde_json::to_string(&bundle).unwrap();
+
+        // Fresh runtime with no workflow registration
+        let target = RARORuntime::new();
+        let run_id = target.import_run(serde_json::from_str(&json).unwrap(), "client-b").await.unwrap();
+
+        let state = target.get_state(&run_id).unwrap();
+        assert_eq!(state.client_id, "client-b");
+        assert_eq!(state.completed_agents, vec!["a".to_string()]);
+        assert_eq!(state.status, RuntimeStatus::AwaitingApproval);
+        assert_eq!(target.get_thought_signature(&run_id, "a"), Some("sig-a".to_string()));
+        assert_eq!(target.dag_store.get(&run_id).unwrap().get_dependencies("b"), vec!["a".to_string()]);
+        // AgentCompleted plus the SignatureUpdated for "a" (besides the StateChanged deltas)
+        let events = target.get_events(&run_id);
+        assert_eq!(events.iter().filter(|e| !matches!(e.event_type, EventType::StateChanged)).count(), 2);
+
+        // Importing twice is a conflict
+        let again = target.import_run(serde_json::from_str(&json).unwrap(), "client-b").await;
+        assert!(matches!(again, Err(RuntimeError::RunAlreadyExists(_))));
+
+        // A registered workflow of the same id stays as it is; the import gets its own copy
+        let mut renamed: RunExport = serde_json::from_str(&json).unwrap();
+        renamed.state.run_id = "run-export-2".to_string();
+        renamed.workflow.name = "imported".to_string();
+        let second = target.import_run(renamed, "client-c").await.unwrap();
+        let pinned = target.get_state(&second).unwrap().workflow_id;
+        assert_eq!(pinned, pinned_workflow_id(&state.workflow_id, &second));
+        assert_eq!(target.workflows.get(&pinned).unwrap().name, "imported");
+        assert_ne!(target.workflows.get(&state.workflow_id).unwrap().name, "imported");
     }
-    /// Get current runtime state
 
-    pub fn get_state(&self, run_id: &str) -> Option<RuntimeState> {
-        self.runtime_states.get(run_id).map(|r| (*r).clone())
+    #[tokio::test]
+    async fn test_halt_client_cancels_runs_and_blocks_new_ones() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-a", vec![agent("a", &[])]);
+        seed_run(&runtime, "run-b", vec![agent("a", &[])]);
+        runtime.runtime_states.get_mut("run-b").unwrap().client_id = "other".to_string();
+
+        let cancelled = runtime.halt_client("public", true, "Incident").await;
+        assert_eq!(cancelled, vec!["run-a".to_string()]);
+        assert_eq!(runtime.get_state("run-a").unwrap().status, RuntimeStatus::Failed);
+        assert_eq!(runtime.get_state("run-b").unwrap().status, RuntimeStatus::Running);
+
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf-new", "name": "new", "agents": [], "max_token_budget": 1, "timeout_ms": 1
+        })).unwrap();
+        assert!(runtime.start_workflow(config, "public").is_err());
+
+        assert!(runtime.unhalt_client("public"));
+        assert!(!runtime.is_client_halted("public"));
     }
-    /// Record an agent invocation (Async + Persistent)
 
-    pub async fn record_invocation(&self, run_id: &str, invocation: AgentInvocation) -> Result<(), String> {
-        {
-            let mut state = self
-                .runtime_states
-                .get_mut(run_id)
-                .ok_or_else(|| "Run not found".to_string())?;
+    #[tokio::test]
+    async fn test_recorded_invocations_update_agent_stats() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[])]);
+        seed_run(&runtime, "run-2", vec![agent("a", &[]), agent("b", &[])]);
+        // Both runs are of the same workflow
+        runtime.runtime_states.get_mut("run-2").unwrap().workflow_id = "wf-run-1".to_string();
+
+        runtime.record_invocation("run-1", AgentInvocation { latency_ms: 1_000, ..success_invocation("a", 400) }, None).await.unwrap();
+        runtime.record_invocations("run-2", vec![
+            AgentInvocation { latency_ms: 3_000, ..success_invocation("a", 900) },
+            AgentInvocation { status: InvocationStatus::Failed, ..success_invocation("b", 50) },
+        ]).await.unwrap();
+
+        let stats = runtime.workflow_stats("wf-run-1", "public").unwrap();
+        assert_eq!(stats["a"].samples, 2);
+        // Moved from the first observation toward the second
+        assert!(stats["a"].latency_ms_ema > 1_000.0 && stats["a"].latency_ms_ema < 3_000.0);
+        assert!(stats["a"].tokens_ema > 400.0 && stats["a"].tokens_ema < 900.0);
+        assert!(!stats.contains_key("b"));
+        // Only the workflow's own client sees them
+        assert!(matches!(runtime.workflow_stats("wf-run-1", "intruder"), Err(RuntimeError::WorkflowNotFound(_))));
+    }
 
-            state.invocations.push(invocation.clone());
-            state.total_tokens_used += invocation.tokens_used;
+    #[tokio::test]
+    async fn test_client_model_policy_rejects_forbidden_models_and_caps_daily_use() {
+        use crate::client_policy::ClientProfile;
+        let runtime = Arc::new(RARORuntime::new());
+        runtime.client_policies.set_profile("public", ClientProfile {
+            allowed_models: Some(vec![ModelVariant::Fast, ModelVariant::Thinking]),
+            daily_model_quotas: std::collections::BTreeMap::from([("thinking".to_string(), 1)]),
+        });
 
-            match invocation.status {
-                InvocationStatus::Running => {
-                    if !state.active_agents.contains(&invocation.agent_id) {
-                        state.active_agents.push(invocation.agent_id.clone());
-                    }
-                }
-                InvocationStatus::Success => {
-                    state.active_agents.retain(|a| a != &invocation.agent_id);
-                    state.completed_agents.push(invocation.agent_id.clone());
-                }
-                InvocationStatus::Failed => {
-                    state.active_agents.retain(|a| a != &invocation.agent_id);
-                    state.failed_agents.push(invocation.agent_id.clone());
-                }
-                _ => {}
+        // Reasoning directly, through agent_defaults, and by its raw API model id
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf-policy", "name": "policy", "max_token_budget": 10_000, "timeout_ms": 60_000,
+            "execution_mode": { "type": "pull" },
+            "agent_defaults": { "model": "reasoning" },
+            "agents": [
+                { "id": "a", "role": "worker", "model": "reasoning", "prompt": "", "position": null },
+                { "id": "b", "role": "worker", "prompt": "", "position": null },
+                { "id": "c", "role": "worker", "model": "gemini-3-pro-preview", "prompt": "", "position": null },
+                { "id": "d", "role": "worker", "model": "thinking", "prompt": "", "position": null }
+            ]
+        })).unwrap();
+        match runtime.start_workflow(config, "public") {
+            Err(RuntimeError::InvalidWorkflow(errors)) => assert_eq!(
+                errors.iter().map(|e| match e {
+                    ValidationError::ModelNotAllowed { agent_id, .. } => agent_id.as_str(),
+                    other => panic!("unexpected {:?}", other),
+                }).collect::<Vec<_>>(),
+                vec!["a", "b", "c"]
+            ),
+            other => panic!("expected a policy rejection, got {:?}", other.map(|_| ())),
+        }
+
+        // One thinking invocation a day
+        let thinking = |id: &str| AgentNodeConfig { model: ModelVariant::Thinking, ..agent(id, &[]) };
+        seed_run(&runtime, "run-quota", vec![thinking("a"), thinking("b")]);
+        let payload = runtime.prepare_invocation_payload("run-quota", "a").await.unwrap();
+        // Thinking shares its API model with fast, yet is recorded as thinking
+        assert_eq!(payload.model_variant(&runtime.model_registry), ModelVariant::Thinking);
+        assert!(matches!(
+            runtime.prepare_invocation_payload("run-quota", "b").await,
+            Err(RuntimeError::ModelQuotaExceeded { limit: 1, .. })
+        ));
+        assert_eq!(runtime.client_policies.report("public").used_today["thinking"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_config_patchable_until_dispatch() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-patch", vec![agent("a", &[]), agent("b", &["a"])]);
+        // A second run of the same workflow keeps the original config
+        seed_run(&runtime, "run-sibling", vec![agent("a", &[]), agent("b", &["a"])]);
+        runtime.runtime_states.get_mut("run-sibling").unwrap().workflow_id = "wf-run-patch".to_string();
+        runtime.record_invocation("run-patch", AgentInvocation { status: InvocationStatus::Running, ..success_invocation("a", 0) }, None).await.unwrap();
+
+        let patch = PatchAgentConfig { prompt: Some("Focus on the outliers".to_string()), thinking_budget: Some(8), max_output_tokens: Some(2048) };
+        let patched = runtime.patch_agent_config("run-patch", "public", "b", &patch).await.unwrap();
+        assert_eq!((patched.prompt.as_str(), patched.thinking_budget), ("Focus on the outliers", Some(8)));
+
+        // Out-of-range values are refused and leave the config alone
+        let bad = PatchAgentConfig { thinking_budget: Some(11), ..Default::default() };
+        assert!(matches!(runtime.patch_agent_config("run-patch", "public", "b", &bad).await, Err(RuntimeError::InvalidWorkflow(_))));
+        // Running, then finished, agents can't be patched; nor can other clients' runs
+        assert!(matches!(runtime.patch_agent_config("run-patch", "public", "a", &patch).await, Err(RuntimeError::AgentNotPatchable { reason: "already running", .. })));
+        runtime.store_artifact("run-patch", "a", &serde_json::json!({ "result": "early findings" })).await;
+        runtime.record_invocation("run-patch", success_invocation("a", 10), None).await.unwrap();
+        assert!(matches!(runtime.patch_agent_config("run-patch", "public", "a", &patch).await, Err(RuntimeError::AgentNotPatchable { reason: "already finished", .. })));
+        assert!(matches!(runtime.patch_agent_config("run-patch", "other", "b", &patch).await, Err(RuntimeError::RunNotFound(_))));
+
+        // The dispatch uses the patched config
+        let payload = runtime.prepare_invocation_payload("run-patch", "b").await.unwrap();
+        assert!(payload.prompt.contains("Focus on the outliers"));
+        assert_eq!(payload.max_output_tokens, Some(2048));
+        let pinned = pinned_workflow_id("wf-run-patch", "run-patch");
+        assert_eq!(runtime.get_state("run-patch").unwrap().workflow_id, pinned);
+        assert_eq!(runtime.workflows.get(&pinned).unwrap().agents[1].thinking_budget, Some(8));
+        assert_eq!(runtime.workflows.get("wf-run-patch").unwrap().agents[1].thinking_budget, None);
+        assert_eq!(runtime.get_state("run-sibling").unwrap().workflow_id, "wf-run-patch");
+    }
+
+    #[tokio::test]
+    async fn test_token_split_sets_totals_and_cache_savings() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-split", vec![agent("a", &[]), agent("b", &[])]);
+
+        let split = TokenSplit { prompt_tokens: Some(10_000), completion_tokens: Some(1_000), cached_tokens: Some(8_000), thinking_tokens: Some(2_000) };
+        // The reported total disagrees with the split; the split wins
+        runtime.record_invocation("run-split", AgentInvocation {
+            model_variant: ModelVariant::Thinking,
+            token_split: split,
+            ..success_invocation("a", 5)
+        }, None).await.unwrap();
+        // No split: counted as reported
+        runtime.record_invocation("run-split", success_invocation("b", 700), None).await.unwrap();
+
+        assert_eq!(runtime.get_state("run-split").unwrap().total_tokens_used, 13_700);
+        let usage = runtime.model_usage();
+        let thinking = usage[&ModelVariant::Thinking].token_breakdown;
+        assert_eq!((thinking.prompt_tokens, thinking.completion_tokens, thinking.cached_tokens, thinking.thinking_tokens), (10_000, 1_000, 8_000, 2_000));
+        // 8k cached tokens at $4/M instead of $1/M
+        assert!((thinking.cache_savings_usd - 0.024).abs() < 1e-9);
+        assert_eq!(usage[&ModelVariant::Fast].token_breakdown, observability::TokenBreakdown::default());
+
+        let stats = runtime.agent_stats.for_workflow("wf-run-split").unwrap();
+        assert_eq!(stats["a"].last_tokens, 13_000);
+        assert_eq!(stats["a"].token_breakdown, thinking);
+    }
+
+    #[tokio::test]
+    async fn test_cross_run_cache_reuses_output_of_identical_run() {
+        let mut runtime = RARORuntime::new();
+        runtime.output_cache = OutputCache::new(None);
+        let mut cached = agent("a", &[]);
+        cached.cache_policy = CROSS_RUN_CACHE_POLICY.to_string();
+        for (run_id, client_id) in [("first", "public"), ("second", "public"), ("other-tenant", "tenant-b")] {
+            seed_run_with(&runtime, run_id, vec![cached.clone(), agent("b", &["a"])],
+                // Same workflow id in every run (stored per run by the helper)
+                serde_json::json!({ "id": "wf-shared", "simulation": true, "simulation_delay_ms": 1 }));
+            let mut state = runtime.runtime_states.get_mut(run_id).unwrap();
+            state.simulation = true;
+            state.client_id = client_id.to_string();
+            drop(state);
+            tokio::time::timeout(std::time::Duration::from_secs(10), runtime.execute_dynamic_dag(run_id.to_string()))
+                .await
+                .expect("simulated run should finish");
+        }
+
+        let tokens = |run_id: &str, agent_id: &str| runtime.get_state(run_id).unwrap().invocations.iter()
+            .find(|i| i.agent_id == agent_id && i.status == InvocationStatus::Success).map(|i| i.tokens_used).unwrap();
+        assert!(tokens("first", "a") > 0);
+        // Served from the cache; the ephemeral agent still ran
+        assert_eq!(tokens("second", "a"), 0);
+        assert!(tokens("second", "b") > 0);
+        assert_eq!(runtime.get_state("second").unwrap().status, RuntimeStatus::Completed);
+        assert_eq!(runtime.get_agent_output("second", "a").await.unwrap(), runtime.get_agent_output("first", "a").await.unwrap());
+        // Another tenant with the same workflow id and inputs gets its own entry
+        assert!(tokens("other-tenant", "a") > 0);
+
+        let stats = runtime.output_cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 1, 2));
+        assert_eq!(stats.tokens_saved, tokens("first", "a") as u64);
+        assert!(runtime.prometheus_metrics().contains("raro_output_cache_hits_total 1"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_agent_retries_then_escalates() {
+        let runtime = RARORuntime::new();
+        let mut a = agent("a", &[]);
+        a.retry = Some(RetryPolicy { max_retries: 1, escalation: Some(ModelVariant::Reasoning) });
+        seed_run(&runtime, "run-retry", vec![a.clone()]);
+
+        let payload = runtime.prepare_invocation_payload("run-retry", "a").await.unwrap();
+        let fast_model = payload.model.clone();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let failing_model = fast_model.clone();
+        let (sent, response, escalated_from) = runtime.invoke_with_retries("run-retry", "inv-1", Some(&a), payload, None, move |payload| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let mut res = simulation::respond(&payload, std::time::Duration::ZERO);
+            if payload.model == failing_model {
+                res.success = false;
+                res.error = Some("flash gave up".to_string());
             }
-        } 
+            tokio::spawn(async move { Ok(res) })
+        }).await;
+
+        // Two attempts on the agent's own model, then one on the escalation variant
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert!(response.unwrap().unwrap().success);
+        assert_ne!(sent.model, fast_model);
+        assert_eq!(sent.model_variant(&runtime.model_registry), ModelVariant::Reasoning);
+        assert_eq!(escalated_from, Some(ModelVariant::Fast));
+
+        let state = runtime.get_state("run-retry").unwrap();
+        let failed: Vec<_> = state.invocations.iter().filter(|i| i.status == InvocationStatus::Failed).collect();
+        assert_eq!(failed.len(), 2);
+        assert!(failed.iter().all(|i| i.model_variant == ModelVariant::Fast));
+        assert_eq!(runtime.agent_stats.for_workflow(&state.workflow_id).unwrap()["a"].escalations, 1);
+        assert!(runtime.prometheus_metrics().contains(&format!("raro_agent_escalations_total{{workflow_id=\"{}\"}} 1\n", state.workflow_id)));
+        assert!(state.failed_agents.is_empty());
+    }
 
-        self.persist_state(run_id).await;
+    #[tokio::test]
+    async fn test_post_flight_failures_are_retried_within_the_model_quota() {
+        use crate::client_policy::ClientProfile;
+        let runtime = RARORuntime::new();
+        runtime.client_policies.set_profile("public", ClientProfile {
+            allowed_models: None,
+            daily_model_quotas: std::collections::BTreeMap::from([("fast".to_string(), 2)]),
+        });
+        let mut a = agent("a", &[]);
+        a.retry = Some(RetryPolicy { max_retries: 5, escalation: None });
+        a.output_schema = serde_json::json!({ "type": "object", "required": ["summary"] });
+        seed_run(&runtime, "run-post-flight", vec![a.clone()]);
+
+        let payload = runtime.prepare_invocation_payload("run-post-flight", "a").await.unwrap();
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let (_, response, _) = runtime.invoke_with_retries("run-post-flight", "inv-1", Some(&a), payload, None, move |payload| {
+            let attempt = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let mut res = simulation::respond(&payload, std::time::Duration::ZERO);
+            res.output = Some(match attempt {
+                0 => serde_json::json!({ "result": "[STATUS: NULL]" }),
+                _ => serde_json::json!({ "result": "done" }),
+            });
+            tokio::spawn(async move { Ok(res) })
+        }).await;
+
+        // Semantic null retried; the schema failure would be too, but the fast quota (prepare + one retry) is spent
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(runtime.client_policies.report("public").used_today["fast"], 2);
+        let last = response.unwrap().unwrap();
+        assert!(RARORuntime::post_flight_problem("a", Some(&a), &last).unwrap().contains("output_schema"));
+
+        // The retried attempt is accounted like any invocation but doesn't fail the agent
+        let state = runtime.get_state("run-post-flight").unwrap();
+        let failed: Vec<_> = state.invocations.iter().filter(|i| i.status == InvocationStatus::Failed).collect();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].error_message.as_deref().unwrap().contains("Semantic Null"));
+        assert_eq!(state.total_tokens_used, failed[0].tokens_used);
+        assert!(state.failed_agents.is_empty());
+        assert_eq!(runtime.usage.report("public", 0, 0).invocations, 1);
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_share_link_shows_finished_run_to_anyone_until_revoked() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-share", vec![agent("a", &[]), agent("b", &["a"])]);
+
+        // Owner only, and only once the run is over
+        assert!(matches!(runtime.create_share("run-share", "public", ShareRequest::default()).await, Err(RuntimeError::RunInProgress(_))));
+        {
+            let mut state = runtime.runtime_states.get_mut("run-share").unwrap();
+            state.status = RuntimeStatus::Completed;
+            state.completed_agents = vec!["a".to_string()];
+            state.skipped_agents = vec!["b".to_string()];
+        }
+        assert!(matches!(runtime.create_share("run-share", "intruder", ShareRequest::default()).await, Err(RuntimeError::RunNotFound(_))));
+        let unpromoted = ShareRequest { artifacts: vec!["report.md".to_string()], expires_in_secs: None };
+        assert!(matches!(runtime.create_share("run-share", "public", unpromoted).await, Err(RuntimeError::Storage(_))));
+        let too_long = ShareRequest { artifacts: vec![], expires_in_secs: Some(share::MAX_SHARE_TTL_SECS + 1) };
+        assert!(matches!(runtime.create_share("run-share", "public", too_long).await, Err(RuntimeError::InvalidConfig(_))));
+
+        let created = runtime.create_share("run-share", "public", ShareRequest::default()).await.unwrap();
+        let view = runtime.shared_run(&created.token).await.unwrap();
+        let statuses: Vec<(&str, &str)> = view.agents.iter().map(|a| (a.agent_id.as_str(), a.status)).collect();
+        assert_eq!(statuses, vec![("a", "completed"), ("b", "skipped")]);
+        let body = serde_json::to_string(&view).unwrap();
+        assert!(!body.contains("You are a") && !body.contains("signature"));
+        assert!(matches!(runtime.shared_artifact_path(&created.token, "report.md"), Err(RuntimeError::Storage(_))));
+
+        assert_eq!(runtime.list_shares("run-share", "public").unwrap().len(), 1);
+        assert!(matches!(runtime.revoke_share("run-share", "public", "nope"), Err(RuntimeError::ShareNotFound(_))));
+        runtime.revoke_share("run-share", "public", &created.link.share_id).unwrap();
+        assert!(matches!(runtime.shared_run(&created.token).await, Err(RuntimeError::InvalidShareToken)));
     }
-    /// Store or retrieve thought signature
 
-    pub fn set_thought_signature(&self, run_id: &str, agent_id: &str, signature: String) -> Result<(), String> {
-        let mut store = self
-            .thought_signatures
-            .get_mut(run_id)
-            .ok_or_else(|| "Run not found".to_string())?;
+    #[tokio::test]
+    async fn test_completed_run_gets_verdict_from_success_criteria() {
+        let runtime = Arc::new(RARORuntime::new());
+        let criteria = serde_json::json!({ "success_criteria": {
+            "output_schema": { "type": "object", "required": ["summary"] },
+            "conditions": ["agents.review.output.score >= 0.8"]
+        }});
+        for (run_id, score) in [("run-pass", 0.9), ("run-fail", 0.4)] {
+            seed_run_with(&runtime, run_id, vec![agent("review", &[]), agent("writer", &["review"])], criteria.clone());
+            for (agent_id, output) in [("review", serde_json::json!({ "score": score })), ("writer", serde_json::json!({ "summary": "ok" }))] {
+                runtime.local_outputs.insert(format!("run:{}:agent:{}:output", run_id, agent_id), output);
+            }
+            runtime.runtime_states.get_mut(run_id).unwrap().completed_agents = vec!["review".to_string(), "writer".to_string()];
+            runtime.complete_run(run_id).await;
+        }
 
-        store.signatures.insert(agent_id.to_string(), signature);
-        Ok(())
+        assert_eq!(runtime.get_state("run-pass").unwrap().verdict, Some(RunVerdict::Passed));
+        let failed = runtime.get_state("run-fail").unwrap();
+        assert_eq!(failed.status, RuntimeStatus::Completed);
+        assert_eq!(failed.verdict, Some(RunVerdict::Failed {
+            reasons: vec!["condition 'agents.review.output.score >= 0.8' does not hold".to_string()],
+        }));
+        let recorded: Vec<serde_json::Value> = runtime.get_events("run-fail").iter()
+            .filter(|e| matches!(e.event_type, EventType::VerdictRecorded))
+            .map(|e| e.payload.clone())
+            .collect();
+        assert_eq!(recorded, vec![serde_json::json!({ "result": "failed", "reasons": ["condition 'agents.review.output.score >= 0.8' does not hold"] })]);
+
+        // Malformed conditions are refused up front rather than failing every run
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf-bad", "name": "wf", "max_token_budget": 10_000, "timeout_ms": 1000,
+            "agents": [{ "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "go" }],
+            "success_criteria": { "conditions": ["agents.a.output.score ~ 1"] }
+        })).unwrap();
+        assert!(matches!(
+            runtime.start_workflow(config, "public"),
+            Err(RuntimeError::InvalidWorkflow(errors)) if matches!(errors[0], ValidationError::InvalidSuccessCriteria(_))
+        ));
     }
 
-    pub fn get_thought_signature(&self, run_id: &str, agent_id: &str) -> Option<String> {
-        self.thought_signatures
-            .get(run_id)
-            .and_then(|store| store.signatures.get(agent_id).cloned())
+    #[tokio::test]
+    async fn test_injected_faults_are_recorded_and_recovered_from() {
+        let mut runtime = RARORuntime::new();
+        runtime.fault_injection = true;
+        let mut a = agent("a", &[]);
+        a.retry = Some(RetryPolicy { max_retries: 1, escalation: None });
+        seed_run_with(&runtime, "run-faults", vec![a, agent("b", &["a"])], serde_json::json!({
+            "simulation": true, "simulation_delay_ms": 1,
+            "faults": [
+                { "agent": "a", "attempts": 1, "fault": { "type": "error" } },
+                { "agent": "b", "fault": { "type": "invalid_output", "output": { "broken": true } } }
+            ]
+        }));
+        runtime.runtime_states.get_mut("run-faults").unwrap().simulation = true;
+        tokio::time::timeout(std::time::Duration::from_secs(10), runtime.execute_dynamic_dag("run-faults".to_string()))
+            .await
+            .expect("run should recover and finish");
+
+        // a's first attempt failed with the injected 503 and was retried
+        let state = runtime.get_state("run-faults").unwrap();
+        assert_eq!(state.status, RuntimeStatus::Completed);
+        let attempts: Vec<(InvocationStatus, Option<String>)> = state.invocations.iter()
+            .filter(|i| i.agent_id == "a")
+            .map(|i| (i.status.clone(), i.error_message.clone()))
+            .collect();
+        assert_eq!(attempts, vec![
+            (InvocationStatus::Failed, Some("Attempt failed, retried: Injected fault: agent service answered HTTP 503".to_string())),
+            (InvocationStatus::Success, None),
+        ]);
+        assert_eq!(runtime.get_agent_output("run-faults", "b").await.unwrap().unwrap()["broken"], true);
+
+        let injected: Vec<(Option<String>, serde_json::Value)> = runtime.get_events("run-faults").iter()
+            .filter(|e| matches!(e.event_type, EventType::FaultInjected))
+            .map(|e| (e.agent_id.clone(), e.payload.clone()))
+            .collect();
+        assert_eq!(injected, vec![
+            (Some("a".to_string()), serde_json::json!({ "fault": { "type": "error", "status": 503 }, "attempt": 1 })),
+            (Some("b".to_string()), serde_json::json!({ "fault": { "type": "invalid_output", "output": { "broken": true } }, "attempt": 1 })),
+        ]);
+
+        // Deployments without the flag refuse faulty workflows outright
+        let mut production = RARORuntime::new();
+        production.fault_injection = false;
+        let config = runtime.workflows.get("wf-run-faults").unwrap().clone();
+        let disabled = |result: Result<_, RuntimeError>| matches!(
+            result, Err(RuntimeError::InvalidWorkflow(errors)) if errors == vec![ValidationError::FaultInjectionDisabled]
+        );
+        let production = Arc::new(production);
+        assert!(disabled(production.start_workflow(config, "public").map(|_| ())));
+        // ...nor can faults arrive through an import or a patch
+        let export = runtime.export_run("run-faults").await.unwrap();
+        assert!(disabled(production.import_run(export, "public").await.map(|_| ())));
+        seed_run(&production, "run-clean", vec![agent("a", &[])]);
+        let patch: Vec<PatchOp> = serde_json::from_value(serde_json::json!([
+            { "op": "add", "path": "/faults", "value": [{ "agent": "a", "fault": { "type": "error" } }] }
+        ])).unwrap();
+        assert!(disabled(production.patch_workflow("wf-run-clean", "public", &patch).await.map(|_| ())));
     }
 
-    pub fn get_all_signatures(&self, run_id: &str) -> Option<ThoughtSignatureStore> {
-        self.thought_signatures.get(run_id).map(|s| (*s).clone())
+    #[tokio::test]
+    async fn test_gc_purges_old_finished_runs() {
+        let runtime = Arc::new(RARORuntime::new());
+        let old = (Utc::now() - chrono::Duration::hours(3)).to_rfc3339();
+        for (run_id, status, ended) in [
+            ("old-done", RuntimeStatus::Completed, Some(old.clone())),
+            ("old-failed", RuntimeStatus::Failed, Some(old.clone())),
+            ("fresh-done", RuntimeStatus::Completed, Some(Utc::now().to_rfc3339())),
+            ("old-running", RuntimeStatus::Running, None),
+        ] {
+            seed_run(&runtime, run_id, vec![agent("a", &[])]);
+            let mut state = runtime.runtime_states.get_mut(run_id).unwrap();
+            state.status = status;
+            state.start_time = old.clone();
+            state.end_time = ended;
+        }
+        runtime.thought_signatures.get_mut("old-done").unwrap().signatures.insert("a".to_string(), "sig".to_string());
+        runtime.emit_event(RuntimeEvent::new("old-done", EventType::AgentCompleted, Some("a".to_string()), serde_json::json!({})));
+
+        let request = |status_filter: Vec<RuntimeStatus>, dry_run: bool| GcRequest { max_age_minutes: 60, status_filter, dry_run };
+        let preview = runtime.collect_garbage(&request(vec![], true)).unwrap();
+        assert_eq!((preview.deleted_run_count, preview.freed_signatures), (2, 1));
+        assert_eq!(preview.run_ids, vec!["old-done", "old-failed"]);
+        assert!(runtime.get_state("old-done").is_some());
+
+        let failed_only = runtime.collect_garbage(&request(vec![RuntimeStatus::Failed], false)).unwrap();
+        assert_eq!(failed_only.run_ids, vec!["old-failed"]);
+        let rest = runtime.collect_garbage(&request(vec![], false)).unwrap();
+        assert_eq!(rest, GcResult { deleted_run_count: 1, freed_signatures: 1, run_ids: vec!["old-done".to_string()], ..GcResult::default() });
+        assert!(runtime.get_state("old-done").is_none());
+        assert!(runtime.get_events("old-done").is_empty());
+        assert_eq!(runtime.system_status().map_entries["thought_signatures"], 2);
+
+        // Live runs are never collected, whatever their age
+        assert!(matches!(runtime.delete_run("old-running"), Err(RuntimeError::RunInProgress(_))));
+        assert!(matches!(runtime.collect_garbage(&request(vec![RuntimeStatus::Running], true)), Err(RuntimeError::InvalidConfig(_))));
+        // Ages past what a timestamp can express are refused rather than panicking
+        for max_age_minutes in [u64::MAX, i64::MAX as u64] {
+            let huge = GcRequest { max_age_minutes, status_filter: vec![], dry_run: true };
+            assert!(matches!(runtime.collect_garbage(&huge), Err(RuntimeError::InvalidConfig(_))));
+        }
     }
 
-    /// Generate a contextual graph view based on agent's delegation privilege.
-    /// Includes "Optics" (specialty info) for Pending nodes to facilitate updates.
-    ///
-    /// - **detailed=true**: Returns JSON array with full topology (for orchestrators)
-    /// - **detailed=false**: Returns linear text view (for workers)
-    fn generate_graph_context(&self, run_id: &str, current_agent_id: &str, detailed: bool) -> String {
-        let state = match self.runtime_states.get(run_id) {
-            Some(s) => s,
-            None => return "Graph state unavailable.".to_string(),
-        };
+    #[tokio::test]
+    async fn test_second_pull_prepare_is_refused_until_the_first_is_recorded() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-dup", vec![agent("a", &[]), agent("b", &[])]);
 
-        let dag = match self.dag_store.get(run_id) {
-            Some(d) => d,
-            None => return "Graph topology unavailable.".to_string(),
-        };
+        let (first, second) = tokio::join!(
+            runtime.prepare_exclusive_invocation("run-dup", "a"),
+            runtime.prepare_exclusive_invocation("run-dup", "a"),
+        );
+        assert_eq!([first.is_ok(), second.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        let refused = if first.is_err() { first } else { second };
+        assert!(matches!(refused, Err(RuntimeError::AgentInFlight { ref agent_id, .. }) if agent_id == "a"));
+        // Other agents are unaffected
+        runtime.prepare_exclusive_invocation("run-dup", "b").await.unwrap();
+
+        // A Running record keeps the claim; the final record releases it
+        runtime.record_invocation("run-dup", AgentInvocation { status: InvocationStatus::Running, ..success_invocation("a", 0) }, None).await.unwrap();
+        assert!(matches!(runtime.prepare_exclusive_invocation("run-dup", "a").await, Err(RuntimeError::AgentInFlight { .. })));
+        runtime.record_invocation("run-dup", success_invocation("a", 10), None).await.unwrap();
+        runtime.prepare_exclusive_invocation("run-dup", "a").await.unwrap();
+
+        // A failed prepare leaves nothing behind
+        assert!(matches!(runtime.prepare_exclusive_invocation("run-dup", "ghost").await, Err(RuntimeError::AgentNotFound(_))));
+        assert!(!runtime.agents_in_flight.contains_key(&("run-dup".to_string(), "ghost".to_string())));
+
+        // A mark outstanding past the agent's timeout was abandoned by its caller
+        assert!(matches!(runtime.prepare_exclusive_invocation("run-dup", "b").await, Err(RuntimeError::AgentInFlight { .. })));
+        runtime.workflows.get_mut("wf-run-dup").unwrap().timeout_per_agent_ms = Some(60_000);
+        runtime.agents_in_flight.insert(("run-dup".to_string(), "b".to_string()), (Utc::now() - chrono::Duration::minutes(5)).to_rfc3339());
+        runtime.prepare_exclusive_invocation("run-dup", "b").await.unwrap();
+        assert!(matches!(runtime.prepare_exclusive_invocation("run-dup", "b").await, Err(RuntimeError::AgentInFlight { .. })));
+
+        // Ending the run releases whatever is still marked
+        runtime.fail_run("run-dup", "b", FailureCode::AgentError, "gave up").await;
+        assert_eq!(runtime.system_status().map_entries["agents_in_flight"], 0);
+    }
 
-        let workflow_id = state.workflow_id.clone();
+    #[tokio::test]
+    async fn test_set_run_metadata_pattern_accumulates_a_searchable_trail() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-flagged", vec![agent("a", &[])]);
+        seed_run(&runtime, "run-clean", vec![agent("a", &[])]);
+        runtime.runtime_states.get_mut("run-flagged").unwrap().metadata
+            .insert("review".to_string(), serde_json::json!({ "owner": "ops", "score": 1 }));
+        for (condition, flag) in [("drop_table", "drop_table_attempted"), ("shell_exec", "shell_attempted")] {
+            runtime.pattern_registry.register(crate::registry::Pattern {
+                id: format!("flag_{}", condition),
+                name: format!("Flag {}", condition),
+                trigger_event: "ToolCall".to_string(),
+                condition: condition.to_string(),
+                action: crate::registry::PatternAction::SetRunMetadata { key: "safety_flags".to_string(), value: serde_json::json!([flag]) },
+            });
+        }
+        let engine = crate::cortex::CortexEngine::new(runtime.clone());
+        for tool in ["drop_table", "shell_exec", "drop_table"] {
+            let event = RuntimeEvent::new("run-flagged", EventType::ToolCall, Some("a".to_string()), serde_json::json!({ "tool": tool }));
+            engine.handle_event(&event).await;
+        }
 
-        // Helper to get specialty for a node
-        let get_node_info = |node_id: &str| -> String {
-            if let Some(workflow) = self.workflows.get(&workflow_id) {
-                if let Some(agent) = workflow.agents.iter().find(|a| a.id == node_id) {
-                    return agent.prompt.chars().take(50).collect::<String>();
-                }
+        // Arrays gain new items once, objects merge, scalars are replaced
+        runtime.upsert_run_metadata("run-flagged", "review", serde_json::json!({ "score": 2, "note": "flagged" })).await.unwrap();
+        let metadata = runtime.get_state("run-flagged").unwrap().metadata;
+        assert_eq!(metadata["safety_flags"], serde_json::json!(["drop_table_attempted", "shell_attempted"]));
+        assert_eq!(metadata["review"], serde_json::json!({ "owner": "ops", "score": 2, "note": "flagged" }));
+
+        let flagged = runtime.list_runs("public", Some("safety_flags"));
+        assert_eq!(flagged.iter().map(|r| r.run_id.as_str()).collect::<Vec<_>>(), vec!["run-flagged"]);
+        assert_eq!(runtime.list_runs("public", None).len(), 2);
+
+        let oversized = serde_json::json!("x".repeat(crate::models::MAX_METADATA_BYTES));
+        assert!(matches!(runtime.upsert_run_metadata("run-clean", "blob", oversized).await, Err(RuntimeError::InvalidConfig(_))));
+        assert!(runtime.get_state("run-clean").unwrap().metadata.is_empty());
+        assert!(matches!(runtime.upsert_run_metadata("ghost", "k", serde_json::json!(1)).await, Err(RuntimeError::RunNotFound(_))));
+    }
+
+    #[test]
+    fn test_start_failures_carry_distinct_machine_readable_kinds() {
+        let runtime = Arc::new(RARORuntime::new());
+        let start = |agents: serde_json::Value| {
+            let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+                "id": "wf-broken", "name": "broken", "agents": agents, "max_token_budget": 10_000, "timeout_ms": 60_000
+            })).unwrap();
+            match runtime.start_workflow(config, "public") {
+                Err(RuntimeError::InvalidWorkflow(errors)) => errors.iter().map(|e| serde_json::to_value(e).unwrap()).collect::<Vec<_>>(),
+                other => panic!("expected a validation failure, got {:?}", other.map(|_| ())),
             }
-            "Unknown Specialty".to_string()
         };
+        let node = |id: &str, deps: &[&str]| serde_json::json!({
+            "id": id, "role": "worker", "model": "fast", "tools": [], "prompt": "go", "depends_on": deps
+        });
 
-        if detailed {
-            let nodes: Vec<serde_json::Value> = dag.export_nodes().iter().map(|node_id| {
-                let status = if state.completed_agents.contains(node_id) { "completed" }
-                else if state.failed_agents.contains(node_id) { "failed" }
-                else if state.active_agents.contains(node_id) { "running" }
-                else { "pending" };
+        let cycle = start(serde_json::json!([node("a", &["b"]), node("b", &["a"])]));
+        assert_eq!(cycle, vec![serde_json::json!({ "code": "cycle_detected", "details": ["a", "b"] })]);
+        let dangling = start(serde_json::json!([node("a", &["ghost"])]));
+        assert_eq!(dangling, vec![serde_json::json!({ "code": "unknown_dependency", "details": { "agent_id": "a", "dep_id": "ghost" } })]);
+        let duplicate = start(serde_json::json!([node("a", &[]), node("a", &[])]));
+        assert_eq!(duplicate, vec![serde_json::json!({ "code": "duplicate_agent_id", "details": "a" })]);
+
+        // Checks that used to fail with a bare message are structured too
+        let mut rule = node("a", &[]);
+        rule["auto_promote"] = serde_json::json!(["[unclosed"]);
+        let promotion = start(serde_json::json!([rule]));
+        assert_eq!((promotion[0]["code"].as_str(), promotion[0]["details"]["rule"].as_str()), (Some("invalid_promotion_rule"), Some("[unclosed")));
+        assert!(runtime.list_runs("public", None).is_empty());
+    }
 
-                let specialty = if status == "pending" {
-                    get_node_info(node_id)
-                } else {
-                    "".to_string()
-                };
+    #[tokio::test]
+    async fn test_drain_refuses_new_runs_while_active_ones_finish() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-a", vec![agent("a", &[])]);
+        seed_run(&runtime, "run-b", vec![agent("a", &[])]);
+        runtime.runtime_states.get_mut("run-b").unwrap().start_time = "2020-01-01T00:00:00+00:00".to_string();
+
+        runtime.set_maintenance_mode(MaintenanceMode::Drain).await;
+        let config = || -> WorkflowConfig { serde_json::from_value(serde_json::json!({
+            "id": "wf-new", "name": "new", "agents": [agent("a", &[])], "max_token_budget": 10_000,
+            "timeout_ms": 60_000, "execution_mode": { "type": "pull" }
+        })).unwrap() };
+        assert!(matches!(runtime.start_workflow(config(), "public"), Err(RuntimeError::Maintenance)));
+
+        // In-flight runs still record results and complete
+        runtime.record_invocation("run-a", success_invocation("a", 10), None).await.unwrap();
+        runtime.complete_run("run-a").await;
+        assert_eq!(runtime.get_state("run-a").unwrap().status, RuntimeStatus::Completed);
+
+        let status = runtime.maintenance_status();
+        assert_eq!((status.mode, status.active_runs), (MaintenanceMode::Drain, 1));
+        assert_eq!(status.oldest_active_run.unwrap().run_id, "run-b");
+
+        runtime.set_maintenance_mode(MaintenanceMode::Normal).await;
+        assert!(runtime.start_workflow(config(), "public").is_ok());
+    }
 
-                serde_json::json!({
-                    "id": node_id,
-                    "status": status,
-                    "is_you": node_id == current_agent_id,
-                    "dependencies": dag.get_dependencies(node_id),
-                    "specialty_preview": specialty
-                })
-            }).collect();
+    #[tokio::test]
+    async fn test_target_agents_prune_to_ancestors() {
+        let runtime = Arc::new(RARORuntime::new());
+        // a -> b -> d, a -> c, e (independent)
+        seed_run(&runtime, "run-1", vec![
+            agent("a", &[]), agent("b", &["a"]), agent("c", &["a"]), agent("d", &["b"]), agent("e", &[]),
+        ]);
 
-            return serde_json::to_string_pretty(&nodes).unwrap_or_default();
-        } else {
-            match dag.topological_sort() {
-                Ok(order) => {
-                    let parts: Vec<String> = order.iter().map(|node_id| {
-                        let status = if state.completed_agents.contains(node_id) { "COMPLETE" }
-                        else if state.failed_agents.contains(node_id) { "FAILED" }
-                        else if state.active_agents.contains(node_id) { "RUNNING" }
-                        else { "PENDING" };
+        let skipped = runtime.restrict_to_targets("run-1", &["b".to_string()]).await.unwrap();
+        assert_eq!(skipped, vec!["c", "d", "e"]);
 
-                        if node_id == current_agent_id {
-                            format!("[{}:{}(YOU)]", node_id, status)
-                        } else if status == "PENDING" {
-                            // OPTICS: Show specialty for pending nodes
-                            format!("[{}:{} ({})]", node_id, status, get_node_info(node_id))
-                        } else {
-                            format!("[{}:{}]", node_id, status)
-                        }
-                    }).collect();
-                    return parts.join(" -> ");
-                },
-                Err(_) => return "Cycle detected in graph view.".to_string()
+        let mut order = runtime.dag_store.get("run-1").unwrap().topological_sort().unwrap();
+        order.sort();
+        assert_eq!(order, vec!["a", "b"]);
+        assert_eq!(runtime.get_state("run-1").unwrap().skipped_agents, vec!["c", "d", "e"]);
+
+        let err = runtime.restrict_to_targets("run-1", &["zz".to_string(), "yy".to_string()]).await.unwrap_err();
+        assert_eq!(err.to_string(), "Unknown target agents: yy, zz");
+    }
+
+    #[test]
+    fn test_routing_explains_multi_parent_signature_choice() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[]), agent("c", &["a", "b"])]);
+        runtime.set_thought_signature("run-1", "b", "sig-b".to_string()).unwrap();
+
+        let decision = runtime.explain_routing("run-1", "c").unwrap();
+        assert_eq!(decision.signature_source.as_deref(), Some("b"));
+        assert_eq!(decision.dependencies_without_signature, vec!["a"]);
+        assert_eq!(decision.cached_content_id, None);
+        assert_eq!(decision.model_variant, "fast");
+        assert!(decision.api_model_name.is_some());
+        assert_eq!(decision.thinking_level, None);
+
+        assert!(matches!(runtime.explain_routing("run-1", "zz"), Err(RuntimeError::AgentNotFound(_))));
+    }
+
+    #[test]
+    fn test_metadata_echoed_in_state_and_summary() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[])]);
+        runtime.runtime_states.get_mut("run-1").unwrap().metadata
+            .insert("experiment".to_string(), serde_json::json!({ "id": "exp-7", "arm": 2 }));
+
+        let state = serde_json::to_value(runtime.get_state("run-1").unwrap()).unwrap();
+        assert_eq!(state["metadata"]["experiment"]["arm"], 2);
+
+        let runs = runtime.list_runs("public", None);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].metadata["experiment"]["id"], "exp-7");
+        assert!(runtime.list_runs("someone-else", None).is_empty());
+
+        let mut config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "agents": [], "max_token_budget": 1, "timeout_ms": 1
+        })).unwrap();
+        config.metadata.insert("blob".to_string(), serde_json::json!("x".repeat(crate::models::MAX_METADATA_BYTES)));
+        assert!(config.validate_metadata().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_batch_record_invocations() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[]), agent("c", &[])]);
+
+        let batch = vec![success_invocation("a", 100), success_invocation("b", 200), success_invocation("c", 300)];
+        let ids: Vec<String> = batch.iter().map(|i| i.id.clone()).collect();
+        let result = runtime.record_invocations("run-1", batch).await.unwrap();
+
+        assert_eq!(result.recorded, ids);
+        assert!(result.budget_exceeded_at.is_none());
+        let state = runtime.get_state("run-1").unwrap();
+        assert_eq!(state.invocations.len(), 3);
+        assert_eq!(state.total_tokens_used, 600);
+        assert_eq!(state.completed_agents, vec!["a", "b", "c"]);
+        assert_eq!(state.status, RuntimeStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_mid_batch_budget_breach() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[]), agent("c", &[])]);
+
+        // Budget is 10_000 tokens; the second invocation crosses it
+        let batch = vec![success_invocation("a", 4_000), success_invocation("b", 7_000), success_invocation("c", 100)];
+        let ids: Vec<String> = batch.iter().map(|i| i.id.clone()).collect();
+        let result = runtime.record_invocations("run-1", batch).await.unwrap();
+
+        assert_eq!(result.recorded, ids[..2].to_vec());
+        assert_eq!(result.budget_exceeded_at.as_deref(), Some(ids[1].as_str()));
+        assert_eq!(result.skipped, vec![ids[2].clone()]);
+
+        let state = runtime.get_state("run-1").unwrap();
+        assert!(!state.invocations.iter().any(|i| i.id == ids[2]));
+        assert_eq!(state.total_tokens_used, 11_000);
+        assert_eq!(state.status, RuntimeStatus::Failed);
+
+        // The skipped remainder can't be sent again once the run has ended
+        let late = runtime.record_invocations("run-1", vec![success_invocation("c", 100)]).await;
+        assert!(matches!(late, Err(RuntimeError::RunNotRunning(_))));
+        assert_eq!(runtime.get_state("run-1").unwrap().total_tokens_used, 11_000);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_prompt_redaction_in_logs() {
+        let runtime = RARORuntime::new();
+        let mut secret = agent("root", &[]);
+        secret.prompt = "Patient Jane Roe, DOB 1980-02-03".to_string();
+        seed_run(&runtime, "run-1", vec![secret]);
+
+        let settings = crate::observability::GlobalSettingsGuard::acquire().await;
+        crate::observability::set_prompt_redaction(true);
+        let payload = runtime.prepare_invocation_payload("run-1", "root").await.unwrap();
+        drop(settings);
+
+        // Downstream payload keeps the real prompt; logs only carry length + hash
+        assert!(payload.prompt.starts_with("Patient Jane Roe"));
+        assert!(logs_contain(&crate::observability::prompt_hash(&payload.prompt)));
+        assert!(!logs_contain("Jane Roe"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_detaches_dead_caches() {
+        use axum::{extract::{Path, RawQuery}, http::{HeaderMap, StatusCode}, routing::get, Router};
+
+        // Fake Gemini API: only cachedContents/alive exists; the key must come as a header
+        let app = Router::new().route("/v1beta/cachedContents/:id", get(|Path(id): Path<String>, RawQuery(query): RawQuery, headers: HeaderMap| async move {
+            if query.is_some() || headers.get("x-goog-api-key").is_none_or(|k| k != "test-key") {
+                return StatusCode::UNAUTHORIZED;
             }
-        }
+            if id == "alive" { StatusCode::OK } else { StatusCode::NOT_FOUND }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let runtime = Arc::new(RARORuntime::new());
+        let mut events = runtime.event_bus.subscribe();
+        runtime.set_cache_resource("run-1", "cachedContents/alive".to_string()).unwrap();
+        runtime.set_cache_resource("run-2", "cachedContents/dead".to_string()).unwrap();
+        assert_eq!(runtime.list_cache_resources().len(), 2);
+
+        let detached = runtime.verify_cache_resources_against(&format!("http://{}/v1beta", addr), "test-key").await;
+        assert_eq!(detached, vec!["run-2"]);
+        assert_eq!(runtime.get_cache_resource("run-1").as_deref(), Some("cachedContents/alive"));
+        assert_eq!(runtime.get_cache_resource("run-2"), None);
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.run_id, "run-2");
+        assert_eq!(event.payload["action"], "cache_detached");
     }
 
-    pub async fn prepare_invocation_payload(
-        &self,
-        run_id: &str,
-        agent_id: &str,
-    ) -> Result<InvocationPayload, String> {
-        let state = self
-            .runtime_states
-            .get(run_id)
-            .ok_or_else(|| "Run not found".to_string())?;
+    #[tokio::test]
+    async fn test_abort_seals_run() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[])]);
+        runtime.note_pattern_trigger("run-1", "no_exfiltration");
 
-        let workflow = self
-            .workflows
-            .get(&state.workflow_id)
-            .ok_or_else(|| "Workflow not found".to_string())?;
+        runtime.abort_run("run-1", "Exfiltration attempt").await.unwrap();
+        assert!(runtime.is_run_aborted("run-1"));
+        assert_eq!(runtime.get_state("run-1").unwrap().status, RuntimeStatus::Aborted);
 
-        let agent_config = workflow
-            .agents
-            .iter()
-            .find(|a| a.id == agent_id)
-            .ok_or_else(|| format!("Agent {} not found", agent_id))?;
+        // Later failure/pause paths cannot reopen or relabel the run
+        runtime.fail_run("run-1", "a", FailureCode::AgentError, "late failure").await;
+        runtime.request_approval("run-1", None, "late pause").await;
+        assert_eq!(runtime.get_state("run-1").unwrap().status, RuntimeStatus::Aborted);
 
-        let parent_signature = if !agent_config.depends_on.is_empty() {
-            agent_config
-                .depends_on
-                .iter()
-                .find_map(|parent_id| self.get_thought_signature(run_id, parent_id))
-        } else {
-            None
-        };
+        let abort = runtime.get_events("run-1").into_iter().find(|e| e.payload["action"] == "abort").unwrap();
+        assert_eq!(abort.payload["triggered_patterns"], serde_json::json!(["no_exfiltration"]));
+    }
 
-        let mut context_prompt_appendix = String::new();
-        let mut input_data_map = serde_json::Map::new();
-        let mut dynamic_file_mounts: Vec<String> = Vec::new();
+    #[tokio::test]
+    async fn test_failure_records_reason_and_blocks_downstream() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"]), agent("c", &["b"]), agent("d", &[])]);
+
+        // One soft-failed attempt before the hard failure
+        let mut attempt = success_invocation("b", 5);
+        attempt.status = InvocationStatus::Paused;
+        runtime.record_invocation("run-1", success_invocation("a", 10), None).await.unwrap();
+        runtime.record_invocation("run-1", attempt, None).await.unwrap();
+
+        let failure = runtime.fail_run("run-1", "b", FailureCode::ServiceUnavailable, "connection refused").await.unwrap();
+        assert_eq!(failure.agent_id, "b");
+        assert_eq!(failure.reason, "connection refused");
+        assert_eq!(failure.error_code, FailureCode::ServiceUnavailable);
+        assert_eq!(failure.attempts, 2);
+
+        let state = runtime.get_state("run-1").unwrap();
+        assert_eq!(state.failed_agents, vec![failure]);
+        assert_eq!(state.blocked_agents, vec![BlockedAgent { agent_id: "c".to_string(), blocked_by: vec!["b".to_string()] }]);
+    }
 
-        if !agent_config.depends_on.is_empty() {
-            if let Some(client) = &self.redis_client {
-                match client.get_async_connection().await {
-                    Ok(mut con) => {
-                        for parent_id in &agent_config.depends_on {
-                            let key = format!("run:{}:agent:{}:output", run_id, parent_id);
-                            
-                            let data: Option<String> = con.get(&key).await.unwrap_or(None);
+    #[test]
+    fn test_oversized_signature_is_dropped_not_truncated() {
+        let mut runtime = RARORuntime::new();
+        runtime.signature_policy = SignaturePolicy { max_bytes: 16, drop_oversized: true, compress_threshold: 1024 };
+        seed_run(&runtime, "run-1", vec![agent("a", &[])]);
+
+        runtime.set_thought_signature("run-1", "a", "sig-a".to_string()).unwrap();
+        runtime.set_thought_signature("run-1", "a", "x".repeat(17)).unwrap();
+        // The stale signature goes too, and the drop is not a write
+        assert_eq!(runtime.get_thought_signature("run-1", "a"), None);
+        let store = runtime.get_all_signatures("run-1").unwrap();
+        assert_eq!(store.dropped, vec!["a"]);
+        assert_eq!(store.history.len(), 1);
+
+        runtime.set_thought_signature("run-1", "a", "sig-a2".to_string()).unwrap();
+        assert!(runtime.get_all_signatures("run-1").unwrap().dropped.is_empty());
+    }
 
-                            if let Some(json_str) = data {
-                                if let Ok(val) = serde_json::from_str::<serde_json::Value>(&json_str) {
-                                    input_data_map.insert(parent_id.clone(), val.clone());
-
-                                    let content = val.get("result")
-                                        .and_then(|v| v.as_str())
-                                        .or_else(|| val.get("output").and_then(|v| v.as_str()))
-                                        .unwrap_or("No text output");
-
-                                    context_prompt_appendix.push_str(&format!("\n\n=== CONTEXT FROM AGENT {} ===\n{}\n", parent_id, content));
-
-                                    if let Some(files_array) = val.get("files_generated").and_then(|v| v.as_array()) {
-                                        for file_val in files_array {
-                                            if let Some(filename) = file_val.as_str() {
-                                                let mount_path = format!("/app/storage/sessions/{}/output/{}", run_id, filename);
-                                                if !dynamic_file_mounts.contains(&mount_path) {
-                                                    dynamic_file_mounts.push(mount_path);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    },
-                    Err(e) => tracing::warn!("Could not connect to Redis for context fetching: {}", e),
-                }
-            } else {
-                tracing::warn!("Redis client unavailable. Context fetching skipped.");
-            }
+    #[test]
+    fn test_signature_history_in_write_order() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"]), agent("c", &["b"])]);
+
+        for agent_id in ["a", "b", "c"] {
+            runtime.set_thought_signature("run-1", agent_id, format!("sig-{}", agent_id)).unwrap();
         }
 
-        // === PRE-FLIGHT: CONTEXT DROUGHT PREVENTION ===
-        // Check if upstream agents provided any usable data
-        let has_null_signal = context_prompt_appendix.contains("[STATUS: NULL]");
-        let has_files = !dynamic_file_mounts.is_empty();
-        let is_root_node = agent_config.depends_on.is_empty();
+        let history = runtime.get_signature_history("run-1").unwrap();
+        assert_eq!(history.iter().map(|w| (w.seq, w.agent_id.as_str())).collect::<Vec<_>>(), vec![(1, "a"), (2, "b"), (3, "c")]);
 
-        // If we depend on others, and they gave us nothing but NULLs or empty text, pause.
-        if !is_root_node && (context_prompt_appendix.trim().is_empty() || (has_null_signal && !has_files)) {
-            let drought_msg = format!("Pre-Execution Halt: Agent '{}' is facing a Context Drought. Upstream nodes provided no usable data.", agent_id);
-            tracing::warn!("{}", drought_msg);
+        // Each write is tied to its event in the run's log
+        let events = runtime.get_events("run-1");
+        let event_ids: Vec<&str> = events.iter()
+            .filter(|e| matches!(e.event_type, EventType::SignatureUpdated))
+            .map(|e| e.id.as_str())
+            .collect();
+        assert_eq!(event_ids, history.iter().map(|w| w.event_id.as_str()).collect::<Vec<_>>());
+    }
 
-            // Trigger the Circuit Breaker
-            self.request_approval(run_id, Some(agent_id), &drought_msg).await;
+    #[test]
+    fn test_agent_logs_filtered_by_agent_and_seq() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"])]);
 
-            return Err("Halted: Contextual Data Drought".to_string());
-        }
+        let log = |agent_id: &str, message: &str, level: &str| {
+            runtime.emit_event(RuntimeEvent::new("run-1", EventType::IntermediateLog, Some(agent_id.to_string()),
+                serde_json::json!({ "message": message, "metadata": level, "category": "TOOL" })));
+        };
+        log("a", "searching", "INFO");
+        log("b", "waiting", "INFO");
+        log("a", "quota hit", "ERROR");
+        runtime.record_trace("run-1", &TraceEvent::new("DEBUG", "Invocation payload prepared", Some("a"), serde_json::json!({})));
+
+        let entries = runtime.get_agent_logs("run-1", "a", 0).unwrap();
+        assert_eq!(entries.iter().map(|e| (e.seq, e.message.as_str())).collect::<Vec<_>>(),
+            vec![(1, "searching"), (3, "quota hit"), (4, "Invocation payload prepared")]);
+        assert_eq!(entries[2].source, "trace");
+
+        // Resume after a dropped connection
+        assert_eq!(runtime.get_agent_logs("run-1", "a", 3).unwrap().len(), 1);
+        assert!(matches!(runtime.get_agent_logs("run-1", "zzz", 0), Err(RuntimeError::AgentNotFound(_))));
+    }
 
-        let cached_content_id = self.get_cache_resource(run_id);
+    #[tokio::test]
+    async fn test_supervisor_directives() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("draft", &[]), agent("edit", &["draft"]), agent("publish", &["edit"]), agent("boss", &["draft"])]);
+        runtime.record_invocation("run-1", success_invocation("draft", 10), None).await.unwrap();
 
-        let model_string = match &agent_config.model {
-            ModelVariant::Fast => "fast".to_string(),
-            ModelVariant::Reasoning => "reasoning".to_string(),
-            ModelVariant::Thinking => "thinking".to_string(),
-            ModelVariant::Custom(s) => s.clone(),
+        let directive = |action: SupervisorAction, target: &str| SupervisorDirective {
+            action,
+            target_agent_id: target.to_string(),
+            reason: "test".to_string(),
         };
 
-        let thinking_level = if matches!(agent_config.model, ModelVariant::Thinking) {
-            Some(5)  // Default budget level for Thinking models
-        } else {
-            None
+        // Cancelling a pending agent also skips its pending dependents
+        runtime.apply_supervisor_directive("run-1", "boss", &directive(SupervisorAction::CancelAgent, "edit")).await.unwrap();
+        let state = runtime.get_state("run-1").unwrap();
+        assert_eq!(state.skipped_agents, vec!["edit".to_string(), "publish".to_string()]);
+        assert!(runtime.dag_store.get("run-1").unwrap().ancestors("edit").is_err());
+
+        // Prompt rewrite + retry of a finished agent
+        let rewrite = SupervisorAction::ModifyPrompt { new_prompt: "Be concise.".to_string() };
+        runtime.apply_supervisor_directive("run-1", "boss", &directive(rewrite, "draft")).await.unwrap();
+        runtime.apply_supervisor_directive("run-1", "boss", &directive(SupervisorAction::RetryAgent, "draft")).await.unwrap();
+        assert!(runtime.get_state("run-1").unwrap().completed_agents.is_empty());
+        let workflow = runtime.workflows.get(&pinned_workflow_id("wf-run-1", "run-1")).unwrap();
+        assert_eq!(workflow.agents.iter().find(|a| a.id == "draft").unwrap().prompt, "Be concise.");
+        drop(workflow);
+        // The shared id keeps the original prompt for other runs
+        assert_ne!(runtime.workflows.get("wf-run-1").unwrap().agents[0].prompt, "Be concise.");
+
+        // Invalid targets
+        let retry_pending = runtime.apply_supervisor_directive("run-1", "boss", &directive(SupervisorAction::RetryAgent, "draft")).await;
+        assert!(matches!(retry_pending, Err(RuntimeError::InvalidDirective(_))));
+        let itself = runtime.apply_supervisor_directive("run-1", "boss", &directive(SupervisorAction::CancelAgent, "boss")).await;
+        assert!(matches!(itself, Err(RuntimeError::InvalidDirective(_))));
+    }
+
+    #[tokio::test]
+    async fn test_failing_a_run_cancels_its_inflight_calls() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[])]);
+        let call = tokio::spawn(std::future::pending::<Result<RemoteAgentResponse, reqwest::Error>>());
+        let probe = call.abort_handle();
+        let waiting = {
+            let runtime = runtime.clone();
+            tokio::spawn(async move { runtime.await_invocation("run-1", "inv-b", call, None).await })
         };
+        while runtime.inflight_invocations.is_empty() {
+            tokio::task::yield_now().await;
+        }
 
-        let mut full_file_paths: Vec<String> = workflow.attached_files.iter()
-            .map(|f| format!("/app/storage/sessions/{}/input/{}", run_id, f))
-            .collect();
+        runtime.fail_run("run-1", "a", FailureCode::AgentError, "boom").await;
+        let outcome = waiting.await.unwrap();
+        assert!(matches!(outcome, Some(Err(RuntimeError::AgentService(_)))));
+        assert!(probe.is_finished() && runtime.inflight_invocations.is_empty());
+        assert!(runtime.run_ended("run-1"));
+    }
 
-        let has_dynamic_artifacts = !dynamic_file_mounts.is_empty();
-        let dynamic_artifact_count = dynamic_file_mounts.len();
+    #[tokio::test]
+    async fn test_hung_invocation_times_out_and_is_aborted() {
+        let runtime = RARORuntime::new();
+        let call = tokio::spawn(std::future::pending::<Result<RemoteAgentResponse, reqwest::Error>>());
+        let probe = call.abort_handle();
 
-        if has_dynamic_artifacts {
-            tracing::info!("Mounting {} dynamic artifacts for agent {}", dynamic_artifact_count, agent_id);
-            full_file_paths.extend(dynamic_file_mounts);
+        let outcome = runtime.await_invocation("run-1", "inv-1", call, Some(std::time::Duration::from_millis(20))).await;
+        assert!(outcome.is_none());
+        assert!(runtime.inflight_invocations.is_empty());
+
+        tokio::task::yield_now().await;
+        assert!(probe.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_partial_result_mid_run() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"]), agent("c", &["b"])]);
+
+        let artifact_id = runtime.store_artifact("run-1", "a", &serde_json::json!({ "result": "draft ready" })).await;
+        let mut done = success_invocation("a", 10);
+        done.artifact_id = artifact_id;
+        runtime.record_invocation("run-1", done, None).await.unwrap();
+        runtime.update_agent_status("run-1", "b", InvocationStatus::Running).await;
+
+        assert!(matches!(runtime.get_run_result("run-1", false).await, Err(RuntimeError::RunInProgress(_))));
+
+        let result = runtime.get_run_result("run-1", true).await.unwrap();
+        assert!(result.partial);
+        assert_eq!(result.outputs.keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(result.outputs["a"]["result"], "draft ready");
+        assert_eq!(result.pending_agents, vec!["b".to_string(), "c".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_generation_params_carried_on_payload() {
+        let runtime = RARORuntime::new();
+        let mut seeded = agent("root", &[]);
+        seeded.generation = GenerationParams { temperature: Some(0.2), top_p: Some(0.9), max_output_tokens: Some(512), seed: Some(42) };
+        seed_run(&runtime, "run-1", vec![seeded.clone(), agent("plain", &[])]);
+
+        let payload = runtime.prepare_invocation_payload("run-1", "root").await.unwrap();
+        assert_eq!(payload.max_output_tokens, Some(512));
+        assert_eq!(payload.generation(), seeded.generation);
+
+        let plain = runtime.prepare_invocation_payload("run-1", "plain").await.unwrap();
+        assert!(plain.generation().is_empty());
+        assert!(serde_json::to_value(&plain).unwrap().get("seed").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ready_agents_require_completed_dependencies() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![
+            agent("a", &[]), agent("b", &[]), agent("c", &["a", "b"]), agent("d", &["a"]),
+        ]);
+        let mut ready = runtime.ready_agents("run-1").unwrap();
+        ready.sort();
+        assert_eq!(ready, vec!["a".to_string(), "b".to_string()]);
+
+        runtime.record_invocation("run-1", success_invocation("a", 10), None).await.unwrap();
+        runtime.update_agent_status("run-1", "b", InvocationStatus::Running).await;
+        // b is running, so c still waits on it; d only needed a
+        assert_eq!(runtime.ready_agents("run-1").unwrap(), vec!["d".to_string()]);
+
+        runtime.set_run_status("run-1", RuntimeStatus::AwaitingApproval);
+        assert!(runtime.ready_agents("run-1").unwrap().is_empty());
+        runtime.set_run_status("run-1", RuntimeStatus::Running);
+        runtime.record_invocation("run-1", success_invocation("b", 10), None).await.unwrap();
+        let mut ready = runtime.ready_agents("run-1").unwrap();
+        ready.sort();
+        assert_eq!(ready, vec!["c".to_string(), "d".to_string()]);
+
+        assert!(matches!(runtime.ready_agents("ghost"), Err(RuntimeError::RunNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_model_usage_across_runs() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"])]);
+        seed_run(&runtime, "run-2", vec![agent("c", &[])]);
+
+        let mut deep = success_invocation("b", 2_000);
+        deep.model_variant = ModelVariant::Thinking;
+        deep.latency_ms = 900;
+        runtime.record_invocation("run-1", success_invocation("a", 1_000), None).await.unwrap();
+        runtime.record_invocation("run-1", deep, None).await.unwrap();
+        runtime.record_invocation("run-2", success_invocation("c", 3_000), None).await.unwrap();
+
+        let usage = runtime.model_usage();
+        assert_eq!(usage[&ModelVariant::Fast].invocations, 2);
+        assert_eq!(usage[&ModelVariant::Fast].total_tokens, 4_000);
+        assert_eq!(usage[&ModelVariant::Thinking].total_tokens, 2_000);
+        assert_eq!(usage[&ModelVariant::Thinking].average_latency_ms, 900.0);
+    }
+
+    #[tokio::test]
+    async fn test_feedback_recorded_only_while_paused() {
+        let runtime = Arc::new(RARORuntime::new());
+        seed_run(&runtime, "run-1", vec![agent("a", &[])]);
+        let feedback = FeedbackPayload { approved: false, reviewer_id: "rev-1".to_string(), comment: Some("Wrong dataset".to_string()) };
+
+        assert!(matches!(runtime.record_feedback("run-1", &feedback).await, Err(RuntimeError::NotAwaitingApproval(_))));
+
+        runtime.request_approval("run-1", Some("a"), "Check sources").await;
+        let mut rx = runtime.event_bus.subscribe();
+        let record = runtime.record_feedback("run-1", &feedback).await.unwrap();
+
+        let state = runtime.get_state("run-1").unwrap();
+        assert_eq!(state.approval_history, vec![record]);
+        assert_eq!(state.status, RuntimeStatus::Failed);
+        assert_eq!(state.failed_agents[0].error_code, FailureCode::ApprovalRejected);
+        let event = rx.recv().await.unwrap();
+        assert!(matches!(event.event_type, EventType::SystemIntervention));
+        assert_eq!(event.payload["reviewer_id"], "rev-1");
+        assert_eq!(event.payload["approved"], false);
+
+        // Concurrent decisions on one pause: only the first is recorded and acted on
+        seed_run(&runtime, "run-2", vec![agent("a", &[])]);
+        runtime.request_approval("run-2", Some("a"), "Check sources").await;
+        let approve = FeedbackPayload { approved: true, ..feedback.clone() };
+        let (first, second) = tokio::join!(runtime.record_feedback("run-2", &approve), runtime.record_feedback("run-2", &feedback));
+        assert_eq!([first.is_ok(), second.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        let state = runtime.get_state("run-2").unwrap();
+        assert_eq!(state.approval_history.len(), 1);
+        assert_eq!(state.status == RuntimeStatus::Failed, !state.approval_history[0].approved);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_queue_orders_by_priority_with_aging() {
+        let runtime = RARORuntime::new();
+        let started = |mins: i64| (Utc::now() - chrono::Duration::minutes(mins)).to_rfc3339();
+        for (run_id, priority, waited_mins) in [("batch-old", 1, 30), ("batch-new", 1, 0), ("interactive", 8, 0), ("default-a", 5, 2), ("default-b", 5, 1)] {
+            seed_run(&runtime, run_id, vec![agent("a", &[])]);
+            let mut state = runtime.runtime_states.get_mut(run_id).unwrap();
+            state.priority = priority;
+            state.start_time = started(waited_mins);
         }
+        seed_run(&runtime, "busy", vec![agent("a", &[])]);
+        runtime.update_agent_status("busy", "a", InvocationStatus::Running).await;
 
-        // === AUTHORITATIVE IDENTITY PROVISIONING (MERGE & VALIDATE) ===
-        // STRATEGY: Start with user configuration, then enforce identity mandates
-        // This allows manual additions while preventing capability removal
+        let order = |queue: Vec<ScheduledRun>| queue.into_iter().map(|r| r.run_id).collect::<Vec<_>>();
 
-        // 1. Start with tools defined in the Configuration (from UI/Architect)
-        let mut tools = agent_config.tools.clone();
+        // No aging: strict priority, FIFO within a level; runs with nothing ready are skipped
+        assert_eq!(order(runtime.dispatch_queue(None)), vec!["interactive", "default-a", "default-b", "batch-old", "batch-new"]);
 
-        // 2. UNIVERSAL BASELINE (Always ensure these exist)
-        if !tools.contains(&"read_file".to_string()) { tools.push("read_file".to_string()); }
-        if !tools.contains(&"list_files".to_string()) { tools.push("list_files".to_string()); }
+        // 5-minute aging: batch-old waited 30 min and climbs 1 -> 7
+        let aged = runtime.dispatch_queue(Some(std::time::Duration::from_secs(300)));
+        assert_eq!(aged[1].run_id, "batch-old");
+        assert_eq!(aged[1].effective_priority, 7);
+        assert_eq!(aged.last().unwrap().run_id, "batch-new");
 
-        let id_lower = agent_id.to_lowercase();
+        assert_eq!(runtime.set_run_priority("batch-new", 9).await.unwrap(), 1);
+        assert_eq!(order(runtime.dispatch_queue(None))[0], "batch-new");
+        assert!(matches!(runtime.set_run_priority("batch-new", 10).await, Err(RuntimeError::InvalidPriority(10))));
+    }
 
-        // 3. MANDATORY IDENTITY GRANTS (Enforce identity contract)
-        // Even if user removed them in UI, identity demands them.
+    #[tokio::test]
+    async fn test_at_least_join_ready_after_second_completion() {
+        let runtime = RARORuntime::new();
+        let mut merge = agent("merge", &["a", "b", "c"]);
+        merge.join_policy = JoinPolicy::AtLeast { n: 2 };
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[]), agent("c", &[]), merge]);
+        let is_ready = |rt: &RARORuntime| rt.ready_agents("run-1").unwrap().contains(&"merge".to_string());
+
+        assert!(!is_ready(&runtime));
+        runtime.record_invocation("run-1", success_invocation("a", 10), None).await.unwrap();
+        assert!(!is_ready(&runtime));
+        runtime.record_invocation("run-1", success_invocation("c", 10), None).await.unwrap();
+        assert!(is_ready(&runtime));
+        // b is still pending but no longer holds merge back
+        assert!(runtime.ready_agents("run-1").unwrap().contains(&"b".to_string()));
+    }
 
-        // Research Class (Broader matching: research_, researcher, web_)
-        if id_lower.contains("research") || id_lower.starts_with("web_") {
-            if !tools.contains(&"web_search".to_string()) {
-                tools.push("web_search".to_string());
-            }
+    #[tokio::test]
+    async fn test_push_mode_posts_first_wave() {
+        use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::sync::mpsc;
+
+        // Orchestrator webhook that rejects the very first delivery to exercise the retry
+        let (tx, mut rx) = mpsc::unbounded_channel::<InvocationPayload>();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/dispatch", post(|State((tx, calls)): State<(mpsc::UnboundedSender<InvocationPayload>, Arc<AtomicUsize>)>, Json(payload): Json<InvocationPayload>| async move {
+                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return StatusCode::SERVICE_UNAVAILABLE;
+                }
+                tx.send(payload).unwrap();
+                StatusCode::ACCEPTED
+            }))
+            .with_state((tx, calls.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/dispatch", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut runtime = RARORuntime::new();
+        seed_run_with(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[]), agent("c", &["a", "b"])],
+            serde_json::json!({ "execution_mode": { "type": "push", "webhook_url": url } }));
+        // The mock orchestrator is on loopback, which the default policy refuses
+        seed_run_with(&runtime, "run-private", vec![agent("a", &[])],
+            serde_json::json!({ "execution_mode": { "type": "push", "webhook_url": url } }));
+        runtime.launch_execution("run-private".to_string()).await;
+        assert_eq!(runtime.get_state("run-private").unwrap().status, RuntimeStatus::Failed);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        runtime.egress = EgressPolicy::allowing(["127.0.0.1"]);
+
+        runtime.launch_execution("run-1".to_string()).await;
+
+        let mut pushed = vec![rx.recv().await.unwrap().agent_id, rx.recv().await.unwrap().agent_id];
+        pushed.sort();
+        assert_eq!(pushed, vec!["a", "b"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(rx.try_recv().is_err());
+
+        // Claimed agents are not pushed again; c waits for its dependencies
+        runtime.push_ready_agents("run-1").await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(runtime.get_state("run-1").unwrap().active_agents.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_system_status_counts() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "live", vec![agent("a", &[])]);
+        seed_run(&runtime, "done", vec![agent("a", &[])]);
+        runtime.set_run_status("done", RuntimeStatus::Completed);
+
+        let forever = tokio::spawn(std::future::pending::<()>());
+        let finished = tokio::spawn(async {});
+        runtime.register_background_task("forever", &forever);
+        runtime.register_background_task("finished", &finished);
+        tokio::task::yield_now().await;
+        while !finished.is_finished() {
+            tokio::task::yield_now().await;
         }
 
-        // Logic/Math Class (analyze_, analyst, coder, math)
-        if id_lower.contains("analy") || id_lower.contains("code") || id_lower.contains("math") {
-            if !tools.contains(&"execute_python".to_string()) {
-                tools.push("execute_python".to_string());
-            }
+        let status = runtime.system_status();
+        assert_eq!((status.active_runs, status.total_registered_runs), (1, 2));
+        assert_eq!(status.background_tasks, 1);
+        assert_eq!(status.total_pattern_count, runtime.pattern_registry.pattern_count());
+        assert_eq!(status.map_entries["dag_store"], 2);
+        forever.abort();
+    }
+
+    #[tokio::test]
+    async fn test_simulated_run_completes_without_usage() {
+        let runtime = RARORuntime::new();
+        seed_run_with(&runtime, "sim", vec![agent("a", &[]), agent("b", &["a"])],
+            serde_json::json!({ "simulation": true, "simulation_delay_ms": 1 }));
+        runtime.runtime_states.get_mut("sim").unwrap().simulation = true;
+
+        tokio::time::timeout(std::time::Duration::from_secs(10), runtime.execute_dynamic_dag("sim".to_string()))
+            .await
+            .expect("simulated run should finish");
+
+        let state = runtime.get_state("sim").unwrap();
+        assert_eq!(state.status, RuntimeStatus::Completed);
+        assert_eq!(state.completed_agents, vec!["a".to_string(), "b".to_string()]);
+        assert!(state.total_tokens_used > 0);
+
+        let output = runtime.get_agent_output("sim", "a").await.unwrap().unwrap();
+        assert_eq!(output["simulated"], true);
+        let signature = runtime.get_thought_signature("sim", "a").unwrap();
+        assert!(signature.starts_with("sim_"));
+
+        // Simulated tokens never reach cost or quota reporting
+        assert!(runtime.model_usage().is_empty());
+        assert_eq!(runtime.usage_report("public").tokens_used, 0);
+    }
+
+    #[tokio::test]
+    async fn test_run_seed_recorded_and_sent_to_every_agent() {
+        let runtime = RARORuntime::new();
+        let mut pinned = agent("pinned", &[]);
+        pinned.generation.seed = Some(7);
+        seed_run_with(&runtime, "run-1", vec![agent("a", &[]), agent("b", &[]), pinned], serde_json::json!({ "seed": 1234 }));
+
+        let config = runtime.workflows.get("wf-run-1").unwrap().clone();
+        let state = RARORuntime::initial_state("run-1", "public", &config, Vec::new());
+        assert_eq!(state.seed, Some(1234));
+        assert_eq!(RunSummary::from(&state).seed, Some(1234));
+        runtime.runtime_states.insert("run-1".to_string(), state);
+
+        for agent_id in ["a", "b"] {
+            let payload = runtime.prepare_invocation_payload("run-1", agent_id).await.unwrap();
+            assert_eq!(payload.seed, Some(1234), "agent {}", agent_id);
         }
+        // An agent's own seed still wins
+        assert_eq!(runtime.prepare_invocation_payload("run-1", "pinned").await.unwrap().seed, Some(7));
+    }
 
-        // Output/I-O Class (writer, coder, logger)
-        if id_lower.contains("code") || id_lower.contains("writ") {
-            if !tools.contains(&"write_file".to_string()) {
-                tools.push("write_file".to_string());
-            }
+    #[tokio::test]
+    async fn test_edge_pass_and_alias_shape_payload() {
+        let runtime = RARORuntime::new();
+        let mut writer = agent("writer", &[]);
+        writer.prompt = "Summarize {{agents.research.output.result}}".to_string();
+        writer.depends_on = vec![
+            Dependency { agent: "scout".to_string(), pass: EdgePass::Output, alias: Some("research".to_string()) },
+            Dependency { agent: "planner".to_string(), pass: EdgePass::Signature, alias: None },
+            Dependency { agent: "clock".to_string(), pass: EdgePass::None, alias: None },
+        ];
+        seed_run(&runtime, "run-1", vec![agent("scout", &[]), agent("planner", &[]), agent("clock", &[]), writer]);
+
+        for (id, result) in [("scout", "scout findings"), ("planner", "planner notes"), ("clock", "clock output")] {
+            runtime.set_thought_signature("run-1", id, format!("sig-{}", id)).unwrap();
+            runtime.local_outputs.insert(format!("run:run-1:agent:{}:output", id), serde_json::json!({ "result": result }));
         }
 
-        // Admin Class (master_, orchestrator)
-        if id_lower.starts_with("master_") || id_lower.starts_with("orchestrator") {
-            for t in ["web_search", "execute_python", "write_file"] {
-                if !tools.contains(&t.to_string()) {
-                    tools.push(t.to_string());
+        let payload = runtime.prepare_invocation_payload("run-1", "writer").await.unwrap();
+        // scout holds a signature too, but its edge passes output only
+        assert_eq!(payload.parent_signature.as_deref(), Some("sig-planner"));
+        assert!(payload.prompt.starts_with("Summarize scout findings"));
+        assert_eq!(payload.input_data.as_object().unwrap().keys().collect::<Vec<_>>(), vec!["research"]);
+        assert!(payload.user_directive.contains("=== CONTEXT FROM AGENT research ==="));
+        assert!(!payload.user_directive.contains("planner notes") && !payload.user_directive.contains("clock output"));
+
+        let topology = runtime.get_topology_snapshot("run-1").unwrap();
+        let label = |from: &str| topology["edges"].as_array().unwrap().iter()
+            .find(|e| e["from"] == from)
+            .map(|e| e["label"].clone())
+            .unwrap();
+        assert_eq!(label("scout"), "output as research");
+        assert_eq!(label("planner"), "signature");
+        assert_eq!(label("clock"), "none");
+    }
+
+    #[tokio::test]
+    async fn test_hooks_rewrite_veto_and_observe() {
+        use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+        use sha2::{Digest, Sha256};
+        use tokio::sync::mpsc;
+
+        // Policy engine: rewrites a's prompt, vetoes b, records every notification
+        let (tx, mut rx) = mpsc::unbounded_channel::<(String, serde_json::Value)>();
+        let app = Router::new()
+            .route("/before", post(|Json(mut payload): Json<InvocationPayload>| async move {
+                if payload.agent_id == "b" {
+                    return (StatusCode::FORBIDDEN, "blocked by policy").into_response();
                 }
+                payload.prompt = "rewritten".to_string();
+                Json(payload).into_response()
+            }))
+            .route("/notify/:hook", post(|State(tx): State<mpsc::UnboundedSender<(String, serde_json::Value)>>,
+                    axum::extract::Path(hook): axum::extract::Path<String>, Json(body): Json<serde_json::Value>| async move {
+                tx.send((hook, body)).unwrap();
+                StatusCode::OK
+            }))
+            .with_state(tx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut runtime = RARORuntime::new();
+        // The policy engine is on loopback, which the default egress policy refuses
+        runtime.hook_client = HookClient::new(EgressPolicy::allowing(["127.0.0.1"]));
+        seed_run_with(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"])], serde_json::json!({
+            "simulation": true,
+            "simulation_delay_ms": 1,
+            "hooks": {
+                "before_agent": format!("{}/before", base),
+                "after_agent": format!("{}/notify/after_agent", base),
+                "on_fail": format!("{}/notify/on_fail", base)
             }
+        }));
+        runtime.runtime_states.get_mut("run-1").unwrap().simulation = true;
+
+        tokio::time::timeout(std::time::Duration::from_secs(10), runtime.execute_dynamic_dag("run-1".to_string()))
+            .await
+            .expect("run should finish");
+
+        let state = runtime.get_state("run-1").unwrap();
+        assert_eq!(state.status, RuntimeStatus::Failed);
+        assert_eq!(state.completed_agents, vec!["a".to_string()]);
+        let failure = state.failed_agents.iter().find(|f| f.agent_id == "b").unwrap();
+        assert_eq!(failure.error_code, FailureCode::HookVetoed);
+        assert_eq!(failure.reason, "blocked by policy");
+
+        // The simulated response digests the prompt it was sent: the hook's rewrite
+        let digest = format!("{:x}", Sha256::digest(b"rewritten"));
+        let output = runtime.get_agent_output("run-1", "a").await.unwrap().unwrap();
+        assert!(output["result"].as_str().unwrap().ends_with(&digest[..12]));
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            received.push(tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await.unwrap().unwrap());
         }
+        received.sort_by(|x, y| x.0.cmp(&y.0));
+        assert_eq!(received[0].0, "after_agent");
+        assert_eq!(received[0].1["agent_id"], "a");
+        assert_eq!(received[1].0, "on_fail");
+        assert_eq!(received[1].1["status"], "failed");
+
+        // Pull-mode prepares go through the same hook
+        seed_run_with(&runtime, "run-pull", vec![agent("a", &[]), agent("b", &[])], serde_json::json!({
+            "execution_mode": { "type": "pull" },
+            "hooks": { "before_agent": format!("{}/before", base) }
+        }));
+        assert_eq!(runtime.prepare_exclusive_invocation("run-pull", "a").await.unwrap().prompt, "rewritten");
+        assert!(matches!(runtime.prepare_exclusive_invocation("run-pull", "b").await, Err(RuntimeError::HookRefused { .. })));
+        let state = runtime.get_state("run-pull").unwrap();
+        assert_eq!(state.status, RuntimeStatus::Failed);
+        assert_eq!(state.failed_agents[0].error_code, FailureCode::HookVetoed);
+    }
 
-        // Dynamic artifacts require python (Special Case)
-        if has_dynamic_artifacts && !tools.contains(&"execute_python".to_string()) {
-            tools.push("execute_python".to_string());
-            tracing::info!("Agent {}: Provisioned 'execute_python' for dynamic artifact handling", agent_id);
-        }
-
-        tracing::info!("Final provisioned tools for {}: {:?}", agent_id, tools);
-
-        // === ARCHITECTURAL FIX: SEPARATE IDENTITY FROM CONTEXT ===
+    #[tokio::test]
+    async fn test_silent_run_flagged_stalled() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "quiet", vec![agent("a", &[]), agent("b", &["a"])]);
+        seed_run(&runtime, "busy", vec![agent("a", &[])]);
+        let long_ago = (Utc::now() - chrono::Duration::minutes(20)).to_rfc3339();
+        runtime.runtime_states.get_mut("quiet").unwrap().mark_active("a", &long_ago);
+        runtime.update_agent_status("busy", "a", InvocationStatus::Running).await;
+
+        let mut events = runtime.event_bus.subscribe();
+        let threshold = chrono::Duration::minutes(10);
+        assert_eq!(runtime.detect_stalled_runs(threshold).await, vec!["quiet"]);
+
+        let state = runtime.get_state("quiet").unwrap();
+        assert!(state.stalled);
+        assert!(RunSummary::from(&state).stalled);
+        assert!(!runtime.get_state("busy").unwrap().stalled);
+
+        let event = events.try_recv().unwrap();
+        assert!(matches!(event.event_type, EventType::SystemIntervention));
+        assert_eq!(event.payload["type"], "stall");
+        assert_eq!(event.payload["stalled_agents"], serde_json::json!(["a"]));
+
+        // Flagged once; activity clears the flag
+        assert!(runtime.detect_stalled_runs(threshold).await.is_empty());
+        runtime.record_invocation("quiet", success_invocation("a", 10), None).await.unwrap();
+        assert!(!runtime.get_state("quiet").unwrap().stalled);
+    }
 
-        // 1. Prepare System Prompt (Identity Only - Pure Constitution)
-        // Do NOT append context here. Keep this immutable.
-        let mut final_prompt = agent_config.prompt.clone();
+    #[tokio::test]
+    async fn test_dry_replay_leaves_the_token_total_alone() {
+        let runtime = RARORuntime::new();
+        seed_run_with(&runtime, "rp", vec![agent("a", &[])], serde_json::json!({ "simulation": true, "simulation_delay_ms": 1 }));
+        runtime.runtime_states.get_mut("rp").unwrap().simulation = true;
+        tokio::time::timeout(std::time::Duration::from_secs(10), runtime.execute_dynamic_dag("rp".to_string()))
+            .await
+            .expect("simulated run should finish");
+        let before = runtime.get_state("rp").unwrap();
+        let original = before.invocations[0].id.clone();
+
+        let dry = runtime.replay_invocation("rp", &original, true, false).await.unwrap().invocation.unwrap();
+        assert!(dry.tokens_used > 0);
+        let after = runtime.get_state("rp").unwrap();
+        assert_eq!(after.total_tokens_used, before.total_tokens_used);
+        assert_eq!(after.invocations.len(), before.invocations.len() + 1);
+        // The replay is itself replayable
+        assert!(runtime.get_payload_snapshot("rp", &dry.id).is_some());
+
+        let committed = runtime.replay_invocation("rp", &original, true, true).await.unwrap().invocation.unwrap();
+        assert_eq!(runtime.get_state("rp").unwrap().total_tokens_used, before.total_tokens_used + committed.tokens_used);
+    }
 
-        if tools.contains(&"write_file".to_string()) {
-            final_prompt.push_str("\n\n[SYSTEM NOTICE]: You have access to 'write_file'. When generating a file, DO NOT output the file content in your text response. Simply state 'Writing [filename]...' and then execute the tool immediately. Duplicating content in text and tool arguments is prohibited.");
+    #[tokio::test]
+    async fn test_payload_snapshots_are_capped_and_dropped_when_unrecorded() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "ps", vec![agent("a", &[])]);
+        let payload: InvocationPayload = serde_json::from_value(serde_json::json!({
+            "run_id": "ps", "agent_id": "a", "model": "fast", "prompt": "p", "user_directive": "", "input_data": {},
+            "parent_signature": null, "cached_content_id": null, "thinking_level": null, "file_paths": [], "tools": [],
+            "allow_delegation": false, "graph_view": ""
+        })).unwrap();
+        for i in 0..MAX_PAYLOAD_SNAPSHOTS + 5 {
+            runtime.snapshot_payload(&format!("inv-{}", i), &payload);
         }
+        assert_eq!(runtime.payload_snapshots.get("ps").unwrap().len(), MAX_PAYLOAD_SNAPSHOTS);
+        assert!(runtime.get_payload_snapshot("ps", "inv-0").is_none());
+        assert!(runtime.get_payload_snapshot("ps", &format!("inv-{}", MAX_PAYLOAD_SNAPSHOTS + 4)).is_some());
+        // Scoped to the run that captured it
+        assert!(runtime.get_payload_snapshot("other", &format!("inv-{}", MAX_PAYLOAD_SNAPSHOTS + 4)).is_none());
+
+        // An invocation the run refuses to record leaves no payload behind
+        runtime.runtime_states.get_mut("ps").unwrap().status = RuntimeStatus::Preparing;
+        let last = format!("inv-{}", MAX_PAYLOAD_SNAPSHOTS + 4);
+        let refused = AgentInvocation { id: last.clone(), ..success_invocation("a", 10) };
+        assert!(runtime.record_invocation("ps", refused, None).await.is_err());
+        assert!(runtime.get_payload_snapshot("ps", &last).is_none());
+    }
 
-        // 2. Prepare User Directive (Task + Context - Dynamic Data)
-        // We prepend context to the directive so it appears in the USER message.
-        // This prevents system instruction leakage.
-        let mut final_user_directive = agent_config.user_directive.clone();
+    #[tokio::test]
+    async fn test_event_log_replays_to_live_state() {
+        let runtime = RARORuntime::new();
+        seed_run_with(&runtime, "tt", vec![agent("a", &[]), agent("b", &["a"])],
+            serde_json::json!({ "simulation": true, "simulation_delay_ms": 1 }));
+        runtime.runtime_states.get_mut("tt").unwrap().simulation = true;
+
+        tokio::time::timeout(std::time::Duration::from_secs(10), runtime.execute_dynamic_dag("tt".to_string()))
+            .await
+            .expect("simulated run should finish");
+        assert_eq!(runtime.get_state("tt").unwrap().status, RuntimeStatus::Completed);
+        assert!(runtime.replay_matches_live("tt"));
+
+        // What did the run look like when b started?
+        let events = runtime.get_events("tt");
+        assert!(events.iter().enumerate().all(|(i, e)| e.seq == i as u64 + 1));
+        let b_started = events.iter()
+            .find(|e| matches!(e.event_type, EventType::AgentStarted) && e.agent_id.as_deref() == Some("b"))
+            .unwrap();
+        let then = runtime.state_at("tt", Some(b_started.seq), None).unwrap();
+        assert_eq!(then.status, RuntimeStatus::Running);
+        assert_eq!(then.completed_agents, vec!["a".to_string()]);
+        assert_eq!(then.invocations.iter().filter(|i| i.status == InvocationStatus::Success).count(), 1);
+
+        assert!(matches!(runtime.state_at("tt", Some(0), None), Err(RuntimeError::NoStateHistory(_))));
+        assert!(matches!(runtime.state_at("nope", None, None), Err(RuntimeError::RunNotFound(_))));
+    }
 
-        if !context_prompt_appendix.is_empty() {
-            // Prepend context before the directive so model sees data before command
-            final_user_directive = if !final_user_directive.is_empty() {
-                format!("{}\n\n[OPERATIONAL CONTEXT]\n{}", context_prompt_appendix, final_user_directive)
-            } else {
-                // If no directive, just pass the context
-                context_prompt_appendix
-            };
+    #[tokio::test]
+    async fn test_patch_workflow_validates_and_pins_existing_runs() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"])]);
+        let ops = |v: serde_json::Value| -> Vec<PatchOp> { serde_json::from_value(v).unwrap() };
+
+        let sharper = ops(serde_json::json!([
+            { "op": "replace", "path": "/agents/0/prompt", "value": "You are a sharper a" }
+        ]));
+        // Another tenant can neither patch the workflow nor see that it exists
+        assert!(matches!(runtime.patch_workflow("wf-run-1", "tenant-b", &sharper).await, Err(RuntimeError::WorkflowNotFound(_))));
+
+        let patched = runtime.patch_workflow("wf-run-1", "public", &sharper).await.unwrap();
+        assert_eq!(patched.agents[0].prompt, "You are a sharper a");
+        assert_eq!(runtime.workflows.get("wf-run-1").unwrap().agents[0].prompt, "You are a sharper a");
+
+        // The run keeps the version it started with
+        let pinned_id = runtime.get_state("run-1").unwrap().workflow_id;
+        assert_eq!(pinned_id, "wf-run-1@run-1");
+        assert_eq!(runtime.workflows.get(&pinned_id).unwrap().agents[0].prompt, "You are a");
+
+        let cycle = runtime.patch_workflow("wf-run-1", "public", &ops(serde_json::json!([
+            { "op": "add", "path": "/agents/0/depends_on/-", "value": "b" }
+        ]))).await;
+        match cycle {
+            Err(RuntimeError::InvalidWorkflow(errors)) => {
+                assert!(errors.iter().any(|e| matches!(e, ValidationError::CycleDetected(_))));
+            }
+            other => panic!("expected a validation failure, got {:?}", other.map(|c| c.id)),
         }
+        assert!(runtime.workflows.get("wf-run-1").unwrap().agents[0].depends_on.is_empty());
+
+        let dangling = runtime.patch_workflow("wf-run-1", "public", &ops(serde_json::json!([
+            { "op": "replace", "path": "/agents/1/depends_on/0", "value": "ghost" }
+        ]))).await;
+        assert!(matches!(dangling, Err(RuntimeError::InvalidWorkflow(_))));
+        assert!(matches!(runtime.patch_workflow("nope", "public", &[]).await, Err(RuntimeError::WorkflowNotFound(_))));
+
+        // Deleting the run drops its pinned copy
+        runtime.runtime_states.get_mut("run-1").unwrap().status = RuntimeStatus::Completed;
+        runtime.delete_run("run-1").unwrap();
+        assert!(!runtime.workflows.contains_key(&pinned_id));
+    }
 
-        let graph_view = self.generate_graph_context(
-            run_id,
-            agent_id,
-            agent_config.allow_delegation
-        );
+    #[tokio::test]
+    async fn test_checkpoint_applies_only_to_paused_or_terminal_runs() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"])]);
+        runtime.runtime_states.get_mut("run-1").unwrap().active_agents.push("a".to_string());
+        let checkpoint = RunCheckpoint {
+            run_id: "run-1".to_string(),
+            agent_outputs: HashMap::new(),
+            thought_signatures: HashMap::new(),
+            completed_agents: vec!["a".to_string()],
+            failed_agents: Vec::new(),
+        };
 
-        Ok(InvocationPayload {
-            run_id: run_id.to_string(),
-            agent_id: agent_id.to_string(),
-            model: model_string,
-            prompt: final_prompt,              // Pure Identity (System Instruction)
-            user_directive: final_user_directive,  // Task + Context (User Message)
-            input_data: serde_json::Value::Object(input_data_map),
-            parent_signature,
-            cached_content_id,
-            thinking_level,
-            file_paths: full_file_paths,
-            tools,  // Now contains Architect's choices + smart baseline guarantees
-            allow_delegation: agent_config.allow_delegation,
-            graph_view,
-        })
+        // A running run keeps its in-flight agents
+        assert!(matches!(runtime.apply_checkpoint("run-1", checkpoint.clone()).await, Err(RuntimeError::RunInProgress(_))));
+        assert_eq!(runtime.get_state("run-1").unwrap().active_agents, vec!["a"]);
+
+        runtime.runtime_states.get_mut("run-1").unwrap().status = RuntimeStatus::Failed;
+        runtime.apply_checkpoint("run-1", checkpoint).await.unwrap();
+        let state = runtime.get_state("run-1").unwrap();
+        assert_eq!(state.status, RuntimeStatus::Running);
+        assert_eq!(state.completed_agents, vec!["a"]);
+        assert!(state.active_agents.is_empty());
     }
 
-    pub fn set_run_status(&self, run_id: &str, status: RuntimeStatus) {
-        if let Some(mut state) = self.runtime_states.get_mut(run_id) {
-            state.status = status;
+    #[test]
+    fn test_checkpoint_writes_refused_for_space_are_insufficient_storage() {
+        let refused = |reply: &[u8]| RARORuntime::checkpoint_write_error(redis::parse_redis_value(reply).unwrap_err());
+        for reply in [&b"-OOM command not allowed when used memory > 'maxmemory'.\r\n"[..], b"-MISCONF Redis is configured to save RDB snapshots\r\n"] {
+            assert_eq!(refused(reply).status_code(), axum::http::StatusCode::INSUFFICIENT_STORAGE);
         }
+        assert!(matches!(refused(b"-ERR wrong number of arguments\r\n"), RuntimeError::Persistence(_)));
     }
-    
-    pub fn get_topology_snapshot(&self, run_id: &str) -> Option<serde_json::Value> {
-        // 1. Get the DAG
-        let dag = self.dag_store.get(run_id)?;
 
-        // 2. Get the Workflow Config (where prompts/metadata live)
-        let workflow_id = self.runtime_states.get(run_id).map(|s| s.workflow_id.clone())?;
-        let workflow = self.workflows.get(&workflow_id)?;
+    #[tokio::test]
+    async fn test_create_run_caps_the_prepare_timeout() {
+        let runtime = RARORuntime::new();
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf-cap", "name": "cap", "max_token_budget": 10_000, "timeout_ms": 60_000,
+            "agents": [{ "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "p" }]
+        })).unwrap();
+        let run_id = runtime.create_run(config, "public", std::time::Duration::from_secs(u64::MAX)).await.unwrap();
+
+        let deadline = runtime.get_state(&run_id).unwrap().prepare_deadline.unwrap();
+        let deadline = chrono::DateTime::parse_from_rfc3339(&deadline).unwrap().with_timezone(&Utc);
+        assert!(deadline <= Utc::now() + chrono::Duration::seconds(MAX_PREPARE_TIMEOUT_SECS as i64));
+    }
 
-        let edges = dag.export_edges();
-        let node_ids = dag.export_nodes();
+    #[tokio::test]
+    async fn test_reused_workflow_id_from_another_client_gets_a_private_copy() {
+        let runtime = Arc::new(RARORuntime::new());
+        let config = |prompt: &str| -> WorkflowConfig {
+            serde_json::from_value(serde_json::json!({
+                "id": "shared", "name": "shared", "max_token_budget": 10_000, "timeout_ms": 60_000,
+                "agents": [{ "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": prompt }]
+            })).unwrap()
+        };
+        let run_a = runtime.create_run(config("tenant a"), "tenant-a", std::time::Duration::from_secs(60)).await.unwrap();
+        let run_b = runtime.create_run(config("tenant b"), "tenant-b", std::time::Duration::from_secs(60)).await.unwrap();
+
+        assert_eq!(runtime.get_state(&run_a).unwrap().workflow_id, "shared");
+        assert_eq!(runtime.workflows.get("shared").unwrap().agents[0].prompt, "tenant a");
+        let b_workflow = runtime.get_state(&run_b).unwrap().workflow_id;
+        assert_eq!(b_workflow, format!("shared@{}", run_b));
+        assert_eq!(runtime.workflows.get(&b_workflow).unwrap().agents[0].prompt, "tenant b");
+
+        // Only the registering client may patch the shared id
+        let patch: Vec<PatchOp> = serde_json::from_value(serde_json::json!([
+            { "op": "replace", "path": "/agents/0/prompt", "value": "hijacked" }
+        ])).unwrap();
+        assert!(matches!(runtime.patch_workflow("shared", "tenant-b", &patch).await, Err(RuntimeError::WorkflowNotFound(_))));
+        assert_eq!(runtime.workflows.get("shared").unwrap().agents[0].prompt, "tenant a");
+    }
 
-        // 3. Enrich Nodes with Config Data
-        let enriched_nodes: Vec<serde_json::Value> = node_ids.iter().map(|node_id| {
-            // Find the config for this node
-            let config = workflow.agents.iter().find(|a| a.id == *node_id);
+    #[tokio::test]
+    async fn test_merge_context_for_fan_in() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("merge", &[])]);
+        let mut base = runtime.prepare_invocation_payload("run-1", "merge").await.unwrap();
+        base.parent_signature = Some("sig-base".to_string());
+        base.file_paths = vec!["/in/a.csv".to_string()];
+        base.thinking_level = Some(5);
+
+        let parent = |sig: Option<&str>, files: &[&str]| {
+            let mut p = base.clone();
+            p.model = "other-model".to_string();
+            p.thinking_level = None;
+            p.tools = vec!["web_search".to_string()];
+            p.parent_signature = sig.map(str::to_string);
+            p.file_paths = files.iter().map(|f| f.to_string()).collect();
+            p
+        };
+        let parent_a = parent(Some("sig-a"), &["/in/a.csv", "/out/a.png"]);
+        let parent_b = parent(None, &["/out/b.txt", "/out/a.png"]);
 
-            if let Some(c) = config {
-                serde_json::json!({
-                    "id": c.id,
-                    "label": c.id, // Or human readable name if available
-                    "prompt": c.prompt,
-                    "model": match c.model {
-                        ModelVariant::Fast => "fast",
-                        ModelVariant::Reasoning => "reasoning",
-                        ModelVariant::Thinking => "thinking",
-                        ModelVariant::Custom(ref s) => s.as_str(),
-                    },
-                    "role": match c.role {
-                        AgentRole::Orchestrator => "orchestrator",
-                        AgentRole::Worker => "worker",
-                        AgentRole::Observer => "observer",
-                    },
-                    "tools": c.tools,
-                    "accepts_directive": c.accepts_directive,
-                    "allow_delegation": c.allow_delegation,
-                    // If position is stored in config (from initial layout), pass it
-                    // Dynamic nodes usually lack this, handled by frontend layout engine
-                    "x": c.position.as_ref().map(|p| p.x).unwrap_or(0.0),
-                    "y": c.position.as_ref().map(|p| p.y).unwrap_or(0.0)
-                })
-            } else {
-                // Fallback for purely structural nodes (rare)
-                serde_json::json!({
-                    "id": node_id,
-                    "label": node_id,
-                    "prompt": "Dynamic Agent (Metadata Missing)",
-                    "model": "fast",
-                    "role": "worker",
-                    "tools": [],
-                    "accepts_directive": false,
-                    "allow_delegation": false,
-                    "x": 0.0,
-                    "y": 0.0
-                })
-            }
-        }).collect();
+        let merged = base.merge_context(&parent_a).merge_context(&parent_b);
+        assert_eq!(merged.file_paths, vec!["/in/a.csv", "/out/a.png", "/out/b.txt"]);
+        assert_eq!(merged.parent_signature.as_deref(), Some("sig-base\n---\nsig-a"));
+        assert_eq!((merged.model.as_str(), merged.thinking_level, &merged.tools), (base.model.as_str(), Some(5), &base.tools));
 
-        Some(serde_json::json!({
-            "nodes": enriched_nodes, // <--- Now returns rich objects, not just strings
-            "edges": edges.into_iter().map(|(from, to)| {
-                serde_json::json!({ "from": from, "to": to })
-            }).collect::<Vec<_>>()
-        }))
+        let kept = base.merge_context_with(&parent_a, &signatures::SignatureMergeStrategy::KeepPrimary);
+        assert_eq!(kept.parent_signature.as_deref(), Some("sig-base"));
     }
 
-    pub fn set_cache_resource(&self, run_id: &str, cached_content_id: String) -> Result<(), String> {
-        self.cache_resources.insert(run_id.to_string(), cached_content_id);
-        Ok(())
+    #[tokio::test]
+    async fn test_invocation_costs_attributed_to_run_tags() {
+        let runtime = RARORuntime::new();
+        seed_run(&runtime, "run-1", vec![agent("a", &[]), agent("b", &["a"])]);
+        runtime.runtime_states.get_mut("run-1").unwrap().metadata.extend([
+            ("project".to_string(), serde_json::json!("q3")),
+            ("experiment".to_string(), serde_json::json!({ "arm": 2 })),
+        ]);
+
+        let mut deep = success_invocation("b", 1_000_000);
+        deep.model_variant = ModelVariant::Thinking;
+        runtime.record_invocations("run-1", vec![deep]).await.unwrap();
+        runtime.record_invocation("run-1", success_invocation("a", 2_000_000), None).await.unwrap();
+
+        let report = runtime.costs.report(&"tag:project".parse().unwrap(), None);
+        assert_eq!((report.groups[0].key.as_str(), report.groups[0].invocations, report.groups[0].cost_usd), ("q3", 2, 5.0));
+        assert!(runtime.costs.report(&"tag:experiment".parse().unwrap(), None).groups.is_empty());
+        assert!(runtime.costs.prometheus().contains("raro_tag_cost_usd_total{tag=\"project\",value=\"q3\"} 5"));
     }
 
-    pub fn get_cache_resource(&self, run_id: &str) -> Option<String> {
-        self.cache_resources.get(run_id).map(|c| c.clone())
+    #[tokio::test]
+    async fn test_stage_report_groups_agents() {
+        let runtime = RARORuntime::new();
+        let staged = |id: &str, deps: &[&str], stage: &str| AgentNodeConfig { stage: Some(stage.to_string()), ..agent(id, deps) };
+        seed_run(&runtime, "run-1", vec![
+            staged("search", &[], "research"),
+            staged("summarize", &["search"], "research"),
+            staged("draft", &["summarize"], "drafting"),
+            agent("notify", &["draft"]),
+        ]);
+
+        runtime.record_invocation("run-1", success_invocation("search", 1_000), None).await.unwrap();
+        runtime.record_invocation("run-1", success_invocation("summarize", 2_500), None).await.unwrap();
+        runtime.update_agent_status("run-1", "draft", InvocationStatus::Running).await;
+
+        let stages = runtime.stage_report("run-1").unwrap();
+        let names: Vec<Option<&str>> = stages.iter().map(|s| s.stage.as_deref()).collect();
+        assert_eq!(names, vec![Some("research"), Some("drafting"), None]);
+
+        assert_eq!((stages[0].completed, stages[0].tokens_used, stages[0].status), (2, 3_500, StageStatus::Completed));
+        assert_eq!((stages[1].completed, stages[1].running, stages[1].tokens_used, stages[1].status), (0, 1, 0, StageStatus::Running));
+        assert_eq!((stages[2].agents.clone(), stages[2].pending, stages[2].status), (vec!["notify".to_string()], 1, StageStatus::Pending));
     }
 
-    pub fn has_dag(&self, run_id: &str) -> bool {
-        self.dag_store.contains_key(run_id)
+    #[test]
+    fn test_log_filter_reloads_and_rejects_invalid() {
+        use tracing_subscriber::{prelude::*, reload, Registry};
+
+        let mut runtime = RARORuntime::new();
+        assert!(matches!(runtime.set_log_filter("info"), Err(RuntimeError::NotConfigured(_))));
+
+        let (layer, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(layer);
+        runtime.log_filter = Some(Arc::new(std::sync::Mutex::new(handle.clone())));
+
+        runtime.set_log_filter("raro_kernel=debug,tower_http=warn").unwrap();
+        assert_eq!(handle.with_current(|f| f.to_string()).unwrap(), "raro_kernel=debug,tower_http=warn");
+
+        assert!(matches!(runtime.set_log_filter("raro_kernel=loud"), Err(RuntimeError::InvalidLogFilter(_))));
+        assert!(matches!(runtime.set_log_filter(" "), Err(RuntimeError::InvalidLogFilter(_))));
+        assert_eq!(handle.with_current(|f| f.to_string()).unwrap(), "raro_kernel=debug,tower_http=warn");
+    }
+
+    #[test]
+    fn test_metric_series_are_capped() {
+        let series: BTreeMap<String, u64> = (0..MAX_METRIC_SERIES as u64 + 10).map(|i| (format!("wf-{:03}", i), i + 1)).collect();
+        let capped = cap_series(series);
+        assert_eq!(capped.len(), MAX_METRIC_SERIES + 1);
+        // The ten smallest (1..=10) are summed; the largest survive by name
+        assert_eq!(capped[costs::OVERFLOW_BUCKET], 55);
+        assert!(capped.contains_key(&format!("wf-{:03}", MAX_METRIC_SERIES + 9)));
+        assert!(!capped.contains_key("wf-000"));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_client_ids_anonymized_in_logs_and_metrics() {
+        let runtime = RARORuntime::new();
+        let settings = crate::observability::GlobalSettingsGuard::acquire().await;
+        crate::observability::set_client_anonymization(Some("pepper".to_string()));
+        runtime.halt_client("acme-corp-7", true, "Incident").await;
+        runtime.costs.record_invocation("acme-corp-7", &[], &success_invocation("a", 10));
+        let metrics = runtime.prometheus_metrics();
+        let hashed = crate::observability::anonymize_client("acme-corp-7");
+        drop(settings);
+
+        // Scoping still uses the real id; only what leaves the process is hashed
+        assert!(runtime.is_client_halted("acme-corp-7"));
+        assert!(logs_contain(&hashed));
+        assert!(!logs_contain("acme-corp-7"));
+        // /metrics is public: spend is exported without any client label
+        assert!(metrics.contains("raro_invocations_total 1\n"));
+        assert!(!metrics.contains("client_id="));
+        assert!(!metrics.contains("acme-corp-7"));
     }
-}
\ No newline at end of file
+}
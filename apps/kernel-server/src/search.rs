@@ -0,0 +1,322 @@
+// [[RARO]]/apps/kernel-server/src/search.rs
+// Purpose: In-memory inverted index for finding runs by workflow name, prompts, directives and outputs.
+// Architecture: Query Layer
+// Dependencies: DashMap, Models
+//
+// Postings are kept sorted so a prefix query walks only the tokens that start with it.
+
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{PoisonError, RwLock};
+use crate::models::WorkflowConfig;
+
+/// Fields larger than this are not indexed (keeps huge agent outputs out of memory)
+const MAX_INDEXED_FIELD_BYTES: usize = 32 * 1024;
+/// Characters of context on each side of a match in a snippet
+const SNIPPET_RADIUS: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    WorkflowName,
+    UserDirective,
+    Prompt,
+    Output,
+}
+
+impl SearchField {
+    fn weight(&self) -> usize {
+        match self {
+            SearchField::WorkflowName => 4,
+            SearchField::UserDirective => 3,
+            SearchField::Prompt => 1,
+            SearchField::Output => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IndexedField {
+    field: SearchField,
+    agent_id: Option<String>,
+    text: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RunDocument {
+    client_id: String,
+    workflow_id: String,
+    workflow_name: String,
+    fields: Vec<IndexedField>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchMatch {
+    pub field: SearchField,
+    pub agent_id: Option<String>,
+    pub snippet: String,
+    /// Character ranges [start, end) inside `snippet` that matched a query term
+    pub highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub run_id: String,
+    pub workflow_id: String,
+    pub workflow_name: String,
+    pub score: usize,
+    pub matches: Vec<SearchMatch>,
+}
+
+pub struct SearchIndex {
+    docs: DashMap<String, RunDocument>,        // run_id -> indexed text
+    postings: RwLock<BTreeMap<String, HashSet<String>>>, // token -> run_ids (candidate filter only)
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self {
+            docs: DashMap::new(),
+            postings: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// (Re)index the static parts of a run: workflow name, prompts and directives.
+    /// Previously indexed outputs are kept.
+    pub fn index_workflow(&self, run_id: &str, client_id: &str, workflow: &WorkflowConfig) {
+        let mut fields: Vec<IndexedField> = vec![IndexedField {
+            field: SearchField::WorkflowName,
+            agent_id: None,
+            text: workflow.name.clone(),
+        }];
+
+        for agent in &workflow.agents {
+            fields.push(IndexedField { field: SearchField::Prompt, agent_id: Some(agent.id.clone()), text: agent.prompt.clone() });
+            if !agent.user_directive.is_empty() {
+                fields.push(IndexedField { field: SearchField::UserDirective, agent_id: Some(agent.id.clone()), text: agent.user_directive.clone() });
+            }
+        }
+
+        let fields: Vec<IndexedField> = fields.into_iter().filter(Self::within_cap).collect();
+        for f in &fields {
+            self.add_postings(run_id, &f.text);
+        }
+
+        let mut doc = self.docs.entry(run_id.to_string()).or_default();
+        doc.client_id = client_id.to_string();
+        doc.workflow_id = workflow.id.clone();
+        doc.workflow_name = workflow.name.clone();
+        doc.fields.retain(|f| f.field == SearchField::Output);
+        doc.fields.extend(fields);
+    }
+
+    /// Make a run searchable when only its identity is known (e.g. rehydrated without its workflow)
+    pub fn register_run(&self, run_id: &str, client_id: &str, workflow_id: &str) {
+        let mut doc = self.docs.entry(run_id.to_string()).or_default();
+        doc.client_id = client_id.to_string();
+        doc.workflow_id = workflow_id.to_string();
+    }
+
+    /// Unindex a deleted run
+    pub fn remove_run(&self, run_id: &str) {
+        self.docs.remove(run_id);
+        self.postings.write().unwrap_or_else(PoisonError::into_inner).retain(|_, runs| {
+            runs.remove(run_id);
+            !runs.is_empty()
+        });
+    }
+
+    /// Index (or replace) the output text of one agent
+    pub fn index_output(&self, run_id: &str, agent_id: &str, text: &str) {
+        let field = IndexedField { field: SearchField::Output, agent_id: Some(agent_id.to_string()), text: text.to_string() };
+        if !Self::within_cap(&field) {
+            tracing::debug!("Skipping search indexing of {} output for run {} ({} bytes)", agent_id, run_id, text.len());
+            return;
+        }
+
+        self.add_postings(run_id, text);
+        if let Some(mut doc) = self.docs.get_mut(run_id) {
+            doc.fields.retain(|f| !(f.field == SearchField::Output && f.agent_id.as_deref() == Some(agent_id)));
+            doc.fields.push(field);
+        }
+    }
+
+    /// Tokenized, case-insensitive search. Every query term must prefix-match a token in the run.
+    pub fn search(&self, client_id: &str, query: &str, limit: usize) -> Vec<SearchHit> {
+        let terms = tokenize(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        // 1. Candidate runs via postings (prefix match per term, AND across terms)
+        let mut candidates: Option<HashSet<String>> = None;
+        let postings = self.postings.read().unwrap_or_else(PoisonError::into_inner);
+        for term in &terms {
+            let mut runs_for_term = HashSet::new();
+            for (_, runs) in postings.range(term.clone()..).take_while(|(token, _)| token.starts_with(term.as_str())) {
+                runs_for_term.extend(runs.iter().cloned());
+            }
+            candidates = Some(match candidates {
+                None => runs_for_term,
+                Some(prev) => prev.intersection(&runs_for_term).cloned().collect(),
+            });
+        }
+        drop(postings);
+
+        // 2. Verify + score against the stored text (postings may be stale after re-indexing)
+        let mut hits: Vec<SearchHit> = candidates.unwrap_or_default().into_iter().filter_map(|run_id| {
+            let doc = self.docs.get(&run_id)?;
+            if doc.client_id != client_id {
+                return None;
+            }
+
+            let mut score = 0;
+            let mut matches = Vec::new();
+            let mut terms_seen: HashSet<&str> = HashSet::new();
+
+            for f in &doc.fields {
+                let lower = f.text.to_lowercase();
+                let mut field_hits = 0;
+                for term in &terms {
+                    let count = lower.matches(term.as_str()).count();
+                    if count > 0 {
+                        terms_seen.insert(term.as_str());
+                        field_hits += count;
+                    }
+                }
+                if field_hits > 0 {
+                    score += field_hits * f.field.weight();
+                    matches.push(make_snippet(f, &terms));
+                }
+            }
+
+            if terms_seen.len() < terms.len() {
+                return None;
+            }
+
+            Some(SearchHit {
+                run_id: run_id.clone(),
+                workflow_id: doc.workflow_id.clone(),
+                workflow_name: doc.workflow_name.clone(),
+                score,
+                matches,
+            })
+        }).collect();
+
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.run_id.cmp(&b.run_id)));
+        hits.truncate(limit);
+        hits
+    }
+
+    fn within_cap(field: &IndexedField) -> bool {
+        !field.text.is_empty() && field.text.len() <= MAX_INDEXED_FIELD_BYTES
+    }
+
+    fn add_postings(&self, run_id: &str, text: &str) {
+        let mut postings = self.postings.write().unwrap_or_else(PoisonError::into_inner);
+        for token in tokenize(text) {
+            postings.entry(token).or_default().insert(run_id.to_string());
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .filter(|t| seen.insert(t.clone()))
+        .collect()
+}
+
+/// Window of text around the first matching term, with every term occurrence highlighted
+fn make_snippet(field: &IndexedField, terms: &[String]) -> SearchMatch {
+    let chars: Vec<char> = field.text.chars().collect();
+    let lower: Vec<char> = field.text.to_lowercase().chars().collect();
+    // to_lowercase can change length for some scripts; fall back to the original if so
+    let haystack = if lower.len() == chars.len() { &lower } else { &chars };
+
+    let find = |term: &[char], from: usize| -> Option<usize> {
+        (from..haystack.len().saturating_sub(term.len() - 1)).find(|&i| haystack[i..i + term.len()] == *term)
+    };
+
+    let term_chars: Vec<Vec<char>> = terms.iter().map(|t| t.chars().collect()).collect();
+    let first = term_chars.iter().filter_map(|t| find(t, 0)).min().unwrap_or(0);
+
+    let start = first.saturating_sub(SNIPPET_RADIUS);
+    let end = (first + SNIPPET_RADIUS).min(chars.len());
+
+    let mut highlights = Vec::new();
+    for t in &term_chars {
+        let mut pos = start;
+        while let Some(i) = find(t, pos) {
+            if i + t.len() > end {
+                break;
+            }
+            highlights.push((i - start, i - start + t.len()));
+            pos = i + t.len();
+        }
+    }
+    highlights.sort();
+
+    SearchMatch {
+        field: field.field,
+        agent_id: field.agent_id.clone(),
+        snippet: chars[start..end].iter().collect(),
+        highlights,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflow(name: &str, directive: &str) -> WorkflowConfig {
+        serde_json::from_value(serde_json::json!({
+            "id": format!("wf-{}", name),
+            "name": name,
+            "agents": [{
+                "id": "researcher",
+                "role": "worker",
+                "model": "fast",
+                "tools": [],
+                "prompt": "You are a financial researcher.",
+                "user_directive": directive,
+                "position": null
+            }],
+            "max_token_budget": 1000,
+            "timeout_ms": 1000
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_search_ranks_and_scopes_by_client() {
+        let index = SearchIndex::new();
+        index.index_workflow("run-1", "alice", &workflow("Quarterly Review", "Summarize the Q3 forecast"));
+        index.index_workflow("run-2", "alice", &workflow("Hiring Plan", "Draft a hiring plan"));
+        index.index_workflow("run-3", "bob", &workflow("Q3 Forecast", "Summarize the Q3 forecast"));
+        index.index_output("run-2", "researcher", "Mentions the Q3 forecast once");
+
+        let hits = index.search("alice", "q3 FORECAST", 10);
+        assert_eq!(hits.iter().map(|h| h.run_id.as_str()).collect::<Vec<_>>(), vec!["run-1", "run-2"]);
+
+        let m = &hits[0].matches[0];
+        assert_eq!(m.field, SearchField::UserDirective);
+        let (s, e) = m.highlights[0];
+        assert_eq!(m.snippet.chars().skip(s).take(e - s).collect::<String>(), "Q3");
+
+        // Prefix matching
+        assert_eq!(index.search("alice", "forec", 10).len(), 2);
+        assert!(index.search("alice", "nonexistent", 10).is_empty());
+    }
+
+    #[test]
+    fn test_huge_outputs_not_indexed() {
+        let index = SearchIndex::new();
+        index.index_workflow("run-1", "alice", &workflow("Review", ""));
+        let huge = format!("needle {}", "x".repeat(MAX_INDEXED_FIELD_BYTES));
+        index.index_output("run-1", "researcher", &huge);
+        assert!(index.search("alice", "needle", 10).is_empty());
+    }
+}
@@ -4,6 +4,9 @@ use axum::{
     extract::FromRequestParts,
     http::{request::Parts, StatusCode},
 };
+use crate::fs_manager::{WorkspaceInitializer, DEFAULTS_SCOPE};
+use crate::observability::anonymize_client;
+use sha2::{Digest, Sha256};
 
 pub struct ClientSession(pub String);
 
@@ -22,12 +25,72 @@ where
             .and_then(|h| h.to_str().ok())
             .unwrap_or("public"); // Default to public/anon if missing (e.g. Health checks)
 
+        // The starter library is an admin-only scope; its files go through the normal library endpoints
+        if client_id == DEFAULTS_SCOPE {
+            check_admin_token(parts)?;
+            return Ok(ClientSession(client_id.to_string()));
+        }
+
         // Basic Sanitization (Alphanumeric + dashes only) to prevent directory traversal attacks
         if !client_id.chars().all(|c| c.is_alphanumeric() || c == '-') {
-            tracing::warn!("Invalid Client ID rejected: {}", client_id);
+            tracing::warn!("Invalid Client ID rejected: {}", anonymize_client(client_id));
             return Err(StatusCode::BAD_REQUEST);
         }
 
+        // First sighting: seed the private library from the defaults (never fails the request)
+        if let Err(e) = WorkspaceInitializer::ensure_client_workspace(client_id).await {
+            tracing::warn!("Workspace bootstrap failed for client {}: {}", anonymize_client(client_id), e);
+        }
+
         Ok(ClientSession(client_id.to_string()))
     }
 }
+
+/// Operator identity for /admin routes.
+/// Requires X-RARO-ADMIN-TOKEN to match the RARO_ADMIN_TOKEN env var; admin is disabled when unset.
+pub struct AdminSession;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminSession
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        check_admin_token(parts)?;
+        Ok(AdminSession)
+    }
+}
+
+/// Shared by AdminSession and the admin-only `_defaults` client scope
+fn check_admin_token(parts: &Parts) -> Result<(), StatusCode> {
+    let expected = match std::env::var("RARO_ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => {
+            tracing::warn!("Admin request rejected: RARO_ADMIN_TOKEN is not configured");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    };
+
+    let provided = parts
+        .headers
+        .get("X-RARO-ADMIN-TOKEN")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+
+    if !tokens_match(provided, &expected) {
+        tracing::warn!("Admin request rejected: invalid token");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(())
+}
+
+/// Compares SHA-256 digests of both tokens without an early exit, so response timing reveals
+/// neither how much of a guess was right nor the token's length
+fn tokens_match(provided: &str, expected: &str) -> bool {
+    let (provided, expected) = (Sha256::digest(provided.as_bytes()), Sha256::digest(expected.as_bytes()));
+    let diff = provided.iter().zip(expected.iter()).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
@@ -1 +1,4 @@
 pub mod handlers;
+pub mod error;
+pub mod timeout;
+pub mod security_headers;
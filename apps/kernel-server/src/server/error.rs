@@ -0,0 +1,447 @@
+// [[RARO]]/apps/kernel-server/src/server/error.rs
+// Purpose: HTTP rendering of RuntimeError, so handlers can return Result<_, RuntimeError> and use `?`.
+//          Every error response carries a JSON body with a stable code; see ERROR_CODES.
+// Architecture: API Layer
+// Dependencies: Axum, Runtime
+
+use axum::{
+    body::HttpBody,
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::io::ErrorKind;
+use serde::Serialize;
+use crate::dag::DAGError;
+use crate::fs_manager;
+use crate::models::ValidationError;
+use crate::runtime::RuntimeError;
+
+/// Code of the 410 reject_aborted_runs answers with; the one code middleware emits directly
+pub const RUN_ABORTED: &str = "run_aborted";
+
+/// Every `error` code a response can carry: each RuntimeError's code(), RUN_ABORTED, and the
+/// snake_case reason ensure_error_body gives each bare status the server returns.
+/// Published under /capabilities so SDKs can switch on codes instead of messages. Tests keep
+/// it in step with every RuntimeError variant and bare status, both ways.
+pub const ERROR_CODES: &[&str] = &[
+    "run_not_found",
+    "checkpoint_mismatch",
+    "persistence_unavailable",
+    "invocation_not_found",
+    "agent_service_error",
+    "run_already_exists",
+    "invalid_import",
+    "agent_not_found",
+    "not_configured",
+    "unknown_targets",
+    "pattern_action_failed",
+    "invalid_directive",
+    "run_in_progress",
+    "dag_error",
+    "invalid_node",
+    "dependency_not_found",
+    "not_awaiting_approval",
+    "invalid_priority",
+    "workflow_not_found",
+    "invalid_patch",
+    "validation_failed",
+    "no_state_history",
+    "state_replay_failed",
+    "client_halted",
+    "maintenance",
+    "model_not_allowed",
+    "model_quota_exceeded",
+    "invalid_config",
+    "storage_error",
+    "insufficient_storage",
+    "context_drought",
+    "template_error",
+    "invalid_signature",
+    "webhook_failed",
+    "invalid_log_filter",
+    "malformed_workflow",
+    RUN_ABORTED,
+    "rate_limited",
+    "memory_key_not_found",
+    "memory_limit_exceeded",
+    "not_preparing",
+    "run_not_launched",
+    "run_not_running",
+    "agent_not_skippable",
+    "agent_not_patchable",
+    "share_not_found",
+    "invalid_share_token",
+    "agent_in_flight",
+    "hook_refused",
+    // Bare statuses (handlers, extractors, the router, the timeout layer)
+    "bad_request",
+    "unauthorized",
+    "forbidden",
+    "not_found",
+    "method_not_allowed",
+    "request_timeout",
+    "conflict",
+    "gone",
+    "payload_too_large",
+    "unprocessable_entity",
+    "too_many_requests",
+    "internal_server_error",
+    "bad_gateway",
+    "service_unavailable",
+];
+
+/// Error body shared by every handler that fails with a RuntimeError
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    /// Stable snake_case code, e.g. "run_not_found"
+    pub error: &'static str,
+    pub message: String,
+    /// Individual problems for "validation_failed"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ValidationError>,
+}
+
+impl RuntimeError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            RuntimeError::RunNotFound(_)
+            | RuntimeError::InvocationNotFound(_)
+            | RuntimeError::AgentNotFound(_)
+            | RuntimeError::NoStateHistory(_)
+            | RuntimeError::MemoryKeyNotFound(_)
+            | RuntimeError::ShareNotFound(_)
+            | RuntimeError::InvalidShareToken
+            | RuntimeError::WorkflowNotFound(_) => StatusCode::NOT_FOUND,
+            RuntimeError::CheckpointMismatch { .. }
+            | RuntimeError::InvalidImport(_)
+            | RuntimeError::UnknownTargets(_)
+            | RuntimeError::InvalidPriority(_)
+            | RuntimeError::InvalidPatch(_)
+            | RuntimeError::InvalidConfig(_)
+            | RuntimeError::Template(_)
+            | RuntimeError::InvalidSignature(_)
+            | RuntimeError::Dag(DAGError::InvalidNode(_) | DAGError::DependencyNotFound(_))
+            | RuntimeError::InvalidWorkflow(_) => StatusCode::BAD_REQUEST,
+            RuntimeError::ClientHalted(_) | RuntimeError::ModelNotAllowed { .. } => StatusCode::FORBIDDEN,
+            RuntimeError::InvalidLogFilter(_) | RuntimeError::MalformedWorkflow(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            RuntimeError::RateLimited { .. } | RuntimeError::ModelQuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            RuntimeError::MemoryLimit(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            RuntimeError::RunAlreadyExists(_) => StatusCode::CONFLICT,
+            RuntimeError::Persistence(_) | RuntimeError::NotConfigured(_) | RuntimeError::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+            RuntimeError::AgentService(_) | RuntimeError::Webhook(_) => StatusCode::BAD_GATEWAY,
+            RuntimeError::Storage(e) if fs_manager::is_storage_full(e) => StatusCode::INSUFFICIENT_STORAGE,
+            RuntimeError::Storage(e) => match e.kind() {
+                ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+                ErrorKind::InvalidInput | ErrorKind::PermissionDenied => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            RuntimeError::PatternAction(_) | RuntimeError::Dag(_) | RuntimeError::StateReplay(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            RuntimeError::InvalidDirective(_)
+            | RuntimeError::RunInProgress(_)
+            | RuntimeError::ContextDrought(_)
+            | RuntimeError::NotPreparing(_)
+            | RuntimeError::RunNotRunning(_)
+            | RuntimeError::RunNotLaunched(_)
+            | RuntimeError::AgentNotSkippable(_)
+            | RuntimeError::AgentNotPatchable { .. }
+            | RuntimeError::AgentInFlight { .. }
+            | RuntimeError::NotAwaitingApproval(_) => StatusCode::CONFLICT,
+            RuntimeError::HookRefused { .. } => StatusCode::FORBIDDEN,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::RunNotFound(_) => "run_not_found",
+            RuntimeError::CheckpointMismatch { .. } => "checkpoint_mismatch",
+            RuntimeError::Persistence(_) => "persistence_unavailable",
+            RuntimeError::InvocationNotFound(_) => "invocation_not_found",
+            RuntimeError::AgentService(_) => "agent_service_error",
+            RuntimeError::RunAlreadyExists(_) => "run_already_exists",
+            RuntimeError::InvalidImport(_) => "invalid_import",
+            RuntimeError::AgentNotFound(_) => "agent_not_found",
+            RuntimeError::NotConfigured(_) => "not_configured",
+            RuntimeError::UnknownTargets(_) => "unknown_targets",
+            RuntimeError::PatternAction(_) => "pattern_action_failed",
+            RuntimeError::InvalidDirective(_) => "invalid_directive",
+            RuntimeError::RunInProgress(_) => "run_in_progress",
+            RuntimeError::Dag(DAGError::InvalidNode(_)) => "invalid_node",
+            RuntimeError::Dag(DAGError::DependencyNotFound(_)) => "dependency_not_found",
+            RuntimeError::Dag(_) => "dag_error",
+            RuntimeError::NotAwaitingApproval(_) => "not_awaiting_approval",
+            RuntimeError::InvalidPriority(_) => "invalid_priority",
+            RuntimeError::WorkflowNotFound(_) => "workflow_not_found",
+            RuntimeError::InvalidPatch(_) => "invalid_patch",
+            RuntimeError::InvalidWorkflow(_) => "validation_failed",
+            RuntimeError::NoStateHistory(_) => "no_state_history",
+            RuntimeError::StateReplay(_) => "state_replay_failed",
+            RuntimeError::ClientHalted(_) => "client_halted",
+            RuntimeError::Maintenance => "maintenance",
+            RuntimeError::ModelNotAllowed { .. } => "model_not_allowed",
+            RuntimeError::ModelQuotaExceeded { .. } => "model_quota_exceeded",
+            RuntimeError::InvalidConfig(_) => "invalid_config",
+            RuntimeError::Storage(e) if fs_manager::is_storage_full(e) => "insufficient_storage",
+            RuntimeError::Storage(_) => "storage_error",
+            RuntimeError::ContextDrought(_) => "context_drought",
+            RuntimeError::Template(_) => "template_error",
+            RuntimeError::InvalidSignature(_) => "invalid_signature",
+            RuntimeError::Webhook(_) => "webhook_failed",
+            RuntimeError::InvalidLogFilter(_) => "invalid_log_filter",
+            RuntimeError::MalformedWorkflow(_) => "malformed_workflow",
+            RuntimeError::RateLimited { .. } => "rate_limited",
+            RuntimeError::MemoryKeyNotFound(_) => "memory_key_not_found",
+            RuntimeError::MemoryLimit(_) => "memory_limit_exceeded",
+            RuntimeError::NotPreparing(_) => "not_preparing",
+            RuntimeError::RunNotLaunched(_) => "run_not_launched",
+            RuntimeError::RunNotRunning(_) => "run_not_running",
+            RuntimeError::AgentNotSkippable(_) => "agent_not_skippable",
+            RuntimeError::AgentNotPatchable { .. } => "agent_not_patchable",
+            RuntimeError::ShareNotFound(_) => "share_not_found",
+            RuntimeError::InvalidShareToken => "invalid_share_token",
+            RuntimeError::AgentInFlight { .. } => "agent_in_flight",
+            RuntimeError::HookRefused { .. } => "hook_refused",
+        }
+    }
+}
+
+impl IntoResponse for RuntimeError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status.is_server_error() {
+            tracing::error!("Request failed: {}", self);
+        } else {
+            tracing::debug!("Request rejected: {}", self);
+        }
+
+        // Whole seconds, rounded up so a client never retries into the same window
+        let retry_after = match &self {
+            RuntimeError::RateLimited { retry_after_ms } => Some(retry_after_ms.div_ceil(1000).max(1)),
+            _ => None,
+        };
+
+        let body = ApiError {
+            error: self.code(),
+            message: self.to_string(),
+            errors: match self {
+                RuntimeError::InvalidWorkflow(errors) => errors,
+                _ => Vec::new(),
+            },
+        };
+        let mut response = (status, Json(body)).into_response();
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(axum::http::header::RETRY_AFTER, secs.into());
+        }
+        response
+    }
+}
+
+/// For handlers that mix RuntimeError with other failures (`Result<_, Response>`)
+impl From<RuntimeError> for Response {
+    fn from(e: RuntimeError) -> Self {
+        e.into_response()
+    }
+}
+
+/// ensure_error_body's code for a bare status: its reason in snake_case, e.g. "not_found"
+fn bare_status_code(status: StatusCode) -> String {
+    status.canonical_reason().unwrap_or("Error").to_lowercase().replace(['-', ' '], "_")
+}
+
+/// Outermost middleware: a 4xx/5xx with an empty body (a bare StatusCode from a handler,
+/// extractor or router fallback) gets an ApiError-shaped body, so clients never have to
+/// special-case bodiless failures.
+pub async fn ensure_error_body(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) || response.body().size_hint().exact() != Some(0) {
+        return response;
+    }
+
+    let body = serde_json::json!({
+        "error": bare_status_code(status),
+        "message": status.canonical_reason().unwrap_or("Error"),
+    });
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    (parts, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_runtime_error_renders_api_error() {
+        let response = RuntimeError::RunNotFound("run-9".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": "run_not_found", "message": "Run not found: run-9" }));
+
+        let invalid = RuntimeError::InvalidWorkflow(vec![ValidationError::BudgetBelowMinimum]).into_response();
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(invalid.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((body["error"].as_str(), body["errors"].as_array().map(Vec::len)), (Some("validation_failed"), Some(1)));
+    }
+
+    /// Statuses the server can return without a body; each reaches clients under
+    /// bare_status_code. Add to it (and ERROR_CODES) when a handler starts returning a new one.
+    const BARE_STATUSES: &[StatusCode] = &[
+        StatusCode::BAD_REQUEST, StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::NOT_FOUND,
+        StatusCode::METHOD_NOT_ALLOWED, StatusCode::REQUEST_TIMEOUT, StatusCode::CONFLICT, StatusCode::GONE,
+        StatusCode::PAYLOAD_TOO_LARGE, StatusCode::UNPROCESSABLE_ENTITY, StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY, StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::INSUFFICIENT_STORAGE,
+    ];
+
+    /// One of every RuntimeError variant, plus the sub-cases that have codes of their own.
+    /// The match has no wildcard, so a new variant does not compile until it is listed here.
+    fn every_error() -> Vec<RuntimeError> {
+        let s = || "x".to_string();
+        let errors = vec![
+            RuntimeError::RunNotFound(s()),
+            RuntimeError::CheckpointMismatch { expected: s(), found: s() },
+            RuntimeError::Persistence(s()),
+            RuntimeError::InvocationNotFound(s()),
+            RuntimeError::AgentService(s()),
+            RuntimeError::RunAlreadyExists(s()),
+            RuntimeError::InvalidImport(s()),
+            RuntimeError::AgentNotFound(s()),
+            RuntimeError::NotConfigured(s()),
+            RuntimeError::UnknownTargets(vec![s()]),
+            RuntimeError::PatternAction(s()),
+            RuntimeError::InvalidDirective(s()),
+            RuntimeError::RunInProgress(s()),
+            RuntimeError::Dag(DAGError::CycleDetected),
+            RuntimeError::Dag(DAGError::InvalidNode(s())),
+            RuntimeError::Dag(DAGError::DependencyNotFound(s())),
+            RuntimeError::NotAwaitingApproval(s()),
+            RuntimeError::InvalidPriority(200),
+            RuntimeError::WorkflowNotFound(s()),
+            RuntimeError::InvalidPatch(s()),
+            RuntimeError::InvalidWorkflow(vec![]),
+            RuntimeError::NoStateHistory(s()),
+            RuntimeError::StateReplay(s()),
+            RuntimeError::ClientHalted(s()),
+            RuntimeError::Maintenance,
+            RuntimeError::ModelNotAllowed { agent_id: s(), model: s() },
+            RuntimeError::ModelQuotaExceeded { model: s(), limit: 1 },
+            RuntimeError::InvalidConfig(s()),
+            RuntimeError::Storage(std::io::Error::from(ErrorKind::Other)),
+            RuntimeError::Storage(std::io::Error::from(ErrorKind::StorageFull)),
+            RuntimeError::ContextDrought(s()),
+            RuntimeError::Template(s()),
+            RuntimeError::InvalidSignature(s()),
+            RuntimeError::Webhook(s()),
+            RuntimeError::InvalidLogFilter(s()),
+            RuntimeError::MalformedWorkflow(s()),
+            RuntimeError::RateLimited { retry_after_ms: 1 },
+            RuntimeError::MemoryKeyNotFound(s()),
+            RuntimeError::MemoryLimit(crate::blackboard::MemoryLimitError::TooManyKeys(1)),
+            RuntimeError::NotPreparing(s()),
+            RuntimeError::RunNotLaunched(s()),
+            RuntimeError::AgentNotSkippable(s()),
+            RuntimeError::AgentNotPatchable { agent_id: s(), reason: "x" },
+            RuntimeError::RunNotRunning(s()),
+            RuntimeError::ShareNotFound(s()),
+            RuntimeError::InvalidShareToken,
+            RuntimeError::AgentInFlight { run_id: s(), agent_id: s() },
+            RuntimeError::HookRefused { agent_id: s(), reason: s() },
+        ];
+        for e in &errors {
+            match e {
+                RuntimeError::RunNotFound(_) | RuntimeError::CheckpointMismatch { .. } | RuntimeError::Persistence(_)
+                | RuntimeError::InvocationNotFound(_) | RuntimeError::AgentService(_) | RuntimeError::RunAlreadyExists(_)
+                | RuntimeError::InvalidImport(_) | RuntimeError::AgentNotFound(_) | RuntimeError::NotConfigured(_)
+                | RuntimeError::UnknownTargets(_) | RuntimeError::PatternAction(_) | RuntimeError::InvalidDirective(_)
+                | RuntimeError::RunInProgress(_) | RuntimeError::Dag(_) | RuntimeError::NotAwaitingApproval(_)
+                | RuntimeError::InvalidPriority(_) | RuntimeError::WorkflowNotFound(_) | RuntimeError::InvalidPatch(_)
+                | RuntimeError::InvalidWorkflow(_) | RuntimeError::NoStateHistory(_) | RuntimeError::StateReplay(_)
+                | RuntimeError::ClientHalted(_) | RuntimeError::Maintenance | RuntimeError::ModelNotAllowed { .. }
+                | RuntimeError::ModelQuotaExceeded { .. } | RuntimeError::InvalidConfig(_) | RuntimeError::Storage(_)
+                | RuntimeError::ContextDrought(_) | RuntimeError::Template(_) | RuntimeError::InvalidSignature(_)
+                | RuntimeError::Webhook(_) | RuntimeError::InvalidLogFilter(_) | RuntimeError::MalformedWorkflow(_)
+                | RuntimeError::RateLimited { .. } | RuntimeError::MemoryKeyNotFound(_) | RuntimeError::MemoryLimit(_)
+                | RuntimeError::NotPreparing(_) | RuntimeError::RunNotLaunched(_) | RuntimeError::AgentNotSkippable(_)
+                | RuntimeError::AgentNotPatchable { .. } | RuntimeError::RunNotRunning(_) | RuntimeError::ShareNotFound(_)
+                | RuntimeError::InvalidShareToken | RuntimeError::AgentInFlight { .. } | RuntimeError::HookRefused { .. } => {}
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn test_error_codes_match_what_the_server_emits() {
+        let mut emitted: Vec<String> = every_error().iter().map(|e| e.code().to_string())
+            .chain([RUN_ABORTED.to_string()])
+            .chain(BARE_STATUSES.iter().map(|s| bare_status_code(*s)))
+            .collect();
+        emitted.sort();
+        emitted.dedup();
+        let mut published: Vec<String> = ERROR_CODES.iter().map(|c| c.to_string()).collect();
+        published.sort();
+        assert_eq!(published.len(), ERROR_CODES.len(), "ERROR_CODES lists a code twice");
+        published.dedup();
+        assert_eq!(published, emitted);
+    }
+
+    #[test]
+    fn test_error_statuses() {
+        let errors = [
+            RuntimeError::ClientHalted("c".to_string()),
+            RuntimeError::InvalidConfig("env".to_string()),
+            RuntimeError::Storage(std::io::Error::from(ErrorKind::AlreadyExists)),
+            RuntimeError::ContextDrought("a".to_string()),
+            RuntimeError::Template("prompt".to_string()),
+            RuntimeError::InvalidSignature("empty".to_string()),
+            RuntimeError::Webhook("HTTP 500".to_string()),
+            RuntimeError::RunNotFound("r".to_string()),
+            RuntimeError::StateReplay("bad".to_string()),
+            RuntimeError::Dag(DAGError::DependencyNotFound("ghost".to_string())),
+            RuntimeError::Dag(DAGError::CycleDetected),
+            RuntimeError::Storage(std::io::Error::from(ErrorKind::StorageFull)),
+            RuntimeError::AgentInFlight { run_id: "r".to_string(), agent_id: "a".to_string() },
+            RuntimeError::HookRefused { agent_id: "a".to_string(), reason: "vetoed".to_string() },
+        ];
+        assert_eq!(errors[2].status_code(), StatusCode::CONFLICT);
+        // A dangling reference is the caller's mistake; a cycle slipping through is ours
+        assert_eq!((errors[9].code(), errors[9].status_code()), ("dependency_not_found", StatusCode::BAD_REQUEST));
+        assert_eq!(errors[10].status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!((errors[11].code(), errors[11].status_code()), ("insufficient_storage", StatusCode::INSUFFICIENT_STORAGE));
+        assert_eq!(errors[12].status_code(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_bare_status_gets_error_body() {
+        use axum::{routing::get, Router};
+
+        let app = Router::new()
+            .route("/bare", get(|| async { StatusCode::FORBIDDEN }))
+            .route("/typed", get(|| async { RuntimeError::RunNotFound("r".to_string()) }))
+            .layer(axum::middleware::from_fn(ensure_error_body));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let call = |path: &'static str| {
+            let url = format!("{}{}", base, path);
+            async move {
+                let response = reqwest::get(url).await.unwrap();
+                (response.status().as_u16(), response.json::<serde_json::Value>().await.unwrap())
+            }
+        };
+
+        let (status, body) = call("/bare").await;
+        assert_eq!((status, body["error"].as_str()), (403, Some("forbidden")));
+        let (status, body) = call("/missing-route").await;
+        assert_eq!((status, body["error"].as_str()), (404, Some("not_found")));
+        // Bodies that are already there are left alone
+        let (_, body) = call("/typed").await;
+        assert_eq!(body["error"], "run_not_found");
+    }
+}
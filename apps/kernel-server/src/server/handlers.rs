@@ -6,26 +6,190 @@
 use axum::{
     extract::{Path, State, Json, Query, Multipart, ws::{WebSocket, WebSocketUpgrade}},
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
 use serde_json::json;
+use std::collections::HashMap;
 use std::sync::Arc;
 use futures::{sink::SinkExt, stream::StreamExt};
 use axum::extract::ws::Message;
 use axum::body::Body;
 use tokio_util::io::ReaderStream; // You might need: cargo add tokio-util
 use redis::AsyncCommands;
+use tracing::Instrument;
 
 use crate::models::*;
-use crate::runtime::{RARORuntime, InvocationPayload};
-use crate::fs_manager::{WorkspaceInitializer, ArtifactMetadata}; // Import the manager and metadata
-use crate::security::ClientSession; // Import extractor
-
-use tokio::fs; // For listing library files
+use crate::observability::{anonymize_client, MetricsTrend, ModelUsage, SystemStatus, DEFAULT_TREND_WINDOW_HOURS, MAX_TREND_WINDOW_HOURS};
+use crate::capabilities::Capabilities;
+use crate::runtime::{RARORuntime, AgentLogEntry, BatchRecordResult, CacheRegistration, GcRequest, GcResult, InvocationPayload, MaintenanceMode, MaintenanceStatus, PatchAgentConfig, PromoteArtifactsRequest, ReplayResult, RoutingDecision, RunExport, RunResult, RuntimeError, ScheduledRun, SharedRunView, ShareRequest, StageSummary};
+use crate::fs_manager::{self, WorkspaceInitializer, ArtifactListFilter, ArtifactMetadata, ArtifactRunSummary, Tombstone}; // Import the manager and metadata
+use crate::security::{AdminSession, ClientSession}; // Import extractors
+use crate::model_registry::ModelMapping;
+use crate::usage::UsageReport;
+use crate::client_policy::{ClientProfile, ModelPolicyReport};
+use crate::costs::{CostBreakdown, CostGrouping, CostReport};
+use crate::execution_trace::{self, ExecutionTrace, TraceComparison};
+use crate::timeline::{self, TimelinePage};
+use crate::share::{CreatedShare, ShareLink};
+use crate::dag::DAG;
+use crate::verdict;
 
 #[derive(serde::Deserialize)]
 pub struct RunQuery {
     run_id: Option<String>,
+    #[serde(default)]
+    summary: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    20
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResumeRequest {
+    #[serde(default)]
+    target_agents: Option<Vec<String>>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct HaltRequest {
+    #[serde(default = "default_block_new_runs")]
+    block_new_runs: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+fn default_block_new_runs() -> bool {
+    true
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResultQuery {
+    #[serde(default)]
+    partial: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TimelineQuery {
+    #[serde(default)]
+    after_seq: u64,
+    limit: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AgentLogQuery {
+    #[serde(default)]
+    follow: bool,
+    #[serde(default)]
+    after_seq: u64,
+    limit: Option<usize>,
+    level: Option<String>,
+}
+
+/// Same fields as the agent-service's Redis log messages
+#[derive(serde::Deserialize)]
+pub struct PostAgentLogRequest {
+    message: String,
+    /// Log level
+    #[serde(default = "default_log_field")]
+    metadata: String,
+    #[serde(default = "default_log_field")]
+    category: String,
+}
+
+fn default_log_field() -> String {
+    "INFO".to_string()
+}
+
+#[derive(serde::Deserialize)]
+pub struct AgentLogTailQuery {
+    /// Buffered logs replayed on connect
+    #[serde(default = "default_tail_replay")]
+    replay: usize,
+}
+
+fn default_tail_replay() -> usize {
+    50
+}
+
+#[derive(serde::Deserialize)]
+pub struct StrictQuery {
+    /// Reject unknown WorkflowConfig fields (defaults to RARO_STRICT_CONFIG)
+    strict: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateRunRequest {
+    /// A WorkflowConfig, parsed after the strict check
+    workflow: serde_json::Value,
+    /// Cancel the run unless launched within this many seconds (default RARO_PREPARE_TIMEOUT_SECS)
+    prepare_timeout_secs: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct VerdictDryRunRequest {
+    /// A WorkflowConfig with success_criteria, parsed after the strict check
+    workflow: serde_json::Value,
+    /// Sample output per agent id, shaped like stored agent outputs
+    #[serde(default)]
+    outputs: serde_json::Map<String, serde_json::Value>,
+    /// Sample session output file names
+    #[serde(default)]
+    output_files: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AttachFilesRequest {
+    /// Library file names (the client's own, then public)
+    files: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RegisterCacheRequest {
+    cached_content_id: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct StateAtQuery {
+    seq: Option<u64>,
+    /// RFC 3339
+    time: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct CostQuery {
+    /// "client" (default) or "tag:<key>"
+    group_by: Option<String>,
+    /// YYYY-MM, defaults to the current month
+    period: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RunListQuery {
+    /// Only runs whose metadata has this key, e.g. safety_flags set by a SetRunMetadata pattern
+    tag_key: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TrendQuery {
+    workflow_id: String,
+    /// Defaults to DEFAULT_TREND_WINDOW_HOURS
+    window_hours: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReplayQuery {
+    #[serde(default)]
+    execute: bool,
+    #[serde(default)]
+    commit: bool,
 }
 
 #[derive(serde::Serialize)]
@@ -41,59 +205,135 @@ pub async fn health() -> Json<HealthResponse> {
     })
 }
 
-// GET /runtime/:run_id/files/:filename
-pub async fn serve_session_file(
+// GET /capabilities
+// Unauthenticated feature and limit discovery for SDKs; contains no secrets
+pub async fn get_capabilities(State(runtime): State<Arc<RARORuntime>>) -> Json<Capabilities> {
+    Json(Capabilities::from_runtime(&runtime))
+}
+
+// GET /runtime/:run_id/outputs
+pub async fn list_session_outputs(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let files = runtime.list_session_outputs(&run_id, &client_id).await?;
+    Ok(Json(json!({ "run_id": run_id, "files": files })))
+}
+
+// GET /runtime/:run_id/output/:filename (also served at the older /runtime/:run_id/files/:filename)
+// Raw bytes under the guessed Content-Type, streamed. `Accept: text/plain` serves a text file
+// (.json, .csv, ...) as plain UTF-8 instead, for clients that would otherwise parse it; the
+// file's first TEXT_SNIFF_BYTES decide whether it is text. Cacheable by the owner's browser
+// only: the response is owner-gated, so shared caches must not keep it.
+pub async fn read_session_output(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
     Path((run_id, filename)): Path<(String, String)>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // 1. Sanitize (Basic security)
-    if filename.contains("..") || filename.starts_with("/") {
-        return Err(StatusCode::FORBIDDEN);
-    }
+    headers: axum::http::HeaderMap,
+) -> Result<Response, RuntimeError> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let path = runtime.session_output_path(&run_id, &client_id, &filename).await?;
+    let mut file = tokio::fs::File::open(&path).await?;
+    let wants_text = headers.get(axum::http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.split(',').any(|t| t.split(';').next().unwrap_or("").trim() == "text/plain"));
+    let is_text = if wants_text {
+        let mut prefix = Vec::with_capacity(TEXT_SNIFF_BYTES);
+        (&mut file).take(TEXT_SNIFF_BYTES as u64).read_to_end(&mut prefix).await?;
+        file.rewind().await?;
+        // A character cut off by the sniff window still counts as text
+        std::str::from_utf8(&prefix).map_or_else(|e| e.error_len().is_none(), |_| true)
+    } else {
+        false
+    };
+    let content_type = if is_text {
+        "text/plain; charset=utf-8".to_string()
+    } else {
+        fs_manager::guess_content_type(&filename)
+    };
+    let headers = [
+        (axum::http::header::CONTENT_TYPE, content_type),
+        (axum::http::header::CACHE_CONTROL, "private, max-age=3600".to_string()),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(file))).into_response())
+}
 
-    // 2. Construct Path (Targeting the RFS Output directory)
-    let file_path = format!("/app/storage/sessions/{}/output/{}", run_id, filename);
-    let path = std::path::Path::new(&file_path);
+/// How much of a session output read_session_output inspects to decide it is text
+const TEXT_SNIFF_BYTES: usize = 8192;
 
-    // 3. Verify Existence
-    if !path.exists() {
-        return Err(StatusCode::NOT_FOUND);
-    }
+// POST /runtime/:run_id/artifacts/promote
+// All-or-nothing promotion of several session outputs; reports each file's digest
+pub async fn promote_artifacts_batch(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+    Json(request): Json<PromoteArtifactsRequest>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let promoted = runtime.promote_artifacts(&run_id, &client_id, request).await?;
+    Ok(Json(json!({ "run_id": run_id, "promoted": promoted })))
+}
 
-    // 4. Open and Stream
-    let file = match tokio::fs::File::open(path).await {
-        Ok(file) => file,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
+// POST /runtime/:run_id/share
+pub async fn create_share(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+    Json(request): Json<ShareRequest>,
+) -> Result<Json<CreatedShare>, RuntimeError> {
+    Ok(Json(runtime.create_share(&run_id, &client_id, request).await?))
+}
 
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+// GET /runtime/:run_id/shares
+pub async fn list_shares(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+) -> Result<Json<Vec<ShareLink>>, RuntimeError> {
+    Ok(Json(runtime.list_shares(&run_id, &client_id)?))
+}
+
+// DELETE /runtime/:run_id/share/:share_id
+pub async fn revoke_share(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path((run_id, share_id)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    runtime.revoke_share(&run_id, &client_id, &share_id)?;
+    Ok(Json(json!({ "success": true, "share_id": share_id })))
+}
 
-    // 5. Determine Content Type (Simple guess)
-    let content_type = if filename.ends_with(".png") { "image/png" }
-    else if filename.ends_with(".jpg") { "image/jpeg" }
-    else if filename.ends_with(".csv") { "text/csv" }
-    else if filename.ends_with(".txt") { "text/plain" }
-    else { "application/octet-stream" };
+// GET /shared/:token
+// No client session: the token is the credential
+pub async fn get_shared_run(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(token): Path<String>,
+) -> Result<Json<SharedRunView>, RuntimeError> {
+    Ok(Json(runtime.shared_run(&token).await?))
+}
 
+// GET /shared/:token/files/:filename
+pub async fn serve_shared_file(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((token, filename)): Path<(String, String)>,
+) -> Result<impl IntoResponse, RuntimeError> {
+    let path = runtime.shared_artifact_path(&token, &filename)?;
+    let file = tokio::fs::File::open(&path).await?;
     let headers = [
-        ("Content-Type", content_type),
-        ("Cache-Control", "public, max-age=3600"),
+        ("Content-Type", fs_manager::guess_content_type(&filename)),
+        // Revocation must take effect, so shared downloads are not cached
+        ("Cache-Control", "no-store".to_string()),
     ];
-
-    Ok((headers, body))
+    Ok((headers, Body::from_stream(ReaderStream::new(file))))
 }
 
 // === NEW HANDLER: LIST LIBRARY FILES ===
 // GET /runtime/library
 pub async fn list_library_files(
     ClientSession(client_id): ClientSession // <--- Auto-extracted
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let files = WorkspaceInitializer::list_scoped_files(&client_id)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to list files for client {}: {}", client_id, e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let files = WorkspaceInitializer::list_scoped_files(&client_id).await?;
 
     Ok(Json(serde_json::json!({ "files": files })))
 }
@@ -103,131 +343,843 @@ pub async fn list_library_files(
 pub async fn upload_library_file(
     ClientSession(client_id): ClientSession, // <--- Auto-extracted
     mut multipart: Multipart
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+) -> Result<Json<serde_json::Value>, Response> {
+    while let Some(field) = multipart.next_field().await.map_err(IntoResponse::into_response)? {
         let name = field.file_name().unwrap_or("unknown").to_string();
-        let data = field.bytes().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let data = field.bytes().await.map_err(IntoResponse::into_response)?;
 
         // Pass client_id to save function
         WorkspaceInitializer::save_to_library(&client_id, &name, &data)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            .map_err(RuntimeError::from)?;
+    }
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+// DELETE /runtime/library/files/:filename
+// Soft delete: the file moves to the client's trash and can be restored until the janitor purges it
+pub async fn delete_library_file(
+    ClientSession(client_id): ClientSession,
+    Path(filename): Path<String>,
+) -> Result<Json<Tombstone>, RuntimeError> {
+    Ok(Json(WorkspaceInitializer::trash_library_file(&WorkspaceInitializer::client_library_dir(&client_id), &filename, &client_id)?))
+}
+
+// GET /runtime/library/trash
+pub async fn list_library_trash(
+    ClientSession(client_id): ClientSession,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let trash = WorkspaceInitializer::list_trash(&WorkspaceInitializer::client_library_dir(&client_id))?;
+    Ok(Json(serde_json::json!({ "files": trash })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct RestoreQuery {
+    #[serde(default)]
+    overwrite: bool,
+}
+
+// POST /runtime/library/trash/:filename/restore?overwrite=true
+// :filename is a tombstone's trash_id, or a filename for its newest trashed copy.
+// 409 if the name was re-uploaded since deletion and overwrite is not set
+pub async fn restore_library_file(
+    ClientSession(client_id): ClientSession,
+    Path(filename): Path<String>,
+    Query(query): Query<RestoreQuery>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let restored = WorkspaceInitializer::restore_library_file(&WorkspaceInitializer::client_library_dir(&client_id), &filename, query.overwrite)?;
+    Ok(Json(serde_json::json!({ "success": true, "filename": restored.filename })))
+}
+
+// POST /runtime/start[?strict=true|false]
+pub async fn start_workflow(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession, // <--- Capture who is starting the run
+    Query(query): Query<StrictQuery>,
+    Json(raw): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let config = parse_workflow(&runtime, &query, raw)?;
+    // Halted clients, size caps and validation problems all come back as machine-readable errors
+    let run_id = runtime.start_workflow(config, &client_id)?;
+    Ok(Json(json!({ "success": true, "run_id": run_id })))
+}
+
+// POST /runtime/runs[?strict=true|false]
+// Two-phase start, phase one: the run is created in Preparing so files and caches can be
+// attached against its id before anything is dispatched. Launch it with POST /runtime/:run_id/launch.
+pub async fn create_run(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Query(query): Query<StrictQuery>,
+    Json(req): Json<CreateRunRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), RuntimeError> {
+    let workflow = parse_workflow(&runtime, &query, req.workflow)?;
+    let timeout = req.prepare_timeout_secs.filter(|s| *s > 0)
+        .map(std::time::Duration::from_secs)
+        .unwrap_or_else(crate::runtime::prepare_timeout_from_env);
+    let run_id = runtime.create_run(workflow, &client_id, timeout).await?;
+    let state = runtime.get_state(&run_id).ok_or_else(|| RuntimeError::RunNotFound(run_id.clone()))?;
+    Ok((StatusCode::CREATED, Json(json!({ "run_id": run_id, "status": state.status, "prepare_deadline": state.prepare_deadline }))))
+}
+
+// POST /runtime/:run_id/attachments
+// Copies library files into a Preparing run's inputs
+pub async fn attach_run_files(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+    Json(req): Json<AttachFilesRequest>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let attached = runtime.attach_run_files(&run_id, &client_id, &req.files).await?;
+    Ok(Json(json!({ "run_id": run_id, "attached": attached })))
+}
+
+// PUT /runtime/:run_id/cache
+// Registers the cached content a Preparing run's invocations will use
+pub async fn register_run_cache(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+    Json(req): Json<RegisterCacheRequest>,
+) -> Result<StatusCode, RuntimeError> {
+    runtime.register_run_cache(&run_id, &client_id, req.cached_content_id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /runtime/:run_id/launch
+// Two-phase start, phase two: Preparing -> Running
+pub async fn launch_run(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    runtime.launch_run(&run_id, &client_id).await?;
+    Ok(Json(json!({ "success": true, "run_id": run_id })))
+}
+
+// PATCH /workflows/:workflow_id
+// RFC 6902 JSON Patch against a stored workflow; validated like a start before it is stored
+pub async fn patch_workflow(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(workflow_id): Path<String>,
+    Json(ops): Json<Vec<crate::json_patch::PatchOp>>,
+) -> Result<Json<WorkflowConfig>, RuntimeError> {
+    Ok(Json(runtime.patch_workflow(&workflow_id, &client_id, &ops).await?))
+}
+
+// GET /workflows/:workflow_id/stats
+// Moving averages of latency and tokens per agent id, over every recorded run of the workflow
+pub async fn get_workflow_stats(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path(workflow_id): Path<String>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let agents = runtime.workflow_stats(&workflow_id, &client_id)?;
+    Ok(Json(json!({
+        "workflow_id": workflow_id,
+        "ema_alpha": crate::agent_stats::EMA_ALPHA,
+        "agents": agents
+    })))
+}
+
+/// Fields of a raw config that strict mode rejects (none when the request isn't strict)
+fn unknown_config_fields(runtime: &RARORuntime, query: &StrictQuery, raw: &serde_json::Value) -> Vec<ValidationError> {
+    if !query.strict.unwrap_or(runtime.strict_config) {
+        return Vec::new();
+    }
+    raro_models::strict::unknown_fields(raw).into_iter().map(ValidationError::UnknownField).collect()
+}
+
+/// Parse a WorkflowConfig body. In strict mode unknown fields fail validation, each named,
+/// instead of being silently dropped.
+fn parse_workflow(runtime: &RARORuntime, query: &StrictQuery, raw: serde_json::Value) -> Result<WorkflowConfig, RuntimeError> {
+    let unknown = unknown_config_fields(runtime, query, &raw);
+    if !unknown.is_empty() {
+        return Err(RuntimeError::InvalidWorkflow(unknown));
+    }
+    serde_json::from_value(raw).map_err(|e| RuntimeError::MalformedWorkflow(e.to_string()))
+}
+
+// POST /runtime/validate[?strict=true|false]
+// Dry-run validation: structure plus budget feasibility from per-agent token estimates
+pub async fn validate_workflow(
+    State(runtime): State<Arc<RARORuntime>>,
+    Query(query): Query<StrictQuery>,
+    Json(raw): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    // Unknown fields are reported alongside the other problems rather than on their own
+    let unknown = unknown_config_fields(&runtime, &query, &raw);
+    let mut config = parse_workflow(&runtime, &StrictQuery { strict: Some(false) }, raw)?;
+    config.apply_agent_defaults();
+    if let Err(mut errors) = config.check_limits(&runtime.workflow_limits) {
+        errors.splice(0..0, unknown);
+        return Ok(Json(json!({ "valid": false, "errors": errors })));
+    }
+    let mut errors = unknown;
+    errors.extend(config.validate().err().unwrap_or_default());
+    errors.extend([config.validate_environment(), config.validate_metadata(), config.validate_promotion_rules()].into_iter().filter_map(Result::err));
+    errors.extend(success_condition_errors(&config));
+    errors.extend(config.check_feasibility().err().unwrap_or_default());
+
+    // Declared estimates first, then what past runs of this workflow actually used
+    let estimates: HashMap<String, usize> = config.agents_in_scope()
+        .into_iter()
+        .map(|a| {
+            let estimate = a.estimated_tokens
+                .or_else(|| runtime.agent_stats.tokens_estimate(&config.id, &a.id))
+                .unwrap_or_else(|| a.token_estimate());
+            (a.id.clone(), estimate)
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "valid": errors.is_empty(),
+        "errors": errors,
+        "estimated_tokens": estimates.values().sum::<usize>(),
+        "agent_estimates": estimates
+    })))
+}
+
+fn success_condition_errors(config: &WorkflowConfig) -> Vec<ValidationError> {
+    config.success_criteria.as_ref()
+        .map(|criteria| verdict::condition_problems(criteria).into_iter().map(ValidationError::InvalidSuccessCriteria).collect())
+        .unwrap_or_default()
+}
+
+// POST /runtime/validate/verdict[?strict=true|false]
+// Success criteria dry run: the verdict a run of `workflow` would get with the sample outputs
+pub async fn dry_run_verdict(
+    State(runtime): State<Arc<RARORuntime>>,
+    Query(query): Query<StrictQuery>,
+    Json(request): Json<VerdictDryRunRequest>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let mut config = parse_workflow(&runtime, &query, request.workflow)?;
+    config.apply_agent_defaults();
+    let mut errors = config.validate().err().unwrap_or_default();
+    errors.extend(success_condition_errors(&config));
+    if !errors.is_empty() {
+        return Err(RuntimeError::InvalidWorkflow(errors));
+    }
+    let criteria = config.success_criteria.as_ref()
+        .ok_or_else(|| RuntimeError::InvalidConfig("workflow has no success_criteria".to_string()))?;
+
+    let dag = DAG::from_config(&config).map_err(|e| RuntimeError::InvalidWorkflow(vec![e.into_validation_error(&config)]))?;
+    let terminal_agents = verdict::terminal_agents(criteria, &dag);
+    let verdict = verdict::evaluate(criteria, &request.outputs, &terminal_agents, &request.output_files);
+    Ok(Json(json!({ "terminal_agents": terminal_agents, "verdict": verdict })))
+}
+
+pub async fn resume_run(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    body: Option<Json<ResumeRequest>>,
+) -> StatusCode {
+    match runtime.resume_run(&run_id, body.and_then(|Json(r)| r.target_agents)).await {
+        Ok(()) => StatusCode::OK,
+        // Kept from before the resume logic moved into the runtime
+        Err(RuntimeError::NotAwaitingApproval(_)) => StatusCode::BAD_REQUEST,
+        Err(e) => {
+            tracing::warn!("Resume of {} rejected: {}", run_id, e);
+            e.status_code()
+        }
+    }
+}
+
+// POST /runtime/:run_id/feedback
+// Reviewer decision on a paused run: approve resumes it, reject fails it with the comment as reason
+pub async fn submit_feedback(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    Json(feedback): Json<FeedbackPayload>,
+) -> Result<Json<ApprovalRecord>, Response> {
+    Ok(Json(runtime.record_feedback(&run_id, &feedback).await?))
+}
+
+pub async fn stop_run(
+    State(runtime): State<Arc<RARORuntime>>, 
+    Path(run_id): Path<String>
+) -> StatusCode {
+    runtime.fail_run(&run_id, "OPERATOR", FailureCode::OperatorStop, "Manual Stop").await;
+    StatusCode::OK
+}
+
+// GET /runtime/:run_id/state/at?seq=N | ?time=<RFC 3339>
+// The run state as it was after event N (or at that time), rebuilt from the event log
+pub async fn get_state_at(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    Query(query): Query<StateAtQuery>,
+) -> Result<Json<RuntimeState>, RuntimeError> {
+    let at = match &query.time {
+        Some(time) => Some(chrono::DateTime::parse_from_rfc3339(time)
+            .map_err(|e| RuntimeError::InvalidConfig(format!("time {:?} is not an RFC 3339 timestamp: {}", time, e)))?
+            .with_timezone(&chrono::Utc)),
+        None => None,
+    };
+    Ok(Json(runtime.state_at(&run_id, query.seq, at)?))
+}
+
+// GET /runtime/:run_id/checkpoint
+pub async fn get_checkpoint(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<RunCheckpoint>, RuntimeError> {
+    Ok(Json(runtime.save_checkpoint(&run_id).await?))
+}
+
+// POST /runtime/:run_id/checkpoint
+pub async fn apply_checkpoint(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    Json(checkpoint): Json<RunCheckpoint>,
+) -> Result<StatusCode, RuntimeError> {
+    // Same constraint as resume: the execution loop needs the DAG in memory
+    if !runtime.has_dag(&run_id) {
+        return Err(RuntimeError::RunNotFound(run_id));
     }
 
-    Ok(Json(serde_json::json!({ "success": true })))
+    runtime.apply_checkpoint(&run_id, checkpoint).await?;
+
+    let rt_clone = runtime.clone();
+    let rid_clone = run_id.clone();
+    tokio::spawn(async move {
+        rt_clone.launch_execution(rid_clone).await;
+    });
+
+    runtime.emit_event(crate::events::RuntimeEvent::new(
+        &run_id,
+        crate::events::EventType::SystemIntervention,
+        None,
+        serde_json::json!({ "action": "resume", "reason": "Restored from checkpoint" })
+    ));
+
+    Ok(StatusCode::OK)
+}
+
+// POST /runtime/:run_id/invocations/batch
+// Records a completed wave in one state mutation; reports a mid-batch budget breach
+pub async fn record_invocations_batch(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    Json(invocations): Json<Vec<AgentInvocation>>,
+) -> Result<Json<BatchRecordResult>, RuntimeError> {
+    let result = runtime.record_invocations(&run_id, invocations).await?;
+
+    // Push-mode runs advance when the orchestrator reports back
+    let rt_clone = runtime.clone();
+    tokio::spawn(async move {
+        rt_clone.push_ready_agents(&run_id).await;
+    });
+
+    Ok(Json(result))
+}
+
+// DELETE /runtime/:run_id/cache
+// Stop attaching the run's cached content (e.g. it expired upstream)
+pub async fn detach_run_cache(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+) -> StatusCode {
+    match runtime.detach_cache_resource(&run_id, "Detached by operator") {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+// GET /runtime/:run_id/agent/:agent_id/routing
+// Explains signature source, cache attachment and model resolution for one agent
+pub async fn get_agent_routing(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, agent_id)): Path<(String, String)>,
+) -> Result<Json<RoutingDecision>, RuntimeError> {
+    Ok(Json(runtime.explain_routing(&run_id, &agent_id)?))
+}
+
+// POST /runtime/:run_id/invocations/:invocation_id/replay?execute=true&commit=false
+pub async fn replay_invocation(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, invocation_id)): Path<(String, String)>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Json<ReplayResult>, RuntimeError> {
+    Ok(Json(runtime.replay_invocation(&run_id, &invocation_id, query.execute, query.commit).await?))
+}
+
+// GET /runtime/:run_id/export
+pub async fn export_run(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+) -> Result<impl IntoResponse, RuntimeError> {
+    let bundle = runtime.export_run(&run_id).await?;
+
+    let body = serde_json::to_string_pretty(&bundle).map_err(|e| RuntimeError::Storage(e.into()))?;
+    let disposition = format!("attachment; filename=\"{}-export.json\"", run_id);
+
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/json".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+// POST /runtime/import
+pub async fn import_run(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Json(bundle): Json<RunExport>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let run_id = runtime.import_run(bundle, &client_id).await?;
+    Ok(Json(json!({ "success": true, "run_id": run_id })))
+}
+
+// GET /runtime/search?q=...&limit=20
+pub async fn search_runs(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Query(query): Query<SearchQuery>,
+) -> Json<serde_json::Value> {
+    let hits = runtime.search_index.search(&client_id, &query.q, query.limit.min(100));
+
+    // Attach live status so the UI can render summaries without a second round-trip
+    let results: Vec<serde_json::Value> = hits.into_iter().map(|hit| {
+        let state = runtime.get_state(&hit.run_id);
+        json!({
+            "run_id": hit.run_id,
+            "workflow_id": hit.workflow_id,
+            "workflow_name": hit.workflow_name,
+            "score": hit.score,
+            "status": state.as_ref().map(|s| s.status.clone()),
+            "start_time": state.as_ref().map(|s| s.start_time.clone()),
+            "matches": hit.matches,
+        })
+    }).collect();
+
+    Json(json!({ "query": query.q, "results": results }))
+}
+
+
+pub async fn get_runtime_state(
+    State(runtime): State<Arc<RARORuntime>>,
+    Query(query): Query<RunQuery>,
+) -> Result<Json<RuntimeState>, StatusCode> {
+    let run_id = query.run_id.ok_or(StatusCode::BAD_REQUEST)?;
+
+    runtime
+        .get_state(&run_id)
+        .ok_or(StatusCode::NOT_FOUND)
+        .map(Json)
+}
+
+// GET /runtime/runs?tag_key=safety_flags
+pub async fn list_runs(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Query(query): Query<RunListQuery>,
+) -> Json<Vec<RunSummary>> {
+    Json(runtime.list_runs(&client_id, query.tag_key.as_deref()))
+}
+
+// GET /me/usage
+// Current-month runs, tokens and storage for the calling client, with configured limits
+pub async fn get_my_usage(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+) -> Json<UsageReport> {
+    Json(runtime.usage_report(&client_id))
+}
+
+pub async fn invoke_agent(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, agent_id)): Path<(String, String)>,
+) -> Result<Json<InvocationPayload>, RuntimeError> {
+    tracing::info!("Preparing invocation for agent: {} in run: {}", agent_id, run_id);
+
+    // The caller dispatches the agent itself, so a second prepare before it records is a duplicate
+    runtime
+        .prepare_exclusive_invocation(&run_id, &agent_id)
+        .instrument(RARORuntime::prepare_span(&run_id, &agent_id))
+        .await
+        .map(Json)
+}
+
+// GET /runtime/signatures?run_id=...&summary=true
+// Summary mode returns lengths and hashes instead of the (potentially huge) content
+pub async fn get_signatures(
+    State(runtime): State<Arc<RARORuntime>>,
+    Query(query): Query<RunQuery>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let run_id = query.run_id.ok_or(StatusCode::BAD_REQUEST)?;
+
+    let summaries = runtime
+        .get_signature_summaries(&run_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let metrics = json!({
+        "count": summaries.len(),
+        "raw_bytes": summaries.values().map(|s| s.raw_bytes).sum::<usize>(),
+        "stored_bytes": summaries.values().map(|s| s.stored_bytes).sum::<usize>(),
+    });
+
+    if query.summary {
+        return Ok(Json(json!({
+            "run_id": run_id,
+            "signatures": summaries,
+            "metrics": metrics
+        })));
+    }
+
+    let signatures = runtime
+        .get_all_signatures(&run_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(json!({
+        "run_id": run_id,
+        "signatures": signatures.signatures,
+        "dropped": signatures.dropped,
+        "metrics": metrics
+    })))
+}
+
+// GET /runtime/:run_id/agent/:agent_id/logs?follow=true&after_seq=N&limit=&level=
+// NDJSON of one agent's IntermediateLog and trace entries. With follow=true the response stays
+// open (chunked) until the agent finishes or the client disconnects; limit applies otherwise.
+pub async fn get_agent_logs(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, agent_id)): Path<(String, String)>,
+    Query(query): Query<AgentLogQuery>,
+) -> Result<impl IntoResponse, RuntimeError> {
+    let headers = [("Content-Type", "application/x-ndjson")];
+
+    // Subscribe before reading the buffer so nothing falls between backlog and live entries
+    let mut live = runtime.log_bus.subscribe();
+    let mut entries: Vec<AgentLogEntry> = runtime
+        .get_agent_logs(&run_id, &agent_id, query.after_seq)?
+        .into_iter()
+        .filter(|e| log_level_matches(e, &query.level))
+        .collect();
+
+    if !query.follow {
+        if let Some(limit) = query.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        let body: String = entries.iter().map(ndjson_line).collect();
+        return Ok((headers, Body::from(body)));
+    }
+
+    let (mut tx, rx) = futures::channel::mpsc::channel::<Result<String, std::convert::Infallible>>(64);
+    tokio::spawn(async move {
+        let mut last_seq = query.after_seq;
+        let mut ticker = tokio::time::interval(std::time::Duration::from_millis(500));
+
+        'stream: loop {
+            for entry in entries.drain(..) {
+                last_seq = last_seq.max(entry.seq);
+                if tx.send(Ok(ndjson_line(&entry))).await.is_err() {
+                    break 'stream; // Client disconnected
+                }
+            }
+
+            tokio::select! {
+                received = live.recv() => match received {
+                    Ok(entry) => {
+                        if entry.run_id == run_id && entry.agent_id == agent_id && entry.seq > last_seq {
+                            last_seq = entry.seq;
+                            if log_level_matches(&entry, &query.level) {
+                                entries.push(entry);
+                            }
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                        // Catch up from the buffer
+                        entries = runtime.get_agent_logs(&run_id, &agent_id, last_seq).unwrap_or_default()
+                            .into_iter()
+                            .filter(|e| log_level_matches(e, &query.level))
+                            .collect();
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => {
+                    if tx.is_closed() {
+                        break;
+                    }
+                    if runtime.is_agent_finished(&run_id, &agent_id) {
+                        // Flush whatever arrived since the last entry, then end the stream
+                        for entry in runtime.get_agent_logs(&run_id, &agent_id, last_seq).unwrap_or_default() {
+                            if log_level_matches(&entry, &query.level) && tx.send(Ok(ndjson_line(&entry))).await.is_err() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    Ok((headers, Body::from_stream(rx)))
+}
+
+// POST /runtime/:run_id/agent/:agent_id/logs
+// HTTP counterpart of the Redis log channel. Past the run's event limits the entry is dropped
+// (and counted) and the response is 429 with Retry-After.
+pub async fn post_agent_log(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, agent_id)): Path<(String, String)>,
+    Json(req): Json<PostAgentLogRequest>,
+) -> Result<StatusCode, RuntimeError> {
+    runtime.ensure_agent_exists(&run_id, &agent_id)?;
+    runtime.try_emit_event(crate::events::RuntimeEvent::new(&run_id, crate::events::EventType::IntermediateLog, Some(agent_id),
+        json!({ "message": req.message, "metadata": req.metadata, "category": req.category })))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+// GET /runtime/:run_id/memory/:key
+// Run working memory: scratch JSON shared by the run's agents (owner only)
+pub async fn get_memory(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path((run_id, key)): Path<(String, String)>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    Ok(Json(runtime.get_memory(&run_id, &client_id, &key)?))
+}
+
+// PUT /runtime/:run_id/memory/:key
+// Body is the value itself. 201 for a new key, 200 for an overwrite, 413 past the run's limits.
+pub async fn put_memory(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path((run_id, key)): Path<(String, String)>,
+    Json(value): Json<serde_json::Value>,
+) -> Result<StatusCode, RuntimeError> {
+    let created = runtime.put_memory(&run_id, &client_id, &key, value)?;
+    Ok(if created { StatusCode::CREATED } else { StatusCode::OK })
+}
+
+// DELETE /runtime/:run_id/memory/:key
+pub async fn delete_memory(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path((run_id, key)): Path<(String, String)>,
+) -> Result<StatusCode, RuntimeError> {
+    runtime.delete_memory(&run_id, &client_id, &key)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// GET /runtime/:run_id/agent/:agent_id/logs/tail?replay=N
+// SSE feed of one agent's IntermediateLog events: the last N buffered ones (default 50), then
+// new ones as they arrive. Ends with an "end" event once the agent completes or fails.
+pub async fn tail_agent_logs(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, agent_id)): Path<(String, String)>,
+    Query(query): Query<AgentLogTailQuery>,
+) -> Result<impl IntoResponse, RuntimeError> {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use crate::events::{EventType, RuntimeEvent};
+
+    // Subscribe first: the stream starts right after the logged events the replay covers
+    let mut stream = runtime.subscribe_run(&run_id);
+    let replay = runtime.recent_intermediate_logs(&run_id, &agent_id, stream.last_seq(), query.replay)?;
+
+    let log_event = |event: &RuntimeEvent| Event::default()
+        .event("log")
+        .id(event.seq.to_string())
+        .json_data(event)
+        .unwrap_or_default();
+
+    let (mut tx, rx) = futures::channel::mpsc::channel::<Result<Event, std::convert::Infallible>>(64);
+    tokio::spawn(async move {
+        for event in &replay {
+            if tx.send(Ok(log_event(event))).await.is_err() {
+                return; // Client disconnected
+            }
+        }
+
+        let mut ticker = tokio::time::interval(std::time::Duration::from_millis(500));
+        loop {
+            tokio::select! {
+                next = stream.next(&runtime) => match next {
+                    Some(event) if event.agent_id.as_deref() == Some(agent_id.as_str()) => match event.event_type {
+                        EventType::IntermediateLog => {
+                            // Sent here rather than in a match guard: guards should have no side effects
+                            let delivered = tx.send(Ok(log_event(&event))).await;
+                            if delivered.is_err() {
+                                return;
+                            }
+                        }
+                        EventType::AgentCompleted | EventType::AgentFailed => break,
+                        _ => {}
+                    },
+                    Some(_) => {}
+                    None => return, // Bus closed: the kernel is shutting down
+                },
+                _ = ticker.tick() => {
+                    if tx.is_closed() {
+                        return;
+                    }
+                    // Covers the run ending without an event for this agent
+                    if runtime.is_agent_finished(&run_id, &agent_id) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let outcome = match runtime.get_state(&run_id) {
+            Some(state) if state.completed_agents.contains(&agent_id) => "completed",
+            Some(state) if state.has_failed(&agent_id) => "failed",
+            _ => "run_ended",
+        };
+        let end = Event::default().event("end").json_data(json!({ "agent_id": agent_id, "outcome": outcome })).unwrap_or_default();
+        let _ = tx.send(Ok(end)).await;
+    });
+
+    Ok(Sse::new(rx).keep_alive(KeepAlive::default()))
 }
 
-// POST /runtime/start
-pub async fn start_workflow(
+fn log_level_matches(entry: &AgentLogEntry, level: &Option<String>) -> bool {
+    level.as_ref().map(|l| entry.level.eq_ignore_ascii_case(l)).unwrap_or(true)
+}
+
+fn ndjson_line(entry: &AgentLogEntry) -> String {
+    format!("{}\n", serde_json::to_string(entry).unwrap_or_default())
+}
+
+// GET /runtime/:run_id/signatures/history
+// Propagation timeline of the signature store (seq, agent, time); bodies are omitted
+pub async fn get_signature_history(
     State(runtime): State<Arc<RARORuntime>>,
-    ClientSession(client_id): ClientSession, // <--- Capture who is starting the run
-    Json(config): Json<WorkflowConfig>,
+    Path(run_id): Path<String>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    // Pass client_id to runtime
-    match runtime.start_workflow(config, &client_id) {
-        Ok(run_id) => Ok(Json(json!({ "success": true, "run_id": run_id }))),
-        Err(e) => {
-            tracing::error!("Failed to start workflow: {}", e);
-            Err(StatusCode::BAD_REQUEST)
-        }
-    }
+    let history = runtime
+        .get_signature_history(&run_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(json!({
+        "run_id": run_id,
+        "history": history
+    })))
 }
 
-pub async fn resume_run(
+// GET /metrics/models
+// Invocation count, tokens, estimated cost and average latency per model variant
+pub async fn get_model_metrics(
     State(runtime): State<Arc<RARORuntime>>,
-    Path(run_id): Path<String>
-) -> StatusCode {
-    // 0. Fail fast if structural integrity is lost (DAG missing from memory)
-    if !runtime.has_dag(&run_id) {
-        tracing::error!("Cannot resume run {}: DAG structure missing from memory.", run_id);
-        return StatusCode::NOT_FOUND;
-    }
-
-    // 1. Verify currently paused
-    let is_paused = runtime.get_state(&run_id)
-        .map(|s| s.status == RuntimeStatus::AwaitingApproval)
-        .unwrap_or(false);
+) -> Json<HashMap<ModelVariant, ModelUsage>> {
+    Json(runtime.model_usage())
+}
 
-    if !is_paused {
-        tracing::warn!("Resume called on non-paused run: {}", run_id);
-        return StatusCode::BAD_REQUEST;
+// GET /metrics/trend?workflow_id=my-wf&window_hours=24
+// Whether latency, cache hits, cost and errors are improving across the caller's recent runs
+pub async fn get_metrics_trend(
+    ClientSession(client_id): ClientSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Query(query): Query<TrendQuery>,
+) -> Result<Json<MetricsTrend>, RuntimeError> {
+    let hours = query.window_hours.unwrap_or(DEFAULT_TREND_WINDOW_HOURS);
+    if hours == 0 || hours > MAX_TREND_WINDOW_HOURS {
+        return Err(RuntimeError::InvalidConfig(format!("window_hours must be 1-{}", MAX_TREND_WINDOW_HOURS)));
     }
-
-    // 2. Flip to Running
-    runtime.set_run_status(&run_id, RuntimeStatus::Running);
-
-    // 3. RESPAWN THE EXECUTION LOOP
-    // This is the critical piece. We fire the engine again.
-    let rt_clone = runtime.clone();
-    let rid_clone = run_id.clone();
-    tokio::spawn(async move {
-        rt_clone.execute_dynamic_dag(rid_clone).await;
-    });
-
-    // 4. Emit event for UI to update logs
-    runtime.emit_event(crate::events::RuntimeEvent::new(
-        &run_id,
-        crate::events::EventType::SystemIntervention,
-        None,
-        serde_json::json!({ "action": "resume", "reason": "User approved execution" })
-    ));
-
-    tracing::info!("Run {} resumed by user", run_id);
-    StatusCode::OK
+    Ok(Json(runtime.metrics_trend(&client_id, &query.workflow_id, chrono::Duration::hours(hours as i64))))
 }
 
-pub async fn stop_run(
-    State(runtime): State<Arc<RARORuntime>>, 
-    Path(run_id): Path<String>
-) -> StatusCode {
-    runtime.fail_run(&run_id, "OPERATOR", "Manual Stop").await;
-    StatusCode::OK
+// GET /metrics/costs?group_by=tag:project&period=2024-06
+// Estimated spend per client or per run tag value for one month (admin only: spans clients)
+pub async fn get_cost_metrics(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Query(query): Query<CostQuery>,
+) -> Result<Json<CostReport>, RuntimeError> {
+    let grouping: CostGrouping = query.group_by.as_deref().unwrap_or("client").parse().map_err(RuntimeError::InvalidConfig)?;
+    if let Some(period) = &query.period {
+        chrono::NaiveDate::parse_from_str(&format!("{}-01", period), "%Y-%m-%d")
+            .map_err(|_| RuntimeError::InvalidConfig(format!("Invalid period '{}' (expected YYYY-MM)", period)))?;
+    }
+    Ok(Json(runtime.costs.report(&grouping, query.period.as_deref())))
 }
 
+// GET /metrics
+// Prometheus text exposition: current month's cost counters and event bus subscribers
+pub async fn get_prometheus_metrics(
+    State(runtime): State<Arc<RARORuntime>>,
+) -> impl IntoResponse {
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        runtime.prometheus_metrics(),
+    )
+}
 
-pub async fn get_runtime_state(
+// GET /runtime/:run_id/ready (alias: /ready_agents)
+// Agents whose dependencies are all completed and that are not yet running or finished.
+// Empty while the run is paused for approval or already finished.
+pub async fn get_ready_agents(
     State(runtime): State<Arc<RARORuntime>>,
-    Query(query): Query<RunQuery>,
-) -> Result<Json<RuntimeState>, StatusCode> {
-    let run_id = query.run_id.ok_or(StatusCode::BAD_REQUEST)?;
+    Path(run_id): Path<String>,
+) -> Result<Json<Vec<String>>, RuntimeError> {
+    Ok(Json(runtime.ready_agents(&run_id)?))
+}
 
-    runtime
-        .get_state(&run_id)
-        .ok_or(StatusCode::NOT_FOUND)
-        .map(Json)
+// GET /runtime/:run_id/stages
+// Completion, status and token totals per agent stage (reporting only)
+pub async fn get_run_stages(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let stages: Vec<StageSummary> = runtime.stage_report(&run_id)?;
+    Ok(Json(json!({ "run_id": run_id, "stages": stages })))
 }
 
-pub async fn invoke_agent(
+// GET /runtime/:run_id/cost_breakdown
+// Cost per agent and per model variant, with the projected total while agents are still running
+pub async fn get_cost_breakdown(
     State(runtime): State<Arc<RARORuntime>>,
-    Path((run_id, agent_id)): Path<(String, String)>,
-) -> Result<Json<InvocationPayload>, StatusCode> {
-    tracing::info!("Preparing invocation for agent: {} in run: {}", agent_id, run_id);
+    Path(run_id): Path<String>,
+) -> Result<Json<CostBreakdown>, RuntimeError> {
+    Ok(Json(runtime.cost_breakdown(&run_id)?))
+}
 
-    // CHANGE: Added .await
-    runtime
-        .prepare_invocation_payload(&run_id, &agent_id)
-        .await 
-        .map(Json)
-        .map_err(|e| {
-            tracing::error!("Failed to prepare invocation: {}", e);
-            StatusCode::NOT_FOUND
-        })
+// GET /runtime/:run_id/trace/execution
+// Gantt data: one interval per invocation, parallelism over time, DAG layers
+pub async fn get_execution_trace(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+) -> Result<Json<ExecutionTrace>, RuntimeError> {
+    Ok(Json(runtime.execution_trace(&run_id)?))
 }
 
-pub async fn get_signatures(
+// GET /runtime/:run_id/timeline?after_seq=&limit=
+// Status changes, agent lifecycle, tool calls, approvals and annotations as one typed list
+pub async fn get_run_timeline(
     State(runtime): State<Arc<RARORuntime>>,
-    Query(query): Query<RunQuery>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let run_id = query.run_id.ok_or(StatusCode::BAD_REQUEST)?;
+    ClientSession(client_id): ClientSession,
+    Path(run_id): Path<String>,
+    Query(query): Query<TimelineQuery>,
+) -> Result<Json<TimelinePage>, RuntimeError> {
+    let limit = query.limit.unwrap_or(timeline::DEFAULT_TIMELINE_LIMIT).clamp(1, timeline::MAX_TIMELINE_LIMIT);
+    Ok(Json(runtime.timeline(&run_id, &client_id, query.after_seq, limit)?))
+}
 
-    let signatures = runtime
-        .get_all_signatures(&run_id)
-        .ok_or(StatusCode::NOT_FOUND)?;
+// GET /runtime/:run_id/trace/execution/compare/:other_run_id
+// Flags agents that waited longer after becoming ready in the other run than in this one
+pub async fn compare_execution_traces(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, other_run_id)): Path<(String, String)>,
+) -> Result<Json<TraceComparison>, RuntimeError> {
+    let base = runtime.execution_trace(&run_id)?;
+    let other = runtime.execution_trace(&other_run_id)?;
+    Ok(Json(execution_trace::compare(base, other)))
+}
 
-    Ok(Json(json!({
-        "run_id": run_id,
-        "signatures": signatures.signatures
-    })))
+// GET /runtime/:run_id/result?partial=true
+// Completed agents' outputs. Mid-run this is 409 unless partial=true, which also lists pending agents.
+pub async fn get_run_result(
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    Query(query): Query<ResultQuery>,
+) -> Result<Json<RunResult>, RuntimeError> {
+    Ok(Json(runtime.get_run_result(&run_id, query.partial).await?))
 }
 
 pub async fn get_artifact(
@@ -266,29 +1218,98 @@ pub async fn get_artifact(
 
 pub async fn ws_runtime_stream(
     State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
     Path(run_id): Path<String>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_runtime_stream(socket, runtime, run_id))
+    ws.on_upgrade(move |socket| handle_runtime_stream(socket, runtime, run_id, client_id))
+}
+
+/// Control messages a client may send over the runtime stream, e.g. `{"cmd":"pause"}`
+#[derive(serde::Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum StreamCommand {
+    Pause {
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    Resume {
+        #[serde(default)]
+        target_agents: Option<Vec<String>>,
+    },
+    /// Forward only this agent's events (run-level events still come through); no agent_id = all
+    Subscribe {
+        #[serde(default)]
+        agent_id: Option<String>,
+    },
+}
+
+/// Run one command for the run's owner. Replies `{"type":"ack","cmd":...}` or
+/// `{"type":"error","cmd":...,"error":<code>,"message":...}` with the HTTP API's error codes.
+async fn handle_stream_command(
+    runtime: &Arc<RARORuntime>,
+    run_id: &str,
+    client_id: &str,
+    text: &str,
+    agent_filter: &mut Option<String>,
+) -> serde_json::Value {
+    let command: StreamCommand = match serde_json::from_str(text) {
+        Ok(command) => command,
+        Err(e) => return json!({ "type": "error", "error": "invalid_command", "message": e.to_string() }),
+    };
+    let (name, result) = match command {
+        StreamCommand::Pause { reason } => {
+            let reason = reason.unwrap_or_else(|| "Paused by client".to_string());
+            ("pause", runtime.pause_run(run_id, client_id, &reason).await.map(|_| json!({})))
+        }
+        StreamCommand::Resume { target_agents } => {
+            let result = match runtime.ensure_run_owner(run_id, client_id) {
+                Ok(()) => runtime.resume_run(run_id, target_agents).await,
+                Err(e) => Err(e),
+            };
+            ("resume", result.map(|_| json!({})))
+        }
+        StreamCommand::Subscribe { agent_id } => {
+            let result = runtime.ensure_run_owner(run_id, client_id)
+                .and_then(|_| agent_id.as_deref().map_or(Ok(()), |id| runtime.ensure_agent_exists(run_id, id)))
+                .map(|_| {
+                    *agent_filter = agent_id.clone();
+                    json!({ "agent_id": agent_id })
+                });
+            ("subscribe", result)
+        }
+    };
+
+    match result {
+        Ok(mut ack) => {
+            ack["type"] = json!("ack");
+            ack["cmd"] = json!(name);
+            ack
+        }
+        Err(e) => json!({ "type": "error", "cmd": name, "error": e.code(), "message": e.to_string() }),
+    }
 }
 
 async fn handle_runtime_stream(
     socket: WebSocket,
     runtime: Arc<RARORuntime>,
     run_id: String,
+    client_id: String,
 ) {
     let (mut sender, mut receiver) = socket.split();
+    // Set by a subscribe command
+    let mut agent_filter: Option<String> = None;
 
     // Wait briefly for state to be initialized if called immediately after start
     if runtime.get_state(&run_id).is_none() {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
 
-    // Verify run exists
-    if runtime.get_state(&run_id).is_none() {
+    // Only the run's owner may watch it; anyone else sees the same answer as for an unknown run
+    if let Err(e) = runtime.ensure_run_owner(&run_id, &client_id) {
         let _ = sender
             .send(Message::Text(
-                json!({"error": "Run not found"}).to_string(),
+                json!({"type": "error", "error": e.code(), "message": e.to_string()}).to_string(),
             ))
             .await;
         return;
@@ -311,16 +1332,25 @@ async fn handle_runtime_stream(
     // Stream updates
     let mut interval = tokio::time::interval(std::time::Duration::from_millis(250));
 
-    // Subscribe to event bus for real-time logs
-    let mut bus_rx = runtime.event_bus.subscribe();
+    // Subscribe to this run's events (lagging behind resyncs from the event log)
+    let mut events = runtime.subscribe_run(&run_id);
 
     loop {
         tokio::select! {
-            // Check for client disconnect
+            // Client commands, or disconnect
             msg = receiver.next() => {
-                if msg.is_none() {
-                    tracing::info!("Client disconnected from runtime stream: {}", run_id);
-                    break;
+                match msg {
+                    None => {
+                        tracing::info!("Client disconnected from runtime stream: {}", run_id);
+                        break;
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = handle_stream_command(&runtime, &run_id, &client_id, &text, &mut agent_filter).await;
+                        if sender.send(Message::Text(reply.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(_) => {}
                 }
             }
 
@@ -346,7 +1376,7 @@ async fn handle_runtime_stream(
                     
                     // === FIX START ===
                     // Check for terminal states to auto-close connection
-                    if state.status == RuntimeStatus::Completed || state.status == RuntimeStatus::Failed {
+                    if state.status.is_terminal() {
                         tracing::info!("Run {} reached terminal state: {:?}. Closing stream.", run_id, state.status);
                         
                         // Optional: Small delay to ensure client processes the final message before close frame
@@ -361,40 +1391,54 @@ async fn handle_runtime_stream(
             }
 
             // Forward real-time events from event bus
-            Ok(event) = bus_rx.recv() => {
-                // Only forward events for THIS run
-                if event.run_id == run_id {
-                    // Event whitelist: Forward time-critical events for real-time UI updates
-                    // (Other events are still available via state polling)
-                    let should_forward = matches!(
-                        event.event_type,
-                        crate::events::EventType::IntermediateLog |
-                        crate::events::EventType::SystemIntervention |
-                        crate::events::EventType::AgentStarted |
-                        crate::events::EventType::AgentCompleted |
-                        crate::events::EventType::AgentFailed
-                    );
-
-                    if should_forward {
-                        let event_type_name = match event.event_type {
-                            crate::events::EventType::IntermediateLog => "log_event",
-                            crate::events::EventType::SystemIntervention => "intervention_event",
-                            crate::events::EventType::AgentStarted => "agent_started",
-                            crate::events::EventType::AgentCompleted => "agent_completed",
-                            crate::events::EventType::AgentFailed => "agent_failed",
-                            _ => "unknown_event",
-                        };
-
-                        let ws_msg = json!({
-                            "type": event_type_name,
-                            "agent_id": event.agent_id,
-                            "payload": event.payload,
-                            "timestamp": event.timestamp
-                        });
-
-                        if sender.send(Message::Text(ws_msg.to_string())).await.is_err() {
-                            tracing::info!("Failed to send event, client disconnected");
-                            break;
+            Some(event) = events.next(&runtime) => {
+                // Event whitelist: Forward time-critical events for real-time UI updates
+                // (Other events are still available via state polling)
+                let should_forward = matches!(
+                    event.event_type,
+                    crate::events::EventType::IntermediateLog |
+                    crate::events::EventType::SystemIntervention |
+                    crate::events::EventType::AgentStarted |
+                    crate::events::EventType::AgentCompleted |
+                    crate::events::EventType::AgentFailed |
+                    crate::events::EventType::ArtifactPromoted
+                );
+
+                let other_agent = matches!((&agent_filter, &event.agent_id), (Some(wanted), Some(agent)) if wanted != agent);
+                if should_forward && !other_agent {
+                    let event_type_name = match event.event_type {
+                        crate::events::EventType::IntermediateLog => "log_event",
+                        crate::events::EventType::SystemIntervention => "intervention_event",
+                        crate::events::EventType::AgentStarted => "agent_started",
+                        crate::events::EventType::AgentCompleted => "agent_completed",
+                        crate::events::EventType::AgentFailed => "agent_failed",
+                        crate::events::EventType::ArtifactPromoted => "artifact_promoted",
+                        _ => "unknown_event",
+                    };
+
+                    let ws_msg = json!({
+                        "type": event_type_name,
+                        "agent_id": event.agent_id,
+                        "payload": event.payload,
+                        "timestamp": event.timestamp
+                    });
+
+                    if sender.send(Message::Text(ws_msg.to_string())).await.is_err() {
+                        tracing::info!("Failed to send event, client disconnected");
+                        break;
+                    }
+
+                    // Pause/resume/feedback change the run status; don't wait for the next tick
+                    if matches!(event.event_type, crate::events::EventType::SystemIntervention) {
+                        if let Some(state) = runtime.get_state(&run_id) {
+                            let update = json!({
+                                "type": "state_update",
+                                "state": state,
+                                "timestamp": chrono::Utc::now().to_rfc3339()
+                            });
+                            if sender.send(Message::Text(update.to_string())).await.is_err() {
+                                break;
+                            }
                         }
                     }
                 }
@@ -431,6 +1475,16 @@ pub async fn list_all_artifacts(
     Ok(Json(json!({ "artifacts": artifacts })))
 }
 
+/// GET /artifacts?expiring_within_days=&content_type=
+/// One summary per run with promoted artifacts: workflow, lifetime, status, size and tags
+pub async fn list_client_artifacts(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Query(filter): Query<ArtifactListFilter>,
+) -> Result<Json<Vec<ArtifactRunSummary>>, RuntimeError> {
+    Ok(Json(runtime.client_artifacts(&client_id, &filter).await?))
+}
+
 /// GET /runtime/artifacts/:run_id
 /// Gets metadata for a specific run's artifacts
 pub async fn get_run_artifacts(
@@ -460,18 +1514,21 @@ pub async fn serve_artifact_file(
 
     // 2. Construct path to artifacts storage (scoped by client_id)
     let file_path = format!("/app/storage/artifacts/{}/{}/{}", client_id, run_id, filename);
-    let path = std::path::Path::new(&file_path);
+    file_download(std::path::Path::new(&file_path), &filename).await
+}
 
+/// Stream a stored file with its guessed content type (security headers are added by the router)
+pub(crate) async fn file_download(path: &std::path::Path, filename: &str) -> Result<impl IntoResponse, StatusCode> {
     // 3. Verify existence
     if !path.exists() {
-        tracing::debug!("Artifact file not found: {}", file_path);
+        tracing::debug!("Artifact file not found: {}", path.display());
         return Err(StatusCode::NOT_FOUND);
     }
 
     // 4. Open and stream
     let file = tokio::fs::File::open(path).await
         .map_err(|e| {
-            tracing::error!("Failed to open artifact file {}: {}", file_path, e);
+            tracing::error!("Failed to open artifact file {}: {}", path.display(), e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
@@ -479,19 +1536,9 @@ pub async fn serve_artifact_file(
     let body = Body::from_stream(stream);
 
     // 5. Determine content type
-    let content_type = if filename.ends_with(".png") { "image/png" }
-    else if filename.ends_with(".jpg") || filename.ends_with(".jpeg") { "image/jpeg" }
-    else if filename.ends_with(".csv") { "text/csv" }
-    else if filename.ends_with(".json") { "application/json" }
-    else if filename.ends_with(".md") { "text/markdown" }
-    else if filename.ends_with(".txt") { "text/plain" }
-    else if filename.ends_with(".json") { "application/json" }
-    else { "application/octet-stream" };
-
-
     let headers = [
-        ("Content-Type", content_type),
-        ("Cache-Control", "public, max-age=86400"), // 24-hour cache
+        ("Content-Type", fs_manager::guess_content_type(filename)),
+        ("Cache-Control", "public, max-age=86400".to_string()), // 24-hour cache
     ];
 
     Ok((headers, body))
@@ -512,7 +1559,7 @@ pub async fn delete_artifact_run(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    tracing::info!("Deleted artifact run: {} for client: {}", run_id, client_id);
+    tracing::info!("Deleted artifact run: {} for client: {}", run_id, anonymize_client(&client_id));
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -548,10 +1595,657 @@ pub async fn promote_artifact_to_library(
     WorkspaceInitializer::save_to_library(&client_id, &filename, &data)
         .await
         .map_err(|e| {
-            tracing::error!("Failed to promote artifact {} to client {} library: {}", filename, client_id, e);
+            tracing::error!("Failed to promote artifact {} to client {} library: {}", filename, anonymize_client(&client_id), e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    tracing::info!("Promoted artifact {} from run {} to client {} library", filename, run_id, client_id);
+    tracing::info!("Promoted artifact {} from run {} to client {} library", filename, run_id, anonymize_client(&client_id));
     Ok(StatusCode::CREATED)
-}
\ No newline at end of file
+}
+
+// === MIDDLEWARE ===
+
+/// Seals aborted runs: any request naming an aborted run_id (path segment or ?run_id=) gets 410.
+pub async fn reject_aborted_runs(
+    State(runtime): State<Arc<RARORuntime>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let uri = request.uri();
+    let from_path = run_id_in_path(uri.path());
+    let from_query = uri.query().unwrap_or("").split('&').filter_map(|pair| pair.strip_prefix("run_id="));
+
+    if let Some(run_id) = from_path.into_iter().chain(from_query).find(|id| runtime.is_run_aborted(id)) {
+        let reason = runtime.abort_reason(run_id).unwrap_or_default();
+        tracing::warn!("Rejected {} on aborted run {}", uri.path(), run_id);
+        return (StatusCode::GONE, Json(json!({ "error": crate::server::error::RUN_ABORTED, "run_id": run_id, "reason": reason }))).into_response();
+    }
+
+    next.run(request).await
+}
+
+/// The :run_id segment of a run-scoped route, so an agent id, filename or key that happens
+/// to equal an aborted run's id doesn't trip the guard
+fn run_id_in_path(path: &str) -> Option<&str> {
+    let segments: Vec<&str> = path.split('/').filter(|seg| !seg.is_empty()).collect();
+    match segments.as_slice() {
+        ["runtime", "artifacts", run_id, ..] | ["admin", "runs", run_id, ..] | ["ws", "runtime", run_id, ..] => Some(run_id),
+        ["runtime", run_id, _, ..] if !matches!(*run_id, "library" | "validate") => Some(run_id),
+        _ => None,
+    }
+}
+
+// === ADMIN HANDLERS ===
+
+/// GET /admin/models
+/// Lists the live ModelVariant -> API model mapping table
+pub async fn list_model_mappings(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Json<Vec<ModelMapping>> {
+    Json(runtime.model_registry.list())
+}
+
+/// POST /admin/models/reload
+/// Re-reads the mapping file without restarting the kernel
+pub async fn reload_model_mappings(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    match runtime.model_registry.reload() {
+        Ok(count) => Ok(Json(json!({ "success": true, "loaded": count }))),
+        Err(e) => {
+            tracing::error!("Model mapping reload failed: {}", e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+    }
+}
+
+/// GET /admin/clients/:client_id/usage
+pub async fn get_client_usage(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(client_id): Path<String>,
+) -> Json<UsageReport> {
+    Json(runtime.usage_report(&client_id))
+}
+
+/// GET /admin/clients/:client_id/model_policy
+/// The client's allowed models and daily quotas, with today's counters
+pub async fn get_model_policy(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(client_id): Path<String>,
+) -> Json<ModelPolicyReport> {
+    Json(runtime.client_policies.report(&client_id))
+}
+
+/// PUT /admin/clients/:client_id/model_policy
+/// Replace the client's policy; an empty body object lifts every restriction
+pub async fn set_model_policy(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(client_id): Path<String>,
+    Json(profile): Json<ClientProfile>,
+) -> Json<ModelPolicyReport> {
+    runtime.client_policies.set_profile(&client_id, profile);
+    Json(runtime.client_policies.report(&client_id))
+}
+
+/// POST /admin/clients/:client_id/halt
+/// Kill-switch: cancels every live run for the client and (by default) blocks new ones
+pub async fn halt_client(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(client_id): Path<String>,
+    body: Option<Json<HaltRequest>>,
+) -> Json<serde_json::Value> {
+    let req = body.map(|Json(r)| r).unwrap_or_default();
+    let reason = req.reason.unwrap_or_else(|| "Client halted by administrator".to_string());
+
+    let cancelled = runtime.halt_client(&client_id, req.block_new_runs, &reason).await;
+
+    Json(json!({
+        "client_id": client_id,
+        "cancelled_runs": cancelled,
+        "blocked": runtime.is_client_halted(&client_id)
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct MaintenanceRequest {
+    mode: MaintenanceMode,
+}
+
+/// GET /admin/maintenance
+/// Current mode, plus how many runs are still active and the oldest of them (drain progress)
+pub async fn get_maintenance(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Json<MaintenanceStatus> {
+    Json(runtime.maintenance_status())
+}
+
+/// POST /admin/maintenance
+/// `drain` refuses new runs with 503 "maintenance"; in-flight runs, reads and streams carry on
+pub async fn set_maintenance(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Json(req): Json<MaintenanceRequest>,
+) -> Json<MaintenanceStatus> {
+    runtime.set_maintenance_mode(req.mode).await;
+    Json(runtime.maintenance_status())
+}
+
+#[derive(serde::Deserialize)]
+pub struct LogLevelRequest {
+    filter: String,
+}
+
+/// POST /admin/log_level
+/// Swap the live tracing filter without a restart; 422 if the expression does not parse
+pub async fn set_log_level(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Json(req): Json<LogLevelRequest>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    runtime.set_log_filter(&req.filter)?;
+    Ok(Json(json!({ "filter": req.filter })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PriorityRequest {
+    priority: u8,
+}
+
+/// POST /admin/runs/:run_id/priority
+/// Override a run's scheduling priority (0-9)
+pub async fn set_run_priority(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(run_id): Path<String>,
+    Json(req): Json<PriorityRequest>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let previous = runtime.set_run_priority(&run_id, req.priority).await?;
+    Ok(Json(json!({ "run_id": run_id, "previous": previous, "priority": req.priority })))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SkipPayload {
+    reason: String,
+}
+
+/// POST /runtime/:run_id/agent/:agent_id/skip (admin)
+/// Debugging override: mark a pending or running agent as skipped so the run can advance
+pub async fn skip_agent(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path((run_id, agent_id)): Path<(String, String)>,
+    Json(req): Json<SkipPayload>,
+) -> Result<Json<AgentInvocation>, RuntimeError> {
+    Ok(Json(runtime.skip_agent(&run_id, &agent_id, &req.reason).await?))
+}
+
+// PATCH /runtime/:run_id/agent/:agent_id/config
+// Hot-update a pending agent's prompt, thinking budget or output cap; 409 once it has been dispatched
+pub async fn patch_agent_config(
+    State(runtime): State<Arc<RARORuntime>>,
+    ClientSession(client_id): ClientSession,
+    Path((run_id, agent_id)): Path<(String, String)>,
+    Json(patch): Json<PatchAgentConfig>,
+) -> Result<Json<AgentNodeConfig>, RuntimeError> {
+    Ok(Json(runtime.patch_agent_config(&run_id, &client_id, &agent_id, &patch).await?))
+}
+
+/// GET /admin/schedule
+/// Live runs with dispatchable agents, highest (aged) priority first
+pub async fn get_dispatch_queue(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Json<Vec<ScheduledRun>> {
+    Json(runtime.dispatch_queue(crate::runtime::priority_aging_from_env()))
+}
+
+/// GET /admin/system
+/// Process vitals: uptime, RSS, run counts, registered patterns, live background tasks
+pub async fn get_system_status(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Json<SystemStatus> {
+    Json(runtime.system_status())
+}
+
+/// POST /admin/gc
+/// Purge finished runs older than max_age_minutes from memory (dry_run only lists them)
+pub async fn collect_garbage(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Json(request): Json<GcRequest>,
+) -> Result<Json<GcResult>, RuntimeError> {
+    Ok(Json(runtime.collect_garbage(&request)?))
+}
+
+/// POST /admin/clients/:client_id/unhalt
+pub async fn unhalt_client(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+    Path(client_id): Path<String>,
+) -> StatusCode {
+    if runtime.unhalt_client(&client_id) {
+        tracing::info!("Client {} unhalted", anonymize_client(&client_id));
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// GET /admin/caches
+/// All run -> cached content registrations
+pub async fn list_caches(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Json<Vec<CacheRegistration>> {
+    Json(runtime.list_cache_resources())
+}
+
+/// POST /admin/caches/verify
+/// Checks every cache id upstream and detaches dead ones
+pub async fn verify_caches(
+    _admin: AdminSession,
+    State(runtime): State<Arc<RARORuntime>>,
+) -> Result<Json<serde_json::Value>, RuntimeError> {
+    let detached = runtime.verify_cache_resources().await?;
+
+    Ok(Json(json!({ "detached_runs": detached })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_halted_client_start_rejected_with_forbidden() {
+        let runtime = Arc::new(RARORuntime::new());
+        runtime.halt_client("tenant-a", true, "Incident").await;
+
+        let config = json!({
+            "id": "wf", "name": "wf", "agents": [], "max_token_budget": 1, "timeout_ms": 1
+        });
+
+        let result = start_workflow(State(runtime), ClientSession("tenant-a".to_string()), Query(StrictQuery { strict: None }), Json(config)).await;
+        let err = result.unwrap_err();
+        assert_eq!((err.status_code(), err.code()), (StatusCode::FORBIDDEN, "client_halted"));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_for_unknown_run_is_a_run_not_found_error() {
+        let runtime = Arc::new(RARORuntime::new());
+        let checkpoint = RunCheckpoint {
+            run_id: "ghost".to_string(),
+            agent_outputs: HashMap::new(),
+            thought_signatures: HashMap::new(),
+            completed_agents: Vec::new(),
+            failed_agents: Vec::new(),
+        };
+        let err = apply_checkpoint(State(runtime), Path("ghost".to_string()), Json(checkpoint)).await.unwrap_err();
+        assert_eq!((err.status_code(), err.code()), (StatusCode::NOT_FOUND, "run_not_found"));
+    }
+
+    #[tokio::test]
+    async fn test_verdict_dry_run_judges_sample_outputs() {
+        let runtime = Arc::new(RARORuntime::new());
+        let request = |score: f64| VerdictDryRunRequest {
+            workflow: json!({
+                "id": "wf", "name": "wf", "max_token_budget": 10_000, "timeout_ms": 1000,
+                "agents": [
+                    { "id": "review", "role": "worker", "model": "fast", "tools": [], "prompt": "go" },
+                    { "id": "writer", "role": "worker", "model": "fast", "tools": [], "prompt": "go", "depends_on": ["review"] }
+                ],
+                "success_criteria": { "conditions": ["agents.review.output.score >= 0.8"], "required_outputs": ["*.md"] }
+            }),
+            outputs: json!({ "review": { "score": score } }).as_object().unwrap().clone(),
+            output_files: vec!["report.md".to_string()],
+        };
+
+        let Json(passed) = dry_run_verdict(State(runtime.clone()), Query(StrictQuery { strict: None }), Json(request(0.9))).await.unwrap();
+        assert_eq!(passed, json!({ "terminal_agents": ["writer"], "verdict": { "result": "passed" } }));
+        let Json(failed) = dry_run_verdict(State(runtime), Query(StrictQuery { strict: None }), Json(request(0.1))).await.unwrap();
+        assert_eq!(failed["verdict"]["result"], "failed");
+    }
+
+    #[tokio::test]
+    async fn test_session_outputs_are_served_to_the_run_owner_only() {
+        let runtime = Arc::new(RARORuntime::new());
+        let config: WorkflowConfig = serde_json::from_value(json!({
+            "id": "wf-outputs", "name": "wf", "max_token_budget": 10_000, "timeout_ms": 1000,
+            "agents": [{ "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "go" }]
+        })).unwrap();
+        let run_id = runtime.create_run(config, "tenant-a", std::time::Duration::from_secs(60)).await.unwrap();
+        let output_dir = WorkspaceInitializer::session_output_dir(&run_id);
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("data.json"), r#"{"rows": 3}"#).unwrap();
+
+        let app = axum::Router::new()
+            .route("/runtime/:run_id/output/:filename", axum::routing::get(read_session_output))
+            .route("/runtime/:run_id/files/:filename", axum::routing::get(read_session_output))
+            .with_state(runtime.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let http = reqwest::Client::new();
+
+        for route in ["output", "files"] {
+            let url = format!("{}/runtime/{}/{}/data.json", base, run_id, route);
+            let stranger = http.get(&url).header("X-RARO-CLIENT-ID", "tenant-b").send().await.unwrap();
+            assert_eq!(stranger.status(), reqwest::StatusCode::NOT_FOUND, "{}", route);
+            // No session header means the public client, which doesn't own the run either
+            assert_eq!(http.get(&url).send().await.unwrap().status(), reqwest::StatusCode::NOT_FOUND, "{}", route);
+
+            let owner = http.get(&url).header("X-RARO-CLIENT-ID", "tenant-a").header("Accept", "text/plain").send().await.unwrap();
+            assert_eq!(owner.status(), reqwest::StatusCode::OK, "{}", route);
+            assert_eq!(owner.headers()["content-type"], "text/plain; charset=utf-8");
+            assert_eq!(owner.headers()["cache-control"], "private, max-age=3600");
+            assert_eq!(owner.text().await.unwrap(), r#"{"rows": 3}"#);
+        }
+        let _ = std::fs::remove_dir_all(output_dir.parent().unwrap());
+    }
+
+    /// A run of independent `agents` owned by the public client, registered through the import
+    /// path (no FS/execution side effects); imported running runs come back paused
+    async fn import_fixture(runtime: &RARORuntime, run_id: &str, status: &str, agents: &[&str]) -> String {
+        let agents: Vec<serde_json::Value> = agents.iter()
+            .map(|id| json!({ "id": id, "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null }))
+            .collect();
+        let bundle: RunExport = serde_json::from_value(json!({
+            "exported_at": "2026-01-01T00:00:00Z",
+            "state": {
+                "run_id": run_id, "workflow_id": format!("wf-{}", run_id), "client_id": "public", "status": status,
+                "active_agents": [], "completed_agents": [], "failed_agents": [], "invocations": [],
+                "total_tokens_used": 0, "start_time": "2026-01-01T00:00:00Z", "end_time": null
+            },
+            "workflow": {
+                "id": format!("wf-{}", run_id), "name": "wf", "max_token_budget": 0, "timeout_ms": 1, "agents": agents
+            },
+            "thought_signatures": {},
+            "dag": { "nodes": agents.iter().map(|a| a["id"].clone()).collect::<Vec<_>>(), "edges": [] },
+            "agent_outputs": {},
+            "events": []
+        })).unwrap();
+        runtime.import_run(bundle, "public").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pause_command_over_socket_pauses_run() {
+        let runtime = Arc::new(RARORuntime::new());
+        let run_id = import_fixture(&runtime, "run-ws", "running", &[]).await;
+        // Imported running runs come back paused
+        runtime.set_run_status(&run_id, RuntimeStatus::Running);
+
+        // Commands are for the run's owner only
+        let mut filter = None;
+        let denied = handle_stream_command(&runtime, &run_id, "intruder", r#"{"cmd":"pause"}"#, &mut filter).await;
+        assert_eq!((denied["type"].as_str(), denied["error"].as_str()), (Some("error"), Some("run_not_found")));
+        let unknown = handle_stream_command(&runtime, &run_id, "public", r#"{"cmd":"explode"}"#, &mut filter).await;
+        assert_eq!(unknown["error"], "invalid_command");
+
+        let app = axum::Router::new()
+            .route("/ws/runtime/:run_id", axum::routing::get(ws_runtime_stream))
+            .with_state(runtime.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // Another client's stream is closed after a not-found error, without the run's state
+        let mut request = tungstenite::client::IntoClientRequest::into_client_request(format!("ws://{}/ws/runtime/{}", addr, run_id)).unwrap();
+        request.headers_mut().insert("X-RARO-CLIENT-ID", "intruder".parse().unwrap());
+        let (mut stranger, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let mut seen = Vec::new();
+        while let Some(Ok(tungstenite::Message::Text(text))) = tokio::time::timeout(std::time::Duration::from_secs(5), stranger.next()).await.unwrap() {
+            seen.push(serde_json::from_str::<serde_json::Value>(&text).unwrap());
+        }
+        assert_eq!(seen.len(), 1, "{:?}", seen);
+        assert_eq!(seen[0]["error"], "run_not_found");
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/runtime/{}", addr, run_id)).await.unwrap();
+        socket.send(tungstenite::Message::Text(r#"{"cmd":"pause","reason":"Checking output"}"#.to_string())).await.unwrap();
+        let ack = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while let Some(Ok(tungstenite::Message::Text(text))) = socket.next().await {
+                let msg: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if msg["type"] == "ack" || msg["type"] == "error" {
+                    return msg;
+                }
+            }
+            panic!("stream closed before the command was answered");
+        }).await.unwrap();
+
+        assert_eq!(ack, json!({ "type": "ack", "cmd": "pause" }));
+        assert_eq!(runtime.get_state(&run_id).unwrap().status, RuntimeStatus::AwaitingApproval);
+        // Pausing twice is refused
+        let again = handle_stream_command(&runtime, &run_id, "public", r#"{"cmd":"pause"}"#, &mut filter).await;
+        assert_eq!(again["error"], "run_not_running");
+    }
+
+    #[tokio::test]
+    async fn test_aborted_run_rejects_api_calls() {
+        let runtime = Arc::new(RARORuntime::new());
+        let run_id = import_fixture(&runtime, "run-abort", "running", &[]).await;
+        runtime.abort_run(&run_id, "Critical violation").await.unwrap();
+
+        let app = axum::Router::new()
+            .route("/runtime/state", axum::routing::get(get_runtime_state))
+            .route("/runtime/:run_id/resume", axum::routing::post(resume_run))
+            .layer(axum::middleware::from_fn_with_state(runtime.clone(), reject_aborted_runs))
+            .with_state(runtime);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let state = client.get(format!("{}/runtime/state?run_id={}", base, run_id)).send().await.unwrap();
+        assert_eq!(state.status().as_u16(), StatusCode::GONE.as_u16());
+        let resume = client.post(format!("{}/runtime/{}/resume", base, run_id)).send().await.unwrap();
+        assert_eq!(resume.status().as_u16(), StatusCode::GONE.as_u16());
+    }
+
+    #[test]
+    fn test_aborted_run_guard_reads_only_the_run_id_segment() {
+        assert_eq!(run_id_in_path("/runtime/run-1/agent/a/skip"), Some("run-1"));
+        assert_eq!(run_id_in_path("/runtime/artifacts/run-1/files/x.md"), Some("run-1"));
+        assert_eq!(run_id_in_path("/admin/runs/run-1/priority"), Some("run-1"));
+        assert_eq!(run_id_in_path("/ws/runtime/run-1"), Some("run-1"));
+        // Other segments never count, even when they equal an aborted run's id
+        assert_eq!(run_id_in_path("/runtime/run-2/agent/run-1/skip"), Some("run-2"));
+        assert_eq!(run_id_in_path("/runtime/library/files/run-1"), None);
+        assert_eq!(run_id_in_path("/runtime/state"), None);
+    }
+
+    #[tokio::test]
+    async fn test_follow_agent_logs_until_agent_finishes() {
+        let runtime = Arc::new(RARORuntime::new());
+        let run_id = import_fixture(&runtime, "run-logs", "running", &["a"]).await;
+
+        let log = |message: &str| crate::events::RuntimeEvent::new(&run_id, crate::events::EventType::IntermediateLog,
+            Some("a".to_string()), json!({ "message": message, "metadata": "INFO" }));
+        runtime.emit_event(log("before"));
+
+        let app = axum::Router::new()
+            .route("/runtime/:run_id/agent/:agent_id/logs", axum::routing::get(get_agent_logs))
+            .with_state(runtime.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let response = reqwest::get(format!("{}/runtime/{}/agent/a/logs?follow=true", base, run_id)).await.unwrap();
+        runtime.emit_event(log("during"));
+        runtime.fail_run(&run_id, "a", FailureCode::AgentError, "boom").await;
+
+        // The body ends once the agent is finished
+        let body = tokio::time::timeout(std::time::Duration::from_secs(5), response.text()).await.unwrap().unwrap();
+        let seqs: Vec<(u64, String)> = body.lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .map(|v| (v["seq"].as_u64().unwrap(), v["message"].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(seqs, vec![(1, "before".to_string()), (2, "during".to_string())]);
+
+        let resumed = reqwest::get(format!("{}/runtime/{}/agent/a/logs?after_seq=1", base, run_id)).await.unwrap().text().await.unwrap();
+        assert_eq!(resumed.lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tail_pushes_new_agent_logs_over_sse() {
+        let runtime = Arc::new(RARORuntime::new());
+        let run_id = import_fixture(&runtime, "run-tail", "running", &["a", "b"]).await;
+
+        let log = |agent: &str, message: &str| crate::events::RuntimeEvent::new(&run_id, crate::events::EventType::IntermediateLog,
+            Some(agent.to_string()), json!({ "message": message, "metadata": "INFO" }));
+        for message in ["old-1", "old-2", "old-3"] {
+            runtime.emit_event(log("a", message));
+        }
+
+        let app = axum::Router::new()
+            .route("/runtime/:run_id/agent/:agent_id/logs/tail", axum::routing::get(tail_agent_logs))
+            .with_state(runtime.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut response = reqwest::get(format!("{}/runtime/{}/agent/a/logs/tail?replay=2", base, run_id)).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        // Read until `needle` shows up; `received` accumulates the whole feed
+        async fn read_until(response: &mut reqwest::Response, received: &mut String, needle: &str) -> String {
+            while !received.contains(needle) {
+                match tokio::time::timeout(std::time::Duration::from_secs(5), response.chunk()).await.unwrap().unwrap() {
+                    Some(bytes) => received.push_str(&String::from_utf8_lossy(&bytes)),
+                    None => break,
+                }
+            }
+            received.clone()
+        }
+        let mut received = String::new();
+
+        assert!(read_until(&mut response, &mut received, "old-3").await.contains("old-2"));
+        runtime.emit_event(log("b", "other agent"));
+        runtime.emit_event(log("a", "live"));
+        let feed = read_until(&mut response, &mut received, "live").await;
+        assert!(!feed.contains("old-1") && !feed.contains("other agent"));
+
+        runtime.fail_run(&run_id, "a", FailureCode::AgentError, "boom").await;
+        let feed = read_until(&mut response, &mut received, "event: end").await;
+        assert!(feed.contains("\"outcome\":\"failed\""), "{}", feed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_posting_logs_past_rate_limit_returns_429() {
+        let mut runtime = RARORuntime::new();
+        runtime.event_limiter = crate::event_limits::EventLimiter::new(crate::event_limits::EventLimits {
+            per_run_per_sec: 10, per_type_per_sec: 2, max_buffered_per_run: 100,
+        });
+        let runtime = Arc::new(runtime);
+        let run_id = import_fixture(&runtime, "run-flood", "running", &["a"]).await;
+
+        // Called directly rather than over a socket: the clock is paused
+        let post = |message: String| {
+            let request: PostAgentLogRequest = serde_json::from_value(json!({ "message": message })).unwrap();
+            post_agent_log(State(runtime.clone()), Path((run_id.clone(), "a".to_string())), Json(request))
+        };
+        let mut statuses = Vec::new();
+        for i in 0..4 {
+            let response = post(format!("step {}", i)).await.into_response();
+            statuses.push(response.status().as_u16());
+            if response.status().as_u16() == 429 {
+                assert_eq!(response.headers()["retry-after"], "1");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["error"], "rate_limited");
+            }
+        }
+        assert_eq!(statuses, vec![202, 202, 429, 429]);
+        assert!(runtime.prometheus_metrics()
+            .contains("raro_events_dropped_total{event_type=\"IntermediateLog\",reason=\"rate_limited\"} 2"));
+
+        // The next window accepts again, and the drops are reported once before its first event
+        tokio::time::advance(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(post("later".to_string()).await.into_response().status().as_u16(), 202);
+        let events = runtime.get_events(&run_id);
+        let summary = events.iter().find(|e| matches!(e.event_type, crate::events::EventType::EventsDropped)).unwrap();
+        assert_eq!(summary.payload["dropped"], json!({ "IntermediateLog": 2 }));
+        let logs = events.iter().filter(|e| matches!(e.event_type, crate::events::EventType::IntermediateLog)).count();
+        assert_eq!(logs, 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_memory_shared_across_calls_and_key_limited() {
+        let mut runtime = RARORuntime::new();
+        runtime.blackboard = crate::blackboard::Blackboard::new(crate::blackboard::MemoryLimits { max_keys_per_run: 2, max_bytes_per_run: 1024 });
+        let runtime = Arc::new(runtime);
+        let run_id = import_fixture(&runtime, "run-memory", "running", &["a"]).await;
+
+        let app = axum::Router::new()
+            .route("/runtime/:run_id/memory/:key", axum::routing::get(get_memory).put(put_memory).delete(delete_memory))
+            .with_state(runtime.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/runtime/{}/memory", listener.local_addr().unwrap(), run_id);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let put = |key: &str, value: serde_json::Value| client.put(format!("{}/{}", base, key)).json(&value).send();
+        assert_eq!(put("plan", json!({ "step": 1 })).await.unwrap().status().as_u16(), 201);
+        assert_eq!(put("plan", json!({ "step": 2 })).await.unwrap().status().as_u16(), 200);
+        assert_eq!(put("notes", json!(["a"])).await.unwrap().status().as_u16(), 201);
+
+        // A separate call (another agent) sees the latest value
+        let read = client.get(format!("{}/plan", base)).send().await.unwrap();
+        assert_eq!(read.json::<serde_json::Value>().await.unwrap(), json!({ "step": 2 }));
+
+        let rejected = put("third", json!(true)).await.unwrap();
+        assert_eq!(rejected.status().as_u16(), 413);
+        assert_eq!(rejected.json::<serde_json::Value>().await.unwrap()["error"], "memory_limit_exceeded");
+
+        // Deleting frees a slot; other clients can't see the run at all
+        assert_eq!(client.delete(format!("{}/notes", base)).send().await.unwrap().status().as_u16(), 204);
+        assert_eq!(put("third", json!(true)).await.unwrap().status().as_u16(), 201);
+        let foreign = client.get(format!("{}/plan", base)).header("X-RARO-CLIENT-ID", "other").send().await.unwrap();
+        assert_eq!(foreign.status().as_u16(), 404);
+        assert_eq!(client.get(format!("{}/notes", base)).send().await.unwrap().status().as_u16(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_rejects_misspelled_config_fields() {
+        let runtime = Arc::new(RARORuntime::new());
+        let app = axum::Router::new()
+            .route("/runtime/validate", axum::routing::post(validate_workflow))
+            .route("/runtime/runs", axum::routing::post(create_run))
+            .with_state(runtime.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let http = reqwest::Client::new();
+
+        let config = json!({
+            "id": "wf", "name": "wf", "max_token_budget": 10_000, "timeout_ms": 1000,
+            "agents": [
+                { "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "go" },
+                { "id": "b", "role": "worker", "model": "fast", "tools": [], "prompt": "go", "dependsOn": ["a"] }
+            ]
+        });
+
+        let resp = http.post(format!("{}/runtime/runs?strict=true", base))
+            .json(&json!({ "workflow": config })).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"], "validation_failed");
+        assert_eq!(body["errors"], json!([{ "code": "unknown_field", "details": "agents[1].dependsOn" }]));
+        assert!(runtime.list_runs("public", None).is_empty());
+
+        let report: serde_json::Value = http.post(format!("{}/runtime/validate?strict=true", base))
+            .json(&config).send().await.unwrap().json().await.unwrap();
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["errors"][0]["details"], "agents[1].dependsOn");
+
+        // Lenient by default: the typo is dropped as before
+        let resp = http.post(format!("{}/runtime/runs", base))
+            .json(&json!({ "workflow": config })).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+    }
+}
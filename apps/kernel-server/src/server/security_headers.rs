@@ -0,0 +1,170 @@
+// [[RARO]]/apps/kernel-server/src/server/security_headers.rs
+// Purpose: CORS policy and browser security headers for every response. Artifacts, session
+//          outputs and shared files are user-controlled content served from the API's own
+//          origin, so browsers must neither sniff them into HTML nor run their scripts.
+// Architecture: API Layer
+// Dependencies: tower-http (cors)
+//
+// Headers a handler sets itself are left alone; the bundle only fills in what is missing.
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum::response::Response;
+use axum::Router;
+use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Nothing loads by default; inline styles and images keep rendered HTML/SVG artifacts legible,
+/// and `sandbox` gives them a unique origin with scripts disabled
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src 'self' data:; style-src 'unsafe-inline'; sandbox";
+pub const DEFAULT_HSTS_MAX_AGE_SECS: u64 = 365 * 86400;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityHeaderConfig {
+    /// False sends no security headers (CORS still applies)
+    pub enabled: bool,
+    /// Strict-Transport-Security max-age; 0 omits the header (plain-HTTP deployments)
+    pub hsts_max_age_secs: u64,
+    pub frame_options: String,
+    pub content_security_policy: String,
+    /// Allowed CORS origins; empty allows any
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for SecurityHeaderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hsts_max_age_secs: DEFAULT_HSTS_MAX_AGE_SECS,
+            frame_options: "DENY".to_string(),
+            content_security_policy: DEFAULT_CONTENT_SECURITY_POLICY.to_string(),
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+/// RARO_SECURITY_HEADERS (default true), RARO_HSTS_MAX_AGE_SECS, RARO_FRAME_OPTIONS,
+/// RARO_CONTENT_SECURITY_POLICY and RARO_CORS_ORIGINS (comma-separated) override the defaults
+pub fn security_headers_from_env() -> SecurityHeaderConfig {
+    let var = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+    let defaults = SecurityHeaderConfig::default();
+    SecurityHeaderConfig {
+        enabled: var("RARO_SECURITY_HEADERS").map_or(defaults.enabled, |v| v != "false" && v != "0"),
+        hsts_max_age_secs: var("RARO_HSTS_MAX_AGE_SECS").and_then(|v| v.parse().ok()).unwrap_or(defaults.hsts_max_age_secs),
+        frame_options: var("RARO_FRAME_OPTIONS").unwrap_or(defaults.frame_options),
+        content_security_policy: var("RARO_CONTENT_SECURITY_POLICY").unwrap_or(defaults.content_security_policy),
+        cors_origins: var("RARO_CORS_ORIGINS")
+            .map(|v| v.split(',').map(|o| o.trim().to_string()).filter(|o| !o.is_empty()).collect())
+            .unwrap_or_default(),
+    }
+}
+
+impl SecurityHeaderConfig {
+    /// Header values that don't parse are dropped with a warning rather than failing startup
+    fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let mut headers = vec![
+            (axum::http::header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (axum::http::header::X_FRAME_OPTIONS, self.frame_options.clone()),
+            (axum::http::header::CONTENT_SECURITY_POLICY, self.content_security_policy.clone()),
+            (axum::http::header::REFERRER_POLICY, "no-referrer".to_string()),
+        ];
+        if self.hsts_max_age_secs > 0 {
+            headers.push((axum::http::header::STRICT_TRANSPORT_SECURITY, format!("max-age={}; includeSubDomains", self.hsts_max_age_secs)));
+        }
+        headers.into_iter()
+            .filter_map(|(name, value)| match HeaderValue::from_str(&value) {
+                Ok(value) => Some((name, value)),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid {} header value '{}'", name, value);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn cors_layer(&self) -> CorsLayer {
+        let origins: Vec<HeaderValue> = self.cors_origins.iter().filter_map(|o| HeaderValue::from_str(o).ok()).collect();
+        let cors = CorsLayer::new()
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+            .allow_headers(Any)
+            .expose_headers(Any); // Allow custom headers like X-RARO-CLIENT-ID
+        if origins.is_empty() { cors.allow_origin(Any) } else { cors.allow_origin(AllowOrigin::list(origins)) }
+    }
+}
+
+/// CORS and the header bundle on every route `router` has so far
+pub fn with_security_headers<S>(router: Router<S>, config: &SecurityHeaderConfig) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let headers = Arc::new(config.headers());
+    router
+        .layer(config.cors_layer())
+        .layer(axum::middleware::map_response(move |mut response: Response| {
+            let headers = headers.clone();
+            async move {
+                for (name, value) in headers.iter() {
+                    if !response.headers().contains_key(name) {
+                        response.headers_mut().insert(name.clone(), value.clone());
+                    }
+                }
+                response
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    #[tokio::test]
+    async fn test_artifact_download_carries_security_headers() {
+        let dir = std::env::temp_dir().join(format!("raro-headers-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("report.html"), "<script>alert(1)</script>").unwrap();
+
+        let served = dir.clone();
+        let download = move |axum::extract::Path(filename): axum::extract::Path<String>| {
+            let dir = served.clone();
+            async move { crate::server::handlers::file_download(&dir.join(&filename), &filename).await }
+        };
+        let config = SecurityHeaderConfig {
+            hsts_max_age_secs: 600,
+            cors_origins: vec!["https://app.example".to_string()],
+            ..SecurityHeaderConfig::default()
+        };
+        let app = with_security_headers(Router::new().route("/runtime/artifacts/run-1/files/:filename", get(download)), &config);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let response = reqwest::Client::new().get(format!("{}/runtime/artifacts/run-1/files/report.html", base))
+            .header("Origin", "https://app.example")
+            .send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+        assert_eq!(header("content-type"), "application/octet-stream");
+        assert_eq!(header("x-content-type-options"), "nosniff");
+        assert_eq!(header("x-frame-options"), "DENY");
+        assert_eq!(header("content-security-policy"), DEFAULT_CONTENT_SECURITY_POLICY);
+        assert_eq!(header("strict-transport-security"), "max-age=600; includeSubDomains");
+        assert_eq!(header("access-control-allow-origin"), "https://app.example");
+
+        // Browsers may preflight the PUT/PATCH routes (memory, model policy, agent config)
+        let preflight = reqwest::Client::new().request(reqwest::Method::OPTIONS, format!("{}/runtime/artifacts/run-1/files/report.html", base))
+            .header("Origin", "https://app.example")
+            .header("Access-Control-Request-Method", "PATCH")
+            .send().await.unwrap();
+        let allowed = preflight.headers()["access-control-allow-methods"].to_str().unwrap().to_string();
+        assert!(allowed.contains("PATCH") && allowed.contains("PUT"), "{}", allowed);
+
+        // Switched off, only CORS remains
+        let disabled = SecurityHeaderConfig { enabled: false, ..config };
+        assert!(disabled.headers().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
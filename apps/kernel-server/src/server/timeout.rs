@@ -0,0 +1,63 @@
+// [[RARO]]/apps/kernel-server/src/server/timeout.rs
+// Purpose: Overall request timeout. A request whose response is not ready in time is answered
+//          with 408, so a hung filesystem call or downstream can't hold a connection forever.
+//          Streaming routes (WebSocket, SSE, uploads) are mounted outside it; see main.rs.
+// Architecture: API Layer
+// Dependencies: tower-http (timeout)
+//
+// The limit covers reading the request body and producing the response head. Bodies streamed
+// after that (file downloads) are not cut off.
+
+use axum::Router;
+use std::time::Duration;
+use tower_http::timeout::TimeoutLayer;
+
+/// RARO_REQUEST_TIMEOUT_SECS: default 30, 0 disables the timeout
+pub fn request_timeout_from_env() -> Option<Duration> {
+    let secs = std::env::var("RARO_REQUEST_TIMEOUT_SECS").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(30);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Time out every route `router` has so far; routes merged in afterwards are exempt
+pub fn with_request_timeout<S>(router: Router<S>, timeout: Option<Duration>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    match timeout {
+        Some(limit) => router.layer(TimeoutLayer::new(limit)),
+        None => router,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    #[tokio::test]
+    async fn test_slow_handler_cut_off_with_408() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            "done"
+        };
+        let limited = Router::new()
+            .route("/slow", get(slow))
+            .route("/fast", get(|| async { "done" }));
+        let app = with_request_timeout(limited, Some(Duration::from_millis(100)))
+            .merge(Router::new().route("/stream", get(slow)))
+            .layer(axum::middleware::from_fn(crate::server::error::ensure_error_body));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let response = reqwest::get(format!("{}/slow", base)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::REQUEST_TIMEOUT);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "request_timeout");
+
+        assert_eq!(reqwest::get(format!("{}/fast", base)).await.unwrap().status(), reqwest::StatusCode::OK);
+        // Exempt routes may take as long as they need
+        assert_eq!(reqwest::get(format!("{}/stream", base)).await.unwrap().status(), reqwest::StatusCode::OK);
+    }
+}
@@ -0,0 +1,222 @@
+// [[RARO]]/apps/kernel-server/src/share.rs
+// Purpose: Read-only public share links for finished runs. A link is a token that carries its
+//          own claims (share id, run, shared artifacts, expiry) signed with HMAC-SHA256, so
+//          checking one needs no lookup beyond the in-memory link list. A token whose share id
+//          is not in that list is refused.
+// Architecture: Security Layer
+// Dependencies: HMAC-SHA256 (hmac, sha2), Base64, DashMap, Redis, Chrono
+//
+// Links are mirrored to Redis (`share_link:{share_id}`, expiring with the link) so listing and
+// revocations survive a restart. Tokens outlive a restart only when RARO_SHARE_SECRET is set.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::redis_keys;
+
+const SHARE_KEY_PREFIX: &str = "share_link:";
+
+pub const DEFAULT_SHARE_TTL_SECS: u64 = 7 * 86400;
+pub const MAX_SHARE_TTL_SECS: u64 = 30 * 86400;
+
+/// What a token grants, embedded in the token itself
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShareClaims {
+    pub share_id: String,
+    pub run_id: String,
+    /// Promoted artifacts of the run the link may download
+    pub artifacts: Vec<String>,
+    /// Unix seconds
+    pub expires_at: i64,
+}
+
+/// A share as listed to the run's owner (the token itself is only returned on creation)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShareLink {
+    pub share_id: String,
+    pub run_id: String,
+    pub artifacts: Vec<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<String>,
+}
+
+/// POST /runtime/:run_id/share response
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedShare {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    pub token: String,
+}
+
+pub struct ShareLinks {
+    secret: Vec<u8>,
+    links: DashMap<String, ShareLink>, // share_id -> link (revoked ones kept until they expire)
+    redis_client: Option<redis::Client>,
+}
+
+impl ShareLinks {
+    /// RARO_SHARE_SECRET signs the tokens; without it a random per-process secret is used
+    pub fn from_env(redis_client: Option<redis::Client>) -> Self {
+        let secret = std::env::var("RARO_SHARE_SECRET").ok().filter(|s| !s.is_empty());
+        if secret.is_none() {
+            tracing::warn!("RARO_SHARE_SECRET not set. Share links will stop working on restart.");
+        }
+        Self::new(secret, redis_client)
+    }
+
+    pub fn new(secret: Option<String>, redis_client: Option<redis::Client>) -> Self {
+        let secret = secret.unwrap_or_else(|| format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4()));
+        Self { secret: secret.into_bytes(), links: DashMap::new(), redis_client }
+    }
+
+    pub fn create(&self, run_id: &str, artifacts: Vec<String>, ttl: chrono::Duration) -> CreatedShare {
+        let now = Utc::now();
+        let claims = ShareClaims {
+            share_id: uuid::Uuid::new_v4().to_string(),
+            run_id: run_id.to_string(),
+            artifacts,
+            expires_at: (now + ttl).timestamp(),
+        };
+        let body = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap_or_default());
+        let token = format!("{}.{}", body, URL_SAFE_NO_PAD.encode(self.mac(body.as_bytes()).finalize().into_bytes()));
+
+        let link = ShareLink {
+            share_id: claims.share_id,
+            run_id: claims.run_id,
+            artifacts: claims.artifacts,
+            created_at: now.to_rfc3339(),
+            expires_at: (now + ttl).to_rfc3339(),
+            revoked_at: None,
+        };
+        self.links.retain(|_, l| !is_expired(l, now));
+        self.links.insert(link.share_id.clone(), link.clone());
+        self.persist(&link, claims.expires_at);
+        CreatedShare { link, token }
+    }
+
+    /// Claims of a token that is authentic, unexpired, known and not revoked
+    pub fn verify(&self, token: &str) -> Option<ShareClaims> {
+        let (body, mac) = token.split_once('.')?;
+        let mac = URL_SAFE_NO_PAD.decode(mac).ok()?;
+        self.mac(body.as_bytes()).verify_slice(&mac).ok()?;
+        let claims: ShareClaims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(body).ok()?).ok()?;
+        if claims.expires_at <= Utc::now().timestamp() {
+            return None;
+        }
+        // Fail closed: a link that was never recorded (or whose record was lost) is not valid
+        let live = self.links.get(&claims.share_id).is_some_and(|l| l.run_id == claims.run_id && l.revoked_at.is_none());
+        live.then_some(claims)
+    }
+
+    fn mac(&self, message: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(message);
+        mac
+    }
+
+    /// Live and revoked-but-unexpired shares of a run, oldest first
+    pub fn for_run(&self, run_id: &str) -> Vec<ShareLink> {
+        let now = Utc::now();
+        let mut links: Vec<ShareLink> = self.links.iter()
+            .filter(|l| l.run_id == run_id && !is_expired(l, now))
+            .map(|l| l.clone())
+            .collect();
+        links.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        links
+    }
+
+    /// False when the run has no such share. Revoking twice keeps the first revocation time.
+    pub fn revoke(&self, run_id: &str, share_id: &str) -> bool {
+        let link = {
+            let Some(mut link) = self.links.get_mut(share_id).filter(|l| l.run_id == run_id) else { return false };
+            link.revoked_at.get_or_insert_with(|| Utc::now().to_rfc3339());
+            link.clone()
+        };
+        let expires_at = DateTime::parse_from_rfc3339(&link.expires_at).map(|t| t.timestamp()).unwrap_or_default();
+        self.persist(&link, expires_at);
+        true
+    }
+
+    fn persist(&self, link: &ShareLink, expires_at: i64) {
+        let Some(client) = self.redis_client.clone() else { return };
+        let key = format!("{}{}", SHARE_KEY_PREFIX, link.share_id);
+        let stored = serde_json::to_string(link).unwrap_or_default();
+        tokio::spawn(async move {
+            let result: redis::RedisResult<()> = async {
+                let mut con = client.get_async_connection().await?;
+                redis::pipe()
+                    .set(&key, stored).ignore()
+                    .expire_at(&key, expires_at).ignore()
+                    .query_async(&mut con)
+                    .await
+            }.await;
+            if let Err(e) = result {
+                tracing::warn!("Failed to persist share link {}: {}", key, e);
+            }
+        });
+    }
+
+    /// Restore links and revocations at boot
+    pub async fn load_from_redis(&self) {
+        let Some(client) = &self.redis_client else { return };
+        let mut con = match client.get_async_connection().await {
+            Ok(con) => con,
+            Err(e) => {
+                tracing::warn!("Share links not restored: {}", e);
+                return;
+            }
+        };
+
+        let keys = redis_keys::scan_keys(&mut con, &format!("{}*", SHARE_KEY_PREFIX)).await.unwrap_or_default();
+        for key in keys {
+            let stored: Option<String> = con.get(&key).await.unwrap_or(None);
+            if let Some(link) = stored.and_then(|json| serde_json::from_str::<ShareLink>(&json).ok()) {
+                self.links.insert(link.share_id.clone(), link);
+            }
+        }
+        tracing::info!("Restored {} share links", self.links.len());
+    }
+}
+
+fn is_expired(link: &ShareLink, now: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc3339(&link.expires_at).map_or(true, |t| t <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_share_tokens_verify_until_revoked_or_expired() {
+        let shares = ShareLinks::new(Some("secret".to_string()), None);
+        let created = shares.create("run-1", vec!["report.md".to_string()], chrono::Duration::hours(1));
+        let claims = shares.verify(&created.token).unwrap();
+        assert_eq!((claims.run_id.as_str(), claims.artifacts.clone()), ("run-1", vec!["report.md".to_string()]));
+
+        // Tampered claims, a foreign secret and expired links are refused
+        let (body, mac) = created.token.split_once('.').unwrap();
+        let mut forged = claims.clone();
+        forged.artifacts.push("secrets.env".to_string());
+        let forged_body = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert!(shares.verify(&format!("{}.{}", forged_body, mac)).is_none());
+        assert!(ShareLinks::new(Some("other".to_string()), None).verify(&created.token).is_none());
+        // Same secret, but the share was never recorded there: refused
+        assert!(ShareLinks::new(Some("secret".to_string()), None).verify(&created.token).is_none());
+        assert!(shares.verify(body).is_none());
+        let expired = shares.create("run-1", vec![], chrono::Duration::seconds(-1));
+        assert!(shares.verify(&expired.token).is_none());
+
+        // Listed per run; revocation is scoped to the run and takes effect immediately
+        assert_eq!(shares.for_run("run-1"), vec![created.link.clone()]);
+        assert!(!shares.revoke("run-2", &created.link.share_id));
+        assert!(shares.revoke("run-1", &created.link.share_id));
+        assert!(shares.verify(&created.token).is_none());
+        assert!(shares.for_run("run-1")[0].revoked_at.is_some());
+    }
+}
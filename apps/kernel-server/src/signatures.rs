@@ -0,0 +1,183 @@
+// [[RARO]]/apps/kernel-server/src/signatures.rs
+// Purpose: Thought signature storage policy. Size/format validation and transparent compression.
+// Architecture: Domain Helper Layer
+// Dependencies: flate2, base64, sha2
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+/// Marks a stored value as gzip+base64. Raw signatures never legitimately start with this.
+const COMPRESSED_PREFIX: &str = "raro-gz1:";
+
+const DEFAULT_MAX_BYTES: usize = 512 * 1024;
+const DEFAULT_COMPRESS_THRESHOLD: usize = 16 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct SignaturePolicy {
+    pub max_bytes: usize,
+    /// Drop oversized signatures (the agent's successors run without one) instead of rejecting
+    /// them. Never truncated: a signature is opaque model state and a prefix of it is invalid.
+    pub drop_oversized: bool,
+    /// Signatures larger than this are stored compressed
+    pub compress_threshold: usize,
+}
+
+impl SignaturePolicy {
+    /// SIGNATURE_MAX_BYTES, SIGNATURE_OVERSIZE_POLICY (reject|drop), SIGNATURE_COMPRESS_THRESHOLD
+    pub fn from_env() -> Self {
+        let read = |key: &str, default: usize| {
+            std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+
+        Self {
+            max_bytes: read("SIGNATURE_MAX_BYTES", DEFAULT_MAX_BYTES),
+            drop_oversized: std::env::var("SIGNATURE_OVERSIZE_POLICY")
+                .map(|p| p.eq_ignore_ascii_case("drop"))
+                .unwrap_or(false),
+            compress_threshold: read("SIGNATURE_COMPRESS_THRESHOLD", DEFAULT_COMPRESS_THRESHOLD),
+        }
+    }
+}
+
+/// How two parents' signatures combine when fan-in payloads are merged
+#[allow(dead_code)] // Builder API: the managed loop routes a single parent signature
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureMergeStrategy {
+    /// Both, joined by `separator` (primary first)
+    Concatenate { separator: String },
+    /// Keep the primary's signature, fall back to the other's
+    KeepPrimary,
+}
+
+impl Default for SignatureMergeStrategy {
+    fn default() -> Self {
+        SignatureMergeStrategy::Concatenate { separator: "\n---\n".to_string() }
+    }
+}
+
+#[allow(dead_code)]
+impl SignatureMergeStrategy {
+    pub fn merge(&self, primary: Option<&str>, other: Option<&str>) -> Option<String> {
+        match (self, primary, other) {
+            (SignatureMergeStrategy::Concatenate { separator }, Some(a), Some(b)) if a != b => Some(format!("{}{}{}", a, separator, b)),
+            (_, Some(a), _) => Some(a.to_string()),
+            (_, None, b) => b.map(str::to_string),
+        }
+    }
+}
+
+/// Validate a raw signature and convert it to its stored form. None when the policy drops it
+/// for its size.
+pub fn encode(policy: &SignaturePolicy, raw: String) -> Result<Option<String>, String> {
+    if raw.is_empty() {
+        return Err("Signature is empty".to_string());
+    }
+    if raw.chars().any(|c| c.is_control()) {
+        return Err("Signature contains control characters".to_string());
+    }
+
+    if raw.len() > policy.max_bytes {
+        if !policy.drop_oversized {
+            return Err(format!("Signature is {} bytes (max {})", raw.len(), policy.max_bytes));
+        }
+        return Ok(None);
+    }
+
+    let stored = if raw.len() > policy.compress_threshold || raw.starts_with(COMPRESSED_PREFIX) {
+        compress(&raw)?
+    } else {
+        raw
+    };
+
+    Ok(Some(stored))
+}
+
+/// Recover the raw signature from its stored form. Uncompressed values pass through.
+pub fn decode(stored: &str) -> String {
+    let Some(encoded) = stored.strip_prefix(COMPRESSED_PREFIX) else {
+        return stored.to_string();
+    };
+
+    let mut raw = String::new();
+    let decoded = STANDARD
+        .decode(encoded)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| GzDecoder::new(bytes.as_slice()).read_to_string(&mut raw).map_err(|e| e.to_string()));
+
+    match decoded {
+        Ok(_) => raw,
+        Err(e) => {
+            tracing::error!("Corrupt compressed signature: {}", e);
+            String::new()
+        }
+    }
+}
+
+fn compress(raw: &str) -> Result<String, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(raw.as_bytes()).map_err(|e| e.to_string())?;
+    let bytes = encoder.finish().map_err(|e| e.to_string())?;
+    Ok(format!("{}{}", COMPRESSED_PREFIX, STANDARD.encode(bytes)))
+}
+
+/// Size/hash view of a stored signature, for listings that should not ship the content
+#[derive(Debug, Clone, Serialize)]
+pub struct SignatureSummary {
+    pub raw_bytes: usize,
+    pub stored_bytes: usize,
+    pub compressed: bool,
+    pub sha256: String,
+}
+
+pub fn summarize(stored: &str) -> SignatureSummary {
+    let raw = decode(stored);
+    SignatureSummary {
+        raw_bytes: raw.len(),
+        stored_bytes: stored.len(),
+        compressed: stored.starts_with(COMPRESSED_PREFIX),
+        sha256: format!("{:x}", Sha256::digest(raw.as_bytes())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(drop_oversized: bool) -> SignaturePolicy {
+        SignaturePolicy { max_bytes: 64 * 1024, drop_oversized, compress_threshold: 1024 }
+    }
+
+    #[test]
+    fn test_large_signatures_compressed_transparently() {
+        let raw = "c2lnbmF0dXJl".repeat(2000);
+        let stored = encode(&policy(false), raw.clone()).unwrap().unwrap();
+        assert!(stored.len() < raw.len());
+        assert_eq!(decode(&stored), raw);
+
+        let small = encode(&policy(false), "sig-a".to_string()).unwrap();
+        assert_eq!(small.as_deref(), Some("sig-a"));
+
+        let summary = summarize(&stored);
+        assert!(summary.compressed);
+        assert_eq!(summary.raw_bytes, raw.len());
+        assert_eq!(summary.sha256.len(), 64);
+    }
+
+    #[test]
+    fn test_oversized_and_malformed_signatures() {
+        let huge = "x".repeat(64 * 1024 + 1);
+        assert!(encode(&policy(false), huge.clone()).is_err());
+
+        assert_eq!(encode(&policy(true), huge).unwrap(), None);
+        let at_limit = "x".repeat(64 * 1024);
+        assert_eq!(encode(&policy(true), at_limit.clone()).unwrap().map(|s| decode(&s)), Some(at_limit));
+
+        assert!(encode(&policy(false), String::new()).is_err());
+        assert!(encode(&policy(false), "bad\u{0}sig".to_string()).is_err());
+    }
+}
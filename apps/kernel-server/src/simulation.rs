@@ -0,0 +1,60 @@
+// [[RARO]]/apps/kernel-server/src/simulation.rs
+// Purpose: Built-in fake agent executor for simulated runs (workflow development without Gemini calls).
+// Architecture: Execution Layer (stand-in for the agent service)
+// Dependencies: sha2, Tokio
+
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use crate::models::RemoteAgentResponse;
+use crate::runtime::InvocationPayload;
+
+const DEFAULT_DELAY_MS: u64 = 250;
+
+/// RARO_SIMULATION_DELAY_MS (default 250), used when the workflow sets no simulation_delay_ms
+pub fn default_delay() -> Duration {
+    let ms = std::env::var("RARO_SIMULATION_DELAY_MS").ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_DELAY_MS);
+    Duration::from_millis(ms)
+}
+
+/// Same call shape as the agent service client so the execution loop can swap it in
+pub async fn invoke(payload: InvocationPayload, delay: Duration) -> Result<RemoteAgentResponse, reqwest::Error> {
+    tokio::time::sleep(delay).await;
+    Ok(respond(&payload, delay))
+}
+
+/// Deterministic response: identical prompts yield identical output, signature and token counts
+pub fn respond(payload: &InvocationPayload, delay: Duration) -> RemoteAgentResponse {
+    let prompt_digest = format!("{:x}", Sha256::digest(payload.prompt.as_bytes()));
+    let result = format!("[SIMULATED] Output of {} for prompt {}", payload.agent_id, &prompt_digest[..12]);
+
+    let signature_digest = format!("{:x}", Sha256::digest(format!(
+        "{}:{}:{}",
+        payload.parent_signature.as_deref().unwrap_or(""),
+        payload.agent_id,
+        prompt_digest,
+    ).as_bytes()));
+
+    let input_tokens = (payload.prompt.len() + payload.user_directive.len()) / 4;
+    let output_tokens = result.len() / 4;
+
+    RemoteAgentResponse {
+        agent_id: payload.agent_id.clone(),
+        success: true,
+        output: Some(serde_json::json!({ "result": result, "simulated": true })),
+        error: None,
+        tokens_used: input_tokens + output_tokens,
+        thought_signature: Some(format!("sim_{}", &signature_digest[..32])),
+        input_tokens,
+        output_tokens,
+        cache_hit: false,
+        latency_ms: delay.as_millis() as f64,
+        cached_content_id: None,
+        cached_tokens: 0,
+        thinking_tokens: 0,
+        // Report every granted tool as used so protocol checks behave as for a compliant agent
+        executed_tools: payload.tools.clone(),
+        delegation: None,
+    }
+}
@@ -0,0 +1,278 @@
+// [[RARO]]/apps/kernel-server/src/template.rs
+// Purpose: Prompt templating. Resolves {{agents.<id>.output.<path>}} against upstream outputs
+//          and {{KEY}} placeholders against the agent's environment. The same references
+//          drive success conditions (`agents.<id>.output.<path> <op> <JSON>`).
+// Architecture: Domain Helper Layer
+// Dependencies: serde_json, thiserror
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("Malformed template expression '{{{{{0}}}}}': expected agents.<id>.output[.<path>]")]
+    Malformed(String),
+    #[error("Template references agent '{0}', which is not an upstream dependency with stored output")]
+    UnknownAgent(String),
+    #[error("Path '{path}' not found in output of agent '{agent}'")]
+    MissingPath { agent: String, path: String },
+    #[error("Invalid condition '{expr}': {reason}")]
+    InvalidCondition { expr: String, reason: String },
+}
+
+/// Longest first, so `>=` is never read as `>`
+const CONDITION_OPERATORS: [&str; 7] = [">=", "<=", "==", "!=", ">", "<", "contains"];
+
+/// A parsed success condition
+#[derive(Debug, PartialEq)]
+pub struct Condition<'a> {
+    agent: &'a str,
+    path: Vec<&'a str>,
+    comparison: Option<(&'a str, Value)>,
+}
+
+/// `agents.<id>.output[.<path>]`, optionally followed by an operator and a JSON literal
+/// (`agents.review.output.score >= 0.8`, `agents.review.output.verdict == "approve"`)
+pub fn parse_condition(expr: &str) -> Result<Condition<'_>, TemplateError> {
+    let invalid = |reason: &str| TemplateError::InvalidCondition { expr: expr.to_string(), reason: reason.to_string() };
+    let expr_trimmed = expr.trim();
+    let (reference, rest) = expr_trimmed.split_once(char::is_whitespace).unwrap_or((expr_trimmed, ""));
+    let (agent, path) = split_reference(reference).map_err(|_| invalid("expected agents.<id>.output[.<path>]"))?;
+
+    let rest = rest.trim_start();
+    if rest.is_empty() {
+        return Ok(Condition { agent, path, comparison: None });
+    }
+    let (op, literal) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if !CONDITION_OPERATORS.contains(&op) {
+        return Err(invalid(&format!("unknown operator '{}'", op)));
+    }
+    let expected = serde_json::from_str(literal.trim())
+        .map_err(|_| invalid("the right-hand side must be a JSON literal (quote strings)"))?;
+    Ok(Condition { agent, path, comparison: Some((op, expected)) })
+}
+
+/// Whether `expr` holds against `outputs`. Without an operator the value must be truthy
+/// (not null, false, 0, "" or empty). Ordering operators compare numbers only (numeric
+/// strings included) and are false for anything else.
+pub fn evaluate_condition(expr: &str, outputs: &Map<String, Value>) -> Result<bool, TemplateError> {
+    let condition = parse_condition(expr)?;
+    let root = outputs.get(condition.agent).ok_or_else(|| TemplateError::UnknownAgent(condition.agent.to_string()))?;
+    let actual = navigate(root, &condition.path).ok_or_else(|| TemplateError::MissingPath {
+        agent: condition.agent.to_string(),
+        path: condition.path.join("."),
+    })?;
+
+    Ok(match &condition.comparison {
+        None => is_truthy(&actual),
+        Some((op, expected)) => compare(&actual, op, expected),
+    })
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn compare(actual: &Value, op: &str, expected: &Value) -> bool {
+    let equal = || match (as_number(actual), expected) {
+        (Some(a), Value::Number(b)) => Some(a) == b.as_f64(),
+        _ => actual == expected,
+    };
+    match op {
+        "==" => equal(),
+        "!=" => !equal(),
+        "contains" => match actual {
+            Value::String(s) => expected.as_str().is_some_and(|e| s.contains(e)),
+            Value::Array(items) => items.contains(expected),
+            Value::Object(map) => expected.as_str().is_some_and(|key| map.contains_key(key)),
+            _ => false,
+        },
+        _ => match (as_number(actual), expected.as_f64()) {
+            (Some(a), Some(b)) => match op {
+                ">" => a > b,
+                ">=" => a >= b,
+                "<" => a < b,
+                _ => a <= b,
+            },
+            _ => false,
+        },
+    }
+}
+
+/// Replace every `{{agents.<id>.output.<path>}}` in `template` with the value found in
+/// `outputs[<id>]`. Strings are inserted raw; other values as compact JSON.
+/// `{{KEY}}` is replaced from `env`; unknown keys, a `{{` that is never closed and any other
+/// text are left untouched. Single pass, so substituted values are never re-expanded.
+pub fn render(template: &str, outputs: &Map<String, Value>, env: &HashMap<String, String>) -> Result<String, TemplateError> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        let Some(end) = after_open.find("}}") else { break };
+        rendered.push_str(&rest[..start]);
+        let expr = after_open[..end].trim();
+
+        if expr.starts_with("agents.") {
+            rendered.push_str(&resolve(expr, outputs)?);
+        } else if let Some(value) = env.get(expr) {
+            rendered.push_str(value);
+        } else {
+            if is_env_key(expr) {
+                tracing::warn!("Template placeholder '{{{{{}}}}}' has no environment value; leaving it intact", expr);
+            }
+            // Not ours (e.g. literal braces in a prompt) - keep verbatim
+            rendered.push_str(&rest[start..start + 2 + end + 2]);
+        }
+
+        rest = &rest[start + 2 + end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+fn is_env_key(expr: &str) -> bool {
+    !expr.is_empty() && expr.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `agents.<id>.output[.<path>]` -> (id, path segments)
+fn split_reference(expr: &str) -> Result<(&str, Vec<&str>), TemplateError> {
+    let mut segments = expr.split('.');
+    let (prefix, agent_id, output_kw) = (segments.next(), segments.next(), segments.next());
+
+    match (prefix, agent_id, output_kw) {
+        (Some("agents"), Some(id), Some("output")) if !id.is_empty() => Ok((id, segments.collect())),
+        _ => Err(TemplateError::Malformed(expr.to_string())),
+    }
+}
+
+fn resolve(expr: &str, outputs: &Map<String, Value>) -> Result<String, TemplateError> {
+    let (agent_id, path) = split_reference(expr)?;
+    let root = outputs.get(agent_id).ok_or_else(|| TemplateError::UnknownAgent(agent_id.to_string()))?;
+
+    let value = navigate(root, &path).ok_or_else(|| TemplateError::MissingPath {
+        agent: agent_id.to_string(),
+        path: path.join("."),
+    })?;
+
+    Ok(match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    })
+}
+
+/// Walk `path` through objects/arrays. A string holding JSON (typical for `result`) is
+/// parsed on the fly so structured model output can be addressed directly.
+fn navigate(root: &Value, path: &[&str]) -> Option<Value> {
+    let mut current = root.clone();
+
+    for segment in path {
+        if let Value::String(s) = &current {
+            current = serde_json::from_str(s).ok()?;
+        }
+
+        current = match &current {
+            Value::Object(map) => map.get(*segment)?.clone(),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?.clone(),
+            _ => return None,
+        };
+    }
+
+    Some(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parent_outputs() -> Map<String, Value> {
+        let mut outputs = Map::new();
+        outputs.insert("researcher".to_string(), json!({
+            "result": "{\"summary\": {\"headline\": \"Q3 revenue up 12%\"}, \"sources\": [\"a\", \"b\"]}",
+            "meta": { "confidence": 0.82 }
+        }));
+        outputs
+    }
+
+    #[test]
+    fn test_child_prompt_interpolates_nested_parent_field() {
+        let prompt = "Write a memo on: {{agents.researcher.output.result.summary.headline}} (confidence {{ agents.researcher.output.meta.confidence }})";
+        let rendered = render(prompt, &parent_outputs(), &HashMap::new()).unwrap();
+        assert_eq!(rendered, "Write a memo on: Q3 revenue up 12% (confidence 0.82)");
+
+        let rendered = render("Cite {{agents.researcher.output.result.sources.1}}", &parent_outputs(), &HashMap::new()).unwrap();
+        assert_eq!(rendered, "Cite b");
+    }
+
+    #[test]
+    fn test_missing_paths_and_agents_error_clearly() {
+        let err = render("{{agents.researcher.output.result.summary.missing}}", &parent_outputs(), &HashMap::new()).unwrap_err();
+        assert_eq!(err, TemplateError::MissingPath {
+            agent: "researcher".to_string(),
+            path: "result.summary.missing".to_string(),
+        });
+
+        let err = render("{{agents.writer.output.result}}", &parent_outputs(), &HashMap::new()).unwrap_err();
+        assert_eq!(err, TemplateError::UnknownAgent("writer".to_string()));
+
+        assert!(matches!(render("{{agents.researcher}}", &parent_outputs(), &HashMap::new()), Err(TemplateError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_conditions_compare_output_fields() {
+        let outputs = parent_outputs();
+        let holds = |expr: &str| evaluate_condition(expr, &outputs).unwrap();
+        assert!(holds("agents.researcher.output.meta.confidence >= 0.8"));
+        assert!(!holds("agents.researcher.output.meta.confidence > 0.9"));
+        assert!(holds("agents.researcher.output.result.summary.headline contains \"revenue up\""));
+        assert!(holds("agents.researcher.output.result.sources contains \"b\""));
+        assert!(holds("agents.researcher.output.result.sources"));
+        assert!(holds("agents.researcher.output.meta.confidence != 1"));
+        // Ordering on a non-number is simply false
+        assert!(!holds("agents.researcher.output.result.summary.headline > 3"));
+
+        assert!(matches!(evaluate_condition("agents.writer.output.result", &outputs), Err(TemplateError::UnknownAgent(_))));
+        assert!(matches!(evaluate_condition("agents.researcher.output.meta.missing == 1", &outputs), Err(TemplateError::MissingPath { .. })));
+        for bad in ["score >= 1", "agents.researcher.output.meta ~ 1", "agents.researcher.output.meta == approve"] {
+            assert!(matches!(parse_condition(bad), Err(TemplateError::InvalidCondition { .. })), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_non_agent_braces_left_alone() {
+        let text = "Return JSON like {{\"key\": 1}} please";
+        assert_eq!(render(text, &parent_outputs(), &HashMap::new()).unwrap(), text);
+        // An opening pair that is never closed is plain text too
+        let text = "Cite {{agents.researcher.output.result.sources.1}} then write {{ as is";
+        assert_eq!(render(text, &parent_outputs(), &HashMap::new()).unwrap(), "Cite b then write {{ as is");
+    }
+
+    #[test]
+    fn test_environment_placeholders() {
+        let env: HashMap<String, String> = [("API_URL", "https://eu.example"), ("ENV", "{{ENV}}")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let rendered = render("Call {{API_URL}} in {{ ENV }}; flag {{MISSING_FLAG}}", &parent_outputs(), &env).unwrap();
+        assert_eq!(rendered, "Call https://eu.example in {{ENV}}; flag {{MISSING_FLAG}}");
+    }
+}
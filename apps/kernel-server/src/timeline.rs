@@ -0,0 +1,207 @@
+// [[RARO]]/apps/kernel-server/src/timeline.rs
+// Purpose: One chronologically ordered, typed timeline of a run for dashboards: status changes,
+//          agent lifecycle with durations, tool calls, interventions, approvals, annotations,
+//          artifact promotions and the verdict. Built from the event log plus state, so the
+//          detailed endpoints (events, invocations, logs) can keep their own shapes.
+// Architecture: Domain Helper Layer
+// Dependencies: chrono, serde_json
+//
+// Entries are ordered by the seq of the event they come from, never by timestamp: events
+// emitted within the same instant keep the order they were logged in.
+
+use chrono::DateTime;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use crate::events::{EventType, RuntimeEvent};
+use crate::models::{InvocationStatus, RuntimeState};
+
+pub const DEFAULT_TIMELINE_LIMIT: usize = 200;
+pub const MAX_TIMELINE_LIMIT: usize = 1000;
+
+/// What happened; serialized as the entry's `kind` with its fields alongside
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TimelineKind {
+    StatusChanged { status: Value },
+    AgentStarted,
+    /// duration_ms from the agent's last AgentStarted, else its recorded invocation latency
+    AgentCompleted { duration_ms: Option<u64> },
+    AgentFailed { duration_ms: Option<u64>, reason: Option<String> },
+    /// A ToolCall event, or a TOOL-category log line (message only)
+    ToolCall { tool: Option<String>, message: Option<String> },
+    ApprovalRequested { reason: Option<String> },
+    /// A reviewer's decision (see RARORuntime::record_feedback)
+    ApprovalDecision { approved: bool, reviewer_id: Option<String>, comment: Option<String> },
+    Resumed,
+    /// Any other SystemIntervention (abort, halt, stall, supervisor directives, ...)
+    Intervention { action: Option<String>, reason: Option<String> },
+    /// The run's metadata after a change (e.g. a SetRunMetadata pattern)
+    Annotation { metadata: Value },
+    ArtifactPromoted { filename: Option<String> },
+    Verdict { verdict: Value },
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TimelineEntry {
+    /// seq of the source event; entries from one event share it
+    pub seq: u64,
+    pub timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    #[serde(flatten)]
+    pub kind: TimelineKind,
+}
+
+/// GET /runtime/:run_id/timeline response
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TimelinePage {
+    pub run_id: String,
+    pub entries: Vec<TimelineEntry>,
+    /// Pass as after_seq for the next page; None once the log is exhausted
+    pub next_seq: Option<u64>,
+}
+
+/// Entries of the events with seq > `after_seq`, at least `limit` of them when available.
+/// A page never ends part-way through one event's entries, so after_seq paging loses nothing.
+pub fn build(events: &[RuntimeEvent], state: &RuntimeState, after_seq: u64, limit: usize) -> TimelinePage {
+    // Durations need the start events, including those before the page
+    let mut started_at: HashMap<&str, &str> = HashMap::new();
+    let mut entries = Vec::new();
+    let mut next_seq = None;
+
+    for event in events {
+        if let (EventType::AgentStarted, Some(agent)) = (&event.event_type, &event.agent_id) {
+            started_at.insert(agent, &event.timestamp);
+        }
+        if event.seq <= after_seq {
+            continue;
+        }
+        if entries.len() >= limit {
+            next_seq = entries.last().map(|e: &TimelineEntry| e.seq);
+            break;
+        }
+        let duration = || event.agent_id.as_deref().and_then(|agent| agent_duration(agent, event, &started_at, state));
+        let kinds = match &event.event_type {
+            EventType::StateChanged => state_changes(&event.payload),
+            EventType::AgentStarted => vec![TimelineKind::AgentStarted],
+            EventType::AgentCompleted => vec![TimelineKind::AgentCompleted { duration_ms: duration() }],
+            EventType::AgentFailed => vec![TimelineKind::AgentFailed { duration_ms: duration(), reason: text(&event.payload, "reason") }],
+            EventType::ToolCall => vec![TimelineKind::ToolCall { tool: text(&event.payload, "tool"), message: None }],
+            EventType::IntermediateLog if event.payload["category"] == "TOOL" => {
+                vec![TimelineKind::ToolCall { tool: None, message: text(&event.payload, "message") }]
+            }
+            EventType::SystemIntervention => vec![intervention(&event.payload)],
+            EventType::ArtifactPromoted => vec![TimelineKind::ArtifactPromoted { filename: text(&event.payload, "filename") }],
+            EventType::VerdictRecorded => vec![TimelineKind::Verdict { verdict: event.payload.clone() }],
+            _ => Vec::new(),
+        };
+        entries.extend(kinds.into_iter().map(|kind| TimelineEntry {
+            seq: event.seq,
+            timestamp: event.timestamp.clone(),
+            agent_id: event.agent_id.clone(),
+            kind,
+        }));
+    }
+
+    TimelinePage { run_id: state.run_id.clone(), entries, next_seq }
+}
+
+fn text(payload: &Value, key: &str) -> Option<String> {
+    payload[key].as_str().map(String::from)
+}
+
+/// Status and metadata changes of a StateChanged delta (see replay::state_delta)
+fn state_changes(payload: &Value) -> Vec<TimelineKind> {
+    let changes = &payload["changes"];
+    let mut kinds = Vec::new();
+    if let Some(status) = changes["status"].get("set") {
+        kinds.push(TimelineKind::StatusChanged { status: status.clone() });
+    }
+    if let Some(metadata) = changes["metadata"].get("set") {
+        kinds.push(TimelineKind::Annotation { metadata: metadata.clone() });
+    }
+    kinds
+}
+
+fn intervention(payload: &Value) -> TimelineKind {
+    let action = text(payload, "action").or_else(|| text(payload, "type"));
+    let reason = text(payload, "reason");
+    match action.as_deref() {
+        Some("pause") => TimelineKind::ApprovalRequested { reason },
+        Some("feedback") => TimelineKind::ApprovalDecision {
+            approved: payload["approved"].as_bool().unwrap_or(false),
+            reviewer_id: text(payload, "reviewer_id"),
+            comment: text(payload, "comment"),
+        },
+        Some("resume") => TimelineKind::Resumed,
+        _ => TimelineKind::Intervention { action, reason },
+    }
+}
+
+fn agent_duration(agent: &str, event: &RuntimeEvent, started_at: &HashMap<&str, &str>, state: &RuntimeState) -> Option<u64> {
+    let from_events = started_at.get(agent)
+        .and_then(|start| Some((DateTime::parse_from_rfc3339(&event.timestamp).ok()? - DateTime::parse_from_rfc3339(start).ok()?).num_milliseconds()))
+        .and_then(|ms| u64::try_from(ms).ok());
+    from_events.or_else(|| state.invocations.iter().rev()
+        .find(|i| i.agent_id == agent && matches!(i.status, InvocationStatus::Success | InvocationStatus::Failed))
+        .map(|i| i.latency_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(seq: u64, event_type: EventType, agent_id: Option<&str>, payload: Value) -> RuntimeEvent {
+        RuntimeEvent {
+            seq,
+            timestamp: "2024-06-01T12:00:00+00:00".to_string(),
+            ..RuntimeEvent::new("run-1", event_type, agent_id.map(String::from), payload)
+        }
+    }
+
+    #[test]
+    fn test_entries_sharing_a_timestamp_keep_log_order_across_pages() {
+        let state: RuntimeState = serde_json::from_value(serde_json::json!({
+            "run_id": "run-1", "workflow_id": "wf", "client_id": "public", "status": "completed",
+            "active_agents": [], "completed_agents": ["a"], "failed_agents": [], "invocations": [],
+            "total_tokens_used": 0, "start_time": "2024-06-01T12:00:00+00:00", "end_time": null
+        })).unwrap();
+        let events = vec![
+            event(1, EventType::StateChanged, None, serde_json::json!({ "reset": true, "changes": { "status": { "set": "running" } } })),
+            event(2, EventType::AgentStarted, Some("a"), serde_json::json!({})),
+            event(3, EventType::IntermediateLog, Some("a"), serde_json::json!({ "message": "thinking", "metadata": "INFO" })),
+            event(4, EventType::ToolCall, Some("a"), serde_json::json!({ "tool": "read_file" })),
+            event(5, EventType::SystemIntervention, Some("a"), serde_json::json!({ "action": "pause", "reason": "check" })),
+            event(6, EventType::SystemIntervention, None, serde_json::json!({ "action": "feedback", "approved": true, "reviewer_id": "rev-1" })),
+            event(7, EventType::StateChanged, None, serde_json::json!({ "reset": false, "changes": {
+                "status": { "set": "completed" }, "metadata": { "set": { "safety_flags": ["x"] } }
+            } })),
+            event(8, EventType::AgentCompleted, Some("a"), serde_json::json!({})),
+            event(9, EventType::ArtifactPromoted, Some("a"), serde_json::json!({ "filename": "report.md" })),
+        ];
+
+        let full = build(&events, &state, 0, MAX_TIMELINE_LIMIT);
+        let kinds: Vec<Value> = full.entries.iter().map(|e| serde_json::to_value(e).unwrap()["kind"].clone()).collect();
+        assert_eq!(kinds, vec![
+            "status_changed", "agent_started", "tool_call", "approval_requested", "approval_decision",
+            "status_changed", "annotation", "agent_completed", "artifact_promoted",
+        ]);
+        assert_eq!(full.entries[7].kind, TimelineKind::AgentCompleted { duration_ms: Some(0) });
+        assert_eq!(full.next_seq, None);
+
+        // Paging in threes yields the same sequence; event 7's two entries stay on one page
+        let mut paged = Vec::new();
+        let mut after = 0;
+        loop {
+            let page = build(&events, &state, after, 3);
+            paged.extend(page.entries);
+            match page.next_seq {
+                Some(seq) => after = seq,
+                None => break,
+            }
+        }
+        assert_eq!(paged, full.entries);
+        assert_eq!(build(&events, &state, 0, 3).entries.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2, 4]);
+    }
+}
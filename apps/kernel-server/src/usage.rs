@@ -0,0 +1,205 @@
+// [[RARO]]/apps/kernel-server/src/usage.rs
+// Purpose: Per-client monthly usage rollups (runs, tokens) and configured quota limits.
+// Architecture: Accounting Layer
+// Dependencies: DashMap, Redis, Chrono
+
+use chrono::Utc;
+use dashmap::DashMap;
+use redis::AsyncCommands;
+use serde::Serialize;
+use std::collections::HashMap;
+use crate::observability;
+use crate::redis_keys;
+
+const USAGE_KEY_PREFIX: &str = "usage:";
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct UsageRollup {
+    pub runs_started: u64,
+    pub tokens_used: u64,
+    pub invocations: u64,
+}
+
+/// Configured quotas. None means unlimited (not enforced yet, reported only).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageLimits {
+    pub monthly_runs: Option<u64>,
+    pub monthly_tokens: Option<u64>,
+    pub storage_bytes: Option<u64>,
+}
+
+impl UsageLimits {
+    /// RARO_QUOTA_MONTHLY_RUNS, RARO_QUOTA_MONTHLY_TOKENS, RARO_QUOTA_STORAGE_BYTES (unset or 0 = unlimited)
+    pub fn from_env() -> Self {
+        let read = |key: &str| std::env::var(key).ok().and_then(|v| v.parse::<u64>().ok()).filter(|v| *v > 0);
+        Self {
+            monthly_runs: read("RARO_QUOTA_MONTHLY_RUNS"),
+            monthly_tokens: read("RARO_QUOTA_MONTHLY_TOKENS"),
+            storage_bytes: read("RARO_QUOTA_STORAGE_BYTES"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsagePercent {
+    pub runs: Option<f64>,
+    pub tokens: Option<f64>,
+    pub storage: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    pub client_id: String,
+    /// Calendar month of the rollup (UTC, YYYY-MM)
+    pub month: String,
+    pub runs_started: u64,
+    pub runs_active: usize,
+    pub tokens_used: u64,
+    pub invocations: u64,
+    pub storage_bytes: u64,
+    pub limits: UsageLimits,
+    pub percent_used: UsagePercent,
+}
+
+/// Rollups are updated incrementally as runs start and invocations are recorded, and mirrored
+/// to Redis (`usage:{client_id}:{month}` hashes) so restarts don't reset them.
+pub struct UsageTracker {
+    rollups: DashMap<(String, String), UsageRollup>, // (client_id, month) -> counters
+    limits: UsageLimits,
+    redis_client: Option<redis::Client>,
+}
+
+impl UsageTracker {
+    pub fn new(redis_client: Option<redis::Client>) -> Self {
+        Self {
+            rollups: DashMap::new(),
+            limits: UsageLimits::from_env(),
+            redis_client,
+        }
+    }
+
+    pub fn record_run_started(&self, client_id: &str) {
+        self.bump(client_id, UsageRollup { runs_started: 1, ..Default::default() });
+    }
+
+    pub fn record_invocations(&self, client_id: &str, invocations: u64, tokens_used: u64) {
+        if invocations > 0 {
+            self.bump(client_id, UsageRollup { invocations, tokens_used, ..Default::default() });
+        }
+    }
+
+    fn bump(&self, client_id: &str, delta: UsageRollup) {
+        let month = current_month();
+        {
+            let mut rollup = self.rollups.entry((client_id.to_string(), month.clone())).or_default();
+            rollup.runs_started += delta.runs_started;
+            rollup.tokens_used += delta.tokens_used;
+            rollup.invocations += delta.invocations;
+        }
+
+        if let Some(client) = self.redis_client.clone() {
+            let key = format!("{}{}:{}", USAGE_KEY_PREFIX, client_id, month);
+            let client_label = observability::anonymize_client(client_id);
+            tokio::spawn(async move {
+                let result: redis::RedisResult<()> = async {
+                    let mut con = client.get_async_connection().await?;
+                    redis::pipe()
+                        .hincr(&key, "runs_started", delta.runs_started).ignore()
+                        .hincr(&key, "tokens_used", delta.tokens_used).ignore()
+                        .hincr(&key, "invocations", delta.invocations).ignore()
+                        .query_async(&mut con)
+                        .await
+                }.await;
+                if let Err(e) = result {
+                    tracing::warn!("Failed to persist usage rollup of {}: {}", client_label, e);
+                }
+            });
+        }
+    }
+
+    /// Restore persisted rollups at boot
+    pub async fn load_from_redis(&self) {
+        let Some(client) = &self.redis_client else { return };
+        let mut con = match client.get_async_connection().await {
+            Ok(con) => con,
+            Err(e) => {
+                tracing::warn!("Usage rollups not restored: {}", e);
+                return;
+            }
+        };
+
+        let keys = redis_keys::scan_keys(&mut con, &format!("{}*", USAGE_KEY_PREFIX)).await.unwrap_or_default();
+        for key in keys {
+            let Some((client_id, month)) = parse_usage_key(&key) else { continue };
+            let fields: HashMap<String, u64> = con.hgetall(&key).await.unwrap_or_default();
+            let field = |name: &str| fields.get(name).copied().unwrap_or(0);
+            self.rollups.insert((client_id, month), UsageRollup {
+                runs_started: field("runs_started"),
+                tokens_used: field("tokens_used"),
+                invocations: field("invocations"),
+            });
+        }
+        tracing::info!("Restored {} usage rollups", self.rollups.len());
+    }
+
+    /// Current-month report. runs_active and storage_bytes are supplied by the caller.
+    pub fn report(&self, client_id: &str, runs_active: usize, storage_bytes: u64) -> UsageReport {
+        let month = current_month();
+        let rollup = self.rollups.get(&(client_id.to_string(), month.clone()))
+            .map(|r| r.clone())
+            .unwrap_or_default();
+
+        let percent = |used: u64, limit: Option<u64>| limit.map(|l| used as f64 * 100.0 / l as f64);
+
+        UsageReport {
+            client_id: client_id.to_string(),
+            month,
+            runs_started: rollup.runs_started,
+            runs_active,
+            tokens_used: rollup.tokens_used,
+            invocations: rollup.invocations,
+            storage_bytes,
+            percent_used: UsagePercent {
+                runs: percent(rollup.runs_started, self.limits.monthly_runs),
+                tokens: percent(rollup.tokens_used, self.limits.monthly_tokens),
+                storage: percent(storage_bytes, self.limits.storage_bytes),
+            },
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+/// `usage:{client_id}:{month}` -> (client_id, month)
+fn parse_usage_key(key: &str) -> Option<(String, String)> {
+    let (client_id, month) = key.strip_prefix(USAGE_KEY_PREFIX)?.rsplit_once(':')?;
+    Some((client_id.to_string(), month.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollups_accumulate_per_client() {
+        let mut tracker = UsageTracker::new(None);
+        tracker.limits = UsageLimits { monthly_runs: None, monthly_tokens: Some(1_000), storage_bytes: Some(200) };
+
+        tracker.record_run_started("alice");
+        tracker.record_run_started("alice");
+        tracker.record_invocations("alice", 2, 250);
+        tracker.record_invocations("bob", 1, 900);
+
+        let report = tracker.report("alice", 1, 50);
+        assert_eq!((report.runs_started, report.invocations, report.tokens_used), (2, 2, 250));
+        assert_eq!(report.percent_used.tokens, Some(25.0));
+        assert_eq!(report.percent_used.storage, Some(25.0));
+        assert_eq!(report.percent_used.runs, None);
+
+        assert_eq!(tracker.report("carol", 0, 0).tokens_used, 0);
+        assert_eq!(parse_usage_key("usage:alice:2026-10"), Some(("alice".to_string(), "2026-10".to_string())));
+    }
+}
@@ -0,0 +1,102 @@
+// [[RARO]]/apps/kernel-server/src/verdict.rs
+// Purpose: Judge a completed run against its workflow's success_criteria: the terminal output
+//          against a JSON Schema, conditions over agent outputs, and required output files.
+// Architecture: Domain Helper Layer
+// Dependencies: raro-models, template, glob
+//
+// Pure functions of outputs and file names, so the dry-run endpoint evaluates sample outputs
+// exactly the way complete_run evaluates a real run.
+
+use crate::dag::DAG;
+use crate::models::{RunVerdict, SuccessCriteria};
+use crate::template;
+use serde_json::{Map, Value};
+
+/// Condition syntax problems, reported at registration rather than as a failed verdict
+pub fn condition_problems(criteria: &SuccessCriteria) -> Vec<String> {
+    criteria.conditions.iter()
+        .filter_map(|c| template::parse_condition(c).err().map(|e| e.to_string()))
+        .collect()
+}
+
+/// Agents whose output output_schema applies to: terminal_agent, or every agent of `dag`
+/// that no other agent depends on
+pub fn terminal_agents(criteria: &SuccessCriteria, dag: &DAG) -> Vec<String> {
+    if let Some(agent) = &criteria.terminal_agent {
+        return vec![agent.clone()];
+    }
+    let mut agents: Vec<String> = dag.export_nodes().into_iter()
+        .filter(|a| dag.get_children(a).is_empty())
+        .collect();
+    agents.sort();
+    agents
+}
+
+/// Every check runs; each failure contributes one or more reasons
+pub fn evaluate(
+    criteria: &SuccessCriteria,
+    outputs: &Map<String, Value>,
+    terminal_agents: &[String],
+    output_files: &[String],
+) -> RunVerdict {
+    let mut reasons = Vec::new();
+
+    if criteria.output_schema.is_some() {
+        for agent in terminal_agents {
+            match outputs.get(agent) {
+                Some(output) => reasons.extend(criteria.schema_violations(output).into_iter()
+                    .map(|v| format!("output of '{}' does not match output_schema: {}", agent, v))),
+                None => reasons.push(format!("terminal agent '{}' produced no output", agent)),
+            }
+        }
+    }
+
+    for condition in &criteria.conditions {
+        match template::evaluate_condition(condition, outputs) {
+            Ok(true) => {}
+            Ok(false) => reasons.push(format!("condition '{}' does not hold", condition)),
+            Err(e) => reasons.push(format!("condition '{}' could not be evaluated: {}", condition, e)),
+        }
+    }
+
+    for rule in &criteria.required_outputs {
+        let matched = glob::Pattern::new(rule).is_ok_and(|p| output_files.iter().any(|f| p.matches(f)));
+        if !matched {
+            reasons.push(format!("no output file matches '{}'", rule));
+        }
+    }
+
+    RunVerdict::from_reasons(reasons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_verdict_collects_every_failed_check() {
+        let criteria: SuccessCriteria = serde_json::from_value(json!({
+            "output_schema": { "type": "object", "required": ["summary"] },
+            "conditions": ["agents.review.output.score >= 0.8"],
+            "required_outputs": ["*.md"]
+        })).unwrap();
+        let terminal = vec!["writer".to_string()];
+
+        let mut outputs = Map::new();
+        outputs.insert("writer".to_string(), json!({ "summary": "done" }));
+        outputs.insert("review".to_string(), json!({ "score": 0.9 }));
+        let verdict = evaluate(&criteria, &outputs, &terminal, &["report.md".to_string()]);
+        assert_eq!(verdict, RunVerdict::Passed);
+
+        outputs.insert("writer".to_string(), json!({ "draft": "..." }));
+        outputs.insert("review".to_string(), json!({ "score": "0.5" }));
+        let RunVerdict::Failed { reasons } = evaluate(&criteria, &outputs, &terminal, &["notes.txt".to_string()]) else {
+            panic!("expected a failed verdict");
+        };
+        assert_eq!(reasons.len(), 3, "{:?}", reasons);
+        assert!(reasons[0].starts_with("output of 'writer' does not match output_schema"));
+        assert_eq!(reasons[1], "condition 'agents.review.output.score >= 0.8' does not hold");
+        assert_eq!(reasons[2], "no output file matches '*.md'");
+    }
+}
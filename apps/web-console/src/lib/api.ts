@@ -53,7 +53,7 @@ export interface WorkflowConfig {
 
 export interface AgentConfig {
   id: string;
-  role: 'orchestrator' | 'worker' | 'observer';
+  role: 'orchestrator' | 'worker' | 'observer' | 'supervisor';
   model: string;
   tools: string[];
   input_schema: any;
@@ -338,6 +338,14 @@ export async function promoteArtifactToLibrary(runId: string, filename: string):
     }
 }
 
+// Session outputs are served to the run's owner only, so they are fetched with the session
+// header rather than linked by URL
+export async function fetchSessionOutput(runId: string, filename: string): Promise<Blob> {
+    const res = await secureFetch(`${KERNEL_API}/runtime/${runId}/output/${filename}`);
+    if (!res.ok) throw new Error(`Failed to fetch session output ${filename}: ${res.status}`);
+    return res.blob();
+}
+
 export function getArtifactFileUrl(runId: string, filename: string): string {
     if (USE_MOCK) {
         // In mock mode, return data URL directly
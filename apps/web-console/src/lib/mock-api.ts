@@ -13,7 +13,13 @@ type SimulationStep = {
         status: string;
         active_agents: string[];
         completed_agents: string[];
-        failed_agents: string[];
+        failed_agents: Array<{
+            agent_id: string;
+            reason: string;
+            error_code: string;
+            failed_at: string;
+            attempts: number;
+        }>;
         total_tokens_used: number;
         invocations: Array<{
             id: string;
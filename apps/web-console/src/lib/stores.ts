@@ -37,7 +37,7 @@ export interface AgentNode {
   model: string;
   prompt: string;
   status: 'idle' | 'running' | 'complete' | 'failed';
-  role: 'orchestrator' | 'worker' | 'observer';
+  role: 'orchestrator' | 'worker' | 'observer' | 'supervisor';
   acceptsDirective: boolean;  // Can this node receive operator directives?
   allowDelegation: boolean;   // Can this node spawn sub-agents?
   tools: string[];            // Active tools provisioned to this agent
@@ -91,7 +91,7 @@ interface TopologyNodeData {
     label?: string;
     prompt?: string;
     model?: string;
-    role?: 'orchestrator' | 'worker' | 'observer';
+    role?: 'orchestrator' | 'worker' | 'observer' | 'supervisor';
     tools?: string[];
     accepts_directive?: boolean;
     allow_delegation?: boolean;
@@ -845,7 +845,7 @@ function syncState(state: any, signatures: Record<string, string> = {}, topology
             let status: 'idle' | 'running' | 'complete' | 'failed' = 'idle';
             if (state.active_agents.includes(n.id)) status = 'running';
             else if (state.completed_agents.includes(n.id)) status = 'complete';
-            else if (state.failed_agents.includes(n.id)) status = 'failed';
+            else if (state.failed_agents.some((f: any) => f.agent_id === n.id)) status = 'failed';
             return { ...n, status };
         });
     });
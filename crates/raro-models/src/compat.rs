@@ -0,0 +1,45 @@
+// [[RARO]]/crates/raro-models/src/compat.rs
+// Purpose: Conversions between the HashMap fields used by the models and BTreeMap.
+// Architecture: Shared Data Layer (interop helper)
+// Dependencies: alloc
+//
+// With `std` the model maps are std HashMaps; without it they are already BTreeMaps and
+// these conversions are identity moves. Callers that need ordered, deterministic output
+// (diffs, hashing, editor round-trips) can use the same calls in both builds.
+
+use alloc::collections::BTreeMap;
+use core::hash::Hash;
+use crate::HashMap;
+
+/// Key-ordered copy of a model map
+pub fn to_btree_map<K, V>(map: HashMap<K, V>) -> BTreeMap<K, V>
+where
+    K: Ord + Hash + Eq,
+{
+    map.into_iter().collect()
+}
+
+/// Back to the map type the models use in this build
+pub fn from_btree_map<K, V>(map: BTreeMap<K, V>) -> HashMap<K, V>
+where
+    K: Ord + Hash + Eq,
+{
+    map.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+
+    #[test]
+    fn test_round_trip_is_ordered_and_lossless() {
+        let mut env: HashMap<String, String> = HashMap::new();
+        env.insert("REGION".to_string(), "eu".to_string());
+        env.insert("ENV".to_string(), "prod".to_string());
+
+        let ordered = to_btree_map(env.clone());
+        assert_eq!(ordered.keys().collect::<alloc::vec::Vec<_>>(), ["ENV", "REGION"]);
+        assert_eq!(from_btree_map(ordered), env);
+    }
+}
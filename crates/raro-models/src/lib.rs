@@ -0,0 +1,2442 @@
+// [[RARO]]/crates/raro-models/src/lib.rs
+// Purpose: Core data models shared by the kernel and (no_std/WASM) clients.
+// Architecture: Shared Data Layer
+// Dependencies: Serde, serde_json (alloc), tracing (no default features)
+//
+// Builds without `std` (default-features = false): maps become BTreeMap-backed and
+// env-driven constructors are compiled out. See `compat` for map conversions.
+// The `schema` feature derives schemars::JsonSchema on the WorkflowConfig tree (cargo xtask schema).
+// `strict` reads the committed schema file instead, so it only needs `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod compat;
+#[cfg(feature = "std")]
+pub mod strict;
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use alloc::collections::VecDeque;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
+/// BTreeMap stand-in for HashMap when built without std (same API for the calls made here)
+#[cfg(not(feature = "std"))]
+pub type HashMap<K, V> = alloc::collections::BTreeMap<K, V>;
+#[cfg(not(feature = "std"))]
+pub type HashSet<T> = alloc::collections::BTreeSet<T>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")] // Serializes to "fast", "reasoning", etc.
+pub enum ModelVariant {
+    #[default]
+    Fast,       // Cheap, quick
+    Reasoning,  // Standard "Pro" level
+    Thinking,   // Deep think / o1-style
+    
+    // Allow an escape hatch for specific IDs if absolutely needed
+    #[serde(untagged)] 
+    Custom(String), 
+}
+
+impl ModelVariant {
+    /// Wire/config key for this variant ("fast", "reasoning", "thinking" or the custom id)
+    pub fn as_str(&self) -> &str {
+        match self {
+            ModelVariant::Fast => "fast",
+            ModelVariant::Reasoning => "reasoning",
+            ModelVariant::Thinking => "thinking",
+            ModelVariant::Custom(s) => s.as_str(),
+        }
+    }
+
+    /// What this variant can do. Custom ids are opaque, so they advertise nothing.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        match self {
+            ModelVariant::Fast => vec![Capability::ToolUse, Capability::LargeContext],
+            ModelVariant::Reasoning => vec![Capability::ToolUse, Capability::LargeContext, Capability::AdvancedReasoning],
+            ModelVariant::Thinking => vec![Capability::ToolUse, Capability::LargeContext, Capability::DeepThinking],
+            ModelVariant::Custom(_) => vec![],
+        }
+    }
+
+    /// Relative cost (1 = cheapest)
+    pub fn cost_rank(&self) -> u8 {
+        match self {
+            ModelVariant::Fast => 1,
+            ModelVariant::Thinking => 2,
+            ModelVariant::Reasoning => 3,
+            ModelVariant::Custom(_) => u8::MAX,
+        }
+    }
+
+    /// Approximate blended USD per million tokens, used for spend breakdowns.
+    /// Custom ids have no known price and report zero.
+    pub fn usd_per_million_tokens(&self) -> f64 {
+        match self {
+            ModelVariant::Fast => 0.50,
+            ModelVariant::Thinking => 4.00,
+            ModelVariant::Reasoning => 10.00,
+            ModelVariant::Custom(_) => 0.0,
+        }
+    }
+
+    /// USD per million prompt tokens served from context cache (billed at a quarter of the rate)
+    pub fn cached_usd_per_million_tokens(&self) -> f64 {
+        self.usd_per_million_tokens() / 4.0
+    }
+
+    /// Rough tokens consumed by one invocation beyond the prompt (output + reasoning)
+    pub fn typical_output_tokens(&self) -> usize {
+        match self {
+            ModelVariant::Fast => 2_000,
+            ModelVariant::Reasoning => 6_000,
+            ModelVariant::Thinking => 8_000,
+            ModelVariant::Custom(_) => 4_000,
+        }
+    }
+}
+
+// Hand-written: serde's untagged Custom variant has no derive equivalent
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for ModelVariant {
+    fn schema_name() -> String {
+        "ModelVariant".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{InstanceType, Metadata, SchemaObject};
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            metadata: Some(Box::new(Metadata {
+                description: Some("Model tier: \"fast\", \"reasoning\", \"thinking\", or a custom model id".to_string()),
+                examples: vec!["fast".into(), "reasoning".into(), "thinking".into()],
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Function/tool calling
+    ToolUse,
+    /// Extended thinking budget
+    DeepThinking,
+    /// Long context window
+    LargeContext,
+    /// Strongest multi-step reasoning
+    AdvancedReasoning,
+}
+
+/// Cheapest built-in variant that has every required capability
+pub fn select_cheapest_capable_model(required: &[Capability]) -> Option<ModelVariant> {
+    [ModelVariant::Fast, ModelVariant::Reasoning, ModelVariant::Thinking]
+        .into_iter()
+        .filter(|v| {
+            let caps = v.capabilities();
+            required.iter().all(|r| caps.contains(r))
+        })
+        .min_by_key(|v| v.cost_rank())
+}
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum AgentRole {
+    /// Plans and delegates work to other agents
+    #[serde(rename = "orchestrator")]
+    Orchestrator,
+    /// Executes a task
+    #[serde(rename = "worker")]
+    Worker,
+    /// Watches the run without producing task output
+    #[serde(rename = "observer")]
+    Observer,
+    /// Sees every other agent's output and may return a SupervisorDirective
+    #[serde(rename = "supervisor")]
+    Supervisor,
+}
+
+/// Configuration for a single agent node.
+/// Used in both static workflow definitions and dynamic delegations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AgentNodeConfig {
+    /// Unique within the workflow
+    pub id: String,
+    /// What the agent does in the graph
+    pub role: AgentRole,
+    /// May be omitted when WorkflowConfig::agent_defaults supplies one
+    #[serde(default)]
+    pub model: ModelVariant,
+    /// Tool names granted to the agent (identity-based grants are added at invocation)
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// JSON Schema the agent's input is expected to match
+    #[serde(default)]
+    pub input_schema: serde_json::Value,
+    /// JSON Schema the agent's output is expected to match
+    #[serde(default)]
+    pub output_schema: serde_json::Value,
+    /// Context caching policy passed to the agent service ("ephemeral" by default).
+    /// CROSS_RUN_CACHE_POLICY also lets later runs of the workflow reuse the agent's output.
+    #[serde(default = "default_cache_policy")]
+    pub cache_policy: String,
+    /// Parent agents, relative to the context (Workflow or Subgraph)
+    #[serde(default)]
+    pub depends_on: Vec<Dependency>,
+    /// System prompt (identity); supports {{KEY}} and {{agents.<id>.output.<path>}} placeholders
+    pub prompt: String,
+    /// Canvas position in the web console
+    pub position: Option<Position>,
+    /// Whether the agent accepts a runtime directive from the operator
+    #[serde(default)]
+    pub accepts_directive: bool,
+    /// Runtime task from the operator, sent as the user message
+    #[serde(default)]
+    pub user_directive: String,
+
+    /// May return a DelegationRequest that splices new agents into the graph
+    #[serde(default)]
+    pub allow_delegation: bool,
+
+    /// Declared needs; if `model` lacks any of them it is upgraded at intake
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<Capability>,
+
+    /// Values for {{KEY}} placeholders in prompt/directive (overrides global_environment)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub environment: HashMap<String, String>,
+    /// Extra auto-promotion globs for files this agent produces (added to the workflow's)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_promote: Vec<String>,
+
+    /// Expected tokens for one invocation; falls back to a prompt/model heuristic when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_tokens: Option<usize>,
+
+    /// Invocation timeout for this agent (overrides WorkflowConfig::timeout_per_agent_ms)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Sampling parameters (temperature, top_p, max_output_tokens, seed); unset means model default
+    #[serde(flatten)]
+    pub generation: GenerationParams,
+
+    /// Thinking level 1-10 (1k-10k token budget) on thinking-capable models; DEFAULT_THINKING_BUDGET when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_budget: Option<u8>,
+
+    /// How many of `depends_on` must complete before this agent is ready
+    #[serde(default, skip_serializing_if = "JoinPolicy::is_all")]
+    pub join_policy: JoinPolicy,
+
+    /// Minimum level the agent service logs at for this agent (service default when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+
+    /// Reporting group (e.g. "research", "review") for GET /runtime/:run_id/stages; never affects scheduling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+
+    /// Automatic retries of failed invocations (none when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+}
+
+/// How often a failed invocation is retried before the agent fails
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RetryPolicy {
+    /// Further attempts on the agent's own model (at most MAX_AGENT_RETRIES)
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Variant for one last attempt once the regular retries have failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<ModelVariant>,
+}
+
+/// What an edge hands the dependent agent from its parent
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum EdgePass {
+    /// Signature and output (plain string entries)
+    #[default]
+    Both,
+    /// Thought signature only
+    Signature,
+    /// Output only
+    Output,
+    /// Ordering only
+    None,
+}
+
+impl EdgePass {
+    pub fn includes_signature(self) -> bool {
+        matches!(self, EdgePass::Both | EdgePass::Signature)
+    }
+
+    pub fn includes_output(self) -> bool {
+        matches!(self, EdgePass::Both | EdgePass::Output)
+    }
+}
+
+/// One `depends_on` entry: either `"parent"` or `{ "agent": "parent", "pass": "output", "alias": "research" }`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "DependencyRepr", into = "DependencyRepr")]
+pub struct Dependency {
+    /// Parent agent id
+    pub agent: String,
+    /// What the edge hands over
+    pub pass: EdgePass,
+    /// Key the parent's output is passed under (defaults to the parent id)
+    pub alias: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+enum DependencyRepr {
+    /// Parent agent id; passes signature and output under the parent's id
+    Id(String),
+    /// Parent with an explicit edge
+    Spec {
+        /// Parent agent id
+        agent: String,
+        /// What the edge hands over
+        #[serde(default)]
+        pass: EdgePass,
+        /// Key the parent's output is passed under (defaults to the parent id)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alias: Option<String>,
+    },
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Dependency {
+    fn schema_name() -> String {
+        "Dependency".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        DependencyRepr::json_schema(gen)
+    }
+}
+
+impl From<DependencyRepr> for Dependency {
+    fn from(repr: DependencyRepr) -> Self {
+        match repr {
+            DependencyRepr::Id(agent) => Dependency::from(agent),
+            DependencyRepr::Spec { agent, pass, alias } => Dependency { agent, pass, alias },
+        }
+    }
+}
+
+impl From<Dependency> for DependencyRepr {
+    fn from(dep: Dependency) -> Self {
+        if dep.is_plain() {
+            DependencyRepr::Id(dep.agent)
+        } else {
+            DependencyRepr::Spec { agent: dep.agent, pass: dep.pass, alias: dep.alias }
+        }
+    }
+}
+
+impl From<String> for Dependency {
+    fn from(agent: String) -> Self {
+        Dependency { agent, pass: EdgePass::Both, alias: None }
+    }
+}
+
+impl From<&str> for Dependency {
+    fn from(agent: &str) -> Self {
+        Dependency::from(agent.to_string())
+    }
+}
+
+impl PartialEq<str> for Dependency {
+    fn eq(&self, other: &str) -> bool {
+        self.agent == other
+    }
+}
+
+impl Dependency {
+    pub fn as_str(&self) -> &str {
+        &self.agent
+    }
+
+    /// Behaves like a plain string entry
+    pub fn is_plain(&self) -> bool {
+        self.pass == EdgePass::Both && self.alias.is_none()
+    }
+
+    /// Key the parent's output appears under in input_data and the context appendix
+    pub fn output_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.agent)
+    }
+
+    /// Short edge label for topology exports, e.g. `output as research`; None for plain entries
+    pub fn label(&self) -> Option<String> {
+        if self.is_plain() {
+            return None;
+        }
+        let pass = match self.pass {
+            EdgePass::Both => "both",
+            EdgePass::Signature => "signature",
+            EdgePass::Output => "output",
+            EdgePass::None => "none",
+        };
+        Some(match &self.alias {
+            Some(alias) => format!("{} as {}", pass, alias),
+            None => pass.to_string(),
+        })
+    }
+}
+
+/// Join semantics over an agent's dependencies
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JoinPolicy {
+    /// Every dependency completed
+    #[default]
+    All,
+    /// At least one dependency completed
+    Any,
+    /// At least `n` dependencies completed
+    AtLeast {
+        /// Required completed dependencies (capped at the number of dependencies)
+        n: usize,
+    },
+}
+
+impl JoinPolicy {
+    pub fn is_all(&self) -> bool {
+        *self == JoinPolicy::All
+    }
+
+    /// Agents without dependencies are always satisfied
+    pub fn is_satisfied(&self, dependencies: &[String], completed: &[String]) -> bool {
+        let done = dependencies.iter().filter(|d| completed.contains(d)).count();
+        match self {
+            JoinPolicy::All => done == dependencies.len(),
+            JoinPolicy::Any => dependencies.is_empty() || done > 0,
+            JoinPolicy::AtLeast { n } => done >= (*n).min(dependencies.len()),
+        }
+    }
+}
+
+/// Generation parameters for one agent, also recorded on each invocation so runs can be compared
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GenerationParams {
+    /// Sampling temperature, 0-2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling mass, 0-1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// Cap on generated tokens per invocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<i64>,
+    /// Sampling seed (overrides WorkflowConfig::seed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl GenerationParams {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// First out-of-range value, if any
+    pub fn problem(&self) -> Option<String> {
+        if let Some(t) = self.temperature.filter(|t| !(0.0..=2.0).contains(t)) {
+            return Some(format!("temperature {} is outside [0, 2]", t));
+        }
+        if let Some(p) = self.top_p.filter(|p| !(0.0..=1.0).contains(p)) {
+            return Some(format!("top_p {} is outside [0, 1]", p));
+        }
+        if let Some(m) = self.max_output_tokens.filter(|m| *m < 0 || *m > u32::MAX as i64) {
+            return Some(format!("max_output_tokens {} must be between 0 and {}", m, u32::MAX));
+        }
+        None
+    }
+}
+
+impl AgentNodeConfig {
+    /// Parent agent ids in depends_on order
+    pub fn dependency_ids(&self) -> Vec<String> {
+        self.depends_on.iter().map(|d| d.agent.clone()).collect()
+    }
+
+    pub fn dependency(&self, parent_id: &str) -> Option<&Dependency> {
+        self.depends_on.iter().find(|d| d.agent == parent_id)
+    }
+
+    /// Token estimate used for feasibility checks: the declared value, else prompt size
+    /// (~4 chars per token) plus the model's typical output
+    pub fn token_estimate(&self) -> usize {
+        self.estimated_tokens.unwrap_or_else(|| {
+            (self.prompt.len() + self.user_directive.len()) / 4 + self.model.typical_output_tokens()
+        })
+    }
+
+    /// Swap `model` for the cheapest variant covering `requires` when the declared one falls short.
+    /// Returns an error if no built-in variant satisfies the requirements. A Custom model was
+    /// picked explicitly and advertises no capabilities, so it is trusted as declared.
+    pub fn assign_capable_model(&mut self) -> Result<(), ValidationError> {
+        if matches!(self.model, ModelVariant::Custom(_)) {
+            return Ok(());
+        }
+        let caps = self.model.capabilities();
+        if self.requires.iter().all(|r| caps.contains(r)) {
+            return Ok(());
+        }
+
+        let chosen = select_cheapest_capable_model(&self.requires)
+            .ok_or_else(|| ValidationError::NoCapableModel { agent_id: self.id.clone(), requires: self.requires.clone() })?;
+        tracing::info!("Agent {}: model {} -> {} to satisfy {:?}", self.id, self.model.as_str(), chosen.as_str(), self.requires);
+        self.model = chosen;
+        Ok(())
+    }
+
+    /// Messages for each way `output` fails the agent's output_schema (empty when it matches or none is set)
+    #[cfg(feature = "std")]
+    pub fn output_schema_violations(&self, output: &serde_json::Value) -> Vec<String> {
+        match &self.output_schema {
+            serde_json::Value::Null => Vec::new(),
+            schema => schema_violations(schema, output),
+        }
+    }
+}
+
+/// cache_policy under which identical inputs in a later run of the workflow reuse the stored output
+pub const CROSS_RUN_CACHE_POLICY: &str = "cross_run";
+
+fn default_cache_policy() -> String {
+    "ephemeral".to_string()
+}
+
+/// Canvas coordinates of an agent node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Position {
+    /// Horizontal position
+    pub x: f64,
+    /// Vertical position
+    pub y: f64,
+}
+
+/// A workflow submitted to POST /runtime/start
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WorkflowConfig {
+    /// Workflow id; runs of the same workflow share it
+    pub id: String,
+    /// Display name
+    pub name: String,
+    /// Agent nodes; edges come from each agent's depends_on
+    pub agents: Vec<AgentNodeConfig>,
+    /// Token budget for the whole run
+    pub max_token_budget: usize,
+    /// Overall run timeout
+    pub timeout_ms: u64,
+    /// Fallback invocation timeout for agents without their own timeout_ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_per_agent_ms: Option<u64>,
+    
+    // === RFS Integration ===
+    /// Library filenames to attach to this run's context
+    #[serde(default)]
+    pub attached_files: Vec<String>, 
+
+    // === Budget Enforcement ===
+    /// Fraction of max_token_budget at which a warning is raised (run keeps going)
+    #[serde(default = "default_budget_warning_threshold")]
+    pub budget_warning_threshold: f64,
+    /// Fraction of max_token_budget at which the run is terminated
+    #[serde(default = "default_budget_hard_limit")]
+    pub budget_hard_limit: f64,
+    /// Percentages of max_token_budget that each emit one TokenBudgetWarning event when first crossed
+    #[serde(default = "default_budget_warning_thresholds")]
+    pub budget_warning_thresholds: Vec<u8>,
+
+    // === Partial Execution ===
+    /// Only run these agents and their ancestors; everything else is out of scope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_agents: Option<Vec<String>>,
+
+    /// Workflow-wide {{KEY}} values; agent-level environment wins on conflict
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub global_environment: HashMap<String, String>,
+
+    /// Caller context copied onto the run state; never read by the runtime
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, serde_json::Value>,
+    /// Globs (e.g. "*.md", "report.*") of session outputs promoted automatically on AgentCompleted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_promote: Vec<String>,
+
+    /// Scheduling priority 0-9 (higher first); DEFAULT_RUN_PRIORITY when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+
+    /// Intended sampling seed for every agent without its own `seed`; recorded on the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    /// Who drives agent execution for runs of this workflow
+    #[serde(default, skip_serializing_if = "ExecutionMode::is_managed")]
+    pub execution_mode: ExecutionMode,
+
+    /// Fallback values for agents that leave the corresponding field unset
+    #[serde(default, skip_serializing_if = "AgentDefaults::is_empty")]
+    pub agent_defaults: AgentDefaults,
+
+    /// Run against the built-in fake executor instead of the agent service (managed mode only)
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub simulation: bool,
+    /// Synthetic per-agent latency for simulated runs; RARO_SIMULATION_DELAY_MS when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simulation_delay_ms: Option<u64>,
+
+    /// External callbacks at agent and run lifecycle points
+    #[serde(default, skip_serializing_if = "WorkflowHooks::is_empty")]
+    pub hooks: WorkflowHooks,
+
+    /// Style every agent id must follow; Unrestricted when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_naming_convention: Option<NamingConvention>,
+
+    /// What a completed run must satisfy to pass; no verdict is recorded when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_criteria: Option<SuccessCriteria>,
+
+    /// Failures to inject into managed execution (testing only; the kernel refuses them
+    /// unless started with RARO_FAULT_INJECTION=true)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub faults: Vec<FaultRule>,
+}
+
+/// Webhook URLs called at lifecycle events. `before_agent` receives the InvocationPayload and
+/// may answer with a modified payload (JSON body) or veto the invocation (403);
+/// `after_agent` receives the AgentInvocation; `on_complete` / `on_fail` the RunSummary.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WorkflowHooks {
+    /// Called with each InvocationPayload before dispatch; may modify or veto it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before_agent: Option<String>,
+    /// Called with each recorded AgentInvocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_agent: Option<String>,
+    /// Called with the RunSummary when the run completes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_complete: Option<String>,
+    /// Called with the RunSummary when the run fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_fail: Option<String>,
+}
+
+impl WorkflowHooks {
+    pub fn is_empty(&self) -> bool {
+        *self == WorkflowHooks::default()
+    }
+
+    /// (hook name, url) for every configured hook
+    pub fn configured(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("before_agent", &self.before_agent),
+            ("after_agent", &self.after_agent),
+            ("on_complete", &self.on_complete),
+            ("on_fail", &self.on_fail),
+        ]
+        .into_iter()
+        .filter_map(|(name, url)| url.as_deref().map(|u| (name, u)))
+        .collect()
+    }
+}
+
+/// Checked when a run completes; every failed check becomes a reason on a Failed verdict.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SuccessCriteria {
+    /// JSON Schema the terminal agent's output must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<serde_json::Value>,
+    /// Agent whose output is checked against output_schema; every agent without dependents when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_agent: Option<String>,
+    /// Expressions that must hold, e.g. `agents.reviewer.output.score >= 0.8`
+    /// (`agents.<id>.output[.<path>] [== != > >= < <= contains <JSON>]`; no operator means truthy)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<String>,
+    /// Globs that must each match at least one session output file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_outputs: Vec<String>,
+}
+
+/// Injected failure for one agent's invocations
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FaultRule {
+    /// Agent whose invocations fail
+    pub agent: String,
+    /// What happens to a covered attempt
+    pub fault: Fault,
+    /// Applies to this many attempts from the first (retries and escalations are attempts); every attempt when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+}
+
+impl FaultRule {
+    /// Whether the rule covers the agent's `attempt` (1-based)
+    pub fn applies_to(&self, agent_id: &str, attempt: u32) -> bool {
+        self.agent == agent_id && self.attempts.is_none_or(|n| attempt <= n)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Fault {
+    /// The agent service answers with this HTTP status (the attempt fails)
+    Error {
+        /// 400-599; 503 when unset
+        #[serde(default = "default_fault_status")]
+        status: u16,
+    },
+    /// The call is held back this long before it is sent (may trip the agent's timeout)
+    Delay {
+        /// Up to MAX_FAULT_DELAY_MS
+        ms: u64,
+    },
+    /// The call succeeds but its output is replaced, e.g. with one violating the agent's output_schema
+    InvalidOutput {
+        /// Output recorded instead of the agent's; `{"injected_fault": "invalid_output"}` when unset
+        #[serde(default = "default_invalid_output")]
+        output: serde_json::Value,
+    },
+}
+
+pub const MAX_FAULT_DELAY_MS: u64 = 10 * 60 * 1000;
+
+fn default_fault_status() -> u16 {
+    503
+}
+
+fn default_invalid_output() -> serde_json::Value {
+    serde_json::json!({ "injected_fault": "invalid_output" })
+}
+
+/// Outcome of a completed run against its workflow's success criteria
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum RunVerdict {
+    Passed,
+    Failed { reasons: Vec<String> },
+}
+
+impl RunVerdict {
+    pub fn from_reasons(reasons: Vec<String>) -> Self {
+        if reasons.is_empty() { RunVerdict::Passed } else { RunVerdict::Failed { reasons } }
+    }
+
+    pub fn passed(&self) -> bool {
+        matches!(self, RunVerdict::Passed)
+    }
+}
+
+/// Workflow-wide agent settings. A default only fills a field the agent left at its
+/// zero value (Fast model, "ephemeral" cache policy, no tools, no timeout, no log level),
+/// so anything set on the agent itself wins.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AgentDefaults {
+    /// Model for agents left on the default (fast)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<ModelVariant>,
+    /// Cache policy for agents left on "ephemeral"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_policy: Option<String>,
+    /// Tools for agents that declare none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
+    /// Invocation timeout for agents without their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Agent-service log level for agents without their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+}
+
+impl AgentDefaults {
+    pub fn is_empty(&self) -> bool {
+        *self == AgentDefaults::default()
+    }
+
+    pub fn apply_to(&self, agent: &mut AgentNodeConfig) {
+        if let Some(model) = self.model.as_ref().filter(|_| agent.model == ModelVariant::default()) {
+            agent.model = model.clone();
+        }
+        if let Some(policy) = self.cache_policy.as_ref().filter(|_| agent.cache_policy == default_cache_policy()) {
+            agent.cache_policy = policy.clone();
+        }
+        if let Some(tools) = self.tools.as_ref().filter(|_| agent.tools.is_empty()) {
+            agent.tools = tools.clone();
+        }
+        if agent.timeout_ms.is_none() {
+            agent.timeout_ms = self.timeout_ms;
+        }
+        if agent.log_level.is_none() {
+            agent.log_level = self.log_level.clone();
+        }
+    }
+}
+
+/// Managed: the kernel's execution loop calls the agent service.
+/// Pull: an external orchestrator polls ready_agents/invoke and reports invocations back.
+/// Push: the kernel POSTs each ready agent's payload to the orchestrator's webhook.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// The kernel calls the agent service itself
+    #[default]
+    Managed,
+    /// An orchestrator polls for ready agents and reports invocations back
+    Pull,
+    /// The kernel POSTs each ready agent's payload to the orchestrator
+    Push {
+        /// Orchestrator endpoint receiving InvocationPayloads
+        webhook_url: String,
+    },
+}
+
+impl ExecutionMode {
+    pub fn is_managed(&self) -> bool {
+        *self == ExecutionMode::Managed
+    }
+}
+
+/// Agent id style enforced by validate(). Every convention also gets the base rules: at most
+/// MAX_AGENT_ID_CHARS, no leading digit, and only ASCII letters, digits, `-` and `_`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum NamingConvention {
+    /// Letters and digits only, e.g. `researcher2`
+    Alphanumeric,
+    /// Lowercase words joined by single hyphens, e.g. `web-researcher`
+    KebabCase,
+    /// Lowercase words joined by single underscores, e.g. `web_researcher`
+    SnakeCase,
+    /// Base rules only
+    #[default]
+    Unrestricted,
+}
+
+impl NamingConvention {
+    /// Why `id` breaks the base rules or this convention, if it does
+    pub fn problem(&self, id: &str) -> Option<String> {
+        if id.is_empty() {
+            return Some("id is empty".to_string());
+        }
+        if id.chars().count() > MAX_AGENT_ID_CHARS {
+            return Some(format!("longer than {} characters", MAX_AGENT_ID_CHARS));
+        }
+        if id.starts_with(|c: char| c.is_ascii_digit()) {
+            return Some("starts with a digit".to_string());
+        }
+        if let Some(c) = id.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-' && *c != '_') {
+            return Some(format!("contains '{}'", c));
+        }
+
+        let lowercase_words = |sep: char| {
+            id.split(sep).all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()))
+        };
+        let follows = match self {
+            NamingConvention::Alphanumeric => id.chars().all(|c| c.is_ascii_alphanumeric()),
+            NamingConvention::KebabCase => lowercase_words('-'),
+            NamingConvention::SnakeCase => lowercase_words('_'),
+            NamingConvention::Unrestricted => true,
+        };
+        (!follows).then(|| format!("is not {}", self.label()))
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            NamingConvention::Alphanumeric => "alphanumeric",
+            NamingConvention::KebabCase => "kebab-case",
+            NamingConvention::SnakeCase => "snake_case",
+            NamingConvention::Unrestricted => "unrestricted",
+        }
+    }
+}
+
+pub const MAX_AGENT_ID_CHARS: usize = 64;
+pub const DEFAULT_THINKING_BUDGET: u8 = 5;
+pub const MAX_THINKING_BUDGET: u8 = 10;
+pub const MAX_AGENT_RETRIES: u32 = 5;
+pub const MAX_TOKEN_BUDGET: usize = 100_000_000;
+pub const MAX_AGENTS_PER_WORKFLOW: usize = 100;
+pub const MAX_EDGES_PER_WORKFLOW: usize = 1_000;
+pub const MAX_DEPENDENCIES_PER_AGENT: usize = 50;
+pub const MAX_PROMPT_CHARS: usize = 100_000;
+pub const MAX_CONFIG_BYTES: usize = 2 * 1024 * 1024;
+
+/// Size caps checked before any graph work, so oversized submissions fail fast
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowLimits {
+    pub max_agents: usize,
+    /// Sum of depends_on entries across all agents
+    pub max_edges: usize,
+    pub max_dependencies_per_agent: usize,
+    pub max_prompt_chars: usize,
+    /// Serialized size of the whole WorkflowConfig
+    pub max_config_bytes: usize,
+}
+
+impl Default for WorkflowLimits {
+    fn default() -> Self {
+        Self {
+            max_agents: MAX_AGENTS_PER_WORKFLOW,
+            max_edges: MAX_EDGES_PER_WORKFLOW,
+            max_dependencies_per_agent: MAX_DEPENDENCIES_PER_AGENT,
+            max_prompt_chars: MAX_PROMPT_CHARS,
+            max_config_bytes: MAX_CONFIG_BYTES,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl WorkflowLimits {
+    /// RARO_MAX_AGENTS, RARO_MAX_EDGES, RARO_MAX_DEPENDENCIES_PER_AGENT, RARO_MAX_PROMPT_CHARS,
+    /// RARO_MAX_CONFIG_BYTES (unset or 0 = built-in default)
+    pub fn from_env() -> Self {
+        let read = |key: &str, default: usize| {
+            std::env::var(key).ok().and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(default)
+        };
+        let defaults = Self::default();
+        Self {
+            max_agents: read("RARO_MAX_AGENTS", defaults.max_agents),
+            max_edges: read("RARO_MAX_EDGES", defaults.max_edges),
+            max_dependencies_per_agent: read("RARO_MAX_DEPENDENCIES_PER_AGENT", defaults.max_dependencies_per_agent),
+            max_prompt_chars: read("RARO_MAX_PROMPT_CHARS", defaults.max_prompt_chars),
+            max_config_bytes: read("RARO_MAX_CONFIG_BYTES", defaults.max_config_bytes),
+        }
+    }
+}
+pub const MAX_RUN_PRIORITY: u8 = 9;
+pub const DEFAULT_RUN_PRIORITY: u8 = 5;
+/// A non-zero budget must allow at least this many tokens per agent
+pub const MIN_TOKENS_PER_AGENT: usize = 1_000;
+
+/// Structural problem in a submitted WorkflowConfig. Serialized as {"code": ..., "details": ...}.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "code", content = "details", rename_all = "snake_case")]
+pub enum ValidationError {
+    DuplicateAgentId(String),
+    UnknownDependency { agent_id: String, dep_id: String },
+    InvalidTokenBudget(usize),
+    CycleDetected(Vec<String>),
+    InvalidSchema { agent_id: String, field: String, reason: String },
+    BudgetBelowMinimum,
+    AgentExceedsBudget { agent_id: String, estimated_tokens: usize, limit: usize },
+    EstimatedTotalExceedsBudget { estimated_tokens: usize, limit: usize },
+    InvalidGenerationParams { agent_id: String, reason: String },
+    InvalidPriority(u8),
+    InvalidJoinPolicy { agent_id: String, n: usize, dependencies: usize },
+    InvalidWebhookUrl(String),
+    InvalidHookUrl { hook: String, url: String },
+    TooManyAgents { observed: usize, max: usize },
+    TooManyEdges { observed: usize, max: usize },
+    TooManyDependencies { agent_id: String, observed: usize, max: usize },
+    PromptTooLong { agent_id: String, observed: usize, max: usize },
+    ConfigTooLarge { observed: usize, max: usize },
+    SimulationRequiresManagedMode,
+    DuplicateAlias { agent_id: String, alias: String },
+    InvalidBudgetThreshold(u8),
+    InvalidAgentId { agent_id: String, reason: String },
+    InvalidRetryPolicy { agent_id: String, reason: String },
+    /// A zero invocation timeout; agent_id is None for the workflow's timeout_per_agent_ms
+    ZeroAgentTimeout { agent_id: Option<String> },
+    InvalidSuccessCriteria(String),
+    InvalidFault { agent_id: String, reason: String },
+    /// No built-in model covers the agent's `requires`
+    NoCapableModel { agent_id: String, requires: Vec<Capability> },
+    TooManyEnvironmentKeys { agent_id: String, observed: usize, max: usize },
+    EnvironmentValueTooLong { agent_id: String, key: String, max: usize },
+    MetadataTooLarge { observed: usize, max: usize },
+    InvalidPromotionRule { rule: String, reason: String },
+    /// The workflow declares `faults` but the kernel doesn't accept them (checked by the kernel)
+    FaultInjectionDisabled,
+    /// The agent's model has no usable mapping in this deployment (checked by the kernel)
+    ModelUnavailable { agent_id: String, model: String, reason: String },
+    /// The submitting client's policy forbids the agent's model (checked by the kernel)
+    ModelNotAllowed { agent_id: String, model: String },
+    /// Reported by strict parsing only (see `strict`)
+    UnknownField(String),
+}
+
+// Hand-written rather than derived: thiserror needs std
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ValidationError::*;
+        match self {
+            DuplicateAgentId(id) => write!(f, "Duplicate agent id '{}'", id),
+            UnknownDependency { agent_id, dep_id } => write!(f, "Agent '{}' depends on unknown agent '{}'", agent_id, dep_id),
+            InvalidTokenBudget(budget) => write!(f, "Token budget {} exceeds the maximum of {}", budget, MAX_TOKEN_BUDGET),
+            CycleDetected(agents) => write!(f, "Dependency cycle between agents: {}", agents.join(", ")),
+            InvalidSchema { agent_id, field, reason } => write!(f, "Agent '{}' has an invalid {}: {}", agent_id, field, reason),
+            BudgetBelowMinimum => write!(f, "Token budget is below {} tokens per agent", MIN_TOKENS_PER_AGENT),
+            AgentExceedsBudget { agent_id, estimated_tokens, limit } =>
+                write!(f, "Agent '{}' is estimated at {} tokens, above the run limit of {}", agent_id, estimated_tokens, limit),
+            EstimatedTotalExceedsBudget { estimated_tokens, limit } =>
+                write!(f, "Agents in scope are estimated at {} tokens in total, above the run limit of {}", estimated_tokens, limit),
+            InvalidGenerationParams { agent_id, reason } => write!(f, "Agent '{}' has invalid generation parameters: {}", agent_id, reason),
+            InvalidPriority(priority) => write!(f, "Priority {} is outside 0-{}", priority, MAX_RUN_PRIORITY),
+            InvalidJoinPolicy { agent_id, n, dependencies } =>
+                write!(f, "Agent '{}' requires {} of {} dependencies to complete", agent_id, n, dependencies),
+            InvalidWebhookUrl(url) => write!(f, "Push execution needs an http(s) webhook_url, got '{}'", url),
+            InvalidHookUrl { hook, url } => write!(f, "Hook '{}' needs an http(s) URL, got '{}'", hook, url),
+            TooManyAgents { observed, max } => write!(f, "Workflow has {} agents (max {})", observed, max),
+            TooManyEdges { observed, max } => write!(f, "Workflow has {} dependency edges (max {})", observed, max),
+            TooManyDependencies { agent_id, observed, max } => write!(f, "Agent '{}' has {} dependencies (max {})", agent_id, observed, max),
+            PromptTooLong { agent_id, observed, max } => write!(f, "Agent '{}' prompt is {} chars (max {})", agent_id, observed, max),
+            ConfigTooLarge { observed, max } => write!(f, "Workflow config is {} bytes (max {})", observed, max),
+            SimulationRequiresManagedMode => write!(f, "Simulated runs require managed execution"),
+            DuplicateAlias { agent_id, alias } => write!(f, "Agent '{}' receives more than one parent output as '{}'", agent_id, alias),
+            InvalidBudgetThreshold(pct) => write!(f, "Budget warning threshold {}% is outside 1-100", pct),
+            InvalidAgentId { agent_id, reason } => write!(f, "Agent id '{}' {}", agent_id, reason),
+            InvalidRetryPolicy { agent_id, reason } => write!(f, "Agent '{}' has an invalid retry policy: {}", agent_id, reason),
+            ZeroAgentTimeout { agent_id: Some(agent_id) } => write!(f, "Agent '{}' has a timeout_ms of 0", agent_id),
+            ZeroAgentTimeout { agent_id: None } => write!(f, "timeout_per_agent_ms must be greater than 0"),
+            InvalidSuccessCriteria(reason) => write!(f, "Invalid success criteria: {}", reason),
+            InvalidFault { agent_id, reason } => write!(f, "Fault for agent '{}' is invalid: {}", agent_id, reason),
+            NoCapableModel { agent_id, requires } => write!(f, "No model satisfies capabilities {:?} for agent '{}'", requires, agent_id),
+            TooManyEnvironmentKeys { agent_id, observed, max } => write!(f, "Agent '{}' has {} environment keys (max {})", agent_id, observed, max),
+            EnvironmentValueTooLong { agent_id, key, max } => write!(f, "Environment value '{}' for agent '{}' exceeds {} chars", key, agent_id, max),
+            MetadataTooLarge { observed, max } => write!(f, "Run metadata is {} bytes serialized (max {})", observed, max),
+            InvalidPromotionRule { rule, reason } => write!(f, "Invalid auto_promote pattern '{}': {}", rule, reason),
+            FaultInjectionDisabled => write!(f, "faults are only accepted when RARO_FAULT_INJECTION=true"),
+            ModelUnavailable { agent_id, model, reason } => write!(f, "Agent '{}' uses model '{}', which is unavailable: {}", agent_id, model, reason),
+            ModelNotAllowed { agent_id, model } => write!(f, "Agent '{}' uses model '{}', which this client may not use", agent_id, model),
+            UnknownField(path) => write!(f, "Unknown field '{}'", path),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ValidationError {}
+
+pub const MAX_METADATA_BYTES: usize = 10 * 1024;
+
+pub const MAX_ENVIRONMENT_KEYS: usize = 50;
+pub const MAX_ENVIRONMENT_VALUE_CHARS: usize = 1000;
+
+impl WorkflowConfig {
+    /// Fill unset agent fields from agent_defaults. Idempotent; runs before validation.
+    pub fn apply_agent_defaults(&mut self) {
+        for agent in &mut self.agents {
+            self.agent_defaults.apply_to(agent);
+        }
+    }
+
+    /// Effective environment for one agent: global values overlaid with the agent's own
+    pub fn environment_for(&self, agent: &AgentNodeConfig) -> HashMap<String, String> {
+        let mut env = self.global_environment.clone();
+        env.extend(agent.environment.iter().map(|(k, v)| (k.clone(), v.clone())));
+        env
+    }
+
+    /// Enforce key-count and value-length limits on every agent's effective environment
+    pub fn validate_environment(&self) -> Result<(), ValidationError> {
+        for agent in &self.agents {
+            let env = self.environment_for(agent);
+            if env.len() > MAX_ENVIRONMENT_KEYS {
+                return Err(ValidationError::TooManyEnvironmentKeys { agent_id: agent.id.clone(), observed: env.len(), max: MAX_ENVIRONMENT_KEYS });
+            }
+            if let Some((key, _)) = env.iter().find(|(_, v)| v.chars().count() > MAX_ENVIRONMENT_VALUE_CHARS) {
+                return Err(ValidationError::EnvironmentValueTooLong { agent_id: agent.id.clone(), key: key.clone(), max: MAX_ENVIRONMENT_VALUE_CHARS });
+            }
+        }
+        Ok(())
+    }
+
+    /// Size caps. Cheap, and meant to run before validate() and DAG construction.
+    /// An oversized agent count is reported alone, without walking the agents.
+    pub fn check_limits(&self, limits: &WorkflowLimits) -> Result<(), Vec<ValidationError>> {
+        if self.agents.len() > limits.max_agents {
+            return Err(vec![ValidationError::TooManyAgents { observed: self.agents.len(), max: limits.max_agents }]);
+        }
+
+        let mut errors = Vec::new();
+        let edges: usize = self.agents.iter().map(|a| a.depends_on.len()).sum();
+        if edges > limits.max_edges {
+            errors.push(ValidationError::TooManyEdges { observed: edges, max: limits.max_edges });
+        }
+        for agent in &self.agents {
+            if agent.depends_on.len() > limits.max_dependencies_per_agent {
+                errors.push(ValidationError::TooManyDependencies {
+                    agent_id: agent.id.clone(),
+                    observed: agent.depends_on.len(),
+                    max: limits.max_dependencies_per_agent,
+                });
+            }
+            let prompt_chars = agent.prompt.chars().count();
+            if prompt_chars > limits.max_prompt_chars {
+                errors.push(ValidationError::PromptTooLong {
+                    agent_id: agent.id.clone(),
+                    observed: prompt_chars,
+                    max: limits.max_prompt_chars,
+                });
+            }
+        }
+        let config_bytes = serde_json::to_vec(self).map(|b| b.len()).unwrap_or(0);
+        if config_bytes > limits.max_config_bytes {
+            errors.push(ValidationError::ConfigTooLarge { observed: config_bytes, max: limits.max_config_bytes });
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Structural validation. Reports every problem found rather than stopping at the first.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        let mut ids = HashSet::new();
+        let convention = self.agent_naming_convention.unwrap_or_default();
+        for agent in &self.agents {
+            if !ids.insert(agent.id.as_str()) {
+                errors.push(ValidationError::DuplicateAgentId(agent.id.clone()));
+            }
+            if let Some(reason) = convention.problem(&agent.id) {
+                errors.push(ValidationError::InvalidAgentId { agent_id: agent.id.clone(), reason });
+            }
+        }
+
+        for agent in &self.agents {
+            for dep in agent.depends_on.iter().filter(|d| !ids.contains(d.as_str())) {
+                errors.push(ValidationError::UnknownDependency { agent_id: agent.id.clone(), dep_id: dep.agent.clone() });
+            }
+            let mut output_keys = HashSet::new();
+            for dep in agent.depends_on.iter().filter(|d| d.pass.includes_output()) {
+                if !output_keys.insert(dep.output_key()) {
+                    errors.push(ValidationError::DuplicateAlias { agent_id: agent.id.clone(), alias: dep.output_key().to_string() });
+                }
+            }
+            for (field, schema) in [("input_schema", &agent.input_schema), ("output_schema", &agent.output_schema)] {
+                if let Some(reason) = schema_problem(schema) {
+                    errors.push(ValidationError::InvalidSchema { agent_id: agent.id.clone(), field: field.to_string(), reason });
+                }
+            }
+            if let Some(reason) = agent.generation.problem() {
+                errors.push(ValidationError::InvalidGenerationParams { agent_id: agent.id.clone(), reason });
+            }
+            if let Some(level) = agent.thinking_budget.filter(|l| !(1..=MAX_THINKING_BUDGET).contains(l)) {
+                errors.push(ValidationError::InvalidGenerationParams {
+                    agent_id: agent.id.clone(),
+                    reason: format!("thinking_budget {} is outside 1-{}", level, MAX_THINKING_BUDGET),
+                });
+            }
+            if let JoinPolicy::AtLeast { n } = agent.join_policy {
+                if n == 0 || n > agent.depends_on.len() {
+                    errors.push(ValidationError::InvalidJoinPolicy { agent_id: agent.id.clone(), n, dependencies: agent.depends_on.len() });
+                }
+            }
+            if let Some(retry) = &agent.retry {
+                let problem = if retry.max_retries > MAX_AGENT_RETRIES {
+                    Some(format!("max_retries {} is above {}", retry.max_retries, MAX_AGENT_RETRIES))
+                } else if retry.escalation.as_ref() == Some(&agent.model) {
+                    Some(format!("escalation repeats the agent's own model '{}'", agent.model.as_str()))
+                } else {
+                    None
+                };
+                if let Some(reason) = problem {
+                    errors.push(ValidationError::InvalidRetryPolicy { agent_id: agent.id.clone(), reason });
+                }
+            }
+            // A zero timeout would fail every call before it is sent
+            if agent.timeout_ms == Some(0) {
+                errors.push(ValidationError::ZeroAgentTimeout { agent_id: Some(agent.id.clone()) });
+            }
+        }
+        if self.timeout_per_agent_ms == Some(0) {
+            errors.push(ValidationError::ZeroAgentTimeout { agent_id: None });
+        }
+
+        // Zero means unlimited
+        if self.max_token_budget > MAX_TOKEN_BUDGET {
+            errors.push(ValidationError::InvalidTokenBudget(self.max_token_budget));
+        } else if self.max_token_budget > 0 && self.max_token_budget < self.agents.len() * MIN_TOKENS_PER_AGENT {
+            errors.push(ValidationError::BudgetBelowMinimum);
+        }
+
+        if let Some(priority) = self.priority.filter(|p| *p > MAX_RUN_PRIORITY) {
+            errors.push(ValidationError::InvalidPriority(priority));
+        }
+        for pct in self.budget_warning_thresholds.iter().filter(|p| !(1..=100).contains(*p)) {
+            errors.push(ValidationError::InvalidBudgetThreshold(*pct));
+        }
+        if let ExecutionMode::Push { webhook_url } = &self.execution_mode {
+            if !webhook_url.starts_with("http://") && !webhook_url.starts_with("https://") {
+                errors.push(ValidationError::InvalidWebhookUrl(webhook_url.clone()));
+            }
+        }
+        for (hook, url) in self.hooks.configured() {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                errors.push(ValidationError::InvalidHookUrl { hook: hook.to_string(), url: url.to_string() });
+            }
+        }
+        if self.simulation && !self.execution_mode.is_managed() {
+            errors.push(ValidationError::SimulationRequiresManagedMode);
+        }
+        if let Some(criteria) = &self.success_criteria {
+            errors.extend(criteria.problems(&ids).into_iter().map(ValidationError::InvalidSuccessCriteria));
+        }
+        for rule in &self.faults {
+            let problem = match &rule.fault {
+                _ if !ids.contains(rule.agent.as_str()) => Some("no such agent".to_string()),
+                _ if rule.attempts == Some(0) => Some("attempts must be at least 1".to_string()),
+                Fault::Error { status } if !(400..=599).contains(status) => Some(format!("status {} is not an HTTP error", status)),
+                Fault::Delay { ms } if *ms > MAX_FAULT_DELAY_MS => Some(format!("delay {}ms is above {}ms", ms, MAX_FAULT_DELAY_MS)),
+                _ => None,
+            };
+            if let Some(reason) = problem {
+                errors.push(ValidationError::InvalidFault { agent_id: rule.agent.clone(), reason });
+            }
+        }
+
+        let cyclic = self.cyclic_agents();
+        if !cyclic.is_empty() {
+            errors.push(ValidationError::CycleDetected(cyclic));
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Invocation timeout for an agent: its own timeout_ms, else the workflow-wide fallback
+    pub fn agent_timeout(&self, agent_id: &str) -> Option<core::time::Duration> {
+        self.agents.iter()
+            .find(|a| a.id == agent_id)
+            .and_then(|a| a.timeout_ms)
+            .or(self.timeout_per_agent_ms)
+            .map(core::time::Duration::from_millis)
+    }
+
+    /// Can the run finish within its hard token limit, given per-agent estimates?
+    /// Only agents in scope (see target_agents) are counted. Zero budget is unlimited.
+    pub fn check_feasibility(&self) -> Result<(), Vec<ValidationError>> {
+        if self.max_token_budget == 0 {
+            return Ok(());
+        }
+        let limit = (self.max_token_budget as f64 * self.budget_hard_limit) as usize;
+
+        let mut errors = Vec::new();
+        let mut total = 0;
+        for agent in self.agents_in_scope() {
+            let estimated_tokens = agent.token_estimate();
+            total += estimated_tokens;
+            if estimated_tokens > limit {
+                errors.push(ValidationError::AgentExceedsBudget { agent_id: agent.id.clone(), estimated_tokens, limit });
+            }
+        }
+
+        // A single oversized agent already explains the total
+        if errors.is_empty() && total > limit {
+            errors.push(ValidationError::EstimatedTotalExceedsBudget { estimated_tokens: total, limit });
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Agents that will run: all of them, or the targets plus their transitive dependencies
+    pub fn agents_in_scope(&self) -> Vec<&AgentNodeConfig> {
+        let Some(targets) = self.target_agents.as_ref().filter(|t| !t.is_empty()) else {
+            return self.agents.iter().collect();
+        };
+
+        let mut scope: HashSet<&str> = HashSet::new();
+        let mut queue: VecDeque<&str> = targets.iter().map(|t| t.as_str()).collect();
+        while let Some(id) = queue.pop_front() {
+            if !scope.insert(id) {
+                continue;
+            }
+            if let Some(agent) = self.agents.iter().find(|a| a.id == id) {
+                queue.extend(agent.depends_on.iter().map(|d| d.as_str()));
+            }
+        }
+
+        self.agents.iter().filter(|a| scope.contains(a.id.as_str())).collect()
+    }
+
+    /// Agents left over after Kahn's algorithm: members of a cycle or downstream of one (sorted)
+    fn cyclic_agents(&self) -> Vec<String> {
+        let known: HashSet<&str> = self.agents.iter().map(|a| a.id.as_str()).collect();
+        let mut in_degree: HashMap<&str, usize> = HashMap::new();
+        for agent in &self.agents {
+            let deps = agent.depends_on.iter().filter(|d| known.contains(d.as_str())).count();
+            *in_degree.entry(agent.id.as_str()).or_default() += deps;
+        }
+
+        let mut queue: VecDeque<&str> = in_degree.iter().filter(|(_, &d)| d == 0).map(|(id, _)| *id).collect();
+        while let Some(done) = queue.pop_front() {
+            for agent in self.agents.iter().filter(|a| a.depends_on.iter().any(|d| d == done)) {
+                if let Some(d) = in_degree.get_mut(agent.id.as_str()) {
+                    *d -= 1;
+                    if *d == 0 {
+                        queue.push_back(agent.id.as_str());
+                    }
+                }
+            }
+        }
+
+        let mut remaining: Vec<String> = in_degree.into_iter().filter(|(_, d)| *d > 0).map(|(id, _)| id.to_string()).collect();
+        remaining.sort();
+        remaining
+    }
+
+    /// Effective auto-promotion globs for one agent
+    pub fn promotion_rules_for(&self, agent: &AgentNodeConfig) -> Vec<String> {
+        let mut rules = self.auto_promote.clone();
+        rules.extend(agent.auto_promote.iter().filter(|r| !rules.contains(r)).cloned().collect::<Vec<_>>());
+        rules
+    }
+
+    /// Every auto_promote glob must compile
+    #[cfg(feature = "std")]
+    pub fn validate_promotion_rules(&self) -> Result<(), ValidationError> {
+        let agent_rules = self.agents.iter().flat_map(|a| a.auto_promote.iter());
+        for rule in self.auto_promote.iter().chain(agent_rules) {
+            glob::Pattern::new(rule)
+                .map_err(|e| ValidationError::InvalidPromotionRule { rule: rule.clone(), reason: e.to_string() })?;
+        }
+        Ok(())
+    }
+
+    pub fn validate_metadata(&self) -> Result<(), ValidationError> {
+        let size = serde_json::to_vec(&self.metadata).map(|v| v.len()).unwrap_or(usize::MAX);
+        if size > MAX_METADATA_BYTES {
+            return Err(ValidationError::MetadataTooLarge { observed: size, max: MAX_METADATA_BYTES });
+        }
+        Ok(())
+    }
+}
+
+fn default_budget_warning_threshold() -> f64 {
+    0.9
+}
+
+fn default_budget_hard_limit() -> f64 {
+    1.0
+}
+
+impl SuccessCriteria {
+    /// Schema and glob problems, plus a terminal_agent that isn't in the workflow.
+    /// Condition syntax is checked by the kernel, which evaluates them.
+    fn problems(&self, agent_ids: &HashSet<&str>) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(reason) = self.output_schema.as_ref().and_then(schema_problem) {
+            problems.push(format!("output_schema: {}", reason));
+        }
+        if let Some(agent) = self.terminal_agent.as_deref().filter(|a| !agent_ids.contains(a)) {
+            problems.push(format!("terminal_agent '{}' is not in the workflow", agent));
+        }
+        #[cfg(feature = "std")]
+        for rule in &self.required_outputs {
+            if let Err(e) = glob::Pattern::new(rule) {
+                problems.push(format!("required_outputs pattern '{}': {}", rule, e));
+            }
+        }
+        problems
+    }
+
+    /// Messages for each way `output` fails output_schema (empty when it matches or none is set)
+    #[cfg(feature = "std")]
+    pub fn schema_violations(&self, output: &serde_json::Value) -> Vec<String> {
+        self.output_schema.as_ref().map(|schema| schema_violations(schema, output)).unwrap_or_default()
+    }
+}
+
+/// Messages for each way `output` fails `schema`
+#[cfg(feature = "std")]
+fn schema_violations(schema: &serde_json::Value, output: &serde_json::Value) -> Vec<String> {
+    match jsonschema::validator_for(schema) {
+        Ok(validator) => validator.iter_errors(output).map(|e| match e.instance_path.to_string() {
+            path if path.is_empty() => e.to_string(),
+            path => format!("{} (at {})", e, path),
+        }).collect(),
+        Err(e) => vec![format!("output_schema does not compile: {}", e)],
+    }
+}
+
+fn default_budget_warning_thresholds() -> Vec<u8> {
+    vec![50, 80]
+}
+
+/// Schemas are optional (null) but must otherwise be JSON Schema objects
+fn schema_problem(schema: &serde_json::Value) -> Option<String> {
+    match schema {
+        serde_json::Value::Null => None,
+        serde_json::Value::Object(map) => compile_problem(schema, map),
+        _ => Some("schema must be a JSON object".to_string()),
+    }
+}
+
+/// Compiling checks the schema against its meta-schema, so authoring mistakes such as
+/// `"type": "strign"` surface here rather than when an agent's output is validated
+#[cfg(feature = "std")]
+fn compile_problem(schema: &serde_json::Value, _: &serde_json::Map<String, serde_json::Value>) -> Option<String> {
+    jsonschema::validator_for(schema).err().map(|e| match e.instance_path.to_string() {
+        path if path.is_empty() => e.to_string(),
+        path => format!("{} (at {})", e, path),
+    })
+}
+
+/// Without std there is no schema compiler; only the shape of `type` is checked
+#[cfg(not(feature = "std"))]
+fn compile_problem(_: &serde_json::Value, map: &serde_json::Map<String, serde_json::Value>) -> Option<String> {
+    match map.get("type") {
+        Some(t) if !t.is_string() && !t.is_array() => Some("'type' must be a string or array".to_string()),
+        _ => None,
+    }
+}
+
+// === NEW: DYNAMIC GRAPH STRUCTURES ===
+
+/// Instruction a Supervisor agent returns as its output to govern another agent
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SupervisorDirective {
+    pub action: SupervisorAction,
+    pub target_agent_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SupervisorAction {
+    /// Skip a pending agent (and its pending dependents)
+    CancelAgent,
+    /// Run a finished agent again
+    RetryAgent,
+    /// Replace the agent's prompt for its next execution
+    ModifyPrompt { new_prompt: String },
+}
+
+impl SupervisorDirective {
+    /// The directive carried by an agent output: either the output object itself or
+    /// JSON text in its `result` field (optionally in a ```json fence)
+    pub fn from_output(output: &serde_json::Value) -> Option<Self> {
+        if let Ok(directive) = serde_json::from_value(output.clone()) {
+            return Some(directive);
+        }
+
+        let text = output.get("result")?.as_str()?.trim();
+        let text = text.strip_prefix("```json").or_else(|| text.strip_prefix("```")).unwrap_or(text);
+        let text = text.strip_suffix("```").unwrap_or(text);
+        serde_json::from_str(text.trim()).ok()
+    }
+}
+
+/// A request from an active agent to spawn new sub-agents.
+/// This supports Flow B (Recursive Fork).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelegationRequest {
+    /// The intent/reason for this delegation (for logging/patterns)
+    pub reason: String,
+
+    /// The new nodes to inject into the graph
+    pub new_nodes: Vec<AgentNodeConfig>,
+
+    /// How these nodes relate to the delegating agent.
+    /// Default: "child" (Parent -> New Nodes -> Original Children)
+    #[serde(default = "default_strategy")]
+    pub strategy: DelegationStrategy,
+
+    /// [NEW] Optional list of pending node IDs to remove from the graph
+    #[serde(default)]
+    pub prune_nodes: Vec<String>,
+}
+
+fn default_strategy() -> DelegationStrategy {
+    DelegationStrategy::Child
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DelegationStrategy {
+    /// New nodes become children of the current node. 
+    /// Current node's original children are re-parented to these new nodes.
+    Child,
+    /// New nodes are siblings (parallel execution), not blocking dependent flow.
+    Sibling,
+}
+
+/// The standardized response from the Remote Agent Service.
+/// Moved here from runtime.rs to centralize the contract.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteAgentResponse {
+    pub agent_id: String,
+    pub success: bool,
+    pub output: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub tokens_used: usize,
+    pub thought_signature: Option<String>,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    pub cache_hit: bool,
+    pub latency_ms: f64,
+    pub cached_content_id: Option<String>,
+    /// Share of input_tokens served from context cache
+    #[serde(default)]
+    pub cached_tokens: usize,
+    #[serde(default)]
+    pub thinking_tokens: usize,
+
+    // [[NEW]] List of tools actually executed by the Python service
+    #[serde(default)]
+    pub executed_tools: Vec<String>,
+
+    // === NEW: The payload for dynamic graph changes ===
+    pub delegation: Option<DelegationRequest>,
+}
+
+impl RemoteAgentResponse {
+    pub fn token_split(&self) -> TokenSplit {
+        TokenSplit {
+            prompt_tokens: Some(self.input_tokens),
+            completion_tokens: Some(self.output_tokens),
+            cached_tokens: Some(self.cached_tokens),
+            thinking_tokens: Some(self.thinking_tokens),
+        }
+    }
+}
+
+/// How an invocation's tokens divide up, as the model API reports them. Every part is optional
+/// so reporters that only know a total, and invocations recorded before the split, still parse.
+/// `cached_tokens` is the part of `prompt_tokens` served from context cache, not an addition.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TokenSplit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_tokens: Option<usize>,
+}
+
+impl TokenSplit {
+    pub fn is_reported(&self) -> bool {
+        *self != TokenSplit::default()
+    }
+
+    /// prompt + completion + thinking (cached tokens are already counted in the prompt)
+    pub fn total(&self) -> usize {
+        [self.prompt_tokens, self.completion_tokens, self.thinking_tokens].into_iter().flatten().sum()
+    }
+}
+
+// === RUNTIME STATE ===
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentInvocation {
+    pub id: String,
+    pub agent_id: String,
+    pub model_variant: ModelVariant,
+    pub thought_signature: Option<String>,
+    pub tools_used: Vec<String>,
+    pub tokens_used: usize,
+    pub latency_ms: u64,
+    pub status: InvocationStatus,
+    /// When the invocation began (RFC 3339). Filled from timestamp - latency_ms when a
+    /// reporter leaves it out.
+    #[serde(default)]
+    pub started_at: String,
+    /// When the invocation finished (or, while Running, was last reported)
+    pub timestamp: String,
+    pub artifact_id: Option<String>,
+    pub error_message: Option<String>, 
+    /// Set when this invocation re-executed a frozen payload from an earlier invocation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_of: Option<String>,
+    /// Effective generation parameters sent with this invocation
+    #[serde(default, skip_serializing_if = "GenerationParams::is_empty")]
+    pub generation: GenerationParams,
+    /// Breakdown of tokens_used, when the reporter provides one
+    #[serde(flatten)]
+    pub token_split: TokenSplit,
+    /// Set on the last-chance attempt of a retry policy's escalation: the agent's own variant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalated_from: Option<ModelVariant>,
+}
+
+impl AgentInvocation {
+    /// Make tokens_used the total of the split, when it has prompt or completion counts: a
+    /// split of only cached or thinking tokens is partial and leaves the reported total alone
+    pub fn reconcile_tokens(&mut self) {
+        if self.token_split.prompt_tokens.is_some() || self.token_split.completion_tokens.is_some() {
+            self.tokens_used = self.token_split.total();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InvocationStatus {
+    Pending,
+    Running,
+    Success,
+    Failed,
+    Paused, // Added for Human-in-the-Loop or Delegation pauses
+    /// Skipped by an operator; counts as done for dependents, with an empty output
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeState {
+    pub run_id: String,
+    pub workflow_id: String,
+    pub client_id: String,
+    pub status: RuntimeStatus,
+    pub active_agents: Vec<String>,
+    pub completed_agents: Vec<String>,
+    pub failed_agents: Vec<FailedAgent>,
+    /// Agents that can no longer run because an ancestor failed
+    #[serde(default)]
+    pub blocked_agents: Vec<BlockedAgent>,
+    pub invocations: Vec<AgentInvocation>,
+    pub total_tokens_used: usize,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    /// Set once total_tokens_used crosses the workflow's soft budget threshold
+    #[serde(default)]
+    pub budget_warning: bool,
+    /// Agents pruned from the DAG by target_agents (never executed)
+    #[serde(default)]
+    pub skipped_agents: Vec<String>,
+    /// Caller-supplied context, echoed verbatim
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+    /// Reviewer decisions on approval pauses, oldest first
+    #[serde(default)]
+    pub approval_history: Vec<ApprovalRecord>,
+    /// 0-9, higher is dispatched first (see RARORuntime::dispatch_queue)
+    #[serde(default = "default_run_priority")]
+    pub priority: u8,
+    /// WorkflowConfig::seed at start, for comparing and reproducing runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Executed by the fake executor; excluded from usage and cost reporting
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub simulation: bool,
+    /// When each agent in active_agents was last marked Running (RFC 3339)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub active_since: HashMap<String, String>,
+    /// Set by the stall detector when active agents stop reporting; cleared by the next invocation
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub stalled: bool,
+    /// Library files attached while Preparing, on top of the workflow's attached_files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attached_files: Vec<String>,
+    /// While Preparing: when the run is cancelled unless launched (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prepare_deadline: Option<String>,
+    /// Set on completion when the workflow has success_criteria
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<RunVerdict>,
+}
+
+fn default_run_priority() -> u8 {
+    DEFAULT_RUN_PRIORITY
+}
+
+/// Reviewer decision on a run paused for approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackPayload {
+    pub approved: bool,
+    pub reviewer_id: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApprovalRecord {
+    pub approved: bool,
+    pub reviewer_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub decided_at: String,
+}
+
+impl RuntimeState {
+    pub fn has_failed(&self, agent_id: &str) -> bool {
+        self.failed_agents.iter().any(|f| f.agent_id == agent_id)
+    }
+
+    /// Add an agent to active_agents (if absent) and stamp when it started running
+    pub fn mark_active(&mut self, agent_id: &str, now: &str) {
+        if !self.active_agents.iter().any(|a| a == agent_id) {
+            self.active_agents.push(agent_id.to_string());
+        }
+        let active = &self.active_agents;
+        self.active_since.retain(|a, _| active.contains(a));
+        self.active_since.insert(agent_id.to_string(), now.to_string());
+    }
+
+    /// Mark an agent failed. Attempts are counted from the invocations recorded for it,
+    /// so callers should push the failing invocation first.
+    pub fn record_failure(&mut self, agent_id: &str, error_code: FailureCode, reason: &str, failed_at: &str) -> FailedAgent {
+        let attempts = self.invocations.iter().filter(|i| i.agent_id == agent_id).count().max(1) as u32;
+        let failure = FailedAgent {
+            agent_id: agent_id.to_string(),
+            reason: reason.to_string(),
+            error_code,
+            failed_at: failed_at.to_string(),
+            attempts,
+        };
+
+        self.active_agents.retain(|a| a != agent_id);
+        self.failed_agents.retain(|f| f.agent_id != agent_id);
+        self.failed_agents.push(failure.clone());
+        failure
+    }
+
+    /// Record `downstream` as blocked by `failed_id`. Agents that already ran are left alone.
+    pub fn mark_blocked(&mut self, failed_id: &str, downstream: impl IntoIterator<Item = String>) {
+        for agent_id in downstream {
+            if self.completed_agents.contains(&agent_id) || self.active_agents.contains(&agent_id) || self.has_failed(&agent_id) {
+                continue;
+            }
+            match self.blocked_agents.iter_mut().find(|b| b.agent_id == agent_id) {
+                Some(blocked) if !blocked.blocked_by.iter().any(|f| f == failed_id) => blocked.blocked_by.push(failed_id.to_string()),
+                Some(_) => {}
+                None => self.blocked_agents.push(BlockedAgent { agent_id, blocked_by: vec![failed_id.to_string()] }),
+            }
+        }
+    }
+}
+
+/// Why an agent (or the run on its behalf) failed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCode {
+    /// The agent returned an execution error
+    AgentError,
+    /// The agent service could not be reached
+    ServiceUnavailable,
+    /// Payload preparation failed before invocation
+    PreparationError,
+    DelegationError,
+    DagCycle,
+    BudgetExceeded,
+    OperatorStop,
+    PatternInterrupt,
+    /// The invocation exceeded its per-agent timeout
+    Timeout,
+    /// A reviewer rejected the run while it awaited approval
+    ApprovalRejected,
+    /// A before_agent hook vetoed the invocation
+    HookVetoed,
+    /// The run was not launched before its prepare deadline
+    PrepareTimeout,
+    /// Migrated from the legacy bare-id format
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "FailedAgentRepr")]
+pub struct FailedAgent {
+    pub agent_id: String,
+    pub reason: String,
+    pub error_code: FailureCode,
+    pub failed_at: String,
+    pub attempts: u32,
+}
+
+/// Accepts both the current object form and the legacy bare agent id
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FailedAgentRepr {
+    Legacy(String),
+    Full {
+        agent_id: String,
+        #[serde(default)]
+        reason: String,
+        #[serde(default)]
+        error_code: FailureCode,
+        #[serde(default)]
+        failed_at: String,
+        #[serde(default)]
+        attempts: u32,
+    },
+}
+
+impl From<FailedAgentRepr> for FailedAgent {
+    fn from(repr: FailedAgentRepr) -> Self {
+        match repr {
+            FailedAgentRepr::Legacy(agent_id) => FailedAgent {
+                agent_id,
+                reason: String::new(),
+                error_code: FailureCode::Unknown,
+                failed_at: String::new(),
+                attempts: 0,
+            },
+            FailedAgentRepr::Full { agent_id, reason, error_code, failed_at, attempts } => {
+                FailedAgent { agent_id, reason, error_code, failed_at, attempts }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockedAgent {
+    pub agent_id: String,
+    /// Failed ancestors that prevent this agent from running
+    pub blocked_by: Vec<String>,
+}
+
+/// Lightweight listing entry for GET /runtime/runs
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub run_id: String,
+    pub workflow_id: String,
+    pub status: RuntimeStatus,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub total_tokens_used: usize,
+    pub metadata: HashMap<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    pub simulation: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "core::ops::Not::not")]
+    pub stalled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<RunVerdict>,
+}
+
+impl From<&RuntimeState> for RunSummary {
+    fn from(state: &RuntimeState) -> Self {
+        Self {
+            run_id: state.run_id.clone(),
+            workflow_id: state.workflow_id.clone(),
+            status: state.status.clone(),
+            start_time: state.start_time.clone(),
+            end_time: state.end_time.clone(),
+            total_tokens_used: state.total_tokens_used,
+            metadata: state.metadata.clone(),
+            simulation: state.simulation,
+            seed: state.seed,
+            stalled: state.stalled,
+            verdict: state.verdict.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeStatus {
+    Idle,
+    /// Created by the two-phase start; inputs are being attached and nothing runs until launch
+    Preparing,
+    Running,
+    Completed,
+    Failed,
+    AwaitingApproval, // Added for Flow C (Safety)
+    Aborted,          // Hard stop by a critical safety pattern; run is sealed
+}
+
+impl RuntimeStatus {
+    /// No further execution will happen for the run
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, RuntimeStatus::Completed | RuntimeStatus::Failed | RuntimeStatus::Aborted)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThoughtSignatureStore {
+    pub signatures: HashMap<String, String>,
+    /// Agents whose last signature was dropped for exceeding the size limit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<String>,
+    /// Write timeline (no bodies), ordered by seq
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<SignatureWrite>,
+}
+
+impl ThoughtSignatureStore {
+    /// Both stores' signatures, `other`'s winning on conflicts (e.g. a parent run's store merged
+    /// into a child's). A drop in `other` wins too: that agent keeps no signature. The write
+    /// timeline is not carried over: its entries point into the source runs' event logs.
+    pub fn merge_stores(&self, other: &ThoughtSignatureStore) -> ThoughtSignatureStore {
+        let mut signatures = self.signatures.clone();
+        signatures.extend(other.signatures.iter().map(|(k, v)| (k.clone(), v.clone())));
+        signatures.retain(|agent_id, _| !other.dropped.contains(agent_id));
+
+        let mut dropped: Vec<String> = self.dropped.iter()
+            .filter(|agent_id| !other.signatures.contains_key(*agent_id))
+            .chain(other.dropped.iter())
+            .cloned()
+            .collect();
+        dropped.sort();
+        dropped.dedup();
+
+        ThoughtSignatureStore { signatures, dropped, history: Vec::new() }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// Like deserializing, but also rejects stores that can't have been produced by a run:
+    /// empty signatures, drop marks alongside a signature, or an unordered timeline
+    pub fn from_json(v: &serde_json::Value) -> Result<Self, serde_json::Error> {
+        use serde::de::Error as _;
+        let store = ThoughtSignatureStore::deserialize(v)?;
+        if let Some((agent_id, _)) = store.signatures.iter().find(|(_, s)| s.is_empty()) {
+            return Err(serde_json::Error::custom(format!("empty signature for agent '{}'", agent_id)));
+        }
+        if let Some(agent_id) = store.dropped.iter().find(|a| store.signatures.contains_key(*a)) {
+            return Err(serde_json::Error::custom(format!("agent '{}' is marked dropped but has a signature", agent_id)));
+        }
+        if store.history.windows(2).any(|w| w[0].seq >= w[1].seq) {
+            return Err(serde_json::Error::custom("signature history is not ordered by seq"));
+        }
+        Ok(store)
+    }
+}
+
+/// One signature write. Mirrors a SignatureUpdated event in the run's event log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SignatureWrite {
+    pub seq: u64,
+    pub agent_id: String,
+    pub timestamp: String,
+    pub event_id: String,
+}
+
+/// Portable snapshot of a run's progress, used to resume after a crash or maintenance window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunCheckpoint {
+    pub run_id: String,
+    /// agent_id -> raw JSON output (as stored under `run:{id}:agent:{id}:output`)
+    #[serde(default)]
+    pub agent_outputs: HashMap<String, String>,
+    #[serde(default)]
+    pub thought_signatures: HashMap<String, String>,
+    #[serde(default)]
+    pub completed_agents: Vec<String>,
+    #[serde(default)]
+    pub failed_agents: Vec<FailedAgent>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_cheapest_capable_model() {
+        assert_eq!(select_cheapest_capable_model(&[]), Some(ModelVariant::Fast));
+        assert_eq!(select_cheapest_capable_model(&[Capability::ToolUse]), Some(ModelVariant::Fast));
+        assert_eq!(select_cheapest_capable_model(&[Capability::DeepThinking]), Some(ModelVariant::Thinking));
+        assert_eq!(select_cheapest_capable_model(&[Capability::AdvancedReasoning, Capability::LargeContext]), Some(ModelVariant::Reasoning));
+        assert_eq!(select_cheapest_capable_model(&[Capability::AdvancedReasoning, Capability::DeepThinking]), None);
+    }
+
+    #[test]
+    fn test_capable_model_upgrades_built_ins_but_keeps_custom_models() {
+        let mut agent: AgentNodeConfig = serde_json::from_value(serde_json::json!({
+            "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+            "requires": ["deep_thinking"]
+        })).unwrap();
+        agent.assign_capable_model().unwrap();
+        assert_eq!(agent.model, ModelVariant::Thinking);
+
+        agent.model = ModelVariant::Custom("gemini-exp-1206".to_string());
+        agent.assign_capable_model().unwrap();
+        assert_eq!(agent.model, ModelVariant::Custom("gemini-exp-1206".to_string()));
+    }
+
+    #[test]
+    fn test_environment_precedence_and_limits() {
+        let mut config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 1, "timeout_ms": 1,
+            "global_environment": { "ENV": "staging", "REGION": "eu" },
+            "agents": [{
+                "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+                "environment": { "ENV": "prod" }
+            }]
+        })).unwrap();
+
+        let env = config.environment_for(&config.agents[0]);
+        assert_eq!(env["ENV"], "prod");
+        assert_eq!(env["REGION"], "eu");
+        assert!(config.validate_environment().is_ok());
+
+        config.agents[0].environment.insert("BIG".into(), "x".repeat(MAX_ENVIRONMENT_VALUE_CHARS + 1));
+        assert!(config.validate_environment().is_err());
+
+        config.agents[0].environment = (0..MAX_ENVIRONMENT_KEYS).map(|i| (format!("K{}", i), String::new())).collect();
+        assert!(config.validate_environment().is_err()); // 50 agent keys + REGION
+    }
+
+    #[test]
+    fn test_auto_promote_rules_validated() {
+        let mut config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 1, "timeout_ms": 1,
+            "auto_promote": ["*.md"],
+            "agents": [{
+                "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+                "auto_promote": ["report.*", "*.md"]
+            }]
+        })).unwrap();
+
+        assert_eq!(config.promotion_rules_for(&config.agents[0]), vec!["*.md", "report.*"]);
+        assert!(config.validate_promotion_rules().is_ok());
+
+        config.agents[0].auto_promote.push("[unclosed".to_string());
+        assert!(matches!(config.validate_promotion_rules(), Err(ValidationError::InvalidPromotionRule { rule, .. }) if rule == "[unclosed"));
+    }
+
+    #[test]
+    fn test_validation_errors_are_structured() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 1_500, "timeout_ms": 1,
+            "agents": [
+                { "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "depends_on": ["c"] },
+                { "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null },
+                { "id": "c", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "depends_on": ["a", "ghost"], "output_schema": "text" }
+            ]
+        })).unwrap();
+
+        let errors = config.validate().unwrap_err();
+        assert!(errors.contains(&ValidationError::DuplicateAgentId("a".to_string())));
+        assert!(errors.contains(&ValidationError::UnknownDependency { agent_id: "c".to_string(), dep_id: "ghost".to_string() }));
+        assert!(errors.contains(&ValidationError::BudgetBelowMinimum));
+        assert!(errors.contains(&ValidationError::CycleDetected(vec!["a".to_string(), "c".to_string()])));
+        assert!(errors.iter().any(|e| matches!(e, ValidationError::InvalidSchema { field, .. } if field == "output_schema")));
+
+        let json = serde_json::to_value(&errors[0]).unwrap();
+        assert_eq!(json, serde_json::json!({ "code": "duplicate_agent_id", "details": "a" }));
+        assert_eq!(errors[0].to_string(), "Duplicate agent id 'a'");
+    }
+
+    #[test]
+    fn test_feasibility_rejects_agent_over_budget() {
+        let mut config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 20_000, "timeout_ms": 1,
+            "agents": [
+                { "id": "scout", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "estimated_tokens": 3_000 },
+                { "id": "deep", "role": "worker", "model": "thinking", "tools": [], "prompt": "", "position": null,
+                  "depends_on": ["scout"], "estimated_tokens": 25_000 }
+            ]
+        })).unwrap();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.check_feasibility().unwrap_err(), vec![ValidationError::AgentExceedsBudget {
+            agent_id: "deep".to_string(), estimated_tokens: 25_000, limit: 20_000,
+        }]);
+
+        // Scoping the run to the cheap agent makes it feasible
+        config.target_agents = Some(vec!["scout".to_string()]);
+        assert!(config.check_feasibility().is_ok());
+
+        // Many affordable agents can still add up past the limit
+        config.target_agents = None;
+        config.agents[1].estimated_tokens = Some(18_000);
+        assert!(matches!(config.check_feasibility().unwrap_err()[0], ValidationError::EstimatedTotalExceedsBudget { estimated_tokens: 21_000, .. }));
+    }
+
+    #[test]
+    fn test_failed_agents_accept_legacy_ids() {
+        let state: RuntimeState = serde_json::from_value(serde_json::json!({
+            "run_id": "r", "workflow_id": "wf", "client_id": "c", "status": "failed",
+            "active_agents": [], "completed_agents": ["a"],
+            "failed_agents": ["b", { "agent_id": "c", "reason": "boom", "error_code": "agent_error", "failed_at": "t", "attempts": 2 }],
+            "invocations": [], "total_tokens_used": 0, "start_time": "t", "end_time": null
+        })).unwrap();
+
+        assert_eq!(state.failed_agents[0].agent_id, "b");
+        assert_eq!(state.failed_agents[0].error_code, FailureCode::Unknown);
+        assert_eq!(state.failed_agents[1].attempts, 2);
+        assert!(state.blocked_agents.is_empty());
+
+        // Re-serialized in the object form
+        let out = serde_json::to_value(&state).unwrap();
+        assert_eq!(out["failed_agents"][0]["agent_id"], "b");
+        assert_eq!(out["failed_agents"][1]["error_code"], "agent_error");
+    }
+
+    #[test]
+    fn test_supervisor_directive_from_output() {
+        let direct = serde_json::json!({
+            "action": { "type": "modify_prompt", "new_prompt": "Cite sources." },
+            "target_agent_id": "writer",
+            "reason": "Unsourced claims"
+        });
+        let directive = SupervisorDirective::from_output(&direct).unwrap();
+        assert_eq!(directive.action, SupervisorAction::ModifyPrompt { new_prompt: "Cite sources.".to_string() });
+
+        let fenced = serde_json::json!({
+            "result": "```json\n{\"action\": {\"type\": \"retry_agent\"}, \"target_agent_id\": \"writer\", \"reason\": \"Truncated\"}\n```"
+        });
+        assert_eq!(SupervisorDirective::from_output(&fenced).unwrap().action, SupervisorAction::RetryAgent);
+
+        assert!(SupervisorDirective::from_output(&serde_json::json!({ "result": "All agents look fine." })).is_none());
+    }
+
+    #[test]
+    fn test_agent_timeout_fallback() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 1, "timeout_ms": 600_000, "timeout_per_agent_ms": 30_000,
+            "agents": [
+                { "id": "slow", "role": "worker", "model": "thinking", "tools": [], "prompt": "", "position": null, "timeout_ms": 120_000 },
+                { "id": "quick", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null }
+            ]
+        })).unwrap();
+
+        assert_eq!(config.agent_timeout("slow"), Some(std::time::Duration::from_secs(120)));
+        assert_eq!(config.agent_timeout("quick"), Some(std::time::Duration::from_secs(30)));
+
+        let zero: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 600_000, "timeout_per_agent_ms": 0,
+            "agents": [{ "id": "hasty", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "timeout_ms": 0 }]
+        })).unwrap();
+        assert_eq!(zero.validate().unwrap_err(), vec![
+            ValidationError::ZeroAgentTimeout { agent_id: Some("hasty".to_string()) },
+            ValidationError::ZeroAgentTimeout { agent_id: None },
+        ]);
+    }
+
+    #[test]
+    fn test_generation_params_bounded() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [
+                { "id": "hot", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "temperature": 2.5 },
+                { "id": "long", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "max_output_tokens": -1 },
+                { "id": "ok", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "temperature": 0.0, "seed": 7 }
+            ]
+        })).unwrap();
+
+        let errors = config.validate().unwrap_err();
+        let invalid: Vec<&str> = errors.iter().filter_map(|e| match e {
+            ValidationError::InvalidGenerationParams { agent_id, .. } => Some(agent_id.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(invalid, vec!["hot", "long"]);
+        assert_eq!(config.agents[2].generation, GenerationParams { temperature: Some(0.0), seed: Some(7), ..Default::default() });
+    }
+
+    #[test]
+    fn test_fault_rules_validated() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [{ "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null }],
+            "faults": [
+                { "agent": "a", "attempts": 1, "fault": { "type": "error" } },
+                { "agent": "a", "fault": { "type": "error", "status": 200 } },
+                { "agent": "ghost", "fault": { "type": "delay", "ms": 10 } },
+                { "agent": "a", "attempts": 0, "fault": { "type": "invalid_output" } }
+            ]
+        })).unwrap();
+
+        assert_eq!(config.faults[0].fault, Fault::Error { status: 503 });
+        assert!(config.faults[0].applies_to("a", 1) && !config.faults[0].applies_to("a", 2));
+        assert!(config.faults[1].applies_to("a", 7));
+        let reasons: Vec<String> = config.validate().unwrap_err().into_iter().filter_map(|e| match e {
+            ValidationError::InvalidFault { reason, .. } => Some(reason),
+            _ => None,
+        }).collect();
+        assert_eq!(reasons, vec!["status 200 is not an HTTP error", "no such agent", "attempts must be at least 1"]);
+    }
+
+    #[test]
+    fn test_retry_policy_bounded() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [
+                { "id": "eager", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "retry": { "max_retries": 9 } },
+                { "id": "same", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "retry": { "max_retries": 1, "escalation": "fast" } },
+                { "id": "ok", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null, "retry": { "max_retries": 2, "escalation": "reasoning" } }
+            ]
+        })).unwrap();
+
+        let errors = config.validate().unwrap_err();
+        let invalid: Vec<&str> = errors.iter().filter_map(|e| match e {
+            ValidationError::InvalidRetryPolicy { agent_id, .. } => Some(agent_id.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(invalid, vec!["eager", "same"]);
+        assert_eq!(config.agents[2].retry, Some(RetryPolicy { max_retries: 2, escalation: Some(ModelVariant::Reasoning) }));
+    }
+
+    #[test]
+    fn test_at_least_join_policy_bounded_by_dependencies() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [
+                { "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null },
+                { "id": "b", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null },
+                { "id": "merge", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+                  "depends_on": ["a", "b"], "join_policy": { "type": "at_least", "n": 3 } },
+                { "id": "first", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+                  "depends_on": ["a", "b"], "join_policy": { "type": "any" } }
+            ]
+        })).unwrap();
+
+        assert_eq!(config.validate().unwrap_err(), vec![
+            ValidationError::InvalidJoinPolicy { agent_id: "merge".to_string(), n: 3, dependencies: 2 },
+        ]);
+        assert_eq!(config.agents[3].join_policy, JoinPolicy::Any);
+        assert!(config.agents[0].join_policy.is_all());
+    }
+
+    #[test]
+    fn test_agent_defaults_fill_only_unset_fields() {
+        let mut config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agent_defaults": {
+                "model": "reasoning", "cache_policy": "none", "tools": ["web_search"],
+                "timeout_ms": 30000, "log_level": "debug"
+            },
+            "agents": [
+                { "id": "plain", "role": "worker", "prompt": "", "position": null },
+                {
+                    "id": "custom", "role": "worker", "prompt": "", "position": null,
+                    "model": "thinking", "cache_policy": "persistent", "tools": ["execute_python"],
+                    "timeout_ms": 5000, "log_level": "warning"
+                }
+            ]
+        })).unwrap();
+        config.apply_agent_defaults();
+        config.apply_agent_defaults();
+
+        let plain = &config.agents[0];
+        assert_eq!(plain.model, ModelVariant::Reasoning);
+        assert_eq!(plain.cache_policy, "none");
+        assert_eq!(plain.tools, vec!["web_search".to_string()]);
+        assert_eq!((plain.timeout_ms, plain.log_level.as_deref()), (Some(30000), Some("debug")));
+
+        let custom = &config.agents[1];
+        assert_eq!(custom.model, ModelVariant::Thinking);
+        assert_eq!(custom.cache_policy, "persistent");
+        assert_eq!(custom.tools, vec!["execute_python".to_string()]);
+        assert_eq!((custom.timeout_ms, custom.log_level.as_deref()), (Some(5000), Some("warning")));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_check_limits_names_each_cap() {
+        let limits = WorkflowLimits {
+            max_agents: 3, max_edges: 2, max_dependencies_per_agent: 1, max_prompt_chars: 10, max_config_bytes: 100_000,
+        };
+        let workflow = |agents: serde_json::Value| -> WorkflowConfig {
+            serde_json::from_value(serde_json::json!({
+                "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1, "agents": agents
+            })).unwrap()
+        };
+
+        let oversized = workflow(serde_json::json!((0..80_000).map(|i| serde_json::json!({
+            "id": format!("a{}", i), "role": "worker", "prompt": "", "position": null
+        })).collect::<Vec<_>>()));
+        assert_eq!(oversized.check_limits(&limits).unwrap_err(), vec![ValidationError::TooManyAgents { observed: 80_000, max: 3 }]);
+
+        let dense = workflow(serde_json::json!([
+            { "id": "a", "role": "worker", "prompt": "", "position": null },
+            { "id": "b", "role": "worker", "prompt": "ok", "position": null, "depends_on": ["a"] },
+            { "id": "c", "role": "worker", "prompt": "much too long", "position": null, "depends_on": ["a", "b"] }
+        ]));
+        assert_eq!(dense.check_limits(&limits).unwrap_err(), vec![
+            ValidationError::TooManyEdges { observed: 3, max: 2 },
+            ValidationError::TooManyDependencies { agent_id: "c".into(), observed: 2, max: 1 },
+            ValidationError::PromptTooLong { agent_id: "c".into(), observed: 13, max: 10 },
+        ]);
+
+        let tight = WorkflowLimits { max_config_bytes: 50, ..WorkflowLimits::default() };
+        let errors = dense.check_limits(&tight).unwrap_err();
+        assert!(matches!(errors.as_slice(), [ValidationError::ConfigTooLarge { max: 50, .. }]));
+        assert!(errors[0].to_string().contains("(max 50)"));
+    }
+
+    #[test]
+    fn test_depends_on_accepts_edge_objects() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [
+                { "id": "a", "role": "worker", "prompt": "", "position": null },
+                { "id": "b", "role": "worker", "prompt": "", "position": null },
+                { "id": "c", "role": "worker", "prompt": "", "position": null,
+                  "depends_on": ["a", { "agent": "b", "pass": "output", "alias": "research" }] },
+                { "id": "d", "role": "worker", "prompt": "", "position": null,
+                  "depends_on": [{ "agent": "a", "alias": "b" }, "b", { "agent": "c", "pass": "signature", "alias": "b" }] }
+            ]
+        })).unwrap();
+
+        let c = &config.agents[2];
+        assert_eq!(c.dependency_ids(), vec!["a", "b"]);
+        assert!(c.depends_on[0].is_plain());
+        assert_eq!(c.depends_on[1].pass, EdgePass::Output);
+        assert_eq!(c.depends_on[1].output_key(), "research");
+        assert_eq!(c.depends_on[1].label().as_deref(), Some("output as research"));
+
+        // Plain entries serialize back to bare strings
+        let json = serde_json::to_value(c).unwrap();
+        assert_eq!(json["depends_on"], serde_json::json!(["a", { "agent": "b", "pass": "output", "alias": "research" }]));
+
+        // "b" arrives twice in d's context; c's alias is unused since that edge passes only a signature
+        assert_eq!(config.validate().unwrap_err(), vec![
+            ValidationError::DuplicateAlias { agent_id: "d".to_string(), alias: "b".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_hook_urls_validated() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [{ "id": "a", "role": "worker", "prompt": "", "position": null }],
+            "hooks": { "before_agent": "https://policy.example/check", "on_fail": "policy.example/fail" }
+        })).unwrap();
+
+        assert_eq!(config.hooks.configured().len(), 2);
+        assert_eq!(config.validate().unwrap_err(), vec![
+            ValidationError::InvalidHookUrl { hook: "on_fail".to_string(), url: "policy.example/fail".to_string() },
+        ]);
+        assert!(serde_json::to_value(WorkflowConfig { hooks: WorkflowHooks::default(), ..config }).unwrap().get("hooks").is_none());
+    }
+
+    #[test]
+    fn test_agent_ids_checked_against_naming_convention() {
+        let config = |convention: serde_json::Value, ids: &[&str]| -> WorkflowConfig {
+            let agents: Vec<serde_json::Value> = ids.iter()
+                .map(|id| serde_json::json!({ "id": id, "role": "worker", "prompt": "", "position": null }))
+                .collect();
+            serde_json::from_value(serde_json::json!({
+                "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+                "agents": agents, "agent_naming_convention": convention
+            })).unwrap()
+        };
+        let invalid = |agent_id: &str, reason: &str| ValidationError::InvalidAgentId { agent_id: agent_id.to_string(), reason: reason.to_string() };
+
+        // Unset: any mix of styles passes the base rules
+        assert!(config(serde_json::Value::Null, &["agent-1", "a", "my_agent_node_v2", "Writer"]).validate().is_ok());
+        let long = "a".repeat(MAX_AGENT_ID_CHARS + 1);
+        assert_eq!(config(serde_json::Value::Null, &["2nd", "web.search", &long]).validate().unwrap_err(), vec![
+            invalid("2nd", "starts with a digit"),
+            invalid("web.search", "contains '.'"),
+            invalid(&long, "longer than 64 characters"),
+        ]);
+
+        assert_eq!(config(serde_json::json!("kebab_case"), &["web-researcher", "web_researcher", "Web-researcher", "web--researcher"]).validate().unwrap_err(), vec![
+            invalid("web_researcher", "is not kebab-case"),
+            invalid("Web-researcher", "is not kebab-case"),
+            invalid("web--researcher", "is not kebab-case"),
+        ]);
+        assert!(config(serde_json::json!("snake_case"), &["web_researcher", "writer2"]).validate().is_ok());
+        assert_eq!(config(serde_json::json!("alphanumeric"), &["Writer2", "writer_2"]).validate().unwrap_err(), vec![
+            invalid("writer_2", "is not alphanumeric"),
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_agent_schema_rejected() {
+        let config: WorkflowConfig = serde_json::from_value(serde_json::json!({
+            "id": "wf", "name": "wf", "max_token_budget": 0, "timeout_ms": 1,
+            "agents": [
+                { "id": "ok", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+                  "output_schema": { "type": "object", "properties": { "summary": { "type": "string" } }, "required": ["summary"] } },
+                { "id": "typo", "role": "worker", "model": "fast", "tools": [], "prompt": "", "position": null,
+                  "input_schema": { "type": "object", "properties": { "n": { "type": "strign" } } } }
+            ]
+        })).unwrap();
+
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let message = errors[0].to_string();
+        assert!(message.starts_with("Agent 'typo' has an invalid input_schema:"), "{}", message);
+        assert!(message.contains("strign") && message.contains("/properties/n/type"), "{}", message);
+    }
+
+    #[test]
+    fn test_signature_stores_merge_and_round_trip() {
+        let parent = ThoughtSignatureStore {
+            signatures: HashMap::from([("a".to_string(), "sig-a".to_string()), ("b".to_string(), "sig-b-parent".to_string())]),
+            dropped: vec!["c".to_string()],
+            history: vec![],
+        };
+        let child = ThoughtSignatureStore {
+            signatures: HashMap::from([("b".to_string(), "sig-b".to_string()), ("c".to_string(), "sig-c".to_string())]),
+            dropped: vec!["a".to_string()],
+            history: vec![SignatureWrite { seq: 1, agent_id: "b".to_string(), timestamp: String::new(), event_id: "e1".to_string() }],
+        };
+
+        let merged = parent.merge_stores(&child);
+        assert_eq!(merged.signatures["b"], "sig-b");
+        assert_eq!(merged.signatures["c"], "sig-c");
+        // The child dropped a's signature; c's parent drop was superseded by the child's signature
+        assert_eq!(merged.signatures.len(), 2);
+        assert_eq!(merged.dropped, vec!["a"]);
+        assert!(merged.history.is_empty());
+
+        let restored = ThoughtSignatureStore::from_json(&child.to_json()).unwrap();
+        assert_eq!((restored.signatures, restored.history), (child.signatures.clone(), child.history.clone()));
+
+        let mut contradictory = child.to_json();
+        contradictory["dropped"] = serde_json::json!(["c"]);
+        assert!(ThoughtSignatureStore::from_json(&contradictory).unwrap_err().to_string().contains("'c'"));
+        let mut empty = child.to_json();
+        empty["signatures"]["c"] = serde_json::json!("");
+        assert!(ThoughtSignatureStore::from_json(&empty).is_err());
+        assert!(ThoughtSignatureStore::from_json(&serde_json::json!({ "signatures": 3 })).is_err());
+    }
+
+    #[test]
+    fn test_token_split_optional_and_reconciled() {
+        let reported = |extra: serde_json::Value| {
+            let mut invocation = serde_json::json!({
+                "id": "i", "agent_id": "a", "model_variant": "fast", "thought_signature": null,
+                "tools_used": [], "tokens_used": 100, "latency_ms": 1, "status": "success",
+                "timestamp": "t", "artifact_id": null, "error_message": null
+            });
+            invocation.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            serde_json::from_value::<AgentInvocation>(invocation).unwrap()
+        };
+
+        // Payloads without a split keep their total
+        let mut legacy = reported(serde_json::json!({}));
+        assert!(!legacy.token_split.is_reported());
+        legacy.reconcile_tokens();
+        assert_eq!(legacy.tokens_used, 100);
+        assert!(serde_json::to_value(&legacy).unwrap().get("prompt_tokens").is_none());
+
+        // A partial split (no prompt/completion counts) does not replace the total
+        let mut partial = reported(serde_json::json!({ "cached_tokens": 80, "thinking_tokens": 30 }));
+        partial.reconcile_tokens();
+        assert_eq!(partial.tokens_used, 100);
+
+        let mut split = reported(serde_json::json!({
+            "prompt_tokens": 1_000, "cached_tokens": 800, "completion_tokens": 200, "thinking_tokens": 300
+        }));
+        split.reconcile_tokens();
+        assert_eq!(split.tokens_used, 1_500);
+        assert_eq!(serde_json::to_value(&split).unwrap()["cached_tokens"], 800);
+    }
+}
@@ -0,0 +1,169 @@
+// [[RARO]]/crates/raro-models/src/strict.rs
+// Purpose: Strict parsing support: find the keys of a raw WorkflowConfig that serde would
+//          silently ignore (typos such as `agent` for `agents` or `dependsOn` for `depends_on`).
+// Architecture: Shared Data Layer (validation helper)
+// Dependencies: serde_json, workflow-schema.json (embedded at build time)
+//
+// Walks the raw JSON alongside the generated JSON Schema rather than deriving
+// deny_unknown_fields, so the lenient default parse is unchanged and every unknown key is
+// reported at once. Free-form fields (metadata, agent schemas, ...) accept any keys.
+// The schema is the committed workflow-schema.json (kept current by `cargo xtask schema
+// --check`), so consumers need neither the `schema` feature nor schemars.
+
+use serde_json::Value;
+use std::sync::LazyLock;
+
+/// workflow-schema.json, parsed on first use
+static WORKFLOW_SCHEMA: LazyLock<Value> = LazyLock::new(|| {
+    serde_json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/../../workflow-schema.json")))
+        .expect("workflow-schema.json is valid JSON")
+});
+
+/// Paths of unknown keys in a raw WorkflowConfig, e.g. `agents[1].dependsOn`, sorted
+pub fn unknown_fields(raw: &Value) -> Vec<String> {
+    let schema = &*WORKFLOW_SCHEMA;
+    let mut unknown = Vec::new();
+    collect(raw, schema, &schema["definitions"], "", &mut unknown);
+    unknown.sort();
+    unknown
+}
+
+fn resolve<'a>(schema: &'a Value, definitions: &'a Value) -> &'a Value {
+    match schema["$ref"].as_str().and_then(|r| r.strip_prefix("#/definitions/")) {
+        Some(name) => resolve(&definitions[name], definitions),
+        None => match schema["allOf"].as_array() {
+            // schemars wraps a documented $ref as { description, allOf: [ref] }
+            Some(parts) if parts.len() == 1 => resolve(&parts[0], definitions),
+            _ => schema,
+        },
+    }
+}
+
+fn alternatives(schema: &Value) -> Option<&Vec<Value>> {
+    schema["anyOf"].as_array().or_else(|| schema["oneOf"].as_array())
+}
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// Whether `value` could be the variant `schema` describes: its type, enum/const, required
+/// keys and (for tagged enums) the values of its enum-valued properties
+fn admits(schema: &Value, value: &Value, definitions: &Value) -> bool {
+    let schema = resolve(schema, definitions);
+    if let Some(alts) = alternatives(schema) {
+        return alts.iter().any(|alt| admits(alt, value, definitions));
+    }
+    let typed = match &schema["type"] {
+        Value::String(name) => type_matches(name, value),
+        Value::Array(names) => names.iter().filter_map(Value::as_str).any(|name| type_matches(name, value)),
+        _ => true,
+    };
+    if !typed
+        || schema["enum"].as_array().is_some_and(|options| !options.contains(value))
+        || schema.get("const").is_some_and(|c| c != value)
+    {
+        return false;
+    }
+    let Some(fields) = value.as_object() else { return true };
+    let has_required = schema["required"].as_array()
+        .is_none_or(|required| required.iter().filter_map(Value::as_str).all(|key| fields.contains_key(key)));
+    let tags_match = schema["properties"].as_object().is_none_or(|properties| {
+        properties.iter()
+            .filter_map(|(key, sub)| Some((fields.get(key)?, resolve(sub, definitions))))
+            .filter(|(_, sub)| sub.get("enum").is_some() || sub.get("const").is_some())
+            .all(|(field, sub)| admits(sub, field, definitions))
+    });
+    has_required && tags_match
+}
+
+fn collect(value: &Value, schema: &Value, definitions: &Value, path: &str, unknown: &mut Vec<String>) {
+    let schema = resolve(schema, definitions);
+    if let Some(alts) = alternatives(schema) {
+        // The variant the value fits with the fewest unknown keys
+        let best = alts.iter()
+            .filter(|alt| admits(alt, value, definitions))
+            .map(|alt| {
+                let mut found = Vec::new();
+                collect(value, alt, definitions, path, &mut found);
+                found
+            })
+            .min_by_key(Vec::len);
+        unknown.extend(best.unwrap_or_default());
+        return;
+    }
+
+    match value {
+        Value::Object(fields) => {
+            let properties = schema["properties"].as_object();
+            let additional = &schema["additionalProperties"];
+            for (key, field) in fields {
+                let field_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match properties.and_then(|p| p.get(key)) {
+                    Some(sub) => collect(field, sub, definitions, &field_path, unknown),
+                    None if additional.is_object() => collect(field, additional, definitions, &field_path, unknown),
+                    // Declared fields only when the schema declares some (or forbids extras)
+                    None if properties.is_some() || *additional == Value::Bool(false) => unknown.push(field_path),
+                    None => {}
+                }
+            }
+        }
+        Value::Array(items) if schema["items"].is_object() => {
+            for (i, item) in items.iter().enumerate() {
+                collect(item, &schema["items"], definitions, &format!("{}[{}]", path, i), unknown);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WorkflowConfig;
+    use serde_json::json;
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_embedded_schema_matches_the_types() {
+        assert_eq!(*WORKFLOW_SCHEMA, serde_json::to_value(schemars::schema_for!(WorkflowConfig)).unwrap());
+    }
+
+    #[test]
+    fn test_misspelled_fields_are_named() {
+        let config = json!({
+            "id": "wf", "name": "wf", "max_token_budget": 10_000, "timeout_ms": 1,
+            "agent": [],
+            "execution_mode": { "type": "push", "webhook_url": "https://example.com", "retries": 3 },
+            "metadata": { "anything": { "goes": true } },
+            "agents": [
+                { "id": "a", "role": "worker", "model": "fast", "tools": [], "prompt": "", "temperature": 0.2 },
+                { "id": "b", "role": "worker", "model": "fast", "tools": [], "prompt": "", "dependsOn": ["a"],
+                  "depends_on": ["a", { "agent": "a", "pass": "output", "alais": "x" }] }
+            ]
+        });
+        assert_eq!(unknown_fields(&config), vec![
+            "agent",
+            "agents[1].dependsOn",
+            "agents[1].depends_on[1].alais",
+            "execution_mode.retries",
+        ]);
+        // Everything a parsed config serializes back to is known
+        let parsed: WorkflowConfig = serde_json::from_value(config).unwrap();
+        assert_eq!(unknown_fields(&serde_json::to_value(parsed).unwrap()), Vec::<String>::new());
+
+        // A field that belongs to another variant of a tagged enum is still unknown
+        let managed = json!({ "id": "wf", "name": "wf", "agents": [], "max_token_budget": 0, "timeout_ms": 1,
+            "execution_mode": { "type": "managed", "webhook_url": "https://example.com" } });
+        assert_eq!(unknown_fields(&managed), vec!["execution_mode.webhook_url"]);
+    }
+}
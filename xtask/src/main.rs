@@ -0,0 +1,92 @@
+// [[RARO]]/xtask/src/main.rs
+// Purpose: Repository automation, run as `cargo xtask <task>`.
+//          schema [--check]: write (or verify) workflow-schema.json from the WorkflowConfig types.
+// Architecture: Tooling
+// Dependencies: raro-models (schema feature), schemars
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use raro_models::WorkflowConfig;
+
+const SCHEMA_FILE: &str = "workflow-schema.json";
+
+fn schema_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join(SCHEMA_FILE)
+}
+
+/// Pretty-printed JSON Schema for WorkflowConfig, as committed
+fn workflow_schema() -> String {
+    let schema = schemars::schema_for!(WorkflowConfig);
+    serde_json::to_string_pretty(&schema).expect("schema serializes") + "\n"
+}
+
+fn schema(check: bool) -> Result<(), String> {
+    let path = schema_path();
+    let generated = workflow_schema();
+    if check {
+        let committed = std::fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        if committed != generated {
+            return Err(format!("{} is out of date; run `cargo xtask schema`", SCHEMA_FILE));
+        }
+        println!("{} is up to date", SCHEMA_FILE);
+    } else {
+        std::fs::write(&path, generated).map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+        println!("Wrote {}", SCHEMA_FILE);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["schema"] => schema(false),
+        ["schema", "--check"] => schema(true),
+        _ => Err("usage: cargo xtask schema [--check]".to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Paths of object properties that carry no description. Serde tag discriminators
+    /// (single-value enums) are exempt; the variant schema around them is documented.
+    fn undocumented(value: &Value, path: &str, out: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::Object(properties)) = map.get("properties") {
+                    for (name, property) in properties {
+                        let is_tag = property.get("enum").and_then(Value::as_array).is_some_and(|e| e.len() == 1);
+                        if property.get("description").is_none() && !is_tag {
+                            out.push(format!("{}.{}", path, name));
+                        }
+                    }
+                }
+                for (key, child) in map {
+                    undocumented(child, &format!("{}/{}", path, key), out);
+                }
+            }
+            Value::Array(items) => items.iter().for_each(|item| undocumented(item, path, out)),
+            _ => {}
+        }
+    }
+
+    // The same comparison as `cargo xtask schema --check`, so the test suite catches drift too
+    #[test]
+    fn test_committed_schema_is_current_and_documented() {
+        let generated = workflow_schema();
+        assert_eq!(std::fs::read_to_string(schema_path()).unwrap(), generated, "run `cargo xtask schema`");
+
+        let mut missing = Vec::new();
+        undocumented(&serde_json::from_str(&generated).unwrap(), "", &mut missing);
+        assert!(missing.is_empty(), "fields without descriptions: {:?}", missing);
+    }
+}